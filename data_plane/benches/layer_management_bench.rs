@@ -1,13 +1,15 @@
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 use experiment_data_plane::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
 use experiment_data_plane::layer::{BucketRange, Layer, LayerManager};
+use experiment_data_plane::sim::{seeded_rng, DEFAULT_SEED};
 use rand::Rng;
 use serde_json::json;
+use std::sync::Arc;
 use tempfile::TempDir;
 
 /// Create random test catalog
-fn create_random_catalog(num_experiments: usize) -> (TempDir, ExperimentCatalog) {
-    let mut rng = rand::thread_rng();
+fn create_random_catalog(num_experiments: usize) -> (TempDir, Arc<ExperimentCatalog>) {
+    let mut rng = seeded_rng(DEFAULT_SEED);
     let temp_dir = TempDir::new().unwrap();
     let experiments_dir = temp_dir.path().join("experiments");
     std::fs::create_dir_all(&experiments_dir).unwrap();
@@ -16,10 +18,15 @@ fn create_random_catalog(num_experiments: usize) -> (TempDir, ExperimentCatalog)
         let exp = ExperimentDef {
             eid: (100 + i) as i64,
             service: format!("service_{}", rng.gen_range(0..10)),
+            expires_at: None,
+            state: Default::default(),
+            caps: None,
             rule: None,
+            exposure_sampling: None,
             variants: vec![VariantDef {
                 vid: (1000 + i * 10) as i64,
                 params: json!({"feature": i}),
+                dark_params: None,
             }],
         };
 
@@ -30,36 +37,45 @@ fn create_random_catalog(num_experiments: usize) -> (TempDir, ExperimentCatalog)
         .unwrap();
     }
 
-    let catalog = ExperimentCatalog::load_from_dir(experiments_dir).unwrap();
+    let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
     (temp_dir, catalog)
 }
 
+/// Create one enabled layer over a random bucket range of variant `1000 + i * 10`
+fn create_random_layer(i: usize, rng: &mut impl Rng) -> Layer {
+    let bucket_start = rng.gen_range(0..9000);
+    let bucket_size = rng.gen_range(100..1000);
+
+    Layer {
+        layer_id: format!("layer_{}", i),
+        version: "v1".to_string(),
+        priority: (1000000 - i * 10) as i32,
+        hash_key: "user_id".into(),
+        salt: Some(format!("salt_{}", rng.gen_range(0..1000))),
+        bucket_size: None,
+        expires_at: None,
+        rule: None,
+        services: vec![],
+        ranges: vec![BucketRange {
+            start: bucket_start,
+            end: (bucket_start + bucket_size).min(10000),
+            vid: (1000 + i * 10) as i64,
+            label: None,
+        }],
+        enabled: true,
+        aa_test: false,
+    }
+}
+
 /// Create random layers with various bucket distributions
-async fn create_random_layers(num_layers: usize, catalog: &ExperimentCatalog) -> (TempDir, LayerManager) {
-    let mut rng = rand::thread_rng();
+async fn create_random_layers(num_layers: usize, catalog: &Arc<ExperimentCatalog>) -> (TempDir, LayerManager) {
+    let mut rng = seeded_rng(DEFAULT_SEED);
     let temp_dir = TempDir::new().unwrap();
     let layers_dir = temp_dir.path().join("layers");
     std::fs::create_dir_all(&layers_dir).unwrap();
 
     for i in 0..num_layers {
-        let bucket_start = rng.gen_range(0..9000);
-        let bucket_size = rng.gen_range(100..1000);
-
-        let layer = Layer {
-            layer_id: format!("layer_{}", i),
-            version: "v1".to_string(),
-            priority: (1000000 - i * 10) as i32,
-            hash_key: "user_id".to_string(),
-            salt: Some(format!("salt_{}", rng.gen_range(0..1000))),
-            services: vec![],
-            ranges: vec![BucketRange {
-                start: bucket_start,
-                end: (bucket_start + bucket_size).min(10000),
-                vid: (1000 + i * 10) as i64,
-            }],
-            enabled: true,
-        };
-
+        let layer = create_random_layer(i, &mut rng);
         std::fs::write(
             layers_dir.join(format!("layer_{}.json", i)),
             serde_json::to_string_pretty(&layer).unwrap(),
@@ -101,7 +117,7 @@ fn bench_layer_filtering(c: &mut Criterion) {
 /// Benchmark: Bucket calculation
 fn bench_bucket_calculation(c: &mut Criterion) {
     let mut group = c.benchmark_group("bucket_calculation");
-    let mut rng = rand::thread_rng();
+    let mut rng = seeded_rng(DEFAULT_SEED);
 
     let users: Vec<String> = (0..1000)
         .map(|i| format!("user_{}", i))
@@ -147,10 +163,41 @@ fn bench_layer_sorting(c: &mut Criterion) {
     group.finish();
 }
 
+/// Benchmark: Publishing a change to one layer
+///
+/// Only the touched shard and the layer's services are rebuilt, but expiry and
+/// the experiment budget are still recomputed over every layer, so this grows
+/// with the fleet size.
+fn bench_single_layer_update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("single_layer_update");
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let mut rng = seeded_rng(DEFAULT_SEED);
+
+    for num_layers in [1_000, 10_000, 50_000].iter() {
+        let (_temp_catalog, catalog) = create_random_catalog(*num_layers);
+        let (temp_layers, manager) = rt.block_on(create_random_layers(*num_layers, &catalog));
+        let path = temp_layers.path().join("layers").join("layer_0.json");
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(num_layers),
+            num_layers,
+            |b, _| {
+                b.iter(|| {
+                    let layer = create_random_layer(0, &mut rng);
+                    manager.upsert_layer(black_box(layer), &path, &catalog).unwrap();
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_layer_filtering,
     bench_bucket_calculation,
     bench_layer_sorting,
+    bench_single_layer_update,
 );
 criterion_main!(benches);
@@ -1,15 +1,16 @@
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 use experiment_data_plane::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
-use experiment_data_plane::layer::{BucketRange, Layer, LayerManager};
-use experiment_data_plane::merge::{merge_layers_batch, ExperimentRequest};
+use experiment_data_plane::engine::Evaluator;
+use experiment_data_plane::layer::{BucketRange, Layer};
+use experiment_data_plane::merge::ExperimentRequest;
+use experiment_data_plane::sim::{seeded_rng, DEFAULT_SEED};
 use rand::Rng;
 use serde_json::json;
-use std::collections::HashMap;
 use tempfile::TempDir;
 
 /// Create random nested params with specified depth and width
 fn create_random_nested_params(depth: usize, fields_per_level: usize, seed: usize) -> serde_json::Value {
-    let mut rng = rand::thread_rng();
+    let mut rng = seeded_rng(DEFAULT_SEED ^ seed as u64);
     
     if depth == 0 {
         return match rng.gen_range(0..3) {
@@ -44,10 +45,15 @@ fn create_catalog_with_random_params(
         let exp = ExperimentDef {
             eid: (100 + i) as i64,
             service: "test_service".to_string(),
+            expires_at: None,
+            state: Default::default(),
+            caps: None,
             rule: None,
+            exposure_sampling: None,
             variants: vec![VariantDef {
                 vid: (1000 + i * 10) as i64,
                 params,
+                dark_params: None,
             }],
         };
 
@@ -62,54 +68,49 @@ fn create_catalog_with_random_params(
     (temp_dir, catalog)
 }
 
-/// Create layers
-async fn create_layers(num_layers: usize, catalog: &ExperimentCatalog) -> (TempDir, LayerManager) {
-    let temp_dir = TempDir::new().unwrap();
-    let layers_dir = temp_dir.path().join("layers");
-    std::fs::create_dir_all(&layers_dir).unwrap();
-
+/// Create an evaluator with one single-bucket layer per experiment
+fn create_evaluator(num_layers: usize, catalog: ExperimentCatalog) -> Evaluator {
     let test_user = "bench_user";
-    for i in 0..num_layers {
+    let layers = (0..num_layers).map(|i| {
         let salt = format!("salt_{}", i);
         let bucket = experiment_data_plane::hash::hash_to_bucket(test_user, &salt);
 
-        let layer = Layer {
+        Layer {
             layer_id: format!("layer_{}", i),
             version: "v1".to_string(),
             priority: (1000000 - i * 10) as i32,
-            hash_key: "user_id".to_string(),
+            hash_key: "user_id".into(),
             salt: Some(salt),
+            bucket_size: None,
+            expires_at: None,
+            rule: None,
             services: vec![],
             ranges: vec![BucketRange {
                 start: bucket,
                 end: bucket.saturating_add(1).min(10000),
                 vid: (1000 + i * 10) as i64,
+                label: None,
             }],
             enabled: true,
-        };
-
-        std::fs::write(
-            layers_dir.join(format!("layer_{}.json", i)),
-            serde_json::to_string_pretty(&layer).unwrap(),
-        )
-        .unwrap();
-    }
-
-    let manager = LayerManager::new(layers_dir);
-    manager.load_all_layers(catalog).await.unwrap();
+            aa_test: false,
+        }
+    });
 
-    (temp_dir, manager)
+    Evaluator::builder()
+        .with_catalog(catalog)
+        .with_layers(layers)
+        .build()
+        .unwrap()
 }
 
 /// Benchmark: Merge with increasing layers
 fn bench_merge_layer_count(c: &mut Criterion) {
     let mut group = c.benchmark_group("merge_layer_count");
     group.sample_size(50);
-    let rt = tokio::runtime::Runtime::new().unwrap();
 
     for num_layers in [10, 50, 100, 500, 1_000, 5_000, 10_000].iter() {
         let (_temp_catalog, catalog) = create_catalog_with_random_params(*num_layers, 3, 5);
-        let (_temp_layers, manager) = rt.block_on(create_layers(*num_layers, &catalog));
+        let evaluator = create_evaluator(*num_layers, catalog);
 
         let request = ExperimentRequest {
             services: vec!["test_service".to_string()],
@@ -117,22 +118,15 @@ fn bench_merge_layer_count(c: &mut Criterion) {
                 .into_iter()
                 .collect(),
             layers: vec![],
+            diagnostics: false,
         };
 
-        let field_types = HashMap::new();
-
         group.bench_with_input(
             BenchmarkId::from_parameter(num_layers),
             num_layers,
             |b, _| {
                 b.iter(|| {
-                    merge_layers_batch(
-                        black_box(&request),
-                        black_box(&manager),
-                        black_box(&catalog),
-                        black_box(&field_types),
-                    )
-                    .unwrap();
+                    evaluator.evaluate(black_box(&request)).unwrap();
                 });
             },
         );
@@ -145,11 +139,10 @@ fn bench_merge_layer_count(c: &mut Criterion) {
 fn bench_merge_param_depth(c: &mut Criterion) {
     let mut group = c.benchmark_group("merge_param_depth");
     group.sample_size(50);
-    let rt = tokio::runtime::Runtime::new().unwrap();
 
     for depth in [1, 2, 3, 5, 8, 10, 15].iter() {
         let (_temp_catalog, catalog) = create_catalog_with_random_params(100, *depth, 5);
-        let (_temp_layers, manager) = rt.block_on(create_layers(100, &catalog));
+        let evaluator = create_evaluator(100, catalog);
 
         let request = ExperimentRequest {
             services: vec!["test_service".to_string()],
@@ -157,22 +150,15 @@ fn bench_merge_param_depth(c: &mut Criterion) {
                 .into_iter()
                 .collect(),
             layers: vec![],
+            diagnostics: false,
         };
 
-        let field_types = HashMap::new();
-
         group.bench_with_input(
             BenchmarkId::from_parameter(depth),
             depth,
             |b, _| {
                 b.iter(|| {
-                    merge_layers_batch(
-                        black_box(&request),
-                        black_box(&manager),
-                        black_box(&catalog),
-                        black_box(&field_types),
-                    )
-                    .unwrap();
+                    evaluator.evaluate(black_box(&request)).unwrap();
                 });
             },
         );
@@ -185,11 +171,10 @@ fn bench_merge_param_depth(c: &mut Criterion) {
 fn bench_merge_param_width(c: &mut Criterion) {
     let mut group = c.benchmark_group("merge_param_width");
     group.sample_size(50);
-    let rt = tokio::runtime::Runtime::new().unwrap();
 
     for width in [5, 10, 20, 50, 100].iter() {
         let (_temp_catalog, catalog) = create_catalog_with_random_params(100, 3, *width);
-        let (_temp_layers, manager) = rt.block_on(create_layers(100, &catalog));
+        let evaluator = create_evaluator(100, catalog);
 
         let request = ExperimentRequest {
             services: vec!["test_service".to_string()],
@@ -197,22 +182,15 @@ fn bench_merge_param_width(c: &mut Criterion) {
                 .into_iter()
                 .collect(),
             layers: vec![],
+            diagnostics: false,
         };
 
-        let field_types = HashMap::new();
-
         group.bench_with_input(
             BenchmarkId::from_parameter(width),
             width,
             |b, _| {
                 b.iter(|| {
-                    merge_layers_batch(
-                        black_box(&request),
-                        black_box(&manager),
-                        black_box(&catalog),
-                        black_box(&field_types),
-                    )
-                    .unwrap();
+                    evaluator.evaluate(black_box(&request)).unwrap();
                 });
             },
         );
@@ -225,9 +203,8 @@ fn bench_merge_param_width(c: &mut Criterion) {
 fn bench_extreme_param_merge(c: &mut Criterion) {
     let mut group = c.benchmark_group("extreme_param_merge");
     group.sample_size(20);
-    let rt = tokio::runtime::Runtime::new().unwrap();
 
-    let test_cases = vec![
+    let test_cases = [
         ("small", 10, 2, 5),
         ("medium", 50, 3, 10),
         ("large", 100, 4, 15),
@@ -238,7 +215,7 @@ fn bench_extreme_param_merge(c: &mut Criterion) {
 
     for (label, num_layers, depth, width) in test_cases.iter() {
         let (_temp_catalog, catalog) = create_catalog_with_random_params(*num_layers, *depth, *width);
-        let (_temp_layers, manager) = rt.block_on(create_layers(*num_layers, &catalog));
+        let evaluator = create_evaluator(*num_layers, catalog);
 
         let request = ExperimentRequest {
             services: vec!["test_service".to_string()],
@@ -246,22 +223,15 @@ fn bench_extreme_param_merge(c: &mut Criterion) {
                 .into_iter()
                 .collect(),
             layers: vec![],
+            diagnostics: false,
         };
 
-        let field_types = HashMap::new();
-
         group.bench_with_input(
             BenchmarkId::from_parameter(label),
             label,
             |b, _| {
                 b.iter(|| {
-                    merge_layers_batch(
-                        black_box(&request),
-                        black_box(&manager),
-                        black_box(&catalog),
-                        black_box(&field_types),
-                    )
-                    .unwrap();
+                    evaluator.evaluate(black_box(&request)).unwrap();
                 });
             },
         );
@@ -273,7 +243,6 @@ fn bench_extreme_param_merge(c: &mut Criterion) {
 /// Benchmark: Merge with conflicting keys (override scenarios)
 fn bench_merge_conflicts(c: &mut Criterion) {
     let mut group = c.benchmark_group("merge_conflicts");
-    let rt = tokio::runtime::Runtime::new().unwrap();
 
     // Create catalog where all params have overlapping keys
     let temp_dir = TempDir::new().unwrap();
@@ -294,10 +263,15 @@ fn bench_merge_conflicts(c: &mut Criterion) {
             let exp = ExperimentDef {
                 eid: (100 + i) as i64,
                 service: "test_service".to_string(),
+                expires_at: None,
+                state: Default::default(),
+                caps: None,
                 rule: None,
+                exposure_sampling: None,
                 variants: vec![VariantDef {
                     vid: (1000 + i * 10) as i64,
                     params,
+                    dark_params: None,
                 }],
             };
 
@@ -309,7 +283,7 @@ fn bench_merge_conflicts(c: &mut Criterion) {
         }
 
         let catalog = ExperimentCatalog::load_from_dir(experiments_dir.clone()).unwrap();
-        let (_temp_layers, manager) = rt.block_on(create_layers(*num_layers, &catalog));
+        let evaluator = create_evaluator(*num_layers, catalog);
 
         let request = ExperimentRequest {
             services: vec!["test_service".to_string()],
@@ -317,22 +291,15 @@ fn bench_merge_conflicts(c: &mut Criterion) {
                 .into_iter()
                 .collect(),
             layers: vec![],
+            diagnostics: false,
         };
 
-        let field_types = HashMap::new();
-
         group.bench_with_input(
             BenchmarkId::from_parameter(num_layers),
             num_layers,
             |b, _| {
                 b.iter(|| {
-                    merge_layers_batch(
-                        black_box(&request),
-                        black_box(&manager),
-                        black_box(&catalog),
-                        black_box(&field_types),
-                    )
-                    .unwrap();
+                    evaluator.evaluate(black_box(&request)).unwrap();
                 });
             },
         );
@@ -1,5 +1,8 @@
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use experiment_data_plane::rule::compiled::CompiledRule;
 use experiment_data_plane::rule::{FieldType, Node, Op};
+use experiment_data_plane::rule_optimizer::optimize;
+use experiment_data_plane::sim::{seeded_rng, DEFAULT_SEED};
 use rand::Rng;
 use serde_json::json;
 use std::collections::HashMap;
@@ -11,10 +14,11 @@ fn create_nested_rule(depth: usize, seed: usize) -> Node {
             field: format!("field_{}", seed % 20),
             op: Op::Eq,
             values: vec![json!(seed % 100)],
+            collation: None,
         };
     }
 
-    if seed % 2 == 0 {
+    if seed.is_multiple_of(2) {
         Node::And {
             children: vec![
                 create_nested_rule(depth - 1, seed * 2),
@@ -35,7 +39,7 @@ fn create_nested_rule(depth: usize, seed: usize) -> Node {
 
 /// Create random context for rule evaluation
 fn create_random_context(num_fields: usize) -> HashMap<String, serde_json::Value> {
-    let mut rng = rand::thread_rng();
+    let mut rng = seeded_rng(DEFAULT_SEED);
     let mut context = HashMap::new();
 
     for i in 0..num_fields {
@@ -67,13 +71,14 @@ fn bench_simple_rules(c: &mut Criterion) {
     .into_iter()
     .collect();
 
-    let rules = vec![
+    let rules = [
         (
             "eq",
             Node::Field {
                 field: "country".to_string(),
                 op: Op::Eq,
                 values: vec![json!("US")],
+                collation: None,
             },
         ),
         (
@@ -82,6 +87,7 @@ fn bench_simple_rules(c: &mut Criterion) {
                 field: "country".to_string(),
                 op: Op::In,
                 values: vec![json!("US"), json!("CA"), json!("UK")],
+                collation: None,
             },
         ),
         (
@@ -90,6 +96,7 @@ fn bench_simple_rules(c: &mut Criterion) {
                 field: "age".to_string(),
                 op: Op::Gte,
                 values: vec![json!(18)],
+                collation: None,
             },
         ),
     ];
@@ -130,6 +137,19 @@ fn bench_rule_depth(c: &mut Criterion) {
                 });
             },
         );
+
+        // As served: optimized and flattened (see `rule::compiled`)
+        let compiled = CompiledRule::compile(&optimize(&rule));
+        group.bench_with_input(
+            BenchmarkId::new("compiled", depth),
+            depth,
+            |b, _| {
+                b.iter(|| {
+                    compiled.evaluate(black_box(&context), black_box(&field_types))
+                        .unwrap()
+                });
+            },
+        );
     }
 
     group.finish();
@@ -152,6 +172,7 @@ fn bench_rule_width(c: &mut Criterion) {
                 field: format!("field_{}", i),
                 op: Op::Eq,
                 values: vec![json!(i * 10)],
+                collation: None,
             })
             .collect();
 
@@ -167,6 +188,24 @@ fn bench_rule_width(c: &mut Criterion) {
                 });
             },
         );
+
+        // Likely-true conditions first, as authored and as snapshots evaluate it
+        let mixed = Node::And {
+            children: (0..*width)
+                .map(|i| Node::Field {
+                    field: format!("field_{}", i),
+                    op: if i < width / 2 { Op::Neq } else { Op::Eq },
+                    values: vec![json!(i * 10)],
+                    collation: None,
+                })
+                .collect(),
+        };
+        let optimized = optimize(&mixed);
+        for (name, rule) in [("mixed", &mixed), ("mixed_optimized", &optimized)] {
+            group.bench_with_input(BenchmarkId::new(name, width), width, |b, _| {
+                b.iter(|| rule.evaluate(black_box(&context), black_box(&field_types)))
+            });
+        }
     }
 
     group.finish();
@@ -200,11 +239,13 @@ fn bench_complex_patterns(c: &mut Criterion) {
                         field: "country".to_string(),
                         op: Op::Eq,
                         values: vec![json!("US")],
+                        collation: None,
                     },
                     Node::Field {
                         field: "country".to_string(),
                         op: Op::Eq,
                         values: vec![json!("CA")],
+                        collation: None,
                     },
                 ],
             },
@@ -212,6 +253,7 @@ fn bench_complex_patterns(c: &mut Criterion) {
                 field: "age".to_string(),
                 op: Op::Gte,
                 values: vec![json!(18)],
+                collation: None,
             },
         ],
     };
@@ -227,11 +269,13 @@ fn bench_complex_patterns(c: &mut Criterion) {
                                 field: "country".to_string(),
                                 op: Op::In,
                                 values: vec![json!("US"), json!("CA"), json!("UK")],
+                                collation: None,
                             },
                             Node::Field {
                                 field: "age".to_string(),
                                 op: Op::Gte,
                                 values: vec![json!(18)],
+                                collation: None,
                             },
                         ],
                     },
@@ -239,6 +283,7 @@ fn bench_complex_patterns(c: &mut Criterion) {
                         field: "premium".to_string(),
                         op: Op::Eq,
                         values: vec![json!(true)],
+                        collation: None,
                     },
                 ],
             },
@@ -246,11 +291,12 @@ fn bench_complex_patterns(c: &mut Criterion) {
                 field: "score".to_string(),
                 op: Op::Gt,
                 values: vec![json!(70)],
+                collation: None,
             },
         ],
     };
 
-    let patterns = vec![("nested_and_or", pattern1), ("complex_nested", pattern2)];
+    let patterns = [("nested_and_or", pattern1), ("complex_nested", pattern2)];
 
     for (name, rule) in patterns.iter() {
         group.bench_with_input(BenchmarkId::from_parameter(name), name, |b, _| {
@@ -0,0 +1,401 @@
+//! Typed async client for the experiment data plane HTTP API.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), experiment_client::ClientError> {
+//! use experiment_client::{Client, EvaluateRequest};
+//!
+//! let client = Client::builder("http://experiment-data-plane:8080")
+//!     .with_sdk_key("my-service-key")
+//!     .build()?;
+//! let response = client
+//!     .evaluate(&EvaluateRequest::new(["ranking"]).with_context("user_id", "u123"))
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! One [`Client`] should be shared per process: it keeps a pool of
+//! keep-alive connections and is cheap to clone. Read-only calls are retried
+//! with exponential backoff on connection errors, timeouts and 429/502/503/504
+//! responses; admin mutations are sent once.
+
+mod types;
+
+pub use types::{Assignment, ConfigSource, EvaluateRequest, EvaluateResponse, Explanation, ServiceResult};
+
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use types::{LayerList, SwitchedSource};
+
+/// Header carrying the SDK key when the server sets `SDK_KEYS_FILE`
+pub const SDK_KEY_HEADER: &str = "x-sdk-key";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("Invalid client configuration: {0}")]
+    Config(String),
+
+    #[error("Request failed: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    /// The server answered with a non-success status; `message` is its `error` field
+    #[error("Server returned {status}: {message}")]
+    Status { status: StatusCode, message: String },
+}
+
+impl ClientError {
+    /// Worth retrying: the server may succeed on another attempt
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ClientError::Config(_) => false,
+            ClientError::Transport(e) => e.is_connect() || e.is_timeout() || e.is_request(),
+            ClientError::Status { status, .. } => matches!(
+                *status,
+                StatusCode::TOO_MANY_REQUESTS
+                    | StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+pub struct ClientBuilder {
+    base_url: String,
+    sdk_key: Option<String>,
+    timeout: Duration,
+    connect_timeout: Duration,
+    max_retries: u32,
+    retry_backoff: Duration,
+    pool_max_idle_per_host: usize,
+    batch_concurrency: usize,
+}
+
+impl ClientBuilder {
+    pub fn with_sdk_key(mut self, key: impl Into<String>) -> Self {
+        self.sdk_key = Some(key.into());
+        self
+    }
+
+    /// Per-attempt timeout (default 1s)
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Retries after the first attempt of read-only calls (default 2, 0 = none)
+    pub fn with_max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Delay before the first retry, doubled for each further one (default 50ms)
+    pub fn with_retry_backoff(mut self, backoff: Duration) -> Self {
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// Idle keep-alive connections kept per host (default 32)
+    pub fn with_pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = max;
+        self
+    }
+
+    /// Requests of one [`Client::evaluate_batch`] in flight at once (default 8)
+    pub fn with_batch_concurrency(mut self, concurrency: usize) -> Self {
+        self.batch_concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn build(self) -> Result<Client> {
+        let base_url = self.base_url.trim_end_matches('/').to_string();
+        if !(base_url.starts_with("http://") || base_url.starts_with("https://")) {
+            return Err(ClientError::Config(format!(
+                "Base URL must start with http:// or https://: {}",
+                self.base_url
+            )));
+        }
+        let http = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .connect_timeout(self.connect_timeout)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .build()?;
+        Ok(Client {
+            http,
+            base_url,
+            sdk_key: self.sdk_key,
+            max_retries: self.max_retries,
+            retry_backoff: self.retry_backoff,
+            batch_concurrency: self.batch_concurrency,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    sdk_key: Option<String>,
+    max_retries: u32,
+    retry_backoff: Duration,
+    batch_concurrency: usize,
+}
+
+impl Client {
+    pub fn builder(base_url: impl Into<String>) -> ClientBuilder {
+        ClientBuilder {
+            base_url: base_url.into(),
+            sdk_key: None,
+            timeout: Duration::from_secs(1),
+            connect_timeout: Duration::from_millis(500),
+            max_retries: 2,
+            retry_backoff: Duration::from_millis(50),
+            pool_max_idle_per_host: 32,
+            batch_concurrency: 8,
+        }
+    }
+
+    /// `POST /experiment`
+    pub async fn evaluate(&self, request: &EvaluateRequest) -> Result<EvaluateResponse> {
+        self.send(Method::POST, "/experiment", Some(request), true).await
+    }
+
+    /// Evaluate several requests (e.g. one per subject), at most
+    /// `batch_concurrency` at a time. Results are in request order.
+    pub async fn evaluate_batch(&self, requests: Vec<EvaluateRequest>) -> Result<Vec<EvaluateResponse>> {
+        let mut responses: Vec<Option<EvaluateResponse>> = vec![None; requests.len()];
+        let mut pending = requests.into_iter().enumerate();
+        let mut in_flight = JoinSet::new();
+
+        loop {
+            while in_flight.len() < self.batch_concurrency {
+                let Some((i, request)) = pending.next() else {
+                    break;
+                };
+                let client = self.clone();
+                in_flight.spawn(async move { (i, client.evaluate(&request).await) });
+            }
+            let Some(joined) = in_flight.join_next().await else {
+                break;
+            };
+            let (i, response) = joined.map_err(|e| ClientError::Config(format!("Batch task failed: {}", e)))?;
+            // Dropping the set aborts the remaining requests
+            responses[i] = Some(response?);
+        }
+
+        Ok(responses.into_iter().flatten().collect())
+    }
+
+    /// Which layer, experiment and variant `subject` is assigned for `service`
+    /// (`GET /subjects/{subject}/assignments`). `context` feeds layer and
+    /// experiment rules; values are sent as strings.
+    pub async fn explain(&self, service: &str, subject: &str, context: &[(&str, &str)]) -> Result<Explanation> {
+        let mut query = vec![("service", service)];
+        query.extend_from_slice(context);
+        let path = format!("/subjects/{}/assignments", encode_path_segment(subject));
+        self.execute(|| self.request(Method::GET, &path).query(&query), true)
+            .await
+    }
+
+    /// `GET /ready`: whether the server has a valid config and is serving
+    pub async fn ready(&self) -> Result<bool> {
+        match self.send::<Value, ()>(Method::GET, "/ready", None, false).await {
+            Ok(_) => Ok(true),
+            Err(ClientError::Status { status, .. }) if status == StatusCode::SERVICE_UNAVAILABLE => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// `GET /layers`: ids of all loaded layers
+    pub async fn list_layers(&self) -> Result<Vec<String>> {
+        let list: LayerList = self.send::<_, ()>(Method::GET, "/layers", None, true).await?;
+        Ok(list.layers)
+    }
+
+    /// `GET /layers/{layer_id}`: the layer document as loaded
+    pub async fn get_layer(&self, layer_id: &str) -> Result<Value> {
+        let path = format!("/layers/{}", encode_path_segment(layer_id));
+        self.send::<_, ()>(Method::GET, &path, None, true).await
+    }
+
+    /// `POST /layers/{layer_id}/rollback`
+    pub async fn rollback_layer(&self, layer_id: &str) -> Result<()> {
+        let path = format!("/layers/{}/rollback", encode_path_segment(layer_id));
+        self.send::<Value, ()>(Method::POST, &path, None, false).await?;
+        Ok(())
+    }
+
+    /// `GET /admin/config_source`
+    pub async fn config_source(&self) -> Result<ConfigSource> {
+        self.send::<_, ()>(Method::GET, "/admin/config_source", None, true).await
+    }
+
+    /// `POST /admin/config_source`; returns the source now in effect
+    pub async fn switch_config_source(&self, source: &ConfigSource) -> Result<ConfigSource> {
+        let switched: SwitchedSource = self
+            .send(Method::POST, "/admin/config_source", Some(source), false)
+            .await?;
+        Ok(switched.source)
+    }
+
+    /// `POST /admin/freeze`: allow (or stop allowing) config changes during
+    /// freeze windows; returns the freeze status
+    pub async fn set_freeze_override(&self, enabled: bool) -> Result<Value> {
+        let body = serde_json::json!({ "override": enabled });
+        self.send(Method::POST, "/admin/freeze", Some(&body), false).await
+    }
+
+    async fn send<T: DeserializeOwned, B: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+        retry: bool,
+    ) -> Result<T> {
+        self.execute(
+            || {
+                let request = self.request(method.clone(), path);
+                match body {
+                    Some(body) => request.json(body),
+                    None => request,
+                }
+            },
+            retry,
+        )
+        .await
+    }
+
+    async fn execute<T: DeserializeOwned>(&self, build: impl Fn() -> RequestBuilder, retry: bool) -> Result<T> {
+        let max_retries = if retry { self.max_retries } else { 0 };
+        let mut backoff = self.retry_backoff;
+        let mut attempt = 0;
+        loop {
+            match attempt_once(build()).await {
+                Err(e) if attempt < max_retries && e.is_transient() => {
+                    tracing::debug!("Retrying data plane request after {:?}: {}", backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self.http.request(method, format!("{}{}", self.base_url, path));
+        match &self.sdk_key {
+            Some(key) => request.header(SDK_KEY_HEADER, key),
+            None => request,
+        }
+    }
+}
+
+async fn attempt_once<T: DeserializeOwned>(request: RequestBuilder) -> Result<T> {
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response.json().await?);
+    }
+    // Errors come back as `{"error": "..."}`; fall back to the raw body
+    let body = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<Value>(&body)
+        .ok()
+        .and_then(|v| v.get("error").and_then(Value::as_str).map(str::to_string))
+        .unwrap_or(body);
+    Err(ClientError::Status { status, message })
+}
+
+/// Percent-encode characters that would change the meaning of a path segment
+fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode as AxumStatus;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_evaluate_retries_unavailable() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        let app = Router::new().route(
+            "/experiment",
+            post(move |Json(request): Json<EvaluateRequest>| async move {
+                // First attempt hits a draining instance
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return Err((AxumStatus::SERVICE_UNAVAILABLE, Json(serde_json::json!({"error": "draining"}))));
+                }
+                let results = request
+                    .services
+                    .iter()
+                    .map(|service| {
+                        let result = ServiceResult {
+                            parameters: serde_json::json!({"user": request.context["user_id"]}),
+                            vids: vec![101],
+                            matched_layers: vec!["l1".to_string()],
+                            truncated: false,
+                            excluded: false,
+                            diagnostics: None,
+                        };
+                        (service.clone(), result)
+                    })
+                    .collect();
+                Ok(Json(EvaluateResponse { results }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = Client::builder(format!("http://{}/", addr))
+            .with_retry_backoff(Duration::from_millis(1))
+            .build()
+            .unwrap();
+        let response = client
+            .evaluate(&EvaluateRequest::new(["svc"]).with_context("user_id", "u1"))
+            .await
+            .unwrap();
+        assert_eq!(response.results["svc"].vids, vec![101]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let batch = (0..5)
+            .map(|i| EvaluateRequest::new(["svc"]).with_context("user_id", format!("u{}", i)))
+            .collect();
+        let responses = client.evaluate_batch(batch).await.unwrap();
+        assert_eq!(responses.len(), 5);
+        assert_eq!(responses[3].results["svc"].parameters, serde_json::json!({"user": "u3"}));
+
+        let err = Client::builder(format!("http://{}", addr))
+            .with_max_retries(0)
+            .build()
+            .unwrap()
+            .list_layers()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ClientError::Status { status, .. } if status == StatusCode::NOT_FOUND));
+    }
+}
@@ -0,0 +1,106 @@
+//! Wire types of the data plane JSON API.
+//!
+//! Kept independent of the `experiment-data-plane` crate so services only
+//! pull in the HTTP client. Unknown response fields are ignored, so newer
+//! servers stay compatible with older clients.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+/// `POST /experiment` body
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EvaluateRequest {
+    pub services: Vec<String>,
+    pub context: HashMap<String, Value>,
+    /// Restrict evaluation to these layers (all layers when empty)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<String>,
+    /// Ask for skipped-layer counts in each [`ServiceResult::diagnostics`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub diagnostics: bool,
+}
+
+impl EvaluateRequest {
+    pub fn new(services: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            services: services.into_iter().map(Into::into).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_context(mut self, field: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.context.insert(field.into(), value.into());
+        self
+    }
+
+    pub fn with_layers(mut self, layers: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.layers = layers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_diagnostics(mut self) -> Self {
+        self.diagnostics = true;
+        self
+    }
+}
+
+/// Merged parameters and assigned variants of one service
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceResult {
+    pub parameters: Value,
+    /// Parallel to `matched_layers`
+    pub vids: Vec<i64>,
+    #[serde(default)]
+    pub matched_layers: Vec<String>,
+    /// Lower-priority layers were skipped by the server's per-service layer limit
+    #[serde(default)]
+    pub truncated: bool,
+    /// Subject is in the server's "do not experiment" population; use defaults
+    #[serde(default)]
+    pub excluded: bool,
+    /// Layers that didn't contribute, by reason (e.g. `missing_hash_key`,
+    /// `unallocated`, `other_service`), when the request asked for diagnostics
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<BTreeMap<String, u32>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EvaluateResponse {
+    pub results: HashMap<String, ServiceResult>,
+}
+
+/// A subject's assignment in one layer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Assignment {
+    pub layer_id: String,
+    pub eid: i64,
+    pub vid: i64,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// `GET /subjects/{key}/assignments` response
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Explanation {
+    pub subject: String,
+    pub service: String,
+    pub assignments: Vec<Assignment>,
+}
+
+/// Where the data plane loads layers and experiments from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigSource {
+    pub layers_dir: String,
+    pub experiments_dir: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct LayerList {
+    pub layers: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct SwitchedSource {
+    pub source: ConfigSource,
+}
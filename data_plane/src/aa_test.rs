@@ -0,0 +1,453 @@
+//! A/A tests: validating randomization before running real experiments.
+//!
+//! A layer with `aa_test: true` splits its traffic between two variants with
+//! identical params, so any difference between the arms comes from the
+//! assignment pipeline itself; layer sets or catalogs where an enabled A/A
+//! layer's variants serve different params are refused. [`AaTests`] tallies
+//! the assignments served by this instance per arm, together with the
+//! distribution of each context field, and [`AaTests::summary`] tests both for
+//! imbalance: a realized split off its configured shares (sample ratio
+//! mismatch) or a field whose values are not spread evenly across the arms
+//! points at broken bucketing, caching or exposure logging.
+//!
+//! Tallies count evaluations, not distinct subjects, and start over when the
+//! layer's version changes.
+
+use crate::error::{ExperimentError, Result};
+use crate::layer::{Layer, Snapshot};
+use crate::merge::ServiceResult;
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+
+/// p-value below which a split or a field counts as imbalanced
+pub const SIGNIFICANCE: f64 = 0.001;
+
+/// Context fields tallied per layer; fields seen later are ignored
+const MAX_FIELDS: usize = 32;
+
+/// Distinct values tallied per field; further values are pooled as [`OTHER_VALUE`]
+const MAX_FIELD_VALUES: usize = 50;
+
+const OTHER_VALUE: &str = "__other__";
+
+#[derive(Debug, Default)]
+struct Tally {
+    version: String,
+    /// vid -> assignments
+    arms: BTreeMap<i64, u64>,
+    /// field -> value -> vid -> assignments
+    fields: BTreeMap<String, BTreeMap<String, BTreeMap<i64, u64>>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArmSummary {
+    pub vid: i64,
+    pub assignments: u64,
+    /// Share of the layer's assigned buckets configured for this arm
+    pub expected_share: f64,
+    pub realized_share: f64,
+}
+
+/// Independence of a context field's values from the arm (chi-square test)
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldBalance {
+    pub field: String,
+    /// Distinct values tallied
+    pub values: usize,
+    pub chi_square: f64,
+    pub p_value: f64,
+    pub balanced: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AaSummary {
+    pub layer_id: String,
+    pub version: String,
+    /// Whether both arms currently serve equal params
+    pub identical_params: bool,
+    pub arms: Vec<ArmSummary>,
+    /// Sample ratio mismatch test of the realized split
+    pub split_p_value: f64,
+    pub fields: Vec<FieldBalance>,
+    /// Split and every field balanced at [`SIGNIFICANCE`]
+    pub balanced: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct AaTests {
+    /// layer_id -> tally
+    tallies: Mutex<HashMap<String, Tally>>,
+}
+
+impl AaTests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count the assignments of `result` made by A/A test layers
+    pub fn record(&self, snapshot: &Snapshot, context: &HashMap<String, Value>, result: &ServiceResult) {
+        for (layer_id, &vid) in result.matched_layers.iter().zip(&result.vids) {
+            let Some(layer) = snapshot.layer(layer_id).filter(|l| l.aa_test) else {
+                continue;
+            };
+
+            let mut tallies = self.tallies.lock();
+            let tally = tallies.entry(layer_id.clone()).or_default();
+            if tally.version != layer.version {
+                *tally = Tally {
+                    version: layer.version.clone(),
+                    ..Default::default()
+                };
+            }
+            *tally.arms.entry(vid).or_default() += 1;
+
+            for (field, value) in context {
+                // Subject ids are unique per subject: nothing to compare
+                if layer.hash_key.fields().contains(field) {
+                    continue;
+                }
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    Value::Number(n) => n.to_string(),
+                    Value::Bool(b) => b.to_string(),
+                    _ => continue,
+                };
+                if tally.fields.len() >= MAX_FIELDS && !tally.fields.contains_key(field) {
+                    continue;
+                }
+                let values = tally.fields.entry(field.clone()).or_default();
+                let value = if values.len() >= MAX_FIELD_VALUES && !values.contains_key(&value) {
+                    OTHER_VALUE.to_string()
+                } else {
+                    value
+                };
+                *values.entry(value).or_default().entry(vid).or_default() += 1;
+            }
+        }
+    }
+
+    /// Realized split and context balance of an A/A test layer
+    pub fn summary(&self, snapshot: &Snapshot, layer_id: &str) -> Result<AaSummary> {
+        let layer = snapshot
+            .layer(layer_id)
+            .ok_or_else(|| ExperimentError::LayerNotFound(layer_id.to_string()))?;
+        if !layer.aa_test {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "Layer {} is not an A/A test",
+                layer_id
+            )));
+        }
+
+        let tallies = self.tallies.lock();
+        let tally = tallies.get(layer_id).filter(|t| t.version == layer.version);
+        let expected = expected_shares(&layer);
+        let total: u64 = tally.map_or(0, |t| t.arms.values().sum());
+
+        let arms: Vec<ArmSummary> = expected
+            .iter()
+            .map(|(&vid, &expected_share)| {
+                let assignments = tally.and_then(|t| t.arms.get(&vid)).copied().unwrap_or_default();
+                ArmSummary {
+                    vid,
+                    assignments,
+                    expected_share,
+                    realized_share: if total == 0 { 0.0 } else { assignments as f64 / total as f64 },
+                }
+            })
+            .collect();
+
+        let split_chi_square: f64 = arms
+            .iter()
+            .map(|arm| {
+                let expected = arm.expected_share * total as f64;
+                if expected > 0.0 {
+                    (arm.assignments as f64 - expected).powi(2) / expected
+                } else {
+                    0.0
+                }
+            })
+            .sum();
+        let split_p_value = chi_square_p_value(split_chi_square, arms.len().saturating_sub(1));
+
+        let vids: Vec<i64> = expected.keys().copied().collect();
+        let fields: Vec<FieldBalance> = tally
+            .map(|t| &t.fields)
+            .into_iter()
+            .flatten()
+            .map(|(field, values)| {
+                let (chi_square, dof) = independence(values, &vids);
+                let p_value = chi_square_p_value(chi_square, dof);
+                FieldBalance {
+                    field: field.clone(),
+                    values: values.len(),
+                    chi_square,
+                    p_value,
+                    balanced: p_value >= SIGNIFICANCE,
+                }
+            })
+            .collect();
+
+        let catalog = snapshot.catalog();
+        let mut params = vids.iter().map(|vid| catalog.get_variant(*vid).map(|v| v.3));
+        let identical_params = match (params.next().flatten(), params.next().flatten()) {
+            (Some(a), Some(b)) => *a == *b,
+            _ => false,
+        };
+
+        Ok(AaSummary {
+            layer_id: layer.layer_id.clone(),
+            version: layer.version.clone(),
+            identical_params,
+            balanced: split_p_value >= SIGNIFICANCE && fields.iter().all(|f| f.balanced),
+            arms,
+            split_p_value,
+            fields,
+        })
+    }
+}
+
+/// vid -> share of the layer's assigned buckets
+fn expected_shares(layer: &Layer) -> BTreeMap<i64, f64> {
+    let mut buckets: BTreeMap<i64, u32> = BTreeMap::new();
+    for range in &layer.ranges {
+        *buckets.entry(range.vid).or_default() += range.end - range.start;
+    }
+    let total: u32 = buckets.values().sum();
+    buckets
+        .into_iter()
+        .map(|(vid, n)| (vid, if total == 0 { 0.0 } else { n as f64 / total as f64 }))
+        .collect()
+}
+
+/// Chi-square statistic and degrees of freedom of a value × arm contingency table
+fn independence(values: &BTreeMap<String, BTreeMap<i64, u64>>, vids: &[i64]) -> (f64, usize) {
+    let count = |arms: &BTreeMap<i64, u64>, vid: &i64| arms.get(vid).copied().unwrap_or_default() as f64;
+    let arm_totals: Vec<f64> = vids.iter().map(|vid| values.values().map(|arms| count(arms, vid)).sum()).collect();
+    let total: f64 = arm_totals.iter().sum();
+    if total == 0.0 {
+        return (0.0, 0);
+    }
+
+    let mut chi_square = 0.0;
+    for arms in values.values() {
+        let value_total: f64 = vids.iter().map(|vid| count(arms, vid)).sum();
+        for (vid, arm_total) in vids.iter().zip(&arm_totals) {
+            let expected = value_total * arm_total / total;
+            if expected > 0.0 {
+                chi_square += (count(arms, vid) - expected).powi(2) / expected;
+            }
+        }
+    }
+    let dof = values.len().saturating_sub(1) * vids.len().saturating_sub(1);
+    (chi_square, dof)
+}
+
+/// P(X >= x) for X chi-square distributed with `dof` degrees of freedom
+fn chi_square_p_value(x: f64, dof: usize) -> f64 {
+    if dof == 0 || x <= 0.0 {
+        return 1.0;
+    }
+    upper_gamma_regularized(dof as f64 / 2.0, x / 2.0).clamp(0.0, 1.0)
+}
+
+/// Q(a, x): series below `a + 1`, continued fraction above
+fn upper_gamma_regularized(a: f64, x: f64) -> f64 {
+    let prefix = (a * x.ln() - x - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        let mut n = a;
+        for _ in 0..500 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term < sum * 1e-15 {
+                break;
+            }
+        }
+        return 1.0 - sum * prefix;
+    }
+
+    // Modified Lentz
+    const TINY: f64 = 1e-300;
+    let mut b = x + 1.0 - a;
+    let mut c = 1.0 / TINY;
+    let mut d = 1.0 / b;
+    let mut h = d;
+    for i in 1..500 {
+        let an = -(i as f64) * (i as f64 - a);
+        b += 2.0;
+        d = an * d + b;
+        if d.abs() < TINY {
+            d = TINY;
+        }
+        c = b + an / c;
+        if c.abs() < TINY {
+            c = TINY;
+        }
+        d = 1.0 / d;
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+    prefix * h
+}
+
+/// Lanczos approximation (g = 7)
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        return (PI / (PI * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let sum = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .skip(1)
+        .fold(COEFFICIENTS[0], |sum, (i, c)| sum + c / (x + i as f64));
+    let t = x + 7.5;
+    0.5 * (2.0 * PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{ExperimentCatalog, ExperimentDef};
+    use crate::engine::Evaluator;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_chi_square_p_values() {
+        assert!((chi_square_p_value(3.841, 1) - 0.05).abs() < 1e-3);
+        assert!((chi_square_p_value(5.991, 2) - 0.05).abs() < 1e-3);
+        assert!((chi_square_p_value(18.307, 10) - 0.05).abs() < 1e-3);
+        assert_eq!(chi_square_p_value(0.0, 3), 1.0);
+    }
+
+    #[test]
+    fn test_summary_flags_imbalanced_field() {
+        let catalog = ExperimentCatalog::from_experiments(
+            vec![ExperimentDef::from_value(json!({
+                "eid": 100,
+                "service": "svc",
+                "variants": [{"vid": 101, "params": {"x": 1}}, {"vid": 102, "params": {"x": 1}}]
+            }))
+            .unwrap()],
+            PathBuf::new(),
+        )
+        .unwrap();
+        let layer = Layer::from_value(
+            json!({
+                "layer_id": "aa",
+                "version": "v1",
+                "priority": 1,
+                "hash_key": "user_id",
+                "enabled": true,
+                "aa_test": true,
+                "ranges": [{"start": 0, "end": 5000, "vid": 101}, {"start": 5000, "end": 10000, "vid": 102}]
+            }),
+            false,
+        )
+        .unwrap();
+        let evaluator = Evaluator::builder().with_catalog(catalog).with_layers([layer]).build().unwrap();
+        let snapshot = evaluator.layer_manager().snapshot();
+
+        let tests = AaTests::new();
+        let result = |vid: i64| ServiceResult {
+            parameters: json!({"x": 1}),
+            vids: vec![vid],
+            matched_layers: vec!["aa".to_string()],
+            truncated: false,
+            excluded: false,
+            dark_parameters: None,
+            unavailable: false,
+            diagnostics: None,
+            trace: None,
+        };
+        for i in 0..400 {
+            let vid = if i % 2 == 0 { 101 } else { 102 };
+            // Every subject of one arm comes from one country: broken randomization
+            let country = if vid == 101 { "US" } else { "CA" };
+            let context = HashMap::from([
+                ("user_id".to_string(), json!(format!("u{}", i))),
+                ("country".to_string(), json!(country)),
+                ("platform".to_string(), json!(["ios", "android"][i / 2 % 2])),
+            ]);
+            tests.record(&snapshot, &context, &result(vid));
+        }
+
+        let summary = tests.summary(&snapshot, "aa").unwrap();
+        assert!(summary.identical_params);
+        assert_eq!(summary.arms.iter().map(|a| a.assignments).collect::<Vec<_>>(), vec![200, 200]);
+        assert!(summary.split_p_value > 0.99);
+        // The hash key is never tallied
+        let fields: Vec<_> = summary.fields.iter().map(|f| (f.field.as_str(), f.balanced)).collect();
+        assert_eq!(fields, vec![("country", false), ("platform", true)]);
+        assert!(!summary.balanced);
+    }
+
+    #[test]
+    fn test_layer_with_different_params_is_rejected() {
+        let catalog = |treatment: Value| {
+            ExperimentCatalog::from_experiments(
+                vec![ExperimentDef::from_value(json!({
+                    "eid": 100,
+                    "service": "svc",
+                    "variants": [{"vid": 101, "params": {"x": 1}}, {"vid": 102, "params": treatment}]
+                }))
+                .unwrap()],
+                PathBuf::new(),
+            )
+            .unwrap()
+        };
+        let layer = Layer::from_value(
+            json!({
+                "layer_id": "aa",
+                "version": "v1",
+                "priority": 1,
+                "hash_key": "user_id",
+                "enabled": true,
+                "aa_test": true,
+                "ranges": [{"start": 0, "end": 5000, "vid": 101}, {"start": 5000, "end": 10000, "vid": 102}]
+            }),
+            false,
+        )
+        .unwrap();
+
+        let err = Evaluator::builder()
+            .with_catalog(catalog(json!({"x": 2})))
+            .with_layers([layer.clone()])
+            .build()
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("vids 101 and 102 whose params differ"), "{}", err);
+        assert!(err.contains("aa"), "{}", err);
+
+        // A catalog change that makes the arms diverge is refused too
+        let evaluator = Evaluator::builder()
+            .with_catalog(catalog(json!({"x": 1})))
+            .with_layers([layer])
+            .build()
+            .unwrap();
+        let diverged = std::sync::Arc::new(catalog(json!({"x": 2})));
+        let err = evaluator.layer_manager().reindex(&diverged).unwrap_err();
+        assert!(err.to_string().contains("whose params differ"), "{}", err);
+    }
+}
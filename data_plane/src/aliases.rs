@@ -0,0 +1,392 @@
+//! Identity aliases: sticky bucketing across a hash key migration.
+//!
+//! When a layer moves from `device_id` to `user_id` (or a subject logs in),
+//! an alias `user_id = u123 -> d456` makes layers hashing on `user_id`
+//! bucket `u123` as `d456`, so the subject keeps the variant it was assigned
+//! under its device key.
+//!
+//! Aliases come from `IDENTITY_ALIASES_FILE` (re-read periodically) and from
+//! the admin API. Precedence:
+//!
+//! - an API alias wins over a file alias for the same field and value
+//! - aliases don't chain: `bucket_as` is hashed as given
+//! - only layers hashing on the aliased field are affected; composite keys
+//!   resolve each part separately
+//!
+//! API aliases live in memory; aliases that must survive a restart belong in the file.
+//!
+//! Separately, an [`IdentityPolicy`] covers the anonymous → identified
+//! transition within a request: callers send both ids, layers hashing on the
+//! stable id fall back to the anonymous id until the subject identifies, and
+//! requests carrying both record a link exposure event for stitching.
+
+use crate::config::migrate;
+use crate::error::{ExperimentError, Result};
+use crate::layer::LayerManager;
+use crate::metrics;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Subjects with `field == value` are bucketed as `bucket_as`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityAlias {
+    pub field: String,
+    pub value: String,
+    pub bucket_as: String,
+}
+
+/// Where an alias was defined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AliasSource {
+    File,
+    Api,
+}
+
+/// An alias in effect, as listed by the admin API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ActiveAlias {
+    #[serde(flatten)]
+    pub alias: IdentityAlias,
+    pub source: AliasSource,
+}
+
+/// Aliases applied during evaluation (published in the layer snapshot)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdentityAliases {
+    /// field -> value -> bucket_as
+    fields: HashMap<String, HashMap<String, String>>,
+}
+
+impl IdentityAliases {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.values().map(HashMap::len).sum()
+    }
+
+    /// Value to bucket on instead of `value`, counted in metrics when aliased
+    pub fn resolve(&self, field: &str, value: &str) -> Option<&str> {
+        let alias = self.fields.get(field)?.get(value)?;
+        metrics::IDENTITY_ALIAS_RESOLUTIONS.with_label_values(&[field]).inc();
+        Some(alias.as_str())
+    }
+}
+
+/// Layers hashing on `stable_field` use `anonymous_field` for subjects
+/// without a stable id (`IDENTITY_STABLE_FIELD` / `IDENTITY_ANONYMOUS_FIELD`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentityPolicy {
+    pub stable_field: String,
+    pub anonymous_field: String,
+}
+
+impl IdentityPolicy {
+    /// Context value of `field`, falling back to the anonymous id for the stable field
+    pub fn lookup<'a>(&self, field: &str, context: &'a HashMap<String, Value>) -> Option<&'a Value> {
+        match context.get(field) {
+            None if field == self.stable_field => context.get(&self.anonymous_field),
+            value => value,
+        }
+    }
+
+    /// `(stable id, anonymous id)` when the request carries both
+    pub fn link(&self, context: &HashMap<String, Value>) -> Option<(String, String)> {
+        let id = |field: &str| match context.get(field)? {
+            Value::String(s) if !s.is_empty() => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        };
+        Some((id(&self.stable_field)?, id(&self.anonymous_field)?))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AliasesFile {
+    aliases: Vec<IdentityAlias>,
+}
+
+/// (field, value) -> bucket_as
+type AliasMap = BTreeMap<(String, String), String>;
+
+/// File and API aliases, merged and pushed into the layer snapshot on every change
+pub struct AliasRegistry {
+    path: Option<PathBuf>,
+    manager: Arc<LayerManager>,
+    /// (file, api)
+    entries: Mutex<(AliasMap, AliasMap)>,
+}
+
+impl AliasRegistry {
+    /// Load `path` (if set) and publish its aliases
+    pub fn load(path: Option<PathBuf>, manager: Arc<LayerManager>) -> Result<Self> {
+        let file = match &path {
+            Some(path) => read_aliases(path)?,
+            None => AliasMap::new(),
+        };
+        if let Some(path) = &path {
+            tracing::info!("Loaded {} identity aliases from {:?}", file.len(), path);
+        }
+        let registry = Self {
+            path,
+            manager,
+            entries: Mutex::new((file, AliasMap::new())),
+        };
+        registry.publish(&registry.entries.lock());
+        Ok(registry)
+    }
+
+    /// Re-read the aliases file; on failure the current aliases stay in effect
+    pub fn reload(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let file = read_aliases(path)?;
+        let mut entries = self.entries.lock();
+        if entries.0 != file {
+            entries.0 = file;
+            self.publish(&entries);
+        }
+        Ok(())
+    }
+
+    /// Add or replace an API alias
+    pub fn link(&self, alias: IdentityAlias) -> Result<()> {
+        validate(&alias)?;
+        let mut entries = self.entries.lock();
+        entries.1.insert((alias.field, alias.value), alias.bucket_as);
+        self.publish(&entries);
+        Ok(())
+    }
+
+    /// Remove an API alias; returns whether one existed
+    pub fn unlink(&self, field: &str, value: &str) -> bool {
+        let mut entries = self.entries.lock();
+        let removed = entries.1.remove(&(field.to_string(), value.to_string())).is_some();
+        if removed {
+            self.publish(&entries);
+        }
+        removed
+    }
+
+    /// Aliases in effect, API aliases shadowing file aliases
+    pub fn list(&self) -> Vec<ActiveAlias> {
+        let entries = self.entries.lock();
+        let mut active: BTreeMap<&(String, String), ActiveAlias> = BTreeMap::new();
+        for (map, source) in [(&entries.0, AliasSource::File), (&entries.1, AliasSource::Api)] {
+            for (key, bucket_as) in map {
+                let alias = IdentityAlias {
+                    field: key.0.clone(),
+                    value: key.1.clone(),
+                    bucket_as: bucket_as.clone(),
+                };
+                active.insert(key, ActiveAlias { alias, source });
+            }
+        }
+        active.into_values().collect()
+    }
+
+    fn publish(&self, (file, api): &(AliasMap, AliasMap)) {
+        let mut aliases = IdentityAliases::default();
+        for ((field, value), bucket_as) in file.iter().chain(api) {
+            aliases
+                .fields
+                .entry(field.clone())
+                .or_default()
+                .insert(value.clone(), bucket_as.clone());
+        }
+        metrics::IDENTITY_ALIASES.set(aliases.len() as i64);
+        self.manager.set_identity_aliases(aliases);
+    }
+}
+
+fn validate(alias: &IdentityAlias) -> Result<()> {
+    if alias.field.is_empty() || alias.value.is_empty() || alias.bucket_as.is_empty() {
+        return Err(ExperimentError::InvalidParameter(
+            "Identity alias needs non-empty `field`, `value` and `bucket_as`".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn read_aliases(path: &PathBuf) -> Result<AliasMap> {
+    let content = std::fs::read_to_string(path)?;
+    let file: AliasesFile = serde_json::from_value(migrate::parse_document(&content)?)?;
+
+    let mut aliases = AliasMap::new();
+    for alias in file.aliases {
+        validate(&alias)?;
+        let key = (alias.field, alias.value);
+        if aliases.contains_key(&key) {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "Duplicate identity alias for {} = {}",
+                key.0, key.1
+            )));
+        }
+        aliases.insert(key, alias.bucket_as);
+    }
+    Ok(aliases)
+}
+
+/// Pick up alias file changes every `interval`
+pub async fn reload_periodically(registry: Arc<AliasRegistry>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        if let Err(e) = registry.reload() {
+            tracing::error!("Failed to reload identity aliases, keeping previous set: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::ExperimentCatalog;
+    use crate::merge::{subject_assignments, ExperimentRequest};
+    use crate::overrides::Overrides;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_alias_keeps_device_assignment_after_login() {
+        let temp_dir = TempDir::new().unwrap();
+        let experiments_dir = temp_dir.path().join("experiments");
+        let layers_dir = temp_dir.path().join("layers");
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::write(
+            experiments_dir.join("100.json"),
+            json!({"eid": 100, "service": "svc", "variants": [{"vid": 101, "params": {}}, {"vid": 102, "params": {}}]})
+                .to_string(),
+        )
+        .unwrap();
+        // Same salt under both keys: only the hashed value decides the bucket
+        for (layer_id, hash_key) in [("by_device", "device_id"), ("by_user", "user_id")] {
+            std::fs::write(
+                layers_dir.join(format!("{}.json", layer_id)),
+                json!({"layer_id": layer_id, "version": "v1", "priority": 1, "hash_key": hash_key, "salt": "s",
+                       "enabled": true, "ranges": [{"start": 0, "end": 5000, "vid": 101}, {"start": 5000, "end": 10000, "vid": 102}]})
+                .to_string(),
+            )
+            .unwrap();
+        }
+
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+        let manager = Arc::new(LayerManager::new(layers_dir));
+        manager.load_all_layers(&catalog).await.unwrap();
+
+        let vid_in = |layer_id: &str, field: &str, value: &str| {
+            let request = ExperimentRequest {
+                services: vec!["svc".to_string()],
+                context: HashMap::from([(field.to_string(), json!(value))]),
+                layers: vec![],
+                diagnostics: false,
+            };
+            let assignments = subject_assignments("svc", value, request.context, &Overrides::new(), &manager.snapshot(), &HashMap::new());
+            assignments.into_iter().find(|a| a.layer_id == layer_id).unwrap().vid
+        };
+        // Find a device and a user landing in different variants
+        let device_vid = vid_in("by_device", "device_id", "d1");
+        let unaliased_device = |u: &str| vid_in("by_device", "device_id", u);
+        let user = (0..100)
+            .map(|i| format!("u{}", i))
+            .find(|u| vid_in("by_user", "user_id", u) != device_vid)
+            .unwrap();
+
+        let aliases_path = temp_dir.path().join("aliases.yaml");
+        std::fs::write(&aliases_path, format!("aliases:\n  - {{field: user_id, value: {}, bucket_as: d1}}\n", user)).unwrap();
+        let before = unaliased_device(&user);
+        let registry = AliasRegistry::load(Some(aliases_path.clone()), manager.clone()).unwrap();
+        assert_eq!(vid_in("by_user", "user_id", &user), device_vid);
+        // Layers hashing on other fields are unaffected
+        assert_eq!(unaliased_device(&user), before);
+
+        // API aliases shadow file aliases; unlinking restores the file alias
+        registry
+            .link(IdentityAlias {
+                field: "user_id".to_string(),
+                value: user.clone(),
+                bucket_as: user.clone(),
+            })
+            .unwrap();
+        assert_ne!(vid_in("by_user", "user_id", &user), device_vid);
+        assert_eq!(registry.list()[0].source, AliasSource::Api);
+        assert!(registry.unlink("user_id", &user));
+        assert_eq!(vid_in("by_user", "user_id", &user), device_vid);
+
+        // A broken file keeps the previous aliases
+        std::fs::write(&aliases_path, "aliases: [{field: user_id}]").unwrap();
+        assert!(registry.reload().is_err());
+        assert_eq!(vid_in("by_user", "user_id", &user), device_vid);
+    }
+
+    #[test]
+    fn test_anonymous_id_fallback_and_link_event() {
+        let catalog = ExperimentCatalog::from_experiments(
+            vec![crate::catalog::ExperimentDef::from_value(
+                json!({"eid": 100, "service": "svc", "variants": [{"vid": 101, "params": {}}, {"vid": 102, "params": {}}]}),
+            )
+            .unwrap()],
+            std::path::PathBuf::new(),
+        )
+        .unwrap();
+        let layer = crate::layer::Layer::from_value(
+            json!({"layer_id": "by_user", "version": "v1", "priority": 1, "hash_key": "user_id", "enabled": true,
+                   "ranges": [{"start": 0, "end": 5000, "vid": 101}, {"start": 5000, "end": 10000, "vid": 102}]}),
+            false,
+        )
+        .unwrap();
+        let manager = Arc::new(LayerManager::new(std::path::PathBuf::new()).with_identity_policy(IdentityPolicy {
+            stable_field: "user_id".to_string(),
+            anonymous_field: "anonymous_id".to_string(),
+        }));
+        let evaluator = crate::engine::Evaluator::builder()
+            .with_catalog(catalog)
+            .with_layer_manager(manager.clone())
+            .with_layers([layer])
+            .build()
+            .unwrap();
+
+        let evaluate = |context: &[(&str, &str)]| {
+            let request = ExperimentRequest {
+                services: vec!["svc".to_string()],
+                context: context.iter().map(|(k, v)| (k.to_string(), json!(v))).collect(),
+                layers: vec![],
+                diagnostics: false,
+            };
+            let response = evaluator.evaluate(&request).unwrap();
+            let events = crate::exposure::events_for(&request, &response, None, &manager.snapshot());
+            (response.results["svc"].vids.clone(), events)
+        };
+
+        // Before login the anonymous id buckets like a user id of the same value
+        let (anonymous, events) = evaluate(&[("anonymous_id", "a1")]);
+        assert_eq!(anonymous, evaluate(&[("user_id", "a1")]).0);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].subject, "a1");
+
+        // After login the stable id wins and the two ids are linked
+        let user = (0..100).map(|i| format!("u{}", i)).find(|u| evaluate(&[("user_id", u)]).0 != anonymous).unwrap();
+        let (identified, events) = evaluate(&[("anonymous_id", "a1"), ("user_id", &user)]);
+        assert_ne!(identified, anonymous);
+        let link = events.iter().find(|e| e.link.is_some()).unwrap();
+        assert_eq!(link.subject, user);
+        assert_eq!(
+            link.link,
+            Some(crate::exposure::IdentityLink {
+                field: "anonymous_id".to_string(),
+                value: "a1".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Append-only log of applied config resources.
+//!
+//! Subscribed to the engine event bus ([`crate::events`]): every snapshot swap
+//! that adds, modifies or removes a layer or experiment appends one JSON line per resource to `applied.log` under `APPLIED_LOG_DIR`:
+//! id, version, content hash, the file it came from and when it went live.
+//! Files rotate at `APPLIED_LOG_MAX_BYTES` (`applied.log.1` is the newest
+//! rotated file) and at most `APPLIED_LOG_MAX_FILES` rotated files are kept.
+//!
+//! `GET /audit/applied` lists entries, or with `at` replays them to answer
+//! "what config was live at 14:32".
+
+use crate::error::Result;
+use crate::events::{self, EngineEvent, EventBus};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use xxhash_rust::xxh3::xxh3_64;
+
+const LOG_FILE: &str = "applied.log";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AppliedChange {
+    Added,
+    Modified,
+    Removed,
+}
+
+/// One resource change as it went live
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppliedEntry {
+    /// Unix timestamp (seconds) of the snapshot swap
+    pub at: u64,
+    pub epoch: u64,
+    /// `layer` or `experiment`
+    pub resource: String,
+    /// layer_id or eid
+    pub id: String,
+    pub change: AppliedChange,
+    /// Layer version (absent for experiments and removals)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// xxh3-64 of the applied definition, lowercase hex (absent for removals)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// File or directory the resource was loaded from
+    pub source: String,
+}
+
+/// Content hash recorded for a definition
+pub fn content_hash(value: &impl Serialize) -> String {
+    format!("{:016x}", xxh3_64(&serde_json::to_vec(value).unwrap_or_default()))
+}
+
+/// Filter for [`AppliedLog::query`]
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AppliedQuery {
+    /// Unix seconds; `GET /audit/applied` then returns what was live at that time
+    pub at: Option<u64>,
+    /// Unix seconds, inclusive
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub resource: Option<String>,
+    pub id: Option<String>,
+    /// Newest entries kept when more match (default 1000)
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Writer {
+    file: File,
+    size: u64,
+}
+
+#[derive(Debug)]
+pub struct AppliedLog {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    writer: Mutex<Writer>,
+}
+
+impl AppliedLog {
+    pub fn open(dir: PathBuf, max_bytes: u64, max_files: usize) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let file = open_append(&dir.join(LOG_FILE))?;
+        let size = file.metadata()?.len();
+        tracing::info!("Recording applied config changes under {:?}", dir);
+
+        Ok(Self {
+            dir,
+            max_bytes,
+            max_files,
+            writer: Mutex::new(Writer { file, size }),
+        })
+    }
+
+    /// Append entries; failures are logged, never fail the apply
+    pub fn append(&self, entries: &[AppliedEntry]) {
+        if entries.is_empty() {
+            return;
+        }
+        let mut writer = self.writer.lock();
+        if let Err(e) = self.write(&mut writer, entries) {
+            tracing::error!("Failed to record applied config changes in {:?}: {}", self.dir, e);
+        }
+    }
+
+    fn write(&self, writer: &mut Writer, entries: &[AppliedEntry]) -> Result<()> {
+        if self.max_bytes > 0 && writer.size >= self.max_bytes {
+            self.rotate(writer)?;
+        }
+
+        let mut buf = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut buf, entry)?;
+            buf.push(b'\n');
+        }
+        writer.file.write_all(&buf)?;
+        writer.size += buf.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&self, writer: &mut Writer) -> Result<()> {
+        let rotated = |n: usize| self.dir.join(format!("{}.{}", LOG_FILE, n));
+        let _ = std::fs::remove_file(rotated(self.max_files));
+        for n in (1..self.max_files).rev() {
+            let from = rotated(n);
+            if from.exists() {
+                std::fs::rename(from, rotated(n + 1))?;
+            }
+        }
+        let current = self.dir.join(LOG_FILE);
+        if self.max_files > 0 {
+            std::fs::rename(&current, rotated(1))?;
+        } else {
+            std::fs::remove_file(&current)?;
+        }
+
+        writer.file = open_append(&current)?;
+        writer.size = 0;
+        Ok(())
+    }
+
+    /// Entries matching `query`, oldest first
+    pub fn query(&self, query: &AppliedQuery) -> Result<Vec<AppliedEntry>> {
+        let mut entries: Vec<AppliedEntry> = self
+            .read_all()?
+            .into_iter()
+            .filter(|e| query.since.is_none_or(|since| e.at >= since))
+            .filter(|e| query.until.is_none_or(|until| e.at <= until))
+            .filter(|e| query.resource.as_ref().is_none_or(|r| &e.resource == r))
+            .filter(|e| query.id.as_ref().is_none_or(|id| &e.id == id))
+            .collect();
+
+        let limit = query.limit.unwrap_or(1000);
+        if entries.len() > limit {
+            entries.drain(..entries.len() - limit);
+        }
+        Ok(entries)
+    }
+
+    /// Latest applied entry of each resource still live at `at` (unix seconds),
+    /// keyed by `resource/id`. Resources whose history was rotated away are missing.
+    pub fn live_at(&self, at: u64) -> Result<BTreeMap<String, AppliedEntry>> {
+        let mut live = BTreeMap::new();
+        for entry in self.read_all()?.into_iter().take_while(|e| e.at <= at) {
+            let key = format!("{}/{}", entry.resource, entry.id);
+            if entry.change == AppliedChange::Removed {
+                live.remove(&key);
+            } else {
+                live.insert(key, entry);
+            }
+        }
+        Ok(live)
+    }
+
+    /// All retained entries, oldest first
+    fn read_all(&self) -> Result<Vec<AppliedEntry>> {
+        // Hold the writer so a rotation can't move files mid-read
+        let _writer = self.writer.lock();
+
+        let mut files: Vec<PathBuf> = (1..=self.max_files)
+            .rev()
+            .map(|n| self.dir.join(format!("{}.{}", LOG_FILE, n)))
+            .filter(|path| path.exists())
+            .collect();
+        files.push(self.dir.join(LOG_FILE));
+
+        let mut entries = Vec::new();
+        for path in files {
+            read_entries(&path, &mut entries)?;
+        }
+        Ok(entries)
+    }
+}
+
+/// Record applied resources from `bus` into `log`. Subscribes immediately, so
+/// call before the initial load to capture it.
+pub fn record(log: Arc<AppliedLog>, bus: &EventBus) -> impl std::future::Future<Output = ()> {
+    let receiver = bus.subscribe();
+    events::consume("applied_log", receiver, move |event| {
+        if let EngineEvent::SnapshotApplied { resources, .. } = event {
+            log.append(resources);
+        }
+    })
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn read_entries(path: &Path, entries: &mut Vec<AppliedEntry>) -> Result<()> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        // A torn last line (crash mid-write) is skipped
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => tracing::warn!("Skipping unreadable applied log line in {:?}: {}", path, e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(at: u64, id: &str, change: AppliedChange, version: &str) -> AppliedEntry {
+        AppliedEntry {
+            at,
+            epoch: at,
+            resource: "layer".to_string(),
+            id: id.to_string(),
+            change,
+            version: Some(version.to_string()),
+            hash: None,
+            source: format!("layers/{}.yaml", id),
+        }
+    }
+
+    #[test]
+    fn test_live_at_replays_across_rotation() {
+        let temp_dir = TempDir::new().unwrap();
+        // Tiny limit: every append after the first rotates
+        let log = AppliedLog::open(temp_dir.path().to_path_buf(), 1, 10).unwrap();
+        log.append(&[entry(100, "a", AppliedChange::Added, "v1"), entry(100, "b", AppliedChange::Added, "v1")]);
+        log.append(&[entry(200, "a", AppliedChange::Modified, "v2")]);
+        log.append(&[entry(300, "b", AppliedChange::Removed, "v1")]);
+        assert!(temp_dir.path().join("applied.log.2").exists());
+
+        let live = log.live_at(250).unwrap();
+        assert_eq!(live["layer/a"].version.as_deref(), Some("v2"));
+        assert!(live.contains_key("layer/b"));
+        let live = log.live_at(300).unwrap();
+        assert_eq!(live.keys().collect::<Vec<_>>(), vec!["layer/a"]);
+
+        let query = AppliedQuery {
+            id: Some("a".to_string()),
+            ..Default::default()
+        };
+        let history = log.query(&query).unwrap();
+        assert_eq!(history.iter().map(|e| e.at).collect::<Vec<_>>(), vec![100, 200]);
+    }
+}
@@ -0,0 +1,451 @@
+//! Per-variant exposure caps: a hard limit on distinct subjects in a variant.
+//!
+//! Risky treatments can be limited in blast radius regardless of how much
+//! bucket space their layer gives them:
+//!
+//! ```yaml
+//! eid: 100
+//! service: checkout
+//! caps:
+//!   control_vid: 1001
+//!   max_subjects: {1002: 10000}   # vid -> distinct subjects
+//! variants: [...]
+//! ```
+//!
+//! Subjects are admitted first come, first served and keep their variant
+//! once admitted; after the cap is reached, new subjects bucketed into the
+//! variant get `control_vid` instead. Support overrides bypass caps.
+//!
+//! Admissions live in the shared [`KvStore`] (`KV_BACKEND`): a counter per
+//! capped vid, raised with compare-and-set so instances sharing the backend
+//! never admit past the cap together, and a key per admitted subject and vid,
+//! so admitted subjects keep their variant across instances and restarts. The
+//! in-memory backend is per process and starts empty on restart. Each
+//! instance caches recent decisions, so returning subjects skip the store.
+//!
+//! Merging never waits for the store: a [`CapGate`] answers from the cache
+//! and collects the undecided subjects, serving them control for that pass.
+//! [`crate::engine::Evaluator::evaluate_traced_async`] admits them and
+//! evaluates again; synchronous evaluation serves control and admits them in
+//! the background for later requests. Previews, simulations and
+//! `/subjects/{key}/assignments` show uncapped assignments.
+
+use crate::catalog::{ExperimentCatalog, VariantDef};
+use crate::error::{ExperimentError, Result};
+use crate::kv::KvStore;
+use crate::metrics;
+use lru::LruCache;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+const COUNT_KEY_PREFIX: &str = "variant_cap_count:";
+
+const SUBJECT_KEY_PREFIX: &str = "variant_cap_subject:";
+
+/// Decisions cached per instance (least recently used dropped first)
+const DECISION_CACHE_CAPACITY: usize = 100_000;
+
+/// Compare-and-set attempts before an admission gives up on a contended counter
+const MAX_UPDATE_ATTEMPTS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VariantCaps {
+    /// Variant served to subjects turned away by a full cap
+    pub control_vid: i64,
+    /// vid -> distinct subjects admitted at most
+    pub max_subjects: BTreeMap<i64, u64>,
+}
+
+impl VariantCaps {
+    pub fn validate(&self, variants: &[VariantDef]) -> Result<()> {
+        let known = |vid: i64| variants.iter().any(|v| v.vid == vid);
+        if !known(self.control_vid) {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "caps.control_vid {} is not a variant of this experiment",
+                self.control_vid
+            )));
+        }
+        if self.max_subjects.contains_key(&self.control_vid) {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "caps.control_vid {} cannot itself be capped",
+                self.control_vid
+            )));
+        }
+        if let Some(vid) = self.max_subjects.keys().find(|vid| !known(**vid)) {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "caps.max_subjects lists vid {}, which is not a variant of this experiment",
+                vid
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Admissions to capped variants, kept in the shared [`KvStore`]
+pub struct CapTracker {
+    store: Arc<dyn KvStore>,
+    /// (vid, subject key) -> last decision, so returning subjects skip the store
+    decisions: Mutex<LruCache<(i64, String), Decision>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decision {
+    Admitted,
+    /// Turned away while the cap was `max_subjects`; re-checked if it changes
+    Full { max_subjects: u64 },
+}
+
+impl CapTracker {
+    pub fn new(store: Arc<dyn KvStore>) -> Self {
+        Self {
+            store,
+            decisions: Mutex::new(LruCache::new(
+                NonZeroUsize::new(DECISION_CACHE_CAPACITY).expect("non-zero capacity"),
+            )),
+        }
+    }
+
+    /// Cached decision for `subject` in `vid`; `None` until the store was asked
+    pub fn cached(&self, vid: i64, max_subjects: u64, subject: &str) -> Option<bool> {
+        match self.decisions.lock().get(&(vid, subject.to_string()))? {
+            Decision::Admitted => Some(true),
+            Decision::Full { max_subjects: max } if *max == max_subjects => Some(false),
+            Decision::Full { .. } => None,
+        }
+    }
+
+    /// Admit `candidate` while there is room, caching the decision. If the
+    /// store fails the subject is turned away and nothing is cached.
+    pub async fn admit(&self, candidate: &CapCandidate) -> bool {
+        let CapCandidate {
+            vid,
+            max_subjects,
+            subject,
+        } = candidate;
+        match self.admit_in_store(*vid, *max_subjects, subject).await {
+            Ok(admitted) => {
+                let decision = if admitted {
+                    Decision::Admitted
+                } else {
+                    Decision::Full {
+                        max_subjects: *max_subjects,
+                    }
+                };
+                self.decisions.lock().put((*vid, subject.clone()), decision);
+                admitted
+            }
+            Err(e) => {
+                tracing::warn!("Exposure cap check for vid {} failed, serving control: {}", vid, e);
+                false
+            }
+        }
+    }
+
+    /// Decide every candidate a [`CapGate`] collected
+    pub async fn admit_all(&self, candidates: Vec<CapCandidate>) {
+        for candidate in candidates {
+            self.admit(&candidate).await;
+        }
+    }
+
+    async fn admit_in_store(&self, vid: i64, max_subjects: u64, subject: &str) -> Result<bool> {
+        let subject_key = format!("{}{}:{}", SUBJECT_KEY_PREFIX, vid, subject);
+        if self.store.get(&subject_key).await?.is_some() {
+            return Ok(true);
+        }
+
+        let count_key = count_key(vid);
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let raw = self.store.get(&count_key).await?;
+            let count = parse_count(vid, raw.as_deref())?;
+            if count >= max_subjects {
+                // Another instance may have admitted this subject with the last slot
+                return Ok(self.store.get(&subject_key).await?.is_some());
+            }
+            let next = (count + 1).to_string().into_bytes();
+            if !self.store.compare_and_set(&count_key, raw.as_deref(), next, None).await? {
+                continue;
+            }
+            if !self.store.compare_and_set(&subject_key, None, b"1".to_vec(), None).await? {
+                // Admitted concurrently elsewhere: give the slot back
+                self.release(vid).await?;
+            }
+            return Ok(true);
+        }
+        Err(ExperimentError::Store(format!(
+            "exposure cap counter for vid {} is too contended",
+            vid
+        )))
+    }
+
+    async fn release(&self, vid: i64) -> Result<()> {
+        let count_key = count_key(vid);
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let raw = self.store.get(&count_key).await?;
+            let count = parse_count(vid, raw.as_deref())?;
+            let next = count.saturating_sub(1).to_string().into_bytes();
+            if self.store.compare_and_set(&count_key, raw.as_deref(), next, None).await? {
+                return Ok(());
+            }
+        }
+        Err(ExperimentError::Store(format!(
+            "exposure cap counter for vid {} is too contended",
+            vid
+        )))
+    }
+
+    /// Subjects admitted to `vid` so far, across every instance sharing the store
+    pub async fn admitted(&self, vid: i64) -> Result<u64> {
+        parse_count(vid, self.store.get(&count_key(vid)).await?.as_deref())
+    }
+}
+
+fn count_key(vid: i64) -> String {
+    format!("{}{}", COUNT_KEY_PREFIX, vid)
+}
+
+fn parse_count(vid: i64, raw: Option<&[u8]>) -> Result<u64> {
+    let Some(raw) = raw else {
+        return Ok(0);
+    };
+    std::str::from_utf8(raw)
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .ok_or_else(|| ExperimentError::Store(format!("corrupt exposure cap counter for vid {}", vid)))
+}
+
+/// A capped variant a subject was bucketed into before its admission was decided
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapCandidate {
+    pub vid: i64,
+    pub max_subjects: u64,
+    pub subject: String,
+}
+
+/// Cap decisions for one evaluation pass: cached ones apply, undecided
+/// subjects get control and are collected for [`CapTracker::admit_all`]
+pub struct CapGate<'a> {
+    tracker: &'a CapTracker,
+    pending: Mutex<Vec<CapCandidate>>,
+}
+
+impl<'a> CapGate<'a> {
+    pub fn new(tracker: &'a CapTracker) -> Self {
+        Self {
+            tracker,
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Whether `subject` may be served `vid` in this pass
+    pub fn admits(&self, vid: i64, max_subjects: u64, subject: &str) -> bool {
+        match self.tracker.cached(vid, max_subjects, subject) {
+            Some(true) => true,
+            Some(false) => {
+                metrics::CAPPED_ASSIGNMENTS.with_label_values(&[&vid.to_string()]).inc();
+                false
+            }
+            None => {
+                self.pending.lock().push(CapCandidate {
+                    vid,
+                    max_subjects,
+                    subject: subject.to_string(),
+                });
+                false
+            }
+        }
+    }
+
+    /// Whether every cap this pass met was already decided
+    pub fn is_settled(&self) -> bool {
+        self.pending.lock().is_empty()
+    }
+
+    pub fn into_pending(self) -> Vec<CapCandidate> {
+        self.pending.into_inner()
+    }
+}
+
+/// One capped variant, for `/diagnostics/variant_caps`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CapUsage {
+    pub eid: i64,
+    pub vid: i64,
+    pub control_vid: i64,
+    pub max_subjects: u64,
+    pub admitted: u64,
+}
+
+/// Every capped variant in `catalog` with its admissions so far
+pub async fn usage(catalog: &ExperimentCatalog, tracker: &CapTracker) -> Result<Vec<CapUsage>> {
+    let mut usage = Vec::new();
+    for (eid, caps) in catalog.variant_caps() {
+        for (vid, max_subjects) in &caps.max_subjects {
+            usage.push(CapUsage {
+                eid,
+                vid: *vid,
+                control_vid: caps.control_vid,
+                max_subjects: *max_subjects,
+                admitted: tracker.admitted(*vid).await?,
+            });
+        }
+    }
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::ExperimentDef;
+    use crate::engine::Evaluator;
+    use crate::kv::MemoryStore;
+    use crate::layer::{BucketRange, Layer};
+    use crate::merge::ExperimentRequest;
+    use crate::overrides::Overrides;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    fn capped_evaluator() -> Evaluator {
+        let experiment = ExperimentDef::from_value(json!({
+            "eid": 100,
+            "service": "svc",
+            "caps": {"control_vid": 101, "max_subjects": {"102": 3}},
+            "variants": [
+                {"vid": 101, "params": {"arm": "control"}},
+                {"vid": 102, "params": {"arm": "treatment"}}
+            ]
+        }))
+        .unwrap();
+        Evaluator::builder()
+            .with_catalog(ExperimentCatalog::from_experiments(vec![experiment], PathBuf::new()).unwrap())
+            .with_layers([Layer {
+                layer_id: "l1".to_string(),
+                version: "v1".to_string(),
+                priority: 1,
+                hash_key: "user_id".into(),
+                salt: None,
+                bucket_size: None,
+                expires_at: None,
+                rule: None,
+                services: vec![],
+                ranges: vec![BucketRange {
+                    start: 0,
+                    end: 10000,
+                    vid: 102,
+                    label: None,
+                }],
+                enabled: true,
+                aa_test: false,
+            }])
+            .build()
+            .unwrap()
+    }
+
+    fn request(user: &str) -> ExperimentRequest {
+        ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([("user_id".to_string(), json!(user))]),
+            layers: vec![],
+            diagnostics: false,
+        }
+    }
+
+    fn candidate(max_subjects: u64, subject: &str) -> CapCandidate {
+        CapCandidate {
+            vid: 102,
+            max_subjects,
+            subject: subject.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_full_variant_falls_through_to_control() {
+        let evaluator = capped_evaluator();
+        let overrides = Overrides::new();
+        let mut vids = Vec::new();
+        for i in 0..5 {
+            let response = evaluator
+                .evaluate_traced_async(&request(&format!("u{}", i)), &overrides, false, &evaluator.layer_manager().snapshot())
+                .await
+                .unwrap();
+            vids.push(response.results["svc"].vids[0]);
+        }
+        assert_eq!(vids, vec![102, 102, 102, 101, 101]);
+        // Admitted subjects keep the treatment
+        assert_eq!(evaluator.evaluate(&request("u1")).unwrap().results["svc"].vids[0], 102);
+        assert_eq!(evaluator.cap_tracker().admitted(102).await.unwrap(), 3);
+
+        let invalid = ExperimentDef::from_value(json!({
+            "eid": 200,
+            "service": "svc",
+            "caps": {"control_vid": 201, "max_subjects": {"201": 3}},
+            "variants": [{"vid": 201, "params": {}}]
+        }))
+        .unwrap();
+        let err = ExperimentCatalog::from_experiments(vec![invalid], PathBuf::new()).unwrap_err();
+        assert!(err.to_string().contains("cannot itself be capped"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_sync_evaluation_admits_in_background() {
+        let evaluator = capped_evaluator();
+        let vid = |user: &str| evaluator.evaluate(&request(user)).unwrap().results["svc"].vids[0];
+
+        // Undecided: control for now, admitted for the next request
+        assert_eq!(vid("u1"), 101);
+        tokio::task::yield_now().await;
+        assert_eq!(vid("u1"), 102);
+        assert_eq!(evaluator.cap_tracker().admitted(102).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_admissions_are_shared_and_survive_restart() {
+        let store: Arc<dyn KvStore> = Arc::new(MemoryStore::new());
+        let first = CapTracker::new(store.clone());
+        let second = CapTracker::new(store.clone());
+
+        assert!(first.admit(&candidate(2, "u1")).await);
+        assert!(second.admit(&candidate(2, "u2")).await);
+        // The cap counts both instances' admissions
+        assert!(!first.admit(&candidate(2, "u3")).await);
+        assert!(!second.admit(&candidate(2, "u3")).await);
+        assert_eq!(first.cached(102, 2, "u3"), Some(false));
+        // Subjects admitted by one instance keep the variant on the other
+        assert_eq!(second.cached(102, 2, "u1"), None);
+        assert!(second.admit(&candidate(2, "u1")).await);
+        assert_eq!(second.cached(102, 2, "u1"), Some(true));
+        assert_eq!(second.admitted(102).await.unwrap(), 2);
+
+        // A restarted instance starts with an empty cache but the same admissions
+        drop(first);
+        let restarted = CapTracker::new(store);
+        assert!(restarted.admit(&candidate(2, "u1")).await);
+        assert!(restarted.admit(&candidate(2, "u2")).await);
+        assert!(!restarted.admit(&candidate(2, "u4")).await);
+        // Raising the cap re-checks subjects turned away under the old one
+        assert_eq!(restarted.cached(102, 3, "u4"), None);
+        assert!(restarted.admit(&candidate(3, "u3")).await);
+        assert_eq!(restarted.admitted(102).await.unwrap(), 3);
+    }
+
+    #[cfg(feature = "rocksdb")]
+    #[tokio::test]
+    async fn test_admissions_persist_in_rocksdb() {
+        use crate::kv::RocksDbStore;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let tracker = CapTracker::new(Arc::new(RocksDbStore::open(temp_dir.path()).unwrap()));
+        assert!(tracker.admit(&candidate(1, "u1")).await);
+        assert!(!tracker.admit(&candidate(1, "u2")).await);
+        drop(tracker);
+
+        let tracker = CapTracker::new(Arc::new(RocksDbStore::open(temp_dir.path()).unwrap()));
+        assert!(tracker.admit(&candidate(1, "u1")).await);
+        assert!(!tracker.admit(&candidate(1, "u2")).await);
+        assert_eq!(tracker.admitted(102).await.unwrap(), 1);
+    }
+}
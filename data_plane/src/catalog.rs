@@ -1,10 +1,23 @@
-use crate::error::{ExperimentError, Result};
+use crate::applied::AppliedChange;
+use crate::caps::VariantCaps;
+use crate::config::{migrate, template};
+use crate::error::{ErrorContext, ExperimentError, ResourceKind, Result};
+use crate::exposure::ExposureSampling;
+use crate::lifecycle::LifecycleState;
+use crate::merge::{flatten_params, merge_params_prioritized};
+use crate::metrics::ChangeCounts;
+use crate::params::{DedupStats, LazyParams, ParamPool, ParamsRef};
+use crate::rule::compiled::CompiledRule;
+use crate::rule_optimizer;
+use crate::source::{precedence, SourceConflict};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 /// Experiment-level definition (strong cohesion)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ExperimentDef {
     /// Globally unique, immutable experiment ID
     pub eid: i64,
@@ -16,22 +29,289 @@ pub struct ExperimentDef {
     #[serde(default)]
     pub rule: Option<crate::rule::Node>,
 
+    /// Unix seconds after which no variant is assigned (see [`crate::expiry`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+
+    /// Lifecycle state; only `ramping` / `running` experiments are assigned (see [`crate::lifecycle`])
+    #[serde(default)]
+    pub state: LifecycleState,
+
+    /// Limits on distinct subjects per variant (see [`crate::caps`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caps: Option<VariantCaps>,
+
+    /// Which exposures are logged; all of them when absent (see [`ExposureSampling`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exposure_sampling: Option<ExposureSampling>,
+
     /// Variants under this experiment (only params differ, controlled variable)
     pub variants: Vec<VariantDef>,
 }
 
+impl ExperimentDef {
+    /// Build an experiment from an already-parsed document (e.g. a preview candidate)
+    pub fn from_value(doc: serde_json::Value) -> Result<Self> {
+        let context = ErrorContext::new(ResourceKind::Experiment);
+        if doc.get("extends").is_some() {
+            return Err(ExperimentError::InvalidParameter(
+                "`extends` is only supported in experiment files".to_string(),
+            )
+            .with_context(context));
+        }
+        Self::from_document(doc, context)
+    }
+
+    fn from_document(doc: serde_json::Value, mut context: ErrorContext) -> Result<Self> {
+        if let Some(eid) = doc.get("eid").and_then(|v| v.as_i64()) {
+            context.id = Some(eid.to_string());
+        }
+
+        // Versioned documents are upgraded to the current schema before parsing
+        migrate::upgrade_experiment(doc)
+            .and_then(|mut doc| {
+                let rule_expr = doc.as_object_mut().and_then(|obj| obj.remove("rule_expr"));
+                let mut exp: Self = serde_json::from_value(doc)?;
+                if let Some(expr) = rule_expr {
+                    exp.rule = Some(parse_rule_expr(&expr, exp.rule.is_some())?);
+                }
+                for variant in &mut exp.variants {
+                    template::resolve(&mut variant.params)?;
+                    variant.split_dark_params()?;
+                }
+                if let Some(sampling) = &exp.exposure_sampling {
+                    sampling.validate()?;
+                }
+                if let Some(rule) = &exp.rule {
+                    rule.compile_expressions()?;
+                }
+                Ok(exp)
+            })
+            .map_err(|e| e.with_context(context))
+    }
+
+    /// Compare params across all variants. Keys missing from a variant show as `null`.
+    pub fn variants_diff(&self) -> VariantsDiff {
+        let flattened: Vec<(i64, BTreeMap<String, serde_json::Value>)> = self
+            .variants
+            .iter()
+            .map(|v| {
+                let mut params = BTreeMap::new();
+                flatten_params("", &v.params, &mut params);
+                (v.vid, params)
+            })
+            .collect();
+
+        let keys: BTreeSet<&String> = flattened.iter().flat_map(|(_, p)| p.keys()).collect();
+
+        let mut diff = VariantsDiff {
+            eid: self.eid,
+            service: self.service.clone(),
+            vids: self.variants.iter().map(|v| v.vid).collect(),
+            differing: BTreeMap::new(),
+            common: Vec::new(),
+        };
+
+        for key in keys {
+            let values: BTreeMap<i64, Option<serde_json::Value>> = flattened
+                .iter()
+                .map(|(vid, params)| (*vid, params.get(key).cloned()))
+                .collect();
+
+            let mut distinct = values.values();
+            let first = distinct.next();
+            if distinct.all(|v| Some(v) == first) {
+                diff.common.push(key.clone());
+            } else {
+                diff.differing.insert(key.clone(), values);
+            }
+        }
+
+        diff
+    }
+}
+
+fn compile_rule(rule: &crate::rule::Node) -> Arc<CompiledRule> {
+    Arc::new(CompiledRule::compile(&rule_optimizer::optimize(rule)))
+}
+
+/// Rule tree of an experiment's `rule_expr` (see [`crate::rule::dsl`])
+fn parse_rule_expr(expr: &serde_json::Value, has_rule: bool) -> Result<crate::rule::Node> {
+    if has_rule {
+        return Err(ExperimentError::InvalidRule(
+            "`rule` and `rule_expr` are exclusive; give one of them".to_string(),
+        ));
+    }
+    let expr = expr
+        .as_str()
+        .ok_or_else(|| ExperimentError::InvalidRule("`rule_expr` must be a string".to_string()))?;
+    Ok(crate::rule::dsl::parse(expr)?)
+}
+
+/// Replace `extends: <path>` (relative to the including file) with the base
+/// document: fields in the including file override the base, `params` are
+/// deep-merged. `chain` holds the files being resolved, for cycle detection.
+fn resolve_extends(mut doc: serde_json::Value, path: &Path, chain: &mut Vec<PathBuf>) -> Result<serde_json::Value> {
+    let Some(base) = doc.as_object_mut().and_then(|obj| obj.remove("extends")) else {
+        return Ok(doc);
+    };
+    let base = base
+        .as_str()
+        .ok_or_else(|| ExperimentError::InvalidParameter("`extends` must be a file path".to_string()))?;
+
+    let base_path = path.parent().unwrap_or(Path::new(".")).join(base);
+    let base_path = std::fs::canonicalize(&base_path).map_err(|e| {
+        ExperimentError::InvalidParameter(format!("Cannot read base {:?}: {}", base_path, e))
+    })?;
+    if chain.contains(&base_path) {
+        let cycle: Vec<String> = chain
+            .iter()
+            .chain(std::iter::once(&base_path))
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(ExperimentError::InvalidParameter(format!(
+            "`extends` cycle: {}",
+            cycle.join(" -> ")
+        )));
+    }
+    chain.push(base_path.clone());
+
+    let content = std::fs::read_to_string(&base_path)?;
+    let base_doc = resolve_extends(migrate::parse_document(&content)?, &base_path, chain)?;
+    chain.pop();
+
+    let (serde_json::Value::Object(mut merged), serde_json::Value::Object(overrides)) = (base_doc, doc) else {
+        return Err(ExperimentError::InvalidParameter(
+            "Experiment and base documents must be objects".to_string(),
+        ));
+    };
+    for (key, value) in overrides {
+        match (key.as_str(), merged.remove(&key)) {
+            ("params", Some(base_params)) => {
+                let mut params = match value {
+                    serde_json::Value::Object(map) => map,
+                    _ => return Err(ExperimentError::InvalidParameter("`params` must be an object".to_string())),
+                };
+                merge_params_prioritized(&mut params, &base_params)?;
+                merged.insert(key, serde_json::Value::Object(params));
+            }
+            _ => {
+                merged.insert(key, value);
+            }
+        }
+    }
+    Ok(serde_json::Value::Object(merged))
+}
+
+/// Fold top-level `params` (usually inherited from a base) into every variant;
+/// a variant's own params win.
+fn apply_shared_params(mut doc: serde_json::Value) -> Result<serde_json::Value> {
+    let Some(obj) = doc.as_object_mut() else {
+        return Ok(doc);
+    };
+    let Some(shared) = obj.remove("params") else {
+        return Ok(doc);
+    };
+
+    if let Some(serde_json::Value::Array(variants)) = obj.get_mut("variants") {
+        for variant in variants {
+            if let Some(serde_json::Value::Object(params)) = variant.get_mut("params") {
+                merge_params_prioritized(params, &shared)?;
+            } else if let Some(variant) = variant.as_object_mut() {
+                variant.entry("params").or_insert_with(|| shared.clone());
+            }
+        }
+    }
+    Ok(doc)
+}
+
+/// Param differences across the variants of one experiment
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VariantsDiff {
+    pub eid: i64,
+    pub service: String,
+    pub vids: Vec<i64>,
+    /// Dot-separated param path -> value per vid, for paths that differ
+    pub differing: BTreeMap<String, BTreeMap<i64, Option<serde_json::Value>>>,
+    /// Paths with the same value in every variant
+    pub common: Vec<String>,
+}
+
 /// Variant definition within an experiment
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VariantDef {
     /// Globally unique, immutable variant ID
     pub vid: i64,
 
     /// JSON or YAML formatted parameters (only this differs across variants in same experiment)
     pub params: serde_json::Value,
+
+    /// Params returned in `dark_parameters` instead of `parameters`, for
+    /// services to log or compare without acting on them; in files, params
+    /// written as `{"dark": true, "value": ...}` end up here
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dark_params: Option<serde_json::Value>,
+}
+
+impl VariantDef {
+    /// Move params marked `{"dark": true, "value": ...}` into `dark_params`;
+    /// `dark_params` given explicitly win over marked ones
+    fn split_dark_params(&mut self) -> Result<()> {
+        if let Some(other) = self.dark_params.as_ref().filter(|dark| !dark.is_object()) {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "vid {}: dark_params must be an object, got {}",
+                self.vid, other
+            )));
+        }
+        let Some(params) = self.params.as_object_mut() else {
+            return Ok(());
+        };
+        let mut marked = serde_json::Map::new();
+        take_dark(params, &mut marked);
+        if marked.is_empty() {
+            return Ok(());
+        }
+        let mut dark = match self.dark_params.take() {
+            Some(serde_json::Value::Object(explicit)) => explicit,
+            _ => serde_json::Map::new(),
+        };
+        merge_params_prioritized(&mut dark, &serde_json::Value::Object(marked))?;
+        self.dark_params = Some(serde_json::Value::Object(dark));
+        Ok(())
+    }
+}
+
+/// Remove dark-marked entries from `params`, collecting their values in
+/// `dark` at the same paths; objects left empty are removed too
+fn take_dark(params: &mut serde_json::Map<String, serde_json::Value>, dark: &mut serde_json::Map<String, serde_json::Value>) {
+    params.retain(|key, value| {
+        if let Some(inner) = dark_marked(value) {
+            dark.insert(key.clone(), inner.clone());
+            return false;
+        }
+        if let serde_json::Value::Object(child) = value {
+            let mut child_dark = serde_json::Map::new();
+            take_dark(child, &mut child_dark);
+            if !child_dark.is_empty() {
+                dark.insert(key.clone(), serde_json::Value::Object(child_dark));
+                return !child.is_empty();
+            }
+        }
+        true
+    });
+}
+
+/// Value of a `{"dark": true, "value": ...}` marker
+fn dark_marked(value: &serde_json::Value) -> Option<&serde_json::Value> {
+    let marker = value.as_object()?;
+    if marker.len() != 2 || marker.get("dark") != Some(&serde_json::Value::Bool(true)) {
+        return None;
+    }
+    marker.get("value")
 }
 
 /// Experiment catalog loaded from `configs/experiments` (or `configs/experiments`)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ExperimentCatalog {
     /// eid → ExperimentDef
     experiments: HashMap<i64, ExperimentDef>,
@@ -40,23 +320,161 @@ pub struct ExperimentCatalog {
     vid_to_eid: HashMap<i64, i64>,
 
     source_dir: PathBuf,
+
+    /// Higher-precedence experiment directories layered over `source_dir`
+    overlay_dirs: Vec<PathBuf>,
+
+    /// Definitions shadowed by a higher-precedence source
+    conflicts: Vec<SourceConflict>,
+
+    /// eid → file the experiment was loaded from
+    files: HashMap<i64, PathBuf>,
+
+    /// Variant params shared by content (eager mode); `experiments` hold `null` params
+    params: ParamPool,
+
+    /// Compressed variant params (lazy mode); `experiments` hold `null` params
+    lazy: Option<Arc<LazyParams>>,
+
+    /// eid → experiment rule as evaluated: optimized and compiled (see [`crate::rule::compiled`])
+    compiled_rules: HashMap<i64, Arc<CompiledRule>>,
+}
+
+/// Params of one variant as stored, for change detection (compressed blobs are
+/// equal for equal params)
+#[derive(Debug, PartialEq)]
+enum StoredParams<'a> {
+    Parsed(&'a serde_json::Value),
+    Compressed(&'a [u8]),
 }
 
 impl ExperimentCatalog {
     pub fn load_from_dir(dir: PathBuf) -> Result<Self> {
+        Self::load_from_dirs(dir, Vec::new())
+    }
+
+    /// Load `dir` with overlay directories on top (highest precedence first).
+    ///
+    /// An experiment from a higher-precedence source replaces one with the same
+    /// eid, and also wins over a lower-precedence experiment reusing any of its
+    /// vids. Duplicates within a single source are still errors.
+    pub fn load_from_dirs(dir: PathBuf, overlay_dirs: Vec<PathBuf>) -> Result<Self> {
+        Self::load_with(dir, overlay_dirs, None)
+    }
+
+    /// [`Self::load_from_dirs`] with params in compressed storage (see
+    /// [`Self::with_lazy_params`]). Each file's params are compressed as it is
+    /// read, so the whole catalog is never held parsed at once.
+    pub fn load_lazy(dir: PathBuf, overlay_dirs: Vec<PathBuf>, cache_entries: usize) -> Result<Self> {
+        Self::load_with(dir, overlay_dirs, Some(LazyParams::new(cache_entries)))
+    }
+
+    fn load_with(dir: PathBuf, overlay_dirs: Vec<PathBuf>, mut lazy: Option<LazyParams>) -> Result<Self> {
         if !dir.exists() {
             tracing::warn!("Experiment catalog directory does not exist: {:?}", dir);
-            return Ok(Self {
-                experiments: HashMap::new(),
-                vid_to_eid: HashMap::new(),
-                source_dir: dir,
-            });
         }
 
-        let mut experiments: HashMap<i64, ExperimentDef> = HashMap::new();
-        let mut vid_to_eid: HashMap<i64, i64> = HashMap::new();
+        let mut sources = Vec::new();
+        for overlay in &overlay_dirs {
+            sources.push(Self::experiment_files(overlay)?);
+        }
+        sources.push(Self::experiment_files(&dir)?);
+
+        let mut entries = Vec::new();
+        let mut conflicts = Vec::new();
+        // eid / vid -> (rank, path) of the source that claimed it
+        let mut eids: HashMap<i64, (usize, PathBuf)> = HashMap::new();
+        let mut vids: HashMap<i64, (usize, i64, PathBuf)> = HashMap::new();
+
+        for (rank, source) in sources.into_iter().enumerate() {
+            for path in source {
+                let mut exp_def = Self::read_experiment_file(&path)?;
+                let shadowed_by = eids
+                    .get(&exp_def.eid)
+                    .filter(|(r, _)| *r < rank)
+                    .map(|(_, winner)| (winner.clone(), None))
+                    .or_else(|| {
+                        exp_def.variants.iter().find_map(|v| {
+                            vids.get(&v.vid).filter(|(r, _, _)| *r < rank).map(|(_, eid, winner)| {
+                                (winner.clone(), Some(format!("vid {} also defined by eid {}", v.vid, eid)))
+                            })
+                        })
+                    });
 
-        for entry in std::fs::read_dir(&dir)? {
+                if let Some((winner, detail)) = shadowed_by {
+                    tracing::warn!(
+                        "Experiment {} in {:?} shadowed by {:?}{}",
+                        exp_def.eid,
+                        path,
+                        winner,
+                        detail.as_deref().map(|d| format!(" ({})", d)).unwrap_or_default()
+                    );
+                    conflicts.push(SourceConflict {
+                        kind: ResourceKind::Experiment,
+                        id: exp_def.eid.to_string(),
+                        winner,
+                        shadowed: path,
+                        detail,
+                    });
+                    continue;
+                }
+
+                eids.entry(exp_def.eid).or_insert((rank, path.clone()));
+                for v in &exp_def.variants {
+                    vids.entry(v.vid).or_insert((rank, exp_def.eid, path.clone()));
+                }
+                if let Some(lazy) = &mut lazy {
+                    for v in &mut exp_def.variants {
+                        lazy.insert(v.vid, &std::mem::take(&mut v.params))
+                            .map_err(|e| e.with_context(ErrorContext::new(ResourceKind::Experiment).with_path(&path)))?;
+                    }
+                }
+                entries.push((exp_def, Some(path)));
+            }
+        }
+
+        let mut catalog = Self::from_entries(entries, dir, lazy)?;
+        catalog.overlay_dirs = overlay_dirs;
+        catalog.conflicts = conflicts;
+        Ok(catalog)
+    }
+
+    /// Re-read the same source and overlay directories (keeping the params mode)
+    pub fn reload(&self) -> Result<Self> {
+        self.load_source(self.source_dir.clone())
+    }
+
+    /// Load another source directory with the same overlays and params mode
+    pub fn load_source(&self, dir: PathBuf) -> Result<Self> {
+        let lazy = self.lazy.as_ref().map(|lazy| LazyParams::new(lazy.capacity()));
+        Self::load_with(dir, self.overlay_dirs.clone(), lazy)
+    }
+
+    /// Move variant params into compressed storage, materialized on access
+    /// into an LRU of `cache_entries` variants. Catalogs read from disk should
+    /// use [`Self::load_lazy`] instead, which never holds every params parsed.
+    pub fn with_lazy_params(mut self, cache_entries: usize) -> Result<Self> {
+        let mut lazy = LazyParams::new(cache_entries);
+        for (vid, params) in self.params.drain() {
+            lazy.insert(vid, &params)?;
+        }
+        self.lazy = Some(Arc::new(lazy));
+        Ok(self)
+    }
+
+    /// Lazy params storage, if enabled
+    pub fn lazy_params(&self) -> Option<&LazyParams> {
+        self.lazy.as_deref()
+    }
+
+    /// Experiment files (json / yaml) directly in `dir`
+    fn experiment_files(dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        if !dir.exists() {
+            return Ok(files);
+        }
+
+        for entry in std::fs::read_dir(dir)? {
             let entry = entry?;
             let path = entry.path();
 
@@ -72,13 +490,45 @@ impl ExperimentCatalog {
                 continue;
             }
 
-            let exp_def = Self::read_experiment_file(&path)?;
+            files.push(path);
+        }
+
+        Ok(files)
+    }
+
+    /// Build a catalog from experiment definitions, validating eid/vid uniqueness
+    pub fn from_experiments(experiments: Vec<ExperimentDef>, source_dir: PathBuf) -> Result<Self> {
+        Self::from_entries(experiments.into_iter().map(|e| (e, None)).collect(), source_dir, None)
+    }
+
+    /// `lazy` already holds the entries' params when set; otherwise they're pooled here
+    fn from_entries(
+        entries: Vec<(ExperimentDef, Option<PathBuf>)>,
+        source_dir: PathBuf,
+        lazy: Option<LazyParams>,
+    ) -> Result<Self> {
+        let mut experiments: HashMap<i64, ExperimentDef> = HashMap::new();
+        let mut vid_to_eid: HashMap<i64, i64> = HashMap::new();
+        let mut params = ParamPool::default();
+        let mut files = HashMap::new();
+        let mut compiled_rules = HashMap::new();
+
+        for (mut exp_def, path) in entries {
+            let mut context = ErrorContext::new(ResourceKind::Experiment).with_id(exp_def.eid.to_string());
+            if let Some(path) = &path {
+                context = context.with_path(path);
+            }
 
             if experiments.contains_key(&exp_def.eid) {
                 return Err(ExperimentError::InvalidParameter(format!(
-                    "Duplicate eid {} in catalog (file: {:?})",
-                    exp_def.eid, path
-                )));
+                    "Duplicate eid {} in catalog",
+                    exp_def.eid
+                ))
+                .with_context(context));
+            }
+
+            if let Some(caps) = &exp_def.caps {
+                caps.validate(&exp_def.variants).map_err(|e| e.with_context(context.clone()))?;
             }
 
             // Build reverse index: vid → eid
@@ -87,48 +537,310 @@ impl ExperimentCatalog {
                     return Err(ExperimentError::InvalidParameter(format!(
                         "Duplicate vid {} (belongs to eid {} and {})",
                         variant.vid, existing_eid, exp_def.eid
-                    )));
+                    ))
+                    .with_context(context));
                 }
             }
 
+            if lazy.is_none() {
+                for variant in &mut exp_def.variants {
+                    params.insert(variant.vid, std::mem::take(&mut variant.params))?;
+                }
+            }
+            if let Some(path) = path {
+                files.insert(exp_def.eid, path);
+            }
+            if let Some(rule) = &exp_def.rule {
+                compiled_rules.insert(exp_def.eid, compile_rule(rule));
+            }
             experiments.insert(exp_def.eid, exp_def);
         }
 
         Ok(Self {
             experiments,
             vid_to_eid,
-            source_dir: dir,
+            source_dir,
+            overlay_dirs: Vec::new(),
+            conflicts: Vec::new(),
+            files,
+            params,
+            lazy: lazy.map(Arc::new),
+            compiled_rules,
         })
     }
 
     fn read_experiment_file(path: &Path) -> Result<ExperimentDef> {
-        let content = std::fs::read_to_string(path)?;
+        let context = ErrorContext::new(ResourceKind::Experiment).with_path(path);
+
+        let doc = std::fs::read_to_string(path)
+            .map_err(ExperimentError::from)
+            .and_then(|content| migrate::parse_document(&content))
+            .and_then(|doc| {
+                let mut chain = vec![std::fs::canonicalize(path)?];
+                resolve_extends(doc, path, &mut chain)
+            })
+            .and_then(apply_shared_params)
+            .map_err(|e| e.with_context(context.clone()))?;
+        ExperimentDef::from_document(doc, context)
+    }
+
+    /// Copy of this catalog with `exp` added or replacing the experiment with the same eid
+    pub fn with_experiment(&self, exp: ExperimentDef) -> Result<Self> {
+        if let Some(caps) = &exp.caps {
+            caps.validate(&exp.variants).map_err(|e| {
+                e.with_context(ErrorContext::new(ResourceKind::Experiment).with_id(exp.eid.to_string()))
+            })?;
+        }
+        let mut catalog = self.clone();
+
+        if let Some(previous) = catalog.experiments.remove(&exp.eid) {
+            for variant in &previous.variants {
+                catalog.vid_to_eid.remove(&variant.vid);
+            }
+        }
+
+        for variant in &exp.variants {
+            if let Some(existing_eid) = catalog.vid_to_eid.insert(variant.vid, exp.eid) {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "Duplicate vid {} (belongs to eid {} and {})",
+                    variant.vid, existing_eid, exp.eid
+                ))
+                .with_context(ErrorContext::new(ResourceKind::Experiment).with_id(exp.eid.to_string())));
+            }
+        }
 
-        // Try JSON first, then YAML
-        let def: ExperimentDef = serde_json::from_str(&content)
-            .or_else(|_| serde_yaml::from_str(&content).map_err(ExperimentError::from))?;
+        let mut exp = exp;
+        let previous_vids = self.experiments.get(&exp.eid).into_iter().flat_map(|p| &p.variants).map(|v| v.vid);
+        if let Some(current) = &self.lazy {
+            let mut lazy = current.fork();
+            for vid in previous_vids {
+                lazy.remove(vid);
+            }
+            for variant in &mut exp.variants {
+                lazy.insert(variant.vid, &std::mem::take(&mut variant.params))?;
+            }
+            catalog.lazy = Some(Arc::new(lazy));
+        } else {
+            for vid in previous_vids {
+                catalog.params.remove(vid);
+            }
+            for variant in &mut exp.variants {
+                catalog.params.insert(variant.vid, std::mem::take(&mut variant.params))?;
+            }
+        }
 
-        Ok(def)
+        match &exp.rule {
+            Some(rule) => catalog.compiled_rules.insert(exp.eid, compile_rule(rule)),
+            None => catalog.compiled_rules.remove(&exp.eid),
+        };
+        catalog.experiments.insert(exp.eid, exp);
+        Ok(catalog)
     }
 
-    /// Get experiment by eid
-    #[inline]
-    pub fn get_experiment(&self, eid: i64) -> Option<&ExperimentDef> {
+    /// Get experiment by eid (params copied back in from their storage).
+    ///
+    /// Materializes every variant's params; use [`Self::experiment_def`] and
+    /// [`Self::variant_params`] when only some of them are needed.
+    pub fn get_experiment(&self, eid: i64) -> Option<Cow<'_, ExperimentDef>> {
+        let mut exp = self.experiments.get(&eid)?.clone();
+        for variant in &mut exp.variants {
+            if let Some(params) = self.stored_params(variant.vid) {
+                variant.params = (*params).clone();
+            }
+        }
+        Some(Cow::Owned(exp))
+    }
+
+    /// Experiment by eid as stored: variant params are `null`
+    pub fn experiment_def(&self, eid: i64) -> Option<&ExperimentDef> {
         self.experiments.get(&eid)
     }
 
+    /// Params of one variant, materializing only that variant
+    pub fn variant_params(&self, vid: i64) -> Option<ParamsRef<'_>> {
+        self.stored_params(vid)
+    }
+
+    fn stored_params(&self, vid: i64) -> Option<ParamsRef<'_>> {
+        match &self.lazy {
+            Some(lazy) => lazy.get(vid).map(ParamsRef::Shared),
+            None => self.params.get(vid).map(|params| ParamsRef::Borrowed(params.as_ref())),
+        }
+    }
+
     /// Get eid by vid (reverse index)
     #[inline]
     pub fn get_eid_by_vid(&self, vid: i64) -> Option<i64> {
         self.vid_to_eid.get(&vid).copied()
     }
 
+    /// eid and service owning a vid, without materializing params
+    pub fn variant_owner(&self, vid: i64) -> Option<(i64, &str)> {
+        let eid = self.get_eid_by_vid(vid)?;
+        let exp = self.experiments.get(&eid)?;
+        Some((eid, exp.service.as_str()))
+    }
+
+    /// Service and vids of an experiment, without materializing params
+    pub fn experiment_outline(&self, eid: i64) -> Option<(&str, Vec<i64>)> {
+        let exp = self.experiments.get(&eid)?;
+        Some((exp.service.as_str(), exp.variants.iter().map(|v| v.vid).collect()))
+    }
+
+    /// vids added, removed or moved to another service going from `self` to `newer`
+    pub fn vids_with_changed_service(&self, newer: &ExperimentCatalog) -> HashSet<i64> {
+        if std::ptr::eq(self, newer) {
+            return HashSet::new();
+        }
+        let mut changed: HashSet<i64> = newer
+            .vid_to_eid
+            .keys()
+            .filter(|vid| {
+                self.variant_owner(**vid).map(|(_, service)| service)
+                    != newer.variant_owner(**vid).map(|(_, service)| service)
+            })
+            .copied()
+            .collect();
+        changed.extend(self.vid_to_eid.keys().filter(|vid| !newer.vid_to_eid.contains_key(vid)));
+        changed
+    }
+
     /// Get variant params by vid (returns (eid, service, rule, params))
-    pub fn get_variant(&self, vid: i64) -> Option<(i64, &str, Option<&crate::rule::Node>, &serde_json::Value)> {
+    pub fn get_variant(&self, vid: i64) -> Option<(i64, &str, Option<&crate::rule::Node>, ParamsRef<'_>)> {
         let eid = self.get_eid_by_vid(vid)?;
-        let exp = self.get_experiment(eid)?;
-        let variant = exp.variants.iter().find(|v| v.vid == vid)?;
-        Some((eid, exp.service.as_str(), exp.rule.as_ref(), &variant.params))
+        let exp = self.experiments.get(&eid)?;
+        let params = self.stored_params(vid)?;
+        Some((eid, exp.service.as_str(), exp.rule.as_ref(), params))
+    }
+
+    /// Experiments keyed by eid, with their variants' stored params alongside
+    #[allow(clippy::type_complexity)]
+    fn comparable(&self) -> HashMap<i64, (&ExperimentDef, Vec<Option<StoredParams<'_>>>)> {
+        self.experiments
+            .iter()
+            .map(|(eid, exp)| {
+                let params = exp
+                    .variants
+                    .iter()
+                    .map(|v| match &self.lazy {
+                        Some(lazy) => lazy.blob(v.vid).map(StoredParams::Compressed),
+                        None => self.params.get(v.vid).map(|params| StoredParams::Parsed(params.as_ref())),
+                    })
+                    .collect();
+                (*eid, (exp, params))
+            })
+            .collect()
+    }
+
+    /// Sharing of equal variant params (eager mode; empty in lazy mode)
+    pub fn param_dedup(&self) -> DedupStats {
+        self.params.stats()
+    }
+
+    /// Whether both catalogs define exactly the same experiments
+    pub fn same_experiments(&self, other: &ExperimentCatalog) -> bool {
+        self.comparable() == other.comparable()
+    }
+
+    /// Experiments added, removed or modified going from `self` to `newer`
+    pub fn changes_to(&self, newer: &ExperimentCatalog) -> ChangeCounts {
+        ChangeCounts::between(&self.comparable(), &newer.comparable())
+    }
+
+    /// Experiments added, modified or removed in `newer`, by eid
+    pub fn experiment_changes(&self, newer: &ExperimentCatalog) -> Vec<(i64, AppliedChange)> {
+        let (old, new) = (self.comparable(), newer.comparable());
+        let mut changes: Vec<(i64, AppliedChange)> = new
+            .iter()
+            .filter_map(|(eid, def)| match old.get(eid) {
+                None => Some((*eid, AppliedChange::Added)),
+                Some(previous) if previous != def => Some((*eid, AppliedChange::Modified)),
+                Some(_) => None,
+            })
+            .chain(
+                old.keys()
+                    .filter(|eid| !new.contains_key(*eid))
+                    .map(|eid| (*eid, AppliedChange::Removed)),
+            )
+            .collect();
+        changes.sort_unstable_by_key(|(eid, _)| *eid);
+        changes
+    }
+
+    /// Exposure sampling of experiment `eid`, if it has a strategy
+    pub fn exposure_sampling(&self, eid: i64) -> Option<ExposureSampling> {
+        self.experiments.get(&eid)?.exposure_sampling
+    }
+
+    /// `(max_subjects, control_vid)` when `vid` is capped
+    pub fn variant_cap(&self, vid: i64) -> Option<(u64, i64)> {
+        let caps = self.experiments.get(&self.get_eid_by_vid(vid)?)?.caps.as_ref()?;
+        Some((*caps.max_subjects.get(&vid)?, caps.control_vid))
+    }
+
+    /// Experiments with exposure caps, by eid
+    pub fn variant_caps(&self) -> BTreeMap<i64, &VariantCaps> {
+        self.experiments
+            .iter()
+            .filter_map(|(eid, exp)| Some((*eid, exp.caps.as_ref()?)))
+            .collect()
+    }
+
+    /// Dark params of a variant, if it has any (see [`VariantDef::dark_params`])
+    pub fn dark_params(&self, vid: i64) -> Option<&serde_json::Value> {
+        let exp = self.experiments.get(&self.get_eid_by_vid(vid)?)?;
+        exp.variants.iter().find(|v| v.vid == vid)?.dark_params.as_ref()
+    }
+
+    /// Experiment `eid`'s rule as evaluated, if it has one
+    pub fn compiled_rule(&self, eid: i64) -> Option<&CompiledRule> {
+        self.compiled_rules.get(&eid).map(Arc::as_ref)
+    }
+
+    /// Whether `eid`'s lifecycle state allows assigning its variants
+    pub fn is_serving(&self, eid: i64) -> bool {
+        self.experiments.get(&eid).is_some_and(|exp| exp.state.serves())
+    }
+
+    /// Reject `newer` if it moves an experiment along a lifecycle edge that
+    /// isn't allowed. Added and removed experiments are not transitions.
+    pub fn check_transitions(&self, newer: &ExperimentCatalog) -> Result<()> {
+        for (eid, next) in &newer.experiments {
+            let Some(current) = self.experiments.get(eid) else {
+                continue;
+            };
+            if !current.state.can_transition_to(next.state) {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "Illegal lifecycle transition {} -> {}",
+                    current.state, next.state
+                ))
+                .with_context(ErrorContext::new(ResourceKind::Experiment).with_id(eid.to_string())));
+            }
+        }
+        Ok(())
+    }
+
+    /// eid -> expires_at of experiments expired at `now`
+    pub fn expired_experiments(&self, now: u64) -> BTreeMap<i64, u64> {
+        self.experiments
+            .iter()
+            .filter_map(|(eid, exp)| {
+                let expires_at = exp.expires_at?;
+                (expires_at <= now).then_some((*eid, expires_at))
+            })
+            .collect()
+    }
+
+    /// All eids in the catalog (unordered)
+    pub fn eids(&self) -> impl Iterator<Item = i64> + '_ {
+        self.experiments.keys().copied()
+    }
+
+    /// Experiment rules as authored, by eid (unordered)
+    pub fn rules(&self) -> impl Iterator<Item = (i64, &crate::rule::Node)> + '_ {
+        self.experiments
+            .values()
+            .filter_map(|exp| Some((exp.eid, exp.rule.as_ref()?)))
     }
 
     /// Get all services from catalog (for building inverted index)
@@ -154,8 +866,289 @@ impl ExperimentCatalog {
         self.experiments.is_empty()
     }
 
-    #[allow(dead_code)]
     pub fn source_dir(&self) -> &Path {
         &self.source_dir
     }
+
+    pub fn overlay_dirs(&self) -> &[PathBuf] {
+        &self.overlay_dirs
+    }
+
+    /// Source precedence rank of an experiment file (see [`precedence`])
+    pub fn precedence_of(&self, path: &Path) -> usize {
+        precedence(path, &self.overlay_dirs)
+    }
+
+    /// File `eid` was loaded from (none for catalogs built in memory)
+    pub fn file_of(&self, eid: i64) -> Option<&Path> {
+        self.files.get(&eid).map(PathBuf::as_path)
+    }
+
+    pub fn conflicts(&self) -> &[SourceConflict] {
+        &self.conflicts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_variants_diff() {
+        let exp = ExperimentDef {
+            eid: 100,
+            service: "svc".to_string(),
+            expires_at: None,
+            state: Default::default(),
+            caps: None,
+            rule: None,
+            exposure_sampling: None,
+            variants: vec![
+                VariantDef {
+                    vid: 101,
+                    params: json!({"ranker": {"model": "a", "timeout": 100}}),
+                    dark_params: None,
+                },
+                VariantDef {
+                    vid: 102,
+                    params: json!({"ranker": {"model": "b", "timeout": 100}}),
+                    dark_params: None,
+                },
+                VariantDef {
+                    vid: 103,
+                    params: json!({"ranker": {"model": "b", "timeout": 100}, "debug": true}),
+                    dark_params: None,
+                },
+            ],
+        };
+
+        let diff = exp.variants_diff();
+        assert_eq!(diff.vids, vec![101, 102, 103]);
+        assert_eq!(diff.common, vec!["ranker.timeout".to_string()]);
+        assert_eq!(
+            diff.differing.keys().collect::<Vec<_>>(),
+            vec!["debug", "ranker.model"]
+        );
+        assert_eq!(
+            diff.differing["debug"],
+            BTreeMap::from([(101, None), (102, None), (103, Some(json!(true)))])
+        );
+        assert_eq!(diff.differing["ranker.model"][&101], Some(json!("a")));
+    }
+
+    #[test]
+    fn test_extends_base_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join("bases")).unwrap();
+        std::fs::write(
+            dir.join("bases/ranker.yaml"),
+            "service: ranker\nparams:\n  ranker:\n    model: base\n    timeout: 100\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("100.json"),
+            json!({
+                "extends": "bases/ranker.yaml",
+                "eid": 100,
+                "variants": [
+                    {"vid": 101, "params": {"ranker": {"model": "a"}}},
+                    {"vid": 102}
+                ]
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let catalog = ExperimentCatalog::load_from_dir(dir.to_path_buf()).unwrap();
+        let exp = catalog.get_experiment(100).unwrap();
+        assert_eq!(exp.service, "ranker");
+        assert_eq!(exp.variants[0].params, json!({"ranker": {"model": "a", "timeout": 100}}));
+        assert_eq!(exp.variants[1].params, json!({"ranker": {"model": "base", "timeout": 100}}));
+
+        // Bases including each other are rejected
+        std::fs::write(dir.join("bases/a.json"), r#"{"extends": "b.json"}"#).unwrap();
+        std::fs::write(dir.join("bases/b.json"), r#"{"extends": "a.json"}"#).unwrap();
+        std::fs::write(
+            dir.join("200.json"),
+            json!({"extends": "bases/a.json", "eid": 200, "service": "svc", "variants": []}).to_string(),
+        )
+        .unwrap();
+        let err = ExperimentCatalog::load_from_dir(dir.to_path_buf()).unwrap_err();
+        assert!(err.to_string().contains("`extends` cycle"), "{}", err);
+        assert!(err.to_string().contains("200.json"), "{}", err);
+    }
+
+    #[test]
+    fn test_lazy_params_catalog() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let write = |model: &str| {
+            let exp = json!({
+                "eid": 100,
+                "service": "svc",
+                "variants": [
+                    {"vid": 101, "params": {"model": model}},
+                    {"vid": 102, "params": {"model": "b"}}
+                ]
+            });
+            std::fs::write(temp_dir.path().join("100.json"), exp.to_string()).unwrap();
+        };
+        write("a");
+
+        let eager = ExperimentCatalog::load_from_dir(temp_dir.path().to_path_buf()).unwrap();
+        let lazy = ExperimentCatalog::load_lazy(temp_dir.path().to_path_buf(), Vec::new(), 1).unwrap();
+        assert!(lazy.same_experiments(&eager.clone().with_lazy_params(1).unwrap()));
+
+        // Stored defs and single variants don't materialize the whole experiment
+        assert!(lazy.experiment_def(100).unwrap().variants.iter().all(|v| v.params.is_null()));
+        assert_eq!(*lazy.variant_params(102).unwrap(), json!({"model": "b"}));
+        assert_eq!(lazy.lazy_params().unwrap().cached_entries(), 1);
+
+        assert_eq!(*lazy.get_variant(101).unwrap().3, json!({"model": "a"}));
+        assert_eq!(*lazy.get_variant(102).unwrap().3, json!({"model": "b"}));
+        assert_eq!(lazy.lazy_params().unwrap().cached_entries(), 1);
+        assert_eq!(*lazy.get_experiment(100).unwrap(), *eager.get_experiment(100).unwrap());
+
+        // Reload keeps lazy mode and still notices param changes
+        assert!(lazy.same_experiments(&lazy.reload().unwrap()));
+        write("c");
+        let reloaded = lazy.reload().unwrap();
+        assert!(reloaded.lazy_params().is_some());
+        assert!(!lazy.same_experiments(&reloaded));
+        assert_eq!(lazy.changes_to(&reloaded).modified, 1);
+
+        // Candidate copies don't disturb the original
+        let candidate = lazy
+            .with_experiment(ExperimentDef {
+                eid: 100,
+                service: "svc".to_string(),
+                expires_at: None,
+                state: Default::default(),
+                caps: None,
+                rule: None,
+                exposure_sampling: None,
+                variants: vec![VariantDef {
+                    vid: 103,
+                    params: json!({"model": "d"}),
+                    dark_params: None,
+                }],
+            })
+            .unwrap();
+        assert_eq!(*candidate.get_variant(103).unwrap().3, json!({"model": "d"}));
+        assert!(candidate.get_variant(101).is_none());
+        assert_eq!(*lazy.get_variant(101).unwrap().3, json!({"model": "a"}));
+    }
+
+    #[test]
+    fn test_equal_params_shared_across_experiments() {
+        let exp = |eid: i64, model: &str| {
+            ExperimentDef::from_value(json!({
+                "eid": eid,
+                "service": "svc",
+                "variants": [{"vid": eid + 1, "params": {"model": model}}]
+            }))
+            .unwrap()
+        };
+        let catalog = ExperimentCatalog::from_experiments(vec![exp(100, "a"), exp(200, "a")], PathBuf::new()).unwrap();
+
+        let (first, second) = (catalog.get_variant(101).unwrap().3, catalog.get_variant(201).unwrap().3);
+        assert!(std::ptr::eq(&*first, &*second));
+        assert_eq!((catalog.param_dedup().variants, catalog.param_dedup().unique), (2, 1));
+        assert_eq!(catalog.get_experiment(200).unwrap().variants[0].params, json!({"model": "a"}));
+
+        // Changing one experiment's params is still detected and unshares them
+        let updated = catalog.with_experiment(exp(200, "b")).unwrap();
+        assert_eq!(catalog.changes_to(&updated).modified, 1);
+        assert_eq!(updated.param_dedup().unique, 2);
+        assert_eq!(*updated.get_variant(101).unwrap().3, json!({"model": "a"}));
+    }
+
+    #[test]
+    fn test_param_placeholders_resolved_at_load() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let vars = temp_dir.path().join("variables.yaml");
+        std::fs::write(&vars, "ranker_host: ranker.eu.internal\n").unwrap();
+        template::init(Some(&vars)).unwrap();
+
+        let exp = ExperimentDef::from_value(json!({
+            "eid": 100,
+            "service": "svc",
+            "variants": [{"vid": 101, "params": {"endpoint": "https://${ranker_host}/rank"}}]
+        }))
+        .unwrap();
+        assert_eq!(exp.variants[0].params["endpoint"], json!("https://ranker.eu.internal/rank"));
+
+        let err = ExperimentDef::from_value(json!({
+            "eid": 200,
+            "service": "svc",
+            "variants": [{"vid": 201, "params": {"endpoint": "${undefined_host}"}}]
+        }))
+        .unwrap_err();
+        assert!(err.to_string().contains("experiment '200'"), "{}", err);
+        assert!(err.to_string().contains("undefined_host"), "{}", err);
+    }
+
+    #[test]
+    fn test_rule_expr_parsed_at_load() {
+        let exp = ExperimentDef::from_value(json!({
+            "eid": 100,
+            "service": "svc",
+            "rule_expr": "country == \"US\" && age >= 18",
+            "variants": []
+        }))
+        .unwrap();
+        assert_eq!(exp.rule, Some(crate::rule::dsl::parse(r#"country == "US" && age >= 18"#).unwrap()));
+
+        let err = ExperimentDef::from_value(json!({
+            "eid": 200,
+            "service": "svc",
+            "rule_expr": "age >= ",
+            "variants": []
+        }))
+        .unwrap_err();
+        assert!(err.to_string().contains("experiment '200'"), "{}", err);
+        assert!(err.to_string().contains("line 1, column 8"), "{}", err);
+
+        let both = ExperimentDef::from_value(json!({
+            "eid": 300,
+            "service": "svc",
+            "rule": {"type": "and", "children": []},
+            "rule_expr": "premium",
+            "variants": []
+        }));
+        assert!(both.is_err());
+    }
+
+    #[test]
+    fn test_dark_params_split_from_live_params() {
+        let exp = ExperimentDef::from_value(json!({
+            "eid": 100,
+            "service": "svc",
+            "variants": [{
+                "vid": 1001,
+                "params": {
+                    "timeout": 100,
+                    "ranker": {"model": {"dark": true, "value": "dnn"}},
+                    "banner": {"dark": true, "value": {"color": "red"}, "note": "not a marker"}
+                },
+                "dark_params": {"ranker": {"model": "gbdt"}, "shadow": true}
+            }]
+        }))
+        .unwrap();
+        let variant = &exp.variants[0];
+        assert_eq!(
+            variant.params,
+            json!({"timeout": 100, "banner": {"dark": true, "value": {"color": "red"}, "note": "not a marker"}})
+        );
+        // Explicit dark_params take precedence over markers
+        assert_eq!(variant.dark_params, Some(json!({"ranker": {"model": "gbdt"}, "shadow": true})));
+
+        let err = ExperimentDef::from_value(json!({
+            "eid": 200,
+            "service": "svc",
+            "variants": [{"vid": 2001, "params": {}, "dark_params": [1]}]
+        }));
+        assert!(err.is_err());
+    }
 }
@@ -0,0 +1,841 @@
+//! CEL expression rules (`cel` feature).
+//!
+//! A `{"type": "cel", "expr": "..."}` rule node holds a
+//! [CEL](https://github.com/google/cel-spec) expression over the request
+//! context, for targeting that would otherwise take a large JSON tree. The
+//! subset supported is what targeting needs:
+//!
+//! - literals: ints, doubles, strings, `true` / `false`, `null`, lists `[...]`
+//! - context fields as identifiers, members (`device.os`) and indexing
+//!   (`tags[0]`, `attrs["k"]`)
+//! - `!`, unary `-`, `*` `/` `%`, `+` `-` (`+` also concatenates strings and
+//!   lists), `<` `<=` `>` `>=` `==` `!=`, `in` (list element or map key), `&&`,
+//!   `||` and `? :`
+//! - `has(a.b)`, `size(x)` / `x.size()`, `x.startsWith(s)`, `x.endsWith(s)`,
+//!   `x.contains(s)`, `int(x)`, `double(x)`, `string(x)` and the `exists`,
+//!   `all` and `exists_one` macros (`tags.exists(t, t == "beta")`)
+//!
+//! Expressions are compiled when their experiment or layer file loads, so a
+//! syntax error rejects the file. Each rule node keeps its program, so
+//! evaluation takes no lock; the process-wide cache keyed by source text only
+//! lets rules sharing an expression share the program at load. As in CEL, `&&` / `||` absorb an error on one
+//! side when the other side decides the result; any other error (a missing
+//! field, mismatched types) fails the rule, like an erroring JSON rule. Values
+//! are typed by their JSON form; `/field_types` is not consulted.
+
+use crate::error::{ExperimentError, Result};
+use lazy_static::lazy_static;
+use lru::LruCache;
+use parking_lot::Mutex;
+use serde_json::{Map, Number, Value};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+/// Compiled programs kept across requests (and across rules sharing an expression)
+const PROGRAM_CACHE_CAPACITY: usize = 1024;
+
+lazy_static! {
+    static ref PROGRAM_CACHE: Mutex<LruCache<String, Arc<Program>>> = Mutex::new(LruCache::new(
+        NonZeroUsize::new(PROGRAM_CACHE_CAPACITY).unwrap_or(NonZeroUsize::MIN)
+    ));
+}
+
+/// A compiled CEL expression
+#[derive(Debug, PartialEq)]
+pub struct Program {
+    root: Expr,
+}
+
+/// Compiled program of `expr`, from the cache when it was compiled before
+pub fn compile(expr: &str) -> Result<Arc<Program>> {
+    if let Some(program) = PROGRAM_CACHE.lock().get(expr) {
+        return Ok(program.clone());
+    }
+    let program = Arc::new(Program::compile(expr)?);
+    PROGRAM_CACHE.lock().put(expr.to_string(), program.clone());
+    Ok(program)
+}
+
+impl Program {
+    pub fn compile(expr: &str) -> Result<Self> {
+        let tokens = tokenize(expr).map_err(|(at, message)| syntax_error(expr, at, &message))?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let root = parser.expr().map_err(|(at, message)| syntax_error(expr, at, &message))?;
+        if let Some((at, token)) = parser.tokens.get(parser.pos) {
+            return Err(syntax_error(expr, *at, &format!("unexpected {}", token)));
+        }
+        Ok(Self { root })
+    }
+
+    /// Result of the expression for `ctx`, which must be a bool
+    pub fn evaluate(&self, ctx: &HashMap<String, Value>) -> Result<bool> {
+        let mut scope = Scope { ctx, vars: Vec::new() };
+        match eval(&self.root, &mut scope).map_err(ExperimentError::InvalidRule)? {
+            Value::Bool(b) => Ok(b),
+            other => Err(ExperimentError::InvalidRule(format!(
+                "CEL rule must evaluate to a bool, got {}",
+                other
+            ))),
+        }
+    }
+}
+
+fn syntax_error(expr: &str, at: usize, message: &str) -> ExperimentError {
+    ExperimentError::InvalidRule(format!("CEL expression '{}' at offset {}: {}", expr, at, message))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(i64),
+    Double(f64),
+    Str(String),
+    Ident(String),
+    Punct(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Int(n) => write!(f, "'{}'", n),
+            Token::Double(n) => write!(f, "'{}'", n),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::Punct(p) => write!(f, "'{}'", p),
+        }
+    }
+}
+
+/// Longest first, so `<=` is not read as `<` `=`
+const PUNCTUATION: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=", "(", ")", "[", "]", ".", ",", "?", ":", "!", "-", "+", "*", "/", "%", "<", ">",
+];
+
+type Spanned<T> = std::result::Result<T, (usize, String)>;
+
+fn tokenize(src: &str) -> Spanned<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    let mut chars = src.char_indices().peekable();
+    while let Some(&(at, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() {
+            let mut end = at;
+            let mut is_double = false;
+            while let Some(&(i, c)) = chars.peek() {
+                let fraction = c == '.' && !is_double && src[i + 1..].starts_with(|c: char| c.is_ascii_digit());
+                if !c.is_ascii_digit() && !fraction {
+                    break;
+                }
+                is_double |= fraction;
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let text = &src[at..end];
+            let token = if is_double {
+                text.parse().map(Token::Double).map_err(|_| (at, format!("invalid number '{}'", text)))?
+            } else {
+                text.parse().map(Token::Int).map_err(|_| (at, format!("integer '{}' out of range", text)))?
+            };
+            tokens.push((at, token));
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, q)) if q == c => break,
+                    Some((i, '\\')) => match chars.next() {
+                        Some((_, 'n')) => text.push('\n'),
+                        Some((_, 't')) => text.push('\t'),
+                        Some((_, 'r')) => text.push('\r'),
+                        Some((_, e @ ('\\' | '"' | '\''))) => text.push(e),
+                        _ => return Err((i, "invalid escape sequence".to_string())),
+                    },
+                    Some((_, ch)) => text.push(ch),
+                    None => return Err((at, "unterminated string".to_string())),
+                }
+            }
+            tokens.push((at, Token::Str(text)));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = at;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push((at, Token::Ident(src[at..end].to_string())));
+        } else {
+            let punct = PUNCTUATION
+                .iter()
+                .find(|p| src[at..].starts_with(**p))
+                .ok_or_else(|| (at, format!("unexpected character '{}'", c)))?;
+            for _ in 0..punct.len() {
+                chars.next();
+            }
+            tokens.push((at, Token::Punct(punct)));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Function {
+    Size,
+    StartsWith,
+    EndsWith,
+    Contains,
+    Int,
+    Double,
+    String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quantifier {
+    Exists,
+    All,
+    ExistsOne,
+}
+
+#[derive(Debug, PartialEq)]
+enum Expr {
+    Literal(Value),
+    Ident(String),
+    Member(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    List(Vec<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `has(...)` of a member or top-level field
+    Has(Box<Expr>),
+    /// Receiver (for methods) comes first
+    Call(Function, Vec<Expr>),
+    Comprehension {
+        quantifier: Quantifier,
+        range: Box<Expr>,
+        var: String,
+        predicate: Box<Expr>,
+    },
+}
+
+/// Recursive descent over CEL's precedence levels
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens.get(self.pos).or(self.tokens.last()).map_or(0, |(at, _)| *at)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Punct(p)) if *p == punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Spanned<()> {
+        if self.eat(punct) {
+            return Ok(());
+        }
+        Err(match self.peek() {
+            Some(token) => (self.offset(), format!("expected '{}', found {}", punct, token)),
+            None => (self.offset(), format!("expected '{}' at end of expression", punct)),
+        })
+    }
+
+    fn expr(&mut self) -> Spanned<Expr> {
+        let condition = self.or()?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        let then = self.or()?;
+        self.expect(":")?;
+        let otherwise = self.expr()?;
+        Ok(Expr::Cond(Box::new(condition), Box::new(then), Box::new(otherwise)))
+    }
+
+    fn or(&mut self) -> Spanned<Expr> {
+        let mut left = self.and()?;
+        while self.eat("||") {
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Spanned<Expr> {
+        let mut left = self.relation()?;
+        while self.eat("&&") {
+            left = Expr::And(Box::new(left), Box::new(self.relation()?));
+        }
+        Ok(left)
+    }
+
+    fn relation(&mut self) -> Spanned<Expr> {
+        let mut left = self.addition()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct("==")) => BinaryOp::Eq,
+                Some(Token::Punct("!=")) => BinaryOp::Ne,
+                Some(Token::Punct("<")) => BinaryOp::Lt,
+                Some(Token::Punct("<=")) => BinaryOp::Le,
+                Some(Token::Punct(">")) => BinaryOp::Gt,
+                Some(Token::Punct(">=")) => BinaryOp::Ge,
+                Some(Token::Ident(name)) if name == "in" => BinaryOp::In,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.addition()?));
+        }
+    }
+
+    fn addition(&mut self) -> Spanned<Expr> {
+        let mut left = self.multiplication()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct("+")) => BinaryOp::Add,
+                Some(Token::Punct("-")) => BinaryOp::Sub,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.multiplication()?));
+        }
+    }
+
+    fn multiplication(&mut self) -> Spanned<Expr> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct("*")) => BinaryOp::Mul,
+                Some(Token::Punct("/")) => BinaryOp::Div,
+                Some(Token::Punct("%")) => BinaryOp::Rem,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Spanned<Expr> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            // Folded so the most negative int literal is representable
+            return Ok(match self.unary()? {
+                Expr::Literal(Value::Number(n)) if n.as_i64().is_some() => {
+                    Expr::Literal(Value::from(n.as_i64().unwrap_or_default().wrapping_neg()))
+                }
+                operand => Expr::Neg(Box::new(operand)),
+            });
+        }
+        self.member()
+    }
+
+    fn member(&mut self) -> Spanned<Expr> {
+        let mut operand = self.primary()?;
+        loop {
+            if self.eat(".") {
+                let at = self.offset();
+                let name = self.ident()?;
+                if !self.eat("(") {
+                    operand = Expr::Member(Box::new(operand), name);
+                    continue;
+                }
+                let args = self.args()?;
+                operand = method(operand, &name, args).map_err(|message| (at, message))?;
+            } else if self.eat("[") {
+                let index = self.expr()?;
+                self.expect("]")?;
+                operand = Expr::Index(Box::new(operand), Box::new(index));
+            } else {
+                return Ok(operand);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Spanned<Expr> {
+        let at = self.offset();
+        let Some((_, token)) = self.tokens.get(self.pos).cloned() else {
+            return Err((at, "unexpected end of expression".to_string()));
+        };
+        self.pos += 1;
+        match token {
+            Token::Int(n) => Ok(Expr::Literal(Value::from(n))),
+            Token::Double(n) => Ok(Expr::Literal(Number::from_f64(n).map_or(Value::Null, Value::Number))),
+            Token::Str(s) => Ok(Expr::Literal(Value::String(s))),
+            Token::Ident(name) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                _ if self.eat("(") => {
+                    let args = self.args()?;
+                    global(&name, args).map_err(|message| (at, message))
+                }
+                _ => Ok(Expr::Ident(name)),
+            },
+            Token::Punct("(") => {
+                let inner = self.expr()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Token::Punct("[") => {
+                let mut items = Vec::new();
+                if !self.eat("]") {
+                    loop {
+                        items.push(self.expr()?);
+                        if self.eat("]") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Expr::List(items))
+            }
+            other => Err((at, format!("unexpected {}", other))),
+        }
+    }
+
+    fn ident(&mut self) -> Spanned<String> {
+        match self.tokens.get(self.pos) {
+            Some((_, Token::Ident(name))) => {
+                self.pos += 1;
+                Ok(name.clone())
+            }
+            Some((at, token)) => Err((*at, format!("expected a field name, found {}", token))),
+            None => Err((self.offset(), "expected a field name at end of expression".to_string())),
+        }
+    }
+
+    /// Call arguments after the opening parenthesis
+    fn args(&mut self) -> Spanned<Vec<Expr>> {
+        let mut args = Vec::new();
+        if self.eat(")") {
+            return Ok(args);
+        }
+        loop {
+            args.push(self.expr()?);
+            if self.eat(")") {
+                return Ok(args);
+            }
+            self.expect(",")?;
+        }
+    }
+}
+
+fn global(name: &str, mut args: Vec<Expr>) -> std::result::Result<Expr, String> {
+    let function = match name {
+        "has" => {
+            return match args.pop() {
+                Some(arg @ (Expr::Member(..) | Expr::Ident(_))) if args.is_empty() => Ok(Expr::Has(Box::new(arg))),
+                _ => Err("has() takes a single field selection, like has(device.os)".to_string()),
+            }
+        }
+        "size" => Function::Size,
+        "int" => Function::Int,
+        "double" => Function::Double,
+        "string" => Function::String,
+        _ => return Err(format!("unknown function '{}'", name)),
+    };
+    if args.len() != 1 {
+        return Err(format!("{}() takes 1 argument, got {}", name, args.len()));
+    }
+    Ok(Expr::Call(function, args))
+}
+
+fn method(receiver: Expr, name: &str, mut args: Vec<Expr>) -> std::result::Result<Expr, String> {
+    let quantifier = match name {
+        "exists" => Some(Quantifier::Exists),
+        "all" => Some(Quantifier::All),
+        "exists_one" => Some(Quantifier::ExistsOne),
+        _ => None,
+    };
+    if let Some(quantifier) = quantifier {
+        return match (args.pop(), args.pop()) {
+            (Some(predicate), Some(Expr::Ident(var))) if args.is_empty() => Ok(Expr::Comprehension {
+                quantifier,
+                range: Box::new(receiver),
+                var,
+                predicate: Box::new(predicate),
+            }),
+            _ => Err(format!("{}() takes a variable name and a predicate, like {}(x, x > 0)", name, name)),
+        };
+    }
+
+    let (function, arity) = match name {
+        "size" => (Function::Size, 0),
+        "startsWith" => (Function::StartsWith, 1),
+        "endsWith" => (Function::EndsWith, 1),
+        "contains" => (Function::Contains, 1),
+        _ => return Err(format!("unknown method '{}'", name)),
+    };
+    if args.len() != arity {
+        return Err(format!("{}() takes {} argument(s), got {}", name, arity, args.len()));
+    }
+    args.insert(0, receiver);
+    Ok(Expr::Call(function, args))
+}
+
+/// Context plus the variables bound by enclosing macros (innermost last)
+struct Scope<'a> {
+    ctx: &'a HashMap<String, Value>,
+    vars: Vec<(String, Value)>,
+}
+
+type Eval = std::result::Result<Value, String>;
+
+fn eval(expr: &Expr, scope: &mut Scope) -> Eval {
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Ident(name) => scope
+            .vars
+            .iter()
+            .rev()
+            .find(|(var, _)| var == name)
+            .map(|(_, value)| value)
+            .or_else(|| scope.ctx.get(name))
+            .cloned()
+            .ok_or_else(|| format!("no such field '{}'", name)),
+        Expr::Member(object, name) => match eval(object, scope)? {
+            Value::Object(mut members) => members.remove(name).ok_or_else(|| format!("no such key '{}'", name)),
+            other => Err(format!("cannot select '{}' from {}", name, kind(&other))),
+        },
+        Expr::Index(object, index) => {
+            let (object, index) = (eval(object, scope)?, eval(index, scope)?);
+            match (object, &index) {
+                (Value::Array(mut items), Value::Number(n)) => {
+                    let i = n.as_i64().ok_or_else(|| format!("invalid list index {}", n))?;
+                    usize::try_from(i)
+                        .ok()
+                        .filter(|i| *i < items.len())
+                        .map(|i| items.swap_remove(i))
+                        .ok_or_else(|| format!("index {} out of range", i))
+                }
+                (Value::Object(mut members), Value::String(key)) => {
+                    members.remove(key).ok_or_else(|| format!("no such key '{}'", key))
+                }
+                (object, _) => Err(format!("cannot index {} with {}", kind(&object), kind(&index))),
+            }
+        }
+        Expr::List(items) => items.iter().map(|item| eval(item, scope)).collect::<std::result::Result<_, _>>().map(Value::Array),
+        Expr::Not(operand) => match eval(operand, scope)? {
+            Value::Bool(b) => Ok(Value::Bool(!b)),
+            other => Err(format!("no matching overload for '!' on {}", kind(&other))),
+        },
+        Expr::Neg(operand) => match eval(operand, scope)? {
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => i.checked_neg().map(Value::from).ok_or_else(|| "integer overflow".to_string()),
+                None => double(-n.as_f64().unwrap_or_default()),
+            },
+            other => Err(format!("no matching overload for '-' on {}", kind(&other))),
+        },
+        Expr::And(left, right) => logical(left, right, false, scope),
+        Expr::Or(left, right) => logical(left, right, true, scope),
+        Expr::Cond(condition, then, otherwise) => match eval(condition, scope)? {
+            Value::Bool(true) => eval(then, scope),
+            Value::Bool(false) => eval(otherwise, scope),
+            other => Err(format!("condition must be a bool, got {}", kind(&other))),
+        },
+        Expr::Has(selection) => {
+            let (object, name) = match &**selection {
+                Expr::Member(object, name) => (eval(object, scope)?, name),
+                Expr::Ident(name) => return Ok(Value::Bool(scope.ctx.get(name).is_some_and(|v| !v.is_null()))),
+                _ => unreachable!("has() argument is checked at compile time"),
+            };
+            match object {
+                // Null counts as absent, as for `exists` rules
+                Value::Object(members) => Ok(Value::Bool(members.get(name).is_some_and(|v| !v.is_null()))),
+                other => Err(format!("has() cannot select '{}' from {}", name, kind(&other))),
+            }
+        }
+        Expr::Binary(op, left, right) => {
+            let (left, right) = (eval(left, scope)?, eval(right, scope)?);
+            binary(*op, left, right)
+        }
+        Expr::Call(function, args) => {
+            let args = args.iter().map(|arg| eval(arg, scope)).collect::<std::result::Result<Vec<_>, _>>()?;
+            call(*function, args)
+        }
+        Expr::Comprehension {
+            quantifier,
+            range,
+            var,
+            predicate,
+        } => {
+            let items = match eval(range, scope)? {
+                Value::Array(items) => items,
+                Value::Object(members) => members.into_iter().map(|(key, _)| Value::String(key)).collect(),
+                other => Err(format!("cannot iterate over {}", kind(&other)))?,
+            };
+            comprehension(*quantifier, items, var, predicate, scope)
+        }
+    }
+}
+
+/// `&&` (`decides` false) or `||` (`decides` true): a deciding side wins over an error
+fn logical(left: &Expr, right: &Expr, decides: bool, scope: &mut Scope) -> Eval {
+    let as_bool = |value: Eval| match value {
+        Ok(Value::Bool(b)) => Ok(b),
+        Ok(other) => Err(format!("no matching overload for logical operator on {}", kind(&other))),
+        Err(e) => Err(e),
+    };
+    let left = as_bool(eval(left, scope));
+    if left == Ok(decides) {
+        return Ok(Value::Bool(decides));
+    }
+    match (left, as_bool(eval(right, scope))) {
+        (_, Ok(b)) if b == decides => Ok(Value::Bool(decides)),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+        _ => Ok(Value::Bool(!decides)),
+    }
+}
+
+fn comprehension(quantifier: Quantifier, items: Vec<Value>, var: &str, predicate: &Expr, scope: &mut Scope) -> Eval {
+    let mut matches = 0;
+    let mut error = None;
+    for item in items {
+        scope.vars.push((var.to_string(), item));
+        let result = eval(predicate, scope);
+        scope.vars.pop();
+        match (quantifier, result) {
+            (Quantifier::Exists, Ok(Value::Bool(true))) => return Ok(Value::Bool(true)),
+            (Quantifier::All, Ok(Value::Bool(false))) => return Ok(Value::Bool(false)),
+            (_, Ok(Value::Bool(b))) => matches += b as usize,
+            (_, Ok(other)) => error = Some(format!("predicate must be a bool, got {}", kind(&other))),
+            // exists_one has no deciding element to absorb an error
+            (Quantifier::ExistsOne, Err(e)) => return Err(e),
+            (_, Err(e)) => error = Some(e),
+        }
+    }
+    match (error, quantifier) {
+        (Some(e), _) => Err(e),
+        (None, Quantifier::Exists) => Ok(Value::Bool(false)),
+        (None, Quantifier::All) => Ok(Value::Bool(true)),
+        (None, Quantifier::ExistsOne) => Ok(Value::Bool(matches == 1)),
+    }
+}
+
+fn binary(op: BinaryOp, left: Value, right: Value) -> Eval {
+    use std::cmp::Ordering;
+
+    let ordering = |left: &Value, right: &Value| -> std::result::Result<Ordering, String> {
+        match (left, right) {
+            (Value::Number(l), Value::Number(r)) => match (l.as_i64(), r.as_i64()) {
+                (Some(l), Some(r)) => Ok(l.cmp(&r)),
+                _ => l
+                    .as_f64()
+                    .zip(r.as_f64())
+                    .and_then(|(l, r)| l.partial_cmp(&r))
+                    .ok_or_else(|| "numbers are not comparable".to_string()),
+            },
+            (Value::String(l), Value::String(r)) => Ok(l.cmp(r)),
+            (Value::Bool(l), Value::Bool(r)) => Ok(l.cmp(r)),
+            _ => Err(format!("cannot compare {} with {}", kind(left), kind(right))),
+        }
+    };
+
+    match op {
+        BinaryOp::Eq => Ok(Value::Bool(equal(&left, &right))),
+        BinaryOp::Ne => Ok(Value::Bool(!equal(&left, &right))),
+        BinaryOp::Lt => Ok(Value::Bool(ordering(&left, &right)? == Ordering::Less)),
+        BinaryOp::Le => Ok(Value::Bool(ordering(&left, &right)? != Ordering::Greater)),
+        BinaryOp::Gt => Ok(Value::Bool(ordering(&left, &right)? == Ordering::Greater)),
+        BinaryOp::Ge => Ok(Value::Bool(ordering(&left, &right)? != Ordering::Less)),
+        BinaryOp::In => match (&left, right) {
+            (_, Value::Array(items)) => Ok(Value::Bool(items.iter().any(|item| equal(&left, item)))),
+            (Value::String(key), Value::Object(members)) => Ok(Value::Bool(members.contains_key(key))),
+            (_, right) => Err(format!("no matching overload for 'in' on {} and {}", kind(&left), kind(&right))),
+        },
+        BinaryOp::Add => match (left, right) {
+            (Value::String(l), Value::String(r)) => Ok(Value::String(l + &r)),
+            (Value::Array(mut l), Value::Array(r)) => {
+                l.extend(r);
+                Ok(Value::Array(l))
+            }
+            (left, right) => arithmetic(op, &left, &right),
+        },
+        _ => arithmetic(op, &left, &right),
+    }
+}
+
+/// Int arithmetic on two ints (checked), double arithmetic on two doubles
+fn arithmetic(op: BinaryOp, left: &Value, right: &Value) -> Eval {
+    let overload = || format!("no matching overload for {:?} on {} and {}", op, kind(left), kind(right));
+    let (Value::Number(l), Value::Number(r)) = (left, right) else {
+        return Err(overload());
+    };
+    match (l.as_i64(), r.as_i64()) {
+        (Some(l), Some(r)) => {
+            let result = match op {
+                BinaryOp::Add => l.checked_add(r),
+                BinaryOp::Sub => l.checked_sub(r),
+                BinaryOp::Mul => l.checked_mul(r),
+                BinaryOp::Div | BinaryOp::Rem if r == 0 => return Err("division by zero".to_string()),
+                BinaryOp::Div => l.checked_div(r),
+                BinaryOp::Rem => l.checked_rem(r),
+                _ => return Err(overload()),
+            };
+            result.map(Value::from).ok_or_else(|| "integer overflow".to_string())
+        }
+        (None, None) => {
+            let (l, r) = (l.as_f64().unwrap_or_default(), r.as_f64().unwrap_or_default());
+            match op {
+                BinaryOp::Add => double(l + r),
+                BinaryOp::Sub => double(l - r),
+                BinaryOp::Mul => double(l * r),
+                BinaryOp::Div => double(l / r),
+                _ => Err(overload()),
+            }
+        }
+        // CEL has no implicit int / double conversion for arithmetic
+        _ => Err(overload()),
+    }
+}
+
+fn call(function: Function, mut args: Vec<Value>) -> Eval {
+    let arg = args.remove(0);
+    match (function, &arg, args.first()) {
+        (Function::Size, Value::String(s), None) => Ok(Value::from(s.chars().count() as i64)),
+        (Function::Size, Value::Array(items), None) => Ok(Value::from(items.len() as i64)),
+        (Function::Size, Value::Object(members), None) => Ok(Value::from(members.len() as i64)),
+        (Function::StartsWith, Value::String(s), Some(Value::String(prefix))) => Ok(Value::Bool(s.starts_with(prefix.as_str()))),
+        (Function::EndsWith, Value::String(s), Some(Value::String(suffix))) => Ok(Value::Bool(s.ends_with(suffix.as_str()))),
+        (Function::Contains, Value::String(s), Some(Value::String(part))) => Ok(Value::Bool(s.contains(part.as_str()))),
+        (Function::Int, Value::Number(n), None) => match n.as_i64() {
+            Some(i) => Ok(Value::from(i)),
+            None => n
+                .as_f64()
+                .filter(|f| f.is_finite() && *f >= i64::MIN as f64 && *f < i64::MAX as f64)
+                .map(|f| Value::from(f.trunc() as i64))
+                .ok_or_else(|| format!("int() of {} out of range", n)),
+        },
+        (Function::Int, Value::String(s), None) => {
+            s.trim().parse::<i64>().map(Value::from).map_err(|_| format!("int() cannot parse {:?}", s))
+        }
+        (Function::Double, Value::Number(n), None) => double(n.as_f64().unwrap_or_default()),
+        (Function::Double, Value::String(s), None) => match s.trim().parse::<f64>() {
+            Ok(f) => double(f),
+            Err(_) => Err(format!("double() cannot parse {:?}", s)),
+        },
+        (Function::String, Value::String(_), None) => Ok(arg),
+        (Function::String, Value::Number(_) | Value::Bool(_), None) => Ok(Value::String(arg.to_string())),
+        _ => Err(format!("no matching overload for {:?} on {}", function, kind(&arg))),
+    }
+}
+
+/// A double result; JSON has no NaN or infinity
+fn double(value: f64) -> Eval {
+    Number::from_f64(value)
+        .map(Value::Number)
+        .ok_or_else(|| format!("double result {} is not finite", value))
+}
+
+/// CEL equality: numbers compare by value across int and double
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => match (l.as_i64(), r.as_i64()) {
+            (Some(l), Some(r)) => l == r,
+            _ => l.as_f64() == r.as_f64(),
+        },
+        (Value::Array(l), Value::Array(r)) => l.len() == r.len() && l.iter().zip(r).all(|(l, r)| equal(l, r)),
+        (Value::Object(l), Value::Object(r)) => object_equal(l, r),
+        _ => left == right,
+    }
+}
+
+fn object_equal(left: &Map<String, Value>, right: &Map<String, Value>) -> bool {
+    left.len() == right.len() && left.iter().all(|(key, l)| right.get(key).is_some_and(|r| equal(l, r)))
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(n) if n.as_i64().is_some() => "int",
+        Value::Number(_) => "double",
+        Value::String(_) => "string",
+        Value::Array(_) => "list",
+        Value::Object(_) => "map",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn context() -> HashMap<String, Value> {
+        [
+            ("country".to_string(), json!("US")),
+            ("age".to_string(), json!(21)),
+            ("premium".to_string(), json!(false)),
+            ("entitlements".to_string(), json!(["pro", "beta"])),
+            ("device".to_string(), json!({"os": {"name": "ios", "version": "17.2"}})),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn test_evaluate_expressions() {
+        let ctx = context();
+        for (expr, expected) in [
+            (r#"country == "US" && (age >= 18 || premium)"#, true),
+            (r#"country in ["CA", "MX"]"#, false),
+            (r#"entitlements.exists(e, e.startsWith("be")) && size(entitlements) == 2"#, true),
+            (r#"entitlements.all(e, e in ["pro", "beta", "enterprise"])"#, true),
+            (r#"device.os.name == "ios" && has(device.os.version) && !has(device.model)"#, true),
+            ("age % 2 == 1 ? age * 2 == 42 : false", true),
+            ("-age < -20 && double(age) / 2.0 == 10.5", true),
+            // `||` decided by its right side absorbs the missing field on its left
+            (r#"missing == "x" || age > 18"#, true),
+        ] {
+            assert_eq!(compile(expr).unwrap().evaluate(&ctx).unwrap(), expected, "{}", expr);
+        }
+
+        // Errors fail the rule instead of matching
+        for expr in [r#"missing == "x""#, r#"age + "1" == 2"#, "age", "entitlements[5] == 1"] {
+            assert!(compile(expr).unwrap().evaluate(&ctx).is_err(), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_syntax_errors_point_at_offset() {
+        let err = Program::compile("age >= 18 &&").unwrap_err().to_string();
+        assert!(err.contains("unexpected end of expression"), "{}", err);
+        let err = Program::compile(r#"country.lower() == "us""#).unwrap_err().to_string();
+        assert!(err.contains("offset 8") && err.contains("unknown method 'lower'"), "{}", err);
+        assert!(Program::compile("has(1)").is_err());
+        assert!(Program::compile("tags.exists(1, true)").is_err());
+        assert!(Program::compile(r#""unterminated"#).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! Re-randomization impact of a layer edit.
+//!
+//! Moving ranges reassigns exactly the bucket slots whose vid changes; changing
+//! the salt (or version without an explicit salt), hash key or bucket size
+//! rehashes every subject, so assignments only survive by chance. Before
+//! applying a risky edit, `POST /preview/churn` reports the changed slots and
+//! the share of traffic expected to switch variants, and optionally replays a
+//! list of active subject keys (e.g. exported from assignment logs or a sticky
+//! store) through both definitions. Layer and experiment rules are not applied.
+
+use crate::error::{ExperimentError, Result};
+use crate::hash::hash_to_bucket_in;
+use crate::layer::{Layer, Snapshot};
+use crate::sim::MAX_POPULATION;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChurnRequest {
+    /// Candidate layer document (same format as a layer file)
+    pub layer: Value,
+
+    /// Layer to compare against; defaults to the live layer with the same id
+    #[serde(default)]
+    pub old: Option<Value>,
+
+    /// Active subject keys, used as the value of every hash key field
+    #[serde(default)]
+    pub subjects: Vec<String>,
+}
+
+/// A contiguous `[start, end)` span of buckets whose vid changes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangedSlots {
+    pub start: u32,
+    pub end: u32,
+    /// `null` when the span was unallocated
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SubjectChurn {
+    pub total: usize,
+    /// Subjects assigned a different vid (or newly (un)assigned)
+    pub affected: usize,
+    pub affected_ratio: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChurnReport {
+    pub layer_id: String,
+    /// Salt, hash key or bucket size changed: every subject lands in a new bucket
+    pub rehashed: bool,
+    /// Slots changing vid (empty when rehashed, where slots don't correspond)
+    pub changed_slots: Vec<ChangedSlots>,
+    pub changed_buckets: u32,
+    /// Expected share of subjects whose vid changes, assuming uniform hashing
+    pub estimated_affected_ratio: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subjects: Option<SubjectChurn>,
+}
+
+pub fn churn(request: &ChurnRequest, snapshot: &Snapshot) -> Result<ChurnReport> {
+    if request.subjects.len() > MAX_POPULATION {
+        return Err(ExperimentError::InvalidParameter(format!(
+            "Churn request has {} subjects (limit {})",
+            request.subjects.len(),
+            MAX_POPULATION
+        )));
+    }
+
+    let new = Layer::from_value_against(request.layer.clone(), false, snapshot.catalog())?;
+    let old = match &request.old {
+        Some(doc) => Layer::from_value_against(doc.clone(), false, snapshot.catalog())?,
+        None => snapshot
+            .layer(&new.layer_id)
+            .map(|layer| layer.as_ref().clone())
+            .ok_or_else(|| ExperimentError::LayerNotFound(new.layer_id.clone()))?,
+    };
+
+    let rehashed =
+        old.get_salt() != new.get_salt() || old.hash_key != new.hash_key || old.bucket_size() != new.bucket_size();
+
+    let (changed_slots, estimated_affected_ratio) = if rehashed {
+        (Vec::new(), rehashed_churn(&old, &new))
+    } else {
+        let slots = changed_slots(&old, &new);
+        let changed: u32 = slots.iter().map(|s| s.end - s.start).sum();
+        let ratio = changed as f64 / new.bucket_size() as f64;
+        (slots, ratio)
+    };
+    let changed_buckets = changed_slots.iter().map(|s| s.end - s.start).sum();
+
+    let subjects = (!request.subjects.is_empty()).then(|| {
+        let affected = request
+            .subjects
+            .iter()
+            .filter(|subject| assigned_vid(&old, subject) != assigned_vid(&new, subject))
+            .count();
+        SubjectChurn {
+            total: request.subjects.len(),
+            affected,
+            affected_ratio: affected as f64 / request.subjects.len() as f64,
+        }
+    });
+
+    Ok(ChurnReport {
+        layer_id: new.layer_id,
+        rehashed,
+        changed_slots,
+        changed_buckets,
+        estimated_affected_ratio,
+        subjects,
+    })
+}
+
+/// vid of every bucket (`None` = unallocated)
+fn slot_vids(layer: &Layer) -> Vec<Option<i64>> {
+    (0..layer.bucket_size())
+        .map(|bucket| layer.get_range(bucket).map(|r| r.vid))
+        .collect()
+}
+
+/// Spans whose vid differs between layers sharing one bucket space
+fn changed_slots(old: &Layer, new: &Layer) -> Vec<ChangedSlots> {
+    let (before, after) = (slot_vids(old), slot_vids(new));
+    let mut slots: Vec<ChangedSlots> = Vec::new();
+    for (bucket, (from, to)) in before.into_iter().zip(after).enumerate() {
+        if from == to {
+            continue;
+        }
+        let bucket = bucket as u32;
+        match slots.last_mut() {
+            Some(last) if last.end == bucket && last.from == from && last.to == to => last.end += 1,
+            _ => slots.push(ChangedSlots {
+                start: bucket,
+                end: bucket + 1,
+                from,
+                to,
+            }),
+        }
+    }
+    slots
+}
+
+/// Independent rehash: a subject keeps its vid with probability sum_v P_old(v) * P_new(v)
+fn rehashed_churn(old: &Layer, new: &Layer) -> f64 {
+    let shares = |layer: &Layer| {
+        let mut shares: HashMap<Option<i64>, f64> = HashMap::new();
+        let size = layer.bucket_size() as f64;
+        for vid in slot_vids(layer) {
+            *shares.entry(vid).or_default() += 1.0 / size;
+        }
+        shares
+    };
+    let (before, after) = (shares(old), shares(new));
+    let kept: f64 = before
+        .iter()
+        .map(|(vid, share)| share * after.get(vid).copied().unwrap_or(0.0))
+        .sum();
+    (1.0 - kept).max(0.0)
+}
+
+fn assigned_vid(layer: &Layer, subject: &str) -> Option<i64> {
+    let context: HashMap<String, Value> = layer
+        .hash_key
+        .fields()
+        .iter()
+        .map(|field| (field.clone(), Value::String(subject.to_string())))
+        .collect();
+    let key = layer.hash_key.value(&context).ok()?;
+    let bucket = hash_to_bucket_in(&key, &layer.get_salt(), layer.bucket_size());
+    layer.get_range(bucket).map(|r| r.vid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn layer(salt: &str, ranges: Value) -> Value {
+        json!({"layer_id": "l1", "version": "v1", "priority": 1, "hash_key": "user_id",
+               "salt": salt, "enabled": true, "ranges": ranges})
+    }
+
+    #[test]
+    fn test_moved_ranges_change_only_their_slots() {
+        let old = layer("s", json!([{"start": 0, "end": 5000, "vid": 1}, {"start": 5000, "end": 10000, "vid": 2}]));
+        let new = layer("s", json!([{"start": 0, "end": 4000, "vid": 1}, {"start": 4000, "end": 10000, "vid": 2}]));
+        let subjects: Vec<String> = (0..2000).map(|i| format!("u{}", i)).collect();
+        let request = ChurnRequest {
+            layer: new,
+            old: Some(old.clone()),
+            subjects: subjects.clone(),
+        };
+
+        let report = churn(&request, &Snapshot::default()).unwrap();
+        assert!(!report.rehashed);
+        assert_eq!(
+            report.changed_slots,
+            vec![ChangedSlots {
+                start: 4000,
+                end: 5000,
+                from: Some(1),
+                to: Some(2),
+            }]
+        );
+        assert!((report.estimated_affected_ratio - 0.1).abs() < 1e-9);
+        let sampled = report.subjects.unwrap().affected_ratio;
+        assert!((sampled - 0.1).abs() < 0.03, "sampled churn {}", sampled);
+
+        // A new salt reshuffles everyone: a 50/50 split keeps half by chance
+        let request = ChurnRequest {
+            layer: layer("t", json!([{"start": 0, "end": 5000, "vid": 1}, {"start": 5000, "end": 10000, "vid": 2}])),
+            old: Some(old),
+            subjects,
+        };
+        let report = churn(&request, &Snapshot::default()).unwrap();
+        assert!(report.rehashed);
+        assert!(report.changed_slots.is_empty());
+        assert!((report.estimated_affected_ratio - 0.5).abs() < 1e-9);
+    }
+}
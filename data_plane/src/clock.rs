@@ -0,0 +1,30 @@
+//! Time source for rule evaluation.
+//!
+//! Timestamp rules may compare against the current time (`"now-7d"`). The
+//! [`LayerManager`](crate::layer::LayerManager) carries a [`Clock`] into every
+//! snapshot, so tests can pin "now" with [`Clock::Fixed`] and get the same
+//! assignments from `merge_layers_batch` on every run.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Clock {
+    /// Wall-clock time
+    #[default]
+    System,
+    /// Always this instant, in unix milliseconds
+    Fixed(i64),
+}
+
+impl Clock {
+    /// Current time in unix milliseconds
+    pub fn now_millis(&self) -> i64 {
+        match self {
+            Clock::System => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or(0),
+            Clock::Fixed(millis) => *millis,
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Cloning a concluded experiment into a draft re-run.
+//!
+//! `POST /experiments/:eid/clone` copies the experiment with a fresh eid and
+//! vids, and each layer holding its ranges into a new layer with the same
+//! ranges (other experiments' ranges left unallocated) and a newly generated
+//! salt. Structure and params are preserved while the re-run's assignment is
+//! independent of the original's. The clone is written to the current config
+//! source as new files in the `draft` state, and is picked up by the watcher
+//! like any other change; it serves once moved to `ramping` / `running`.
+
+use crate::catalog::{ExperimentDef, VariantDef};
+use crate::config::migrate::{self, DEFAULT_EID_START, EID_STRIDE, EXPERIMENT_SCHEMA_VERSION, LAYER_SCHEMA_VERSION};
+use crate::error::{ExperimentError, Result};
+use crate::layer::{BucketRange, Layer, Snapshot};
+use crate::lifecycle::LifecycleState;
+use crate::source::ConfigSource;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// A cloned experiment and its layers, not yet written
+#[derive(Debug, Clone)]
+pub struct ExperimentClone {
+    pub source_eid: i64,
+    pub experiment: ExperimentDef,
+    /// Source vid -> cloned vid
+    pub vids: BTreeMap<i64, i64>,
+    /// Source layer_id -> cloned layer
+    pub layers: BTreeMap<String, Layer>,
+}
+
+/// What `POST /experiments/:eid/clone` wrote
+#[derive(Debug, Clone, Serialize)]
+pub struct CloneReport {
+    pub source_eid: i64,
+    pub eid: i64,
+    /// Source vid -> cloned vid
+    pub vids: BTreeMap<i64, i64>,
+    pub layers: Vec<ClonedLayer>,
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClonedLayer {
+    pub source_layer_id: String,
+    pub layer_id: String,
+    pub salt: String,
+}
+
+/// Clone of `eid` against the experiments and layers of `snapshot`
+pub fn clone_experiment(snapshot: &Snapshot, eid: i64) -> Result<ExperimentClone> {
+    let catalog = snapshot.catalog();
+    let source = catalog.get_experiment(eid).ok_or(ExperimentError::ExperimentNotFound(eid))?;
+    if source.variants.len() as i64 >= EID_STRIDE {
+        return Err(ExperimentError::InvalidParameter(format!(
+            "Experiment {} has {} variants, more than eid stride {} allows",
+            eid,
+            source.variants.len(),
+            EID_STRIDE
+        )));
+    }
+
+    // Next free stride above every existing eid, like generated ids
+    let mut new_eid = catalog
+        .eids()
+        .max()
+        .map_or(DEFAULT_EID_START, |max| (max.div_euclid(EID_STRIDE) + 1) * EID_STRIDE)
+        .max(DEFAULT_EID_START);
+    while (0..=source.variants.len() as i64).any(|i| catalog.get_eid_by_vid(new_eid + i).is_some()) {
+        new_eid += EID_STRIDE;
+    }
+    let vids: BTreeMap<i64, i64> = source
+        .variants
+        .iter()
+        .enumerate()
+        .map(|(i, variant)| (variant.vid, new_eid + 1 + i as i64))
+        .collect();
+
+    let experiment = ExperimentDef {
+        eid: new_eid,
+        service: source.service.clone(),
+        rule: source.rule.clone(),
+        // A concluded test has usually expired; the re-run gets its own schedule
+        expires_at: None,
+        state: LifecycleState::Draft,
+        caps: source.caps.clone().map(|mut caps| {
+            caps.control_vid = vids[&caps.control_vid];
+            caps.max_subjects = caps.max_subjects.into_iter().map(|(vid, max)| (vids[&vid], max)).collect();
+            caps
+        }),
+        exposure_sampling: source.exposure_sampling,
+        variants: source
+            .variants
+            .iter()
+            .map(|variant| VariantDef {
+                vid: vids[&variant.vid],
+                params: variant.params.clone(),
+                dark_params: variant.dark_params.clone(),
+            })
+            .collect(),
+    };
+
+    let mut layers = BTreeMap::new();
+    for layer in snapshot.layers() {
+        let ranges: Vec<BucketRange> = layer
+            .ranges
+            .iter()
+            .filter_map(|range| {
+                vids.get(&range.vid).map(|&vid| BucketRange {
+                    vid,
+                    ..range.clone()
+                })
+            })
+            .collect();
+        if ranges.is_empty() {
+            continue;
+        }
+        let layer_id = format!("{}_clone_{}", layer.layer_id, new_eid);
+        layers.insert(
+            layer.layer_id.clone(),
+            Layer {
+                salt: Some(format!("{}_{:016x}", layer_id, rand::random::<u64>())),
+                layer_id,
+                version: "v1".to_string(),
+                expires_at: None,
+                services: Vec::new(),
+                ranges,
+                ..(**layer).clone()
+            },
+        );
+    }
+
+    Ok(ExperimentClone {
+        source_eid: eid,
+        experiment,
+        vids,
+        layers,
+    })
+}
+
+impl ExperimentClone {
+    /// Write the clone as new files of `source`; existing files are never overwritten
+    pub fn write(&self, source: &ConfigSource) -> Result<CloneReport> {
+        let mut doc = serde_json::to_value(&self.experiment)?;
+        doc["schema_version"] = serde_json::Value::from(EXPERIMENT_SCHEMA_VERSION);
+        let mut documents = vec![(source.experiments_dir.join(format!("{}.json", self.experiment.eid)), doc)];
+        for layer in self.layers.values() {
+            let mut doc = serde_json::to_value(layer)?;
+            if let Some(obj) = doc.as_object_mut() {
+                // Deprecated in the current layer schema
+                obj.remove("services");
+                obj.insert("schema_version".to_string(), LAYER_SCHEMA_VERSION.into());
+            }
+            documents.push((source.layers_dir.join(format!("{}.json", layer.layer_id)), doc));
+        }
+
+        // Checked up front so a conflict doesn't leave half a clone behind
+        if let Some((path, _)) = documents.iter().find(|(path, _)| path.exists()) {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "Refusing to overwrite existing file {:?}",
+                path
+            )));
+        }
+        let mut files = Vec::with_capacity(documents.len());
+        for (path, doc) in documents {
+            migrate::write_new_file(&path, &serde_json::to_string_pretty(&doc)?)?;
+            files.push(path);
+        }
+
+        Ok(CloneReport {
+            source_eid: self.source_eid,
+            eid: self.experiment.eid,
+            vids: self.vids.clone(),
+            layers: self
+                .layers
+                .iter()
+                .map(|(source_layer_id, layer)| ClonedLayer {
+                    source_layer_id: source_layer_id.clone(),
+                    layer_id: layer.layer_id.clone(),
+                    salt: layer.get_salt(),
+                })
+                .collect(),
+            files,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::ExperimentCatalog;
+    use crate::layer::LayerManager;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_clone_gets_fresh_ids_and_salt() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let source = ConfigSource {
+            layers_dir: temp_dir.path().join("layers"),
+            experiments_dir: temp_dir.path().join("experiments"),
+        };
+        std::fs::create_dir_all(&source.layers_dir).unwrap();
+        std::fs::create_dir_all(&source.experiments_dir).unwrap();
+        std::fs::write(
+            source.experiments_dir.join("100.json"),
+            json!({
+                "eid": 100, "service": "svc", "state": "completed",
+                "variants": [{"vid": 101, "params": {"color": "red"}}, {"vid": 102, "params": {"color": "blue"}}]
+            })
+            .to_string(),
+        )
+        .unwrap();
+        std::fs::write(
+            source.experiments_dir.join("200.json"),
+            json!({"eid": 200, "service": "svc", "variants": [{"vid": 201, "params": {}}]}).to_string(),
+        )
+        .unwrap();
+        std::fs::write(
+            source.layers_dir.join("checkout.json"),
+            json!({
+                "layer_id": "checkout", "version": "v3", "priority": 10, "hash_key": "user_id", "enabled": true,
+                "ranges": [{"start": 0, "end": 100, "vid": 101}, {"start": 100, "end": 200, "vid": 102}, {"start": 200, "end": 300, "vid": 201}]
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(source.experiments_dir.clone()).unwrap());
+        let manager = LayerManager::new(source.layers_dir.clone());
+        manager.load_all_layers(&catalog).await.unwrap();
+
+        let clone = clone_experiment(&manager.snapshot(), 100).unwrap();
+        assert_eq!(clone.experiment.eid, DEFAULT_EID_START);
+        assert_eq!(clone.experiment.state, LifecycleState::Draft);
+        assert_eq!(clone.vids, BTreeMap::from([(101, 100_001), (102, 100_002)]));
+        let layer = &clone.layers["checkout"];
+        assert_eq!(layer.layer_id, "checkout_clone_100000");
+        assert_ne!(layer.get_salt(), manager.snapshot().layer("checkout").unwrap().get_salt());
+        // Other experiments' ranges stay behind
+        let ranges: Vec<_> = layer.ranges.iter().map(|r| (r.start, r.end, r.vid)).collect();
+        assert_eq!(ranges, vec![(0, 100, 100_001), (100, 200, 100_002)]);
+
+        // Written files load back as they are
+        let report = clone.write(&source).unwrap();
+        assert_eq!(report.files.len(), 2);
+        let reloaded = ExperimentCatalog::load_from_dir(source.experiments_dir.clone()).unwrap();
+        assert_eq!(reloaded.get_experiment(100_000).unwrap().into_owned(), clone.experiment);
+        assert_eq!(&Layer::from_file(&report.files[1]).unwrap(), layer);
+        assert!(clone.write(&source).is_err());
+
+        assert!(clone_experiment(&manager.snapshot(), 999).is_err());
+    }
+}
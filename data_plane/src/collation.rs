@@ -0,0 +1,75 @@
+//! String collation for rule comparisons.
+//!
+//! String fields compare byte-wise by default, so `"München"` never equals
+//! `"munchen"` and `"Ärzte"` sorts after `"Zoo"`. A rule node can opt into a
+//! [`Collation`] instead: both sides are reduced to a collation key before
+//! comparing, which makes equality, sets, patterns and ordering ignore the
+//! differences the collation doesn't care about — like ICU's secondary
+//! (case-insensitive) and primary (case- and accent-insensitive) strengths.
+
+use icu_normalizer::DecomposingNormalizerBorrowed;
+use icu_properties::props::{GeneralCategory, GeneralCategoryGroup};
+use icu_properties::CodePointMapData;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Collation {
+    /// Byte-wise, same as no collation
+    Binary,
+    /// Letter case ignored (`"Straße"` = `"STRASSE"`)
+    CaseInsensitive,
+    /// Letter case and accents ignored (`"Ärzte"` = `"arzte"`)
+    AccentInsensitive,
+}
+
+impl Collation {
+    /// Key that compares as `text` does under this collation
+    pub fn key<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            Collation::Binary => Cow::Borrowed(text),
+            Collation::CaseInsensitive => Cow::Owned(fold_case(text)),
+            Collation::AccentInsensitive => {
+                let marks = CodePointMapData::<GeneralCategory>::new();
+                let decomposed = DecomposingNormalizerBorrowed::new_nfd().normalize(text);
+                let base: String = decomposed
+                    .chars()
+                    .filter(|c| !GeneralCategoryGroup::Mark.contains(marks.get(*c)))
+                    .collect();
+                Cow::Owned(fold_case(&base))
+            }
+        }
+    }
+
+    /// JSON string values replaced by their keys; other values are kept
+    pub fn apply<'a>(&self, value: &'a serde_json::Value) -> Cow<'a, serde_json::Value> {
+        match value {
+            serde_json::Value::String(s) if *self != Collation::Binary => {
+                Cow::Owned(serde_json::Value::String(self.key(s).into_owned()))
+            }
+            _ => Cow::Borrowed(value),
+        }
+    }
+}
+
+/// Lowercase, with the sharp s expanded the way Unicode case folding does
+fn fold_case(text: &str) -> String {
+    text.to_lowercase().replace('ß', "ss")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collation_keys() {
+        assert_eq!(Collation::Binary.key("Ärzte"), "Ärzte");
+        assert_eq!(Collation::CaseInsensitive.key("Straße"), Collation::CaseInsensitive.key("STRASSE"));
+        assert_ne!(Collation::CaseInsensitive.key("Ärzte"), Collation::CaseInsensitive.key("arzte"));
+        assert_eq!(Collation::AccentInsensitive.key("Ärzte"), "arzte");
+        assert_eq!(Collation::AccentInsensitive.key("São Paulo"), "sao paulo");
+        // Precomposed and combining forms collate the same
+        assert_eq!(Collation::AccentInsensitive.key("e\u{301}"), Collation::AccentInsensitive.key("é"));
+    }
+}
@@ -0,0 +1,862 @@
+//! Config format migration.
+//!
+//! Two parts:
+//! - Schema versioning: files may declare `schema_version`; older versions are
+//!   upgraded step by step through the converter tables below at load time, and
+//!   unknown (newer) versions are rejected. Unversioned files keep the legacy
+//!   shape-based handling.
+//! - `migrate-config`: offline migration of legacy layer files (boundary `buckets` /
+//!   `ranges.group` + inline `groups`) into the `ranges` layer format plus catalog
+//!   `ExperimentDef` files. Groups in a layer that share the same service and rule
+//!   become variants of one experiment; groups whose rules differ become separate
+//!   experiments, since a rule is experiment-level in the catalog. Layer salt/version
+//!   are preserved so every subject keeps its bucket.
+
+use crate::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
+use crate::error::{ExperimentError, Result};
+use crate::layer::{validate_and_sort_ranges, BucketRange, BUCKET_SIZE};
+use crate::rule::Node;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Current layer schema: 1 = legacy `buckets`/`groups`, 2 = `ranges` with vids
+pub const LAYER_SCHEMA_VERSION: u32 = 2;
+
+/// Current experiment (catalog) schema
+pub const EXPERIMENT_SCHEMA_VERSION: u32 = 1;
+
+/// Step converter from version N to N+1
+type Converter = fn(serde_json::Value) -> Result<serde_json::Value>;
+
+/// `LAYER_CONVERTERS[n - 1]` upgrades a layer document from version n to n + 1
+const LAYER_CONVERTERS: &[Converter] = &[layer_v1_to_v2];
+
+/// `EXPERIMENT_CONVERTERS[n - 1]` upgrades an experiment document from version n to n + 1
+const EXPERIMENT_CONVERTERS: &[Converter] = &[];
+
+/// Fields that must not appear in a current-version layer
+const DEPRECATED_LAYER_FIELDS: &[&str] = &["buckets", "groups", "services"];
+
+/// Parse a JSON or YAML document into a JSON value (JSON first, then YAML).
+///
+/// Documents that start with `{` are reported with the JSON error so the
+/// location points at the actual syntax problem rather than a YAML reinterpretation.
+pub fn parse_document(content: &str) -> Result<serde_json::Value> {
+    match serde_json::from_str(content) {
+        Ok(doc) => Ok(doc),
+        Err(e) if content.trim_start().starts_with('{') => Err(e.into()),
+        Err(_) => Ok(serde_yaml::from_str(content)?),
+    }
+}
+
+/// Upgrade a layer document to `LAYER_SCHEMA_VERSION`.
+///
+/// Unversioned documents are returned unchanged.
+pub fn upgrade_layer(doc: serde_json::Value) -> Result<serde_json::Value> {
+    let upgraded = upgrade(doc, "layer", LAYER_SCHEMA_VERSION, LAYER_CONVERTERS)?;
+
+    if let Some(obj) = upgraded.as_object() {
+        if obj.contains_key("schema_version") {
+            let present: Vec<&str> = DEPRECATED_LAYER_FIELDS
+                .iter()
+                .copied()
+                .filter(|f| obj.contains_key(*f))
+                .collect();
+            if !present.is_empty() {
+                return Err(ExperimentError::SchemaVersion(format!(
+                    "layer schema_version {} does not allow field(s): {}",
+                    LAYER_SCHEMA_VERSION,
+                    present.join(", ")
+                )));
+            }
+        }
+    }
+
+    Ok(upgraded)
+}
+
+/// Deprecated fields set in a layer document, checked before any upgrade so
+/// strict mode also rejects a `schema_version: 1` file the converter would
+/// otherwise rewrite
+pub fn deprecated_layer_fields(doc: &serde_json::Value) -> Vec<&'static str> {
+    let is_set = |field: &str| match doc.get(field) {
+        Some(serde_json::Value::Object(map)) => !map.is_empty(),
+        Some(serde_json::Value::Array(items)) => !items.is_empty(),
+        Some(serde_json::Value::Null) | None => false,
+        Some(_) => true,
+    };
+    let mut fields: Vec<&'static str> = DEPRECATED_LAYER_FIELDS
+        .iter()
+        .copied()
+        .filter(|f| is_set(f))
+        .collect();
+    let group_ranges = doc
+        .get("ranges")
+        .and_then(|r| r.as_array())
+        .is_some_and(|ranges| ranges.iter().any(|r| r.get("group").is_some()));
+    if group_ranges {
+        fields.push("ranges[].group");
+    }
+    fields
+}
+
+/// Upgrade an experiment document to `EXPERIMENT_SCHEMA_VERSION`
+pub fn upgrade_experiment(doc: serde_json::Value) -> Result<serde_json::Value> {
+    upgrade(doc, "experiment", EXPERIMENT_SCHEMA_VERSION, EXPERIMENT_CONVERTERS)
+}
+
+fn upgrade(
+    mut doc: serde_json::Value,
+    kind: &str,
+    current: u32,
+    converters: &[Converter],
+) -> Result<serde_json::Value> {
+    let Some(raw) = doc.get("schema_version") else {
+        return Ok(doc);
+    };
+    let mut version = raw
+        .as_u64()
+        .filter(|v| *v >= 1)
+        .ok_or_else(|| {
+            ExperimentError::SchemaVersion(format!("{} schema_version must be a positive integer, got {}", kind, raw))
+        })? as u32;
+
+    if version > current {
+        return Err(ExperimentError::SchemaVersion(format!(
+            "{} schema_version {} is newer than supported version {}",
+            kind, version, current
+        )));
+    }
+
+    while version < current {
+        doc = converters[(version - 1) as usize](doc)?;
+        version += 1;
+        doc["schema_version"] = serde_json::Value::from(version);
+    }
+
+    Ok(doc)
+}
+
+/// Layer v1 → v2: resolve `buckets`/`ranges.group` through inline `groups` into vid ranges
+fn layer_v1_to_v2(mut doc: serde_json::Value) -> Result<serde_json::Value> {
+    let obj = doc
+        .as_object_mut()
+        .ok_or_else(|| ExperimentError::SchemaVersion("layer document must be an object".to_string()))?;
+
+    let groups: HashMap<String, LegacyGroup> = match obj.remove("groups") {
+        Some(v) => serde_json::from_value(v)?,
+        None => HashMap::new(),
+    };
+    let buckets: HashMap<u32, String> = match obj.remove("buckets") {
+        Some(v) => serde_json::from_value(v)?,
+        None => HashMap::new(),
+    };
+    let services = obj.remove("services");
+    let ranges: Vec<LegacyRange> = match obj.remove("ranges") {
+        Some(v) => serde_json::from_value(v)?,
+        None => Vec::new(),
+    };
+    // Kept in the document: the upgraded ranges are validated against it on parse
+    let bucket_size: Option<u32> = match obj.get("bucket_size") {
+        Some(v) => serde_json::from_value(v.clone())?,
+        None => None,
+    };
+
+    let legacy = LegacyLayerFile {
+        layer_id: String::new(),
+        version: String::new(),
+        priority: 0,
+        hash_key: String::new(),
+        salt: None,
+        bucket_size,
+        services: Vec::new(),
+        enabled: false,
+        ranges,
+        buckets,
+        groups,
+    };
+
+    let converted: Vec<BucketRange> = legacy_ranges(&legacy)
+        .into_iter()
+        .map(|(start, end, target)| {
+            let vid = match target {
+                RangeTarget::Vid(vid) => vid,
+                RangeTarget::Group(g) => match g.parse::<i64>() {
+                    Ok(vid) => vid,
+                    Err(_) => legacy
+                        .groups
+                        .get(&g)
+                        .ok_or_else(|| ExperimentError::GroupNotFound(g.clone()))?
+                        .vid
+                        .ok_or_else(|| {
+                            ExperimentError::SchemaVersion(format!(
+                                "group '{}' has no vid; run `migrate-config` to generate catalog entries",
+                                g
+                            ))
+                        })?,
+                },
+            };
+            Ok(BucketRange { start, end, vid, label: None })
+        })
+        .collect::<Result<_>>()?;
+
+    // Services are inferred from the catalog and group params / rules come
+    // from catalog variants; say what the upgrade left behind
+    let mut dropped = Vec::new();
+    if services.is_some_and(|s| s.as_array().is_some_and(|s| !s.is_empty())) {
+        dropped.push("services");
+    }
+    let group_fields = legacy.groups.values().any(|g| {
+        g.service.is_some() || g.rule.is_some() || g.params.as_object().is_some_and(|p| !p.is_empty())
+    });
+    if group_fields {
+        dropped.push("groups[].service/rule/params");
+    }
+    if !dropped.is_empty() {
+        tracing::warn!(
+            "Layer {} upgraded from schema_version 1 dropped {}; define them on catalog experiments",
+            obj.get("layer_id").and_then(|v| v.as_str()).unwrap_or("?"),
+            dropped.join(", ")
+        );
+    }
+
+    obj.insert("ranges".to_string(), serde_json::to_value(converted)?);
+    Ok(doc)
+}
+
+/// Generated eids are spaced by this stride; vids are `eid + 1 ..= eid + n`
+pub const EID_STRIDE: i64 = 100;
+
+/// Default first generated eid (kept clear of hand-assigned ids)
+pub const DEFAULT_EID_START: i64 = 100_000;
+
+#[derive(Debug, Clone, Deserialize)]
+struct LegacyLayerFile {
+    layer_id: String,
+    version: String,
+    priority: i32,
+    hash_key: String,
+    #[serde(default)]
+    salt: Option<String>,
+    #[serde(default)]
+    bucket_size: Option<u32>,
+    #[serde(default)]
+    services: Vec<String>,
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default)]
+    ranges: Vec<LegacyRange>,
+    #[serde(default)]
+    buckets: HashMap<u32, String>,
+    #[serde(default)]
+    groups: HashMap<String, LegacyGroup>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum LegacyRange {
+    Vid { start: u32, end: u32, vid: i64 },
+    Group { start: u32, end: u32, group: String },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct LegacyGroup {
+    #[serde(default)]
+    vid: Option<i64>,
+    #[serde(default)]
+    service: Option<String>,
+    #[serde(default = "empty_params")]
+    params: serde_json::Value,
+    #[serde(default)]
+    rule: Option<Node>,
+}
+
+fn empty_params() -> serde_json::Value {
+    serde_json::Value::Object(serde_json::Map::new())
+}
+
+impl LegacyLayerFile {
+    fn is_legacy(&self) -> bool {
+        !self.buckets.is_empty()
+            || !self.groups.is_empty()
+            || self.ranges.iter().any(|r| matches!(r, LegacyRange::Group { .. }))
+    }
+}
+
+/// Migration options
+#[derive(Debug, Clone)]
+pub struct MigrateOptions {
+    pub layers_dir: PathBuf,
+    pub out_dir: PathBuf,
+    pub eid_start: i64,
+    /// Existing catalog whose eids/vids must not be reused
+    pub existing_experiments_dir: Option<PathBuf>,
+}
+
+/// group → generated ids
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct GroupMapping {
+    pub group: String,
+    pub service: String,
+    pub eid: i64,
+    pub vid: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LayerMigration {
+    pub layer_id: String,
+    pub source: PathBuf,
+    pub groups: Vec<GroupMapping>,
+}
+
+/// Mapping report written to `<out>/migration_report.json`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MigrationReport {
+    pub migrated: Vec<LayerMigration>,
+    /// Files already in the `ranges` format (left untouched)
+    pub skipped: Vec<PathBuf>,
+    pub experiments_written: usize,
+}
+
+/// Migrated layer file (field order matches hand-written configs)
+#[derive(Debug, Clone, Serialize)]
+struct MigratedLayer {
+    schema_version: u32,
+    layer_id: String,
+    version: String,
+    priority: i32,
+    hash_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bucket_size: Option<u32>,
+    enabled: bool,
+    ranges: Vec<BucketRange>,
+}
+
+/// Allocates eids/vids, skipping ids already in use
+struct IdAllocator {
+    next_eid: i64,
+    used_eids: HashSet<i64>,
+    used_vids: HashSet<i64>,
+}
+
+impl IdAllocator {
+    /// Allocator starting at `next_eid` that skips every eid and vid of `catalog`
+    fn reserving(next_eid: i64, catalog: &ExperimentCatalog) -> Self {
+        let mut alloc = IdAllocator {
+            next_eid,
+            used_eids: HashSet::new(),
+            used_vids: HashSet::new(),
+        };
+        for eid in catalog.eids() {
+            alloc.used_eids.insert(eid);
+            if let Some(exp) = catalog.experiment_def(eid) {
+                alloc.used_vids.extend(exp.variants.iter().map(|v| v.vid));
+            }
+        }
+        alloc
+    }
+
+    fn next_experiment(&mut self, variant_count: usize) -> Result<i64> {
+        if variant_count as i64 >= EID_STRIDE {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "Experiment with {} variants exceeds eid stride {}",
+                variant_count, EID_STRIDE
+            )));
+        }
+        loop {
+            let eid = self.next_eid;
+            self.next_eid += EID_STRIDE;
+            let vids_free = (1..=variant_count as i64).all(|i| !self.used_vids.contains(&(eid + i)));
+            if !self.used_eids.contains(&eid) && vids_free {
+                self.used_eids.insert(eid);
+                return Ok(eid);
+            }
+        }
+    }
+}
+
+/// Ids to register for a layer document's inline groups: eids from one past
+/// the highest eid in `catalog` (or [`DEFAULT_EID_START`] when it is empty),
+/// skipping every vid the catalog already uses. Empty when the groups can't
+/// be migrated as they are (e.g. no service, or a declared vid in use).
+pub fn inline_group_plan(doc: &serde_json::Value, catalog: &ExperimentCatalog) -> Vec<GroupMapping> {
+    let next_eid = catalog.eids().max().map_or(DEFAULT_EID_START, |max| max + 1);
+    let mut alloc = IdAllocator::reserving(next_eid, catalog);
+    serde_json::from_value::<LegacyLayerFile>(doc.clone())
+        .ok()
+        .and_then(|legacy| migrate_layer(&legacy, &mut alloc).ok())
+        .map(|(_, _, groups)| groups)
+        .unwrap_or_default()
+}
+
+/// Run the migration, writing `layers/`, `experiments/` and the report under `out_dir`
+pub fn migrate(options: &MigrateOptions) -> Result<MigrationReport> {
+    let existing = match &options.existing_experiments_dir {
+        Some(dir) => ExperimentCatalog::load_from_dir(dir.clone())?,
+        None => ExperimentCatalog::default(),
+    };
+    let mut alloc = IdAllocator::reserving(options.eid_start, &existing);
+
+    let out_layers = options.out_dir.join("layers");
+    let out_experiments = options.out_dir.join("experiments");
+    std::fs::create_dir_all(&out_layers)?;
+    std::fs::create_dir_all(&out_experiments)?;
+
+    let mut files: Vec<PathBuf> = std::fs::read_dir(&options.layers_dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.is_file()
+                && p.extension()
+                    .and_then(|s| s.to_str())
+                    .is_some_and(|ext| ext == "json" || ext == "yaml" || ext == "yml")
+        })
+        .collect();
+    files.sort();
+
+    let mut report = MigrationReport::default();
+
+    for path in files {
+        let legacy = read_legacy_layer(&path)?;
+        if !legacy.is_legacy() {
+            report.skipped.push(path);
+            continue;
+        }
+
+        let (layer, experiments, groups) = migrate_layer(&legacy, &mut alloc)
+            .map_err(|e| ExperimentError::InvalidParameter(format!("{:?}: {}", path, e)))?;
+
+        write_new_file(
+            &out_layers.join(format!("{}.json", legacy.layer_id)),
+            &serde_json::to_string_pretty(&layer)?,
+        )?;
+        for exp in &experiments {
+            let mut doc = serde_json::to_value(exp)?;
+            doc["schema_version"] = serde_json::Value::from(EXPERIMENT_SCHEMA_VERSION);
+            write_new_file(
+                &out_experiments.join(format!("{}.json", exp.eid)),
+                &serde_json::to_string_pretty(&doc)?,
+            )?;
+        }
+
+        report.experiments_written += experiments.len();
+        report.migrated.push(LayerMigration {
+            layer_id: legacy.layer_id.clone(),
+            source: path,
+            groups,
+        });
+    }
+
+    write_new_file(
+        &options.out_dir.join("migration_report.json"),
+        &serde_json::to_string_pretty(&report)?,
+    )?;
+
+    Ok(report)
+}
+
+fn read_legacy_layer(path: &Path) -> Result<LegacyLayerFile> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_value(parse_document(&content)?)?)
+}
+
+pub(crate) fn write_new_file(path: &Path, content: &str) -> Result<()> {
+    if path.exists() {
+        return Err(ExperimentError::InvalidParameter(format!(
+            "Refusing to overwrite existing file {:?}",
+            path
+        )));
+    }
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// Legacy (start, end, target) where target is a group name or an explicit vid
+enum RangeTarget {
+    Group(String),
+    Vid(i64),
+}
+
+fn legacy_ranges(legacy: &LegacyLayerFile) -> Vec<(u32, u32, RangeTarget)> {
+    if !legacy.ranges.is_empty() {
+        return legacy
+            .ranges
+            .iter()
+            .map(|r| match r {
+                LegacyRange::Vid { start, end, vid } => (*start, *end, RangeTarget::Vid(*vid)),
+                LegacyRange::Group { start, end, group } => {
+                    (*start, *end, RangeTarget::Group(group.clone()))
+                }
+            })
+            .collect();
+    }
+
+    let bucket_size = legacy.bucket_size.unwrap_or(BUCKET_SIZE);
+    let mut boundaries: Vec<(u32, &String)> = legacy.buckets.iter().map(|(k, v)| (*k, v)).collect();
+    boundaries.sort_by_key(|(k, _)| *k);
+
+    boundaries
+        .iter()
+        .enumerate()
+        .map(|(i, (start, group))| {
+            let end = boundaries.get(i + 1).map_or(bucket_size, |(next, _)| *next);
+            (*start, end, RangeTarget::Group((*group).clone()))
+        })
+        .collect()
+}
+
+fn migrate_layer(
+    legacy: &LegacyLayerFile,
+    alloc: &mut IdAllocator,
+) -> Result<(MigratedLayer, Vec<ExperimentDef>, Vec<GroupMapping>)> {
+    let ranges = legacy_ranges(legacy);
+
+    // Only groups actually referenced by a range are migrated
+    let mut referenced: Vec<&String> = ranges
+        .iter()
+        .filter_map(|(_, _, t)| match t {
+            RangeTarget::Group(g) if g.parse::<i64>().is_err() => Some(g),
+            _ => None,
+        })
+        .collect();
+    referenced.sort();
+    referenced.dedup();
+
+    // (service, rule json) → group names, ordered for deterministic id allocation
+    let mut groups_by_experiment: BTreeMap<(String, String), Vec<&String>> = BTreeMap::new();
+    for name in &referenced {
+        let group = legacy
+            .groups
+            .get(*name)
+            .ok_or_else(|| ExperimentError::GroupNotFound((*name).clone()))?;
+        let service = match (&group.service, legacy.services.as_slice()) {
+            (Some(s), _) => s.clone(),
+            (None, [only]) => only.clone(),
+            _ => {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "Group '{}' has no service and layer does not declare exactly one",
+                    name
+                )))
+            }
+        };
+        let rule_key = serde_json::to_string(&group.rule)?;
+        groups_by_experiment
+            .entry((service, rule_key))
+            .or_default()
+            .push(name);
+    }
+
+    let mut experiments = Vec::new();
+    let mut mappings = Vec::new();
+    let mut group_vids: HashMap<&String, i64> = HashMap::new();
+
+    for ((service, _), names) in groups_by_experiment {
+        let eid = alloc.next_experiment(names.len())?;
+        let mut variants = Vec::with_capacity(names.len());
+
+        for (i, name) in names.iter().enumerate() {
+            let group = &legacy.groups[*name];
+            let vid = match group.vid {
+                Some(vid) if alloc.used_vids.insert(vid) => vid,
+                Some(vid) => {
+                    return Err(ExperimentError::InvalidParameter(format!(
+                        "Group '{}' declares vid {} which is already in use",
+                        name, vid
+                    )))
+                }
+                None => {
+                    let vid = eid + i as i64 + 1;
+                    alloc.used_vids.insert(vid);
+                    vid
+                }
+            };
+
+            variants.push(VariantDef {
+                vid,
+                params: group.params.clone(),
+                dark_params: None,
+            });
+            group_vids.insert(*name, vid);
+            mappings.push(GroupMapping {
+                group: (*name).clone(),
+                service: service.clone(),
+                eid,
+                vid,
+            });
+        }
+
+        experiments.push(ExperimentDef {
+            eid,
+            service,
+            expires_at: None,
+            state: Default::default(),
+            caps: None,
+            exposure_sampling: None,
+            rule: legacy.groups[names[0]].rule.clone(),
+            variants,
+        });
+    }
+
+    let mut new_ranges: Vec<BucketRange> = ranges
+        .iter()
+        .map(|(start, end, target)| {
+            let vid = match target {
+                RangeTarget::Vid(vid) => *vid,
+                RangeTarget::Group(g) => match g.parse::<i64>() {
+                    Ok(vid) => vid,
+                    Err(_) => group_vids[g],
+                },
+            };
+            BucketRange {
+                start: *start,
+                end: *end,
+                vid,
+                label: None,
+            }
+        })
+        .collect();
+    // Don't write a layer file the loader would reject
+    validate_and_sort_ranges(&mut new_ranges, legacy.bucket_size.unwrap_or(BUCKET_SIZE))?;
+
+    let layer = MigratedLayer {
+        schema_version: LAYER_SCHEMA_VERSION,
+        layer_id: legacy.layer_id.clone(),
+        version: legacy.version.clone(),
+        priority: legacy.priority,
+        hash_key: legacy.hash_key.clone(),
+        salt: legacy.salt.clone(),
+        bucket_size: legacy.bucket_size,
+        enabled: legacy.enabled,
+        ranges: new_ranges,
+    };
+
+    Ok((layer, experiments, mappings))
+}
+
+/// Entry point for `experiment-data-plane migrate-config ...`
+pub fn run_cli(args: &[String]) -> anyhow::Result<()> {
+    let mut layers_dir = None;
+    let mut out_dir = None;
+    let mut eid_start = DEFAULT_EID_START;
+    let mut existing_experiments_dir = None;
+
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("Missing value for {}", flag))
+        };
+        match flag.as_str() {
+            "--layers-dir" => layers_dir = Some(PathBuf::from(value()?)),
+            "--out-dir" => out_dir = Some(PathBuf::from(value()?)),
+            "--eid-start" => eid_start = value()?.parse()?,
+            "--experiments-dir" => existing_experiments_dir = Some(PathBuf::from(value()?)),
+            other => anyhow::bail!(
+                "Unknown flag {}\nUsage: migrate-config --layers-dir <dir> --out-dir <dir> \
+                 [--eid-start <n>] [--experiments-dir <existing catalog>]",
+                other
+            ),
+        }
+    }
+
+    let options = MigrateOptions {
+        layers_dir: layers_dir.ok_or_else(|| anyhow::anyhow!("--layers-dir is required"))?,
+        out_dir: out_dir.ok_or_else(|| anyhow::anyhow!("--out-dir is required"))?,
+        eid_start,
+        existing_experiments_dir,
+    };
+
+    let report = migrate(&options)?;
+    tracing::info!(
+        "Migrated {} layer(s) into {} experiment(s), skipped {} already-migrated file(s); report: {:?}",
+        report.migrated.len(),
+        report.experiments_written,
+        report.skipped.len(),
+        options.out_dir.join("migration_report.json")
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::hash_to_bucket;
+    use crate::layer::{Layer, LayerManager};
+    use serde_json::json;
+    use tempfile::TempDir;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_migrate_legacy_buckets_preserves_assignment() {
+        let temp_dir = TempDir::new().unwrap();
+        let legacy_dir = temp_dir.path().join("legacy");
+        let out_dir = temp_dir.path().join("out");
+        std::fs::create_dir_all(&legacy_dir).unwrap();
+
+        let legacy = json!({
+            "layer_id": "legacy_layer",
+            "version": "v1",
+            "priority": 100,
+            "hash_key": "user_id",
+            "salt": "legacy_salt",
+            "enabled": true,
+            "buckets": {"0": "control", "5000": "treatment", "8000": "eu"},
+            "groups": {
+                "control": {"service": "svc", "params": {"arm": "control"}},
+                "treatment": {"service": "svc", "params": {"arm": "treatment"}},
+                "eu": {
+                    "service": "svc",
+                    "params": {"arm": "eu"},
+                    "rule": {"type": "field", "field": "country", "op": "eq", "values": ["DE"]}
+                }
+            }
+        });
+        std::fs::write(legacy_dir.join("legacy_layer.json"), legacy.to_string()).unwrap();
+
+        let report = migrate(&MigrateOptions {
+            layers_dir: legacy_dir,
+            out_dir: out_dir.clone(),
+            eid_start: 5000,
+            existing_experiments_dir: None,
+        })
+        .unwrap();
+
+        // control + treatment share (service, rule); eu has its own rule
+        assert_eq!(report.experiments_written, 2);
+        let groups = &report.migrated[0].groups;
+        let control = groups.iter().find(|g| g.group == "control").unwrap();
+        let treatment = groups.iter().find(|g| g.group == "treatment").unwrap();
+        let eu = groups.iter().find(|g| g.group == "eu").unwrap();
+        assert_eq!(control.eid, treatment.eid);
+        assert_ne!(control.eid, eu.eid);
+
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(out_dir.join("experiments")).unwrap());
+        let manager = LayerManager::new(out_dir.join("layers"));
+        manager.load_all_layers(&catalog).await.unwrap();
+        let layer = manager.get_layer("legacy_layer").unwrap();
+
+        for i in 0..200 {
+            let bucket = hash_to_bucket(&format!("user_{}", i), "legacy_salt");
+            let expected = match bucket {
+                b if b < 5000 => control.vid,
+                b if b < 8000 => treatment.vid,
+                _ => eu.vid,
+            };
+            assert_eq!(layer.get_vid(bucket), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_upgrade_layer_v1_to_v2() {
+        let v1 = json!({
+            "schema_version": 1,
+            "layer_id": "l",
+            "version": "v1",
+            "priority": 1,
+            "hash_key": "user_id",
+            "services": ["svc"],
+            "buckets": {"0": "a", "6000": "b"},
+            "groups": {"a": {"vid": 11, "params": {}}, "b": {"vid": 12, "params": {}}}
+        });
+
+        // Strict mode judges the file as written, not the converted document
+        let err = Layer::from_value(v1.clone(), true).unwrap_err().to_string();
+        assert!(err.contains("buckets, groups, services"), "{}", err);
+        assert!(Layer::from_value(v1.clone(), false).is_ok());
+
+        let v2 = upgrade_layer(v1).unwrap();
+        assert_eq!(v2["schema_version"], json!(2));
+        assert!(v2.get("buckets").is_none() && v2.get("groups").is_none());
+        assert_eq!(
+            v2["ranges"],
+            json!([
+                {"start": 0, "end": 6000, "vid": 11},
+                {"start": 6000, "end": 10000, "vid": 12}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_migrate_respects_layer_bucket_size() {
+        let legacy: LegacyLayerFile = serde_json::from_value(json!({
+            "layer_id": "small",
+            "version": "v1",
+            "priority": 1,
+            "hash_key": "user_id",
+            "bucket_size": 1000,
+            "buckets": {"0": "a", "400": "b"},
+            "groups": {"a": {"service": "svc", "params": {}}, "b": {"service": "svc", "params": {}}}
+        }))
+        .unwrap();
+        let mut alloc = IdAllocator {
+            next_eid: DEFAULT_EID_START,
+            used_eids: HashSet::new(),
+            used_vids: HashSet::new(),
+        };
+
+        let (layer, _, _) = migrate_layer(&legacy, &mut alloc).unwrap();
+        assert_eq!(layer.ranges[1].end, 1000);
+
+        // The written file loads back with the same size and ranges
+        let written = Layer::from_value(serde_json::to_value(&layer).unwrap(), true).unwrap();
+        assert_eq!(written.bucket_size(), 1000);
+        assert_eq!(written.ranges, layer.ranges);
+
+        // A boundary past the layer's size is rejected instead of written
+        let mut too_big = legacy.clone();
+        too_big.buckets.insert(1000, "b".to_string());
+        assert!(migrate_layer(&too_big, &mut alloc).is_err());
+    }
+
+    #[test]
+    fn test_upgrade_rejects_unknown_and_deprecated() {
+        let future = json!({"schema_version": 99, "layer_id": "l"});
+        assert!(upgrade_layer(future).unwrap_err().to_string().contains("newer than supported"));
+
+        let v2_with_buckets = json!({"schema_version": 2, "buckets": {"0": "a"}});
+        assert!(upgrade_layer(v2_with_buckets)
+            .unwrap_err()
+            .to_string()
+            .contains("buckets"));
+
+        let unversioned = json!({"buckets": {"0": "a"}});
+        assert_eq!(upgrade_layer(unversioned.clone()).unwrap(), unversioned);
+
+        let bad_experiment = json!({"schema_version": 2, "eid": 1});
+        assert!(upgrade_experiment(bad_experiment).is_err());
+    }
+
+    #[test]
+    fn test_migrate_skips_new_format_and_refuses_overwrite() {
+        let temp_dir = TempDir::new().unwrap();
+        let layers_dir = temp_dir.path().join("layers");
+        let out_dir = temp_dir.path().join("out");
+        std::fs::create_dir_all(&layers_dir).unwrap();
+
+        let new_format = json!({
+            "layer_id": "new_layer",
+            "version": "v1",
+            "priority": 1,
+            "hash_key": "user_id",
+            "ranges": [{"start": 0, "end": 10000, "vid": 1}]
+        });
+        std::fs::write(layers_dir.join("new_layer.json"), new_format.to_string()).unwrap();
+
+        let options = MigrateOptions {
+            layers_dir,
+            out_dir,
+            eid_start: DEFAULT_EID_START,
+            existing_experiments_dir: None,
+        };
+        let report = migrate(&options).unwrap();
+        assert!(report.migrated.is_empty());
+        assert_eq!(report.skipped.len(), 1);
+
+        // Second run would overwrite the report
+        assert!(migrate(&options).is_err());
+    }
+}
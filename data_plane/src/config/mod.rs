@@ -0,0 +1,427 @@
+pub mod migrate;
+pub mod template;
+
+use anyhow::Result;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub layers_dir: PathBuf,
+    pub experiments_dir: PathBuf,
+    pub server_host: String,
+    pub server_port: u16,
+    #[allow(dead_code)]
+    pub metrics_port: u16,
+    /// Pushgateway URL metrics are pushed to (see [`crate::metrics_push`]); unset = no push
+    pub metrics_push_url: Option<String>,
+    pub metrics_push_interval_secs: u64,
+    /// Enable gzip/br response compression
+    pub compression_enabled: bool,
+    /// Responses smaller than this (bytes) are sent uncompressed
+    pub compression_min_size: u16,
+
+    // Connection tuning
+    /// Disable Nagle's algorithm on accepted sockets
+    pub tcp_nodelay: bool,
+    /// TCP keep-alive probe interval in seconds (0 = OS default / disabled)
+    pub tcp_keepalive_secs: u64,
+    /// Listen backlog passed to listen(2)
+    pub listen_backlog: i32,
+    /// Bind with SO_REUSEPORT so a new binary can listen alongside this one
+    pub listen_reuse_port: bool,
+    /// Serve on this inherited listening socket instead of binding (unix)
+    pub listen_fd: Option<i32>,
+    /// How long open connections may finish in-flight requests on shutdown
+    pub shutdown_drain_secs: u64,
+    /// Keep HTTP/1.1 connections open between requests
+    pub http1_keep_alive: bool,
+    /// HTTP/2 PING interval in seconds (0 = disabled)
+    pub http2_keep_alive_interval_secs: u64,
+    /// Max concurrent HTTP/2 streams per connection
+    pub http2_max_concurrent_streams: u32,
+    /// PEM certificate chain; with `tls_key_file`, the listener serves TLS and
+    /// negotiates HTTP/2 via ALPN (feature `tls`)
+    pub tls_cert_file: Option<PathBuf>,
+    /// PEM private key for `tls_cert_file`
+    pub tls_key_file: Option<PathBuf>,
+
+    /// KV store backend: memory | redis | rocksdb
+    pub kv_backend: String,
+    pub redis_url: String,
+    pub rocksdb_path: PathBuf,
+
+    /// Reject deprecated config fields (`buckets`, `groups`, layer `services`)
+    pub strict_config: bool,
+
+    /// Pending distinct file changes before the watcher forces a full resync
+    pub watch_queue_capacity: usize,
+    /// Quiet period (ms) without new file changes before applying a burst
+    pub watch_debounce_ms: u64,
+    /// How often config directories are polled in builds without the `watcher` feature
+    pub watch_poll_secs: u64,
+    /// Control plane endpoints, in priority order, to subscribe to config from
+    /// over xDS (feature `grpc`, see [`crate::xds`]); empty disables it
+    pub xds_servers: Vec<String>,
+    /// Node id this data plane announces to the control plane
+    pub xds_node_id: String,
+    /// Upper bound (seconds) of the reconnect backoff after the config stream breaks
+    pub xds_reconnect_max_secs: u64,
+    /// Periodic full resync from disk in seconds (0 = disabled)
+    pub resync_interval_secs: u64,
+    /// Worker threads of the dedicated config runtime (0 = share the serving runtime)
+    pub config_worker_threads: usize,
+    /// Nice value added to config worker threads (Linux only)
+    pub config_worker_nice: i32,
+    /// Source description read on SIGUSR2 to switch config source at runtime
+    pub config_source_file: Option<PathBuf>,
+    /// Prioritized sources to fail over between (see [`crate::failover`])
+    pub config_sources_file: Option<PathBuf>,
+    /// Seconds the active source must keep failing before failing over
+    pub config_failover_after_secs: u64,
+    /// Seconds a higher-priority source must stay valid before failing back
+    pub config_failback_after_secs: u64,
+    /// How often listed sources are checked (seconds)
+    pub config_failover_check_secs: u64,
+    /// Overlay roots (each with `layers/` and `experiments/`) taking precedence
+    /// over the primary source, highest first
+    pub config_overlay_dirs: Vec<PathBuf>,
+    /// Values for `${var}` placeholders in params (see [`template`])
+    pub config_variables_file: Option<PathBuf>,
+    /// Expected config files + hashes; mismatches fail readiness (see [`crate::manifest`])
+    pub config_manifest_file: Option<PathBuf>,
+    /// Keep variant params compressed and materialize them on access
+    pub catalog_lazy_params: bool,
+    /// Materialized variants kept in the lazy params LRU
+    pub catalog_param_cache_entries: usize,
+    /// Per-subject result cache TTL (0 = disabled)
+    pub result_cache_ttl_ms: u64,
+    pub result_cache_max_entries: usize,
+    /// Most core segments a semver rule value may have (`1.2.3.4` needs 4)
+    pub semver_max_segments: usize,
+    /// Coalesce identical concurrent evaluations into one
+    pub evaluation_coalescing: bool,
+    /// Fraction of evaluations timed per stage (0 = disabled)
+    pub stage_timing_sample_rate: f64,
+    /// Break-glass file force-disabling layers/experiments above all sources
+    pub emergency_overrides_file: PathBuf,
+    /// How often the emergency override file is re-checked (seconds)
+    pub emergency_overrides_check_secs: u64,
+    /// Longest `GET /config/subscribe` holds a request open (seconds)
+    pub config_subscribe_max_wait_secs: u64,
+    /// Subjects never experimented on (see [`crate::exclusion`])
+    pub exclusions_file: PathBuf,
+    /// How often the exclusion file is re-checked (seconds)
+    pub exclusions_check_secs: u64,
+    /// Freeze windows refusing non-emergency config changes (see [`crate::freeze`])
+    pub freeze_windows_file: Option<PathBuf>,
+    /// Start with the freeze lifted (also toggled via `POST /admin/freeze`)
+    pub freeze_override: bool,
+    /// How often a queued freeze checks whether its window closed (seconds)
+    pub freeze_check_secs: u64,
+    /// How often layer / experiment `expires_at` is re-checked (seconds)
+    pub expiry_check_secs: u64,
+    /// Directory of the applied config change log (see [`crate::applied`])
+    pub applied_log_dir: Option<PathBuf>,
+    /// Rotate the applied log once it reaches this size (0 = never)
+    pub applied_log_max_bytes: u64,
+    /// Rotated applied log files kept
+    pub applied_log_max_files: usize,
+    /// How often the watchdog checks config task heartbeats (seconds, 0 = disabled)
+    pub watchdog_interval_secs: u64,
+    /// Grace past a task's heartbeat interval before it counts as stalled (seconds)
+    pub watchdog_stall_secs: u64,
+    /// Consecutive watcher restarts before the watchdog fails readiness instead
+    pub watchdog_max_restarts: u32,
+
+    /// Layers evaluated per service per request, highest priority first (0 = unlimited)
+    pub max_evaluated_layers_per_service: usize,
+    /// Max enabled layers per service (0 = unlimited)
+    pub max_layers_per_service: usize,
+    /// Max active experiments per service (0 = unlimited)
+    pub max_experiments_per_service: usize,
+
+    /// Enable support overrides (adds one KV lookup per /experiment request)
+    pub support_overrides_enabled: bool,
+    /// Upper bound on a support override's lifetime
+    pub support_override_max_ttl_secs: u64,
+
+    /// SDK keys file; when set, /experiment requires a valid `X-SDK-Key`
+    pub sdk_keys_file: Option<PathBuf>,
+    /// How often the SDK keys file is re-read (picks up revocations)
+    pub sdk_keys_reload_secs: u64,
+
+    /// File holding the shared secret `/experiment` responses are signed with
+    /// (see [`crate::signing`]); unset = unsigned
+    pub response_signing_secret_file: Option<PathBuf>,
+
+    /// Identity aliases for bucketing (see [`crate::aliases`]); API aliases work without it
+    pub identity_aliases_file: Option<PathBuf>,
+    pub identity_aliases_reload_secs: u64,
+    /// Field layers hash on once a subject is identified (see [`crate::aliases::IdentityPolicy`])
+    pub identity_stable_field: String,
+    /// Field the stable id falls back to before identification; empty = no fallback
+    pub identity_anonymous_field: Option<String>,
+
+    /// Per-service context field allow-lists; unset = no filtering
+    pub context_allowlist_file: Option<PathBuf>,
+    /// Reject requests with undeclared fields instead of dropping them
+    pub context_allowlist_strict: bool,
+    pub context_allowlist_reload_secs: u64,
+
+    /// Federation routing table (see [`crate::federation`]); unset = every service is local
+    pub federation_routes_file: Option<PathBuf>,
+    pub federation_reload_secs: u64,
+    /// Deadline for a remote plane's answer, after which its services come back unavailable
+    pub federation_timeout_ms: u64,
+
+    /// GeoIP / device databases for context providers, by name (see [`crate::context_db`])
+    pub context_databases: Vec<(String, PathBuf)>,
+    pub context_database_reload_secs: u64,
+
+    /// Record exposure events for matched layers
+    pub exposure_enabled: bool,
+    /// Local write-ahead spool directory
+    pub exposure_spool_dir: PathBuf,
+    /// Spool segment size before rotation
+    pub exposure_segment_bytes: u64,
+    /// Sealed segments kept while the sink is down (oldest dropped beyond this)
+    pub exposure_max_segments: usize,
+    /// Events buffered in memory before they reach the spool
+    pub exposure_buffer: usize,
+    /// Seal-and-ship interval in milliseconds
+    pub exposure_flush_ms: u64,
+    /// Suppress repeated (subject, eid, vid) exposures within this window (0 = disabled)
+    pub exposure_dedup_window_secs: u64,
+    /// Bound on remembered exposures for dedup
+    pub exposure_dedup_max_entries: usize,
+    /// Exposure sink: log | file
+    pub exposure_sink: String,
+    /// Output file for the `file` sink
+    pub exposure_sink_path: PathBuf,
+    /// Fraction of requests whose exposures carry an evaluation trace (0 = disabled)
+    pub exposure_trace_sample_rate: f64,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self> {
+        // Backward compat: support GROUPS_DIR as fallback
+        let experiments_dir = std::env::var("EXPERIMENTS_DIR")
+            .or_else(|_| std::env::var("GROUPS_DIR"))
+            .unwrap_or_else(|_| "../configs/experiments".to_string())
+            .into();
+
+        let config = Self {
+            layers_dir: std::env::var("LAYERS_DIR")
+                .unwrap_or_else(|_| "../configs/layers".to_string())
+                .into(),
+            experiments_dir,
+            server_host: std::env::var("SERVER_HOST").unwrap_or_else(|_| "0.0.0.0".to_string()),
+            server_port: env_or("SERVER_PORT", "8080")?,
+            metrics_port: env_or("METRICS_PORT", "9090")?,
+            metrics_push_url: std::env::var("METRICS_PUSH_URL")
+                .ok()
+                .filter(|s| !s.is_empty()),
+            metrics_push_interval_secs: env_or("METRICS_PUSH_INTERVAL_SECS", "15")?,
+            compression_enabled: env_or("COMPRESSION_ENABLED", "true")?,
+            compression_min_size: env_or("COMPRESSION_MIN_SIZE", "1024")?,
+            tcp_nodelay: env_or("TCP_NODELAY", "true")?,
+            tcp_keepalive_secs: env_or("TCP_KEEPALIVE_SECS", "60")?,
+            listen_backlog: env_or("LISTEN_BACKLOG", "1024")?,
+            listen_reuse_port: env_or("LISTEN_REUSE_PORT", "false")?,
+            listen_fd: std::env::var("LISTEN_FD")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(|s| {
+                    s.parse()
+                        .map_err(|e| anyhow::anyhow!("Invalid value for LISTEN_FD: {:?} ({})", s, e))
+                })
+                .transpose()?,
+            shutdown_drain_secs: env_or("SHUTDOWN_DRAIN_SECS", "30")?,
+            http1_keep_alive: env_or("HTTP1_KEEP_ALIVE", "true")?,
+            http2_keep_alive_interval_secs: env_or("HTTP2_KEEP_ALIVE_INTERVAL_SECS", "30")?,
+            http2_max_concurrent_streams: env_or("HTTP2_MAX_CONCURRENT_STREAMS", "256")?,
+            tls_cert_file: std::env::var("TLS_CERT_FILE")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from),
+            tls_key_file: std::env::var("TLS_KEY_FILE")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from),
+            kv_backend: std::env::var("KV_BACKEND").unwrap_or_else(|_| "memory".to_string()),
+            redis_url: std::env::var("REDIS_URL")
+                .unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string()),
+            rocksdb_path: std::env::var("ROCKSDB_PATH")
+                .unwrap_or_else(|_| "./data/kv".to_string())
+                .into(),
+            strict_config: env_or("STRICT_CONFIG", "false")?,
+            watch_queue_capacity: env_or("WATCH_QUEUE_CAPACITY", "100")?,
+            watch_debounce_ms: env_or("WATCH_DEBOUNCE_MS", "100")?,
+            watch_poll_secs: env_or("WATCH_POLL_SECS", "5")?,
+            xds_servers: std::env::var("XDS_SERVER")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect(),
+            xds_node_id: std::env::var("XDS_NODE_ID").unwrap_or_else(|_| "experiment-data-plane".to_string()),
+            xds_reconnect_max_secs: env_or("XDS_RECONNECT_MAX_SECS", "30")?,
+            resync_interval_secs: env_or("RESYNC_INTERVAL_SECS", "600")?,
+            config_worker_threads: env_or("CONFIG_WORKER_THREADS", "2")?,
+            config_worker_nice: env_or("CONFIG_WORKER_NICE", "10")?,
+            config_source_file: std::env::var("CONFIG_SOURCE_FILE")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from),
+            config_sources_file: std::env::var("CONFIG_SOURCES_FILE")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from),
+            config_failover_after_secs: env_or("CONFIG_FAILOVER_AFTER_SECS", "60")?,
+            config_failback_after_secs: env_or("CONFIG_FAILBACK_AFTER_SECS", "300")?,
+            config_failover_check_secs: env_or("CONFIG_FAILOVER_CHECK_SECS", "10")?,
+            config_overlay_dirs: std::env::var("CONFIG_OVERLAY_DIRS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from)
+                .collect(),
+            config_variables_file: std::env::var("CONFIG_VARIABLES_FILE")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from),
+            config_manifest_file: std::env::var("CONFIG_MANIFEST_FILE")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from),
+            catalog_lazy_params: env_or("CATALOG_LAZY_PARAMS", "false")?,
+            catalog_param_cache_entries: env_or("CATALOG_PARAM_CACHE_ENTRIES", "10000")?,
+            result_cache_ttl_ms: env_or("RESULT_CACHE_TTL_MS", "0")?,
+            result_cache_max_entries: env_or("RESULT_CACHE_MAX_ENTRIES", "100000")?,
+            evaluation_coalescing: env_or("EVALUATION_COALESCING", "false")?,
+            semver_max_segments: env_or("SEMVER_MAX_SEGMENTS", "3")?,
+            stage_timing_sample_rate: env_or("STAGE_TIMING_SAMPLE_RATE", "0.01")?,
+            emergency_overrides_file: std::env::var("EMERGENCY_OVERRIDES_FILE")
+                .unwrap_or_else(|_| "./emergency_overrides.json".to_string())
+                .into(),
+            emergency_overrides_check_secs: env_or("EMERGENCY_OVERRIDES_CHECK_SECS", "5")?,
+            config_subscribe_max_wait_secs: env_or("CONFIG_SUBSCRIBE_MAX_WAIT_SECS", "30")?,
+            exclusions_file: std::env::var("EXCLUSIONS_FILE")
+                .unwrap_or_else(|_| "./exclusions.yaml".to_string())
+                .into(),
+            exclusions_check_secs: env_or("EXCLUSIONS_CHECK_SECS", "5")?,
+            freeze_windows_file: std::env::var("FREEZE_WINDOWS_FILE")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from),
+            freeze_override: env_or("FREEZE_OVERRIDE", "false")?,
+            freeze_check_secs: env_or("FREEZE_CHECK_SECS", "30")?,
+            expiry_check_secs: env_or("EXPIRY_CHECK_SECS", "10")?,
+            applied_log_dir: std::env::var("APPLIED_LOG_DIR")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from),
+            applied_log_max_bytes: env_or("APPLIED_LOG_MAX_BYTES", "10485760")?,
+            applied_log_max_files: env_or("APPLIED_LOG_MAX_FILES", "5")?,
+            watchdog_interval_secs: env_or("WATCHDOG_INTERVAL_SECS", "10")?,
+            watchdog_stall_secs: env_or("WATCHDOG_STALL_SECS", "60")?,
+            watchdog_max_restarts: env_or("WATCHDOG_MAX_RESTARTS", "3")?,
+            max_evaluated_layers_per_service: env_or("MAX_EVALUATED_LAYERS_PER_SERVICE", "0")?,
+            max_layers_per_service: env_or("MAX_LAYERS_PER_SERVICE", "0")?,
+            max_experiments_per_service: env_or("MAX_EXPERIMENTS_PER_SERVICE", "0")?,
+            support_overrides_enabled: env_or("SUPPORT_OVERRIDES_ENABLED", "false")?,
+            support_override_max_ttl_secs: env_or("SUPPORT_OVERRIDE_MAX_TTL_SECS", "86400")?,
+            sdk_keys_file: std::env::var("SDK_KEYS_FILE")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from),
+            sdk_keys_reload_secs: env_or("SDK_KEYS_RELOAD_SECS", "30")?,
+            response_signing_secret_file: std::env::var("RESPONSE_SIGNING_SECRET_FILE")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from),
+            identity_aliases_file: std::env::var("IDENTITY_ALIASES_FILE")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from),
+            identity_aliases_reload_secs: env_or("IDENTITY_ALIASES_RELOAD_SECS", "30")?,
+            identity_stable_field: env_or("IDENTITY_STABLE_FIELD", "user_id")?,
+            identity_anonymous_field: std::env::var("IDENTITY_ANONYMOUS_FIELD")
+                .ok()
+                .filter(|s| !s.is_empty()),
+            context_allowlist_file: std::env::var("CONTEXT_ALLOWLIST_FILE")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from),
+            context_allowlist_strict: env_or("CONTEXT_ALLOWLIST_STRICT", "false")?,
+            context_allowlist_reload_secs: env_or("CONTEXT_ALLOWLIST_RELOAD_SECS", "30")?,
+            federation_routes_file: std::env::var("FEDERATION_ROUTES_FILE")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from),
+            federation_reload_secs: env_or("FEDERATION_RELOAD_SECS", "30")?,
+            federation_timeout_ms: env_or("FEDERATION_TIMEOUT_MS", "200")?,
+            context_databases: named_paths("CONTEXT_DATABASES")?,
+            context_database_reload_secs: env_or("CONTEXT_DATABASE_RELOAD_SECS", "60")?,
+            exposure_enabled: env_or("EXPOSURE_ENABLED", "false")?,
+            exposure_spool_dir: std::env::var("EXPOSURE_SPOOL_DIR")
+                .unwrap_or_else(|_| "./data/exposure_spool".to_string())
+                .into(),
+            exposure_segment_bytes: env_or("EXPOSURE_SEGMENT_BYTES", "8388608")?,
+            exposure_max_segments: env_or("EXPOSURE_MAX_SEGMENTS", "256")?,
+            exposure_buffer: env_or("EXPOSURE_BUFFER", "10000")?,
+            exposure_flush_ms: env_or("EXPOSURE_FLUSH_MS", "1000")?,
+            exposure_dedup_window_secs: env_or("EXPOSURE_DEDUP_WINDOW_SECS", "0")?,
+            exposure_dedup_max_entries: env_or("EXPOSURE_DEDUP_MAX_ENTRIES", "1000000")?,
+            exposure_sink: std::env::var("EXPOSURE_SINK").unwrap_or_else(|_| "log".to_string()),
+            exposure_sink_path: std::env::var("EXPOSURE_SINK_PATH")
+                .unwrap_or_else(|_| "./data/exposures.jsonl".to_string())
+                .into(),
+            exposure_trace_sample_rate: env_or("EXPOSURE_TRACE_SAMPLE_RATE", "0")?,
+        };
+
+        if config.tls_cert_file.is_some() != config.tls_key_file.is_some() {
+            anyhow::bail!("TLS_CERT_FILE and TLS_KEY_FILE must be set together");
+        }
+        #[cfg(not(feature = "tls"))]
+        if config.tls_cert_file.is_some() {
+            anyhow::bail!("TLS_CERT_FILE requires the `tls` cargo feature");
+        }
+
+        // Support routes authenticate callers by SDK key
+        if config.support_overrides_enabled && config.sdk_keys_file.is_none() {
+            anyhow::bail!("SUPPORT_OVERRIDES_ENABLED requires SDK_KEYS_FILE with a `support: true` key");
+        }
+        Ok(config)
+    }
+}
+
+/// Comma-separated `name=path` pairs from an env var; empty when unset
+fn named_paths(key: &str) -> Result<Vec<(String, PathBuf)>> {
+    std::env::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, path)) if !name.trim().is_empty() && !path.trim().is_empty() => {
+                Ok((name.trim().to_string(), PathBuf::from(path.trim())))
+            }
+            _ => Err(anyhow::anyhow!("Invalid value for {}: {:?} (expected name=path)", key, pair)),
+        })
+        .collect()
+}
+
+/// Read and parse an env var, falling back to `default` when unset
+fn env_or<T>(key: &str, default: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let raw = std::env::var(key).unwrap_or_else(|_| default.to_string());
+    raw.parse::<T>()
+        .map_err(|e| anyhow::anyhow!("Invalid value for {}: {:?} ({})", key, raw, e))
+}
@@ -0,0 +1,116 @@
+//! `${var}` placeholders in experiment / group params, resolved at load time.
+//!
+//! Values come from the variables file (`CONFIG_VARIABLES_FILE`, a flat
+//! name -> string map) and can be overridden per instance with
+//! `CONFIG_VAR_<name>` environment variables. Only strings are substituted;
+//! `$${` escapes a literal `${`. An unknown variable fails the load.
+
+use crate::config::migrate;
+use crate::error::{ExperimentError, Result};
+use arc_swap::ArcSwap;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Environment variable prefix for per-instance overrides
+pub const ENV_PREFIX: &str = "CONFIG_VAR_";
+
+lazy_static! {
+    static ref VARIABLES: ArcSwap<HashMap<String, String>> = ArcSwap::from_pointee(HashMap::new());
+}
+
+/// Load the variables file used by every subsequent config load
+pub fn init(path: Option<&Path>) -> Result<()> {
+    let variables = match path {
+        Some(path) => {
+            let content = std::fs::read_to_string(path)?;
+            serde_json::from_value(migrate::parse_document(&content)?)?
+        }
+        None => HashMap::new(),
+    };
+    VARIABLES.store(std::sync::Arc::new(variables));
+    Ok(())
+}
+
+fn lookup(name: &str) -> Option<String> {
+    std::env::var(format!("{}{}", ENV_PREFIX, name))
+        .ok()
+        .or_else(|| VARIABLES.load().get(name).cloned())
+}
+
+/// Substitute placeholders in every string of `value`, in place
+pub fn resolve(value: &mut Value) -> Result<()> {
+    match value {
+        Value::String(s) if s.contains('$') => {
+            *s = substitute(s, &lookup)?;
+        }
+        Value::Array(items) => {
+            for item in items {
+                resolve(item)?;
+            }
+        }
+        Value::Object(map) => {
+            for item in map.values_mut() {
+                resolve(item)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn substitute(input: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        if let Some(after) = rest.strip_prefix("$${") {
+            out.push_str("${");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after.find('}').ok_or_else(|| {
+                ExperimentError::InvalidParameter(format!("Unterminated placeholder in '{}'", input))
+            })?;
+            let name = &after[..end];
+            let value = lookup(name).ok_or_else(|| {
+                ExperimentError::InvalidParameter(format!("Undefined config variable '{}'", name))
+            })?;
+            out.push_str(&value);
+            rest = &after[end + 1..];
+        } else {
+            out.push('$');
+            rest = &rest[1..];
+        }
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute() {
+        let vars = |name: &str| match name {
+            "region" => Some("eu".to_string()),
+            "host" => Some("ranker.eu.internal".to_string()),
+            _ => None,
+        };
+
+        assert_eq!(
+            substitute("https://${host}/v1?r=${region}", &vars).unwrap(),
+            "https://ranker.eu.internal/v1?r=eu"
+        );
+        assert_eq!(substitute("cost $5, $${region}", &vars).unwrap(), "cost $5, ${region}");
+
+        let err = substitute("${missing}", &vars).unwrap_err();
+        assert!(err.to_string().contains("'missing'"), "{}", err);
+        assert!(substitute("${region", &vars).is_err());
+    }
+}
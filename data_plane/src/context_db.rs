@@ -0,0 +1,383 @@
+//! Reference databases (GeoIP, device / user-agent) for context providers.
+//!
+//! Providers that derive context fields from a request (country from the
+//! client IP, device class from the user agent) read vendor databases that are
+//! tens to hundreds of MB and replaced by an updater every few days. Each
+//! database configured in `CONTEXT_DATABASES` is memory-mapped and re-checked
+//! every `CONTEXT_DATABASE_RELOAD_SECS`: when the file is replaced (size, mtime
+//! or inode changes) it is mapped again and swapped in.
+//! Evaluations holding the previous [`Database`] keep using it until they drop
+//! it, so a refresh never blocks them. A file that fails to load keeps the
+//! previous version in effect.
+//!
+//! Updaters must replace a database by renaming a new file over it (as
+//! `geoipupdate` does): a mapped file truncated in place faults its readers.
+//!
+//! The build date of a MaxMind DB (`.mmdb`) comes from its metadata
+//! (`build_epoch`); for other formats it is the file's mtime. It is exported as
+//! `experiment_context_database_build_timestamp_seconds{database}` for
+//! staleness alerts.
+
+use crate::error::{ExperimentError, Result};
+use crate::metrics;
+use arc_swap::ArcSwap;
+use memmap2::Mmap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Start of the metadata section at the end of a MaxMind DB
+const MMDB_METADATA_MARKER: &[u8] = b"\xAB\xCD\xEFMaxMind.com";
+
+/// The metadata section sits within this many bytes of the end of the file
+const MMDB_METADATA_MAX_BYTES: usize = 128 * 1024;
+
+/// One loaded version of a database file
+pub struct Database {
+    contents: Mmap,
+    /// Unix seconds: MMDB `build_epoch`, else the file's mtime
+    build_epoch: i64,
+    modified: SystemTime,
+    len: u64,
+    /// Inode: a replacement renamed in within the mtime granularity still differs
+    file_id: u64,
+}
+
+impl Database {
+    fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let stat = file.metadata()?;
+        if stat.len() == 0 {
+            return Err(ExperimentError::InvalidParameter(format!("Context database {:?} is empty", path)));
+        }
+        let modified = stat.modified()?;
+        // SAFETY: updaters replace databases by rename, never truncating a
+        // mapped file in place (see the module docs)
+        let contents = unsafe { Mmap::map(&file)? };
+        let build_epoch = mmdb_build_epoch(&contents).unwrap_or_else(|| unix_secs(modified));
+        Ok(Self {
+            contents,
+            build_epoch,
+            modified,
+            len: stat.len(),
+            file_id: file_id(&stat),
+        })
+    }
+
+    /// Raw database contents, for the provider's own reader
+    pub fn bytes(&self) -> &[u8] {
+        &self.contents
+    }
+
+    /// Unix seconds the database was built
+    pub fn build_epoch(&self) -> i64 {
+        self.build_epoch
+    }
+
+    fn is_current(&self, stat: &std::fs::Metadata) -> bool {
+        stat.len() == self.len && stat.modified().ok() == Some(self.modified) && file_id(stat) == self.file_id
+    }
+}
+
+#[cfg(unix)]
+fn file_id(stat: &std::fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(stat)
+}
+
+#[cfg(not(unix))]
+fn file_id(_stat: &std::fs::Metadata) -> u64 {
+    0
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+}
+
+struct Entry {
+    path: PathBuf,
+    current: ArcSwap<Database>,
+    loaded_at: ArcSwap<SystemTime>,
+}
+
+/// Databases from `CONTEXT_DATABASES`, each swapped atomically on refresh
+pub struct ContextDatabases {
+    entries: BTreeMap<String, Entry>,
+}
+
+/// A database as listed by `/admin/context_databases`
+#[derive(Debug, Serialize)]
+pub struct DatabaseStatus {
+    pub name: String,
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub build_epoch: i64,
+    /// Unix seconds the current version was loaded
+    pub loaded_at: i64,
+}
+
+impl ContextDatabases {
+    /// Load every `(name, path)`; any failure fails the whole load
+    pub fn load(paths: Vec<(String, PathBuf)>) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+        for (name, path) in paths {
+            if entries.contains_key(&name) {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "Context database '{}' configured twice",
+                    name
+                )));
+            }
+            let database = Database::open(&path).map_err(|e| {
+                ExperimentError::InvalidParameter(format!("Context database '{}' ({:?}): {}", name, path, e))
+            })?;
+            tracing::info!("Loaded context database '{}' from {:?} ({} bytes)", name, path, database.len);
+            metrics::CONTEXT_DATABASE_BUILD_TIMESTAMP
+                .with_label_values(&[&name])
+                .set(database.build_epoch);
+            entries.insert(
+                name,
+                Entry {
+                    path,
+                    current: ArcSwap::from_pointee(database),
+                    loaded_at: ArcSwap::from_pointee(SystemTime::now()),
+                },
+            );
+        }
+        Ok(Self { entries })
+    }
+
+    /// Current version of database `name`; keep it for as long as one lookup needs
+    pub fn get(&self, name: &str) -> Option<Arc<Database>> {
+        self.entries.get(name).map(|entry| entry.current.load_full())
+    }
+
+    /// Swap in databases whose file changed; a file that can't be loaded
+    /// keeps its previous version. Returns the names refreshed.
+    pub fn refresh(&self) -> Vec<String> {
+        let mut refreshed = Vec::new();
+        for (name, entry) in &self.entries {
+            let changed = std::fs::metadata(&entry.path).map(|stat| !entry.current.load().is_current(&stat));
+            let result = match changed {
+                Ok(false) => continue,
+                Ok(true) => Database::open(&entry.path),
+                Err(e) => Err(e.into()),
+            };
+            match result {
+                Ok(database) => {
+                    tracing::info!("Refreshed context database '{}' ({} bytes)", name, database.len);
+                    metrics::CONTEXT_DATABASE_BUILD_TIMESTAMP
+                        .with_label_values(&[name])
+                        .set(database.build_epoch);
+                    entry.current.store(Arc::new(database));
+                    entry.loaded_at.store(Arc::new(SystemTime::now()));
+                    refreshed.push(name.clone());
+                }
+                Err(e) => {
+                    metrics::CONTEXT_DATABASE_REFRESH_ERRORS.with_label_values(&[name]).inc();
+                    tracing::error!("Failed to refresh context database '{}', keeping previous version: {}", name, e);
+                }
+            }
+        }
+        refreshed
+    }
+
+    pub fn status(&self) -> Vec<DatabaseStatus> {
+        self.entries
+            .iter()
+            .map(|(name, entry)| {
+                let database = entry.current.load();
+                DatabaseStatus {
+                    name: name.clone(),
+                    path: entry.path.clone(),
+                    bytes: database.len,
+                    build_epoch: database.build_epoch,
+                    loaded_at: unix_secs(**entry.loaded_at.load()),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Pick up replaced database files every `interval`
+pub async fn refresh_periodically(databases: Arc<ContextDatabases>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        // Stats and maps files, and parses MMDB metadata: keep it off the runtime
+        let databases = databases.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || databases.refresh()).await {
+            tracing::error!("Context database refresh task failed: {}", e);
+        }
+    }
+}
+
+/// `build_epoch` from a MaxMind DB's metadata map, if `bytes` is one
+fn mmdb_build_epoch(bytes: &[u8]) -> Option<i64> {
+    let tail_start = bytes.len().saturating_sub(MMDB_METADATA_MAX_BYTES);
+    let marker = bytes[tail_start..]
+        .windows(MMDB_METADATA_MARKER.len())
+        .rposition(|window| window == MMDB_METADATA_MARKER)?;
+    let mut decoder = MmdbDecoder {
+        data: bytes,
+        pos: tail_start + marker + MMDB_METADATA_MARKER.len(),
+    };
+
+    let (kind, entries) = decoder.header()?;
+    if kind != MMDB_MAP {
+        return None;
+    }
+    for _ in 0..entries {
+        let key = decoder.string()?;
+        if key == "build_epoch" {
+            return i64::try_from(decoder.unsigned()?).ok();
+        }
+        decoder.skip()?;
+    }
+    None
+}
+
+const MMDB_POINTER: u8 = 1;
+const MMDB_STRING: u8 = 2;
+const MMDB_DOUBLE: u8 = 3;
+const MMDB_MAP: u8 = 7;
+const MMDB_ARRAY: u8 = 11;
+const MMDB_BOOL: u8 = 14;
+const MMDB_FLOAT: u8 = 15;
+
+/// Just enough of the MaxMind DB data format to walk the metadata map
+struct MmdbDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> MmdbDecoder<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let byte = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    /// Type and size of the next field
+    fn header(&mut self) -> Option<(u8, usize)> {
+        let control = self.byte()?;
+        let kind = match control >> 5 {
+            0 => self.byte()?.checked_add(7)?,
+            kind => kind,
+        };
+        if kind == MMDB_POINTER {
+            // Not used in metadata
+            return None;
+        }
+        let size = match (control & 0x1f) as usize {
+            size @ 0..=28 => size,
+            29 => 29 + self.byte()? as usize,
+            30 => 285 + self.take(2)?.iter().fold(0, |n, b| (n << 8) | *b as usize),
+            _ => 65_821 + self.take(3)?.iter().fold(0, |n, b| (n << 8) | *b as usize),
+        };
+        Some((kind, size))
+    }
+
+    fn string(&mut self) -> Option<&'a str> {
+        match self.header()? {
+            (MMDB_STRING, len) => std::str::from_utf8(self.take(len)?).ok(),
+            _ => None,
+        }
+    }
+
+    /// An unsigned (or non-negative int32) field
+    fn unsigned(&mut self) -> Option<u64> {
+        match self.header()? {
+            (5 | 6 | 8 | 9, len) if len <= 8 => Some(self.take(len)?.iter().fold(0, |n, b| (n << 8) | *b as u64)),
+            _ => None,
+        }
+    }
+
+    fn skip(&mut self) -> Option<()> {
+        match self.header()? {
+            (MMDB_MAP, entries) => (0..entries * 2).try_for_each(|_| self.skip()),
+            (MMDB_ARRAY, items) => (0..items).try_for_each(|_| self.skip()),
+            (MMDB_BOOL, _) => Some(()),
+            (MMDB_DOUBLE, _) => self.take(8).map(|_| ()),
+            (MMDB_FLOAT, _) => self.take(4).map(|_| ()),
+            (_, len) => self.take(len).map(|_| ()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// MMDB metadata: {"languages": ["en"], "build_epoch": <epoch as uint64>}
+    fn mmdb(epoch: u32) -> Vec<u8> {
+        let mut bytes = b"search tree and data section".to_vec();
+        bytes.extend_from_slice(MMDB_METADATA_MARKER);
+        bytes.push((MMDB_MAP << 5) | 2);
+        bytes.push((MMDB_STRING << 5) | 9);
+        bytes.extend_from_slice(b"languages");
+        bytes.extend_from_slice(&[1, MMDB_ARRAY - 7, (MMDB_STRING << 5) | 2]);
+        bytes.extend_from_slice(b"en");
+        bytes.push((MMDB_STRING << 5) | 11);
+        bytes.extend_from_slice(b"build_epoch");
+        bytes.extend_from_slice(&[4, 9 - 7]);
+        bytes.extend_from_slice(&epoch.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_malformed_metadata_is_ignored() {
+        let mut bytes = MMDB_METADATA_MARKER.to_vec();
+        // Extended type byte that would overflow past u8
+        bytes.extend_from_slice(&[0x00, 0xff]);
+        assert_eq!(mmdb_build_epoch(&bytes), None);
+    }
+
+    #[test]
+    fn test_databases_refresh_on_replace() {
+        let temp_dir = TempDir::new().unwrap();
+        let geoip = temp_dir.path().join("GeoLite2-City.mmdb");
+        let ua = temp_dir.path().join("regexes.yaml");
+        std::fs::write(&geoip, mmdb(1_700_000_000)).unwrap();
+        std::fs::write(&ua, "user_agent_parsers: []\n").unwrap();
+
+        let databases = ContextDatabases::load(vec![
+            ("geoip".to_string(), geoip.clone()),
+            ("ua".to_string(), ua.clone()),
+        ])
+        .unwrap();
+        let held = databases.get("geoip").unwrap();
+        assert_eq!(held.build_epoch(), 1_700_000_000);
+        assert_eq!(databases.get("ua").unwrap().bytes(), b"user_agent_parsers: []\n");
+        // Not an MMDB: built when last written
+        let ua_mtime = unix_secs(std::fs::metadata(&ua).unwrap().modified().unwrap());
+        assert_eq!(databases.get("ua").unwrap().build_epoch(), ua_mtime);
+        assert!(databases.refresh().is_empty());
+
+        // Replaced by rename, as updaters do
+        let staged = temp_dir.path().join("GeoLite2-City.mmdb.new");
+        std::fs::write(&staged, mmdb(1_700_600_000)).unwrap();
+        std::fs::rename(&staged, &geoip).unwrap();
+        assert_eq!(databases.refresh(), vec!["geoip"]);
+        assert_eq!(databases.get("geoip").unwrap().build_epoch(), 1_700_600_000);
+        // Lookups that started before the refresh still read the old version
+        assert_eq!(held.build_epoch(), 1_700_000_000);
+        assert_eq!(held.bytes(), mmdb(1_700_000_000).as_slice());
+
+        // A broken replacement keeps the current version
+        std::fs::write(&staged, b"").unwrap();
+        std::fs::rename(&staged, &geoip).unwrap();
+        assert!(databases.refresh().is_empty());
+        assert_eq!(databases.get("geoip").unwrap().build_epoch(), 1_700_600_000);
+    }
+}
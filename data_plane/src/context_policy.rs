@@ -0,0 +1,165 @@
+//! Per-service allow-list of context fields. Fields a service has not declared
+//! are dropped (or rejected in strict mode) before rule evaluation, so stray PII
+//! sent by a caller never reaches experiment rules.
+
+use crate::config::migrate;
+use crate::error::{ExperimentError, Result};
+use crate::layer::LayerManager;
+use crate::merge::ExperimentRequest;
+use crate::metrics;
+use arc_swap::ArcSwap;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Deserialize)]
+struct AllowListFile {
+    /// service -> permitted context fields
+    services: HashMap<String, HashSet<String>>,
+}
+
+/// Allow-lists loaded from `CONTEXT_ALLOWLIST_FILE`. Services without an entry
+/// are unrestricted; hash keys of a service's layers are always permitted.
+#[derive(Debug)]
+pub struct ContextPolicy {
+    path: PathBuf,
+    strict: bool,
+    services: ArcSwap<HashMap<String, HashSet<String>>>,
+}
+
+impl ContextPolicy {
+    pub fn load(path: PathBuf, strict: bool) -> Result<Self> {
+        let services = read_allow_lists(&path)?;
+        tracing::info!("Loaded context allow-lists for {} services from {:?}", services.len(), path);
+        Ok(Self {
+            path,
+            strict,
+            services: ArcSwap::from_pointee(services),
+        })
+    }
+
+    /// Re-read the allow-list file; on failure the current lists stay in effect
+    pub fn reload(&self) -> Result<()> {
+        self.services.store(Arc::new(read_allow_lists(&self.path)?));
+        Ok(())
+    }
+
+    /// Context restricted to what `service` may use
+    pub fn apply(
+        &self,
+        service: &str,
+        context: &HashMap<String, Value>,
+        layer_manager: &LayerManager,
+    ) -> Result<HashMap<String, Value>> {
+        let services = self.services.load();
+        let Some(allowed) = services.get(service) else {
+            return Ok(context.clone());
+        };
+
+        let hash_keys: HashSet<String> = layer_manager
+            .get_layers_for_service(service)
+            .iter()
+            .flat_map(|l| l.hash_key.fields().to_vec())
+            .collect();
+
+        let mut filtered = HashMap::with_capacity(context.len());
+        let mut rejected = BTreeSet::new();
+        for (field, value) in context {
+            if allowed.contains(field) || hash_keys.contains(field) {
+                filtered.insert(field.clone(), value.clone());
+            } else {
+                rejected.insert(field.as_str());
+            }
+        }
+
+        if rejected.is_empty() {
+            return Ok(filtered);
+        }
+
+        metrics::CONTEXT_FIELDS_DROPPED
+            .with_label_values(&[service])
+            .inc_by(rejected.len() as u64);
+
+        if self.strict {
+            return Err(ExperimentError::ContextNotAllowed(format!(
+                "service '{}' does not accept context fields: {}",
+                service,
+                rejected.into_iter().collect::<Vec<_>>().join(", ")
+            )));
+        }
+
+        tracing::debug!("Dropped context fields for service {}: {:?}", service, rejected);
+        Ok(filtered)
+    }
+
+    /// Split a batch request into one request per service with its own filtered context
+    pub fn scope(&self, request: &ExperimentRequest, layer_manager: &LayerManager) -> Result<Vec<ExperimentRequest>> {
+        request
+            .services
+            .iter()
+            .map(|service| {
+                Ok(ExperimentRequest {
+                    services: vec![service.clone()],
+                    context: self.apply(service, &request.context, layer_manager)?,
+                    layers: request.layers.clone(),
+                    diagnostics: request.diagnostics,
+                })
+            })
+            .collect()
+    }
+}
+
+fn read_allow_lists(path: &PathBuf) -> Result<HashMap<String, HashSet<String>>> {
+    let content = std::fs::read_to_string(path)?;
+    let file: AllowListFile = serde_json::from_value(migrate::parse_document(&content)?)?;
+    Ok(file.services)
+}
+
+/// Pick up allow-list edits every `interval`
+pub async fn reload_periodically(policy: Arc<ContextPolicy>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        if let Err(e) = policy.reload() {
+            tracing::error!("Failed to reload context allow-lists, keeping previous: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_allow_list_filters_and_strict_rejects() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("allow.yaml");
+        std::fs::write(&path, "services:\n  svc: [country, app_version]\n").unwrap();
+        let manager = LayerManager::new(temp_dir.path().join("layers"));
+
+        let context: HashMap<String, Value> = [
+            ("country".to_string(), json!("US")),
+            ("email".to_string(), json!("a@example.com")),
+        ]
+        .into_iter()
+        .collect();
+
+        let policy = ContextPolicy::load(path.clone(), false).unwrap();
+        let filtered = policy.apply("svc", &context, &manager).unwrap();
+        assert_eq!(filtered.len(), 1);
+        assert!(filtered.contains_key("country"));
+        // Undeclared services are unrestricted
+        assert_eq!(policy.apply("other", &context, &manager).unwrap().len(), 2);
+
+        let strict = ContextPolicy::load(path, true).unwrap();
+        let err = strict.apply("svc", &context, &manager).unwrap_err();
+        assert!(err.to_string().contains("email"), "{}", err);
+    }
+}
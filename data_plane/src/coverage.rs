@@ -0,0 +1,175 @@
+//! Layer coverage gaps.
+//!
+//! A bucket span no range covers serves no experiment at all. Gaps are easy to
+//! leave behind when ranges are shrunk or a variant is removed, and they only
+//! show up much later as missing traffic in analysis, so `/diagnostics/coverage_gaps`
+//! reports them for every enabled layer and for every service those layers feed.
+//! Rules are not applied: shares are of hashed traffic, not eligible traffic.
+
+use crate::layer::{Layer, Snapshot};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LayerGaps {
+    pub layer_id: String,
+    pub bucket_size: u32,
+    /// Buckets not covered by any range
+    pub uncovered_buckets: u32,
+    pub uncovered_ratio: f64,
+    /// Uncovered `[start, end)` spans, in order
+    pub holes: Vec<(u32, u32)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServiceGaps {
+    /// Share of the service's traffic landing in a hole of at least one of its layers
+    pub hole_ratio: f64,
+    /// Share of the service's traffic assigned no variant of the service in any layer
+    pub unassigned_ratio: f64,
+    /// Uncovered share of each of the service's layers (only layers with holes)
+    pub layers: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CoverageGaps {
+    pub epoch: u64,
+    /// Enabled layers with at least one hole, highest priority first
+    pub layers: Vec<LayerGaps>,
+    /// By service, for every service with an enabled layer
+    pub services: BTreeMap<String, ServiceGaps>,
+}
+
+/// Coverage gaps of the enabled layers in `snapshot`
+pub fn gaps(snapshot: &Snapshot) -> CoverageGaps {
+    let mut enabled: Vec<&Layer> = snapshot.layers().filter(|l| l.enabled).map(|l| l.as_ref()).collect();
+    enabled.sort_by(|a, b| b.priority.cmp(&a.priority).then_with(|| a.layer_id.cmp(&b.layer_id)));
+
+    let layers = enabled
+        .into_iter()
+        .filter_map(|layer| {
+            let holes = layer.uncovered_ranges();
+            if holes.is_empty() {
+                return None;
+            }
+            let uncovered_buckets = holes.iter().map(|(start, end)| end - start).sum();
+            Some(LayerGaps {
+                layer_id: layer.layer_id.clone(),
+                bucket_size: layer.bucket_size(),
+                uncovered_buckets,
+                uncovered_ratio: layer.uncovered_share(),
+                holes,
+            })
+        })
+        .collect();
+
+    let catalog = snapshot.catalog();
+    let services = catalog
+        .get_all_services()
+        .into_iter()
+        .filter_map(|service| {
+            let layers = snapshot.layers_for_service(&service);
+            if layers.is_empty() {
+                return None;
+            }
+            // Layers hash independently, so misses multiply
+            let mut clear = 1.0;
+            let mut miss = 1.0;
+            let mut with_holes = BTreeMap::new();
+            for layer in layers.iter() {
+                let uncovered = layer.uncovered_share();
+                clear *= 1.0 - uncovered;
+                miss *= 1.0 - layer.service_share(&service, catalog);
+                if uncovered > 0.0 {
+                    with_holes.insert(layer.layer_id.clone(), uncovered);
+                }
+            }
+            let gaps = ServiceGaps {
+                hole_ratio: 1.0 - clear,
+                unassigned_ratio: miss,
+                layers: with_holes,
+            };
+            Some((service, gaps))
+        })
+        .collect();
+
+    CoverageGaps {
+        epoch: snapshot.epoch(),
+        layers,
+        services,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
+    use crate::engine::Evaluator;
+    use crate::layer::BucketRange;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_reports_holes_per_layer_and_service() {
+        let catalog = ExperimentCatalog::from_experiments(
+            vec![ExperimentDef {
+                eid: 100,
+                service: "svc".to_string(),
+                expires_at: None,
+                state: Default::default(),
+                caps: None,
+                rule: None,
+                exposure_sampling: None,
+                variants: vec![VariantDef {
+                    vid: 101,
+                    params: json!({}),
+                    dark_params: None,
+                }],
+            }],
+            PathBuf::new(),
+        )
+        .unwrap();
+        let layer = |layer_id: &str, ranges: Vec<(u32, u32)>| Layer {
+            layer_id: layer_id.to_string(),
+            version: "v1".to_string(),
+            priority: 1,
+            hash_key: "user_id".into(),
+            salt: None,
+            bucket_size: None,
+            expires_at: None,
+            rule: None,
+            services: vec![],
+            ranges: ranges
+                .into_iter()
+                .map(|(start, end)| BucketRange {
+                    start,
+                    end,
+                    vid: 101,
+                    label: None,
+                })
+                .collect(),
+            enabled: true,
+            aa_test: false,
+        };
+        let evaluator = Evaluator::builder()
+            .with_catalog(catalog)
+            .with_layers([
+                layer("full", vec![(0, 10000)]),
+                layer("gappy", vec![(1000, 4000), (5000, 8000)]),
+            ])
+            .build()
+            .unwrap();
+
+        let report = gaps(&evaluator.layer_manager().snapshot());
+        assert_eq!(report.layers.len(), 1);
+        assert_eq!(report.layers[0].layer_id, "gappy");
+        assert_eq!(report.layers[0].holes, vec![(0, 1000), (4000, 5000), (8000, 10000)]);
+        assert_eq!(report.layers[0].uncovered_buckets, 4000);
+
+        let svc = &report.services["svc"];
+        assert!((svc.hole_ratio - 0.4).abs() < 1e-9);
+        // The full layer assigns everyone
+        assert_eq!(svc.unassigned_ratio, 0.0);
+        assert_eq!(svc.layers.keys().collect::<Vec<_>>(), vec!["gappy"]);
+    }
+}
@@ -0,0 +1,159 @@
+//! Minimal built-in dashboard served at `/ui` (feature `admin`).
+//!
+//! The static page is embedded into the binary and polls
+//! `/diagnostics/overview`, which summarizes the live snapshot: layers,
+//! experiments with the traffic this instance assigned to them, the config
+//! epoch and recent changes.
+
+use crate::layer::{ConfigChange, LayerManager};
+use crate::stats::TrafficStats;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use rust_embed::RustEmbed;
+use serde::Serialize;
+
+#[derive(RustEmbed)]
+#[folder = "ui/"]
+struct Assets;
+
+#[derive(Debug, Serialize)]
+pub struct LayerSummary {
+    pub layer_id: String,
+    pub version: String,
+    pub priority: i32,
+    /// Composite keys joined with `+`
+    pub hash_key: String,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VariantTraffic {
+    pub vid: i64,
+    pub assignments: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExperimentSummary {
+    pub eid: i64,
+    pub service: String,
+    pub assignments: u64,
+    pub variants: Vec<VariantTraffic>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Overview {
+    pub epoch: u64,
+    /// Highest priority first
+    pub layers: Vec<LayerSummary>,
+    /// By eid
+    pub experiments: Vec<ExperimentSummary>,
+    /// Newest first
+    pub recent_changes: Vec<ConfigChange>,
+}
+
+/// Summarize the live snapshot with this instance's traffic counters
+pub fn overview(manager: &LayerManager, stats: &TrafficStats) -> Overview {
+    let snapshot = manager.snapshot();
+    let counts = stats.counts();
+
+    let mut layers: Vec<LayerSummary> = snapshot
+        .layers()
+        .map(|layer| LayerSummary {
+            layer_id: layer.layer_id.clone(),
+            version: layer.version.clone(),
+            priority: layer.priority,
+            hash_key: layer.hash_key.to_string(),
+            enabled: layer.enabled,
+        })
+        .collect();
+    layers.sort_by(|a, b| b.priority.cmp(&a.priority).then_with(|| a.layer_id.cmp(&b.layer_id)));
+
+    let catalog = snapshot.catalog();
+    let mut experiments: Vec<ExperimentSummary> = catalog
+        .eids()
+        .filter_map(|eid| {
+            let (service, vids) = catalog.experiment_outline(eid)?;
+            let variants: Vec<VariantTraffic> = vids
+                .into_iter()
+                .map(|vid| VariantTraffic {
+                    vid,
+                    assignments: counts.get(&vid).copied().unwrap_or(0),
+                })
+                .collect();
+            Some(ExperimentSummary {
+                eid,
+                service: service.to_string(),
+                assignments: variants.iter().map(|v| v.assignments).sum(),
+                variants,
+            })
+        })
+        .collect();
+    experiments.sort_by_key(|e| e.eid);
+
+    Overview {
+        epoch: snapshot.epoch(),
+        layers,
+        experiments,
+        recent_changes: manager.recent_changes(),
+    }
+}
+
+/// Embedded asset at `path` (`index.html` for the dashboard root)
+pub fn asset(path: &str) -> Response {
+    let path = if path.is_empty() { "index.html" } else { path };
+    match Assets::get(path) {
+        Some(file) => (
+            [(header::CONTENT_TYPE, file.metadata.mimetype().to_string())],
+            file.data,
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::ExperimentCatalog;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_overview_counts_traffic_per_experiment() {
+        let temp_dir = TempDir::new().unwrap();
+        let experiments_dir = temp_dir.path().join("experiments");
+        let layers_dir = temp_dir.path().join("layers");
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::write(
+            experiments_dir.join("100.json"),
+            r#"{"eid": 100, "service": "svc", "variants": [{"vid": 1001, "params": {}}, {"vid": 1002, "params": {}}]}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            layers_dir.join("l1.json"),
+            r#"{"layer_id": "l1", "version": "v1", "priority": 1, "hash_key": "user_id", "enabled": true,
+                "ranges": [{"start": 0, "end": 5000, "vid": 1001}, {"start": 5000, "end": 10000, "vid": 1002}]}"#,
+        )
+        .unwrap();
+
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+        let manager = LayerManager::new(layers_dir);
+        manager.load_all_layers(&catalog).await.unwrap();
+
+        let stats = TrafficStats::new();
+        stats.record(&[1001]);
+        stats.record(&[1001, 1002]);
+
+        let overview = overview(&manager, &stats);
+        assert!(overview.epoch > 0);
+        assert_eq!(overview.layers[0].layer_id, "l1");
+        assert_eq!(overview.experiments[0].assignments, 3);
+        assert_eq!(overview.experiments[0].variants[0].assignments, 2);
+        assert_eq!(overview.recent_changes[0].layers.added, 1);
+        assert_eq!(overview.recent_changes[0].epoch, overview.epoch);
+
+        assert_eq!(asset("").status(), StatusCode::OK);
+        assert_eq!(asset("missing.js").status(), StatusCode::NOT_FOUND);
+    }
+}
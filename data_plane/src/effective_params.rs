@@ -0,0 +1,257 @@
+//! Final payload of a set of variants, as a service would receive it.
+//!
+//! `GET /services/:service/effective_params?vid=...` merges the params of the
+//! given vids in the service's resolution order, exactly as evaluation merges
+//! a subject's matched variants: a higher-priority layer wins a conflicting
+//! path. A launch review sees the payload instead of each variant's delta.
+//! Listing a vid of every layer a subject lands in reproduces its response.
+//!
+//! The data plane keeps no service-level default params (callers apply their
+//! own defaults to paths nothing sets), so for each resulting path the preview
+//! also lists the higher-ranked layers whose variants could still override it
+//! for subjects assigned there.
+
+use crate::error::{ExperimentError, Result};
+use crate::layer::Snapshot;
+use crate::merge::{flatten_params, merge_params_prioritized};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EffectiveParams {
+    pub service: String,
+    /// Merged params, as in an evaluation response
+    pub parameters: Value,
+    /// Requested variants in merge order (highest priority first)
+    pub variants: Vec<MergedVariant>,
+    /// Dot path -> vid supplying its value
+    pub sources: BTreeMap<String, i64>,
+    /// Dot path -> higher-ranked layers whose variants set the same path
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub overridable: BTreeMap<String, Vec<PossibleOverride>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MergedVariant {
+    /// Position in `/services/:service/resolution_order`
+    pub rank: usize,
+    pub layer_id: String,
+    pub eid: i64,
+    pub vid: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PossibleOverride {
+    pub layer_id: String,
+    pub vid: i64,
+}
+
+/// Params a subject assigned to `vids` of `service` receives
+pub fn effective_params(snapshot: &Snapshot, service: &str, vids: &[i64]) -> Result<EffectiveParams> {
+    if vids.is_empty() {
+        return Err(ExperimentError::InvalidParameter("At least one vid is required".to_string()));
+    }
+    let catalog = snapshot.catalog();
+    let layers = snapshot.layers_for_service(service);
+
+    let mut variants = Vec::with_capacity(vids.len());
+    for &vid in vids {
+        match catalog.variant_owner(vid) {
+            Some((_, owner)) if owner == service => {}
+            Some((_, owner)) => {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "vid {} belongs to service '{}', not '{}'",
+                    vid, owner, service
+                )))
+            }
+            None => return Err(ExperimentError::InvalidParameter(format!("Unknown vid {}", vid))),
+        }
+        // A vid allocated in several layers merges at its highest rank
+        let (rank, layer) = layers
+            .iter()
+            .enumerate()
+            .find(|(_, layer)| layer.ranges.iter().any(|range| range.vid == vid))
+            .ok_or_else(|| {
+                ExperimentError::InvalidParameter(format!(
+                    "vid {} is not allocated in any enabled layer of service '{}'",
+                    vid, service
+                ))
+            })?;
+        if let Some(other) = variants.iter().find(|m: &&MergedVariant| m.rank == rank + 1) {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "vids {} and {} are in the same layer '{}'; a subject gets only one",
+                other.vid, vid, layer.layer_id
+            )));
+        }
+        variants.push(MergedVariant {
+            rank: rank + 1,
+            layer_id: layer.layer_id.clone(),
+            eid: catalog.get_eid_by_vid(vid).unwrap_or_default(),
+            vid,
+            label: layer.label_for(vid).map(str::to_string),
+        });
+    }
+    variants.sort_by_key(|m| m.rank);
+
+    let mut merged = serde_json::Map::new();
+    let mut variant_paths = Vec::with_capacity(variants.len());
+    for m in &variants {
+        let Some((_, _, _, params)) = catalog.get_variant(m.vid) else {
+            continue;
+        };
+        merge_params_prioritized(&mut merged, &params)?;
+        let mut paths = BTreeMap::new();
+        flatten_params("", &params, &mut paths);
+        variant_paths.push((m.vid, paths));
+    }
+    let parameters = Value::Object(merged);
+
+    // Every merged leaf is the leaf of the highest-ranked variant setting it
+    let mut leaves = BTreeMap::new();
+    flatten_params("", &parameters, &mut leaves);
+    let sources: BTreeMap<String, i64> = leaves
+        .into_keys()
+        .filter_map(|path| {
+            let (vid, _) = variant_paths.iter().find(|(_, paths)| paths.contains_key(&path))?;
+            Some((path, *vid))
+        })
+        .collect();
+
+    // Paths set by variants of layers ranked above the one supplying them
+    let mut overridable: BTreeMap<String, Vec<PossibleOverride>> = BTreeMap::new();
+    for (i, layer) in layers.iter().enumerate() {
+        if variants.iter().any(|m| m.rank == i + 1) {
+            continue;
+        }
+        let mut layer_vids: Vec<i64> = layer
+            .ranges
+            .iter()
+            .map(|range| range.vid)
+            .filter(|vid| catalog.variant_owner(*vid).is_some_and(|(_, owner)| owner == service))
+            .collect();
+        layer_vids.sort_unstable();
+        layer_vids.dedup();
+        for vid in layer_vids {
+            let Some((_, _, _, params)) = catalog.get_variant(vid) else {
+                continue;
+            };
+            let mut paths = BTreeMap::new();
+            flatten_params("", &params, &mut paths);
+            for (path, source_vid) in &sources {
+                let source_rank = variants.iter().find(|m| m.vid == *source_vid).map_or(0, |m| m.rank);
+                if i + 1 < source_rank && paths.keys().any(|p| overlaps(p, path)) {
+                    overridable.entry(path.clone()).or_default().push(PossibleOverride {
+                        layer_id: layer.layer_id.clone(),
+                        vid,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(EffectiveParams {
+        service: service.to_string(),
+        parameters,
+        variants,
+        sources,
+        overridable,
+    })
+}
+
+/// Whether setting one dot path affects the other (equal, or one nested in the other)
+fn overlaps(a: &str, b: &str) -> bool {
+    let nested = |outer: &str, inner: &str| inner.strip_prefix(outer).is_some_and(|rest| rest.starts_with('.'));
+    a == b || nested(a, b) || nested(b, a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
+    use crate::layer::{BucketRange, Layer, LayerManager};
+    use serde_json::json;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    fn experiment(eid: i64, variants: Vec<(i64, Value)>) -> ExperimentDef {
+        ExperimentDef {
+            eid,
+            service: "svc".to_string(),
+            rule: None,
+            expires_at: None,
+            state: Default::default(),
+            caps: None,
+            exposure_sampling: None,
+            variants: variants.into_iter().map(|(vid, params)| VariantDef { vid, params, dark_params: None }).collect(),
+        }
+    }
+
+    fn layer(layer_id: &str, priority: i32, vids: &[i64]) -> Layer {
+        Layer {
+            layer_id: layer_id.to_string(),
+            version: "v1".to_string(),
+            priority,
+            hash_key: "user_id".into(),
+            salt: None,
+            bucket_size: None,
+            expires_at: None,
+            rule: None,
+            services: vec![],
+            ranges: vids
+                .iter()
+                .enumerate()
+                .map(|(i, &vid)| BucketRange {
+                    start: i as u32 * 100,
+                    end: (i as u32 + 1) * 100,
+                    vid,
+                    label: None,
+                })
+                .collect(),
+            enabled: true,
+            aa_test: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_effective_params_follow_resolution_order() {
+        let catalog = Arc::new(
+            ExperimentCatalog::from_experiments(
+                vec![
+                    experiment(100, vec![(101, json!({"ranker": {"model": "gbdt", "timeout_ms": 50}}))]),
+                    experiment(200, vec![(201, json!({"ranker": {"model": "dnn"}, "banner": true}))]),
+                    experiment(300, vec![(301, json!({"ranker": {"timeout_ms": 80}})), (302, json!({}))]),
+                ],
+                PathBuf::new(),
+            )
+            .unwrap(),
+        );
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let manager = LayerManager::new(temp_dir.path().to_path_buf());
+        for layer in [layer("top", 30, &[301, 302]), layer("mid", 20, &[201]), layer("low", 10, &[101])] {
+            let path = manager.layer_path(&layer.layer_id);
+            manager.upsert_layer(layer, &path, &catalog).unwrap();
+        }
+        let snapshot = manager.snapshot();
+
+        // Listed in any order, merged by rank: mid's model wins over low's
+        let preview = effective_params(&snapshot, "svc", &[101, 201]).unwrap();
+        assert_eq!(preview.parameters, json!({"ranker": {"model": "dnn", "timeout_ms": 50}, "banner": true}));
+        let order: Vec<_> = preview.variants.iter().map(|m| (m.rank, m.vid)).collect();
+        assert_eq!(order, vec![(2, 201), (3, 101)]);
+        assert_eq!(preview.sources["ranker.model"], 201);
+        assert_eq!(preview.sources["ranker.timeout_ms"], 101);
+        // Subjects also in top's 301 get its timeout instead
+        assert_eq!(
+            preview.overridable["ranker.timeout_ms"],
+            vec![PossibleOverride { layer_id: "top".to_string(), vid: 301 }]
+        );
+        assert!(!preview.overridable.contains_key("ranker.model"));
+
+        assert!(effective_params(&snapshot, "svc", &[301, 302]).is_err());
+        assert!(effective_params(&snapshot, "svc", &[999]).is_err());
+        assert!(effective_params(&snapshot, "other", &[101]).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Emergency overrides: a local break-glass file that force-disables layers and
+//! experiments regardless of what the config sources say.
+//!
+//! The file is re-read every few seconds. A missing file means nothing is
+//! disabled; a file that fails to parse keeps the previous state.
+
+use crate::config::migrate;
+use crate::error::Result;
+use crate::layer::LayerManager;
+use crate::metrics;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EmergencyOverrides {
+    /// layer_ids taken out of evaluation
+    #[serde(default)]
+    pub layers: BTreeSet<String>,
+
+    /// eids whose variants are never assigned
+    #[serde(default)]
+    pub experiments: BTreeSet<i64>,
+
+    /// Free-form note (incident id, operator)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl EmergencyOverrides {
+    /// Read the override file; a missing file disables nothing
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_value(migrate::parse_document(&content)?)?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty() && self.experiments.is_empty()
+    }
+
+    /// Whether a hit on `eid` in `layer_id` must be dropped
+    pub fn disables(&self, layer_id: &str, eid: i64) -> bool {
+        self.layers.contains(layer_id) || self.experiments.contains(&eid)
+    }
+}
+
+/// Re-read the file and apply it if it changed
+pub fn apply_file(path: &Path, manager: &LayerManager) {
+    match EmergencyOverrides::load(path) {
+        Ok(overrides) if overrides == *manager.emergency_overrides() => {}
+        Ok(overrides) => {
+            if overrides.is_empty() {
+                tracing::warn!("Emergency overrides cleared");
+            } else {
+                tracing::warn!(
+                    "Emergency overrides active: layers {:?}, experiments {:?} (reason: {})",
+                    overrides.layers,
+                    overrides.experiments,
+                    overrides.reason.as_deref().unwrap_or("-")
+                );
+            }
+            metrics::EMERGENCY_DISABLED
+                .with_label_values(&["layer"])
+                .set(overrides.layers.len() as i64);
+            metrics::EMERGENCY_DISABLED
+                .with_label_values(&["experiment"])
+                .set(overrides.experiments.len() as i64);
+            manager.set_emergency_overrides(overrides);
+        }
+        Err(e) => {
+            tracing::error!("Failed to read emergency overrides {:?}, keeping previous state: {}", path, e);
+            metrics::EMERGENCY_OVERRIDE_ERRORS.inc();
+        }
+    }
+}
+
+/// Re-check the override file every `interval`
+pub async fn watch_file(path: std::path::PathBuf, manager: Arc<LayerManager>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        apply_file(&path, &manager);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::ExperimentCatalog;
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_emergency_overrides_disable_and_clear() {
+        let temp_dir = TempDir::new().unwrap();
+        let experiments_dir = temp_dir.path().join("experiments");
+        let layers_dir = temp_dir.path().join("layers");
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+        std::fs::create_dir_all(&layers_dir).unwrap();
+
+        for (eid, layer_id) in [(100, "a"), (200, "b")] {
+            let exp = serde_json::json!({
+                "eid": eid,
+                "service": "svc",
+                "variants": [{"vid": eid + 1, "params": {layer_id: true}}]
+            });
+            std::fs::write(experiments_dir.join(format!("{}.json", eid)), exp.to_string()).unwrap();
+            let layer = serde_json::json!({
+                "layer_id": layer_id,
+                "version": "v1",
+                "priority": 1,
+                "hash_key": "user_id",
+                "enabled": true,
+                "ranges": [{"start": 0, "end": 10000, "vid": eid + 1}]
+            });
+            std::fs::write(layers_dir.join(format!("{}.json", layer_id)), layer.to_string()).unwrap();
+        }
+
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+        let manager = LayerManager::new(layers_dir);
+        manager.load_all_layers(&catalog).await.unwrap();
+
+        let request = crate::merge::ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([("user_id".to_string(), serde_json::json!("u1"))]),
+            layers: vec![],
+            diagnostics: false,
+        };
+        let evaluate = || {
+            crate::merge::merge_layers_batch(&request, &manager.snapshot(), &HashMap::new())
+                .unwrap()
+                .results["svc"]
+                .parameters
+                .clone()
+        };
+        assert_eq!(evaluate(), serde_json::json!({"a": true, "b": true}));
+
+        let path = temp_dir.path().join("emergency_overrides.json");
+        std::fs::write(&path, r#"{"layers": ["a"], "experiments": [200], "reason": "INC-1"}"#).unwrap();
+        apply_file(&path, &manager);
+        assert_eq!(evaluate(), serde_json::json!({}));
+        assert_eq!(manager.get_layers_for_service("svc").len(), 1);
+
+        // A broken file keeps the previous state
+        std::fs::write(&path, "{not json").unwrap();
+        apply_file(&path, &manager);
+        assert_eq!(evaluate(), serde_json::json!({}));
+
+        // Layer pushes don't bypass the override
+        manager.load_all_layers(&catalog).await.unwrap();
+        assert_eq!(evaluate(), serde_json::json!({}));
+
+        std::fs::remove_file(&path).unwrap();
+        apply_file(&path, &manager);
+        assert_eq!(evaluate(), serde_json::json!({"a": true, "b": true}));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::merge::{ExperimentRequest, ServiceResult};
+use crate::metrics;
+use lru::LruCache;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+/// Short-lived per-subject results, absorbing retries and fan-out calls that
+/// evaluate the same subject several times within one user interaction.
+///
+/// Entries are keyed by service and the full request context (which carries
+/// the subject key and every field a rule may read), and only served for the
+/// snapshot epoch they were computed at, so any config change invalidates them.
+/// Past `max_entries` the least recently used entry is evicted; expired
+/// entries are dropped when read.
+///
+/// The cache is per process on purpose: a hit must be cheaper than the
+/// evaluation it saves (microseconds), which a network round trip to a shared
+/// [`crate::kv::KvStore`] backend isn't, and entries only live for the TTL of
+/// one user interaction at one epoch, which other instances wouldn't share.
+pub struct ResultCache {
+    ttl: Duration,
+    entries: Mutex<LruCache<CacheKey, CachedResult>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct CacheKey {
+    pub(super) service: String,
+    /// Canonical JSON of the context and explicit layer list
+    subject: String,
+}
+
+struct CachedResult {
+    epoch: u64,
+    at: Instant,
+    result: ServiceResult,
+}
+
+impl ResultCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(LruCache::new(NonZeroUsize::new(max_entries).unwrap_or(NonZeroUsize::MIN))),
+        }
+    }
+
+    /// Cached result for `service`, if computed at `epoch` within the TTL
+    pub fn get(&self, service: &str, request: &ExperimentRequest, epoch: u64) -> Option<ServiceResult> {
+        let key = CacheKey::new(service, request);
+        let mut entries = self.entries.lock();
+        let hit = match entries.get(&key) {
+            Some(cached) if cached.epoch == epoch && cached.at.elapsed() < self.ttl => Some(cached.result.clone()),
+            Some(_) => {
+                entries.pop(&key);
+                None
+            }
+            None => None,
+        };
+        let len = entries.len();
+        drop(entries);
+        metrics::RESULT_CACHE_ENTRIES.set(len as i64);
+
+        let label = if hit.is_some() { "hit" } else { "miss" };
+        metrics::RESULT_CACHE_LOOKUPS.with_label_values(&[label]).inc();
+        hit
+    }
+
+    pub fn insert(&self, service: &str, request: &ExperimentRequest, epoch: u64, result: &ServiceResult) {
+        let key = CacheKey::new(service, request);
+        let mut entries = self.entries.lock();
+        entries.put(
+            key,
+            CachedResult {
+                epoch,
+                at: Instant::now(),
+                result: result.clone(),
+            },
+        );
+        metrics::RESULT_CACHE_ENTRIES.set(entries.len() as i64);
+    }
+
+    /// Drop everything, e.g. when evaluation inputs outside the snapshot change
+    pub fn clear(&self) {
+        self.entries.lock().clear();
+        metrics::RESULT_CACHE_ENTRIES.set(0);
+    }
+}
+
+impl CacheKey {
+    pub(super) fn new(service: &str, request: &ExperimentRequest) -> Self {
+        let context: BTreeMap<_, _> = request.context.iter().collect();
+        let subject = serde_json::to_string(&(context, &request.layers)).unwrap_or_default();
+        Self {
+            service: service.to_string(),
+            subject,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn request(user: &str) -> ExperimentRequest {
+        ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([("user_id".to_string(), json!(user))]),
+            layers: vec![],
+            diagnostics: false,
+        }
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used_and_expired() {
+        let cache = ResultCache::new(Duration::from_secs(60), 2);
+        let result = ServiceResult {
+            parameters: json!({}),
+            vids: vec![],
+            matched_layers: vec![],
+            truncated: false,
+            excluded: false,
+            dark_parameters: None,
+            unavailable: false,
+            diagnostics: None,
+            trace: None,
+        };
+        cache.insert("svc", &request("u1"), 1, &result);
+        cache.insert("svc", &request("u2"), 1, &result);
+        // u1 was read last, so u2 makes room for u3
+        assert!(cache.get("svc", &request("u1"), 1).is_some());
+        cache.insert("svc", &request("u3"), 1, &result);
+        assert!(cache.get("svc", &request("u2"), 1).is_none());
+        assert!(cache.get("svc", &request("u1"), 1).is_some());
+        assert!(cache.get("svc", &request("u3"), 1).is_some());
+        // Another epoch misses and drops the entry
+        assert!(cache.get("svc", &request("u3"), 2).is_none());
+        assert!(cache.get("svc", &request("u3"), 1).is_none());
+
+        let expiring = ResultCache::new(Duration::ZERO, 2);
+        expiring.insert("svc", &request("u1"), 1, &result);
+        assert!(expiring.get("svc", &request("u1"), 1).is_none());
+    }
+}
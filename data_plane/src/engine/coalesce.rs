@@ -0,0 +1,222 @@
+use super::cache::CacheKey;
+use crate::merge::{ExperimentRequest, ServiceResult};
+use crate::metrics;
+use parking_lot::{Condvar, Mutex};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Per-subject in-flight dedup, absorbing thundering herds from fan-out
+/// architectures where many callers evaluate the same subject at once.
+///
+/// The first evaluation of a service + context (the same key as
+/// [`super::ResultCache`]) at a snapshot epoch leads; identical evaluations
+/// arriving before it finishes wait for its result instead of repeating the
+/// work. Request handlers await the flight (`Flight::wait_async`) so a herd
+/// of followers doesn't park runtime worker threads; synchronous embedders
+/// block (`Flight::wait`). If the leader fails (error or panic) its
+/// followers evaluate on their own.
+#[derive(Default)]
+pub struct Coalescer {
+    flights: Mutex<HashMap<(CacheKey, u64), Arc<Flight>>>,
+}
+
+/// One evaluation in progress
+pub struct Flight {
+    /// `None` while pending, then the leader's result if it produced one
+    result: Mutex<Option<Option<ServiceResult>>>,
+    /// Wakes blocking followers
+    ready: Condvar,
+    /// Wakes followers awaiting on the runtime
+    done: Notify,
+}
+
+/// Whether a caller evaluates a service itself or waits for another caller
+pub enum Role<'a> {
+    Lead(Lead<'a>),
+    Follow(Arc<Flight>),
+}
+
+/// Obligation to publish a result; dropping it unfinished releases followers
+/// without one
+pub struct Lead<'a> {
+    coalescer: &'a Coalescer,
+    key: (CacheKey, u64),
+    flight: Arc<Flight>,
+}
+
+impl Coalescer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lead the evaluation of `service` for this request, or follow the one in flight
+    pub fn join(&self, service: &str, request: &ExperimentRequest, epoch: u64) -> Role<'_> {
+        let key = (CacheKey::new(service, request), epoch);
+        let mut flights = self.flights.lock();
+        if let Some(flight) = flights.get(&key) {
+            metrics::EVALUATION_COALESCING.with_label_values(&["coalesced"]).inc();
+            return Role::Follow(flight.clone());
+        }
+        let flight = Arc::new(Flight {
+            result: Mutex::new(None),
+            ready: Condvar::new(),
+            done: Notify::new(),
+        });
+        flights.insert(key.clone(), flight.clone());
+        drop(flights);
+
+        metrics::EVALUATION_COALESCING.with_label_values(&["led"]).inc();
+        Role::Lead(Lead {
+            coalescer: self,
+            key,
+            flight,
+        })
+    }
+}
+
+impl Flight {
+    /// Block until the leader finishes; `None` when it failed
+    pub fn wait(&self) -> Option<ServiceResult> {
+        let mut result = self.result.lock();
+        while result.is_none() {
+            self.ready.wait(&mut result);
+        }
+        outcome(result.clone().flatten())
+    }
+
+    /// Wait for the leader without blocking the thread; `None` when it failed
+    pub async fn wait_async(&self) -> Option<ServiceResult> {
+        loop {
+            // Registered before checking so a finish in between isn't missed
+            let finished = self.done.notified();
+            tokio::pin!(finished);
+            finished.as_mut().enable();
+            if let Some(result) = self.result.lock().clone() {
+                return outcome(result);
+            }
+            finished.await;
+        }
+    }
+}
+
+fn outcome(result: Option<ServiceResult>) -> Option<ServiceResult> {
+    if result.is_none() {
+        metrics::EVALUATION_COALESCING.with_label_values(&["fallback"]).inc();
+    }
+    result
+}
+
+impl Lead<'_> {
+    pub fn service(&self) -> &str {
+        &self.key.0.service
+    }
+
+    /// Hand the result to every follower
+    pub fn finish(self, result: Option<&ServiceResult>) {
+        *self.flight.result.lock() = Some(result.cloned());
+    }
+}
+
+impl Drop for Lead<'_> {
+    fn drop(&mut self) {
+        // Later arrivals lead a new evaluation rather than reuse this one
+        self.coalescer.flights.lock().remove(&self.key);
+        let mut result = self.flight.result.lock();
+        if result.is_none() {
+            *result = Some(None);
+        }
+        drop(result);
+        self.flight.ready.notify_all();
+        self.flight.done.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::thread;
+
+    fn result(vid: i64) -> ServiceResult {
+        ServiceResult {
+            parameters: json!({"model": "dnn"}),
+            vids: vec![vid],
+            matched_layers: vec!["l1".to_string()],
+            trace: None,
+            diagnostics: None,
+            truncated: false,
+            excluded: false,
+            dark_parameters: None,
+            unavailable: false,
+        }
+    }
+
+    #[test]
+    fn test_followers_share_leader_result() {
+        let coalescer = Coalescer::new();
+        let request = ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([("user_id".to_string(), json!("u1"))]),
+            layers: vec![],
+            diagnostics: false,
+        };
+
+        let Role::Lead(lead) = coalescer.join("svc", &request, 1) else {
+            panic!("first evaluation must lead");
+        };
+        // Another epoch or service is a different evaluation
+        assert!(matches!(coalescer.join("svc", &request, 2), Role::Lead(_)));
+        assert!(matches!(coalescer.join("other", &request, 1), Role::Lead(_)));
+        let Role::Follow(flight) = coalescer.join("svc", &request, 1) else {
+            panic!("identical evaluation must follow");
+        };
+        let waiter = thread::spawn(move || flight.wait());
+        lead.finish(Some(&result(1001)));
+        assert_eq!(waiter.join().unwrap().unwrap().vids, vec![1001]);
+
+        // A failed leader releases its followers empty-handed, and the next caller leads
+        let Role::Lead(lead) = coalescer.join("svc", &request, 1) else {
+            panic!("finished flights are not reused");
+        };
+        let Role::Follow(flight) = coalescer.join("svc", &request, 1) else {
+            panic!("identical evaluation must follow");
+        };
+        drop(lead);
+        assert!(flight.wait().is_none());
+        assert!(matches!(coalescer.join("svc", &request, 1), Role::Lead(_)));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_async_followers_leave_the_runtime_free() {
+        let coalescer = Arc::new(Coalescer::new());
+        let request = ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([("user_id".to_string(), json!("u1"))]),
+            layers: vec![],
+            diagnostics: false,
+        };
+
+        let Role::Lead(lead) = coalescer.join("svc", &request, 1) else {
+            panic!("first evaluation must lead");
+        };
+        let followers: Vec<_> = (0..16)
+            .map(|_| {
+                let Role::Follow(flight) = coalescer.join("svc", &request, 1) else {
+                    panic!("identical evaluation must follow");
+                };
+                tokio::spawn(async move { flight.wait_async().await })
+            })
+            .collect();
+
+        // With every follower waiting, the only thread still runs other tasks
+        let other = tokio::spawn(async { "health" });
+        let other = tokio::time::timeout(std::time::Duration::from_secs(5), other).await;
+        assert_eq!(other.unwrap().unwrap(), "health");
+
+        lead.finish(Some(&result(1001)));
+        for follower in followers {
+            assert_eq!(follower.await.unwrap().unwrap().vids, vec![1001]);
+        }
+    }
+}
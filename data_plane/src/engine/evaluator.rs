@@ -0,0 +1,658 @@
+use super::cache::ResultCache;
+use super::coalesce::{Coalescer, Flight, Role};
+use crate::caps::{CapCandidate, CapGate, CapTracker};
+use crate::catalog::ExperimentCatalog;
+use crate::error::{ExperimentError, Result};
+use crate::kv::{KvStore, MemoryStore};
+use crate::layer::{validate_and_sort_ranges, Layer, LayerManager, Snapshot};
+use crate::merge::{merge_layers_batch_traced, ExperimentRequest, ExperimentResponse, ServiceResult};
+use crate::metrics;
+use crate::overrides::Overrides;
+use crate::rule::FieldType;
+use crate::sim::{simulate, SimulateRequest, SimulateResponse};
+use crate::whatif::{what_if, WhatIfRequest, WhatIfResponse};
+use parking_lot::RwLock;
+use std::collections::{HashMap, HashSet};
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Evaluates requests against the current catalog, layers and field types.
+///
+/// Catalog and layers are read from the layer manager's snapshot: hot reload,
+/// source switching and emergency overrides keep applying to an evaluator
+/// built on top of it.
+pub struct Evaluator {
+    layer_manager: Arc<LayerManager>,
+    field_types: RwLock<HashMap<String, FieldType>>,
+    result_cache: Option<ResultCache>,
+    coalescer: Option<Coalescer>,
+    /// Subjects admitted to capped variants (see [`crate::caps`])
+    cap_tracker: Arc<CapTracker>,
+}
+
+/// Builder for [`Evaluator`]; every part is optional
+#[derive(Default)]
+pub struct EvaluatorBuilder {
+    catalog: Option<ExperimentCatalog>,
+    layer_manager: Option<Arc<LayerManager>>,
+    layers: Vec<Layer>,
+    field_types: HashMap<String, FieldType>,
+    result_cache: Option<(Duration, usize)>,
+    coalescing: bool,
+    kv_store: Option<Arc<dyn KvStore>>,
+}
+
+impl EvaluatorBuilder {
+    /// Publish `catalog` to the layer manager on build. Without it the
+    /// manager's current catalog is kept (empty for a new manager).
+    pub fn with_catalog(mut self, catalog: ExperimentCatalog) -> Self {
+        self.catalog = Some(catalog);
+        self
+    }
+
+    /// In-memory layers, validated and indexed against the catalog on build
+    pub fn with_layers(mut self, layers: impl IntoIterator<Item = Layer>) -> Self {
+        self.layers.extend(layers);
+        self
+    }
+
+    /// An existing (usually file-backed, already loaded) layer manager.
+    /// Layers passed to [`with_layers`](Self::with_layers) are added to it.
+    pub fn with_layer_manager(mut self, layer_manager: Arc<LayerManager>) -> Self {
+        self.layer_manager = Some(layer_manager);
+        self
+    }
+
+    pub fn with_field_types(mut self, field_types: HashMap<String, FieldType>) -> Self {
+        self.field_types = field_types;
+        self
+    }
+
+    /// Reuse untraced, override-free results for the same service and context
+    /// for up to `ttl`, as long as the config snapshot is unchanged
+    pub fn with_result_cache(mut self, ttl: Duration, max_entries: usize) -> Self {
+        self.result_cache = Some((ttl, max_entries));
+        self
+    }
+
+    /// Let identical concurrent evaluations that the result cache could
+    /// serve wait for the one in flight instead of repeating it
+    pub fn with_coalescing(mut self) -> Self {
+        self.coalescing = true;
+        self
+    }
+
+    /// Keep exposure cap admissions in `store` instead of a private in-memory
+    /// store, sharing them with every instance on the same backend
+    pub fn with_kv_store(mut self, store: Arc<dyn KvStore>) -> Self {
+        self.kv_store = Some(store);
+        self
+    }
+
+    /// Build the snapshot: catalog first, then layers indexed against it
+    pub fn build(self) -> Result<Evaluator> {
+        let layer_manager = self
+            .layer_manager
+            .unwrap_or_else(|| Arc::new(LayerManager::new(PathBuf::new())));
+
+        // Publish the layer set against this catalog even if it was loaded with another
+        if let Some(catalog) = self.catalog {
+            layer_manager.reindex(&Arc::new(catalog))?;
+        }
+        for mut layer in self.layers {
+            let bucket_size = layer.bucket_size();
+            validate_and_sort_ranges(&mut layer.ranges, bucket_size)?;
+            let path = layer_manager.layer_path(&layer.layer_id);
+            layer_manager.upsert_layer(layer, &path, layer_manager.snapshot().catalog())?;
+        }
+
+        layer_manager.set_semver_fields(semver_fields(&self.field_types));
+        Ok(Evaluator {
+            layer_manager,
+            field_types: RwLock::new(self.field_types),
+            result_cache: self
+                .result_cache
+                .map(|(ttl, max_entries)| ResultCache::new(ttl, max_entries)),
+            coalescer: self.coalescing.then(Coalescer::new),
+            cap_tracker: Arc::new(CapTracker::new(
+                self.kv_store.unwrap_or_else(|| Arc::new(MemoryStore::new())),
+            )),
+        })
+    }
+}
+
+impl Evaluator {
+    pub fn builder() -> EvaluatorBuilder {
+        EvaluatorBuilder::default()
+    }
+
+    /// Merged params per requested service
+    pub fn evaluate(&self, request: &ExperimentRequest) -> Result<ExperimentResponse> {
+        self.evaluate_with_overrides(request, &Overrides::new())
+    }
+
+    /// Same as [`evaluate`](Self::evaluate), honoring support overrides for the subject
+    pub fn evaluate_with_overrides(
+        &self,
+        request: &ExperimentRequest,
+        overrides: &Overrides,
+    ) -> Result<ExperimentResponse> {
+        self.evaluate_traced(request, overrides, false, &self.layer_manager.snapshot())
+    }
+
+    /// Same as [`evaluate_with_overrides`](Self::evaluate_with_overrides), recording
+    /// per-layer outcomes in each result's `trace` when `trace` is set.
+    /// Evaluates against `snapshot`, which the caller loaded from
+    /// [`Self::layer_manager`] and can keep using for what follows (e.g. exposure events).
+    ///
+    /// With coalescing on, waiting for an identical evaluation in flight blocks
+    /// the calling thread; async callers use [`Self::evaluate_traced_async`].
+    /// Subjects whose admission to a capped variant isn't decided yet get
+    /// control, and are admitted in the background on the current tokio
+    /// runtime for later requests (without a runtime they stay in control).
+    pub fn evaluate_traced(
+        &self,
+        request: &ExperimentRequest,
+        overrides: &Overrides,
+        trace: bool,
+        snapshot: &Snapshot,
+    ) -> Result<ExperimentResponse> {
+        let epoch = snapshot.epoch();
+        let gate = CapGate::new(&self.cap_tracker);
+        let response = match contain_panics(request, epoch, || {
+            self.evaluate_leading(request, overrides, trace, snapshot, &gate)
+        })? {
+            Evaluation::Done(response) => response,
+            Evaluation::Following { mut results, follows } => {
+                let failed = collect_followed(&mut results, follows.into_iter().map(|(s, f)| (s, f.wait())));
+                contain_panics(request, epoch, || {
+                    self.evaluate_failed(request, failed, snapshot, &gate, results)
+                })?
+            }
+        };
+        self.admit_in_background(gate.into_pending());
+        Ok(response)
+    }
+
+    /// Same as [`evaluate_traced`](Self::evaluate_traced), awaiting identical
+    /// evaluations in flight instead of blocking, so a herd of followers
+    /// doesn't park runtime worker threads. Subjects bucketed into a capped
+    /// variant for the first time are admitted (awaiting the KV store) and
+    /// the request is evaluated again with their decisions.
+    pub async fn evaluate_traced_async(
+        &self,
+        request: &ExperimentRequest,
+        overrides: &Overrides,
+        trace: bool,
+        snapshot: &Snapshot,
+    ) -> Result<ExperimentResponse> {
+        let gate = CapGate::new(&self.cap_tracker);
+        let response = self.evaluate_pass_async(request, overrides, trace, snapshot, &gate).await?;
+        let pending = gate.into_pending();
+        if pending.is_empty() {
+            return Ok(response);
+        }
+
+        self.cap_tracker.admit_all(pending).await;
+        // Decisions the store failed to make still serve control
+        let gate = CapGate::new(&self.cap_tracker);
+        self.evaluate_pass_async(request, overrides, trace, snapshot, &gate).await
+    }
+
+    async fn evaluate_pass_async(
+        &self,
+        request: &ExperimentRequest,
+        overrides: &Overrides,
+        trace: bool,
+        snapshot: &Snapshot,
+        gate: &CapGate<'_>,
+    ) -> Result<ExperimentResponse> {
+        let epoch = snapshot.epoch();
+        let (mut results, follows) = match contain_panics(request, epoch, || {
+            self.evaluate_leading(request, overrides, trace, snapshot, gate)
+        })? {
+            Evaluation::Done(response) => return Ok(response),
+            Evaluation::Following { results, follows } => (results, follows),
+        };
+        let mut followed = Vec::with_capacity(follows.len());
+        for (service, flight) in follows {
+            let result = flight.wait_async().await;
+            followed.push((service, result));
+        }
+        let failed = collect_followed(&mut results, followed);
+        contain_panics(request, epoch, || self.evaluate_failed(request, failed, snapshot, gate, results))
+    }
+
+    /// Admit what a synchronous evaluation couldn't wait for
+    fn admit_in_background(&self, pending: Vec<CapCandidate>) {
+        if pending.is_empty() {
+            return;
+        }
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                let tracker = self.cap_tracker.clone();
+                runtime.spawn(async move { tracker.admit_all(pending).await });
+            }
+            Err(_) => tracing::debug!("No tokio runtime to admit {} capped subjects on", pending.len()),
+        }
+    }
+
+    /// Everything this request can compute without waiting: uncached results
+    /// and the evaluations it leads. Identical evaluations in flight elsewhere
+    /// are returned to be waited for.
+    fn evaluate_leading(
+        &self,
+        request: &ExperimentRequest,
+        overrides: &Overrides,
+        trace: bool,
+        snapshot: &Snapshot,
+        gate: &CapGate<'_>,
+    ) -> Result<Evaluation> {
+        let field_types = self.field_types.read();
+        // Traces, diagnostics and pinned variants are specific to this request
+        let shared = !trace && !request.diagnostics && overrides.is_empty();
+        if !shared || (self.result_cache.is_none() && self.coalescer.is_none()) {
+            return merge_layers_batch_traced(
+                request,
+                overrides,
+                snapshot,
+                &field_types,
+                Some(gate),
+                trace,
+            )
+            .map(Evaluation::Done);
+        }
+
+        let epoch = snapshot.epoch();
+        let mut results = HashMap::new();
+        let mut missing = Vec::new();
+        for service in &request.services {
+            match self.result_cache.as_ref().and_then(|cache| cache.get(service, request, epoch)) {
+                Some(result) => {
+                    results.insert(service.clone(), result);
+                }
+                None => missing.push(service.clone()),
+            }
+        }
+
+        let mut leads = Vec::new();
+        let mut follows = Vec::new();
+        if let Some(coalescer) = &self.coalescer {
+            for service in std::mem::take(&mut missing) {
+                match coalescer.join(&service, request, epoch) {
+                    Role::Lead(lead) => {
+                        leads.push(lead);
+                        missing.push(service);
+                    }
+                    Role::Follow(flight) => follows.push((service, flight)),
+                }
+            }
+        }
+        // Publish what this request leads before waiting on others, so two
+        // requests following each other can't deadlock. On error the leads
+        // are dropped and their followers evaluate on their own.
+        self.evaluate_uncached(request, missing, snapshot, &field_types, gate, &mut results)?;
+        // Results with undecided caps serve control for now: followers evaluate on their own
+        let settled = gate.is_settled();
+        for lead in leads {
+            let result = results.get(lead.service()).filter(|_| settled);
+            lead.finish(result);
+        }
+        Ok(Evaluation::Following { results, follows })
+    }
+
+    /// Evaluate the services whose leader failed and assemble the response
+    fn evaluate_failed(
+        &self,
+        request: &ExperimentRequest,
+        failed: Vec<String>,
+        snapshot: &Snapshot,
+        gate: &CapGate<'_>,
+        mut results: HashMap<String, ServiceResult>,
+    ) -> Result<ExperimentResponse> {
+        let field_types = self.field_types.read();
+        self.evaluate_uncached(request, failed, snapshot, &field_types, gate, &mut results)?;
+        Ok(ExperimentResponse { results })
+    }
+
+    /// Evaluate `services` for the request's subject, filling the result cache
+    fn evaluate_uncached(
+        &self,
+        request: &ExperimentRequest,
+        services: Vec<String>,
+        snapshot: &Snapshot,
+        field_types: &HashMap<String, FieldType>,
+        gate: &CapGate<'_>,
+        results: &mut HashMap<String, ServiceResult>,
+    ) -> Result<()> {
+        if services.is_empty() {
+            return Ok(());
+        }
+        let uncached = ExperimentRequest {
+            services,
+            context: request.context.clone(),
+            layers: request.layers.clone(),
+            diagnostics: false,
+        };
+        let response = merge_layers_batch_traced(
+            &uncached,
+            &Overrides::new(),
+            snapshot,
+            field_types,
+            Some(gate),
+            false,
+        )?;
+        let settled = gate.is_settled();
+        for (service, result) in response.results {
+            if let Some(cache) = self.result_cache.as_ref().filter(|_| settled) {
+                cache.insert(&service, request, snapshot.epoch(), &result);
+            }
+            results.insert(service, result);
+        }
+        Ok(())
+    }
+
+    /// Assignment distribution over a synthetic population (CPU-bound for large populations)
+    pub fn simulate(&self, request: &SimulateRequest) -> Result<SimulateResponse> {
+        let field_types = self.field_types();
+        simulate(request, &self.layer_manager.snapshot(), &field_types)
+    }
+
+    /// Base context and each variation, against one snapshot
+    pub fn what_if(&self, request: &WhatIfRequest) -> Result<WhatIfResponse> {
+        let field_types = self.field_types.read();
+        what_if(request, &self.layer_manager.snapshot(), &field_types)
+    }
+
+    pub fn layer_manager(&self) -> &Arc<LayerManager> {
+        &self.layer_manager
+    }
+
+    pub fn cap_tracker(&self) -> &CapTracker {
+        &self.cap_tracker
+    }
+
+    /// Catalog of the live snapshot
+    pub fn catalog(&self) -> Arc<ExperimentCatalog> {
+        self.layer_manager.snapshot().catalog().clone()
+    }
+
+    pub fn field_types(&self) -> HashMap<String, FieldType> {
+        self.field_types.read().clone()
+    }
+
+    /// Replace the field types. Semver-typed fields are declared to the layer
+    /// manager, which checks config applied from now on against them;
+    /// returns the live rules whose values aren't valid versions for them
+    /// (see [`crate::rule::semver`]), which keep serving and fail at evaluation.
+    pub fn set_field_types(&self, field_types: HashMap<String, FieldType>) -> Vec<String> {
+        let violations = self.layer_manager.set_semver_fields(semver_fields(&field_types));
+        *self.field_types.write() = field_types;
+        // Field types change rule results without a new snapshot epoch
+        if let Some(cache) = &self.result_cache {
+            cache.clear();
+        }
+        violations
+    }
+}
+
+fn semver_fields(field_types: &HashMap<String, FieldType>) -> HashSet<String> {
+    field_types
+        .iter()
+        .filter(|(_, field_type)| **field_type == FieldType::SemVer)
+        .map(|(field, _)| field.clone())
+        .collect()
+}
+
+/// Result of the part of an evaluation that doesn't wait on other requests
+enum Evaluation {
+    Done(ExperimentResponse),
+    Following {
+        results: HashMap<String, ServiceResult>,
+        follows: Vec<(String, Arc<Flight>)>,
+    },
+}
+
+/// Add what followed evaluations produced; returns the services whose leader failed
+fn collect_followed(
+    results: &mut HashMap<String, ServiceResult>,
+    followed: impl IntoIterator<Item = (String, Option<ServiceResult>)>,
+) -> Vec<String> {
+    let mut failed = Vec::new();
+    for (service, result) in followed {
+        match result {
+            Some(result) => {
+                results.insert(service, result);
+            }
+            None => failed.push(service),
+        }
+    }
+    failed
+}
+
+/// Run one request's evaluation so that a panic in it (a bug in rule
+/// evaluation or merging) fails only that request: it is counted, logged with
+/// what identifies the request, and returned as an error instead of unwinding
+/// into the worker. Context values are left out of the diagnostics.
+fn contain_panics<T>(request: &ExperimentRequest, epoch: u64, evaluate: impl FnOnce() -> Result<T>) -> Result<T> {
+    std::panic::catch_unwind(AssertUnwindSafe(evaluate)).unwrap_or_else(|payload| {
+        metrics::EVALUATION_PANICS.inc();
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "non-string panic payload".to_string());
+        let mut fields: Vec<&str> = request.context.keys().map(String::as_str).collect();
+        fields.sort_unstable();
+        let diagnostics = format!(
+            "{} (services {:?}, layers {:?}, context fields {:?}, config epoch {})",
+            message, request.services, request.layers, fields, epoch
+        );
+        tracing::error!("Evaluation panicked: {}", diagnostics);
+        Err(ExperimentError::EvaluationPanicked(diagnostics))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{ExperimentDef, VariantDef};
+    use crate::layer::BucketRange;
+    use crate::rule::{Node, Op};
+    use serde_json::json;
+
+    fn layer(layer_id: &str, ranges: Vec<BucketRange>) -> Layer {
+        Layer {
+            layer_id: layer_id.to_string(),
+            version: "v1".to_string(),
+            priority: 1,
+            hash_key: "user_id".into(),
+            salt: None,
+            bucket_size: None,
+            expires_at: None,
+            rule: None,
+            services: vec![],
+            ranges,
+            enabled: true,
+            aa_test: false,
+        }
+    }
+
+    #[test]
+    fn test_builder_in_memory_snapshot() {
+        let catalog = ExperimentCatalog::from_experiments(
+            vec![ExperimentDef {
+                eid: 100,
+                service: "svc".to_string(),
+                expires_at: None,
+                state: Default::default(),
+                caps: None,
+                rule: Some(Node::Field {
+                    field: "age".to_string(),
+                    op: Op::Gte,
+                    values: vec![json!(18)],
+                    collation: None,
+                }),
+                exposure_sampling: None,
+                variants: vec![VariantDef {
+                    vid: 1001,
+                    params: json!({"model": "v2"}),
+                    dark_params: None,
+                }],
+            }],
+            PathBuf::new(),
+        )
+        .unwrap();
+
+        let evaluator = Evaluator::builder()
+            .with_catalog(catalog)
+            .with_layers([layer(
+                "l1",
+                vec![BucketRange {
+                    start: 0,
+                    end: 10000,
+                    vid: 1001,
+                    label: None,
+                }],
+            )])
+            .with_field_types(HashMap::from([("age".to_string(), FieldType::Int)]))
+            .build()
+            .unwrap();
+
+        let request = |age: i64| ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([
+                ("user_id".to_string(), json!("u1")),
+                ("age".to_string(), json!(age)),
+            ]),
+            layers: vec![],
+            diagnostics: false,
+        };
+        // Rules only evaluate fields with a configured type
+        let response = evaluator.evaluate(&request(20)).unwrap();
+        assert_eq!(response.results["svc"].parameters, json!({"model": "v2"}));
+        assert!(evaluator.evaluate(&request(12)).unwrap().results["svc"].vids.is_empty());
+
+        // Invalid in-memory layers are rejected like files would be
+        let invalid = Evaluator::builder()
+            .with_layers([layer(
+                "bad",
+                vec![BucketRange {
+                    start: 10,
+                    end: 5,
+                    vid: 1001,
+                    label: None,
+                }],
+            )])
+            .build();
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_semver_fields_are_per_evaluator() {
+        let mut versioned = layer("l1", vec![]);
+        versioned.rule = Some(Node::Field {
+            field: "app_version".to_string(),
+            op: Op::Gte,
+            values: vec![json!("2.1.x")],
+            collation: None,
+        });
+        let typed = Evaluator::builder().with_layers([versioned.clone()]).build().unwrap();
+        let untyped = Evaluator::builder().build().unwrap();
+
+        // The live layer is reported, not unloaded
+        let violations = typed.set_field_types(HashMap::from([("app_version".to_string(), FieldType::SemVer)]));
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].contains("l1") && violations[0].contains("'2.1.x'"), "{:?}", violations);
+        assert!(typed.layer_manager().get_layer("l1").is_some());
+
+        // Only the evaluator that was told rejects the value from now on
+        let catalog = typed.catalog();
+        let path = typed.layer_manager().layer_path("l1");
+        assert!(typed.layer_manager().upsert_layer(versioned.clone(), &path, &catalog).is_err());
+        assert!(!untyped.layer_manager().semver().is_declared("app_version"));
+        assert!(untyped.layer_manager().upsert_layer(versioned, &path, &catalog).is_ok());
+    }
+
+    #[test]
+    fn test_result_cache_invalidated_on_publish() {
+        let catalog = ExperimentCatalog::from_experiments(
+            vec![ExperimentDef {
+                eid: 100,
+                service: "svc".to_string(),
+                expires_at: None,
+                state: Default::default(),
+                caps: None,
+                rule: None,
+                exposure_sampling: None,
+                variants: vec![
+                    VariantDef {
+                        vid: 1001,
+                        params: json!({"model": "a"}),
+                        dark_params: None,
+                    },
+                    VariantDef {
+                        vid: 1002,
+                        params: json!({"model": "b"}),
+                        dark_params: None,
+                    },
+                ],
+            }],
+            PathBuf::new(),
+        )
+        .unwrap();
+        let full = |vid| {
+            vec![BucketRange {
+                start: 0,
+                end: 10000,
+                vid,
+                label: None,
+            }]
+        };
+
+        let evaluator = Evaluator::builder()
+            .with_catalog(catalog)
+            .with_layers([layer("l1", full(1001))])
+            .with_result_cache(Duration::from_secs(60), 100)
+            .build()
+            .unwrap();
+        let request = ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([("user_id".to_string(), json!("u1"))]),
+            layers: vec![],
+            diagnostics: false,
+        };
+
+        assert_eq!(evaluator.evaluate(&request).unwrap().results["svc"].vids, vec![1001]);
+        assert_eq!(evaluator.evaluate(&request).unwrap().results["svc"].vids, vec![1001]);
+
+        // A new snapshot must not serve results computed against the old one
+        let manager = evaluator.layer_manager();
+        manager
+            .upsert_layer(layer("l1", full(1002)), &manager.layer_path("l1"), &evaluator.catalog())
+            .unwrap();
+        assert_eq!(evaluator.evaluate(&request).unwrap().results["svc"].vids, vec![1002]);
+    }
+
+    #[test]
+    fn test_panic_in_evaluation_fails_only_that_request() {
+        let request = ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([("user_id".to_string(), json!("secret-user"))]),
+            layers: vec![],
+            diagnostics: false,
+        };
+        let before = metrics::EVALUATION_PANICS.get();
+
+        let err = contain_panics::<()>(&request, 7, || panic!("index out of bounds")).unwrap_err();
+        let message = err.to_string();
+        assert!(matches!(err, ExperimentError::EvaluationPanicked(_)));
+        assert!(message.contains("index out of bounds") && message.contains("config epoch 7"));
+        assert!(message.contains("user_id") && !message.contains("secret-user"));
+        assert!(metrics::EVALUATION_PANICS.get() > before);
+
+        assert_eq!(contain_panics(&request, 7, || Ok(1)).unwrap(), 1);
+    }
+}
@@ -0,0 +1,14 @@
+//! Library entry point for evaluation.
+//!
+//! [`Evaluator`] owns a config snapshot (catalog, layers, field types) and
+//! evaluates requests against it. The HTTP server, benches and embedding
+//! applications all build one through [`Evaluator::builder`] instead of
+//! wiring `LayerManager` / `ExperimentCatalog` / field types by hand.
+
+mod cache;
+mod coalesce;
+mod evaluator;
+
+pub use cache::ResultCache;
+pub use coalesce::Coalescer;
+pub use evaluator::{Evaluator, EvaluatorBuilder};
@@ -1,10 +1,85 @@
+use crate::config::migrate::GroupMapping;
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+/// Kind of config resource an error refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceKind {
+    Layer,
+    Experiment,
+}
+
+/// Identifies the resource and source location an error came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorContext {
+    pub kind: ResourceKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+}
+
+impl ErrorContext {
+    pub fn new(kind: ResourceKind) -> Self {
+        Self {
+            kind,
+            id: None,
+            path: None,
+            line: None,
+            column: None,
+        }
+    }
+
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn with_path(mut self, path: &Path) -> Self {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            ResourceKind::Layer => "layer",
+            ResourceKind::Experiment => "experiment",
+        };
+        write!(f, "{}", kind)?;
+        if let Some(id) = &self.id {
+            write!(f, " '{}'", id)?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, " ({}", path.display())?;
+            if let Some(line) = self.line {
+                write!(f, ":{}", line)?;
+                if let Some(column) = self.column {
+                    write!(f, ":{}", column)?;
+                }
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum ExperimentError {
     #[error("Layer not found: {0}")]
     LayerNotFound(String),
 
+    #[error("Experiment not found: {0}")]
+    ExperimentNotFound(i64),
+
     #[error("Invalid layer version: {0}")]
     InvalidVersion(String),
 
@@ -26,13 +101,53 @@ pub enum ExperimentError {
     #[error("Invalid parameter format: {0}")]
     InvalidParameter(String),
 
+    #[error("Unsupported schema: {0}")]
+    SchemaVersion(String),
+
+    #[error("Deprecated config (strict mode): {0}")]
+    DeprecatedConfig(String),
+
+    /// Inline `groups` in a layer; carries what `migrate-config` would register
+    #[error(
+        "Layer embeds legacy inline `groups`, whose params, rules and services are not served; \
+         move them into the catalog with `migrate-config --layers-dir <dir> --out-dir <dir> \
+         --experiments-dir <catalog>`{}",
+        describe_group_plan(.0)
+    )]
+    InlineGroups(Vec<GroupMapping>),
+
+    #[error("Service budget exceeded: {0}")]
+    BudgetExceeded(String),
+
+    #[error("Config frozen: {0}")]
+    ConfigFrozen(String),
+
     #[error("Invalid rule: {0}")]
     InvalidRule(String),
 
+    /// A bug hit while evaluating one request, contained by the evaluator
+    #[error("Evaluation panicked: {0}")]
+    EvaluationPanicked(String),
+
     #[error("Rule evaluation failed: {0}")]
     #[allow(dead_code)]
     RuleEvaluationFailed(String),
 
+    #[error("Context not allowed: {0}")]
+    ContextNotAllowed(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Store error: {0}")]
+    Store(String),
+
+    #[error("Remote plane error: {0}")]
+    RemotePlane(String),
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -41,6 +156,78 @@ pub enum ExperimentError {
 
     #[error("YAML error: {0}")]
     Yaml(#[from] serde_yaml::Error),
+
+    #[error("{context}: {source}")]
+    Context {
+        context: ErrorContext,
+        source: Box<ExperimentError>,
+    },
+}
+
+/// `: group 'a' → eid 100000 / vid 100001 (svc), ...`, empty without a plan
+fn describe_group_plan(plan: &[GroupMapping]) -> String {
+    if plan.is_empty() {
+        return String::new();
+    }
+    let groups: Vec<String> = plan
+        .iter()
+        .map(|g| format!("group '{}' → eid {} / vid {} ({})", g.group, g.eid, g.vid, g.service))
+        .collect();
+    format!("; it would generate {}", groups.join(", "))
+}
+
+impl ExperimentError {
+    /// Attach resource context. Line/column are taken from parse errors when available;
+    /// fields already set by an inner context are kept.
+    pub fn with_context(self, mut context: ErrorContext) -> Self {
+        match self {
+            ExperimentError::Context {
+                context: inner,
+                source,
+            } => {
+                context.id = inner.id.or(context.id);
+                context.path = inner.path.or(context.path);
+                context.line = inner.line.or(context.line);
+                context.column = inner.column.or(context.column);
+                ExperimentError::Context { context, source }
+            }
+            other => {
+                if let Some((line, column)) = other.location() {
+                    context.line = Some(line);
+                    context.column = Some(column);
+                }
+                ExperimentError::Context {
+                    context,
+                    source: Box::new(other),
+                }
+            }
+        }
+    }
+
+    /// Resource context, if attached
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            ExperimentError::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The underlying error with any context stripped
+    pub fn inner(&self) -> &ExperimentError {
+        match self {
+            ExperimentError::Context { source, .. } => source.inner(),
+            other => other,
+        }
+    }
+
+    /// Line/column for parse errors (1-based)
+    fn location(&self) -> Option<(usize, usize)> {
+        match self {
+            ExperimentError::Json(e) if e.line() > 0 => Some((e.line(), e.column())),
+            ExperimentError::Yaml(e) => e.location().map(|l| (l.line(), l.column())),
+            _ => None,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, ExperimentError>;
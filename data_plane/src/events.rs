@@ -0,0 +1,179 @@
+//! Internal engine events.
+//!
+//! The layer manager publishes an [`EngineEvent`] whenever the live config
+//! changes; subsystems that react to changes (the applied change log, future
+//! webhooks or dashboard pushes) subscribe instead of being called directly
+//! from the apply path. Publishing never blocks: a subscriber that falls more
+//! than the bus capacity behind skips the oldest events and logs how many.
+
+use crate::applied::AppliedEntry;
+use crate::layer::ConfigChange;
+use crate::metrics;
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Events buffered per subscriber before the slowest starts losing them
+pub const BUS_CAPACITY: usize = 1024;
+
+/// Why a layer stopped serving
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisableReason {
+    /// `enabled: false` or removed from the config
+    Config,
+    Emergency,
+    Expired,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EngineEvent {
+    /// A snapshot changing layers, experiments or emergency overrides went live
+    SnapshotApplied {
+        change: ConfigChange,
+        /// Per-resource changes, only computed while someone is subscribed
+        resources: Vec<AppliedEntry>,
+    },
+    /// A layer that served in the previous snapshot no longer does
+    LayerDisabled {
+        epoch: u64,
+        layer_id: String,
+        reason: DisableReason,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<EngineEvent>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(BUS_CAPACITY)
+    }
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    /// Whether anyone listens; lets publishers skip building costly events
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub fn publish(&self, event: EngineEvent) {
+        // Err only means nobody is subscribed
+        let _ = self.sender.send(Arc::new(event));
+    }
+
+    /// Receive every event published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<EngineEvent>> {
+        self.sender.subscribe()
+    }
+}
+
+/// Feed events to `handler` until the bus is dropped. `name` labels lag reports.
+pub async fn consume(
+    name: &'static str,
+    mut receiver: broadcast::Receiver<Arc<EngineEvent>>,
+    mut handler: impl FnMut(&EngineEvent),
+) {
+    loop {
+        match receiver.recv().await {
+            Ok(event) => handler(&event),
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!("Event subscriber '{}' fell behind, skipped {} events", name, skipped);
+                metrics::EVENTS_DROPPED.with_label_values(&[name]).inc_by(skipped);
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
+    use crate::emergency::EmergencyOverrides;
+    use crate::engine::Evaluator;
+    use crate::layer::{BucketRange, Layer};
+    use serde_json::json;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_snapshot_and_disable_events() {
+        let catalog = ExperimentCatalog::from_experiments(
+            vec![ExperimentDef {
+                eid: 100,
+                service: "svc".to_string(),
+                expires_at: None,
+                state: Default::default(),
+                caps: None,
+                rule: None,
+                exposure_sampling: None,
+                variants: vec![VariantDef {
+                    vid: 101,
+                    params: json!({}),
+                    dark_params: None,
+                }],
+            }],
+            PathBuf::new(),
+        )
+        .unwrap();
+        let layer = |layer_id: &str, enabled: bool| Layer {
+            layer_id: layer_id.to_string(),
+            version: "v1".to_string(),
+            priority: 1,
+            hash_key: "user_id".into(),
+            salt: None,
+            bucket_size: None,
+            expires_at: None,
+            rule: None,
+            services: vec![],
+            ranges: vec![BucketRange {
+                start: 0,
+                end: 10000,
+                vid: 101,
+                label: None,
+            }],
+            enabled,
+            aa_test: false,
+        };
+        let evaluator = Evaluator::builder()
+            .with_catalog(catalog)
+            .with_layers([layer("a", true), layer("b", true)])
+            .build()
+            .unwrap();
+        let manager = evaluator.layer_manager();
+        let mut events = manager.events().subscribe();
+
+        manager.set_emergency_overrides(EmergencyOverrides {
+            layers: ["a".to_string()].into(),
+            ..Default::default()
+        });
+        assert!(matches!(*events.try_recv().unwrap(), EngineEvent::SnapshotApplied { ref change, .. } if change.emergency_overrides));
+        assert!(matches!(
+            *events.try_recv().unwrap(),
+            EngineEvent::LayerDisabled { ref layer_id, reason: DisableReason::Emergency, .. } if layer_id == "a"
+        ));
+
+        let catalog = evaluator.catalog();
+        manager.upsert_layer(layer("b", false), Path::new("b.json"), &catalog).unwrap();
+        match &*events.try_recv().unwrap() {
+            EngineEvent::SnapshotApplied { resources, .. } => {
+                assert_eq!(resources.len(), 1);
+                assert_eq!(resources[0].id, "b");
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(matches!(
+            *events.try_recv().unwrap(),
+            EngineEvent::LayerDisabled { ref layer_id, reason: DisableReason::Config, .. } if layer_id == "b"
+        ));
+        assert!(events.try_recv().is_err());
+    }
+}
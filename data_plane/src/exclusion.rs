@@ -0,0 +1,273 @@
+//! "Do not experiment" population: subjects that must always get defaults.
+//!
+//! Contracts with some customers rule out experimenting on their users. The
+//! exclusion file lists them globally or per service, either explicitly by
+//! context value (e.g. enterprise `account_id`s) or as a deterministic
+//! percentage of a hashed field. Excluded subjects skip layer evaluation
+//! entirely, support overrides included, and get empty parameters with
+//! `excluded: true`.
+//!
+//! ```yaml
+//! global:
+//!   values: {account_id: [ent_1, ent_2]}
+//! services:
+//!   ranking:
+//!     percentage: 5        # of user_id hashes, 0-100
+//!     field: user_id
+//! ```
+//!
+//! Like emergency overrides, the file is re-read every few seconds; a missing
+//! file excludes nothing and a file that fails to parse keeps the previous set.
+
+use crate::config::migrate;
+use crate::error::{ExperimentError, Result};
+use crate::hash::hash_to_bucket_in;
+use crate::layer::LayerManager;
+use crate::metrics;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Percentage buckets: 0.01% resolution
+const BUCKETS: u32 = 10000;
+
+/// Salt for percentage hashing unless the rule sets its own, so the excluded
+/// slice is independent of every layer's bucketing
+const DEFAULT_SALT: &str = "exclusion";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExclusionRule {
+    /// Context field -> values excluded outright
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub values: BTreeMap<String, BTreeSet<String>>,
+
+    /// Share (0-100) of `field` hashes excluded
+    #[serde(default)]
+    pub percentage: f64,
+
+    /// Field hashed for `percentage`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
+}
+
+impl ExclusionRule {
+    fn validate(&self, scope: &str) -> Result<()> {
+        if !(0.0..=100.0).contains(&self.percentage) {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "Exclusion percentage for {} must be within 0-100, got {}",
+                scope, self.percentage
+            )));
+        }
+        if self.percentage > 0.0 && self.field.is_none() {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "Exclusion percentage for {} needs a `field` to hash",
+                scope
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn excludes(&self, context: &HashMap<String, Value>) -> bool {
+        let listed = self.values.iter().any(|(field, values)| {
+            context
+                .get(field)
+                .and_then(context_string)
+                .is_some_and(|value| values.contains(value.as_ref()))
+        });
+        listed || self.percentage_excludes(context)
+    }
+
+    fn percentage_excludes(&self, context: &HashMap<String, Value>) -> bool {
+        if self.percentage <= 0.0 {
+            return false;
+        }
+        let Some(value) = self.field.as_ref().and_then(|f| context.get(f)).and_then(context_string) else {
+            return false;
+        };
+        let salt = self.salt.as_deref().unwrap_or(DEFAULT_SALT);
+        let bucket = hash_to_bucket_in(&value, salt, BUCKETS);
+        (bucket as f64) < self.percentage * (BUCKETS as f64) / 100.0
+    }
+}
+
+/// Strings and numbers compare by their text; other values never match
+fn context_string(value: &Value) -> Option<std::borrow::Cow<'_, str>> {
+    match value {
+        Value::String(s) => Some(s.as_str().into()),
+        Value::Number(n) => Some(n.to_string().into()),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Exclusions {
+    /// Applies to every service
+    #[serde(default)]
+    pub global: ExclusionRule,
+
+    /// Applies on top of `global` for one service
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub services: BTreeMap<String, ExclusionRule>,
+}
+
+impl Exclusions {
+    /// Read the exclusion file; a missing file excludes nothing
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)?;
+        let exclusions: Self = serde_json::from_value(migrate::parse_document(&content)?)?;
+        exclusions.global.validate("all services")?;
+        for (service, rule) in &exclusions.services {
+            rule.validate(&format!("service '{}'", service))?;
+        }
+        Ok(exclusions)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether the subject described by `context` must not be experimented on in `service`
+    pub fn excludes(&self, service: &str, context: &HashMap<String, Value>) -> bool {
+        let excluded = self.global.excludes(context)
+            || self.services.get(service).is_some_and(|rule| rule.excludes(context));
+        if excluded {
+            metrics::EXCLUDED_EVALUATIONS.inc();
+        }
+        excluded
+    }
+}
+
+/// Re-read the file and apply it if it changed
+pub fn apply_file(path: &Path, manager: &LayerManager) {
+    match Exclusions::load(path) {
+        Ok(exclusions) if exclusions == **manager.snapshot().exclusions() => {}
+        Ok(exclusions) => {
+            tracing::info!(
+                "Exclusions updated: global {}, {} services",
+                if exclusions.global == ExclusionRule::default() { "none" } else { "set" },
+                exclusions.services.len()
+            );
+            manager.set_exclusions(exclusions);
+        }
+        Err(e) => {
+            tracing::error!("Failed to read exclusions {:?}, keeping previous set: {}", path, e);
+            metrics::EXCLUSION_FILE_ERRORS.inc();
+        }
+    }
+}
+
+/// Re-check the exclusion file every `interval`
+pub async fn watch_file(path: std::path::PathBuf, manager: Arc<LayerManager>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        apply_file(&path, &manager);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
+    use crate::engine::Evaluator;
+    use crate::layer::{BucketRange, Layer};
+    use crate::merge::ExperimentRequest;
+    use serde_json::json;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_excluded_subjects_get_defaults() {
+        let catalog = ExperimentCatalog::from_experiments(
+            vec![ExperimentDef {
+                eid: 100,
+                service: "svc".to_string(),
+                expires_at: None,
+                state: Default::default(),
+                caps: None,
+                rule: None,
+                exposure_sampling: None,
+                variants: vec![VariantDef {
+                    vid: 101,
+                    params: json!({"color": "red"}),
+                    dark_params: None,
+                }],
+            }],
+            PathBuf::new(),
+        )
+        .unwrap();
+        let evaluator = Evaluator::builder()
+            .with_catalog(catalog)
+            .with_layers([Layer {
+                layer_id: "l1".to_string(),
+                version: "v1".to_string(),
+                priority: 1,
+                hash_key: "user_id".into(),
+                salt: None,
+                bucket_size: None,
+                expires_at: None,
+                rule: None,
+                services: vec![],
+                ranges: vec![BucketRange {
+                    start: 0,
+                    end: 10000,
+                    vid: 101,
+                    label: None,
+                }],
+                enabled: true,
+                aa_test: false,
+            }])
+            .build()
+            .unwrap();
+        let manager = evaluator.layer_manager();
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("exclusions.yaml");
+        std::fs::write(
+            &path,
+            "global: {values: {account_id: [ent_1]}}\nservices: {svc: {percentage: 50, field: user_id}}\n",
+        )
+        .unwrap();
+        apply_file(&path, manager);
+
+        let evaluate = |user: &str, account: &str| {
+            let request = ExperimentRequest {
+                services: vec!["svc".to_string()],
+                context: HashMap::from([
+                    ("user_id".to_string(), json!(user)),
+                    ("account_id".to_string(), json!(account)),
+                ]),
+                layers: vec![],
+                diagnostics: false,
+            };
+            evaluator.evaluate(&request).unwrap().results.remove("svc").unwrap()
+        };
+
+        let users: Vec<String> = (0..1000).map(|i| format!("u{}", i)).collect();
+        let excluded = users.iter().filter(|u| evaluate(u, "acme").excluded).count();
+        assert!((400..600).contains(&excluded), "excluded {} of 1000", excluded);
+
+        let kept = users.iter().find(|u| !evaluate(u, "acme").excluded).unwrap();
+        assert_eq!(evaluate(kept, "acme").parameters, json!({"color": "red"}));
+        let result = evaluate(kept, "ent_1");
+        assert!(result.excluded);
+        assert_eq!(result.parameters, json!({}));
+        assert!(result.vids.is_empty());
+
+        // An invalid file keeps the previous exclusions
+        std::fs::write(&path, "global: {percentage: 150, field: user_id}\n").unwrap();
+        apply_file(&path, manager);
+        assert!(evaluate(kept, "ent_1").excluded);
+    }
+}
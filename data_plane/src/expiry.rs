@@ -0,0 +1,80 @@
+//! Resource expiry: layers and experiments may carry `expires_at` (unix
+//! seconds) set by the control plane. Once it passes the resource stops
+//! serving even if the control plane never pushes again, so a wedged control
+//! plane can't leave a stale experiment running forever.
+//!
+//! Expiry is evaluated on every publish and re-checked periodically (see
+//! [`sweep_periodically`]); expired resources are reported at
+//! `/diagnostics/expired` and by the `experiment_expired_resources` gauge.
+
+use crate::catalog::ExperimentCatalog;
+use crate::layer::{Layer, LayerManager};
+use crate::metrics;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Resources whose `expires_at` has passed, with the time they expired
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Expired {
+    /// layer_id -> expires_at
+    pub layers: BTreeMap<String, u64>,
+    /// eid -> expires_at
+    pub experiments: BTreeMap<i64, u64>,
+}
+
+impl Expired {
+    /// Expired resources among `layers` and `catalog` at `now`
+    pub fn collect<'a>(layers: impl Iterator<Item = &'a Layer>, catalog: &ExperimentCatalog, now: u64) -> Self {
+        Self {
+            layers: layers
+                .filter_map(|layer| {
+                    let expires_at = layer.expires_at?;
+                    (expires_at <= now).then(|| (layer.layer_id.clone(), expires_at))
+                })
+                .collect(),
+            experiments: catalog.expired_experiments(now),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty() && self.experiments.is_empty()
+    }
+
+    /// Whether a hit on `eid` in `layer_id` must be dropped
+    pub fn disables(&self, layer_id: &str, eid: i64) -> bool {
+        self.layers.contains_key(layer_id) || self.experiments.contains_key(&eid)
+    }
+
+    /// Log and export a change of the expired set
+    pub(crate) fn report(&self, previous: &Expired) {
+        for (layer_id, expires_at) in &self.layers {
+            if !previous.layers.contains_key(layer_id) {
+                tracing::warn!("Layer {} expired at {}, no longer serving", layer_id, expires_at);
+            }
+        }
+        for (eid, expires_at) in &self.experiments {
+            if !previous.experiments.contains_key(eid) {
+                tracing::warn!("Experiment {} expired at {}, no longer serving", eid, expires_at);
+            }
+        }
+        metrics::EXPIRED_RESOURCES
+            .with_label_values(&["layer"])
+            .set(self.layers.len() as i64);
+        metrics::EXPIRED_RESOURCES
+            .with_label_values(&["experiment"])
+            .set(self.experiments.len() as i64);
+    }
+}
+
+/// Re-check expiry every `interval` so resources stop serving on time
+/// without waiting for the next config change
+pub async fn sweep_periodically(manager: Arc<LayerManager>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        manager.refresh_expiry();
+    }
+}
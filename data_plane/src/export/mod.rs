@@ -0,0 +1,365 @@
+//! Bulk assignment export for offline cohort analysis.
+//!
+//! Evaluates a list of subject keys (or a generated `{prefix}{n}` range)
+//! against one config snapshot and streams one row per assignment as CSV,
+//! JSON lines or (with the `parquet` feature) Parquet. Support overrides are
+//! not applied: the export describes what the config assigns, not what
+//! individual subjects were pinned to.
+
+#[cfg(feature = "parquet")]
+mod parquet;
+
+use crate::error::{ExperimentError, Result};
+use crate::layer::Snapshot;
+use crate::merge::{subject_assignments, Assignment};
+use crate::overrides::Overrides;
+use crate::rule::FieldType;
+use crate::sim::MAX_POPULATION;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Rows are sent in chunks of roughly this many bytes
+const CHUNK_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Jsonl,
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Jsonl => "application/x-ndjson",
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// Generated subjects `{prefix}{start}` .. `{prefix}{start + count - 1}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubjectRange {
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub start: u64,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportRequest {
+    pub services: Vec<String>,
+
+    /// Context shared by every subject (the hash key is filled in per subject)
+    #[serde(default)]
+    pub context: HashMap<String, Value>,
+
+    /// Explicit subject keys, exported before `range`
+    #[serde(default)]
+    pub subjects: Vec<String>,
+
+    #[serde(default)]
+    pub range: Option<SubjectRange>,
+
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+impl ExportRequest {
+    fn subject_count(&self) -> u64 {
+        self.subjects.len() as u64 + self.range.as_ref().map_or(0, |r| r.count)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.services.is_empty() {
+            return Err(ExperimentError::InvalidParameter(
+                "Export needs at least one service".to_string(),
+            ));
+        }
+        let count = self.subject_count();
+        if count == 0 {
+            return Err(ExperimentError::InvalidParameter(
+                "Export needs `subjects` or a non-empty `range`".to_string(),
+            ));
+        }
+        if count > MAX_POPULATION as u64 {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "Export of {} subjects exceeds limit {}",
+                count, MAX_POPULATION
+            )));
+        }
+        if let Some(range) = &self.range {
+            if range.start.checked_add(range.count).is_none() {
+                return Err(ExperimentError::InvalidParameter(
+                    "Subject range overflows".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn subjects(&self) -> impl Iterator<Item = String> + '_ {
+        let generated = self
+            .range
+            .iter()
+            .flat_map(|r| (r.start..r.start + r.count).map(move |n| format!("{}{}", r.prefix, n)));
+        self.subjects.iter().cloned().chain(generated)
+    }
+}
+
+#[derive(Serialize)]
+struct Row<'a> {
+    subject: &'a str,
+    service: &'a str,
+    #[serde(flatten)]
+    assignment: &'a Assignment,
+}
+
+const CSV_HEADER: &str = "subject,service,layer_id,eid,vid,label\n";
+
+/// Rows encoded into response body chunks in the requested format
+enum Encoder {
+    Csv(String),
+    Jsonl(String),
+    #[cfg(feature = "parquet")]
+    Parquet(Box<parquet::ParquetRows>),
+}
+
+impl Encoder {
+    fn new(format: ExportFormat) -> Result<Self> {
+        let buf = String::with_capacity(CHUNK_BYTES * 2);
+        Ok(match format {
+            ExportFormat::Csv => Encoder::Csv(buf + CSV_HEADER),
+            ExportFormat::Jsonl => Encoder::Jsonl(buf),
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => Encoder::Parquet(Box::new(parquet::ParquetRows::new().map_err(std::io::Error::other)?)),
+        })
+    }
+
+    fn push(&mut self, subject: &str, service: &str, assignment: &Assignment) -> Result<()> {
+        match self {
+            Encoder::Csv(out) => {
+                let _ = writeln!(
+                    out,
+                    "{},{},{},{},{},{}",
+                    csv_field(subject),
+                    csv_field(service),
+                    csv_field(&assignment.layer_id),
+                    assignment.eid,
+                    assignment.vid,
+                    csv_field(assignment.label.as_deref().unwrap_or(""))
+                );
+            }
+            Encoder::Jsonl(out) => {
+                let row = Row {
+                    subject,
+                    service,
+                    assignment,
+                };
+                out.push_str(&serde_json::to_string(&row)?);
+                out.push('\n');
+            }
+            #[cfg(feature = "parquet")]
+            Encoder::Parquet(rows) => rows.push(subject, service, assignment).map_err(std::io::Error::other)?,
+        }
+        Ok(())
+    }
+
+    /// The next chunk, once at least [`CHUNK_BYTES`] are encoded
+    fn ready(&mut self) -> Option<Bytes> {
+        match self {
+            Encoder::Csv(out) | Encoder::Jsonl(out) => (out.len() >= CHUNK_BYTES)
+                .then(|| Bytes::from(std::mem::replace(out, String::with_capacity(CHUNK_BYTES * 2)))),
+            #[cfg(feature = "parquet")]
+            Encoder::Parquet(rows) => rows.ready(),
+        }
+    }
+
+    /// Whatever is left, including any trailer the format needs
+    fn finish(self) -> Result<Bytes> {
+        match self {
+            Encoder::Csv(out) | Encoder::Jsonl(out) => Ok(Bytes::from(out)),
+            #[cfg(feature = "parquet")]
+            Encoder::Parquet(rows) => Ok(rows.finish().map_err(std::io::Error::other)?),
+        }
+    }
+}
+
+/// Quote a CSV field when it contains a delimiter, quote or line break
+fn csv_field(value: &str) -> std::borrow::Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
+
+/// Evaluate every subject against `snapshot` on a blocking thread, sending
+/// the encoded rows in chunks. Stops early when the receiver is dropped.
+pub fn spawn(
+    request: ExportRequest,
+    snapshot: Arc<Snapshot>,
+    field_types: HashMap<String, FieldType>,
+) -> Result<mpsc::Receiver<Bytes>> {
+    request.validate()?;
+    let mut encoder = Encoder::new(request.format)?;
+    let (tx, rx) = mpsc::channel(8);
+
+    tokio::task::spawn_blocking(move || {
+        let overrides = Overrides::new();
+
+        for subject in request.subjects() {
+            for service in &request.services {
+                let assignments = subject_assignments(
+                    service,
+                    &subject,
+                    request.context.clone(),
+                    &overrides,
+                    &snapshot,
+                    &field_types,
+                );
+                for assignment in &assignments {
+                    if let Err(e) = encoder.push(&subject, service, assignment) {
+                        // The response is already streaming: end it short
+                        tracing::error!("Assignment export failed at subject {}: {}", subject, e);
+                        return;
+                    }
+                }
+            }
+
+            if let Some(chunk) = encoder.ready() {
+                if tx.blocking_send(chunk).is_err() {
+                    tracing::debug!("Assignment export cancelled by client");
+                    return;
+                }
+            }
+        }
+
+        match encoder.finish() {
+            Ok(chunk) if !chunk.is_empty() => {
+                let _ = tx.blocking_send(chunk);
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!("Assignment export failed to finish: {}", e),
+        }
+    });
+
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::ExperimentCatalog;
+    use crate::layer::LayerManager;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    async fn manager(temp_dir: &TempDir) -> LayerManager {
+        let experiments_dir = temp_dir.path().join("experiments");
+        let layers_dir = temp_dir.path().join("layers");
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::write(
+            experiments_dir.join("100.json"),
+            r#"{"eid": 100, "service": "svc", "variants": [{"vid": 1001, "params": {}}]}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            layers_dir.join("l1.json"),
+            r#"{"layer_id": "l1", "version": "v1", "priority": 1, "hash_key": "user_id", "enabled": true,
+                "ranges": [{"start": 0, "end": 10000, "vid": 1001, "label": "a,b"}]}"#,
+        )
+        .unwrap();
+
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+        let manager = LayerManager::new(layers_dir);
+        manager.load_all_layers(&catalog).await.unwrap();
+        manager
+    }
+
+    #[tokio::test]
+    async fn test_export_streams_rows_for_every_subject() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = manager(&temp_dir).await;
+
+        let request: ExportRequest = serde_json::from_value(json!({
+            "services": ["svc"],
+            "subjects": ["x"],
+            "range": {"prefix": "u", "start": 10, "count": 3}
+        }))
+        .unwrap();
+        let mut rx = spawn(request, manager.snapshot(), HashMap::new()).unwrap();
+        let mut csv = String::new();
+        while let Some(chunk) = rx.recv().await {
+            csv.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER.trim_end());
+        assert_eq!(lines[1], "x,svc,l1,100,1001,\"a,b\"");
+        assert_eq!(lines.len(), 5);
+        assert!(lines[4].starts_with("u12,"));
+
+        let empty: ExportRequest = serde_json::from_value(json!({"services": ["svc"]})).unwrap();
+        assert!(empty.validate().is_err());
+    }
+
+    #[cfg(feature = "parquet")]
+    #[tokio::test]
+    async fn test_export_parquet() {
+        use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        use arrow_array::{Int64Array, StringArray};
+
+        let temp_dir = TempDir::new().unwrap();
+        let manager = manager(&temp_dir).await;
+
+        let request: ExportRequest = serde_json::from_value(json!({
+            "services": ["svc"],
+            "range": {"prefix": "u", "count": 100000},
+            "format": "parquet"
+        }))
+        .unwrap();
+        let mut rx = spawn(request, manager.snapshot(), HashMap::new()).unwrap();
+        let mut file = Vec::new();
+        let mut chunks = 0;
+        while let Some(chunk) = rx.recv().await {
+            file.extend_from_slice(&chunk);
+            chunks += 1;
+        }
+        assert!(chunks > 1, "streamed in {} chunk(s)", chunks);
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(file)).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 100000);
+        assert!(reader.metadata().num_row_groups() > 1);
+        let batch = reader.build().unwrap().next().unwrap().unwrap();
+        let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+        let subjects = column("subject");
+        let subjects = subjects.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!((subjects.value(0), subjects.value(1)), ("u0", "u1"));
+        let vids = column("vid");
+        assert_eq!(vids.as_any().downcast_ref::<Int64Array>().unwrap().value(0), 1001);
+        let labels = column("label");
+        assert_eq!(labels.as_any().downcast_ref::<StringArray>().unwrap().value(0), "a,b");
+    }
+}
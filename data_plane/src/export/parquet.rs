@@ -0,0 +1,98 @@
+//! Parquet encoding of exported assignments.
+//!
+//! Rows are collected into Arrow columns and written as one row group per
+//! [`BATCH_ROWS`] rows, so the file streams out while the export runs and
+//! memory stays bounded by a single batch.
+
+use super::CHUNK_BYTES;
+use crate::merge::Assignment;
+use arrow_array::builder::{Int64Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use bytes::Bytes;
+use parquet::arrow::ArrowWriter;
+use parquet::errors::Result;
+use std::sync::Arc;
+
+/// Rows per row group
+const BATCH_ROWS: usize = 64 * 1024;
+
+/// Columns of the row group being built, plus the bytes written so far
+pub(super) struct ParquetRows {
+    writer: ArrowWriter<Vec<u8>>,
+    schema: SchemaRef,
+    subject: StringBuilder,
+    service: StringBuilder,
+    layer_id: StringBuilder,
+    eid: Int64Builder,
+    vid: Int64Builder,
+    label: StringBuilder,
+    rows: usize,
+}
+
+impl ParquetRows {
+    pub(super) fn new() -> Result<Self> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("subject", DataType::Utf8, false),
+            Field::new("service", DataType::Utf8, false),
+            Field::new("layer_id", DataType::Utf8, false),
+            Field::new("eid", DataType::Int64, false),
+            Field::new("vid", DataType::Int64, false),
+            Field::new("label", DataType::Utf8, true),
+        ]));
+        Ok(Self {
+            writer: ArrowWriter::try_new(Vec::with_capacity(CHUNK_BYTES * 2), schema.clone(), None)?,
+            schema,
+            subject: StringBuilder::new(),
+            service: StringBuilder::new(),
+            layer_id: StringBuilder::new(),
+            eid: Int64Builder::new(),
+            vid: Int64Builder::new(),
+            label: StringBuilder::new(),
+            rows: 0,
+        })
+    }
+
+    pub(super) fn push(&mut self, subject: &str, service: &str, assignment: &Assignment) -> Result<()> {
+        self.subject.append_value(subject);
+        self.service.append_value(service);
+        self.layer_id.append_value(&assignment.layer_id);
+        self.eid.append_value(assignment.eid);
+        self.vid.append_value(assignment.vid);
+        self.label.append_option(assignment.label.as_deref());
+        self.rows += 1;
+        if self.rows >= BATCH_ROWS {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    /// Bytes written so far, once there are at least [`CHUNK_BYTES`] of them
+    pub(super) fn ready(&mut self) -> Option<Bytes> {
+        let written = self.writer.inner_mut();
+        (written.len() >= CHUNK_BYTES).then(|| Bytes::from(std::mem::take(written)))
+    }
+
+    /// Write the last row group and the footer; returns the remaining bytes
+    pub(super) fn finish(mut self) -> Result<Bytes> {
+        if self.rows > 0 {
+            self.write_row_group()?;
+        }
+        Ok(Bytes::from(self.writer.into_inner()?))
+    }
+
+    fn write_row_group(&mut self) -> Result<()> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.subject.finish()),
+            Arc::new(self.service.finish()),
+            Arc::new(self.layer_id.finish()),
+            Arc::new(self.eid.finish()),
+            Arc::new(self.vid.finish()),
+            Arc::new(self.label.finish()),
+        ];
+        self.writer.write(&RecordBatch::try_new(self.schema.clone(), columns)?)?;
+        self.writer.flush()?;
+        self.rows = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use super::ExposureEvent;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Per-process suppression of repeated exposures.
+///
+/// Remembers a hash of (subject, eid, vid) with the time it was last emitted and
+/// drops the same exposure until `window` has passed. Memory is bounded by
+/// `max_entries`; when the map is full of live entries new exposures pass through
+/// unrecorded, so overload produces duplicates rather than lost events.
+#[derive(Debug)]
+pub struct ExposureDedup {
+    window: Duration,
+    max_entries: usize,
+    seen: Mutex<HashMap<u64, Instant>>,
+}
+
+impl ExposureDedup {
+    pub fn new(window: Duration, max_entries: usize) -> Self {
+        Self {
+            window,
+            max_entries,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the event should be emitted; records it if so
+    pub fn admit(&self, event: &ExposureEvent) -> bool {
+        self.admit_at(event, Instant::now())
+    }
+
+    fn admit_at(&self, event: &ExposureEvent, now: Instant) -> bool {
+        let key = dedup_key(event);
+        let mut seen = self.seen.lock();
+
+        if let Some(last) = seen.get(&key) {
+            if now.duration_since(*last) < self.window {
+                return false;
+            }
+        }
+
+        if seen.len() >= self.max_entries && !seen.contains_key(&key) {
+            seen.retain(|_, last| now.duration_since(*last) < self.window);
+            if seen.len() >= self.max_entries {
+                return true;
+            }
+        }
+
+        seen.insert(key, now);
+        true
+    }
+}
+
+fn dedup_key(event: &ExposureEvent) -> u64 {
+    let mut buf = Vec::with_capacity(event.subject.len() + 17);
+    buf.extend_from_slice(event.subject.as_bytes());
+    buf.push(0);
+    buf.extend_from_slice(&event.eid.to_le_bytes());
+    buf.extend_from_slice(&event.vid.to_le_bytes());
+    xxh3_64(&buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(subject: &str, vid: i64) -> ExposureEvent {
+        ExposureEvent {
+            timestamp_ms: 0,
+            service: "svc".to_string(),
+            layer_id: "layer".to_string(),
+            eid: 100,
+            vid,
+            subject: subject.to_string(),
+            client: None,
+            trace: None,
+            link: None,
+            aa_test: false,
+            sample_rate: None,
+        }
+    }
+
+    #[test]
+    fn test_dedup_window() {
+        let dedup = ExposureDedup::new(Duration::from_secs(60), 100);
+        let start = Instant::now();
+
+        assert!(dedup.admit_at(&event("alice", 101), start));
+        assert!(!dedup.admit_at(&event("alice", 101), start + Duration::from_secs(30)));
+        // Different vid or subject is a different exposure
+        assert!(dedup.admit_at(&event("alice", 102), start));
+        assert!(dedup.admit_at(&event("bob", 101), start));
+        // Window elapsed
+        assert!(dedup.admit_at(&event("alice", 101), start + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn test_dedup_full_map_fails_open() {
+        let dedup = ExposureDedup::new(Duration::from_secs(60), 1);
+        let start = Instant::now();
+
+        assert!(dedup.admit_at(&event("alice", 101), start));
+        // Map is full of live entries: bob passes through without being tracked
+        assert!(dedup.admit_at(&event("bob", 101), start));
+        assert!(dedup.admit_at(&event("bob", 101), start));
+        assert!(!dedup.admit_at(&event("alice", 101), start));
+    }
+}
@@ -0,0 +1,36 @@
+use super::{ExposureEvent, ExposureSink};
+use crate::error::Result;
+use async_trait::async_trait;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Appends events as JSON lines to a file picked up by a log shipper
+#[derive(Debug)]
+pub struct FileSink {
+    path: PathBuf,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl ExposureSink for FileSink {
+    async fn ship(&self, events: &[ExposureEvent]) -> Result<()> {
+        let mut buf = Vec::new();
+        for event in events {
+            serde_json::to_writer(&mut buf, event)?;
+            buf.push(b'\n');
+        }
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&buf)?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,17 @@
+use super::{ExposureEvent, ExposureSink};
+use crate::error::Result;
+use async_trait::async_trait;
+
+/// Writes each event as a JSON log line under the `exposure` tracing target
+#[derive(Debug, Default)]
+pub struct LogSink;
+
+#[async_trait]
+impl ExposureSink for LogSink {
+    async fn ship(&self, events: &[ExposureEvent]) -> Result<()> {
+        for event in events {
+            tracing::info!(target: "exposure", "{}", serde_json::to_string(event)?);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,440 @@
+//! Exposure events: which subject was assigned which variant, for downstream
+//! analysis. Events go through a local on-disk spool before reaching the sink,
+//! so a sink outage or process crash doesn't lose them once spooled.
+//!
+//! Recording never blocks the request: events first wait in a bounded
+//! in-memory channel (`EXPOSURE_BUFFER`) and are spooled in batches. A crash
+//! loses whatever is still in that channel, at most `EXPOSURE_BUFFER` events
+//! and normally well under one batch, and a full channel drops new events
+//! (`buffer_full`). Only events already appended to the spool are durable.
+
+mod dedup;
+mod file;
+mod log;
+mod sampling;
+mod spool;
+
+pub use dedup::ExposureDedup;
+pub use file::FileSink;
+pub use log::LogSink;
+pub use sampling::ExposureSampling;
+pub use spool::{read_segment, Spool};
+
+use crate::config::Config;
+use crate::error::{ExperimentError, Result};
+use crate::layer::Snapshot;
+use crate::merge::{ExperimentRequest, ExperimentResponse, LayerTrace};
+use crate::metrics;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+/// Events moved from the in-memory buffer to the spool per write
+const SPOOL_BATCH: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExposureEvent {
+    pub timestamp_ms: u64,
+    pub service: String,
+    pub layer_id: String,
+    pub eid: i64,
+    pub vid: i64,
+    /// Hash key value the subject was bucketed on
+    pub subject: String,
+    /// SDK key client that made the request, when keys are enforced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    /// Outcome of every layer considered for the service, on a sampled
+    /// fraction of requests (`EXPOSURE_TRACE_SAMPLE_RATE`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<Vec<LayerTrace>>,
+    /// Identity link instead of an assignment: `subject` (the stable id) is the
+    /// same subject as this anonymous id. `service` and `layer_id` are empty,
+    /// `eid` and `vid` 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<IdentityLink>,
+    /// Assignment made by an A/A test layer (see [`crate::aa_test`])
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub aa_test: bool,
+    /// Fraction of subjects whose exposures to this experiment are logged,
+    /// when the experiment samples them (weight events by its inverse)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<f64>,
+}
+
+/// Anonymous id linked to an exposure event's stable `subject`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityLink {
+    /// Context field holding the anonymous id (`IDENTITY_ANONYMOUS_FIELD`)
+    pub field: String,
+    pub value: String,
+}
+
+/// Destination for spooled exposure events
+#[async_trait]
+pub trait ExposureSink: Send + Sync {
+    /// Deliver a batch. On error the batch stays spooled and is retried.
+    async fn ship(&self, events: &[ExposureEvent]) -> Result<()>;
+}
+
+/// Build the sink selected by `EXPOSURE_SINK`
+pub fn sink_from_config(config: &Config) -> Result<Arc<dyn ExposureSink>> {
+    match config.exposure_sink.as_str() {
+        "log" => Ok(Arc::new(LogSink)),
+        "file" => Ok(Arc::new(FileSink::new(config.exposure_sink_path.clone()))),
+        other => Err(ExperimentError::InvalidParameter(format!(
+            "Unsupported exposure sink '{}'",
+            other
+        ))),
+    }
+}
+
+/// Request-path handle; recording never blocks or fails the request
+#[derive(Clone)]
+pub struct ExposureLog {
+    tx: mpsc::Sender<ExposureEvent>,
+    /// Present when `EXPOSURE_DEDUP_WINDOW_SECS` > 0
+    dedup: Option<Arc<ExposureDedup>>,
+    trace_sample_rate: f64,
+}
+
+impl ExposureLog {
+    /// Open the spool and start the background spool/ship task
+    pub fn start(config: &Config) -> Result<Self> {
+        let spool = Spool::open(
+            config.exposure_spool_dir.clone(),
+            config.exposure_segment_bytes,
+            config.exposure_max_segments,
+        )?;
+        let sink = sink_from_config(config)?;
+        let (tx, rx) = mpsc::channel(config.exposure_buffer);
+
+        tokio::spawn(run(
+            rx,
+            Arc::new(Mutex::new(spool)),
+            sink,
+            Duration::from_millis(config.exposure_flush_ms),
+        ));
+
+        let dedup = (config.exposure_dedup_window_secs > 0).then(|| {
+            Arc::new(ExposureDedup::new(
+                Duration::from_secs(config.exposure_dedup_window_secs),
+                config.exposure_dedup_max_entries,
+            ))
+        });
+
+        Ok(Self {
+            tx,
+            dedup,
+            trace_sample_rate: config.exposure_trace_sample_rate.clamp(0.0, 1.0),
+        })
+    }
+
+    /// Whether to trace the evaluation of the next request
+    pub fn sample_trace(&self) -> bool {
+        let rate = self.trace_sample_rate;
+        rate >= 1.0 || (rate > 0.0 && rand::random::<f64>() < rate)
+    }
+
+    pub fn record(&self, events: Vec<ExposureEvent>) {
+        for event in events {
+            if self.dedup.as_ref().is_some_and(|d| !d.admit(&event)) {
+                metrics::EXPOSURE_DEDUPLICATED.inc();
+                continue;
+            }
+            if self.tx.try_send(event).is_err() {
+                metrics::EXPOSURE_DROPPED
+                    .with_label_values(&["buffer_full"])
+                    .inc();
+            }
+        }
+    }
+}
+
+/// One event per matched layer in the response, carrying the service's
+/// evaluation trace when the request was traced, plus an identity link event
+/// when the request carries both the stable and the anonymous id.
+///
+/// `snapshot` is the one the response was evaluated against, so layers and
+/// experiments are those that produced it even if a reload landed since.
+pub fn events_for(
+    request: &ExperimentRequest,
+    response: &ExperimentResponse,
+    client: Option<&str>,
+    snapshot: &Snapshot,
+) -> Vec<ExposureEvent> {
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+
+    let catalog = snapshot.catalog();
+    let policy = snapshot.identity_policy();
+    let mut events = Vec::new();
+    for (service, result) in &response.results {
+        for (layer_id, &vid) in result.matched_layers.iter().zip(&result.vids) {
+            let Some(layer) = snapshot.layer(layer_id) else {
+                continue;
+            };
+            let Ok(subject) = layer.hash_key.subject_value(&request.context, policy) else {
+                continue;
+            };
+            let eid = catalog.get_eid_by_vid(vid).unwrap_or_default();
+            let sampling = catalog.exposure_sampling(eid);
+            if sampling.is_some_and(|s| !s.samples(&subject)) {
+                metrics::EXPOSURE_SAMPLED_OUT.inc();
+                continue;
+            }
+            let subject = subject.into_owned();
+            events.push(ExposureEvent {
+                timestamp_ms,
+                service: service.clone(),
+                layer_id: layer_id.clone(),
+                eid,
+                vid,
+                subject,
+                client: client.map(str::to_string),
+                trace: result.trace.clone(),
+                link: None,
+                aa_test: layer.aa_test,
+                sample_rate: sampling.map(|s| s.rate()),
+            });
+        }
+    }
+
+    let link = policy.and_then(|p| Some((&p.anonymous_field, p.link(&request.context)?)));
+    if let Some((field, (subject, value))) = link {
+        events.push(ExposureEvent {
+            timestamp_ms,
+            service: String::new(),
+            layer_id: String::new(),
+            eid: 0,
+            vid: 0,
+            subject,
+            client: client.map(str::to_string),
+            trace: None,
+            link: Some(IdentityLink {
+                field: field.clone(),
+                value,
+            }),
+            aa_test: false,
+            sample_rate: None,
+        });
+    }
+    events
+}
+
+/// Run `op` on the blocking pool: spool writes fsync and reads load whole
+/// segments, neither of which may stall the async workers
+async fn on_spool<T, F>(spool: &Arc<Mutex<Spool>>, op: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Spool) -> Result<T> + Send + 'static,
+{
+    let spool = spool.clone();
+    tokio::task::spawn_blocking(move || op(&mut spool.lock()))
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+/// Move buffered events into the spool; every `flush` seal the active segment
+/// and ship whatever is sealed
+async fn run(
+    mut rx: mpsc::Receiver<ExposureEvent>,
+    spool: Arc<Mutex<Spool>>,
+    sink: Arc<dyn ExposureSink>,
+    flush: Duration,
+) {
+    let mut ticker = tokio::time::interval(flush);
+    let mut batch = Vec::with_capacity(SPOOL_BATCH);
+
+    loop {
+        tokio::select! {
+            received = rx.recv_many(&mut batch, SPOOL_BATCH) => {
+                if received == 0 {
+                    break;
+                }
+                let events = std::mem::replace(&mut batch, Vec::with_capacity(SPOOL_BATCH));
+                let count = events.len() as u64;
+                match on_spool(&spool, move |spool| spool.append(&events)).await {
+                    Ok(()) => metrics::EXPOSURE_SPOOLED.inc_by(count),
+                    Err(e) => {
+                        tracing::error!("Failed to spool {} exposure events: {}", count, e);
+                        metrics::EXPOSURE_DROPPED
+                            .with_label_values(&["spool_error"])
+                            .inc_by(count);
+                    }
+                }
+            }
+            _ = ticker.tick() => {
+                if let Err(e) = on_spool(&spool, Spool::seal).await {
+                    tracing::error!("Failed to seal exposure segment: {}", e);
+                }
+                ship_sealed(&spool, sink.as_ref()).await;
+            }
+        }
+    }
+
+    // Sender dropped: flush what is left
+    let _ = on_spool(&spool, Spool::seal).await;
+    ship_sealed(&spool, sink.as_ref()).await;
+}
+
+/// Ship sealed segments oldest first, stopping at the first failure
+pub async fn ship_sealed(spool: &Arc<Mutex<Spool>>, sink: &dyn ExposureSink) {
+    let segments = match on_spool(spool, |spool| spool.sealed()).await {
+        Ok(segments) => segments,
+        Err(e) => {
+            tracing::error!("Failed to list exposure spool: {}", e);
+            return;
+        }
+    };
+
+    for segment in segments {
+        let path = segment.clone();
+        let result = match on_spool(spool, move |_| read_segment(&path)).await {
+            Ok(events) if events.is_empty() => Ok(0),
+            Ok(events) => sink.ship(&events).await.map(|_| events.len()),
+            Err(e) => Err(e),
+        };
+        let result = match result {
+            Ok(shipped) => {
+                let path = segment.clone();
+                on_spool(spool, move |spool| spool.remove(&path)).await.map(|_| shipped)
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(shipped) => metrics::EXPOSURE_SHIPPED.inc_by(shipped as u64),
+            Err(e) => {
+                tracing::warn!("Exposure sink unavailable, keeping {:?} spooled: {}", segment, e);
+                metrics::EXPOSURE_SHIP_FAILURES.inc();
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tempfile::TempDir;
+
+    /// Fails until switched on, then collects events
+    #[derive(Default)]
+    struct FlakySink {
+        up: AtomicBool,
+        received: parking_lot::Mutex<Vec<ExposureEvent>>,
+    }
+
+    #[async_trait]
+    impl ExposureSink for FlakySink {
+        async fn ship(&self, events: &[ExposureEvent]) -> Result<()> {
+            if !self.up.load(Ordering::SeqCst) {
+                return Err(ExperimentError::Store("sink down".to_string()));
+            }
+            self.received.lock().extend_from_slice(events);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sink_outage_keeps_events_spooled() {
+        let temp_dir = TempDir::new().unwrap();
+        let spool = Arc::new(Mutex::new(Spool::open(temp_dir.path().to_path_buf(), 1 << 20, 10).unwrap()));
+        let sink = FlakySink::default();
+
+        let event = ExposureEvent {
+            timestamp_ms: 1,
+            service: "svc".to_string(),
+            layer_id: "layer".to_string(),
+            eid: 100,
+            vid: 101,
+            subject: "user".to_string(),
+            client: None,
+            trace: None,
+            link: None,
+            aa_test: false,
+            sample_rate: None,
+        };
+        spool.lock().append(&[event.clone(), event.clone()]).unwrap();
+        spool.lock().seal().unwrap();
+
+        ship_sealed(&spool, &sink).await;
+        assert_eq!(spool.lock().sealed().unwrap().len(), 1);
+
+        sink.up.store(true, Ordering::SeqCst);
+        ship_sealed(&spool, &sink).await;
+        assert!(spool.lock().sealed().unwrap().is_empty());
+        assert_eq!(sink.received.lock().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_events_follow_the_evaluation_snapshot() {
+        use crate::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
+        use crate::engine::Evaluator;
+        use crate::layer::{BucketRange, Layer};
+        use crate::overrides::Overrides;
+        use std::collections::HashMap;
+
+        let catalog = ExperimentCatalog::from_experiments(
+            vec![ExperimentDef {
+                eid: 100,
+                service: "svc".to_string(),
+                expires_at: None,
+                state: Default::default(),
+                caps: None,
+                rule: None,
+                exposure_sampling: None,
+                variants: vec![VariantDef {
+                    vid: 101,
+                    params: serde_json::json!({"on": true}),
+                    dark_params: None,
+                }],
+            }],
+            std::path::PathBuf::new(),
+        )
+        .unwrap();
+        let layer = Layer {
+            layer_id: "l1".to_string(),
+            version: "v1".to_string(),
+            priority: 1,
+            hash_key: "user_id".into(),
+            salt: None,
+            bucket_size: None,
+            expires_at: None,
+            rule: None,
+            services: vec![],
+            ranges: vec![BucketRange {
+                start: 0,
+                end: 10000,
+                vid: 101,
+                label: None,
+            }],
+            enabled: true,
+            aa_test: false,
+        };
+        let evaluator = Evaluator::builder().with_catalog(catalog).with_layers([layer]).build().unwrap();
+        let request = ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([("user_id".to_string(), serde_json::json!("u1"))]),
+            layers: vec![],
+            diagnostics: false,
+        };
+
+        let manager = evaluator.layer_manager();
+        let snapshot = manager.snapshot();
+        let response = evaluator.evaluate_traced(&request, &Overrides::new(), false, &snapshot).unwrap();
+        // The layer is removed before the exposure is recorded
+        manager.remove_layer("l1", snapshot.catalog()).await.unwrap();
+
+        let events = events_for(&request, &response, None, &snapshot);
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].layer_id.as_str(), events[0].eid, events[0].vid), ("l1", 100, 101));
+        assert!(events_for(&request, &response, None, &manager.snapshot()).is_empty());
+    }
+}
@@ -0,0 +1,76 @@
+use crate::error::{ExperimentError, Result};
+use crate::hash::hash_to_bucket_in;
+use serde::{Deserialize, Serialize};
+
+/// Sampling resolution: rates are applied in steps of one in a million
+const RESOLUTION: u32 = 1_000_000;
+
+/// Shared by every experiment, so at equal rates the same subjects are sampled
+/// everywhere and a lower rate samples a subset of a higher one
+const SALT: &str = "exposure_sampling";
+
+/// Which exposures to an experiment are logged, configured per experiment
+/// (`exposure_sampling` in `ExperimentDef`). Small experiments usually log
+/// everything; massive holdouts can log a small, stable panel of subjects.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum ExposureSampling {
+    /// Every exposure (same as no strategy)
+    All,
+    /// Exposures of a `rate` fraction of subjects, picked by a deterministic
+    /// hash of the subject so the same subjects are sampled on every request
+    Subjects { rate: f64 },
+    /// No exposures
+    Off,
+}
+
+impl ExposureSampling {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Self::Subjects { rate } if !(0.0..=1.0).contains(rate) => Err(ExperimentError::InvalidParameter(
+                format!("exposure_sampling rate must be within [0, 1], got {}", rate),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fraction of subjects whose exposures are logged
+    pub fn rate(&self) -> f64 {
+        match self {
+            Self::All => 1.0,
+            Self::Subjects { rate } => *rate,
+            Self::Off => 0.0,
+        }
+    }
+
+    /// Whether exposures of `subject` are logged
+    pub fn samples(&self, subject: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Subjects { rate } => {
+                hash_to_bucket_in(subject, SALT, RESOLUTION) < (rate * RESOLUTION as f64).round() as u32
+            }
+            Self::Off => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_subject_sampling_is_deterministic_and_nested() {
+        let sampling: ExposureSampling = serde_json::from_value(json!({"strategy": "subjects", "rate": 0.1})).unwrap();
+        let wider = ExposureSampling::Subjects { rate: 0.5 };
+
+        let subjects: Vec<String> = (0..10_000).map(|i| format!("user_{}", i)).collect();
+        let sampled: Vec<&String> = subjects.iter().filter(|s| sampling.samples(s)).collect();
+        assert!((900..1100).contains(&sampled.len()), "{}", sampled.len());
+        assert!(sampled.iter().all(|s| sampling.samples(s) && wider.samples(s)));
+
+        assert!(ExposureSampling::Subjects { rate: 1.5 }.validate().is_err());
+        assert!(!ExposureSampling::Off.samples("user_1"));
+    }
+}
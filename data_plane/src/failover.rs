@@ -0,0 +1,300 @@
+//! Prioritized config sources with failover.
+//!
+//! Each source is a layers/experiments directory pair kept in sync by one
+//! control plane (or one replica of it). `CONFIG_SOURCES_FILE` lists them in
+//! priority order:
+//!
+//! ```yaml
+//! sources:
+//!   - {name: primary, layers_dir: /config/primary/layers, experiments_dir: /config/primary/experiments}
+//!   - {name: standby, layers_dir: /config/standby/layers, experiments_dir: /config/standby/experiments}
+//! ```
+//!
+//! The active source fails when its directories disappear or its applies keep
+//! failing; after `CONFIG_FAILOVER_AFTER_SECS` of that the switcher moves to
+//! the highest-priority source that loads and validates. A higher-priority
+//! source that stays valid for `CONFIG_FAILBACK_AFTER_SECS` is switched back
+//! to. A source switched to by hand (`POST /admin/config_source`) that isn't
+//! in the list is left alone.
+
+use crate::config::migrate;
+use crate::error::{ExperimentError, Result};
+use crate::health::ConfigHealth;
+use crate::metrics;
+use crate::source::{ConfigSource, SourceSwitcher};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamedSource {
+    pub name: String,
+    #[serde(flatten)]
+    pub source: ConfigSource,
+}
+
+#[derive(Debug, Deserialize)]
+struct SourcesFile {
+    sources: Vec<NamedSource>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FailoverOptions {
+    pub check_interval: Duration,
+    /// How long the active source must keep failing before switching away
+    pub failover_after: Duration,
+    /// How long a higher-priority source must stay valid before switching back
+    pub failback_after: Duration,
+}
+
+/// Last observed health of one source, as reported by `GET /admin/config_sources`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceStatus {
+    pub name: String,
+    pub active: bool,
+    /// `None` until the source was first checked
+    pub healthy: Option<bool>,
+    /// Unix seconds since the current health was first observed
+    pub since: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct SourceFailover {
+    sources: Vec<NamedSource>,
+    options: FailoverOptions,
+    status: RwLock<Vec<SourceStatus>>,
+}
+
+impl SourceFailover {
+    pub fn load(path: &Path, options: FailoverOptions) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let file: SourcesFile = serde_json::from_value(migrate::parse_document(&content)?)?;
+        if file.sources.is_empty() {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "No config sources listed in {}",
+                path.display()
+            )));
+        }
+        tracing::info!(
+            "Config source failover order: {:?}",
+            file.sources
+                .iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        Ok(Self::new(file.sources, options))
+    }
+
+    pub fn new(sources: Vec<NamedSource>, options: FailoverOptions) -> Self {
+        let status = sources
+            .iter()
+            .map(|s| SourceStatus {
+                name: s.name.clone(),
+                active: false,
+                healthy: None,
+                since: 0,
+                error: None,
+            })
+            .collect();
+        Self {
+            sources,
+            options,
+            status: RwLock::new(status),
+        }
+    }
+
+    /// Highest-priority source whose directories exist, to start from
+    pub fn initial(&self) -> &NamedSource {
+        self.sources
+            .iter()
+            .find(|s| s.source.check_dirs().is_ok())
+            .unwrap_or(&self.sources[0])
+    }
+
+    pub fn status(&self) -> Vec<SourceStatus> {
+        self.status.read().clone()
+    }
+
+    /// Run one health check and switch sources if due
+    pub async fn check(&self, switcher: &SourceSwitcher, health: &ConfigHealth) {
+        let now = unix_now();
+        let current = switcher.current();
+        let active = self.sources.iter().position(|s| s.source == current);
+
+        for (i, named) in self.sources.iter().enumerate() {
+            let result = if Some(i) == active {
+                named
+                    .source
+                    .check_dirs()
+                    .and_then(|_| match health.degradation() {
+                        Some(degraded) => Err(ExperimentError::InvalidParameter(degraded.reason)),
+                        None => Ok(()),
+                    })
+            } else if active.is_none_or(|active| i < active) || self.active_failing(active, now) {
+                // Candidates to fail back or over to; lower-priority standbys only when needed
+                switcher.probe(&named.source).await
+            } else {
+                self.status.write()[i].active = false;
+                continue;
+            };
+            self.record(i, Some(i) == active, result, now);
+        }
+
+        let Some(active) = active else {
+            return;
+        };
+        let target = if self.active_failing(Some(active), now) {
+            self.healthy_for(now, Duration::ZERO).find(|&i| i != active)
+        } else {
+            self.healthy_for(now, self.options.failback_after)
+                .find(|&i| i < active)
+        };
+        let Some(target) = target else {
+            return;
+        };
+
+        let (from, to) = (&self.sources[active].name, &self.sources[target].name);
+        tracing::warn!("Switching config source from '{}' to '{}'", from, to);
+        let direction = if target < active { "failback" } else { "failover" };
+        metrics::CONFIG_SOURCE_FAILOVERS
+            .with_label_values(&[direction])
+            .inc();
+        if switcher
+            .switch(self.sources[target].source.clone())
+            .await
+            .is_ok()
+        {
+            let mut status = self.status.write();
+            status[active].active = false;
+            status[target].active = true;
+        }
+    }
+
+    /// The active source has been failing for at least `failover_after`
+    fn active_failing(&self, active: Option<usize>, now: u64) -> bool {
+        let Some(active) = active else {
+            return false;
+        };
+        let status = &self.status.read()[active];
+        status.healthy == Some(false)
+            && now.saturating_sub(status.since) >= self.options.failover_after.as_secs()
+    }
+
+    /// Sources (in priority order) healthy for at least `duration`
+    fn healthy_for(&self, now: u64, duration: Duration) -> impl Iterator<Item = usize> {
+        let status = self.status.read();
+        let healthy: Vec<usize> = status
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| {
+                s.healthy == Some(true) && now.saturating_sub(s.since) >= duration.as_secs()
+            })
+            .map(|(i, _)| i)
+            .collect();
+        healthy.into_iter()
+    }
+
+    fn record(&self, i: usize, active: bool, result: Result<()>, now: u64) {
+        let mut status = self.status.write();
+        let entry = &mut status[i];
+        let healthy = result.is_ok();
+        if entry.healthy != Some(healthy) {
+            if !healthy {
+                tracing::warn!("Config source '{}' unhealthy", entry.name);
+            }
+            entry.healthy = Some(healthy);
+            entry.since = now;
+        }
+        entry.active = active;
+        entry.error = result.err().map(|e| e.to_string());
+        metrics::CONFIG_SOURCE_ACTIVE
+            .with_label_values(&[&entry.name])
+            .set(active as i64);
+    }
+}
+
+/// Check sources every `check_interval`
+pub async fn run(
+    failover: Arc<SourceFailover>,
+    switcher: Arc<SourceSwitcher>,
+    health: Arc<ConfigHealth>,
+) {
+    let mut ticker = tokio::time::interval(failover.options.check_interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        failover.check(&switcher, &health).await;
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::ExperimentCatalog;
+    use crate::layer::LayerManager;
+    use crate::watcher::WatchOptions;
+    use tempfile::TempDir;
+
+    fn write_source(root: &Path, name: &str) -> NamedSource {
+        let source = ConfigSource {
+            layers_dir: root.join("layers"),
+            experiments_dir: root.join("experiments"),
+        };
+        std::fs::create_dir_all(&source.layers_dir).unwrap();
+        std::fs::create_dir_all(&source.experiments_dir).unwrap();
+        NamedSource {
+            name: name.to_string(),
+            source,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_and_back() {
+        let primary_root = TempDir::new().unwrap();
+        let standby_root = TempDir::new().unwrap();
+        let primary = write_source(primary_root.path(), "primary");
+        let standby = write_source(standby_root.path(), "standby");
+
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(primary.source.experiments_dir.clone()).unwrap());
+        let manager = Arc::new(LayerManager::new(primary.source.layers_dir.clone()));
+        manager.load_all_layers(&catalog).await.unwrap();
+        let health = Arc::new(ConfigHealth::new());
+        let switcher = SourceSwitcher::start(manager, health.clone(), WatchOptions::default());
+
+        let failover = SourceFailover::new(
+            vec![primary.clone(), standby.clone()],
+            FailoverOptions {
+                check_interval: Duration::from_secs(1),
+                failover_after: Duration::ZERO,
+                failback_after: Duration::ZERO,
+            },
+        );
+
+        // Primary's volume goes away: first check marks it failing, the next switches
+        std::fs::remove_dir_all(&primary.source.layers_dir).unwrap();
+        failover.check(&switcher, &health).await;
+        failover.check(&switcher, &health).await;
+        assert_eq!(switcher.current(), standby.source);
+        let status = failover.status();
+        assert_eq!(status[0].healthy, Some(false));
+        assert!(status[1].active);
+
+        // Primary recovers: fail back
+        std::fs::create_dir_all(&primary.source.layers_dir).unwrap();
+        failover.check(&switcher, &health).await;
+        failover.check(&switcher, &health).await;
+        assert_eq!(switcher.current(), primary.source);
+        assert!(failover.status()[0].active);
+    }
+}
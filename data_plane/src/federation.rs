@@ -0,0 +1,462 @@
+//! Federation: a front data plane delegating services to remote data planes.
+//!
+//! Teams that run their own data plane keep owning their layers and
+//! experiments, while callers still make one `/experiment` call to a front
+//! plane. `FEDERATION_ROUTES_FILE` names the remote planes and the services
+//! each one owns:
+//!
+//! ```yaml
+//! planes:
+//!   ads: http://ads-plane:8080
+//! services:
+//!   ads_ranking: ads
+//! ```
+//!
+//! The front splits a request by owner, evaluates its own services locally
+//! and sends the rest, with the same context, to their planes concurrently
+//! (`X-SDK-Key` is passed through), then merges the results into one
+//! response. A plane that fails or misses `FEDERATION_TIMEOUT_MS` yields empty
+//! results marked `unavailable` for its services, so callers fall back to
+//! their defaults. Delegated calls carry [`FEDERATED_HEADER`]; a plane
+//! receiving one evaluates everything locally, so misrouted tables can't loop.
+//!
+//! Remote planes apply their own context policy, overrides and exposure
+//! logging. `/metrics/federated` serves the front's metrics together with
+//! every plane's, each sample labelled with the `plane` it came from.
+
+use crate::config::migrate;
+use crate::error::{ExperimentError, Result};
+use crate::merge::{ExperimentRequest, ExperimentResponse, ServiceResult};
+use crate::metrics;
+use arc_swap::ArcSwap;
+use axum::body::Body;
+use axum::http::{header, HeaderValue, Request};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+/// Marks a request delegated by a front plane; it is never delegated again
+pub const FEDERATED_HEADER: &str = "x-experiment-federated";
+
+/// `plane` label of the front's own samples in `/metrics/federated`
+pub const LOCAL_PLANE: &str = "local";
+
+/// Largest remote response body accepted
+const MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct RoutesFile {
+    /// Plane name -> base URL
+    planes: BTreeMap<String, String>,
+    /// Service -> plane name
+    #[serde(default)]
+    services: BTreeMap<String, String>,
+}
+
+/// A validated routing table
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Routes {
+    /// Plane name -> base URL without trailing slash
+    planes: BTreeMap<String, String>,
+    services: HashMap<String, String>,
+}
+
+impl Routes {
+    fn from_file(file: RoutesFile) -> Result<Self> {
+        let mut planes = BTreeMap::new();
+        for (name, url) in file.planes {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "Plane name '{}' must be non-empty ASCII letters, digits, '_' or '-'",
+                    name
+                )));
+            }
+            if name == LOCAL_PLANE {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "Plane name '{}' is reserved for this plane",
+                    LOCAL_PLANE
+                )));
+            }
+            let valid = url
+                .parse::<axum::http::Uri>()
+                .is_ok_and(|uri| uri.scheme_str() == Some("http") && uri.host().is_some());
+            if !valid {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "Plane '{}' URL '{}' must be an http:// base URL",
+                    name, url
+                )));
+            }
+            planes.insert(name, url.trim_end_matches('/').to_string());
+        }
+        for (service, plane) in &file.services {
+            if !planes.contains_key(plane) {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "Service '{}' is routed to unknown plane '{}'",
+                    service, plane
+                )));
+            }
+        }
+        Ok(Self {
+            planes,
+            services: file.services.into_iter().collect(),
+        })
+    }
+
+    /// Remote plane owning `service`, if it isn't evaluated here
+    pub fn plane_of(&self, service: &str) -> Option<&str> {
+        self.services.get(service).map(String::as_str)
+    }
+}
+
+/// Routing table from `FEDERATION_ROUTES_FILE` and the client reaching the planes
+pub struct Federation {
+    path: PathBuf,
+    routes: ArcSwap<Routes>,
+    client: Client<HttpConnector, Body>,
+    timeout: Duration,
+}
+
+impl Federation {
+    pub fn load(path: PathBuf, timeout: Duration) -> Result<Self> {
+        let routes = read_routes(&path)?;
+        tracing::info!(
+            "Loaded federation routes from {:?}: {} services on {} remote planes",
+            path,
+            routes.services.len(),
+            routes.planes.len()
+        );
+        Ok(Self {
+            path,
+            routes: ArcSwap::from_pointee(routes),
+            client: Client::builder(TokioExecutor::new()).build_http(),
+            timeout,
+        })
+    }
+
+    /// Re-read the routes file; on failure the current routes stay in effect
+    pub fn reload(&self) -> Result<()> {
+        let routes = read_routes(&self.path)?;
+        self.routes.store(Arc::new(routes));
+        Ok(())
+    }
+
+    pub fn routes(&self) -> Arc<Routes> {
+        self.routes.load_full()
+    }
+
+    /// Move the services owned by remote planes out of `request` and start
+    /// evaluating them there; `None` when every service is local
+    pub fn delegate(
+        self: &Arc<Self>,
+        request: &mut ExperimentRequest,
+        sdk_key: Option<HeaderValue>,
+    ) -> Option<tokio::task::JoinHandle<HashMap<String, ServiceResult>>> {
+        let routes = self.routes();
+        let mut by_plane: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        request.services.retain(|service| match routes.plane_of(service) {
+            Some(plane) => {
+                by_plane.entry(plane.to_string()).or_default().push(service.clone());
+                false
+            }
+            None => true,
+        });
+        if by_plane.is_empty() {
+            return None;
+        }
+
+        let mut calls = JoinSet::new();
+        for (plane, services) in by_plane {
+            let federation = self.clone();
+            let url = routes.planes[&plane].clone();
+            let remote_request = ExperimentRequest {
+                services,
+                ..request.clone()
+            };
+            let sdk_key = sdk_key.clone();
+            calls.spawn(async move {
+                let results = federation.evaluate_remote(&plane, &url, &remote_request, sdk_key).await;
+                (plane, remote_request.services, results)
+            });
+        }
+
+        Some(tokio::spawn(async move {
+            let mut results = HashMap::new();
+            while let Some(joined) = calls.join_next().await {
+                let Ok((plane, services, outcome)) = joined else {
+                    continue;
+                };
+                match outcome {
+                    Ok(response) => results.extend(response.results),
+                    Err(e) => {
+                        tracing::warn!("Federated plane '{}' failed for {:?}: {}", plane, services, e);
+                        results.extend(services.into_iter().map(|service| (service, unavailable_result())));
+                    }
+                }
+            }
+            results
+        }))
+    }
+
+    async fn evaluate_remote(
+        &self,
+        plane: &str,
+        url: &str,
+        request: &ExperimentRequest,
+        sdk_key: Option<HeaderValue>,
+    ) -> Result<ExperimentResponse> {
+        let started = Instant::now();
+        let body = serde_json::to_vec(request)?;
+        let mut builder = Request::post(format!("{}/experiment", url))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(FEDERATED_HEADER, "1");
+        if let Some(key) = sdk_key {
+            builder = builder.header(crate::sdk_keys::SDK_KEY_HEADER, key);
+        }
+        let http_request = builder
+            .body(Body::from(body))
+            .map_err(|e| ExperimentError::InvalidParameter(format!("Invalid request to plane '{}': {}", plane, e)))?;
+
+        let (result, outcome) = match tokio::time::timeout(self.timeout, self.fetch(http_request)).await {
+            Ok(Ok(bytes)) => match serde_json::from_slice::<ExperimentResponse>(&bytes) {
+                // Services the plane doesn't know come back as nothing at all
+                Ok(response) if request.services.iter().any(|s| !response.results.contains_key(s)) => {
+                    (Ok(response), "incomplete")
+                }
+                Ok(response) => (Ok(response), "ok"),
+                Err(e) => (Err(e.into()), "error"),
+            },
+            Ok(Err(e)) => (Err(e), "error"),
+            Err(_) => (
+                Err(ExperimentError::RemotePlane(format!("no answer within {:?}", self.timeout))),
+                "timeout",
+            ),
+        };
+        metrics::FEDERATION_REQUESTS.with_label_values(&[plane, outcome]).inc();
+        metrics::FEDERATION_DURATION
+            .with_label_values(&[plane])
+            .observe(started.elapsed().as_secs_f64());
+        result
+    }
+
+    /// Body of a successful response
+    async fn fetch(&self, request: Request<Body>) -> Result<bytes::Bytes> {
+        let uri = request.uri().clone();
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(|e| ExperimentError::RemotePlane(format!("{}: {}", uri, e)))?;
+        let status = response.status();
+        let body = axum::body::to_bytes(Body::new(response.into_body()), MAX_RESPONSE_BYTES)
+            .await
+            .map_err(|e| ExperimentError::RemotePlane(format!("{}: {}", uri, e)))?;
+        if !status.is_success() {
+            return Err(ExperimentError::RemotePlane(format!(
+                "{} answered {}: {}",
+                uri,
+                status,
+                String::from_utf8_lossy(&body)
+            )));
+        }
+        Ok(body)
+    }
+
+    /// `/metrics` of every remote plane that answers in time, by plane name
+    pub async fn remote_metrics(&self) -> Vec<(String, String)> {
+        let mut scrapes = JoinSet::new();
+        for (plane, url) in self.routes().planes.clone() {
+            let client = self.client.clone();
+            let timeout = self.timeout;
+            scrapes.spawn(async move {
+                let request = Request::get(format!("{}/metrics", url)).body(Body::empty());
+                let scrape = async {
+                    let response = client.request(request.ok()?).await.ok()?;
+                    if !response.status().is_success() {
+                        return None;
+                    }
+                    let body = axum::body::to_bytes(Body::new(response.into_body()), MAX_RESPONSE_BYTES).await.ok()?;
+                    String::from_utf8(body.to_vec()).ok()
+                };
+                let text = tokio::time::timeout(timeout, scrape).await.ok().flatten();
+                if text.is_none() {
+                    tracing::warn!("Failed to scrape metrics of federated plane '{}'", plane);
+                }
+                (plane, text)
+            });
+        }
+
+        let mut scraped = Vec::new();
+        while let Some(joined) = scrapes.join_next().await {
+            if let Ok((plane, Some(text))) = joined {
+                scraped.push((plane, text));
+            }
+        }
+        scraped.sort();
+        scraped
+    }
+}
+
+/// Result of a service whose plane failed: no variants, caller defaults apply
+fn unavailable_result() -> ServiceResult {
+    ServiceResult {
+        parameters: serde_json::Value::Object(Default::default()),
+        vids: Vec::new(),
+        matched_layers: Vec::new(),
+        truncated: false,
+        excluded: false,
+        dark_parameters: None,
+        unavailable: true,
+        diagnostics: None,
+        trace: None,
+    }
+}
+
+fn read_routes(path: &PathBuf) -> Result<Routes> {
+    let content = std::fs::read_to_string(path)?;
+    Routes::from_file(serde_json::from_value(migrate::parse_document(&content)?)?)
+}
+
+/// Pick up routing changes every `interval`
+pub async fn reload_periodically(federation: Arc<Federation>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        if let Err(e) = federation.reload() {
+            tracing::error!("Failed to reload federation routes, keeping previous table: {}", e);
+        }
+    }
+}
+
+/// One Prometheus text exposition from several planes' expositions: each
+/// sample gets a `plane` label, and each metric family is listed once
+pub fn merge_expositions(sources: &[(&str, &str)]) -> String {
+    #[derive(Default)]
+    struct Family {
+        help: Option<String>,
+        kind: Option<String>,
+        samples: Vec<String>,
+    }
+    let mut order: Vec<String> = Vec::new();
+    let mut families: HashMap<String, Family> = HashMap::new();
+
+    for (plane, text) in sources {
+        let mut current: Option<String> = None;
+        for line in text.lines() {
+            let line = line.trim_end();
+            if let Some(comment) = line.strip_prefix('#') {
+                let mut parts = comment.trim_start().splitn(3, ' ');
+                let (Some(keyword @ ("HELP" | "TYPE")), Some(name)) = (parts.next(), parts.next()) else {
+                    continue;
+                };
+                if !families.contains_key(name) {
+                    order.push(name.to_string());
+                }
+                let family = families.entry(name.to_string()).or_default();
+                let rest = parts.next().unwrap_or_default().to_string();
+                if keyword == "HELP" {
+                    family.help.get_or_insert(rest);
+                } else {
+                    family.kind.get_or_insert(rest);
+                }
+                current = Some(name.to_string());
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let name_end = line.find(['{', ' ']).unwrap_or(line.len());
+            let name = &line[..name_end];
+            // Histogram / summary series belong to the family declared above them
+            let family_name = match &current {
+                Some(family) if name.starts_with(family.as_str()) => family.clone(),
+                _ => name.to_string(),
+            };
+            if !families.contains_key(&family_name) {
+                order.push(family_name.clone());
+            }
+            let labelled = match line[name_end..].strip_prefix('{') {
+                Some(rest) if rest.starts_with('}') => format!("{}{{plane=\"{}\"{}", name, plane, rest),
+                Some(rest) => format!("{}{{plane=\"{}\",{}", name, plane, rest),
+                None => format!("{}{{plane=\"{}\"}}{}", name, plane, &line[name_end..]),
+            };
+            families.entry(family_name).or_default().samples.push(labelled);
+        }
+    }
+
+    let mut out = String::new();
+    for name in order {
+        let family = &families[&name];
+        if let Some(help) = &family.help {
+            out.push_str(&format!("# HELP {} {}\n", name, help));
+        }
+        if let Some(kind) = &family.kind {
+            out.push_str(&format!("# TYPE {} {}\n", name, kind));
+        }
+        for sample in &family.samples {
+            out.push_str(sample);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_routes_split_request_by_plane() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("federation.yaml");
+        // Nothing listens on port 9: the plane is unavailable
+        std::fs::write(&path, "planes:\n  ads: http://127.0.0.1:9/\nservices:\n  ads_ranking: ads\n").unwrap();
+        let federation = Arc::new(Federation::load(path.clone(), Duration::from_millis(500)).unwrap());
+        assert_eq!(federation.routes().plane_of("ads_ranking"), Some("ads"));
+
+        let mut request = ExperimentRequest {
+            services: vec!["search".to_string(), "ads_ranking".to_string()],
+            context: HashMap::new(),
+            layers: vec![],
+            diagnostics: false,
+        };
+        let remote = federation.delegate(&mut request, None).unwrap().await.unwrap();
+        assert_eq!(request.services, vec!["search".to_string()]);
+        assert!(remote["ads_ranking"].unavailable && remote["ads_ranking"].vids.is_empty());
+        assert!(federation.delegate(&mut request, None).is_none());
+
+        // Bad tables are rejected and the previous one kept
+        for table in [
+            "planes:\n  ads: https://ads:8080\n",
+            "planes:\n  local: http://ads:8080\n",
+            "planes:\n  ads: http://ads:8080\nservices:\n  ads_ranking: search\n",
+        ] {
+            std::fs::write(&path, table).unwrap();
+            assert!(federation.reload().is_err(), "{}", table);
+        }
+        assert_eq!(federation.routes().plane_of("ads_ranking"), Some("ads"));
+    }
+
+    #[test]
+    fn test_merge_expositions_labels_planes() {
+        let local = "# HELP requests_total Requests\n# TYPE requests_total counter\nrequests_total 3\n\
+                     # HELP latency Latency\n# TYPE latency histogram\nlatency_bucket{le=\"0.1\"} 1\nlatency_sum 0.05\nlatency_count 1\n";
+        let ads = "# HELP requests_total Requests\n# TYPE requests_total counter\nrequests_total{} 7\n";
+        let merged = merge_expositions(&[("local", local), ("ads", ads)]);
+        assert_eq!(
+            merged,
+            "# HELP requests_total Requests\n# TYPE requests_total counter\n\
+             requests_total{plane=\"local\"} 3\nrequests_total{plane=\"ads\"} 7\n\
+             # HELP latency Latency\n# TYPE latency histogram\n\
+             latency_bucket{plane=\"local\",le=\"0.1\"} 1\nlatency_sum{plane=\"local\"} 0.05\nlatency_count{plane=\"local\"} 1\n"
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Config change feed for simple SDKs.
+//!
+//! `GET /config/subscribe?since_epoch=N` long-polls until layers or
+//! experiments change after epoch `N`, then returns the net change per
+//! resource since then: a layer added and then modified is reported once as
+//! added, one added and removed again not at all. Clients pass the returned
+//! `epoch` to the next call. When `N` is 0 or older than the feed remembers,
+//! the response has `reset: true` and lists every live resource as added.
+//!
+//! The feed subscribes to the engine event bus ([`crate::events`]) and keeps
+//! the last [`FEED_CAPACITY`] resource changes.
+
+use crate::applied::{AppliedChange, AppliedEntry};
+use crate::events::EngineEvent;
+use crate::layer::LayerManager;
+use crate::metrics;
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+
+/// Resource changes remembered for diffs
+pub const FEED_CAPACITY: usize = 4096;
+
+/// One resource's net change
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FeedChange {
+    /// `layer` or `experiment`
+    pub resource: String,
+    /// layer_id or eid
+    pub id: String,
+    pub change: AppliedChange,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Content hash of the live definition (absent for removals)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FeedDiff {
+    /// Pass as `since_epoch` on the next call
+    pub epoch: u64,
+    /// `changes` is the full live config, not a diff
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub reset: bool,
+    pub changes: Vec<FeedChange>,
+}
+
+#[derive(Debug)]
+struct FeedState {
+    /// Diffs are only known for epochs after this
+    covered_from: u64,
+    /// Newest epoch that changed layers or experiments
+    latest: u64,
+    entries: VecDeque<AppliedEntry>,
+}
+
+pub struct ConfigFeed {
+    manager: Arc<LayerManager>,
+    state: RwLock<FeedState>,
+    latest: watch::Sender<u64>,
+}
+
+impl ConfigFeed {
+    /// Subscribe to `manager`'s events; changes from now on are diffable
+    pub fn start(manager: Arc<LayerManager>) -> Arc<Self> {
+        let epoch = manager.snapshot().epoch();
+        let feed = Arc::new(Self {
+            state: RwLock::new(FeedState {
+                covered_from: epoch,
+                latest: epoch,
+                entries: VecDeque::new(),
+            }),
+            latest: watch::Sender::new(epoch),
+            manager: manager.clone(),
+        });
+
+        let mut receiver = manager.events().subscribe();
+        let ingest = feed.clone();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        if let EngineEvent::SnapshotApplied { change, resources } = event.as_ref() {
+                            ingest.ingest(change.epoch, resources);
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Config feed fell behind, skipped {} events", skipped);
+                        metrics::EVENTS_DROPPED.with_label_values(&["config_feed"]).inc_by(skipped);
+                        ingest.forget_before(ingest.manager.snapshot().epoch());
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        });
+        feed
+    }
+
+    /// Skipped events leave gaps: send clients behind `epoch` a full reset
+    fn forget_before(&self, epoch: u64) {
+        let mut state = self.state.write();
+        state.covered_from = state.covered_from.max(epoch);
+        state.latest = state.latest.max(epoch);
+        let latest = state.latest;
+        drop(state);
+        self.latest.send_replace(latest);
+    }
+
+    fn ingest(&self, epoch: u64, resources: &[AppliedEntry]) {
+        // Emergency-only publishes don't change what SDKs cache
+        if resources.is_empty() {
+            return;
+        }
+        let mut state = self.state.write();
+        state.entries.extend(resources.iter().cloned());
+        while state.entries.len() > FEED_CAPACITY {
+            let evicted = state.entries.pop_front().map_or(0, |e| e.epoch);
+            state.covered_from = state.covered_from.max(evicted);
+        }
+        state.latest = state.latest.max(epoch);
+        let latest = state.latest;
+        drop(state);
+        self.latest.send_replace(latest);
+    }
+
+    /// Changes after `since_epoch`, or `None` while there are none
+    pub fn diff(&self, since_epoch: u64) -> Option<FeedDiff> {
+        let state = self.state.read();
+        if since_epoch == 0 || since_epoch < state.covered_from {
+            drop(state);
+            return Some(self.full());
+        }
+        if since_epoch >= state.latest {
+            return None;
+        }
+
+        // Net change per resource, ordered by resource and id
+        let mut net: BTreeMap<(&str, &str), FeedChange> = BTreeMap::new();
+        for entry in state.entries.iter().filter(|e| e.epoch > since_epoch) {
+            let key = (entry.resource.as_str(), entry.id.as_str());
+            let change = match net.get(&key).map(|previous| previous.change) {
+                None => entry.change,
+                Some(previous) => match (previous, entry.change) {
+                    (AppliedChange::Added, AppliedChange::Removed) => {
+                        net.remove(&key);
+                        continue;
+                    }
+                    (AppliedChange::Added, _) => AppliedChange::Added,
+                    (AppliedChange::Removed, AppliedChange::Added) => AppliedChange::Modified,
+                    (_, latest) => latest,
+                },
+            };
+            net.insert(key, FeedChange {
+                resource: entry.resource.clone(),
+                id: entry.id.clone(),
+                change,
+                version: entry.version.clone(),
+                hash: entry.hash.clone(),
+            });
+        }
+
+        Some(FeedDiff {
+            epoch: state.latest,
+            reset: false,
+            changes: net.into_values().collect(),
+        })
+    }
+
+    /// Every live layer and experiment as added
+    fn full(&self) -> FeedDiff {
+        let snapshot = self.manager.snapshot();
+        let changes = snapshot
+            .resources()
+            .into_iter()
+            .map(|entry| FeedChange {
+                resource: entry.resource,
+                id: entry.id,
+                change: entry.change,
+                version: entry.version,
+                hash: entry.hash,
+            })
+            .collect();
+        FeedDiff {
+            epoch: snapshot.epoch(),
+            reset: true,
+            changes,
+        }
+    }
+
+    /// Wait up to `timeout` for changes after `since_epoch`
+    pub async fn wait(&self, since_epoch: u64, timeout: Duration) -> Option<FeedDiff> {
+        let mut latest = self.latest.subscribe();
+        let wait = async {
+            loop {
+                if let Some(diff) = self.diff(since_epoch) {
+                    return diff;
+                }
+                // The sender lives as long as the feed
+                if latest.changed().await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::ExperimentCatalog;
+    use crate::engine::Evaluator;
+    use crate::layer::{BucketRange, Layer};
+    use std::path::{Path, PathBuf};
+
+    fn layer(layer_id: &str, version: &str) -> Layer {
+        Layer {
+            layer_id: layer_id.to_string(),
+            version: version.to_string(),
+            priority: 1,
+            hash_key: "user_id".into(),
+            salt: None,
+            bucket_size: None,
+            expires_at: None,
+            rule: None,
+            services: vec![],
+            ranges: vec![BucketRange {
+                start: 0,
+                end: 10000,
+                vid: 1,
+                label: None,
+            }],
+            enabled: true,
+            aa_test: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_long_poll_returns_net_changes() {
+        let evaluator = Evaluator::builder()
+            .with_catalog(ExperimentCatalog::from_experiments(vec![], PathBuf::new()).unwrap())
+            .with_layers([layer("a", "v1"), layer("b", "v1")])
+            .build()
+            .unwrap();
+        let manager = evaluator.layer_manager().clone();
+        let feed = ConfigFeed::start(manager.clone());
+
+        let initial = feed.diff(0).unwrap();
+        assert!(initial.reset);
+        assert_eq!(initial.changes.len(), 2);
+        let since = initial.epoch;
+        assert!(feed.wait(since, Duration::from_millis(20)).await.is_none());
+
+        let waiter = {
+            let feed = feed.clone();
+            tokio::spawn(async move { feed.wait(since, Duration::from_secs(5)).await })
+        };
+        let catalog = manager.snapshot().catalog().clone();
+        manager.upsert_layer(layer("a", "v2"), Path::new("a.json"), &catalog).unwrap();
+        manager.upsert_layer(layer("c", "v1"), Path::new("c.json"), &catalog).unwrap();
+        manager.upsert_layer(layer("c", "v2"), Path::new("c.json"), &catalog).unwrap();
+
+        let diff = waiter.await.unwrap().unwrap();
+        assert!(!diff.reset);
+        assert_eq!(diff.changes[0].id, "a");
+        assert_eq!(diff.changes[0].change, AppliedChange::Modified);
+
+        // Once all three publishes are in, `c` shows up once, as added at v2
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let diff = feed.diff(since).unwrap();
+        let c = diff.changes.iter().find(|change| change.id == "c").unwrap();
+        assert_eq!((c.change, c.version.as_deref()), (AppliedChange::Added, Some("v2")));
+        assert_eq!(diff.changes.len(), 2);
+        assert!(feed.diff(diff.epoch).is_none());
+    }
+}
@@ -0,0 +1,95 @@
+//! Context field renames that existing rules keep working across.
+//!
+//! When client SDKs rename a context field (`region` → `country`), every rule
+//! still reading the old name would stop matching until rewritten. An alias
+//! map from the old name to the new one lets those rules resolve against the
+//! renamed field instead: a context sending only `country` answers rules on
+//! `region` too. A context that still sends `region` itself keeps its own
+//! value, so old and new clients can coexist during the rollout.
+//!
+//! Aliases only resolve values: the old name keeps its own declared field type
+//! (see `/field_types`), and aliases are not chained.
+
+use crate::error::{ExperimentError, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Old rule field name -> field the context now sends instead
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FieldAliases {
+    renames: HashMap<String, String>,
+}
+
+impl FieldAliases {
+    pub fn new(renames: HashMap<String, String>) -> Result<Self> {
+        for (old, new) in &renames {
+            if old == new {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "Field alias '{}' points to itself",
+                    old
+                )));
+            }
+            if renames.contains_key(new) {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "Field alias '{}' -> '{}' targets another alias; point it at the final field name",
+                    old, new
+                )));
+            }
+        }
+        Ok(Self { renames })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.renames.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.renames.len()
+    }
+
+    /// `context` with each missing old name filled from its renamed field;
+    /// borrowed when no alias applies
+    pub fn apply<'a>(&self, context: &'a HashMap<String, Value>) -> Cow<'a, HashMap<String, Value>> {
+        let mut resolved = Cow::Borrowed(context);
+        for (old, new) in &self.renames {
+            if context.contains_key(old) {
+                continue;
+            }
+            if let Some(value) = context.get(new) {
+                resolved.to_mut().insert(old.clone(), value.clone());
+            }
+        }
+        resolved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_old_names_resolve_to_renamed_fields() {
+        let aliases = FieldAliases::new(HashMap::from([("region".to_string(), "country".to_string())])).unwrap();
+
+        let new_client = HashMap::from([("country".to_string(), json!("US"))]);
+        assert_eq!(aliases.apply(&new_client).get("region"), Some(&json!("US")));
+
+        // An old client's own value wins
+        let old_client = HashMap::from([
+            ("region".to_string(), json!("EU")),
+            ("country".to_string(), json!("US")),
+        ]);
+        assert!(matches!(aliases.apply(&old_client), Cow::Borrowed(_)));
+        assert_eq!(aliases.apply(&old_client).get("region"), Some(&json!("EU")));
+
+        assert!(FieldAliases::new(HashMap::from([
+            ("a".to_string(), "b".to_string()),
+            ("b".to_string(), "c".to_string()),
+        ]))
+        .is_err());
+    }
+}
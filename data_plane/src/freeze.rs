@@ -0,0 +1,242 @@
+//! Config change freeze windows (e.g. holiday code freezes).
+//!
+//! `FREEZE_WINDOWS_FILE` lists windows as a cron schedule (UTC, with a
+//! seconds field: `sec min hour day month weekday [year]`) marking when each
+//! window opens, plus how long it stays open:
+//!
+//! ```yaml
+//! mode: reject            # or `queue`
+//! windows:
+//!   - name: holiday
+//!     schedule: "0 0 0 20 12 *"
+//!     duration_secs: 1209600
+//! ```
+//!
+//! While a window is open the layer manager refuses every layer and catalog
+//! change, whatever source it comes from. Emergency overrides and expiry are
+//! not config changes and still apply. In `reject` mode a refused change fails
+//! like any other invalid config; in `queue` mode it is deferred quietly and a
+//! full resync runs once the window closes. An operator can lift the freeze
+//! with the override flag (`FREEZE_OVERRIDE` or `POST /admin/freeze`).
+
+use crate::config::migrate;
+use crate::error::{ExperimentError, Result};
+use crate::health::ConfigHealth;
+use crate::layer::LayerManager;
+use crate::metrics;
+use crate::watcher;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use cron::Schedule;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FreezeMode {
+    /// Refused changes fail the apply
+    #[default]
+    Reject,
+    /// Refused changes are applied by a full resync after the window
+    Queue,
+}
+
+#[derive(Debug, Deserialize)]
+struct FreezeFile {
+    #[serde(default)]
+    mode: FreezeMode,
+    windows: Vec<WindowDef>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WindowDef {
+    name: String,
+    schedule: String,
+    duration_secs: u64,
+}
+
+#[derive(Debug, Clone)]
+struct FreezeWindow {
+    name: String,
+    schedule: Schedule,
+    duration: ChronoDuration,
+}
+
+/// An open window, as reported by `GET /admin/freeze`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ActiveWindow {
+    pub name: String,
+    /// Unix seconds
+    pub opened_at: i64,
+    pub closes_at: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FreezeStatus {
+    pub mode: FreezeMode,
+    pub active: Option<ActiveWindow>,
+    #[serde(rename = "override")]
+    pub overridden: bool,
+    /// A change was deferred and waits for the window to close
+    pub pending: bool,
+}
+
+/// Freeze windows enforced by [`LayerManager`] on every apply
+#[derive(Debug)]
+pub struct FreezeSchedule {
+    mode: FreezeMode,
+    windows: Vec<FreezeWindow>,
+    overridden: AtomicBool,
+    pending: AtomicBool,
+}
+
+impl FreezeSchedule {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let file: FreezeFile = serde_json::from_value(migrate::parse_document(&content)?)?;
+
+        let windows = file
+            .windows
+            .into_iter()
+            .map(|def| {
+                let schedule = Schedule::from_str(&def.schedule).map_err(|e| {
+                    ExperimentError::InvalidParameter(format!(
+                        "Freeze window '{}': invalid schedule '{}': {}",
+                        def.name, def.schedule, e
+                    ))
+                })?;
+                Ok(FreezeWindow {
+                    name: def.name,
+                    schedule,
+                    duration: ChronoDuration::seconds(def.duration_secs as i64),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        tracing::info!("Loaded {} freeze windows from {:?} ({:?} mode)", windows.len(), path, file.mode);
+
+        Ok(Self {
+            mode: file.mode,
+            windows,
+            overridden: AtomicBool::new(false),
+            pending: AtomicBool::new(false),
+        })
+    }
+
+    pub fn mode(&self) -> FreezeMode {
+        self.mode
+    }
+
+    /// Let changes through while a window is open
+    pub fn set_override(&self, enabled: bool) {
+        if self.overridden.swap(enabled, Ordering::Relaxed) != enabled {
+            tracing::warn!("Config freeze override {}", if enabled { "enabled" } else { "disabled" });
+        }
+    }
+
+    /// Window open at `now`, if any
+    pub fn active_at(&self, now: DateTime<Utc>) -> Option<ActiveWindow> {
+        self.windows.iter().find_map(|window| {
+            // The window is open iff it opened within the last `duration`
+            let opened_at = window.schedule.after(&(now - window.duration)).next()?;
+            (opened_at <= now).then(|| ActiveWindow {
+                name: window.name.clone(),
+                opened_at: opened_at.timestamp(),
+                closes_at: (opened_at + window.duration).timestamp(),
+            })
+        })
+    }
+
+    pub fn status(&self) -> FreezeStatus {
+        FreezeStatus {
+            mode: self.mode,
+            active: self.active_at(Utc::now()),
+            overridden: self.overridden.load(Ordering::Relaxed),
+            pending: self.pending.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Refuse a config change while a window is open and not overridden
+    pub fn check(&self) -> Result<()> {
+        if self.overridden.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let Some(window) = self.active_at(Utc::now()) else {
+            return Ok(());
+        };
+
+        let mode = match self.mode {
+            FreezeMode::Reject => "reject",
+            FreezeMode::Queue => "queue",
+        };
+        metrics::FREEZE_REFUSED.with_label_values(&[mode]).inc();
+        if self.mode == FreezeMode::Queue {
+            self.pending.store(true, Ordering::Relaxed);
+        }
+        Err(ExperimentError::ConfigFrozen(format!(
+            "freeze window '{}' is open until {}",
+            window.name, window.closes_at
+        )))
+    }
+}
+
+/// Whether `e` is a change deferred by a queueing freeze (not an apply failure)
+pub fn is_queued(e: &ExperimentError, manager: &LayerManager) -> bool {
+    matches!(e.inner(), ExperimentError::ConfigFrozen(_))
+        && manager.freeze().is_some_and(|freeze| freeze.mode() == FreezeMode::Queue)
+}
+
+/// Apply changes deferred in `queue` mode once no window is open (checked every `interval`)
+pub async fn resync_after_windows(
+    freeze: Arc<FreezeSchedule>,
+    manager: Arc<LayerManager>,
+    health: Arc<ConfigHealth>,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        if !freeze.pending.load(Ordering::Relaxed) {
+            continue;
+        }
+        if freeze.overridden.load(Ordering::Relaxed) || freeze.active_at(Utc::now()).is_none() {
+            freeze.pending.store(false, Ordering::Relaxed);
+            tracing::info!("Freeze lifted, applying deferred config changes");
+            watcher::full_resync(&manager, &health).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_window_open_for_duration_after_schedule() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("freeze.yaml");
+        // Opens every 20 December at 00:00 UTC for 14 days
+        std::fs::write(
+            &path,
+            "mode: queue\nwindows:\n  - {name: holiday, schedule: \"0 0 0 20 12 *\", duration_secs: 1209600}\n",
+        )
+        .unwrap();
+        let freeze = FreezeSchedule::load(&path).unwrap();
+
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        assert!(freeze.active_at(at("2026-12-19T23:59:59Z")).is_none());
+        let window = freeze.active_at(at("2026-12-25T12:00:00Z")).unwrap();
+        assert_eq!(window.name, "holiday");
+        assert_eq!(window.closes_at, at("2027-01-03T00:00:00Z").timestamp());
+        // Still open across the new year, closed afterwards
+        assert!(freeze.active_at(at("2027-01-02T23:00:00Z")).is_some());
+        assert!(freeze.active_at(at("2027-01-03T00:00:01Z")).is_none());
+
+        std::fs::write(&path, "windows: [{name: bad, schedule: \"every day\", duration_secs: 1}]").unwrap();
+        assert!(FreezeSchedule::load(&path).is_err());
+    }
+}
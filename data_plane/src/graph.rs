@@ -0,0 +1,283 @@
+//! Experiment topology graph.
+//!
+//! Traffic flows layer → variant (bucket ranges) → experiment → service.
+//! Experiments sharing a layer split its traffic and never both assign the
+//! same subject, so each layer is also reported as a mutex group. `GET /graph`
+//! serves the graph as JSON or Graphviz DOT (`?format=dot`).
+
+use crate::layer::Snapshot;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeKind {
+    Layer,
+    Variant,
+    Experiment,
+    Service,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GraphNode {
+    /// `<kind>:<id>`, e.g. `layer:click_experiment`, `variant:1001`
+    pub id: String,
+    pub kind: NodeKind,
+    pub label: String,
+    /// Layers only: disabled layers still show, with their edges
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+    /// Layer → variant: share of the layer's buckets assigned to the variant
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share: Option<f64>,
+}
+
+/// Experiments splitting one layer's traffic
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MutexGroup {
+    pub layer_id: String,
+    pub eids: Vec<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExperimentGraph {
+    pub epoch: u64,
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+    /// Layers shared by more than one experiment
+    pub mutex_groups: Vec<MutexGroup>,
+}
+
+/// Graph of `snapshot`, limited to the layers feeding `service` when given
+pub fn build(snapshot: &Snapshot, service: Option<&str>) -> ExperimentGraph {
+    let catalog = snapshot.catalog();
+    let mut layers: Vec<_> = snapshot.layers().collect();
+    layers.sort_by(|a, b| a.layer_id.cmp(&b.layer_id));
+
+    let mut nodes = BTreeMap::new();
+    let mut edges = Vec::new();
+    let mut mutex_groups = Vec::new();
+    let mut experiments = BTreeSet::new();
+
+    for layer in layers {
+        // vid -> buckets, for vids known to the catalog (and of `service`)
+        let mut vids: BTreeMap<i64, u32> = BTreeMap::new();
+        for range in &layer.ranges {
+            let Some((eid, owner)) = catalog.variant_owner(range.vid) else {
+                continue;
+            };
+            if service.is_some_and(|s| s != owner) {
+                continue;
+            }
+            *vids.entry(range.vid).or_default() += range.end - range.start;
+            experiments.insert(eid);
+        }
+        if vids.is_empty() && service.is_some() {
+            continue;
+        }
+
+        let layer_node = format!("layer:{}", layer.layer_id);
+        nodes.insert(
+            layer_node.clone(),
+            GraphNode {
+                id: layer_node.clone(),
+                kind: NodeKind::Layer,
+                label: format!("{} ({})", layer.layer_id, layer.version),
+                enabled: Some(layer.enabled),
+            },
+        );
+
+        let bucket_size = layer.bucket_size() as f64;
+        let mut eids = BTreeSet::new();
+        for (vid, buckets) in vids {
+            let variant_node = format!("variant:{}", vid);
+            nodes.insert(
+                variant_node.clone(),
+                GraphNode {
+                    id: variant_node.clone(),
+                    kind: NodeKind::Variant,
+                    label: vid.to_string(),
+                    enabled: None,
+                },
+            );
+            edges.push(GraphEdge {
+                from: layer_node.clone(),
+                to: variant_node,
+                share: Some(buckets as f64 / bucket_size),
+            });
+            eids.extend(catalog.get_eid_by_vid(vid));
+        }
+        if eids.len() > 1 {
+            mutex_groups.push(MutexGroup {
+                layer_id: layer.layer_id.clone(),
+                eids: eids.into_iter().collect(),
+            });
+        }
+    }
+
+    for eid in experiments {
+        let Some((owner, vids)) = catalog.experiment_outline(eid) else {
+            continue;
+        };
+        let experiment_node = format!("experiment:{}", eid);
+        let service_node = format!("service:{}", owner);
+        for vid in vids {
+            let variant_node = format!("variant:{}", vid);
+            if nodes.contains_key(&variant_node) {
+                edges.push(GraphEdge {
+                    from: variant_node,
+                    to: experiment_node.clone(),
+                    share: None,
+                });
+            }
+        }
+        nodes.insert(
+            experiment_node.clone(),
+            GraphNode {
+                id: experiment_node.clone(),
+                kind: NodeKind::Experiment,
+                label: eid.to_string(),
+                enabled: None,
+            },
+        );
+        nodes.insert(
+            service_node.clone(),
+            GraphNode {
+                id: service_node.clone(),
+                kind: NodeKind::Service,
+                label: owner.to_string(),
+                enabled: None,
+            },
+        );
+        edges.push(GraphEdge {
+            from: experiment_node,
+            to: service_node,
+            share: None,
+        });
+    }
+
+    let mut nodes: Vec<GraphNode> = nodes.into_values().collect();
+    nodes.sort_by(|a, b| a.kind.cmp(&b.kind).then_with(|| a.id.cmp(&b.id)));
+
+    ExperimentGraph {
+        epoch: snapshot.epoch(),
+        nodes,
+        edges,
+        mutex_groups,
+    }
+}
+
+impl ExperimentGraph {
+    /// Graphviz DOT rendering, one cluster per node kind
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph experiments {\n  rankdir=LR;\n");
+        for node in &self.nodes {
+            let shape = match node.kind {
+                NodeKind::Layer => "box",
+                NodeKind::Variant => "ellipse",
+                NodeKind::Experiment => "diamond",
+                NodeKind::Service => "doubleoctagon",
+            };
+            let style = if node.enabled == Some(false) { ", style=dashed" } else { "" };
+            let _ = writeln!(dot, "  {:?} [label={:?}, shape={}{}];", node.id, node.label, shape, style);
+        }
+        for edge in &self.edges {
+            match edge.share {
+                Some(share) => {
+                    let _ = writeln!(dot, "  {:?} -> {:?} [label=\"{:.1}%\"];", edge.from, edge.to, share * 100.0);
+                }
+                None => {
+                    let _ = writeln!(dot, "  {:?} -> {:?};", edge.from, edge.to);
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
+    use crate::engine::Evaluator;
+    use crate::layer::{BucketRange, Layer};
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_graph_links_layers_to_services() {
+        let experiment = |eid: i64, service: &str, vid: i64| ExperimentDef {
+            eid,
+            service: service.to_string(),
+            expires_at: None,
+            state: Default::default(),
+            caps: None,
+            rule: None,
+            exposure_sampling: None,
+            variants: vec![VariantDef {
+                vid,
+                params: json!({}),
+                dark_params: None,
+            }],
+        };
+        let catalog = ExperimentCatalog::from_experiments(
+            vec![experiment(100, "svc_a", 101), experiment(200, "svc_a", 201), experiment(300, "svc_b", 301)],
+            PathBuf::new(),
+        )
+        .unwrap();
+        let range = |start, end, vid| BucketRange {
+            start,
+            end,
+            vid,
+            label: None,
+        };
+        let layer = |layer_id: &str, ranges| Layer {
+            layer_id: layer_id.to_string(),
+            version: "v1".to_string(),
+            priority: 1,
+            hash_key: "user_id".into(),
+            salt: None,
+            bucket_size: None,
+            expires_at: None,
+            rule: None,
+            services: vec![],
+            ranges,
+            enabled: true,
+            aa_test: false,
+        };
+        let evaluator = Evaluator::builder()
+            .with_catalog(catalog)
+            .with_layers([
+                layer("shared", vec![range(0, 2500, 101), range(5000, 7500, 201)]),
+                layer("other", vec![range(0, 10000, 301)]),
+            ])
+            .build()
+            .unwrap();
+        let snapshot = evaluator.layer_manager().snapshot();
+
+        let graph = build(&snapshot, None);
+        assert_eq!(graph.nodes.len(), 2 + 3 + 3 + 2);
+        let share = graph.edges.iter().find(|e| e.to == "variant:101").unwrap().share;
+        assert_eq!(share, Some(0.25));
+        assert!(graph.edges.iter().any(|e| e.from == "experiment:300" && e.to == "service:svc_b"));
+        assert_eq!(graph.mutex_groups, vec![MutexGroup {
+            layer_id: "shared".to_string(),
+            eids: vec![100, 200],
+        }]);
+        assert!(graph.to_dot().contains("\"layer:shared\" -> \"variant:101\" [label=\"25.0%\"];"));
+
+        let graph = build(&snapshot, Some("svc_b"));
+        assert_eq!(
+            graph.nodes.iter().map(|n| n.id.as_str()).collect::<Vec<_>>(),
+            vec!["layer:other", "variant:301", "experiment:300", "service:svc_b"]
+        );
+    }
+}
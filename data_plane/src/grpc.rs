@@ -0,0 +1,204 @@
+//! Middleware for the tonic evaluation server (feature `grpc`), mirroring the
+//! HTTP stack: SDK key auth, per-method metrics and client deadline handling.
+//!
+//! Wire-up once the service exists:
+//!
+//! ```ignore
+//! Server::builder()
+//!     .layer(GrpcMetricsLayer)
+//!     .add_service(EvaluatorServer::with_interceptor(svc, AuthInterceptor::new(registry)))
+//! ```
+//!
+//! Handlers read the caller with `request.extensions().get::<Arc<SdkKey>>()`,
+//! check service scopes with [`SdkKey::check_services`] and run evaluation
+//! under [`with_deadline`].
+
+use crate::error::ExperimentError;
+use crate::metrics;
+use crate::sdk_keys::{SdkKey, SdkKeyRegistry, SDK_KEY_HEADER};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tonic::codegen::http;
+use tonic::metadata::MetadataMap;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+use tower::{Layer, Service};
+
+/// Map domain errors onto gRPC status codes
+pub fn to_status(err: ExperimentError) -> Status {
+    match err {
+        ExperimentError::Unauthorized(msg) => Status::unauthenticated(msg),
+        ExperimentError::Forbidden(msg) => Status::permission_denied(msg),
+        ExperimentError::ContextNotAllowed(msg) => Status::invalid_argument(msg),
+        other => Status::internal(other.to_string()),
+    }
+}
+
+/// Validates the `x-sdk-key` metadata and stores the caller's [`SdkKey`] in the
+/// request extensions. Service scopes are checked by the handler, which knows
+/// the requested services.
+#[derive(Clone)]
+pub struct AuthInterceptor {
+    registry: Arc<SdkKeyRegistry>,
+}
+
+impl AuthInterceptor {
+    pub fn new(registry: Arc<SdkKeyRegistry>) -> Self {
+        Self { registry }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let key = request
+            .metadata()
+            .get(SDK_KEY_HEADER)
+            .and_then(|v| v.to_str().ok());
+        let sdk_key = self.registry.authenticate(key).map_err(to_status)?;
+        request.extensions_mut().insert(sdk_key);
+        Ok(request)
+    }
+}
+
+/// Caller identity set by [`AuthInterceptor`]
+pub fn caller<T>(request: &Request<T>) -> Option<Arc<SdkKey>> {
+    request.extensions().get::<Arc<SdkKey>>().cloned()
+}
+
+/// Parse a `grpc-timeout` header value (`<digits><unit>`, unit one of H M S m u n)
+pub fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+    let (digits, unit) = value.split_at(value.len() - 1);
+    let amount: u64 = digits.parse().ok()?;
+
+    Some(match unit {
+        "H" => Duration::from_secs(amount * 3600),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    })
+}
+
+/// Client deadline carried in request metadata, if any
+pub fn client_deadline(metadata: &MetadataMap) -> Option<Duration> {
+    metadata
+        .get("grpc-timeout")
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_grpc_timeout)
+}
+
+/// Run evaluation within the client's deadline, capped at `max`.
+/// Returns `DEADLINE_EXCEEDED` instead of finishing work nobody waits for.
+pub async fn with_deadline<T, F>(metadata: &MetadataMap, max: Duration, fut: F) -> Result<T, Status>
+where
+    F: Future<Output = Result<T, Status>>,
+{
+    let budget = client_deadline(metadata).map_or(max, |d| d.min(max));
+    match tokio::time::timeout(budget, fut).await {
+        Ok(result) => result,
+        Err(_) => {
+            metrics::GRPC_DEADLINE_EXCEEDED.inc();
+            Err(Status::deadline_exceeded(format!(
+                "evaluation exceeded {:?} deadline",
+                budget
+            )))
+        }
+    }
+}
+
+/// Per-method request count and latency, labelled by gRPC path and status code
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GrpcMetricsLayer;
+
+impl<S> Layer<S> for GrpcMetricsLayer {
+    type Service = GrpcMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcMetrics { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GrpcMetrics<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for GrpcMetrics<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let method = request.uri().path().to_string();
+        let started = Instant::now();
+        let future = self.inner.call(request);
+
+        Box::pin(async move {
+            let result = future.await;
+            // Unary errors are trailers-only, so the status is in the headers;
+            // a missing header means the call proceeded normally
+            let code = match &result {
+                Ok(response) => response
+                    .headers()
+                    .get("grpc-status")
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("0")
+                    .to_string(),
+                Err(_) => "transport".to_string(),
+            };
+            metrics::GRPC_REQUESTS
+                .with_label_values(&[&method, &code])
+                .inc();
+            metrics::GRPC_DURATION
+                .with_label_values(&[&method])
+                .observe(started.elapsed().as_secs_f64());
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_grpc_timeout() {
+        assert_eq!(parse_grpc_timeout("250m"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_grpc_timeout("1S"), Some(Duration::from_secs(1)));
+        assert_eq!(parse_grpc_timeout("2H"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_grpc_timeout("m"), None);
+        assert_eq!(parse_grpc_timeout("10x"), None);
+        assert_eq!(parse_grpc_timeout("1234567890S"), None);
+    }
+
+    #[tokio::test]
+    async fn test_with_deadline_uses_client_timeout() {
+        let mut metadata = MetadataMap::new();
+        metadata.insert("grpc-timeout", "10m".parse().unwrap());
+
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok::<_, Status>(())
+        };
+        let err = with_deadline(&metadata, Duration::from_secs(10), slow)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::DeadlineExceeded);
+    }
+}
@@ -4,10 +4,15 @@ use crate::layer::BUCKET_SIZE;
 /// Hash a key with salt to a bucket index
 /// Salt ensures different layers produce different distributions for the same key
 pub fn hash_to_bucket(key: &str, salt: &str) -> u32 {
+    hash_to_bucket_in(key, salt, BUCKET_SIZE)
+}
+
+/// Same as [`hash_to_bucket`] for a layer with its own bucket size
+pub fn hash_to_bucket_in(key: &str, salt: &str, bucket_size: u32) -> u32 {
     // Concatenate key and salt, then hash
     let combined = format!("{}{}", key, salt);
     let hash = xxh3_64(combined.as_bytes());
-    (hash % BUCKET_SIZE as u64) as u32
+    (hash % bucket_size as u64) as u32
 }
 
 #[cfg(test)]
@@ -43,7 +48,7 @@ mod tests {
     
     #[test]
     fn test_salt_ensures_different_distribution() {
-        let salts = vec!["layer1_v1", "layer2_v1", "layer3_v1"];
+        let salts = ["layer1_v1", "layer2_v1", "layer3_v1"];
         let mut distributions: Vec<HashSet<u32>> = vec![HashSet::new(); salts.len()];
         
         // Test first 100 users
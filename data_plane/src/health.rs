@@ -0,0 +1,117 @@
+use crate::manifest::ManifestMismatch;
+use crate::watchdog::{self, Heartbeat, StalledTask};
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Why the instance is serving a stale config snapshot
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Degradation {
+    pub reason: String,
+    /// Unix timestamp (seconds) of the first failure since the last successful apply
+    pub since: u64,
+    /// Consecutive failed applies
+    pub failures: u64,
+}
+
+/// Config apply status reported by `/ready`
+#[derive(Debug, Default)]
+pub struct ConfigHealth {
+    degraded: RwLock<Option<Degradation>>,
+    /// Manifest verified after full loads (see [`crate::manifest`])
+    manifest: Option<PathBuf>,
+    /// Entries that failed the last manifest check; non-empty fails readiness
+    manifest_mismatches: RwLock<Vec<ManifestMismatch>>,
+    /// Liveness of config tasks, by task name (see [`crate::watchdog`])
+    heartbeats: RwLock<BTreeMap<String, Arc<Heartbeat>>>,
+    /// Tasks the watchdog found stalled; non-empty fails readiness
+    stalled: RwLock<Vec<StalledTask>>,
+}
+
+impl ConfigHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_manifest(mut self, manifest: Option<PathBuf>) -> Self {
+        self.manifest = manifest;
+        self
+    }
+
+    /// Record a failed apply; the previous snapshot keeps serving
+    pub fn mark_degraded(&self, reason: impl Into<String>) {
+        let mut degraded = self.degraded.write();
+        match degraded.as_mut() {
+            Some(d) => {
+                d.reason = reason.into();
+                d.failures += 1;
+            }
+            None => {
+                let since = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                *degraded = Some(Degradation {
+                    reason: reason.into(),
+                    since,
+                    failures: 1,
+                });
+            }
+        }
+    }
+
+    /// Record a successful apply
+    pub fn mark_healthy(&self) {
+        *self.degraded.write() = None;
+    }
+
+    pub fn degradation(&self) -> Option<Degradation> {
+        self.degraded.read().clone()
+    }
+
+    pub fn manifest_path(&self) -> Option<&Path> {
+        self.manifest.as_deref()
+    }
+
+    pub fn set_manifest_mismatches(&self, mismatches: Vec<ManifestMismatch>) {
+        *self.manifest_mismatches.write() = mismatches;
+    }
+
+    pub fn manifest_mismatches(&self) -> Vec<ManifestMismatch> {
+        self.manifest_mismatches.read().clone()
+    }
+
+    /// Register `task`, expected to beat at least every `interval`
+    pub fn heartbeat(&self, task: &str, interval: Duration) -> Arc<Heartbeat> {
+        let heartbeat = Arc::new(Heartbeat::new(interval));
+        self.heartbeats.write().insert(task.to_string(), heartbeat.clone());
+        heartbeat
+    }
+
+    /// Tasks more than `grace` past their beat interval
+    pub fn stalled_tasks(&self, grace: Duration) -> Vec<StalledTask> {
+        let now = watchdog::unix_millis();
+        self.heartbeats
+            .read()
+            .iter()
+            .filter_map(|(task, heartbeat)| {
+                let overdue = heartbeat.overdue(now);
+                (overdue > grace).then(|| StalledTask {
+                    task: task.clone(),
+                    overdue_secs: overdue.as_secs(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn set_stalled(&self, stalled: Vec<StalledTask>) {
+        *self.stalled.write() = stalled;
+    }
+
+    pub fn stalled(&self) -> Vec<StalledTask> {
+        self.stalled.read().clone()
+    }
+}
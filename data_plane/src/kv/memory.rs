@@ -0,0 +1,169 @@
+use super::KvStore;
+use crate::error::Result;
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Expired entries are swept once every this many writes
+const PURGE_EVERY_WRITES: u64 = 1024;
+
+#[derive(Debug, Clone)]
+struct Entry {
+    value: Vec<u8>,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn new(value: Vec<u8>, ttl: Option<Duration>) -> Self {
+        Self {
+            value,
+            expires_at: ttl.map(|d| Instant::now() + d),
+        }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|t| t <= now)
+    }
+}
+
+/// Process-local store (default backend)
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: RwLock<HashMap<String, Entry>>,
+    writes: AtomicU64,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop all expired entries
+    pub fn purge_expired(&self) {
+        let now = Instant::now();
+        self.entries.write().retain(|_, e| !e.is_expired(now));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.read().is_empty()
+    }
+
+    fn after_write(&self) {
+        if self.writes.fetch_add(1, Ordering::Relaxed) % PURGE_EVERY_WRITES == PURGE_EVERY_WRITES - 1 {
+            self.purge_expired();
+        }
+    }
+}
+
+#[async_trait]
+impl KvStore for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let now = Instant::now();
+        Ok(self
+            .entries
+            .read()
+            .get(key)
+            .filter(|e| !e.is_expired(now))
+            .map(|e| e.value.clone()))
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<()> {
+        self.entries.write().insert(key.to_string(), Entry::new(value, ttl));
+        self.after_write();
+        Ok(())
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>> {
+        let now = Instant::now();
+        Ok(self
+            .entries
+            .read()
+            .get(key)
+            .filter(|e| !e.is_expired(now))
+            .and_then(|e| e.expires_at)
+            .map(|t| t.saturating_duration_since(now)))
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool> {
+        let now = Instant::now();
+        Ok(self
+            .entries
+            .write()
+            .remove(key)
+            .is_some_and(|e| !e.is_expired(now)))
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<bool> {
+        let now = Instant::now();
+        let mut entries = self.entries.write();
+        let current = entries.get(key).filter(|e| !e.is_expired(now)).map(|e| e.value.as_slice());
+        if current != expected {
+            return Ok(false);
+        }
+        entries.insert(key.to_string(), Entry::new(value, ttl));
+        drop(entries);
+
+        self.after_write();
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_store_roundtrip() {
+        let store = MemoryStore::new();
+        assert_eq!(store.get("k").await.unwrap(), None);
+
+        store.set("k", b"v".to_vec(), None).await.unwrap();
+        assert_eq!(store.get("k").await.unwrap(), Some(b"v".to_vec()));
+        assert_eq!(store.ttl("k").await.unwrap(), None);
+
+        assert!(store.delete("k").await.unwrap());
+        assert!(!store.delete("k").await.unwrap());
+        assert_eq!(store.get("k").await.unwrap(), None);
+
+        // Compare-and-set only writes over the expected value
+        assert!(store.compare_and_set("k", None, b"1".to_vec(), None).await.unwrap());
+        assert!(!store.compare_and_set("k", None, b"2".to_vec(), None).await.unwrap());
+        assert!(!store.compare_and_set("k", Some(b"0"), b"2".to_vec(), None).await.unwrap());
+        assert!(store.compare_and_set("k", Some(b"1"), b"2".to_vec(), None).await.unwrap());
+        assert_eq!(store.get("k").await.unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_expiry() {
+        let store = MemoryStore::new();
+        store
+            .set("short", b"1".to_vec(), Some(Duration::from_millis(20)))
+            .await
+            .unwrap();
+        store
+            .set("long", b"2".to_vec(), Some(Duration::from_secs(60)))
+            .await
+            .unwrap();
+
+        assert!(store.ttl("long").await.unwrap().unwrap() > Duration::from_secs(50));
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(store.get("short").await.unwrap(), None);
+        assert_eq!(store.get("long").await.unwrap(), Some(b"2".to_vec()));
+
+        store.purge_expired();
+        assert_eq!(store.len(), 1);
+    }
+}
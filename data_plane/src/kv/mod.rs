@@ -0,0 +1,65 @@
+//! Pluggable key-value store for state that must be shared across instances
+//! or outlive a restart: support overrides ([`crate::overrides`]) and
+//! exposure cap admissions ([`crate::caps`]).
+//! Evaluation-path caches such as the result cache stay in process, because a
+//! round trip to a shared backend costs more than the evaluation it would
+//! save. Backends other than in-memory sit behind features.
+
+mod memory;
+#[cfg(feature = "redis")]
+mod redis;
+#[cfg(feature = "rocksdb")]
+mod rocksdb;
+
+pub use memory::MemoryStore;
+#[cfg(feature = "redis")]
+pub use self::redis::RedisStore;
+#[cfg(feature = "rocksdb")]
+pub use self::rocksdb::RocksDbStore;
+
+use crate::config::Config;
+use crate::error::{ExperimentError, Result};
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Minimal KV interface: opaque byte values with optional per-key TTL
+#[async_trait]
+pub trait KvStore: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Set a value; `ttl = None` means no expiry
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<()>;
+
+    /// Remaining TTL. `None` when the key is missing or has no expiry.
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>>;
+
+    /// Delete a key, returning whether it existed
+    async fn delete(&self, key: &str) -> Result<bool>;
+
+    /// Set a value only if the key still holds `expected` (`None`: missing or
+    /// expired), atomically across every instance sharing the backend.
+    /// Returns whether the value was written.
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<bool>;
+}
+
+/// Build the store selected by `KV_BACKEND`
+pub async fn from_config(config: &Config) -> Result<Arc<dyn KvStore>> {
+    match config.kv_backend.as_str() {
+        "memory" => Ok(Arc::new(MemoryStore::new())),
+        #[cfg(feature = "redis")]
+        "redis" => Ok(Arc::new(RedisStore::connect(&config.redis_url).await?)),
+        #[cfg(feature = "rocksdb")]
+        "rocksdb" => Ok(Arc::new(RocksDbStore::open(&config.rocksdb_path)?)),
+        other => Err(ExperimentError::Store(format!(
+            "Unsupported KV backend '{}' (is the cargo feature enabled?)",
+            other
+        ))),
+    }
+}
@@ -0,0 +1,91 @@
+use super::KvStore;
+use crate::error::{ExperimentError, Result};
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use std::time::Duration;
+
+/// Redis-backed store (feature `redis`). Uses a multiplexed, auto-reconnecting connection.
+pub struct RedisStore {
+    conn: ConnectionManager,
+}
+
+impl RedisStore {
+    pub async fn connect(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url).map_err(store_err)?;
+        let conn = client.get_connection_manager().await.map_err(store_err)?;
+        Ok(Self { conn })
+    }
+}
+
+fn store_err(e: redis::RedisError) -> ExperimentError {
+    ExperimentError::Store(format!("redis: {}", e))
+}
+
+#[async_trait]
+impl KvStore for RedisStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let mut conn = self.conn.clone();
+        conn.get(key).await.map_err(store_err)
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<()> {
+        let mut conn = self.conn.clone();
+        match ttl {
+            // PSETEX rejects 0; round sub-millisecond TTLs up
+            Some(ttl) => conn
+                .pset_ex(key, value, (ttl.as_millis() as u64).max(1))
+                .await
+                .map_err(store_err),
+            None => conn.set(key, value).await.map_err(store_err),
+        }
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>> {
+        let mut conn = self.conn.clone();
+        // -2 = missing, -1 = no expiry
+        let millis: i64 = conn.pttl(key).await.map_err(store_err)?;
+        Ok((millis >= 0).then(|| Duration::from_millis(millis as u64)))
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool> {
+        let mut conn = self.conn.clone();
+        let removed: i64 = conn.del(key).await.map_err(store_err)?;
+        Ok(removed > 0)
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<bool> {
+        let mut conn = self.conn.clone();
+        let written: i64 = redis::cmd("EVAL")
+            .arg(COMPARE_AND_SET)
+            .arg(1)
+            .arg(key)
+            .arg(i64::from(expected.is_some()))
+            .arg(expected.unwrap_or_default())
+            .arg(value)
+            .arg(ttl.map_or(0, |ttl| (ttl.as_millis() as u64).max(1)))
+            .query_async(&mut conn)
+            .await
+            .map_err(store_err)?;
+        Ok(written == 1)
+    }
+}
+
+/// KEYS[1] = key; ARGV = expected present (0/1), expected, value, ttl millis (0 = none)
+const COMPARE_AND_SET: &str = r#"
+local current = redis.call('GET', KEYS[1])
+if ARGV[1] == '0' and current ~= false then return 0 end
+if ARGV[1] == '1' and current ~= ARGV[2] then return 0 end
+if ARGV[4] == '0' then
+    redis.call('SET', KEYS[1], ARGV[3])
+else
+    redis.call('SET', KEYS[1], ARGV[3], 'PX', ARGV[4])
+end
+return 1
+"#;
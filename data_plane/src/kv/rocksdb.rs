@@ -0,0 +1,184 @@
+use super::KvStore;
+use crate::error::{ExperimentError, Result};
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use rocksdb::DB;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// RocksDB-backed store (feature `rocksdb`).
+///
+/// RocksDB only supports a DB-wide TTL, so each value is prefixed with an
+/// 8-byte big-endian expiry timestamp in unix millis (0 = no expiry) and
+/// expired entries are treated as missing on read. The database is owned by a
+/// single process, so compare-and-set only has to exclude this process's
+/// other writers. Reads and writes hit the disk, so every call runs on the
+/// blocking pool rather than a runtime worker.
+pub struct RocksDbStore {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    db: DB,
+    /// Held across every read and write so compare-and-set is atomic
+    cas: Mutex<()>,
+}
+
+const NO_EXPIRY: u64 = 0;
+
+impl RocksDbStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let db = DB::open_default(path).map_err(store_err)?;
+        Ok(Self {
+            inner: Arc::new(Inner { db, cas: Mutex::new(()) }),
+        })
+    }
+
+    /// Run `op` on the blocking pool with the compare-and-set lock held
+    async fn blocking<T: Send + 'static>(&self, op: impl FnOnce(&Inner) -> Result<T> + Send + 'static) -> Result<T> {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || {
+            let _cas = inner.cas.lock();
+            op(&inner)
+        })
+        .await
+        .map_err(|e| ExperimentError::Store(format!("rocksdb: {}", e)))?
+    }
+}
+
+impl Inner {
+    /// Returns (value, expires_at_millis) for a live entry, deleting an
+    /// expired one. Callers hold `cas`.
+    fn read_live(&self, key: &str) -> Result<Option<(Vec<u8>, u64)>> {
+        let Some(raw) = self.db.get(key).map_err(store_err)? else {
+            return Ok(None);
+        };
+        if raw.len() < 8 {
+            return Err(ExperimentError::Store(format!(
+                "rocksdb: corrupt entry for key '{}'",
+                key
+            )));
+        }
+        let (header, value) = raw.split_at(8);
+        let expires_at = u64::from_be_bytes(header.try_into().expect("8-byte header"));
+        if expires_at != NO_EXPIRY && expires_at <= now_millis() {
+            self.db.delete(key).map_err(store_err)?;
+            return Ok(None);
+        }
+        Ok(Some((value.to_vec(), expires_at)))
+    }
+
+    fn write(&self, key: &str, value: &[u8], ttl: Option<Duration>) -> Result<()> {
+        let expires_at = ttl.map_or(NO_EXPIRY, |d| now_millis() + (d.as_millis() as u64).max(1));
+        let mut raw = Vec::with_capacity(8 + value.len());
+        raw.extend_from_slice(&expires_at.to_be_bytes());
+        raw.extend_from_slice(value);
+        self.db.put(key, raw).map_err(store_err)
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn store_err(e: rocksdb::Error) -> ExperimentError {
+    ExperimentError::Store(format!("rocksdb: {}", e))
+}
+
+#[async_trait]
+impl KvStore for RocksDbStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let key = key.to_string();
+        self.blocking(move |inner| Ok(inner.read_live(&key)?.map(|(value, _)| value)))
+            .await
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<()> {
+        let key = key.to_string();
+        self.blocking(move |inner| inner.write(&key, &value, ttl)).await
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>> {
+        let key = key.to_string();
+        self.blocking(move |inner| {
+            Ok(inner.read_live(&key)?.and_then(|(_, expires_at)| {
+                (expires_at != NO_EXPIRY)
+                    .then(|| Duration::from_millis(expires_at.saturating_sub(now_millis())))
+            }))
+        })
+        .await
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool> {
+        let key = key.to_string();
+        self.blocking(move |inner| {
+            let existed = inner.read_live(&key)?.is_some();
+            inner.db.delete(&key).map_err(store_err)?;
+            Ok(existed)
+        })
+        .await
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<bool> {
+        let key = key.to_string();
+        let expected = expected.map(<[u8]>::to_vec);
+        self.blocking(move |inner| {
+            let current = inner.read_live(&key)?;
+            if current.map(|(value, _)| value) != expected {
+                return Ok(false);
+            }
+            inner.write(&key, &value, ttl)?;
+            Ok(true)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_rocksdb_store_roundtrip_and_expiry() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = RocksDbStore::open(temp_dir.path()).unwrap();
+        assert_eq!(store.get("k").await.unwrap(), None);
+
+        store.set("k", b"v".to_vec(), None).await.unwrap();
+        assert_eq!(store.get("k").await.unwrap(), Some(b"v".to_vec()));
+        assert_eq!(store.ttl("k").await.unwrap(), None);
+        store
+            .set("short", b"1".to_vec(), Some(Duration::from_millis(20)))
+            .await
+            .unwrap();
+        store
+            .set("long", b"2".to_vec(), Some(Duration::from_secs(60)))
+            .await
+            .unwrap();
+        assert!(store.ttl("long").await.unwrap().unwrap() > Duration::from_secs(50));
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(store.get("short").await.unwrap(), None);
+        assert_eq!(store.ttl("short").await.unwrap(), None);
+        assert!(!store.delete("short").await.unwrap());
+
+        // Entries and their expiry survive reopening
+        drop(store);
+        let store = RocksDbStore::open(temp_dir.path()).unwrap();
+        assert_eq!(store.get("long").await.unwrap(), Some(b"2".to_vec()));
+        assert!(store.ttl("long").await.unwrap().unwrap() > Duration::from_secs(50));
+        assert!(store.delete("k").await.unwrap());
+        assert_eq!(store.get("k").await.unwrap(), None);
+    }
+}
@@ -1,36 +1,225 @@
-use crate::catalog::{ExperimentCatalog, VariantDef};
-use crate::error::{ExperimentError, Result};
+use crate::aliases::{IdentityAliases, IdentityPolicy};
+use crate::applied::{self, AppliedChange, AppliedEntry};
+use crate::catalog::ExperimentCatalog;
+use crate::config::migrate::{self, GroupMapping};
+use crate::emergency::EmergencyOverrides;
+use crate::exclusion::Exclusions;
+use crate::clock::Clock;
+use crate::field_alias::FieldAliases;
+use crate::number_coercion::LenientNumbers;
+use crate::error::{ErrorContext, ExperimentError, ResourceKind, Result};
+use crate::events::{DisableReason, EngineEvent, EventBus};
+use crate::expiry::Expired;
+use crate::freeze::{self, FreezeSchedule};
+use crate::metrics::{self, ChangeCounts};
+use crate::rule::compiled::CompiledRule;
+use crate::rule::semver::SemverPolicy;
+use crate::rule::Node;
+use crate::rule_optimizer::{self, OptimizedRules};
+use crate::shard::ShardedMap;
+use crate::source::{precedence, SourceConflict};
 use arc_swap::ArcSwap;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 /// Bucket size (10000 slots = 0.01% granularity)
 pub const BUCKET_SIZE: u32 = 10000;
 
+/// Snapshot changes kept for [`LayerManager::recent_changes`]
+const RECENT_CHANGES: usize = 50;
+
 /// Explicit bucket range mapping
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct BucketRange {
     pub start: u32,
     pub end: u32,
     pub vid: i64,
+
+    /// Human-readable arm name (e.g. "control", "treatment-a") for reporting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Context field(s) a layer buckets subjects by.
+///
+/// Configured as one field (`"hash_key": "user_id"`) or as a composite
+/// (`"hash_key": ["user_id", "campaign_id"]`) to randomize per combination.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "HashKeyConfig", into = "HashKeyConfig")]
+pub struct HashKey(Vec<String>);
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum HashKeyConfig {
+    Field(String),
+    Composite(Vec<String>),
+}
+
+impl TryFrom<HashKeyConfig> for HashKey {
+    type Error = String;
+
+    fn try_from(cfg: HashKeyConfig) -> std::result::Result<Self, String> {
+        let fields = match cfg {
+            HashKeyConfig::Field(field) => vec![field],
+            HashKeyConfig::Composite(fields) => fields,
+        };
+        if fields.is_empty() {
+            return Err("hash_key must name at least one field".to_string());
+        }
+        let unique: HashSet<&String> = fields.iter().collect();
+        if unique.len() != fields.len() {
+            return Err(format!("hash_key lists a field twice: {:?}", fields));
+        }
+        Ok(Self(fields))
+    }
+}
+
+impl From<HashKey> for HashKeyConfig {
+    fn from(key: HashKey) -> Self {
+        match <[String; 1]>::try_from(key.0) {
+            Ok([field]) => HashKeyConfig::Field(field),
+            Err(fields) => HashKeyConfig::Composite(fields),
+        }
+    }
+}
+
+impl From<&str> for HashKey {
+    fn from(field: &str) -> Self {
+        Self(vec![field.to_string()])
+    }
+}
+
+impl From<String> for HashKey {
+    fn from(field: String) -> Self {
+        Self(vec![field])
+    }
+}
+
+impl std::fmt::Display for HashKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0.join("+"))
+    }
+}
+
+/// Separates composite hash key values (cannot appear in typical ids)
+const COMPOSITE_SEPARATOR: char = '\u{1f}';
+
+/// Why a subject has no hash key value for a layer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HashKeyError {
+    Missing(String),
+    NotScalar(String),
+}
+
+impl std::fmt::Display for HashKeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HashKeyError::Missing(field) => write!(f, "hash key field '{}' not found in context", field),
+            HashKeyError::NotScalar(field) => write!(f, "hash key field '{}' must be a string or number", field),
+        }
+    }
+}
+
+impl HashKey {
+    pub fn fields(&self) -> &[String] {
+        &self.0
+    }
+
+    pub fn is_composite(&self) -> bool {
+        self.0.len() > 1
+    }
+
+    /// Value hashed into a bucket. A single field is used as-is (numbers as
+    /// their decimal form); composite values are joined in declared order with
+    /// U+001F, so `["a", "b"]` never collides with another split of the same text.
+    pub fn value<'a>(&self, context: &'a HashMap<String, serde_json::Value>) -> std::result::Result<Cow<'a, str>, HashKeyError> {
+        self.value_in(context, None, None)
+    }
+
+    /// [`HashKey::value`] of the subject as served under `policy`: the stable
+    /// id field falls back to the anonymous id
+    pub fn subject_value<'a>(
+        &self,
+        context: &'a HashMap<String, serde_json::Value>,
+        policy: Option<&IdentityPolicy>,
+    ) -> std::result::Result<Cow<'a, str>, HashKeyError> {
+        self.value_in(context, None, policy)
+    }
+
+    /// [`HashKey::subject_value`] with each field's value replaced by its identity alias, if any
+    pub fn aliased_value<'a>(
+        &self,
+        context: &'a HashMap<String, serde_json::Value>,
+        aliases: &'a IdentityAliases,
+        policy: Option<&IdentityPolicy>,
+    ) -> std::result::Result<Cow<'a, str>, HashKeyError> {
+        self.value_in(context, (!aliases.is_empty()).then_some(aliases), policy)
+    }
+
+    fn value_in<'a>(
+        &self,
+        context: &'a HashMap<String, serde_json::Value>,
+        aliases: Option<&'a IdentityAliases>,
+        policy: Option<&IdentityPolicy>,
+    ) -> std::result::Result<Cow<'a, str>, HashKeyError> {
+        let field_value = |field: &String| {
+            let value = match policy.map_or_else(|| context.get(field), |p| p.lookup(field, context)) {
+                Some(serde_json::Value::String(s)) => Cow::Borrowed(s.as_str()),
+                Some(serde_json::Value::Number(n)) => Cow::Owned(n.to_string()),
+                Some(_) => return Err(HashKeyError::NotScalar(field.clone())),
+                None => return Err(HashKeyError::Missing(field.clone())),
+            };
+            match aliases.and_then(|aliases| aliases.resolve(field, &value)) {
+                Some(alias) => Ok(Cow::Borrowed(alias)),
+                None => Ok(value),
+            }
+        };
+
+        if let [field] = self.0.as_slice() {
+            return field_value(field);
+        }
+        let mut joined = String::new();
+        for (i, field) in self.0.iter().enumerate() {
+            if i > 0 {
+                joined.push(COMPOSITE_SEPARATOR);
+            }
+            joined.push_str(&field_value(field)?);
+        }
+        Ok(Cow::Owned(joined))
+    }
 }
 
 /// Layer definition (runtime)
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Layer {
     pub layer_id: String,
     pub version: String,
     pub priority: i32,
-    pub hash_key: String,
+    pub hash_key: HashKey,
 
     /// Optional salt for hash calculation
     /// If not provided, defaults to "{layer_id}_{version}"
     #[serde(default)]
     pub salt: Option<String>,
 
+    /// Number of buckets subjects are hashed into; defaults to [`BUCKET_SIZE`].
+    /// Ranges are validated against it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket_size: Option<u32>,
+
+    /// Unix seconds after which the layer stops serving (see [`crate::expiry`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+
+    /// Layer-level targeting, evaluated before bucket hashing. Subjects that
+    /// don't match skip the whole layer; experiment rules still apply on top.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<crate::rule::Node>,
+
     /// DEPRECATED: Services this layer may affect.
     /// Now inferred from catalog via ranges->vids during index build.
     /// Keep for backward compatibility but no longer used in new logic.
@@ -43,22 +232,37 @@ pub struct Layer {
 
     #[serde(default)]
     pub enabled: bool,
+
+    /// A/A test: ranges split traffic between two variants with identical
+    /// params, to validate randomization end to end (see [`crate::aa_test`])
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub aa_test: bool,
 }
 
 /// Backward/forward compatible config schema.
 ///
 /// - New format: `ranges: [{start,end,vid}, ...]` + `services: [...]`
-/// - Backward compat: `buckets` + `groups` (boundary encoding) will be converted into `ranges`
+/// - Backward compat: `buckets` (boundary encoding) and `ranges.group` naming a vid
+///   are converted into `ranges`; inline `groups` are rejected with a migration hint
 #[derive(Debug, Clone, Deserialize)]
 struct LayerConfig {
     pub layer_id: String,
     pub version: String,
     pub priority: i32,
-    pub hash_key: String,
+    pub hash_key: HashKey,
 
     #[serde(default)]
     pub salt: Option<String>,
 
+    #[serde(default)]
+    pub bucket_size: Option<u32>,
+
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+
+    #[serde(default)]
+    pub rule: Option<crate::rule::Node>,
+
     #[serde(default)]
     pub services: Vec<String>,
 
@@ -68,19 +272,28 @@ struct LayerConfig {
     #[serde(default)]
     pub ranges: Vec<BucketRangeConfig>,
 
+    #[serde(default)]
+    pub aa_test: bool,
+
     /// Deprecated: boundary buckets, converted into `ranges`
     #[serde(default)]
     pub buckets: HashMap<u32, String>,
 
-    /// Deprecated: inline groups, used only to resolve legacy `buckets`/`ranges.group`
+    /// Legacy inline experiment definitions; only their presence is checked
     #[serde(default)]
-    pub groups: HashMap<String, VariantDef>,
+    pub groups: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(untagged)]
 enum BucketRangeConfig {
-    Vid { start: u32, end: u32, vid: i64 },
+    Vid {
+        start: u32,
+        end: u32,
+        vid: i64,
+        #[serde(default)]
+        label: Option<String>,
+    },
     Group { start: u32, end: u32, group: String },
 }
 
@@ -93,16 +306,91 @@ impl Layer {
             .unwrap_or_else(|| format!("{}_{}", self.layer_id, self.version))
     }
 
+    /// Buckets subjects are hashed into (explicit or [`BUCKET_SIZE`])
+    pub fn bucket_size(&self) -> u32 {
+        self.bucket_size.unwrap_or(BUCKET_SIZE)
+    }
+
     pub fn from_file(path: &Path) -> Result<Self> {
+        Self::from_file_strict(path, false)
+    }
+
+    /// Load a layer file; in strict mode deprecated fields are rejected instead of converted.
+    ///
+    /// Errors carry the layer id (when readable) and file path/line.
+    pub fn from_file_strict(path: &Path, strict: bool) -> Result<Self> {
+        Self::load_file(path, strict, None)
+    }
+
+    /// [`Self::from_file_strict`] for a layer to be indexed against `catalog`:
+    /// ids suggested for inline groups are allocated around its experiments
+    pub fn from_file_against(path: &Path, strict: bool, catalog: &ExperimentCatalog) -> Result<Self> {
+        Self::load_file(path, strict, Some(catalog))
+    }
+
+    /// Build a layer from an already-parsed document (e.g. a preview candidate)
+    pub fn from_value(doc: serde_json::Value, strict: bool) -> Result<Self> {
+        Self::load_value(doc, strict, None)
+    }
+
+    /// [`Self::from_value`] for a layer to be indexed against `catalog`
+    pub fn from_value_against(doc: serde_json::Value, strict: bool, catalog: &ExperimentCatalog) -> Result<Self> {
+        Self::load_value(doc, strict, Some(catalog))
+    }
+
+    fn load_file(path: &Path, strict: bool, catalog: Option<&ExperimentCatalog>) -> Result<Self> {
+        let mut context = ErrorContext::new(ResourceKind::Layer).with_path(path);
+        Self::parse_file(path, strict, catalog, &mut context).map_err(|e| e.with_context(context))
+    }
+
+    fn load_value(doc: serde_json::Value, strict: bool, catalog: Option<&ExperimentCatalog>) -> Result<Self> {
+        let mut context = ErrorContext::new(ResourceKind::Layer);
+        Self::from_document(doc, strict, catalog, &mut context).map_err(|e| e.with_context(context))
+    }
+
+    fn parse_file(
+        path: &Path,
+        strict: bool,
+        catalog: Option<&ExperimentCatalog>,
+        context: &mut ErrorContext,
+    ) -> Result<Self> {
         let content = std::fs::read_to_string(path)?;
+        let doc = migrate::parse_document(&content)?;
+        Self::from_document(doc, strict, catalog, context)
+    }
+
+    fn from_document(
+        doc: serde_json::Value,
+        strict: bool,
+        catalog: Option<&ExperimentCatalog>,
+        context: &mut ErrorContext,
+    ) -> Result<Self> {
+        if let Some(layer_id) = doc.get("layer_id").and_then(|v| v.as_str()) {
+            context.id = Some(layer_id.to_string());
+        }
+
+        // Checked on the file as written: upgrading a v1 file converts these away
+        if strict {
+            let deprecated = migrate::deprecated_layer_fields(&doc);
+            if !deprecated.is_empty() {
+                return Err(ExperimentError::DeprecatedConfig(format!(
+                    "uses deprecated field(s): {} (run `migrate-config`)",
+                    deprecated.join(", ")
+                )));
+            }
+        }
 
-        // Try JSON first, then YAML
-        let cfg: LayerConfig = serde_json::from_str(&content)
-            .or_else(|_| serde_yaml::from_str(&content).map_err(ExperimentError::from))?;
+        // Versioned files are upgraded to the current schema before parsing
+        let doc = migrate::upgrade_layer(doc)?;
+        let cfg: LayerConfig = serde_json::from_value(doc.clone())?;
 
-        let layer = Self::try_from_config(cfg)?;
+        // Their params and rules would be silently dropped; point at the catalog instead
+        if !cfg.groups.is_empty() {
+            let plan = migrate::inline_group_plan(&doc, catalog.unwrap_or(&ExperimentCatalog::default()));
+            return Err(ExperimentError::InlineGroups(plan));
+        }
 
-        Ok(layer)
+        Self::try_from_config(cfg)
     }
 
     fn try_from_config(mut cfg: LayerConfig) -> Result<Self> {
@@ -110,6 +398,8 @@ impl Layer {
         cfg.services = normalize_services(cfg.services);
         // Note: services will be inferred from catalog during index build
 
+        let bucket_size = cfg.bucket_size.unwrap_or(BUCKET_SIZE);
+
         // Normalize ranges
         let mut ranges: Vec<BucketRange> = Vec::new();
 
@@ -117,14 +407,26 @@ impl Layer {
             ranges = cfg
                 .ranges
                 .into_iter()
-                .map(|r| resolve_range(r, &cfg.groups))
+                .map(resolve_range)
                 .collect::<Result<Vec<_>>>()?;
         } else if !cfg.buckets.is_empty() {
             // Backward compat: treat buckets as boundary encoding
-            ranges = convert_buckets_to_ranges(&cfg.buckets, &cfg.groups)?;
+            ranges = convert_buckets_to_ranges(&cfg.buckets, bucket_size)?;
         }
 
-        validate_and_sort_ranges(&mut ranges)?;
+        validate_and_sort_ranges(&mut ranges, bucket_size)?;
+        if let Some(rule) = &cfg.rule {
+            rule.compile_expressions()?;
+        }
+        if cfg.aa_test {
+            let vids: BTreeSet<i64> = ranges.iter().map(|r| r.vid).collect();
+            if vids.len() != 2 {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "A/A test layer must split traffic between exactly 2 vids, found {}",
+                    vids.len()
+                )));
+            }
+        }
 
         Ok(Self {
             layer_id: cfg.layer_id,
@@ -132,19 +434,61 @@ impl Layer {
             priority: cfg.priority,
             hash_key: cfg.hash_key,
             salt: cfg.salt,
+            bucket_size: cfg.bucket_size,
+            expires_at: cfg.expires_at,
+            rule: cfg.rule,
             services: cfg.services,
             ranges,
             enabled: cfg.enabled,
+            aa_test: cfg.aa_test,
         })
     }
 
+    /// Fraction of buckets not covered by any range
+    pub fn uncovered_share(&self) -> f64 {
+        let uncovered: u32 = self.uncovered_ranges().iter().map(|(start, end)| end - start).sum();
+        uncovered as f64 / self.bucket_size() as f64
+    }
+
+    /// Fraction of buckets assigned to vids of `service` (rules not applied)
+    pub fn service_share(&self, service: &str, catalog: &ExperimentCatalog) -> f64 {
+        let covered: u32 = self
+            .ranges
+            .iter()
+            .filter(|r| matches!(catalog.variant_owner(r.vid), Some((_, s)) if s == service))
+            .map(|r| r.end - r.start)
+            .sum();
+        covered as f64 / self.bucket_size() as f64
+    }
+
+    /// Bucket spans `[start, end)` not covered by any range, in order
+    pub fn uncovered_ranges(&self) -> Vec<(u32, u32)> {
+        let mut holes = Vec::new();
+        let mut next = 0;
+        for range in &self.ranges {
+            if range.start > next {
+                holes.push((next, range.start));
+            }
+            next = next.max(range.end);
+        }
+        if next < self.bucket_size() {
+            holes.push((next, self.bucket_size()));
+        }
+        holes
+    }
+
     /// Get matched VID for a bucket/slot.
     ///
     /// Returns `None` when the slot is not covered by any range (hole/unoccupied).
     ///
     /// Uses binary search (O(log n)) since ranges are sorted by start.
     pub fn get_vid(&self, bucket: u32) -> Option<i64> {
-        if bucket >= BUCKET_SIZE {
+        self.get_range(bucket).map(|r| r.vid)
+    }
+
+    /// Range covering a bucket/slot, if any
+    pub fn get_range(&self, bucket: u32) -> Option<&BucketRange> {
+        if bucket >= self.bucket_size() {
             return None;
         }
 
@@ -155,12 +499,20 @@ impl Layer {
         if pos > 0 {
             let candidate = &self.ranges[pos - 1];
             if bucket < candidate.end {
-                return Some(candidate.vid);
+                return Some(candidate);
             }
         }
 
         None
     }
+
+    /// Label of the first labelled range assigned to `vid`
+    pub fn label_for(&self, vid: i64) -> Option<&str> {
+        self.ranges
+            .iter()
+            .find(|r| r.vid == vid && r.label.is_some())
+            .and_then(|r| r.label.as_deref())
+    }
 }
 
 fn normalize_services(services: Vec<String>) -> Vec<String> {
@@ -177,64 +529,52 @@ fn normalize_services(services: Vec<String>) -> Vec<String> {
     v
 }
 
-fn resolve_range(r: BucketRangeConfig, groups: &HashMap<String, VariantDef>) -> Result<BucketRange> {
+fn resolve_range(r: BucketRangeConfig) -> Result<BucketRange> {
     match r {
-        BucketRangeConfig::Vid { start, end, vid } => Ok(BucketRange { start, end, vid }),
-        BucketRangeConfig::Group { start, end, group } => {
-            if let Ok(vid) = group.parse::<i64>() {
-                return Ok(BucketRange { start, end, vid });
-            }
-            let def = groups
-                .get(&group)
-                .ok_or_else(|| ExperimentError::GroupNotFound(group.clone()))?;
-            Ok(BucketRange {
-                start,
-                end,
-                vid: def.vid,
-            })
-        }
+        BucketRangeConfig::Vid { start, end, vid, label } => Ok(BucketRange { start, end, vid, label }),
+        BucketRangeConfig::Group { start, end, group } => Ok(BucketRange {
+            start,
+            end,
+            vid: group_vid(&group)?,
+            label: None,
+        }),
     }
 }
 
-fn convert_buckets_to_ranges(
-    buckets: &HashMap<u32, String>,
-    groups: &HashMap<String, VariantDef>,
-) -> Result<Vec<BucketRange>> {
-    if buckets.is_empty() {
-        return Ok(Vec::new());
-    }
+/// Legacy group reference: without inline `groups` it can only name a vid
+fn group_vid(group: &str) -> Result<i64> {
+    group
+        .parse::<i64>()
+        .map_err(|_| ExperimentError::GroupNotFound(group.to_string()))
+}
 
-    let mut boundaries: Vec<(u32, String)> = buckets
-        .iter()
-        .map(|(k, v)| (*k, v.clone()))
-        .collect();
+/// Boundary encoding: each bucket key starts a range that ends at the next key
+/// (the last one at `bucket_size`)
+fn convert_buckets_to_ranges(buckets: &HashMap<u32, String>, bucket_size: u32) -> Result<Vec<BucketRange>> {
+    let mut boundaries: Vec<(u32, &String)> = buckets.iter().map(|(k, v)| (*k, v)).collect();
     boundaries.sort_by_key(|(k, _)| *k);
 
-    let mut ranges = Vec::with_capacity(boundaries.len());
-
-    for i in 0..boundaries.len() {
-        let (start, group_id) = &boundaries[i];
-        let end = if i + 1 < boundaries.len() {
-            boundaries[i + 1].0
-        } else {
-            BUCKET_SIZE
-        };
-
-        let def = groups
-            .get(group_id)
-            .ok_or_else(|| ExperimentError::GroupNotFound(group_id.clone()))?;
-
-        ranges.push(BucketRange {
-            start: *start,
-            end,
-            vid: def.vid,
-        });
-    }
-
-    Ok(ranges)
+    boundaries
+        .iter()
+        .enumerate()
+        .map(|(i, (start, group))| {
+            let end = boundaries.get(i + 1).map_or(bucket_size, |(next, _)| *next);
+            Ok(BucketRange {
+                start: *start,
+                end,
+                vid: group_vid(group)?,
+                label: None,
+            })
+        })
+        .collect()
 }
 
-fn validate_and_sort_ranges(ranges: &mut Vec<BucketRange>) -> Result<()> {
+pub(crate) fn validate_and_sort_ranges(ranges: &mut [BucketRange], bucket_size: u32) -> Result<()> {
+    if bucket_size == 0 {
+        return Err(ExperimentError::InvalidParameter(
+            "Invalid bucket_size: must be > 0".to_string(),
+        ));
+    }
     for r in ranges.iter() {
         if r.start >= r.end {
             return Err(ExperimentError::InvalidParameter(format!(
@@ -242,10 +582,10 @@ fn validate_and_sort_ranges(ranges: &mut Vec<BucketRange>) -> Result<()> {
                 r.start, r.end
             )));
         }
-        if r.end > BUCKET_SIZE {
+        if r.end > bucket_size {
             return Err(ExperimentError::InvalidParameter(format!(
-                "Invalid range: end {} exceeds BUCKET_SIZE {}",
-                r.end, BUCKET_SIZE
+                "Invalid range: end {} exceeds bucket size {}",
+                r.end, bucket_size
             )));
         }
     }
@@ -253,6 +593,19 @@ fn validate_and_sort_ranges(ranges: &mut Vec<BucketRange>) -> Result<()> {
     // Sort for determinism and to enable overlap check
     ranges.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.end.cmp(&b.end)));
 
+    // A label names one arm: it can't point at two different vids
+    let mut labels: HashMap<&str, i64> = HashMap::new();
+    for r in ranges.iter() {
+        if let Some(label) = r.label.as_deref() {
+            if let Some(other) = labels.insert(label, r.vid).filter(|v| *v != r.vid) {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "Range label '{}' used for vids {} and {}",
+                    label, other, r.vid
+                )));
+            }
+        }
+    }
+
     // Check overlap
     for w in ranges.windows(2) {
         let prev = &w[0];
@@ -268,207 +621,1473 @@ fn validate_and_sort_ranges(ranges: &mut Vec<BucketRange>) -> Result<()> {
     Ok(())
 }
 
+/// Bucket share of one variant within a layer
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VariantCoverage {
+    pub eid: i64,
+    pub vid: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub percent: f64,
+}
+
+/// A layer's position in a service's merge order
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LayerResolution {
+    /// 1-based; lower ranks win parameter conflicts
+    pub rank: usize,
+    pub layer_id: String,
+    pub version: String,
+    pub priority: i32,
+    /// Effective salt (explicit or `{layer_id}_{version}`)
+    pub salt: String,
+    pub hash_key: HashKey,
+    /// Percent of buckets assigned to this service's vids
+    pub coverage_percent: f64,
+    pub variants: Vec<VariantCoverage>,
+}
+
+/// Effective layer merge order for a service
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResolutionOrder {
+    pub service: String,
+    /// Percent of subjects assigned at least one variant (rules not applied)
+    pub coverage_percent: f64,
+    pub layers: Vec<LayerResolution>,
+}
+
+/// Result of parsing a layers directory
+#[derive(Debug, Default)]
+struct ParsedLayers {
+    layers: HashMap<String, LayerVersion>,
+    load_errors: BTreeMap<PathBuf, LoadError>,
+    strict_violations: Vec<String>,
+    conflicts: Vec<SourceConflict>,
+}
+
+/// Strict mode: fail the whole load with per-file diagnostics
+fn check_strict_violations(mut violations: Vec<String>) -> Result<()> {
+    if violations.is_empty() {
+        return Ok(());
+    }
+    violations.sort();
+    Err(ExperimentError::DeprecatedConfig(format!(
+        "{} layer file(s) rejected:\n  {}",
+        violations.len(),
+        violations.join("\n  ")
+    )))
+}
+
 /// Layer version tracking
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 struct LayerVersion {
     layer: Arc<Layer>,
     file_path: PathBuf,
 }
 
-/// Layer Manager - manages all layers with hot reload support
-pub struct LayerManager {
-    pub(crate) layers_dir: PathBuf,
-
-    /// layer_id -> LayerVersion
-    layers: Arc<ArcSwap<HashMap<String, LayerVersion>>>,
+/// layer_id -> LayerVersion, sharded by layer_id (see [`crate::shard`])
+type LayerMap = ShardedMap<LayerVersion>;
 
-    /// Service → Layers inverted index for sparse matrix optimization
-    /// service -> [layer_id] (sorted by priority)
-    service_index: Arc<ArcSwap<HashMap<String, Vec<String>>>>,
-
-    /// Rollback history: layer_id -> previous versions
-    history: Arc<RwLock<HashMap<String, Vec<Arc<Layer>>>>>,
+/// Most recent load failure for a layer file
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadError {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<ErrorContext>,
+    pub message: String,
+    /// Catalog entries `migrate-config` would create for rejected inline groups
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub migration: Vec<GroupMapping>,
+    /// Unix timestamp (seconds)
+    pub at: u64,
 }
 
-impl LayerManager {
-    pub fn new(layers_dir: PathBuf) -> Self {
+impl LoadError {
+    fn from_error(e: &ExperimentError) -> Self {
+        let migration = match e.inner() {
+            ExperimentError::InlineGroups(plan) => plan.clone(),
+            _ => Vec::new(),
+        };
         Self {
-            layers_dir,
-            layers: Arc::new(ArcSwap::from_pointee(HashMap::new())),
-            service_index: Arc::new(ArcSwap::from_pointee(HashMap::new())),
-            history: Arc::new(RwLock::new(HashMap::new())),
+            context: e.context().cloned(),
+            message: e.inner().to_string(),
+            migration,
+            at: unix_now(),
         }
     }
+}
 
-    /// Rebuild service inverted index (inferred from catalog via ranges->vids)
-    ///
-    /// NEW LOGIC: For each layer, collect all vids from ranges, then reverse-query
-    /// catalog (vid → eid → service) to determine which services this layer affects.
-    fn rebuild_service_index(&self, layers_map: &HashMap<String, LayerVersion>, catalog: &ExperimentCatalog) {
-        let mut service_to_layers: HashMap<String, Vec<(String, i32)>> = HashMap::new();
+/// Compiled rules for `layers`, reusing `current`'s where unchanged
+fn optimized_rules(current: &Snapshot, layers: &LayerMap) -> Arc<OptimizedRules> {
+    let layer_rules = layers.map_shards(&current.layers, &current.rules.layers, |layer_id, version| {
+        let rule = version.layer.rule.as_ref()?;
+        let reused = current
+            .layers
+            .get(layer_id)
+            .filter(|previous| Arc::ptr_eq(&previous.layer, &version.layer))
+            .and_then(|_| current.rules.layers.get(layer_id).cloned());
+        Some(reused.unwrap_or_else(|| Arc::new(CompiledRule::compile(&rule_optimizer::optimize(rule)))))
+    });
+    Arc::new(OptimizedRules { layers: layer_rules })
+}
 
-        for (layer_id, layer_ver) in layers_map {
-            if !layer_ver.layer.enabled {
-                continue;
+/// Layers added, removed or modified going from `old` to `new`
+fn layer_changes(old: &LayerMap, new: &LayerMap) -> ChangeCounts {
+    let mut counts = ChangeCounts::default();
+    for (old, new) in new.changed_shards(old) {
+        let shard = ChangeCounts::between(old, new);
+        counts.added += shard.added;
+        counts.removed += shard.removed;
+        counts.modified += shard.modified;
+    }
+    counts
+}
+
+/// Per-resource changes between `current` and the layers / catalog about to be published
+fn applied_entries(
+    current: &Snapshot,
+    layers: &LayerMap,
+    catalog: &Arc<ExperimentCatalog>,
+    epoch: u64,
+    at: u64,
+) -> Vec<AppliedEntry> {
+    let entry = |resource: &str, id: String, change, version, hash, source: String| AppliedEntry {
+        at,
+        epoch,
+        resource: resource.to_string(),
+        id,
+        change,
+        version,
+        hash,
+        source,
+    };
+
+    let mut entries = Vec::new();
+    for (previous_shard, shard) in layers.changed_shards(&current.layers) {
+        for (layer_id, lv) in shard {
+            let change = match previous_shard.get(layer_id) {
+                None => AppliedChange::Added,
+                Some(previous) if previous != lv => AppliedChange::Modified,
+                Some(_) => continue,
+            };
+            entries.push(entry(
+                "layer",
+                layer_id.clone(),
+                change,
+                Some(lv.layer.version.clone()),
+                Some(applied::content_hash(lv.layer.as_ref())),
+                lv.file_path.display().to_string(),
+            ));
+        }
+        for (layer_id, lv) in previous_shard {
+            if !shard.contains_key(layer_id) {
+                let source = lv.file_path.display().to_string();
+                entries.push(entry("layer", layer_id.clone(), AppliedChange::Removed, None, None, source));
             }
+        }
+    }
+    entries.sort_by(|a, b| a.id.cmp(&b.id));
+
+    if !Arc::ptr_eq(&current.catalog, catalog) {
+        let source = catalog.source_dir().display().to_string();
+        for (eid, change) in current.catalog.experiment_changes(catalog) {
+            let hash = (change != AppliedChange::Removed)
+                .then(|| catalog.get_experiment(eid).map(|exp| applied::content_hash(exp.as_ref())))
+                .flatten();
+            entries.push(entry("experiment", eid.to_string(), change, None, hash, source.clone()));
+        }
+    }
+    entries
+}
 
-            // Collect all vids from ranges
-            let vids: Vec<i64> = layer_ver.layer.ranges.iter().map(|r| r.vid).collect();
+/// Layers serving in `current` that stop serving in the snapshot being published
+fn disabled_layers(
+    current: &Snapshot,
+    layers: &LayerMap,
+    emergency: &EmergencyOverrides,
+    expired: &Expired,
+    epoch: u64,
+) -> Vec<EngineEvent> {
+    let mut disabled: Vec<(&String, DisableReason)> = current
+        .layers
+        .iter()
+        .filter(|(layer_id, lv)| {
+            lv.layer.enabled
+                && !current.emergency.layers.contains(*layer_id)
+                && !current.expired.layers.contains_key(*layer_id)
+        })
+        .filter_map(|(layer_id, _)| {
+            let reason = if !layers.get(layer_id).is_some_and(|lv| lv.layer.enabled) {
+                DisableReason::Config
+            } else if emergency.layers.contains(layer_id) {
+                DisableReason::Emergency
+            } else if expired.layers.contains_key(layer_id) {
+                DisableReason::Expired
+            } else {
+                return None;
+            };
+            Some((layer_id, reason))
+        })
+        .collect();
+    disabled.sort();
+
+    disabled
+        .into_iter()
+        .map(|(layer_id, reason)| EngineEvent::LayerDisabled {
+            epoch,
+            layer_id: layer_id.clone(),
+            reason,
+        })
+        .collect()
+}
 
-            // Reverse-query catalog to get services
-            let mut services = std::collections::HashSet::new();
-            for vid in vids {
-                if let Some((_, service, _, _)) = catalog.get_variant(vid) {
-                    services.insert(service.to_string());
-                } else {
-                    tracing::warn!(
-                        "Layer {} references unknown vid {} (catalog may be incomplete)",
-                        layer_id,
-                        vid
-                    );
-                }
-            }
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
 
-            // Build inverted index
-            for service in services {
-                service_to_layers
-                    .entry(service)
-                    .or_insert_with(Vec::new)
-                    .push((layer_id.clone(), layer_ver.layer.priority));
-            }
-        }
+/// One published snapshot that changed layers, experiments or emergency overrides
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigChange {
+    pub epoch: u64,
+    /// Unix timestamp (seconds)
+    pub at: u64,
+    pub layers: ChangeCounts,
+    pub experiments: ChangeCounts,
+    pub emergency_overrides: bool,
+}
 
-        // Sort by priority (descending) and layer_id (for determinism)
-        let mut service_index: HashMap<String, Vec<String>> = HashMap::new();
-        for (service, mut layer_list) in service_to_layers {
-            layer_list.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
-            service_index.insert(
-                service,
-                layer_list.into_iter().map(|(id, _)| id).collect(),
-            );
-        }
+/// Per-service limits on concurrently enabled layers and experiments (0 = unlimited)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServiceBudget {
+    pub max_layers: usize,
+    pub max_experiments: usize,
+}
 
-        self.service_index.store(Arc::new(service_index));
+impl ServiceBudget {
+    fn is_unlimited(&self) -> bool {
+        self.max_layers == 0 && self.max_experiments == 0
     }
+}
 
-    /// Load all layers from directory
-    ///
-    /// NOTE: This method now requires catalog to build service index.
-    /// Caller must ensure catalog is loaded before calling this method.
-    pub async fn load_all_layers(&self, catalog: &ExperimentCatalog) -> Result<()> {
-        let mut new_layers = HashMap::new();
+/// What a [`LayerManager::resync`] changed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ResyncSummary {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+}
 
-        if !self.layers_dir.exists() {
-            tracing::warn!("Layers directory does not exist: {:?}", self.layers_dir);
-            return Ok(());
-        }
+impl ResyncSummary {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
 
-        let entries = std::fs::read_dir(&self.layers_dir)?;
+/// Enabled layers for one service, highest priority first
+pub type ServiceLayers = Arc<[Arc<Layer>]>;
 
-        for entry in entries {
-            let entry = entry?;
-            let path = entry.path();
+/// Service → Layers inverted index for sparse matrix optimization.
+///
+/// Services are inferred from the catalog owners of each layer's vids, so a
+/// catalog change only needs to revisit the layers referencing changed vids.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ServiceIndex {
+    /// service -> enabled layers (sorted by priority), sharded by service
+    services: ShardedMap<ServiceLayers>,
+
+    /// Indexed layer -> services it was filed under
+    layer_services: ShardedMap<BTreeSet<String>>,
+}
 
-            if path.is_file() {
-                if let Some(ext) = path.extension() {
-                    if ext == "json" || ext == "yaml" || ext == "yml" {
-                        match Layer::from_file(&path) {
-                            Ok(layer) => {
-                                tracing::info!(
-                                    "Loaded layer: {} (version: {}, priority: {})",
-                                    layer.layer_id,
-                                    layer.version,
-                                    layer.priority
-                                );
+impl ServiceIndex {
+    /// Full build: reverse-query the catalog (vid → eid → service) for every layer's vids
+    fn build(layers: &LayerMap, catalog: &ExperimentCatalog, emergency: &EmergencyOverrides, expired: &Expired) -> Self {
+        let mut index = Self::default();
+        let mut service_to_layers: HashMap<String, Vec<Arc<Layer>>> = HashMap::new();
 
-                                new_layers.insert(
-                                    layer.layer_id.clone(),
-                                    LayerVersion {
-                                        layer: Arc::new(layer),
-                                        file_path: path.clone(),
-                                    },
-                                );
-                            }
-                            Err(e) => {
-                                tracing::error!("Failed to load layer from {:?}: {}", path, e);
-                            }
-                        }
-                    }
-                }
+        for (layer_id, layer_ver) in layers.iter() {
+            if !is_indexed(layer_id, &layer_ver.layer, emergency, expired) {
+                continue;
             }
-        }
 
-        // Rebuild service index (now requires catalog)
-        self.rebuild_service_index(&new_layers, catalog);
+            let services = layer_services(&layer_ver.layer, catalog);
+            for service in &services {
+                service_to_layers
+                    .entry(service.clone())
+                    .or_default()
+                    .push(layer_ver.layer.clone());
+            }
+            index.layer_services.insert(layer_id.clone(), services);
+        }
 
-        // Atomic swap
-        self.layers.store(Arc::new(new_layers));
+        index.services = service_to_layers
+            .into_iter()
+            .map(|(service, layer_list)| (service, sorted_layers(layer_list)))
+            .collect();
+        index
+    }
 
-        Ok(())
+    /// Same index for `catalog`, where only the owners of `changed_vids` differ
+    /// from the catalog `self` was built against. Entries of unaffected
+    /// services are shared with `self`.
+    fn with_changed_vids(
+        &self,
+        layers: &LayerMap,
+        catalog: &ExperimentCatalog,
+        emergency: &EmergencyOverrides,
+        expired: &Expired,
+        changed_vids: &HashSet<i64>,
+    ) -> Self {
+        if changed_vids.is_empty() {
+            return self.clone();
+        }
+        let changed = layers.iter().filter_map(|(layer_id, layer_ver)| {
+            let layer = &layer_ver.layer;
+            (is_indexed(layer_id, layer, emergency, expired) && layer.ranges.iter().any(|r| changed_vids.contains(&r.vid)))
+                .then_some(layer_id.as_str())
+        });
+        self.with_changed_layers(layers, catalog, emergency, expired, changed)
     }
 
-    /// Load or reload a single layer
-    pub async fn load_layer(&self, layer_id: &str, file_path: &Path, catalog: &ExperimentCatalog) -> Result<()> {
-        let layer = Layer::from_file(file_path)?;
+    /// Same index after the `changed` layers were added, replaced, removed or
+    /// had their owners change. Entries of unaffected services are shared with `self`.
+    fn with_changed_layers<'a>(
+        &self,
+        layers: &LayerMap,
+        catalog: &ExperimentCatalog,
+        emergency: &EmergencyOverrides,
+        expired: &Expired,
+        changed: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        let mut index = self.clone();
+        let mut changed_layers: HashMap<&str, Option<&Arc<Layer>>> = HashMap::new();
+        let mut affected_services: BTreeSet<String> = BTreeSet::new();
+        for layer_id in changed {
+            let indexed = layers
+                .get(layer_id)
+                .map(|layer_ver| &layer_ver.layer)
+                .filter(|layer| is_indexed(layer_id, layer, emergency, expired));
+            let previous = match indexed {
+                Some(layer) => {
+                    let services = layer_services(layer, catalog);
+                    affected_services.extend(services.iter().cloned());
+                    index.layer_services.insert(layer_id.to_string(), services)
+                }
+                None => index.layer_services.remove(layer_id),
+            };
+            affected_services.extend(previous.into_iter().flatten());
+            changed_layers.insert(layer_id, indexed);
+        }
 
-        // Verify layer_id matches
-        if layer.layer_id != layer_id {
-            return Err(ExperimentError::InvalidParameter(format!(
-                "Layer ID mismatch: expected {}, got {}",
-                layer_id, layer.layer_id
-            )));
+        for service in affected_services {
+            let mut layer_list: Vec<Arc<Layer>> = index
+                .services
+                .get(&service)
+                .map(|current| {
+                    current
+                        .iter()
+                        .filter(|l| !changed_layers.contains_key(l.layer_id.as_str()))
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default();
+            layer_list.extend(changed_layers.iter().filter_map(|(layer_id, layer)| {
+                let layer = (*layer)?;
+                index.layer_services[*layer_id].contains(&service).then(|| layer.clone())
+            }));
+
+            if layer_list.is_empty() {
+                index.services.remove(&service);
+            } else {
+                index.services.insert(service, sorted_layers(layer_list));
+            }
         }
 
-        let current = self.layers.load();
-        let mut new_layers = (**current).clone();
+        index
+    }
+}
 
-        // Save to history if updating
-        if let Some(old_version) = new_layers.get(layer_id) {
-            let mut history = self.history.write();
-            history
-                .entry(layer_id.to_string())
-                .or_insert_with(Vec::new)
-                .push(old_version.layer.clone());
+/// Enabled layers not force-disabled by emergency overrides nor expired are indexed
+fn is_indexed(layer_id: &str, layer: &Layer, emergency: &EmergencyOverrides, expired: &Expired) -> bool {
+    layer.enabled && !emergency.layers.contains(layer_id) && !expired.layers.contains_key(layer_id)
+}
 
-            tracing::info!(
-                "Updating layer {} from version {} to {}",
-                layer_id,
-                old_version.layer.version,
-                layer.version
+/// Services owning the layer's vids (reverse query of the catalog)
+fn layer_services(layer: &Layer, catalog: &ExperimentCatalog) -> BTreeSet<String> {
+    let mut services = BTreeSet::new();
+    for range in &layer.ranges {
+        if let Some((_, service)) = catalog.variant_owner(range.vid) {
+            services.insert(service.to_string());
+        } else {
+            tracing::warn!(
+                "Layer {} references unknown vid {} (catalog may be incomplete)",
+                layer.layer_id,
+                range.vid
             );
+        }
+    }
+    services
+}
+
+/// Canonical evaluation order: priority descending, then layer_id ascending.
+/// Responses list vids and matched layers in this order; duplicates are dropped.
+pub(crate) fn sorted_layers(mut layer_list: Vec<Arc<Layer>>) -> ServiceLayers {
+    layer_list.sort_by(|a, b| {
+        b.priority
+            .cmp(&a.priority)
+            .then_with(|| a.layer_id.cmp(&b.layer_id))
+    });
+    layer_list.dedup_by(|a, b| a.layer_id == b.layer_id);
+    layer_list.into()
+}
+
+/// Everything request evaluation reads, published as one unit: a request
+/// never sees layers indexed against another catalog, or half of an update.
+#[derive(Debug, Default, Clone)]
+pub struct Snapshot {
+    /// layer_id -> LayerVersion
+    layers: LayerMap,
+
+    index: ServiceIndex,
+
+    /// Catalog the index was built against
+    catalog: Arc<ExperimentCatalog>,
+
+    /// Break-glass disables applied on top of the layer set
+    emergency: Arc<EmergencyOverrides>,
+
+    /// Layers and experiments past their `expires_at` as of this publish
+    expired: Arc<Expired>,
+
+    /// Identity aliases applied when hashing subjects into buckets
+    aliases: Arc<IdentityAliases>,
+
+    /// Subjects never experimented on (see [`crate::exclusion`])
+    exclusions: Arc<Exclusions>,
+
+    /// Renamed context fields that rules on the old names resolve against
+    field_aliases: Arc<FieldAliases>,
+
+    /// Context fields whose numeric strings rules parse as numbers
+    lenient_numbers: Arc<LenientNumbers>,
+
+    /// Layers evaluated per service per request, highest priority first (0 = all)
+    max_evaluated_layers: usize,
+
+    /// "Now" for timestamp rules
+    clock: Clock,
+
+    /// Anonymous → identified fallback for hash keys
+    identity_policy: Option<Arc<IdentityPolicy>>,
+
+    /// Layer rules as evaluated (see [`crate::rule_optimizer`])
+    rules: Arc<OptimizedRules>,
+
+    /// Incremented on every publish; identifies the config a response was computed from
+    epoch: u64,
+}
+
+impl Snapshot {
+    pub fn catalog(&self) -> &Arc<ExperimentCatalog> {
+        &self.catalog
+    }
+
+    /// Per-request cap on layers evaluated for one service (0 = unlimited)
+    pub fn max_evaluated_layers(&self) -> usize {
+        self.max_evaluated_layers
+    }
+
+    pub fn clock(&self) -> Clock {
+        self.clock
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn identity_aliases(&self) -> &IdentityAliases {
+        &self.aliases
+    }
+
+    pub fn identity_policy(&self) -> Option<&IdentityPolicy> {
+        self.identity_policy.as_deref()
+    }
+
+    pub fn exclusions(&self) -> &Arc<Exclusions> {
+        &self.exclusions
+    }
+
+    pub fn field_aliases(&self) -> &Arc<FieldAliases> {
+        &self.field_aliases
+    }
+
+    pub fn lenient_numbers(&self) -> &Arc<LenientNumbers> {
+        &self.lenient_numbers
+    }
+
+    /// `layer`'s rule as evaluated: the optimized, compiled form of its authored rule
+    pub fn layer_rule(&self, layer: &Layer) -> Option<Cow<'_, CompiledRule>> {
+        let authored = layer.rule.as_ref()?;
+        Some(match self.rules.layers.get(&layer.layer_id) {
+            Some(rule) => Cow::Borrowed(rule.as_ref()),
+            None => Cow::Owned(CompiledRule::compile(authored)),
+        })
+    }
+
+    /// Experiment `eid`'s rule as evaluated, given its authored rule
+    pub fn experiment_rule(&self, eid: i64, authored: &Node) -> Cow<'_, CompiledRule> {
+        match self.catalog.compiled_rule(eid) {
+            Some(rule) => Cow::Borrowed(rule),
+            None => Cow::Owned(CompiledRule::compile(authored)),
+        }
+    }
+
+    /// Every live layer and experiment, as if just added
+    pub fn resources(&self) -> Vec<AppliedEntry> {
+        applied_entries(&Snapshot::default(), &self.layers, &self.catalog, self.epoch, unix_now())
+    }
+
+    /// Every loaded layer, enabled or not (unordered)
+    pub fn layers(&self) -> impl Iterator<Item = &Arc<Layer>> {
+        self.layers.values().map(|v| &v.layer)
+    }
+
+    /// Enabled layers for a service, highest priority first
+    pub fn layers_for_service(&self, service: &str) -> ServiceLayers {
+        self.index
+            .services
+            .get(service)
+            .cloned()
+            .unwrap_or_else(|| Arc::new([]))
+    }
+
+    /// File `layer_id` was loaded from
+    pub fn layer_file(&self, layer_id: &str) -> Option<&Path> {
+        self.layers.get(layer_id).map(|lv| lv.file_path.as_path())
+    }
+
+    pub fn layer(&self, layer_id: &str) -> Option<Arc<Layer>> {
+        self.layers.get(layer_id).map(|v| v.layer.clone())
+    }
+
+    /// Whether a hit on `eid` in `layer_id` is suppressed by emergency overrides
+    pub fn is_emergency_disabled(&self, layer_id: &str, eid: i64) -> bool {
+        self.emergency.disables(layer_id, eid)
+    }
+
+    pub fn expired(&self) -> &Arc<Expired> {
+        &self.expired
+    }
+
+    /// Whether a hit on `eid` in `layer_id` is dropped because either has expired
+    pub fn is_expired(&self, layer_id: &str, eid: i64) -> bool {
+        self.expired.disables(layer_id, eid)
+    }
+}
+
+/// Layer Manager - manages all layers with hot reload support
+pub struct LayerManager {
+    /// Current source directory. Whole-directory reads hold this lock until
+    /// their result is swapped in, so a source switch can't be overwritten by a
+    /// concurrent resync of the old directory.
+    layers_dir: Mutex<PathBuf>,
+
+    /// Higher-precedence layer directories, highest first (see [`crate::source`])
+    overlay_dirs: Vec<PathBuf>,
+
+    /// Layers shadowed by a higher-precedence source in the last full read
+    source_conflicts: Arc<RwLock<Vec<SourceConflict>>>,
+
+    /// Layers, index, catalog and emergency overrides; replaced on every change
+    snapshot: Arc<ArcSwap<Snapshot>>,
+
+    /// Rollback history: layer_id -> previous versions
+    history: Arc<RwLock<HashMap<String, Vec<Arc<Layer>>>>>,
+
+    /// Reject deprecated config fields instead of converting them
+    strict_config: bool,
+
+    /// Semver-typed fields and segment limit rule values are checked against
+    semver: SemverPolicy,
+
+    /// Files that currently fail to load: path -> last error
+    load_errors: Arc<RwLock<BTreeMap<PathBuf, LoadError>>>,
+
+    /// Limits enforced whenever the layer set changes
+    budget: ServiceBudget,
+
+    /// Per-request cap on layers evaluated for one service (0 = unlimited)
+    max_evaluated_layers: usize,
+
+    /// Time source for timestamp rules, carried into every snapshot
+    clock: Clock,
+
+    /// Anonymous → identified fallback for hash keys
+    identity_policy: Option<Arc<IdentityPolicy>>,
+
+    /// Latest snapshot changes, oldest first (bounded by `RECENT_CHANGES`)
+    recent_changes: Arc<RwLock<VecDeque<ConfigChange>>>,
+
+    /// Export snapshot metrics and record changes on swap (off for forked candidate copies)
+    publish_metrics: bool,
+
+    /// Windows during which layer and catalog changes are refused
+    freeze: Option<Arc<FreezeSchedule>>,
+
+    /// Snapshot changes announced to subscribers (see [`crate::events`])
+    events: EventBus,
+
+    /// Held by every snapshot write from loading the live snapshot to storing
+    /// its successor, so concurrent writers can't drop each other's changes
+    writer: Mutex<()>,
+}
+
+impl LayerManager {
+    pub fn new(layers_dir: PathBuf) -> Self {
+        Self {
+            layers_dir: Mutex::new(layers_dir),
+            overlay_dirs: Vec::new(),
+            source_conflicts: Arc::new(RwLock::new(Vec::new())),
+            snapshot: Arc::new(ArcSwap::from_pointee(Snapshot::default())),
+            history: Arc::new(RwLock::new(HashMap::new())),
+            strict_config: false,
+            semver: SemverPolicy::default(),
+            load_errors: Arc::new(RwLock::new(BTreeMap::new())),
+            budget: ServiceBudget::default(),
+            max_evaluated_layers: 0,
+            clock: Clock::System,
+            identity_policy: None,
+            recent_changes: Arc::new(RwLock::new(VecDeque::new())),
+            publish_metrics: true,
+            freeze: None,
+            events: EventBus::default(),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// Enable strict config mode (see `STRICT_CONFIG`)
+    pub fn with_strict_config(mut self, strict: bool) -> Self {
+        self.strict_config = strict;
+        self
+    }
+
+    pub fn strict_config(&self) -> bool {
+        self.strict_config
+    }
+
+    /// Most core segments a semver rule value may have (see `SEMVER_MAX_SEGMENTS`)
+    pub fn with_semver_max_segments(mut self, segments: usize) -> Self {
+        self.semver = SemverPolicy::new(segments);
+        self
+    }
+
+    pub fn semver(&self) -> &SemverPolicy {
+        &self.semver
+    }
+
+    /// Declare the semver-typed fields and re-check the live layers and
+    /// catalog against them. Rules already serving keep serving; what fails
+    /// is returned (and logged) so the caller can report it.
+    pub fn set_semver_fields(&self, fields: HashSet<String>) -> Vec<String> {
+        self.semver.declare_fields(fields);
+        let snapshot = self.snapshot.load();
+        let mut violations: Vec<String> = snapshot
+            .layers()
+            .filter_map(|layer| self.check_layer_versions(layer).err())
+            .chain(self.catalog_version_errors(&snapshot.catalog))
+            .map(|e| e.to_string())
+            .collect();
+        violations.sort();
+        for violation in &violations {
+            tracing::warn!("Live rule fails the new semver field types: {}", violation);
+        }
+        violations
+    }
+
+    /// Check a layer's rule against the declared semver fields
+    pub fn check_layer_versions(&self, layer: &Layer) -> Result<()> {
+        match &layer.rule {
+            Some(rule) => rule.check_versions(&self.semver).map_err(|e| {
+                e.with_context(ErrorContext::new(ResourceKind::Layer).with_id(layer.layer_id.clone()))
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Check every experiment rule of `catalog` against the declared semver fields
+    pub fn check_catalog_versions(&self, catalog: &ExperimentCatalog) -> Result<()> {
+        match self.catalog_version_errors(catalog).next() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn catalog_version_errors<'a>(
+        &'a self,
+        catalog: &'a ExperimentCatalog,
+    ) -> impl Iterator<Item = ExperimentError> + 'a {
+        catalog.rules().filter_map(|(eid, rule)| {
+            let context = ErrorContext::new(ResourceKind::Experiment).with_id(eid.to_string());
+            rule.check_versions(&self.semver).err().map(|e| e.with_context(context))
+        })
+    }
+
+    /// Layer directories that override the primary source, highest precedence first
+    pub fn with_overlays(mut self, overlay_dirs: Vec<PathBuf>) -> Self {
+        self.overlay_dirs = overlay_dirs;
+        self
+    }
+
+    /// Reject layer sets that exceed the per-service budget
+    pub fn with_budget(mut self, budget: ServiceBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Evaluate at most `max_layers` layers per service per request, highest
+    /// priority first; results cut short are marked `truncated`
+    pub fn with_max_evaluated_layers(mut self, max_layers: usize) -> Self {
+        self.max_evaluated_layers = max_layers;
+        self
+    }
+
+    /// Evaluate timestamp rules against `clock` instead of wall-clock time;
+    /// set before layers are loaded, it applies from the next publish
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Let layers hashing on the policy's stable id fall back to the anonymous id
+    pub fn with_identity_policy(mut self, policy: IdentityPolicy) -> Self {
+        self.identity_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Refuse layer and catalog changes during freeze windows (see [`crate::freeze`])
+    pub fn with_freeze(mut self, freeze: Arc<FreezeSchedule>) -> Self {
+        self.freeze = Some(freeze);
+        self
+    }
+
+    pub fn freeze(&self) -> Option<&Arc<FreezeSchedule>> {
+        self.freeze.as_ref()
+    }
+
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Fails while a freeze window is open (and not overridden)
+    fn check_freeze(&self) -> Result<()> {
+        match &self.freeze {
+            Some(freeze) => freeze.check(),
+            None => Ok(()),
+        }
+    }
+
+    /// Verify every service stays within budget for the given layer set
+    fn check_budget(&self, layers_map: &LayerMap, catalog: &ExperimentCatalog) -> Result<()> {
+        if self.budget.is_unlimited() {
+            return Ok(());
+        }
+
+        // service -> (layer ids, eids)
+        let mut usage: BTreeMap<&str, (BTreeSet<&str>, BTreeSet<i64>)> = BTreeMap::new();
+        for (layer_id, layer_ver) in layers_map.iter() {
+            if !layer_ver.layer.enabled {
+                continue;
+            }
+            for range in &layer_ver.layer.ranges {
+                if let Some((eid, service)) = catalog.variant_owner(range.vid) {
+                    let (layers, eids) = usage.entry(service).or_default();
+                    layers.insert(layer_id.as_str());
+                    eids.insert(eid);
+                }
+            }
+        }
+
+        let mut violations = Vec::new();
+        for (service, (layers, eids)) in &usage {
+            if self.budget.max_layers > 0 && layers.len() > self.budget.max_layers {
+                violations.push(format!(
+                    "service '{}' has {} enabled layers (limit {}): {}",
+                    service,
+                    layers.len(),
+                    self.budget.max_layers,
+                    layers.iter().copied().collect::<Vec<_>>().join(", ")
+                ));
+            }
+            if self.budget.max_experiments > 0 && eids.len() > self.budget.max_experiments {
+                violations.push(format!(
+                    "service '{}' has {} active experiments (limit {})",
+                    service,
+                    eids.len(),
+                    self.budget.max_experiments
+                ));
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
         } else {
-            tracing::info!("Adding new layer: {} (version: {})", layer_id, layer.version);
+            Err(ExperimentError::BudgetExceeded(violations.join("; ")))
+        }
+    }
+
+    /// Reject enabled A/A test layers whose two variants serve different
+    /// params: their arms would measure a real treatment effect
+    fn check_aa_tests(&self, layers_map: &LayerMap, catalog: &ExperimentCatalog) -> Result<()> {
+        for (layer_id, layer_ver) in layers_map.iter() {
+            let layer = &layer_ver.layer;
+            if !layer.enabled || !layer.aa_test {
+                continue;
+            }
+            let vids: BTreeSet<i64> = layer.ranges.iter().map(|r| r.vid).collect();
+            let mut params = vids.iter().map(|vid| catalog.variant_params(*vid));
+            // Variants missing from the catalog are skipped at evaluation instead
+            if let (Some(Some(a)), Some(Some(b))) = (params.next(), params.next()) {
+                if *a != *b {
+                    let vids: Vec<String> = vids.iter().map(i64::to_string).collect();
+                    return Err(ExperimentError::InvalidParameter(format!(
+                        "A/A test layer splits traffic between vids {} whose params differ",
+                        vids.join(" and ")
+                    ))
+                    .with_context(ErrorContext::new(ResourceKind::Layer).with_id(layer_id.clone())));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Catalog a layer change is indexed against: the published one once there
+    /// is one, so a caller holding an older handle can't roll the index back.
+    /// Catalogs change only through [`Self::reindex`] and [`Self::switch_source`].
+    fn live_catalog(&self, current: &Snapshot, requested: &Arc<ExperimentCatalog>) -> Arc<ExperimentCatalog> {
+        if current.epoch > 0 {
+            current.catalog.clone()
+        } else {
+            requested.clone()
+        }
+    }
+
+    /// Index `layers` against `catalog` and publish the result as the live
+    /// snapshot; callers hold `writer`
+    fn publish(
+        &self,
+        layers: LayerMap,
+        catalog: &Arc<ExperimentCatalog>,
+        emergency: Arc<EmergencyOverrides>,
+    ) {
+        let expired = Arc::new(Expired::collect(layers.values().map(|v| v.layer.as_ref()), catalog, unix_now()));
+        let index = ServiceIndex::build(&layers, catalog, &emergency, &expired);
+        self.publish_indexed(layers, catalog, emergency, expired, index);
+    }
+
+    /// Publish a snapshot whose index was already built for `catalog` and `expired`
+    fn publish_indexed(
+        &self,
+        layers: LayerMap,
+        catalog: &Arc<ExperimentCatalog>,
+        emergency: Arc<EmergencyOverrides>,
+        expired: Arc<Expired>,
+        index: ServiceIndex,
+    ) {
+        let current = self.snapshot.load();
+        let epoch = current.epoch + 1;
+        let mut events = Vec::new();
+
+        if self.publish_metrics {
+            if current.expired != expired {
+                expired.report(&current.expired);
+            }
+
+            publish_service_metrics(&layers, &index.services, catalog);
+
+            let change = ConfigChange {
+                epoch,
+                at: unix_now(),
+                layers: layer_changes(&current.layers, &layers),
+                experiments: if Arc::ptr_eq(&current.catalog, catalog) {
+                    ChangeCounts::default()
+                } else {
+                    current.catalog.changes_to(catalog)
+                },
+                emergency_overrides: current.emergency != emergency,
+            };
+            change.layers.record("layer");
+            if !change.layers.is_empty() || !change.experiments.is_empty() || change.emergency_overrides {
+                if self.events.has_subscribers() {
+                    events.push(EngineEvent::SnapshotApplied {
+                        change: change.clone(),
+                        resources: applied_entries(&current, &layers, catalog, epoch, change.at),
+                    });
+                    events.extend(disabled_layers(&current, &layers, &emergency, &expired, epoch));
+                }
+                let mut recent = self.recent_changes.write();
+                if recent.len() == RECENT_CHANGES {
+                    recent.pop_front();
+                }
+                recent.push_back(change);
+            } else if current.expired != expired && self.events.has_subscribers() {
+                events.extend(disabled_layers(&current, &layers, &emergency, &expired, epoch));
+            }
+        }
+
+        let rules = optimized_rules(&current, &layers);
+        self.snapshot.store(Arc::new(Snapshot {
+            layers,
+            index,
+            catalog: catalog.clone(),
+            emergency,
+            expired,
+            aliases: current.aliases.clone(),
+            exclusions: current.exclusions.clone(),
+            field_aliases: current.field_aliases.clone(),
+            lenient_numbers: current.lenient_numbers.clone(),
+            max_evaluated_layers: self.max_evaluated_layers,
+            clock: self.clock,
+            identity_policy: self.identity_policy.clone(),
+            rules,
+            epoch,
+        }));
+
+        for event in events {
+            self.events.publish(event);
+        }
+    }
+
+    /// Publish `new_layers` as the live layer set
+    fn swap_layers(&self, new_layers: LayerMap, catalog: &Arc<ExperimentCatalog>) {
+        let emergency = self.snapshot.load().emergency.clone();
+        self.publish(new_layers, catalog, emergency);
+    }
+
+    /// Publish `new_layers`, which differs from the live set only in the
+    /// `changed` layers: only the services those are (or were) filed under are
+    /// re-indexed
+    fn swap_changed_layers(&self, new_layers: LayerMap, catalog: &Arc<ExperimentCatalog>, changed: &[&str]) {
+        let current = self.snapshot.load();
+        let expired = Expired::collect(new_layers.values().map(|v| v.layer.as_ref()), catalog, unix_now());
+        // A new catalog or other layers reaching `expires_at` change the rest of the index too
+        let unchanged = |expired: &Expired| {
+            expired
+                .layers
+                .iter()
+                .filter(|(layer_id, _)| !changed.contains(&layer_id.as_str()))
+                .map(|(layer_id, _)| layer_id.clone())
+                .collect::<Vec<_>>()
+        };
+        if !Arc::ptr_eq(&current.catalog, catalog) || unchanged(&expired) != unchanged(&current.expired) {
+            self.publish(new_layers, catalog, current.emergency.clone());
+            return;
+        }
+
+        let index = current.index.with_changed_layers(
+            &new_layers,
+            catalog,
+            &current.emergency,
+            &expired,
+            changed.iter().copied(),
+        );
+        self.publish_indexed(new_layers, catalog, current.emergency.clone(), Arc::new(expired), index);
+    }
+
+    /// Snapshots that changed config, newest first
+    pub fn recent_changes(&self) -> Vec<ConfigChange> {
+        self.recent_changes.read().iter().rev().cloned().collect()
+    }
+
+    /// Load all layers from directory
+    ///
+    /// NOTE: This method now requires catalog to build service index.
+    /// Caller must ensure catalog is loaded before calling this method.
+    pub async fn load_all_layers(&self, catalog: &Arc<ExperimentCatalog>) -> Result<()> {
+        let _writer = self.writer.lock();
+        let layers_dir = self.layers_dir.lock();
+        if !layers_dir.exists() {
+            tracing::warn!("Layers directory does not exist: {:?}", *layers_dir);
+            return Ok(());
+        }
+
+        let (new_layers, _) = self.read_layers_dir(&layers_dir, catalog)?;
+        let new_layers = LayerMap::from(new_layers);
+        tracing::info!("Loaded {} layers from {:?}", new_layers.len(), *layers_dir);
+
+        // The initial load isn't a change
+        if self.snapshot.load().epoch > 0 {
+            self.check_freeze()?;
+        }
+        self.check_catalog_versions(catalog)?;
+        self.check_budget(&new_layers, catalog)?;
+        self.check_aa_tests(&new_layers, catalog)?;
+
+        // Rebuild service index and swap atomically
+        self.swap_layers(new_layers, catalog);
+
+        Ok(())
+    }
+
+    /// Re-read the layers directory and apply only what changed.
+    ///
+    /// Unchanged layers keep their `Arc` and the index is not rebuilt when nothing
+    /// differs. Layers whose file currently fails to parse keep their last good version.
+    pub async fn resync(&self, catalog: &Arc<ExperimentCatalog>) -> Result<ResyncSummary> {
+        let _writer = self.writer.lock();
+        let layers_dir = self.layers_dir.lock();
+        if !layers_dir.exists() {
+            return Ok(ResyncSummary::default());
+        }
+
+        let current = self.snapshot.load();
+        let catalog = &self.live_catalog(&current, catalog);
+        let (mut new_layers, failed_paths) = self.read_layers_dir(&layers_dir, catalog)?;
+        let current = &current.layers;
+
+        let mut summary = ResyncSummary::default();
+        for (layer_id, old) in current.iter() {
+            match new_layers.get_mut(layer_id) {
+                Some(new) if new.layer == old.layer && new.file_path == old.file_path => {
+                    new.layer = old.layer.clone();
+                }
+                Some(_) => summary.updated.push(layer_id.clone()),
+                None if failed_paths.contains(&old.file_path) => {
+                    new_layers.insert(layer_id.clone(), old.clone());
+                }
+                None => summary.removed.push(layer_id.clone()),
+            }
+        }
+        summary.added = new_layers
+            .keys()
+            .filter(|id| !current.contains_key(id))
+            .cloned()
+            .collect();
+
+        if summary.is_empty() {
+            return Ok(summary);
+        }
+
+        // Apply the differences to the live set so unchanged shards stay shared
+        let mut layers = current.clone();
+        for layer_id in summary.added.iter().chain(&summary.updated) {
+            layers.insert(layer_id.clone(), new_layers[layer_id].clone());
+        }
+        for layer_id in &summary.removed {
+            layers.remove(layer_id);
+        }
+
+        self.check_freeze()?;
+        self.check_budget(&layers, catalog)?;
+        self.check_aa_tests(&layers, catalog)?;
+
+        {
+            let mut history = self.history.write();
+            for layer_id in &summary.updated {
+                history
+                    .entry(layer_id.clone())
+                    .or_default()
+                    .push(current[layer_id].layer.clone());
+            }
+        }
+
+        let changed: Vec<&str> = summary
+            .added
+            .iter()
+            .chain(&summary.updated)
+            .chain(&summary.removed)
+            .map(String::as_str)
+            .collect();
+        self.swap_changed_layers(layers, catalog, &changed);
+
+        summary.added.sort();
+        summary.updated.sort();
+        summary.removed.sort();
+        Ok(summary)
+    }
+
+    /// Parse every layer file. Returns the loaded layers and the paths that failed;
+    /// strict-mode violations fail the whole read.
+    fn read_layers_dir(
+        &self,
+        dir: &Path,
+        catalog: &ExperimentCatalog,
+    ) -> Result<(HashMap<String, LayerVersion>, HashSet<PathBuf>)> {
+        let parsed = self.parse_sources(dir, catalog)?;
+
+        let failed_paths = parsed.load_errors.keys().cloned().collect();
+        *self.load_errors.write() = parsed.load_errors;
+        *self.source_conflicts.write() = parsed.conflicts;
+        check_strict_violations(parsed.strict_violations)?;
+
+        Ok((parsed.layers, failed_paths))
+    }
+
+    /// Parse the primary directory and every overlay, keeping the
+    /// highest-precedence definition of each layer_id
+    fn parse_sources(&self, primary: &Path, catalog: &ExperimentCatalog) -> Result<ParsedLayers> {
+        let mut merged = self.parse_layers_in(primary, catalog)?;
+
+        // Lowest precedence first so each overlay replaces what is below it
+        for dir in self.overlay_dirs.iter().rev() {
+            if !dir.exists() {
+                continue;
+            }
+
+            let overlay = self.parse_layers_in(dir, catalog)?;
+            for (layer_id, version) in overlay.layers {
+                let winner = version.file_path.clone();
+                if let Some(shadowed) = merged.layers.insert(layer_id.clone(), version) {
+                    tracing::warn!("Layer {} in {:?} shadowed by {:?}", layer_id, shadowed.file_path, winner);
+                    merged.conflicts.push(SourceConflict {
+                        kind: ResourceKind::Layer,
+                        id: layer_id,
+                        winner,
+                        shadowed: shadowed.file_path,
+                        detail: None,
+                    });
+                }
+            }
+            merged.load_errors.extend(overlay.load_errors);
+            merged.strict_violations.extend(overlay.strict_violations);
+        }
+
+        merged.conflicts.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(merged)
+    }
+
+    fn parse_layers_in(&self, dir: &Path, catalog: &ExperimentCatalog) -> Result<ParsedLayers> {
+        let mut parsed = ParsedLayers::default();
+
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+
+            if path.is_file() {
+                if let Some(ext) = path.extension() {
+                    if ext == "json" || ext == "yaml" || ext == "yml" {
+                        let loaded = Layer::from_file_against(&path, self.strict_config, catalog).and_then(|layer| {
+                            self.check_layer_versions(&layer)
+                                .map_err(|e| e.with_context(ErrorContext::new(ResourceKind::Layer).with_path(&path)))?;
+                            Ok(layer)
+                        });
+                        match loaded {
+                            Ok(layer) => {
+                                tracing::debug!(
+                                    "Loaded layer: {} (version: {}, priority: {})",
+                                    layer.layer_id,
+                                    layer.version,
+                                    layer.priority
+                                );
+
+                                parsed.layers.insert(
+                                    layer.layer_id.clone(),
+                                    LayerVersion {
+                                        layer: Arc::new(layer),
+                                        file_path: path.clone(),
+                                    },
+                                );
+                            }
+                            Err(e) => {
+                                parsed.load_errors.insert(path.clone(), LoadError::from_error(&e));
+                                if matches!(e.inner(), ExperimentError::DeprecatedConfig(_)) {
+                                    parsed.strict_violations.push(e.to_string());
+                                } else {
+                                    tracing::error!("Failed to load layer: {}", e);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// Directory layers are currently loaded from
+    pub fn layers_dir(&self) -> PathBuf {
+        self.layers_dir.lock().clone()
+    }
+
+    pub fn overlay_dirs(&self) -> &[PathBuf] {
+        &self.overlay_dirs
+    }
+
+    /// Layers shadowed by a higher-precedence source
+    pub fn source_conflicts(&self) -> Vec<SourceConflict> {
+        self.source_conflicts.read().clone()
+    }
+
+    /// Whether a layer is currently served from an overlay rather than the primary source
+    pub fn is_overridden(&self, layer_id: &str) -> bool {
+        self.snapshot
+            .load()
+            .layers
+            .get(layer_id)
+            .is_some_and(|v| precedence(&v.file_path, &self.overlay_dirs) < self.overlay_dirs.len())
+    }
+
+    /// Replace the whole layer set with the contents of another directory.
+    ///
+    /// The new directory is parsed and validated (strict mode, budget) before
+    /// anything changes; on error the current source keeps serving. Rollback
+    /// history belongs to the old source and is discarded.
+    pub async fn switch_source(&self, layers_dir: PathBuf, catalog: &Arc<ExperimentCatalog>) -> Result<()> {
+        let _writer = self.writer.lock();
+        let mut current_dir = self.layers_dir.lock();
+        let parsed = self.parse_sources(&layers_dir, catalog)?;
+        check_strict_violations(parsed.strict_violations)?;
+        self.check_freeze()?;
+        self.check_catalog_versions(catalog)?;
+        let layers = LayerMap::from(parsed.layers);
+        self.check_budget(&layers, catalog)?;
+        self.check_aa_tests(&layers, catalog)?;
+
+        tracing::info!(
+            "Switching layers source to {:?} ({} layers)",
+            layers_dir,
+            layers.len()
+        );
+        *current_dir = layers_dir;
+        *self.load_errors.write() = parsed.load_errors;
+        *self.source_conflicts.write() = parsed.conflicts;
+        self.history.write().clear();
+        self.swap_layers(layers, catalog);
+
+        Ok(())
+    }
+
+    /// Detached copy of the current layer set. Changes to the copy are not visible here;
+    /// used to evaluate candidate configs without touching live traffic.
+    pub fn fork(&self) -> LayerManager {
+        Self {
+            layers_dir: Mutex::new(self.layers_dir()),
+            overlay_dirs: self.overlay_dirs.clone(),
+            source_conflicts: Arc::new(RwLock::new(Vec::new())),
+            snapshot: Arc::new(ArcSwap::new(self.snapshot.load_full())),
+            history: Arc::new(RwLock::new(HashMap::new())),
+            strict_config: self.strict_config,
+            semver: self.semver.clone(),
+            load_errors: Arc::new(RwLock::new(BTreeMap::new())),
+            budget: self.budget,
+            max_evaluated_layers: self.max_evaluated_layers,
+            clock: self.clock,
+            identity_policy: self.identity_policy.clone(),
+            recent_changes: Arc::new(RwLock::new(VecDeque::new())),
+            publish_metrics: false,
+            freeze: None,
+            events: EventBus::default(),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// The live snapshot; evaluate a request against one load of it
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot.load_full()
+    }
+
+    pub fn emergency_overrides(&self) -> Arc<EmergencyOverrides> {
+        self.snapshot.load().emergency.clone()
+    }
+
+    /// Install new break-glass disables and drop disabled layers from the index
+    pub fn set_emergency_overrides(&self, overrides: EmergencyOverrides) {
+        let _writer = self.writer.lock();
+        let current = self.snapshot.load();
+        self.publish(current.layers.clone(), &current.catalog, Arc::new(overrides));
+    }
+
+    /// Replace the identity aliases used for bucketing; layers and index are kept
+    pub fn set_identity_aliases(&self, aliases: IdentityAliases) {
+        self.republish(|snapshot| snapshot.aliases = Arc::new(aliases));
+    }
+
+    /// Replace the excluded population; layers and index are kept
+    pub fn set_exclusions(&self, exclusions: Exclusions) {
+        self.republish(|snapshot| snapshot.exclusions = Arc::new(exclusions));
+    }
+
+    /// Replace the fields whose numeric strings rules parse; layers and index are kept
+    pub fn set_lenient_numbers(&self, lenient_numbers: LenientNumbers) {
+        self.republish(|snapshot| snapshot.lenient_numbers = Arc::new(lenient_numbers));
+    }
+
+    /// Replace the context field aliases used by rules; layers and index are kept
+    pub fn set_field_aliases(&self, field_aliases: FieldAliases) {
+        self.republish(|snapshot| snapshot.field_aliases = Arc::new(field_aliases));
+    }
+
+    /// Publish a copy of the live snapshot changed by `update`; layers and
+    /// index are kept unless `update` replaces them
+    fn republish(&self, update: impl FnOnce(&mut Snapshot)) {
+        let _writer = self.writer.lock();
+        let current = self.snapshot.load();
+        let mut next = Snapshot::clone(&current);
+        update(&mut next);
+        next.epoch = current.epoch + 1;
+        self.snapshot.store(Arc::new(next));
+    }
+
+    /// Whether a hit on `eid` in `layer_id` is suppressed by emergency overrides
+    pub fn is_emergency_disabled(&self, layer_id: &str, eid: i64) -> bool {
+        self.snapshot.load().is_emergency_disabled(layer_id, eid)
+    }
+
+    /// Re-index against a different catalog and publish both. Only layers
+    /// referencing vids whose owning service changed are revisited.
+    pub fn reindex(&self, catalog: &Arc<ExperimentCatalog>) -> Result<()> {
+        let _writer = self.writer.lock();
+        let current = self.snapshot.load();
+        if !Arc::ptr_eq(&current.catalog, catalog) {
+            self.check_freeze()?;
+            self.check_catalog_versions(catalog)?;
+        }
+        self.check_budget(&current.layers, catalog)?;
+        self.check_aa_tests(&current.layers, catalog)?;
+        let expired = Arc::new(Expired::collect(current.layers().map(Arc::as_ref), catalog, unix_now()));
+        if expired.layers != current.expired.layers {
+            // A layer expired since the last publish: the incremental index would keep it
+            self.publish(current.layers.clone(), catalog, current.emergency.clone());
+            return Ok(());
+        }
+        let changed_vids = current.catalog.vids_with_changed_service(catalog);
+        let index = current
+            .index
+            .with_changed_vids(&current.layers, catalog, &current.emergency, &expired, &changed_vids);
+        self.publish_indexed(current.layers.clone(), catalog, current.emergency.clone(), expired, index);
+        Ok(())
+    }
+
+    /// Republish if a layer or experiment reached its `expires_at` since the
+    /// last publish. Returns whether anything changed.
+    pub fn refresh_expiry(&self) -> bool {
+        let _writer = self.writer.lock();
+        let current = self.snapshot.load();
+        let expired = Expired::collect(current.layers().map(Arc::as_ref), &current.catalog, unix_now());
+        if expired == *current.expired {
+            return false;
+        }
+        self.publish(current.layers.clone(), &current.catalog, current.emergency.clone());
+        true
+    }
+
+    /// Load or reload a single layer
+    pub async fn load_layer(&self, layer_id: &str, file_path: &Path, catalog: &Arc<ExperimentCatalog>) -> Result<()> {
+        // Overlay files are applied by full resync; a primary file can't replace them
+        if self.is_overridden(layer_id) {
+            tracing::warn!("Ignoring {:?}: layer {} is overridden by an overlay source", file_path, layer_id);
+            return Ok(());
+        }
+
+        let live = self.live_catalog(&self.snapshot.load(), catalog);
+        let result = Layer::from_file_against(file_path, self.strict_config, &live).and_then(|layer| {
+            // Verify layer_id matches
+            if layer.layer_id != layer_id {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "Layer ID mismatch: expected {}, got {}",
+                    layer_id, layer.layer_id
+                )));
+            }
+
+            self.upsert_layer(layer, file_path, catalog)
+        });
+
+        match &result {
+            Ok(()) => {
+                self.load_errors.write().remove(file_path);
+            }
+            // Deferred, not broken
+            Err(e) if freeze::is_queued(e, self) => {}
+            Err(e) => {
+                self.load_errors
+                    .write()
+                    .insert(file_path.to_path_buf(), LoadError::from_error(e));
+            }
         }
 
-        new_layers.insert(
-            layer_id.to_string(),
+        result
+    }
+
+    /// Install an already-parsed layer, keeping the replaced version in history.
+    ///
+    /// Rejected (nothing changes) if the result would exceed the service budget.
+    pub fn upsert_layer(&self, layer: Layer, file_path: &Path, catalog: &Arc<ExperimentCatalog>) -> Result<()> {
+        let layer_id = layer.layer_id.clone();
+        self.check_layer_versions(&layer)
+            .map_err(|e| e.with_context(ErrorContext::new(ResourceKind::Layer).with_path(file_path)))?;
+
+        let _writer = self.writer.lock();
+        let current = self.snapshot.load();
+        let catalog = &self.live_catalog(&current, catalog);
+        let mut new_layers = current.layers.clone();
+        let previous = new_layers.insert(
+            layer_id.clone(),
             LayerVersion {
                 layer: Arc::new(layer),
                 file_path: file_path.to_path_buf(),
             },
         );
 
-        // Rebuild service index (now requires catalog)
-        self.rebuild_service_index(&new_layers, catalog);
+        self.check_freeze()
+            .and_then(|_| self.check_budget(&new_layers, catalog))
+            .and_then(|_| self.check_aa_tests(&new_layers, catalog))
+            .map_err(|e| {
+                e.with_context(
+                    ErrorContext::new(ResourceKind::Layer)
+                        .with_id(layer_id.clone())
+                        .with_path(file_path),
+                )
+            })?;
+
+        // Save to history if updating
+        let version = &new_layers[&layer_id].layer.version;
+        if let Some(old_version) = previous {
+            tracing::info!(
+                "Updating layer {} from version {} to {}",
+                layer_id,
+                old_version.layer.version,
+                version
+            );
+
+            self.history
+                .write()
+                .entry(layer_id.clone())
+                .or_default()
+                .push(old_version.layer);
+        } else {
+            tracing::info!("Adding new layer: {} (version: {})", layer_id, version);
+        }
 
-        // Atomic swap
-        self.layers.store(Arc::new(new_layers));
+        // Re-index the layer's services and swap atomically
+        self.swap_changed_layers(new_layers, catalog, &[&layer_id]);
 
         Ok(())
     }
 
+    /// Path a layer was loaded from (or would be written to)
+    pub fn layer_path(&self, layer_id: &str) -> PathBuf {
+        self.snapshot
+            .load()
+            .layers
+            .get(layer_id)
+            .map(|v| v.file_path.clone())
+            .unwrap_or_else(|| self.layers_dir().join(format!("{}.json", layer_id)))
+    }
+
     /// Remove a layer
-    pub async fn remove_layer(&self, layer_id: &str, catalog: &ExperimentCatalog) -> Result<()> {
-        let current = self.layers.load();
-        let mut new_layers = (**current).clone();
+    pub async fn remove_layer(&self, layer_id: &str, catalog: &Arc<ExperimentCatalog>) -> Result<()> {
+        let _writer = self.writer.lock();
+        let current = self.snapshot.load();
+        let catalog = &self.live_catalog(&current, catalog);
+        let mut new_layers = current.layers.clone();
 
         if new_layers.remove(layer_id).is_some() {
+            self.check_freeze()?;
             tracing::info!("Removed layer: {}", layer_id);
 
-            // Rebuild service index (now requires catalog)
-            self.rebuild_service_index(&new_layers, catalog);
-
-            self.layers.store(Arc::new(new_layers));
+            self.swap_changed_layers(new_layers, catalog, &[layer_id]);
             Ok(())
         } else {
             Err(ExperimentError::LayerNotFound(layer_id.to_string()))
@@ -476,13 +2095,15 @@ impl LayerManager {
     }
 
     /// Rollback layer to previous version
-    pub async fn rollback_layer(&self, layer_id: &str) -> Result<()> {
+    pub async fn rollback_layer(&self, layer_id: &str, catalog: &Arc<ExperimentCatalog>) -> Result<()> {
+        let _writer = self.writer.lock();
         let mut history = self.history.write();
 
         if let Some(versions) = history.get_mut(layer_id) {
-            if let Some(prev_layer) = versions.pop() {
-                let current = self.layers.load();
-                let mut new_layers = (**current).clone();
+            if let Some(prev_layer) = versions.last().cloned() {
+                let current = self.snapshot.load();
+                let catalog = &self.live_catalog(&current, catalog);
+                let mut new_layers = current.layers.clone();
 
                 if let Some(layer_version) = new_layers.get(layer_id) {
                     new_layers.insert(
@@ -493,7 +2114,12 @@ impl LayerManager {
                         },
                     );
 
-                    self.layers.store(Arc::new(new_layers));
+                    // Previous version may differ in enabled/priority
+                    self.check_freeze()?;
+                    self.check_budget(&new_layers, catalog)?;
+                    self.check_aa_tests(&new_layers, catalog)?;
+                    versions.pop();
+                    self.swap_changed_layers(new_layers, catalog, &[layer_id]);
 
                     tracing::info!(
                         "Rolled back layer {} to version {}",
@@ -511,36 +2137,118 @@ impl LayerManager {
         )))
     }
 
+    /// Enabled layers of a service in the order parameters are merged
+    pub fn resolution_order(&self, service: &str, catalog: &ExperimentCatalog) -> ResolutionOrder {
+        let layers = self.get_layers_for_service(service);
+
+        let resolved = layers
+            .iter()
+            .enumerate()
+            .map(|(i, layer)| {
+                let mut buckets: BTreeMap<i64, (i64, u32)> = BTreeMap::new();
+                for range in &layer.ranges {
+                    if let Some((eid, s)) = catalog.variant_owner(range.vid) {
+                        if s == service {
+                            buckets.entry(range.vid).or_insert((eid, 0)).1 += range.end - range.start;
+                        }
+                    }
+                }
+
+                LayerResolution {
+                    rank: i + 1,
+                    layer_id: layer.layer_id.clone(),
+                    version: layer.version.clone(),
+                    priority: layer.priority,
+                    salt: layer.get_salt(),
+                    hash_key: layer.hash_key.clone(),
+                    coverage_percent: layer.service_share(service, catalog) * 100.0,
+                    variants: buckets
+                        .into_iter()
+                        .map(|(vid, (eid, n))| VariantCoverage {
+                            eid,
+                            vid,
+                            label: layer.label_for(vid).map(str::to_string),
+                            percent: n as f64 * 100.0 / layer.bucket_size() as f64,
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        ResolutionOrder {
+            service: service.to_string(),
+            coverage_percent: combined_share(&layers, service, catalog) * 100.0,
+            layers: resolved,
+        }
+    }
+
     /// Get specific layer
     pub fn get_layer(&self, layer_id: &str) -> Option<Arc<Layer>> {
-        self.layers.load().get(layer_id).map(|v| v.layer.clone())
+        self.snapshot.load().layer(layer_id)
     }
 
     /// Get all layer IDs
     pub fn get_layer_ids(&self) -> Vec<String> {
-        self.layers.load().keys().cloned().collect()
+        self.snapshot.load().layers.keys().cloned().collect()
     }
 
-    /// Get layers for a specific service (using inverted index)
-    pub fn get_layers_for_service(&self, service: &str) -> Vec<Arc<Layer>> {
-        let service_index = self.service_index.load();
-        let layers = self.layers.load();
+    /// Layer files that currently fail to load
+    pub fn load_errors(&self) -> BTreeMap<PathBuf, LoadError> {
+        self.load_errors.read().clone()
+    }
 
-        if let Some(layer_ids) = service_index.get(service) {
-            layer_ids
-                .iter()
-                .filter_map(|id| layers.get(id).map(|v| v.layer.clone()))
-                .filter(|layer| layer.enabled)
-                .collect()
-        } else {
-            Vec::new()
-        }
+    /// Drop the recorded load error for a removed file
+    pub fn forget_load_error(&self, path: &Path) {
+        self.load_errors.write().remove(path);
     }
+
+    /// Get enabled layers for a specific service, highest priority first (using inverted index)
+    pub fn get_layers_for_service(&self, service: &str) -> ServiceLayers {
+        self.snapshot.load().layers_for_service(service)
+    }
+}
+
+/// Share of subjects landing in at least one of the service's vids across `layers`.
+///
+/// Layers hash with independent salts, so a subject misses a service only if it
+/// misses the service's vids in every layer. Rules are not applied.
+fn combined_share(layers: &[Arc<Layer>], service: &str, catalog: &ExperimentCatalog) -> f64 {
+    let miss: f64 = layers
+        .iter()
+        .map(|layer| 1.0 - layer.service_share(service, catalog))
+        .product();
+    1.0 - miss
+}
+
+/// Export enabled-layer counts and static coverage per service
+fn publish_service_metrics(
+    layers_map: &LayerMap,
+    service_index: &ShardedMap<ServiceLayers>,
+    catalog: &ExperimentCatalog,
+) {
+    let enabled = layers_map.values().filter(|v| v.layer.enabled).count();
+
+    let services: Vec<(String, usize, f64)> = service_index
+        .iter()
+        .map(|(service, layers)| {
+            (service.clone(), layers.len(), combined_share(layers, service, catalog))
+        })
+        .collect();
+
+    metrics::set_service_gauges(enabled, &services);
+
+    let uncovered: Vec<(&str, f64)> = layers_map
+        .values()
+        .filter(|v| v.layer.enabled)
+        .map(|v| (v.layer.layer_id.as_str(), v.layer.uncovered_share()))
+        .collect();
+    metrics::set_layer_uncovered(&uncovered);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::catalog::VariantDef;
     use tempfile::TempDir;
 
     #[test]
@@ -549,22 +2257,28 @@ mod tests {
             layer_id: "test".to_string(),
             version: "v1".to_string(),
             priority: 100,
-            hash_key: "user_id".to_string(),
+            hash_key: "user_id".into(),
             salt: None,
+            bucket_size: None,
+            expires_at: None,
+            rule: None,
             services: vec!["svc".to_string()],
             ranges: vec![
                 BucketRange {
                     start: 0,
                     end: 5000,
                     vid: 1,
+                    label: None,
                 },
                 BucketRange {
                     start: 7500,
                     end: 10000,
                     vid: 2,
+                    label: None,
                 },
             ],
             enabled: true,
+            aa_test: false,
         };
 
         assert_eq!(layer.get_vid(0), Some(1));
@@ -582,15 +2296,17 @@ mod tests {
                 start: 0,
                 end: 10,
                 vid: 1,
+                label: None,
             },
             BucketRange {
                 start: 5,
                 end: 20,
                 vid: 2,
+                label: None,
             },
         ];
 
-        let err = validate_and_sort_ranges(&mut ranges).unwrap_err();
+        let err = validate_and_sort_ranges(&mut ranges, BUCKET_SIZE).unwrap_err();
         assert!(format!("{}", err).contains("Overlapping ranges"));
     }
 
@@ -600,10 +2316,723 @@ mod tests {
             start: 0,
             end: BUCKET_SIZE + 1,
             vid: 1,
+            label: None,
         }];
 
-        let err = validate_and_sort_ranges(&mut ranges).unwrap_err();
-        assert!(format!("{}", err).contains("exceeds BUCKET_SIZE"));
+        let err = validate_and_sort_ranges(&mut ranges, BUCKET_SIZE).unwrap_err();
+        assert!(format!("{}", err).contains("exceeds bucket size"));
+    }
+
+    #[test]
+    fn test_custom_bucket_size() {
+        let doc = |extra: serde_json::Value| {
+            let mut doc = serde_json::json!({
+                "layer_id": "small", "version": "v1", "priority": 1, "hash_key": "user_id",
+                "enabled": true, "bucket_size": 100
+            });
+            doc.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            doc
+        };
+
+        let layer = Layer::from_value(doc(serde_json::json!({"ranges": [{"start": 0, "end": 100, "vid": 1}]})), false)
+            .unwrap();
+        assert_eq!(layer.bucket_size(), 100);
+        assert_eq!(layer.get_vid(99), Some(1));
+        assert_eq!(layer.get_vid(100), None);
+
+        // Serialized layers load back unchanged
+        let roundtrip = Layer::from_value(serde_json::to_value(&layer).unwrap(), false).unwrap();
+        assert_eq!(roundtrip, layer);
+
+        // Ranges are checked against the layer's own size, not BUCKET_SIZE
+        let err = Layer::from_value(doc(serde_json::json!({"ranges": [{"start": 0, "end": 101, "vid": 1}]})), false)
+            .unwrap_err();
+        assert!(err.to_string().contains("exceeds bucket size 100"), "{}", err);
+
+        // Legacy buckets: the last boundary range ends at the layer's size
+        let legacy = Layer::from_value(doc(serde_json::json!({"buckets": {"0": "1", "60": "2"}})), false).unwrap();
+        assert_eq!((legacy.ranges[1].start, legacy.ranges[1].end, legacy.ranges[1].vid), (60, 100, 2));
+        let err = Layer::from_value(doc(serde_json::json!({"buckets": {"0": "1", "100": "2"}})), false).unwrap_err();
+        assert!(err.to_string().contains("start 100 must be < end 100"), "{}", err);
+
+        let zero = doc(serde_json::json!({"bucket_size": 0}));
+        assert!(Layer::from_value(zero, false).unwrap_err().to_string().contains("bucket_size"));
+    }
+
+    #[test]
+    fn test_composite_hash_key() {
+        let doc = serde_json::json!({
+            "layer_id": "campaign", "version": "v1", "priority": 1,
+            "hash_key": ["user_id", "campaign_id"], "enabled": true,
+            "ranges": [{"start": 0, "end": 10000, "vid": 1}]
+        });
+        let layer = Layer::from_value(doc, false).unwrap();
+        assert!(layer.hash_key.is_composite());
+        assert_eq!(layer.hash_key.to_string(), "user_id+campaign_id");
+
+        // Composite keys serialize as arrays, single keys stay plain strings
+        let value = serde_json::to_value(&layer).unwrap();
+        assert_eq!(value["hash_key"], serde_json::json!(["user_id", "campaign_id"]));
+        assert_eq!(Layer::from_value(value, false).unwrap(), layer);
+        assert_eq!(serde_json::to_value(HashKey::from("user_id")).unwrap(), serde_json::json!("user_id"));
+
+        let context = |campaign: serde_json::Value| {
+            HashMap::from([
+                ("user_id".to_string(), serde_json::json!("u1")),
+                ("campaign_id".to_string(), campaign),
+            ])
+        };
+        assert_eq!(layer.hash_key.value(&context(serde_json::json!(7))).unwrap(), "u1\u{1f}7");
+        assert_eq!(
+            layer.hash_key.value(&context(serde_json::json!([1]))),
+            Err(HashKeyError::NotScalar("campaign_id".to_string()))
+        );
+        let partial = HashMap::from([("user_id".to_string(), serde_json::json!("u1"))]);
+        assert_eq!(
+            layer.hash_key.value(&partial),
+            Err(HashKeyError::Missing("campaign_id".to_string()))
+        );
+
+        // A single key hashes the raw value, as before composite keys existed
+        assert_eq!(HashKey::from("user_id").value(&partial).unwrap(), "u1");
+
+        for bad in [serde_json::json!([]), serde_json::json!(["user_id", "user_id"])] {
+            assert!(serde_json::from_value::<HashKey>(bad).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_strict_config_rejects_deprecated_fields() {
+        let temp_dir = TempDir::new().unwrap();
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(temp_dir.path().join("none")).unwrap());
+
+        let legacy = serde_json::json!({
+            "layer_id": "legacy",
+            "version": "v1",
+            "priority": 1,
+            "hash_key": "user_id",
+            "services": ["svc"],
+            "buckets": {"0": "a"},
+            "groups": {"a": {"vid": 1, "params": {}}}
+        });
+        let path = temp_dir.path().join("legacy.json");
+        std::fs::write(&path, legacy.to_string()).unwrap();
+
+        // Lenient mode still refuses inline groups, naming the ids migration would generate
+        let err = Layer::from_file(&path).unwrap_err();
+        let ExperimentError::InlineGroups(plan) = err.inner() else {
+            panic!("unexpected error {}", err);
+        };
+        assert_eq!(plan, &vec![GroupMapping {
+            group: "a".to_string(),
+            service: "svc".to_string(),
+            eid: migrate::DEFAULT_EID_START,
+            vid: 1,
+        }]);
+        assert!(err.to_string().contains("group 'a' → eid 100000 / vid 1 (svc)"), "{}", err);
+
+        let err = Layer::from_file_strict(&path, true).unwrap_err().to_string();
+        assert!(err.contains("buckets, groups, services"), "{}", err);
+
+        let strict = LayerManager::new(temp_dir.path().to_path_buf()).with_strict_config(true);
+        let err = strict.load_all_layers(&catalog).await.unwrap_err().to_string();
+        assert!(err.contains("1 layer file(s) rejected"), "{}", err);
+        assert!(err.contains("legacy.json"), "{}", err);
+
+        let lenient = LayerManager::new(temp_dir.path().to_path_buf());
+        lenient.load_all_layers(&catalog).await.unwrap();
+        assert!(lenient.get_layer("legacy").is_none());
+        assert_eq!(&lenient.load_errors()[&path].migration, plan);
+    }
+
+    #[tokio::test]
+    async fn test_inline_groups_plan_avoids_catalog_ids() {
+        use crate::catalog::ExperimentDef;
+
+        let exp = |eid: i64, vids: &[i64]| ExperimentDef {
+            eid,
+            service: "svc".to_string(),
+            expires_at: None,
+            state: Default::default(),
+            caps: None,
+            rule: None,
+            exposure_sampling: None,
+            variants: vids
+                .iter()
+                .map(|&vid| VariantDef {
+                    vid,
+                    params: serde_json::json!({}),
+                    dark_params: None,
+                })
+                .collect(),
+        };
+        // vid 252 would be the first vid of eid 251
+        let catalog = Arc::new(
+            ExperimentCatalog::from_experiments(vec![exp(100, &[101, 102]), exp(250, &[252])], PathBuf::new())
+                .unwrap(),
+        );
+
+        let temp_dir = TempDir::new().unwrap();
+        let legacy = serde_json::json!({
+            "layer_id": "legacy",
+            "version": "v1",
+            "priority": 1,
+            "hash_key": "user_id",
+            "buckets": {"0": "a", "5000": "b"},
+            "groups": {"a": {"service": "svc", "params": {}}, "b": {"service": "svc", "params": {}}}
+        });
+        let path = temp_dir.path().join("legacy.json");
+        std::fs::write(&path, legacy.to_string()).unwrap();
+
+        let manager = LayerManager::new(temp_dir.path().to_path_buf());
+        manager.load_all_layers(&catalog).await.unwrap();
+        let load_error = &manager.load_errors()[&path];
+        let plan = &load_error.migration;
+        assert_eq!(plan.len(), 2);
+        assert!(plan.iter().all(|g| g.eid == 351), "{:?}", plan);
+        assert_eq!(plan.iter().map(|g| g.vid).collect::<Vec<_>>(), vec![352, 353]);
+        assert!(load_error.message.contains("group 'a' → eid 351 / vid 352 (svc)"), "{}", load_error.message);
+
+        // Registering the plan as suggested is accepted by the catalog
+        let planned = exp(351, &plan.iter().map(|g| g.vid).collect::<Vec<_>>());
+        assert!(catalog.with_experiment(planned).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_service_budget() {
+        use crate::catalog::ExperimentDef;
+
+        let temp_dir = TempDir::new().unwrap();
+        let layers_dir = temp_dir.path().join("layers");
+        let experiments_dir = temp_dir.path().join("experiments");
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+
+        for eid in [100, 200, 300] {
+            let exp = ExperimentDef {
+                eid,
+                service: "svc".to_string(),
+                expires_at: None,
+                state: Default::default(),
+                caps: None,
+                rule: None,
+                exposure_sampling: None,
+                variants: vec![VariantDef {
+                    vid: eid + 1,
+                    params: serde_json::json!({}),
+                    dark_params: None,
+                }],
+            };
+            std::fs::write(
+                experiments_dir.join(format!("{}.json", eid)),
+                serde_json::to_string(&exp).unwrap(),
+            )
+            .unwrap();
+        }
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+
+        let write_layer = |layer_id: &str, vid: i64| {
+            let path = layers_dir.join(format!("{}.json", layer_id));
+            let layer = serde_json::json!({
+                "layer_id": layer_id,
+                "version": "v1",
+                "priority": 1,
+                "hash_key": "user_id",
+                "enabled": true,
+                "ranges": [{"start": 0, "end": 100, "vid": vid}]
+            });
+            std::fs::write(&path, layer.to_string()).unwrap();
+            path
+        };
+        write_layer("a", 101);
+        write_layer("b", 201);
+
+        let tight = LayerManager::new(layers_dir.clone()).with_budget(ServiceBudget {
+            max_layers: 1,
+            max_experiments: 0,
+        });
+        let err = tight.load_all_layers(&catalog).await.unwrap_err().to_string();
+        assert!(err.contains("service 'svc' has 2 enabled layers (limit 1): a, b"), "{}", err);
+
+        let manager = LayerManager::new(layers_dir.clone()).with_budget(ServiceBudget {
+            max_layers: 0,
+            max_experiments: 2,
+        });
+        manager.load_all_layers(&catalog).await.unwrap();
+
+        // A third experiment is rejected and the live set is unchanged
+        let path = write_layer("c", 301);
+        let err = manager.load_layer("c", &path, &catalog).await.unwrap_err();
+        assert!(matches!(err.inner(), ExperimentError::BudgetExceeded(_)));
+        assert!(err.to_string().contains("layer 'c'"), "{}", err);
+        assert!(manager.get_layer("c").is_none());
+        assert_eq!(manager.get_layers_for_service("svc").len(), 2);
+        assert!(manager.load_errors().contains_key(&path));
+    }
+
+    #[tokio::test]
+    async fn test_service_index_tracks_enabled_state() {
+        use crate::catalog::ExperimentDef;
+
+        let temp_dir = TempDir::new().unwrap();
+        let layers_dir = temp_dir.path().join("layers");
+        let experiments_dir = temp_dir.path().join("experiments");
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+
+        let exp = ExperimentDef {
+            eid: 100,
+            service: "svc".to_string(),
+            expires_at: None,
+            state: Default::default(),
+            caps: None,
+            rule: None,
+            exposure_sampling: None,
+            variants: vec![
+                VariantDef {
+                    vid: 101,
+                    params: serde_json::json!({}),
+                    dark_params: None,
+                },
+                VariantDef {
+                    vid: 102,
+                    params: serde_json::json!({}),
+                    dark_params: None,
+                },
+            ],
+        };
+        std::fs::write(experiments_dir.join("100.json"), serde_json::to_string(&exp).unwrap()).unwrap();
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+
+        let write_layer = |layer_id: &str, priority: i32, vid: i64, enabled: bool| {
+            let path = layers_dir.join(format!("{}.json", layer_id));
+            let layer = serde_json::json!({
+                "layer_id": layer_id,
+                "version": "v1",
+                "priority": priority,
+                "hash_key": "user_id",
+                "enabled": enabled,
+                "ranges": [{"start": 0, "end": 100, "vid": vid}]
+            });
+            std::fs::write(&path, layer.to_string()).unwrap();
+            path
+        };
+        write_layer("low", 1, 101, true);
+        let high = write_layer("high", 10, 102, true);
+
+        let manager = LayerManager::new(layers_dir.clone());
+        manager.load_all_layers(&catalog).await.unwrap();
+
+        let ids = |m: &LayerManager| -> Vec<String> {
+            m.get_layers_for_service("svc")
+                .iter()
+                .map(|l| l.layer_id.clone())
+                .collect()
+        };
+        assert_eq!(ids(&manager), vec!["high", "low"]);
+
+        // Disabling drops the layer from the index; rollback restores it
+        write_layer("high", 10, 102, false);
+        manager.load_layer("high", &high, &catalog).await.unwrap();
+        assert_eq!(ids(&manager), vec!["low"]);
+
+        manager.rollback_layer("high", &catalog).await.unwrap();
+        assert_eq!(ids(&manager), vec!["high", "low"]);
+        assert!(manager.get_layers_for_service("other").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_expired_resources_stop_serving() {
+        use crate::catalog::ExperimentDef;
+
+        let exp = |eid: i64, expires_at: Option<u64>| ExperimentDef {
+            eid,
+            service: "svc".to_string(),
+            expires_at,
+            state: Default::default(),
+            caps: None,
+            rule: None,
+            exposure_sampling: None,
+            variants: vec![VariantDef {
+                vid: eid + 1,
+                params: serde_json::json!({}),
+                dark_params: None,
+            }],
+        };
+        let catalog = Arc::new(
+            ExperimentCatalog::from_experiments(vec![exp(100, None), exp(200, Some(1))], PathBuf::new()).unwrap(),
+        );
+
+        let temp_dir = TempDir::new().unwrap();
+        let far_future = unix_now() + 86_400;
+        for (layer_id, priority, vid, expires_at) in
+            [("live", 3, 101, Some(far_future)), ("stale_exp", 2, 201, None), ("stale", 1, 101, Some(1))]
+        {
+            let layer = serde_json::json!({
+                "layer_id": layer_id, "version": "v1", "priority": priority, "hash_key": "user_id",
+                "enabled": true, "expires_at": expires_at,
+                "ranges": [{"start": 0, "end": 10000, "vid": vid}]
+            });
+            std::fs::write(temp_dir.path().join(format!("{}.json", layer_id)), layer.to_string()).unwrap();
+        }
+
+        let manager = LayerManager::new(temp_dir.path().to_path_buf());
+        manager.load_all_layers(&catalog).await.unwrap();
+        let snapshot = manager.snapshot();
+
+        // Expired layers leave the index; expired experiments are never assigned
+        let ids: Vec<String> = snapshot.layers_for_service("svc").iter().map(|l| l.layer_id.clone()).collect();
+        assert_eq!(ids, vec!["live", "stale_exp"]);
+        assert_eq!(snapshot.expired().layers.keys().collect::<Vec<_>>(), vec!["stale"]);
+        assert_eq!(snapshot.expired().experiments.keys().collect::<Vec<_>>(), vec![&200]);
+
+        let request = crate::merge::ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([("user_id".to_string(), serde_json::json!("u1"))]),
+            layers: vec![],
+            diagnostics: false,
+        };
+        let response = crate::merge::merge_layers_batch(&request, &snapshot, &HashMap::new()).unwrap();
+        assert_eq!(response.results["svc"].vids, vec![101]);
+
+        // Nothing new expired since the load
+        assert!(!manager.refresh_expiry());
+        assert!(Expired::collect(snapshot.layers().map(Arc::as_ref), &catalog, 0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reindex_revisits_only_changed_vids() {
+        use crate::catalog::ExperimentDef;
+
+        let catalog_with = |owner_of_200: &str| {
+            let exp = |eid: i64, service: &str| ExperimentDef {
+                eid,
+                service: service.to_string(),
+                expires_at: None,
+                state: Default::default(),
+                caps: None,
+                rule: None,
+                exposure_sampling: None,
+                variants: vec![VariantDef {
+                    vid: eid + 1,
+                    params: serde_json::json!({}),
+                    dark_params: None,
+                }],
+            };
+            Arc::new(
+                ExperimentCatalog::from_experiments(
+                    vec![exp(100, "a"), exp(200, owner_of_200), exp(300, "c")],
+                    PathBuf::new(),
+                )
+                .unwrap(),
+            )
+        };
+
+        let temp_dir = TempDir::new().unwrap();
+        for (layer_id, vids) in [("l1", vec![101, 201]), ("l2", vec![301]), ("l3", vec![201])] {
+            let ranges: Vec<_> = vids
+                .iter()
+                .enumerate()
+                .map(|(i, vid)| serde_json::json!({"start": i * 100, "end": i * 100 + 100, "vid": vid}))
+                .collect();
+            let layer = serde_json::json!({
+                "layer_id": layer_id, "version": "v1", "priority": 1, "hash_key": "user_id",
+                "enabled": true, "ranges": ranges
+            });
+            std::fs::write(temp_dir.path().join(format!("{}.json", layer_id)), layer.to_string()).unwrap();
+        }
+
+        let manager = LayerManager::new(temp_dir.path().to_path_buf());
+        manager.load_all_layers(&catalog_with("b")).await.unwrap();
+        let before = manager.snapshot();
+        let ids = |service: &str| -> Vec<String> {
+            manager
+                .get_layers_for_service(service)
+                .iter()
+                .map(|l| l.layer_id.clone())
+                .collect()
+        };
+        assert_eq!(ids("b"), vec!["l1", "l3"]);
+
+        // Experiment 200 moves from service b to a
+        let moved = catalog_with("a");
+        manager.reindex(&moved).unwrap();
+        assert_eq!(ids("a"), vec!["l1", "l3"]);
+        assert!(ids("b").is_empty());
+
+        // Same result as a full rebuild; untouched services keep their entry
+        let after = manager.snapshot();
+        assert_eq!(after.index, ServiceIndex::build(&after.layers, &moved, &after.emergency, &after.expired));
+        assert!(Arc::ptr_eq(
+            &before.index.services["c"],
+            &after.index.services["c"]
+        ));
+
+        // Single-layer changes re-index just their services, copying only their shards
+        let mut l1 = (*after.layer("l1").unwrap()).clone();
+        l1.ranges.retain(|r| r.vid != 201);
+        manager.upsert_layer(l1, Path::new("l1.json"), &moved).unwrap();
+        manager.remove_layer("l2", &moved).await.unwrap();
+        let last = manager.snapshot();
+        assert_eq!(last.index, ServiceIndex::build(&last.layers, &moved, &last.emergency, &last.expired));
+        assert_eq!(ids("a"), vec!["l1", "l3"]);
+        assert!(ids("c").is_empty());
+        assert!(last.layers.changed_shards(&after.layers).count() <= 2);
+
+        // A layer update holding the pre-reindex catalog doesn't roll the index back
+        let l3 = (*last.layer("l3").unwrap()).clone();
+        manager.upsert_layer(l3, Path::new("l3.json"), &catalog_with("b")).unwrap();
+        assert!(Arc::ptr_eq(&manager.snapshot().catalog, &moved));
+        assert_eq!(ids("a"), vec!["l1", "l3"]);
+    }
+
+    #[test]
+    fn test_concurrent_writers_keep_every_change() {
+        let catalog = Arc::new(ExperimentCatalog::default());
+        let manager = Arc::new(LayerManager::new(PathBuf::new()));
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let (manager, catalog) = (manager.clone(), catalog.clone());
+                std::thread::spawn(move || {
+                    for j in 0..25 {
+                        let layer = Layer::from_value(
+                            serde_json::json!({
+                                "layer_id": format!("l{}_{}", i, j), "version": "v1", "priority": 1,
+                                "hash_key": "user_id", "enabled": true, "ranges": []
+                            }),
+                            false,
+                        )
+                        .unwrap();
+                        manager.upsert_layer(layer, Path::new("l.json"), &catalog).unwrap();
+                        manager.set_exclusions(Exclusions::default());
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let snapshot = manager.snapshot();
+        assert_eq!(snapshot.layers.len(), 200);
+        assert_eq!(snapshot.epoch(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_resolution_order() {
+        use crate::catalog::ExperimentDef;
+
+        let temp_dir = TempDir::new().unwrap();
+        let variants = |vids: &[i64]| {
+            vids.iter()
+                .map(|&vid| VariantDef {
+                    vid,
+                    params: serde_json::json!({}),
+                    dark_params: None,
+                })
+                .collect()
+        };
+        let catalog = ExperimentCatalog::from_experiments(
+            vec![
+                ExperimentDef {
+                    eid: 100,
+                    service: "svc".to_string(),
+                    expires_at: None,
+                    state: Default::default(),
+                    caps: None,
+                    rule: None,
+                    exposure_sampling: None,
+                    variants: variants(&[101, 102]),
+                },
+                ExperimentDef {
+                    eid: 200,
+                    service: "svc".to_string(),
+                    expires_at: None,
+                    state: Default::default(),
+                    caps: None,
+                    rule: None,
+                    exposure_sampling: None,
+                    variants: variants(&[201]),
+                },
+            ],
+            temp_dir.path().to_path_buf(),
+        )
+        .unwrap();
+        let catalog = Arc::new(catalog);
+
+        let write_layer = |layer_id: &str, priority: i32, ranges: serde_json::Value| {
+            let layer = serde_json::json!({
+                "layer_id": layer_id,
+                "version": "v1",
+                "priority": priority,
+                "hash_key": "user_id",
+                "enabled": true,
+                "ranges": ranges
+            });
+            std::fs::write(temp_dir.path().join(format!("{}.json", layer_id)), layer.to_string()).unwrap();
+        };
+        write_layer(
+            "low",
+            1,
+            serde_json::json!([
+                {"start": 0, "end": 2500, "vid": 101},
+                {"start": 2500, "end": 5000, "vid": 102, "label": "treatment"}
+            ]),
+        );
+        write_layer("high", 10, serde_json::json!([{"start": 0, "end": 5000, "vid": 201}]));
+
+        let manager = LayerManager::new(temp_dir.path().to_path_buf());
+        manager.load_all_layers(&catalog).await.unwrap();
+
+        let order = manager.resolution_order("svc", &catalog);
+        let ids: Vec<&str> = order.layers.iter().map(|l| l.layer_id.as_str()).collect();
+        assert_eq!(ids, vec!["high", "low"]);
+        assert_eq!(order.layers[0].rank, 1);
+        assert_eq!(order.layers[0].salt, "high_v1");
+        assert_eq!(order.layers[1].coverage_percent, 50.0);
+        assert_eq!(
+            order.layers[1].variants[1],
+            VariantCoverage {
+                eid: 100,
+                vid: 102,
+                label: Some("treatment".to_string()),
+                percent: 25.0
+            }
+        );
+        // Independent layers at 50% each cover 75% of subjects
+        assert!((order.coverage_percent - 75.0).abs() < 1e-9);
+
+        assert!(manager.resolution_order("unknown", &catalog).layers.is_empty());
+    }
+
+    #[test]
+    fn test_range_labels() {
+        let layer = |ranges: serde_json::Value| {
+            Layer::from_value(
+                serde_json::json!({
+                    "layer_id": "labelled",
+                    "version": "v1",
+                    "priority": 1,
+                    "hash_key": "user_id",
+                    "ranges": ranges
+                }),
+                false,
+            )
+        };
+
+        let ok = layer(serde_json::json!([
+            {"start": 0, "end": 5000, "vid": 101, "label": "control"},
+            {"start": 5000, "end": 10000, "vid": 102}
+        ]))
+        .unwrap();
+        assert_eq!(ok.get_range(100).unwrap().label.as_deref(), Some("control"));
+        assert_eq!(ok.label_for(101), Some("control"));
+        assert_eq!(ok.label_for(102), None);
+
+        // One label can't name two arms
+        let err = layer(serde_json::json!([
+            {"start": 0, "end": 5000, "vid": 101, "label": "control"},
+            {"start": 5000, "end": 10000, "vid": 102, "label": "control"}
+        ]))
+        .unwrap_err();
+        assert!(err.to_string().contains("label 'control'"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_resync_applies_only_changes() {
+        let temp_dir = TempDir::new().unwrap();
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(temp_dir.path().join("none")).unwrap());
+
+        let write_layer = |layer_id: &str, end: u32| {
+            let layer = serde_json::json!({
+                "layer_id": layer_id,
+                "version": "v1",
+                "priority": 1,
+                "hash_key": "user_id",
+                "ranges": [{"start": 0, "end": end, "vid": 1}]
+            });
+            std::fs::write(temp_dir.path().join(format!("{}.json", layer_id)), layer.to_string()).unwrap();
+        };
+        write_layer("a", 100);
+        write_layer("b", 100);
+
+        let manager = LayerManager::new(temp_dir.path().to_path_buf());
+        manager.load_all_layers(&catalog).await.unwrap();
+        let before = manager.get_layer("a").unwrap();
+
+        // Nothing changed: same Arc, empty summary
+        let summary = manager.resync(&catalog).await.unwrap();
+        assert!(summary.is_empty());
+        assert!(Arc::ptr_eq(&before, &manager.get_layer("a").unwrap()));
+
+        write_layer("a", 200);
+        write_layer("c", 100);
+        std::fs::write(temp_dir.path().join("b.json"), "{ broken").unwrap();
+
+        let summary = manager.resync(&catalog).await.unwrap();
+        assert_eq!(summary.updated, vec!["a"]);
+        assert_eq!(summary.added, vec!["c"]);
+        assert!(summary.removed.is_empty());
+        assert_eq!(manager.get_layer("a").unwrap().ranges[0].end, 200);
+        // A file that fails to parse keeps serving its previous version
+        assert!(manager.get_layer("b").is_some());
+
+        std::fs::remove_file(temp_dir.path().join("c.json")).unwrap();
+        let summary = manager.resync(&catalog).await.unwrap();
+        assert_eq!(summary.removed, vec!["c"]);
+    }
+
+    #[tokio::test]
+    async fn test_load_errors_carry_layer_context() {
+        let temp_dir = TempDir::new().unwrap();
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(temp_dir.path().join("none")).unwrap());
+
+        let overlapping = serde_json::json!({
+            "layer_id": "overlap",
+            "version": "v1",
+            "priority": 1,
+            "hash_key": "user_id",
+            "ranges": [
+                {"start": 0, "end": 10, "vid": 1},
+                {"start": 5, "end": 20, "vid": 2}
+            ]
+        });
+        let overlap_path = temp_dir.path().join("overlap.json");
+        std::fs::write(&overlap_path, overlapping.to_string()).unwrap();
+
+        let broken_path = temp_dir.path().join("broken.json");
+        std::fs::write(&broken_path, "{\n  \"layer_id\": \"broken\",\n  oops\n}").unwrap();
+
+        let err = Layer::from_file(&overlap_path).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("layer 'overlap'"), "{}", message);
+        assert!(message.contains("overlap.json"), "{}", message);
+        assert!(matches!(err.inner(), ExperimentError::InvalidParameter(_)));
+
+        let manager = LayerManager::new(temp_dir.path().to_path_buf());
+        manager.load_all_layers(&catalog).await.unwrap();
+
+        let errors = manager.load_errors();
+        assert_eq!(errors.len(), 2);
+        let broken = errors[&broken_path].context.as_ref().unwrap();
+        assert_eq!(broken.kind, ResourceKind::Layer);
+        assert_eq!(broken.line, Some(3));
+        assert_eq!(
+            errors[&overlap_path].context.as_ref().unwrap().id.as_deref(),
+            Some("overlap")
+        );
+
+        // Fixing the file clears its entry
+        std::fs::write(&overlap_path, serde_json::json!({
+            "layer_id": "overlap",
+            "version": "v2",
+            "priority": 1,
+            "hash_key": "user_id",
+            "ranges": [{"start": 0, "end": 10, "vid": 1}]
+        }).to_string())
+        .unwrap();
+        manager.load_layer("overlap", &overlap_path, &catalog).await.unwrap();
+        assert!(!manager.load_errors().contains_key(&overlap_path));
     }
 
     #[tokio::test]
@@ -619,10 +3048,15 @@ mod tests {
         let exp_def = ExperimentDef {
             eid: 100,
             service: "svc".to_string(),
+            expires_at: None,
+            state: Default::default(),
+            caps: None,
             rule: None,
+            exposure_sampling: None,
             variants: vec![VariantDef {
                 vid: 1001,
                 params: serde_json::json!({}),
+                dark_params: None,
             }],
         };
         std::fs::write(
@@ -630,21 +3064,26 @@ mod tests {
             serde_json::to_string_pretty(&exp_def).unwrap(),
         )
         .unwrap();
-        let catalog = ExperimentCatalog::load_from_dir(groups_dir).unwrap();
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(groups_dir).unwrap());
 
         let layer = Layer {
             layer_id: "test".to_string(),
             version: "v1".to_string(),
             priority: 100,
-            hash_key: "user_id".to_string(),
+            hash_key: "user_id".into(),
             salt: None,
+            bucket_size: None,
+            expires_at: None,
+            rule: None,
             services: vec!["svc".to_string()],
             ranges: vec![BucketRange {
                 start: 0,
                 end: 1,
                 vid: 1001,
+                label: None,
             }],
             enabled: true,
+            aa_test: false,
         };
 
         std::fs::write(&layer_path, serde_json::to_string_pretty(&layer).unwrap()).unwrap();
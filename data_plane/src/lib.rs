@@ -1,10 +1,67 @@
+// The metrics `lazy_static!` block outgrows the default macro recursion limit
+#![recursion_limit = "256"]
+
+pub mod aa_test;
+pub mod aliases;
+pub mod applied;
+pub mod caps;
 pub mod catalog;
+#[cfg(feature = "cel")]
+pub mod cel;
+pub mod churn;
+pub mod clock;
+pub mod clone;
+pub mod collation;
 pub mod config;
+pub mod context_db;
+pub mod context_policy;
+pub mod coverage;
+#[cfg(feature = "admin")]
+pub mod dashboard;
+pub mod effective_params;
+pub mod emergency;
+pub mod engine;
 pub mod error;
+pub mod events;
+pub mod exclusion;
+pub mod export;
+pub mod expiry;
+pub mod failover;
+pub mod federation;
+pub mod feed;
+pub mod field_alias;
+pub mod freeze;
+pub mod graph;
+pub mod exposure;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod hash;
+pub mod health;
+pub mod kv;
 pub mod layer;
+pub mod lifecycle;
+pub mod manifest;
 pub mod merge;
 pub mod metrics;
+pub mod metrics_push;
+pub mod net;
+pub mod number_coercion;
+pub mod openfeature;
+pub mod overrides;
+pub mod params;
+pub mod preview;
 pub mod rule;
+pub mod rule_optimizer;
+pub mod runtime;
+pub mod sdk_keys;
 pub mod server;
+pub mod shard;
+pub mod signing;
+pub mod sim;
+pub mod source;
+pub mod stats;
+pub mod watchdog;
 pub mod watcher;
+pub mod whatif;
+#[cfg(feature = "grpc")]
+pub mod xds;
@@ -0,0 +1,97 @@
+//! Experiment lifecycle states.
+//!
+//! The control plane sets `state` on each experiment; the data plane decides
+//! what that means for serving and refuses catalog updates that move an
+//! experiment along an edge the lifecycle doesn't allow, keeping the previous
+//! catalog in place (like any other failed apply).
+//!
+//! Allowed transitions (staying in the same state is always allowed):
+//!
+//! | from        | to                                       |
+//! |-------------|------------------------------------------|
+//! | `draft`     | `ramping`, `running`, `archived`         |
+//! | `ramping`   | `running`, `paused`, `completed`         |
+//! | `running`   | `ramping`, `paused`, `completed`         |
+//! | `paused`    | `ramping`, `running`, `completed`, `archived` |
+//! | `completed` | `archived`                               |
+//! | `archived`  | —                                        |
+//!
+//! Only `ramping` and `running` experiments are assigned. Experiments without
+//! a `state` are `running`, so existing configs keep serving.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LifecycleState {
+    /// Defined but never served
+    Draft,
+    /// Serving while traffic is ramped up
+    Ramping,
+    #[default]
+    Running,
+    /// Temporarily not assigned; may resume
+    Paused,
+    /// Finished; no longer assigned
+    Completed,
+    /// Terminal; kept for reference only
+    Archived,
+}
+
+impl LifecycleState {
+    /// Whether subjects are assigned to this experiment's variants
+    pub fn serves(self) -> bool {
+        matches!(self, Self::Ramping | Self::Running)
+    }
+
+    /// Whether an experiment in `self` may move to `next`
+    pub fn can_transition_to(self, next: Self) -> bool {
+        use LifecycleState::*;
+        self == next
+            || matches!(
+                (self, next),
+                (Draft, Ramping | Running | Archived)
+                    | (Ramping, Running | Paused | Completed)
+                    | (Running, Ramping | Paused | Completed)
+                    | (Paused, Ramping | Running | Completed | Archived)
+                    | (Completed, Archived)
+            )
+    }
+}
+
+impl fmt::Display for LifecycleState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Draft => "draft",
+            Self::Ramping => "ramping",
+            Self::Running => "running",
+            Self::Paused => "paused",
+            Self::Completed => "completed",
+            Self::Archived => "archived",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        use LifecycleState::*;
+        assert!(Draft.can_transition_to(Running));
+        assert!(Running.can_transition_to(Paused));
+        assert!(Paused.can_transition_to(Running));
+        assert!(Completed.can_transition_to(Archived));
+
+        assert!(!Completed.can_transition_to(Running));
+        assert!(!Archived.can_transition_to(Draft));
+        assert!(!Running.can_transition_to(Draft));
+        assert!(Archived.can_transition_to(Archived));
+
+        assert!(Ramping.serves() && Running.serves());
+        assert!(!Draft.serves() && !Paused.serves() && !Completed.serves());
+    }
+}
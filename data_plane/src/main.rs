@@ -1,16 +1,7 @@
-mod catalog;
-mod config;
-mod error;
-mod layer;
-mod merge;
-mod hash;
-mod rule;
-mod server;
-mod watcher;
-mod metrics;
-
 use anyhow::Result;
+use experiment_data_plane::{aliases, applied, catalog, config, engine, failover, freeze, health, kv, layer, manifest, runtime, server, source, watchdog, watcher};
 use std::sync::Arc;
+use std::time::Duration;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
@@ -24,52 +15,216 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    // Offline subcommands
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("migrate-config") {
+        return config::migrate::run_cli(&args[2..]);
+    }
+
     tracing::info!("Starting Experiment Data Plane Server");
 
     // Load configuration
-    let config = config::Config::from_env()?;
+    let mut config = config::Config::from_env()?;
     tracing::info!("Configuration loaded: {:?}", config);
 
+    // Edge builds have no other way to receive config changes
+    #[cfg(feature = "edge")]
+    if config.xds_servers.is_empty() {
+        anyhow::bail!("XDS_SERVER is required in edge builds");
+    }
+
+    let source_failover = prioritized_sources(&mut config)?;
+
+    config::template::init(config.config_variables_file.as_deref())?;
+
     // Step 1: Load experiment catalog first (happens-before layer loading)
     tracing::info!("Loading experiment catalog from {:?}", config.experiments_dir);
-    let catalog = Arc::new(catalog::ExperimentCatalog::load_from_dir(config.experiments_dir.clone())?);
+    let experiments_dir = config.experiments_dir.clone();
+    let overlay_dirs = source::overlay_experiment_dirs(&config.config_overlay_dirs);
+    let initial_catalog = if config.catalog_lazy_params {
+        catalog::ExperimentCatalog::load_lazy(experiments_dir, overlay_dirs, config.catalog_param_cache_entries)?
+    } else {
+        catalog::ExperimentCatalog::load_from_dirs(experiments_dir, overlay_dirs)?
+    };
+    let catalog = Arc::new(initial_catalog);
     tracing::info!("Experiment catalog loaded: {} experiments", catalog.len());
 
     // Step 2: Initialize layer manager
-    let layer_manager = Arc::new(layer::LayerManager::new(config.layers_dir.clone()));
+    let mut layer_manager = layer::LayerManager::new(config.layers_dir.clone())
+        .with_strict_config(config.strict_config)
+        .with_overlays(source::overlay_layer_dirs(&config.config_overlay_dirs))
+        .with_budget(layer::ServiceBudget {
+            max_layers: config.max_layers_per_service,
+            max_experiments: config.max_experiments_per_service,
+        })
+        .with_max_evaluated_layers(config.max_evaluated_layers_per_service)
+        .with_semver_max_segments(config.semver_max_segments);
+    if let Some(anonymous_field) = &config.identity_anonymous_field {
+        layer_manager = layer_manager.with_identity_policy(aliases::IdentityPolicy {
+            stable_field: config.identity_stable_field.clone(),
+            anonymous_field: anonymous_field.clone(),
+        });
+    }
+    if let Some(path) = &config.freeze_windows_file {
+        let freeze = Arc::new(freeze::FreezeSchedule::load(path)?);
+        freeze.set_override(config.freeze_override);
+        layer_manager = layer_manager.with_freeze(freeze);
+    }
+    let layer_manager = Arc::new(layer_manager);
+
+    // Config processing gets its own workers so rebuilds can't starve serving
+    let config_runtime = runtime::ConfigRuntime::start(config.config_worker_threads, config.config_worker_nice)?;
+    let config_tasks = config_runtime.handle().clone();
+
+    // Subscribers to engine events attach before the initial load so they see it
+    let applied_log = match &config.applied_log_dir {
+        Some(dir) => {
+            let log = Arc::new(applied::AppliedLog::open(
+                dir.clone(),
+                config.applied_log_max_bytes,
+                config.applied_log_max_files,
+            )?);
+            config_tasks.spawn(applied::record(log.clone(), layer_manager.events()));
+            Some(log)
+        }
+        None => None,
+    };
 
     // Step 3: Load initial layers (requires catalog for index building)
     layer_manager.load_all_layers(&catalog).await?;
     tracing::info!("Initial layers loaded");
 
-    // Start file watcher for hot reload (layers only)
-    let watcher_manager = layer_manager.clone();
-    let watcher_catalog = catalog.clone();
-    let watcher_handle = tokio::spawn(async move {
-        if let Err(e) = watcher::watch_layers(watcher_manager, watcher_catalog).await {
-            tracing::error!("Watcher error: {}", e);
+    let health = Arc::new(health::ConfigHealth::new().with_manifest(config.config_manifest_file.clone()));
+    manifest::check(&layer_manager, &health);
+
+    // Start file watcher for hot reload (layers and experiment catalog), or the
+    // poller in builds without the `watcher` feature; the switcher restarts it
+    // when the config source changes at runtime
+    let watch_options = watcher::WatchOptions {
+        queue_capacity: config.watch_queue_capacity,
+        debounce: Duration::from_millis(config.watch_debounce_ms),
+        poll_interval: Duration::from_secs(config.watch_poll_secs.max(1)),
+    };
+    let switcher = {
+        let _config_context = config_tasks.enter();
+        source::SourceSwitcher::start(layer_manager.clone(), health.clone(), watch_options)
+    };
+
+    #[cfg(all(unix, not(feature = "edge")))]
+    if let Some(source_file) = config.config_source_file.clone() {
+        config_tasks.spawn(source::switch_on_signal(switcher.clone(), source_file));
+    }
+
+    #[cfg(not(feature = "edge"))]
+    if let Some(failover) = &source_failover {
+        config_tasks.spawn(failover::run(failover.clone(), switcher.clone(), health.clone()));
+    }
+
+    // Edge builds don't poll the config directories; xDS applies changes directly
+    #[cfg(not(feature = "edge"))]
+    if config.resync_interval_secs > 0 {
+        let interval = Duration::from_secs(config.resync_interval_secs);
+        config_tasks.spawn(watcher::resync_periodically(layer_manager.clone(), health.clone(), interval));
+        tracing::info!("Scheduled full resync every {:?}", interval);
+    }
+
+    if config.watchdog_interval_secs > 0 {
+        config_tasks.spawn(watchdog::supervise(
+            health.clone(),
+            switcher.clone(),
+            watchdog::WatchdogOptions {
+                interval: Duration::from_secs(config.watchdog_interval_secs),
+                stall_after: Duration::from_secs(config.watchdog_stall_secs),
+                max_restarts: config.watchdog_max_restarts,
+            },
+        ));
+    }
+
+    #[cfg(feature = "grpc")]
+    if !config.xds_servers.is_empty() {
+        let subscriber = Arc::new(experiment_data_plane::xds::XdsSubscriber::new(
+            experiment_data_plane::xds::XdsOptions {
+                endpoints: config.xds_servers.clone(),
+                node_id: config.xds_node_id.clone(),
+                initial_backoff: Duration::from_millis(500),
+                max_backoff: Duration::from_secs(config.xds_reconnect_max_secs.max(1)),
+                failover: failover_options(&config),
+            },
+            layer_manager.clone(),
+            health.clone(),
+        ));
+        config_tasks.spawn(subscriber.run());
+    }
+
+    if let Some(freeze) = layer_manager.freeze() {
+        if freeze.mode() == freeze::FreezeMode::Queue {
+            config_tasks.spawn(freeze::resync_after_windows(
+                freeze.clone(),
+                layer_manager.clone(),
+                health.clone(),
+                Duration::from_secs(config.freeze_check_secs.max(1)),
+            ));
         }
-    });
+    }
+
+    // Shared by exposure caps and support overrides
+    let kv = kv::from_config(&config).await?;
+    let mut builder = engine::Evaluator::builder()
+        .with_layer_manager(layer_manager)
+        .with_kv_store(kv.clone());
+    if config.result_cache_ttl_ms > 0 {
+        builder = builder.with_result_cache(
+            Duration::from_millis(config.result_cache_ttl_ms),
+            config.result_cache_max_entries,
+        );
+    }
+    if config.evaluation_coalescing {
+        builder = builder.with_coalescing();
+    }
+    let evaluator = Arc::new(builder.build()?);
 
     // Start HTTP server
     let server_handle = tokio::spawn(async move {
-        if let Err(e) = server::run_server(config, layer_manager, catalog).await {
+        if let Err(e) = server::run_server(config, evaluator, kv, health, switcher, applied_log, source_failover).await {
             tracing::error!("Server error: {}", e);
         }
     });
 
-    // Wait for both tasks
-    tokio::select! {
-        _ = watcher_handle => {
-            tracing::warn!("Watcher stopped");
-        }
-        _ = server_handle => {
-            tracing::warn!("Server stopped");
-        }
-        _ = tokio::signal::ctrl_c() => {
-            tracing::info!("Received shutdown signal");
-        }
-    }
+    // The server stops on SIGTERM / Ctrl-C once open connections are drained
+    let _ = server_handle.await;
+    tracing::info!("Server stopped");
 
     Ok(())
 }
+
+/// With prioritized sources, start from the first one that is present
+#[cfg(not(feature = "edge"))]
+fn prioritized_sources(config: &mut config::Config) -> Result<Option<Arc<failover::SourceFailover>>> {
+    let Some(path) = &config.config_sources_file else {
+        return Ok(None);
+    };
+    let failover = Arc::new(failover::SourceFailover::load(
+        path,
+        failover_options(config),
+    )?);
+    let initial = failover.initial();
+    tracing::info!("Starting from config source '{}'", initial.name);
+    config.layers_dir = initial.source.layers_dir.clone();
+    config.experiments_dir = initial.source.experiments_dir.clone();
+    Ok(Some(failover))
+}
+
+/// Edge builds take config from xDS only
+#[cfg(feature = "edge")]
+fn prioritized_sources(_config: &mut config::Config) -> Result<Option<Arc<failover::SourceFailover>>> {
+    Ok(None)
+}
+
+/// Policy shared by config source and xDS endpoint failover
+fn failover_options(config: &config::Config) -> failover::FailoverOptions {
+    failover::FailoverOptions {
+        check_interval: Duration::from_secs(config.config_failover_check_secs.max(1)),
+        failover_after: Duration::from_secs(config.config_failover_after_secs),
+        failback_after: Duration::from_secs(config.config_failback_after_secs),
+    }
+}
@@ -0,0 +1,173 @@
+//! Config manifest: the files a synced config volume is expected to contain.
+//!
+//! The sync job writes a manifest listing every config file (relative to the
+//! manifest's directory) with the xxh3 hash of its content. It is verified at
+//! startup and after every full reload; a missing, modified or unloaded file
+//! fails readiness until a later check passes, so a half-synced volume is not
+//! silently served as complete.
+
+use crate::config::migrate;
+use crate::error::Result;
+use crate::health::ConfigHealth;
+use crate::layer::LayerManager;
+use crate::metrics;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::xxh3_64;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Manifest {
+    /// Relative path -> content hash (see [`file_hash`])
+    pub files: BTreeMap<String, String>,
+}
+
+/// One manifest entry that doesn't match what is on disk / being served
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ManifestMismatch {
+    pub path: String,
+    pub problem: String,
+}
+
+/// Hash recorded in the manifest: xxh3-64 of the file content, lowercase hex
+pub fn file_hash(content: &[u8]) -> String {
+    format!("{:016x}", xxh3_64(content))
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_value(migrate::parse_document(&content)?)?)
+    }
+
+    /// Compare every listed file against `root` and the layers being served
+    pub fn verify(&self, root: &Path, manager: &LayerManager) -> Vec<ManifestMismatch> {
+        let layers_dir = canonical(&manager.layers_dir());
+        let mut mismatches = Vec::new();
+
+        for (relative, expected) in &self.files {
+            let mismatch = |problem: String| ManifestMismatch {
+                path: relative.clone(),
+                problem,
+            };
+            let path = root.join(relative);
+
+            let content = match std::fs::read(&path) {
+                Ok(content) => content,
+                Err(e) => {
+                    mismatches.push(mismatch(format!("unreadable: {}", e)));
+                    continue;
+                }
+            };
+            let actual = file_hash(&content);
+            if !actual.eq_ignore_ascii_case(expected) {
+                mismatches.push(mismatch(format!("hash {} != expected {}", actual, expected)));
+                continue;
+            }
+
+            // A layer file that is present but rejected (or still loading) isn't served
+            let path = canonical(&path);
+            if path.parent() == Some(layers_dir.as_path()) {
+                let layer_id = path.file_stem().unwrap_or_default().to_string_lossy();
+                if manager.get_layer(&layer_id).is_none() {
+                    mismatches.push(mismatch(format!("layer {} is not loaded", layer_id)));
+                }
+            }
+        }
+
+        mismatches
+    }
+}
+
+/// Verify the manifest configured on `health` (if any) and record the outcome
+pub fn check(manager: &LayerManager, health: &ConfigHealth) {
+    let Some(path) = health.manifest_path() else {
+        return;
+    };
+
+    let mismatches = match Manifest::load(path) {
+        Ok(manifest) => {
+            let root = path.parent().unwrap_or(Path::new("."));
+            manifest.verify(root, manager)
+        }
+        Err(e) => vec![ManifestMismatch {
+            path: path.display().to_string(),
+            problem: format!("unreadable manifest: {}", e),
+        }],
+    };
+
+    if mismatches.is_empty() {
+        tracing::debug!("Config manifest {:?} verified", path);
+    } else {
+        tracing::error!(
+            "Config manifest {:?} mismatch ({} entries), failing readiness: {:?}",
+            path,
+            mismatches.len(),
+            mismatches
+        );
+    }
+    metrics::CONFIG_MANIFEST_MISMATCHES.set(mismatches.len() as i64);
+    health.set_manifest_mismatches(mismatches);
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::ExperimentCatalog;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_manifest_detects_partial_sync() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let layers_dir = root.join("layers");
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::create_dir_all(root.join("experiments")).unwrap();
+
+        let layer = r#"{"layer_id": "a", "version": "v1", "priority": 1, "hash_key": "user_id", "enabled": true, "ranges": []}"#;
+        std::fs::write(layers_dir.join("a.json"), layer).unwrap();
+        let experiment = r#"{"eid": 100, "service": "svc", "variants": [{"vid": 1001, "params": {}}]}"#;
+
+        let manifest_path = root.join("manifest.json");
+        let manifest = serde_json::json!({"files": {
+            "layers/a.json": file_hash(layer.as_bytes()),
+            "experiments/100.json": file_hash(experiment.as_bytes()),
+        }});
+        std::fs::write(&manifest_path, manifest.to_string()).unwrap();
+
+        let manager = LayerManager::new(layers_dir.clone());
+        let health = ConfigHealth::new().with_manifest(Some(manifest_path));
+
+        // Experiment file not synced yet
+        manager
+            .load_all_layers(&Arc::new(ExperimentCatalog::load_from_dir(root.join("experiments")).unwrap()))
+            .await
+            .unwrap();
+        check(&manager, &health);
+        let mismatches = health.manifest_mismatches();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].path, "experiments/100.json");
+
+        // Truncated experiment file
+        std::fs::write(root.join("experiments/100.json"), &experiment[..20]).unwrap();
+        check(&manager, &health);
+        assert!(health.manifest_mismatches()[0].problem.starts_with("hash"));
+
+        std::fs::write(root.join("experiments/100.json"), experiment).unwrap();
+        check(&manager, &health);
+        assert!(health.manifest_mismatches().is_empty());
+
+        // Present on disk but not served
+        manager
+            .remove_layer("a", &Arc::new(ExperimentCatalog::load_from_dir(root.join("experiments")).unwrap()))
+            .await
+            .unwrap();
+        check(&manager, &health);
+        assert_eq!(health.manifest_mismatches()[0].problem, "layer a is not loaded");
+    }
+}
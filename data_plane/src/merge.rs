@@ -1,166 +1,529 @@
-use crate::catalog::ExperimentCatalog;
+use crate::caps::CapGate;
 use crate::error::{ExperimentError, Result};
-use crate::hash::hash_to_bucket;
-use crate::layer::LayerManager;
+use crate::hash::hash_to_bucket_in;
+use crate::layer::{sorted_layers, ServiceLayers, Snapshot};
+use crate::metrics::{self, Stage, StageTimer};
+use crate::overrides::Overrides;
+use crate::params::ParamsRef;
 use crate::rule::FieldType;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 
 /// Experiment request
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ExperimentRequest {
     pub services: Vec<String>,
     pub context: HashMap<String, serde_json::Value>,
     #[serde(default)]
     pub layers: Vec<String>,
+    /// Report skipped layers per service in [`ServiceResult::diagnostics`]
+    #[serde(default)]
+    pub diagnostics: bool,
 }
 
 /// Per-service result
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ServiceResult {
     pub parameters: Value,
+    /// Parallel to `matched_layers`, in canonical layer order
+    /// (priority descending, then layer_id ascending)
     pub vids: Vec<i64>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub matched_layers: Vec<String>,
+    /// Lower-priority layers were skipped by the per-service layer limit
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Subject is in the "do not experiment" population: no layer was evaluated
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub excluded: bool,
+    /// Merged dark params of the matched variants (see
+    /// [`crate::catalog::VariantDef::dark_params`]), for logging only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dark_parameters: Option<Value>,
+    /// The federated plane owning the service couldn't be reached (see
+    /// [`crate::federation`]); callers should use their defaults
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unavailable: bool,
+    /// Layers that didn't contribute, counted by outcome, when the request asked for diagnostics
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<BTreeMap<LayerOutcome, u32>>,
+    /// Every layer considered, when the request was traced (never sent to clients)
+    #[serde(skip)]
+    pub trace: Option<Vec<LayerTrace>>,
+}
+
+/// Why a considered layer did or did not contribute to a service result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerOutcome {
+    Matched,
+    /// Matched through a support override
+    Pinned,
+    /// Bucketed into a variant whose exposure cap was reached; served its control variant
+    Capped,
+    MissingHashKey,
+    LayerRuleFailed,
+    /// Bucket not allocated to any variant
+    Unallocated,
+    UnknownVariant,
+    /// Bucket belongs to another service's variant
+    OtherService,
+    EmergencyDisabled,
+    /// Layer or experiment past its `expires_at`
+    Expired,
+    /// Experiment's lifecycle state doesn't serve (draft, paused, completed, archived)
+    NotServing,
+    /// Not evaluated: the service's per-request layer limit was reached
+    Truncated,
+    ExperimentRuleFailed,
+}
+
+impl LayerOutcome {
+    /// Whether the layer contributed a variant to the result
+    pub fn contributed(self) -> bool {
+        matches!(self, LayerOutcome::Matched | LayerOutcome::Pinned | LayerOutcome::Capped)
+    }
+}
+
+/// One layer's outcome in an evaluation trace
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LayerTrace {
+    pub layer_id: String,
+    pub outcome: LayerOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eid: Option<i64>,
+}
+
+/// Append to `trace` when tracing; a no-op otherwise
+fn note(trace: &mut Option<Vec<LayerTrace>>, layer_id: &str, outcome: LayerOutcome, eid: Option<i64>) {
+    if let Some(trace) = trace {
+        trace.push(LayerTrace {
+            layer_id: layer_id.to_string(),
+            outcome,
+            eid,
+        });
+    }
 }
 
 /// Experiment response
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ExperimentResponse {
     pub results: HashMap<String, ServiceResult>,
 }
 
-/// Merge multiple layers for multiple services
+/// Merge multiple layers for multiple services against one config snapshot
+/// (see [`crate::layer::LayerManager::snapshot`])
 pub fn merge_layers_batch(
     request: &ExperimentRequest,
-    layer_manager: &LayerManager,
-    catalog: &ExperimentCatalog,
+    snapshot: &Snapshot,
     field_types: &HashMap<String, FieldType>,
+) -> Result<ExperimentResponse> {
+    merge_layers_batch_with_overrides(request, &Overrides::new(), snapshot, field_types)
+}
+
+/// Same as [`merge_layers_batch`], honoring support overrides for the subject
+pub fn merge_layers_batch_with_overrides(
+    request: &ExperimentRequest,
+    overrides: &Overrides,
+    snapshot: &Snapshot,
+    field_types: &HashMap<String, FieldType>,
+) -> Result<ExperimentResponse> {
+    merge_layers_batch_traced(request, overrides, snapshot, field_types, None, false)
+}
+
+/// Same as [`merge_layers_batch_with_overrides`]; with `trace`, each
+/// [`ServiceResult::trace`] lists the outcome of every layer considered.
+/// Exposure caps apply only with a `caps` gate (see [`crate::caps`]).
+pub fn merge_layers_batch_traced(
+    request: &ExperimentRequest,
+    overrides: &Overrides,
+    snapshot: &Snapshot,
+    field_types: &HashMap<String, FieldType>,
+    caps: Option<&CapGate>,
+    trace: bool,
 ) -> Result<ExperimentResponse> {
     let mut results = HashMap::new();
+    let mut timer = StageTimer::sampled();
+    let env = EvalEnv {
+        overrides,
+        snapshot,
+        field_types,
+        caps,
+    };
+    // Experiment rules see the same context for every service in the request
+    let mut rule_memo = HashMap::new();
 
     for service in &request.services {
-        let service_result =
-            merge_layers_for_service(service, request, layer_manager, catalog, field_types)?;
+        let service_result = merge_layers_for_service(service, request, &env, &mut timer, &mut rule_memo, trace)?;
         results.insert(service.clone(), service_result);
     }
 
+    timer.finish();
     Ok(ExperimentResponse { results })
 }
 
-fn merge_layers_for_service(
+/// A subject's assignment in one layer
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Assignment {
+    pub layer_id: String,
+    pub eid: i64,
+    pub vid: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Assignments for a single subject and service, without merging params.
+///
+/// `subject_key` is used as the value of every hash key field (including
+/// composite key parts) not already present in `context`.
+pub fn subject_assignments(
     service: &str,
-    request: &ExperimentRequest,
-    layer_manager: &LayerManager,
-    catalog: &ExperimentCatalog,
+    subject_key: &str,
+    mut context: HashMap<String, Value>,
+    overrides: &Overrides,
+    snapshot: &Snapshot,
     field_types: &HashMap<String, FieldType>,
-) -> Result<ServiceResult> {
-    let mut final_params = serde_json::Map::new();
-    let mut matched_vids = Vec::new();
-    let mut matched_layers = Vec::new();
+) -> Vec<Assignment> {
+    for layer in snapshot.layers_for_service(service).iter() {
+        for field in layer.hash_key.fields() {
+            context
+                .entry(field.clone())
+                .or_insert_with(|| Value::String(subject_key.to_string()));
+        }
+    }
+
+    if snapshot.exclusions().excludes(service, &context) {
+        return Vec::new();
+    }
+
+    let request = ExperimentRequest {
+        services: vec![service.to_string()],
+        context,
+        layers: vec![],
+        diagnostics: false,
+    };
 
-    let layers = if request.layers.is_empty() {
-        layer_manager.get_layers_for_service(service)
+    let mut timer = StageTimer::sampled();
+    let env = EvalEnv {
+        overrides,
+        snapshot,
+        field_types,
+        caps: None,
+    };
+    let (matched, _) = matched_variants(service, &request, &env, &mut timer, &mut HashMap::new(), &mut None);
+    timer.finish();
+
+    matched
+        .into_iter()
+        .map(|m| Assignment {
+            layer_id: m.layer_id,
+            eid: m.eid,
+            vid: m.vid,
+            label: m.label,
+        })
+        .collect()
+}
+
+/// What an evaluation reads besides the request
+#[derive(Clone, Copy)]
+struct EvalEnv<'a> {
+    overrides: &'a Overrides,
+    snapshot: &'a Snapshot,
+    field_types: &'a HashMap<String, FieldType>,
+    /// Decisions for capped variants; caps are ignored without it
+    caps: Option<&'a CapGate<'a>>,
+}
+
+/// A layer hit that passed its experiment rule
+struct MatchedVariant<'a> {
+    layer_id: String,
+    eid: i64,
+    vid: i64,
+    label: Option<String>,
+    params: ParamsRef<'a>,
+}
+
+/// Layers (in priority order) where the subject lands in a variant of `service`,
+/// and whether the per-service layer limit cut evaluation short.
+///
+/// `rule_memo` holds experiment rule outcomes by eid, so layers sharing an
+/// experiment evaluate its rule once per request
+fn matched_variants<'a>(
+    service: &str,
+    request: &ExperimentRequest,
+    env: &EvalEnv<'a>,
+    timer: &mut StageTimer,
+    rule_memo: &mut HashMap<i64, bool>,
+    trace: &mut Option<Vec<LayerTrace>>,
+) -> (Vec<MatchedVariant<'a>>, bool) {
+    let EvalEnv {
+        overrides,
+        snapshot,
+        field_types,
+        caps,
+    } = *env;
+    let mut matched = Vec::new();
+    let catalog = snapshot.catalog();
+    // Rules on renamed fields resolve against the new names, then lenient
+    // fields' numeric strings are parsed
+    let rule_context = snapshot
+        .lenient_numbers()
+        .apply(snapshot.field_aliases().apply(&request.context), field_types);
+    let now = snapshot.clock().now_millis();
+
+    let layers: ServiceLayers = if request.layers.is_empty() {
+        snapshot.layers_for_service(service)
     } else {
-        request
-            .layers
-            .iter()
-            .filter_map(|id| layer_manager.get_layer(id))
-            .collect()
+        // Same canonical order as the index, whatever order the caller listed
+        sorted_layers(request.layers.iter().filter_map(|id| snapshot.layer(id)).collect())
     };
 
-    for layer in layers {
-        let hash_key_value = match request.context.get(&layer.hash_key) {
-            Some(Value::String(s)) => s.as_str(),
-            Some(Value::Number(n)) => {
-                tracing::warn!(
-                    "Hash key '{}' is a number, converting to string for layer '{}'",
-                    layer.hash_key,
-                    layer.layer_id
-                );
-                &n.to_string()
-            }
-            Some(_) => {
-                tracing::warn!(
-                    "Hash key '{}' must be a string or number for layer '{}', skipping",
-                    layer.hash_key,
-                    layer.layer_id
-                );
-                continue;
-            }
-            None => {
-                tracing::warn!(
-                    "Hash key '{}' not found in context for layer '{}', skipping",
-                    layer.hash_key,
-                    layer.layer_id
-                );
+    let limit = match snapshot.max_evaluated_layers() {
+        0 => usize::MAX,
+        limit => limit,
+    };
+    let truncated = layers.len() > limit;
+
+    for layer in layers.iter().take(limit) {
+        let hash_key_value = match layer.hash_key.subject_value(&request.context, snapshot.identity_policy()) {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::warn!("Layer '{}': {}, skipping", layer.layer_id, e);
+                note(trace, &layer.layer_id, LayerOutcome::MissingHashKey, None);
                 continue;
             }
         };
+        let hash_key_value = hash_key_value.as_ref();
+        // Aliased subjects bucket as the identity they were assigned under
+        let bucket_key = layer
+            .hash_key
+            .aliased_value(&request.context, snapshot.identity_aliases(), snapshot.identity_policy())
+            .unwrap_or(Cow::Borrowed(hash_key_value));
 
-        let salt = layer.get_salt();
-        let bucket = hash_to_bucket(hash_key_value, &salt);
+        // Support overrides pin the vid and bypass the experiment rule
+        let pinned = overrides
+            .get(hash_key_value)
+            .and_then(|list| list.iter().find(|o| o.layer_id == layer.layer_id))
+            .map(|o| o.vid);
 
-        let Some(vid) = layer.get_vid(bucket) else {
-            continue;
+        let (vid, label) = match pinned {
+            Some(vid) => (vid, layer.label_for(vid)),
+            None => {
+                if let Some(rule) = snapshot.layer_rule(layer) {
+                    match timer.time(Stage::Rule, || rule.evaluate_at(&rule_context, field_types, now)) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            note(trace, &layer.layer_id, LayerOutcome::LayerRuleFailed, None);
+                            continue;
+                        }
+                        Err(e) => {
+                            tracing::warn!("Layer rule evaluation failed for layer {}: {}", layer.layer_id, e);
+                            note(trace, &layer.layer_id, LayerOutcome::LayerRuleFailed, None);
+                            continue;
+                        }
+                    }
+                }
+
+                let range = timer.time(Stage::Hash, || {
+                    let salt = layer.get_salt();
+                    layer.get_range(hash_to_bucket_in(&bucket_key, &salt, layer.bucket_size()))
+                });
+                let Some(range) = range else {
+                    note(trace, &layer.layer_id, LayerOutcome::Unallocated, None);
+                    continue;
+                };
+                (range.vid, range.label.as_deref())
+            }
         };
 
-        let Some((eid, variant_service, rule_opt, params)) = catalog.get_variant(vid) else {
+        let Some((eid, variant_service, rule_opt, params)) = timer.time(Stage::Catalog, || catalog.get_variant(vid)) else {
             tracing::warn!(
-                "Missing vid {} in catalog (layer: {}, bucket: {}), skipping",
+                "Missing vid {} in catalog (layer: {}), skipping",
                 vid,
-                layer.layer_id,
-                bucket
+                layer.layer_id
             );
+            note(trace, &layer.layer_id, LayerOutcome::UnknownVariant, None);
             continue;
         };
 
         if variant_service != service {
+            note(trace, &layer.layer_id, LayerOutcome::OtherService, Some(eid));
+            continue;
+        }
+        if snapshot.is_emergency_disabled(&layer.layer_id, eid) {
+            note(trace, &layer.layer_id, LayerOutcome::EmergencyDisabled, Some(eid));
+            continue;
+        }
+        if snapshot.is_expired(&layer.layer_id, eid) {
+            note(trace, &layer.layer_id, LayerOutcome::Expired, Some(eid));
+            continue;
+        }
+        if !catalog.is_serving(eid) {
+            note(trace, &layer.layer_id, LayerOutcome::NotServing, Some(eid));
             continue;
         }
 
-        if let Some(rule) = rule_opt {
-            let rule_passed = match rule.evaluate(&request.context, field_types) {
-                Ok(passed) => passed,
-                Err(e) => {
-                    tracing::warn!(
-                        "Rule evaluation failed for eid {} (layer {}, vid {}): {}",
-                        eid,
-                        layer.layer_id,
-                        vid,
-                        e
-                    );
-                    false
+        if let Some(rule) = rule_opt.filter(|_| pinned.is_none()) {
+            let rule_passed = *rule_memo.entry(eid).or_insert_with(|| {
+                let rule = snapshot.experiment_rule(eid, rule);
+                match timer.time(Stage::Rule, || rule.evaluate_at(&rule_context, field_types, now)) {
+                    Ok(passed) => passed,
+                    Err(e) => {
+                        tracing::warn!(
+                            "Rule evaluation failed for eid {} (layer {}, vid {}): {}",
+                            eid,
+                            layer.layer_id,
+                            vid,
+                            e
+                        );
+                        false
+                    }
                 }
-            };
+            });
 
             if !rule_passed {
+                note(trace, &layer.layer_id, LayerOutcome::ExperimentRuleFailed, Some(eid));
                 continue;
             }
         }
 
-        merge_params_prioritized(&mut final_params, params)?;
-        matched_vids.push(vid);
-        matched_layers.push(layer.layer_id.clone());
+        // A full capped variant serves new subjects its control variant instead
+        let cap = caps
+            .filter(|_| pinned.is_none())
+            .zip(catalog.variant_cap(vid))
+            .filter(|(gate, (max_subjects, _))| !gate.admits(vid, *max_subjects, &bucket_key));
+        let (outcome, vid, label, params) = match cap {
+            Some((_, (_, control_vid))) => {
+                let Some((_, _, _, control_params)) = catalog.get_variant(control_vid) else {
+                    note(trace, &layer.layer_id, LayerOutcome::UnknownVariant, Some(eid));
+                    continue;
+                };
+                (LayerOutcome::Capped, control_vid, layer.label_for(control_vid), control_params)
+            }
+            None if pinned.is_some() => (LayerOutcome::Pinned, vid, label, params),
+            None => (LayerOutcome::Matched, vid, label, params),
+        };
+        note(trace, &layer.layer_id, outcome, Some(eid));
+
+        matched.push(MatchedVariant {
+            layer_id: layer.layer_id.clone(),
+            eid,
+            vid,
+            label: label.map(str::to_string),
+            params,
+        });
+    }
+
+    if truncated {
+        metrics::EVALUATION_TRUNCATED.with_label_values(&[service]).inc();
+        for layer in layers.iter().skip(limit) {
+            note(trace, &layer.layer_id, LayerOutcome::Truncated, None);
+        }
+    }
+
+    (matched, truncated)
+}
+
+fn merge_layers_for_service(
+    service: &str,
+    request: &ExperimentRequest,
+    env: &EvalEnv,
+    timer: &mut StageTimer,
+    rule_memo: &mut HashMap<i64, bool>,
+    trace: bool,
+) -> Result<ServiceResult> {
+    let mut final_params = serde_json::Map::new();
+    let mut matched_vids = Vec::new();
+    let mut matched_layers = Vec::new();
+    // Diagnostics are tallied from the trace, which is only returned when asked for
+    let traced = trace;
+    let mut trace = (traced || request.diagnostics).then(Vec::new);
+
+    if env.snapshot.exclusions().excludes(service, &request.context) {
+        return Ok(ServiceResult {
+            parameters: Value::Object(final_params),
+            vids: matched_vids,
+            matched_layers,
+            truncated: false,
+            excluded: true,
+            dark_parameters: None,
+            unavailable: false,
+            diagnostics: request.diagnostics.then(BTreeMap::new),
+            trace: trace.filter(|_| traced),
+        });
+    }
+
+    let (matched, truncated) = matched_variants(service, request, env, timer, rule_memo, &mut trace);
+    // Params shared by several matched variants only need merging once
+    let mut merged: Vec<&Value> = Vec::new();
+    for m in &matched {
+        if !merged.iter().any(|params| std::ptr::eq(*params, &*m.params)) {
+            merged.push(&m.params);
+            timer.time(Stage::Merge, || merge_params_prioritized(&mut final_params, &m.params))?;
+        }
+    }
+    let mut dark = serde_json::Map::new();
+    for m in &matched {
+        if let Some(params) = env.snapshot.catalog().dark_params(m.vid) {
+            timer.time(Stage::Merge, || merge_params_prioritized(&mut dark, params))?;
+        }
+    }
+    for m in matched {
+        matched_vids.push(m.vid);
+        matched_layers.push(m.layer_id);
     }
 
+    let diagnostics = request.diagnostics.then(|| skipped_layers(trace.as_deref().unwrap_or_default()));
     Ok(ServiceResult {
         parameters: Value::Object(final_params),
         vids: matched_vids,
         matched_layers,
+        truncated,
+        excluded: false,
+        dark_parameters: (!dark.is_empty()).then_some(Value::Object(dark)),
+        unavailable: false,
+        diagnostics,
+        trace: trace.filter(|_| traced),
     })
 }
 
+/// Layers in `trace` that didn't contribute, counted by outcome
+fn skipped_layers(trace: &[LayerTrace]) -> BTreeMap<LayerOutcome, u32> {
+    let mut counts = BTreeMap::new();
+    for entry in trace.iter().filter(|entry| !entry.outcome.contributed()) {
+        *counts.entry(entry.outcome).or_default() += 1;
+    }
+    counts
+}
+
+/// Flatten nested objects into dot-separated paths; non-object values are leaves
+pub fn flatten_params(prefix: &str, value: &Value, out: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(map) if !map.is_empty() || prefix.is_empty() => {
+            for (key, child) in map {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten_params(&path, child, out);
+            }
+        }
+        _ => {
+            out.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
 /// Merge parameters with priority (higher priority layer wins for same keys)
-fn merge_params_prioritized(target: &mut serde_json::Map<String, Value>, source: &Value) -> Result<()> {
+pub(crate) fn merge_params_prioritized(target: &mut serde_json::Map<String, Value>, source: &Value) -> Result<()> {
     match source {
         Value::Object(source_map) => {
             for (key, value) in source_map {
                 match (target.get_mut(key), value) {
-                    (Some(Value::Object(target_obj)), Value::Object(source_obj)) => {
-                        let mut target_map = target_obj.clone();
-                        merge_params_prioritized(&mut target_map, &Value::Object(source_obj.clone()))?;
-                        target.insert(key.clone(), Value::Object(target_map));
+                    (Some(Value::Object(target_obj)), Value::Object(_)) => {
+                        merge_params_prioritized(target_obj, value)?;
                     }
                     (Some(_), _) => {}
                     (None, _) => {
@@ -182,10 +545,12 @@ fn merge_params_prioritized(target: &mut serde_json::Map<String, Value>, source:
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::Clock;
     use crate::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
     use crate::layer::{BucketRange, Layer, LayerManager};
     use serde_json::json;
     use tempfile::TempDir;
+    use std::sync::Arc;
 
     #[test]
     fn test_merge_params_nested() {
@@ -217,6 +582,350 @@ mod tests {
         assert_eq!(target.get("key"), Some(&json!("high_priority")));
     }
 
+    #[tokio::test]
+    async fn test_matched_layers_follow_canonical_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let layers_dir = temp_dir.path().join("layers");
+        let experiments_dir = temp_dir.path().join("experiments");
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+
+        // Equal priorities tie-break on layer_id; "z_top" outranks them all
+        let layers = [("b", 10, 200), ("z_top", 20, 400), ("c", 10, 300), ("a", 10, 100)];
+        for (layer_id, priority, eid) in layers {
+            std::fs::write(
+                experiments_dir.join(format!("{}.json", eid)),
+                json!({"eid": eid, "service": "svc", "variants": [{"vid": eid + 1, "params": {}}]}).to_string(),
+            )
+            .unwrap();
+            std::fs::write(
+                layers_dir.join(format!("{}.json", layer_id)),
+                json!({"layer_id": layer_id, "version": "v1", "priority": priority, "hash_key": "user_id",
+                       "enabled": true, "ranges": [{"start": 0, "end": 10000, "vid": eid + 1}]})
+                .to_string(),
+            )
+            .unwrap();
+        }
+
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+        let manager = LayerManager::new(layers_dir);
+        manager.load_all_layers(&catalog).await.unwrap();
+
+        let mut request = ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([("user_id".to_string(), json!("u1"))]),
+            layers: vec![],
+            diagnostics: false,
+        };
+        let expected_layers = vec!["z_top", "a", "b", "c"];
+        let expected_vids = vec![401, 101, 201, 301];
+
+        let response = merge_layers_batch(&request, &manager.snapshot(), &HashMap::new()).unwrap();
+        assert_eq!(response.results["svc"].matched_layers, expected_layers);
+        assert_eq!(response.results["svc"].vids, expected_vids);
+
+        // Explicit layers are evaluated in the same order, duplicates once
+        request.layers = ["c", "a", "z_top", "c", "b"].map(String::from).to_vec();
+        let response = merge_layers_batch(&request, &manager.snapshot(), &HashMap::new()).unwrap();
+        assert_eq!(response.results["svc"].matched_layers, expected_layers);
+        assert_eq!(response.results["svc"].vids, expected_vids);
+    }
+
+    #[tokio::test]
+    async fn test_layer_limit_truncates_lowest_priority() {
+        let temp_dir = TempDir::new().unwrap();
+        let layers_dir = temp_dir.path().join("layers");
+        let experiments_dir = temp_dir.path().join("experiments");
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+        for (layer_id, priority, eid) in [("low", 1, 100), ("mid", 2, 200), ("high", 3, 300)] {
+            std::fs::write(
+                experiments_dir.join(format!("{}.json", eid)),
+                json!({"eid": eid, "service": "svc", "variants": [{"vid": eid + 1, "params": {}}]}).to_string(),
+            )
+            .unwrap();
+            std::fs::write(
+                layers_dir.join(format!("{}.json", layer_id)),
+                json!({"layer_id": layer_id, "version": "v1", "priority": priority, "hash_key": "user_id",
+                       "enabled": true, "ranges": [{"start": 0, "end": 10000, "vid": eid + 1}]})
+                .to_string(),
+            )
+            .unwrap();
+        }
+
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+        let manager = LayerManager::new(layers_dir).with_max_evaluated_layers(2);
+        manager.load_all_layers(&catalog).await.unwrap();
+
+        let request = ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([("user_id".to_string(), json!("u1"))]),
+            layers: vec![],
+            diagnostics: false,
+        };
+        let response =
+            merge_layers_batch_traced(&request, &Overrides::new(), &manager.snapshot(), &HashMap::new(), None, true).unwrap();
+        let result = &response.results["svc"];
+        assert_eq!(result.matched_layers, vec!["high", "mid"]);
+        assert!(result.truncated);
+        let trace = result.trace.as_ref().unwrap();
+        assert_eq!(trace[2].layer_id, "low");
+        assert_eq!(trace[2].outcome, LayerOutcome::Truncated);
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_rules_use_snapshot_clock() {
+        let temp_dir = TempDir::new().unwrap();
+        let layers_dir = temp_dir.path().join("layers");
+        let experiments_dir = temp_dir.path().join("experiments");
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+        std::fs::write(
+            experiments_dir.join("100.json"),
+            json!({"eid": 100, "service": "svc", "variants": [{"vid": 101, "params": {"onboarding": "v2"}}]}).to_string(),
+        )
+        .unwrap();
+        std::fs::write(
+            layers_dir.join("new_users.json"),
+            json!({"layer_id": "new_users", "version": "v1", "priority": 1, "hash_key": "user_id", "enabled": true,
+                   "rule": {"type": "field", "field": "signup_date", "op": "gte", "values": ["now-7d"]},
+                   "ranges": [{"start": 0, "end": 10000, "vid": 101}]})
+            .to_string(),
+        )
+        .unwrap();
+
+        // 2024-03-10T00:00:00Z
+        let now = 1_710_028_800_000;
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+        let manager = LayerManager::new(layers_dir).with_clock(Clock::Fixed(now));
+        manager.load_all_layers(&catalog).await.unwrap();
+        let field_types = HashMap::from([("signup_date".to_string(), FieldType::Timestamp)]);
+
+        let matched = |signup: serde_json::Value| {
+            let request = ExperimentRequest {
+                services: vec!["svc".to_string()],
+                context: HashMap::from([("user_id".to_string(), json!("u1")), ("signup_date".to_string(), signup)]),
+                layers: vec![],
+                diagnostics: false,
+            };
+            !merge_layers_batch(&request, &manager.snapshot(), &field_types).unwrap().results["svc"].vids.is_empty()
+        };
+        assert!(matched(json!("2024-03-05T12:00:00+08:00")));
+        assert!(matched(json!(now / 1000 - 7 * 86_400)));
+        assert!(!matched(json!("2024-03-02T23:59:59Z")));
+    }
+
+    #[tokio::test]
+    async fn test_traced_evaluation_records_layer_outcomes() {
+        let temp_dir = TempDir::new().unwrap();
+        let layers_dir = temp_dir.path().join("layers");
+        let experiments_dir = temp_dir.path().join("experiments");
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+        for (eid, vid) in [(100, 1001), (200, 2001), (300, 3001)] {
+            std::fs::write(
+                experiments_dir.join(format!("{}.json", eid)),
+                json!({"eid": eid, "service": "svc", "variants": [{"vid": vid, "params": {}}]}).to_string(),
+            )
+            .unwrap();
+        }
+        let layers = [
+            json!({"layer_id": "open", "version": "v1", "priority": 300, "hash_key": "user_id", "enabled": true,
+                   "ranges": [{"start": 0, "end": 10000, "vid": 1001}]}),
+            json!({"layer_id": "gated", "version": "v1", "priority": 200, "hash_key": "user_id", "enabled": true,
+                   "rule": {"type": "field", "field": "country", "op": "eq", "values": ["US"]},
+                   "ranges": [{"start": 0, "end": 10000, "vid": 2001}]}),
+            json!({"layer_id": "campaign", "version": "v1", "priority": 100,
+                   "hash_key": ["user_id", "campaign_id"], "enabled": true,
+                   "ranges": [{"start": 0, "end": 10000, "vid": 3001}]}),
+        ];
+        for layer in &layers {
+            std::fs::write(layers_dir.join(format!("{}.json", layer["layer_id"].as_str().unwrap())), layer.to_string())
+                .unwrap();
+        }
+
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+        let manager = LayerManager::new(layers_dir);
+        manager.load_all_layers(&catalog).await.unwrap();
+        let field_types = HashMap::from([("country".to_string(), FieldType::String)]);
+
+        let request = ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([("user_id".to_string(), json!("u1")), ("country".to_string(), json!("CA"))]),
+            layers: vec![],
+            diagnostics: false,
+        };
+        let snapshot = manager.snapshot();
+        let traced = merge_layers_batch_traced(&request, &Overrides::new(), &snapshot, &field_types, None, true).unwrap();
+        let outcomes: Vec<(&str, LayerOutcome, Option<i64>)> = traced.results["svc"]
+            .trace
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|t| (t.layer_id.as_str(), t.outcome, t.eid))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                ("open", LayerOutcome::Matched, Some(100)),
+                ("gated", LayerOutcome::LayerRuleFailed, None),
+                ("campaign", LayerOutcome::MissingHashKey, None),
+            ]
+        );
+
+        // Untraced evaluation is unchanged and carries no trace
+        let plain = merge_layers_batch(&request, &snapshot, &field_types).unwrap();
+        assert_eq!(plain.results["svc"].vids, traced.results["svc"].vids);
+        assert!(plain.results["svc"].trace.is_none());
+        assert!(plain.results["svc"].diagnostics.is_none());
+
+        // Diagnostics count skipped layers by reason, without returning the trace
+        let diagnosed = ExperimentRequest {
+            diagnostics: true,
+            ..request
+        };
+        let result = &merge_layers_batch(&diagnosed, &snapshot, &field_types).unwrap().results["svc"];
+        assert!(result.trace.is_none());
+        assert_eq!(
+            serde_json::to_value(result).unwrap()["diagnostics"],
+            json!({"missing_hash_key": 1, "layer_rule_failed": 1})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_diagnostics_count_each_skip_reason() {
+        let temp_dir = TempDir::new().unwrap();
+        let layers_dir = temp_dir.path().join("layers");
+        let experiments_dir = temp_dir.path().join("experiments");
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+        let experiments = [
+            json!({"eid": 100, "service": "svc", "rule_expr": "country == \"US\"",
+                   "variants": [{"vid": 1001, "params": {}}]}),
+            json!({"eid": 200, "service": "svc", "variants": [{"vid": 2001, "params": {}}]}),
+            json!({"eid": 300, "service": "svc", "expires_at": 1, "variants": [{"vid": 3001, "params": {}}]}),
+            json!({"eid": 400, "service": "svc", "caps": {"control_vid": 4001, "max_subjects": {"4002": 1}},
+                   "variants": [{"vid": 4001, "params": {"arm": "control"}},
+                                {"vid": 4002, "params": {"arm": "treatment"}}]}),
+        ];
+        for experiment in &experiments {
+            std::fs::write(experiments_dir.join(format!("{}.json", experiment["eid"])), experiment.to_string())
+                .unwrap();
+        }
+
+        // Every range but the one `u1` hashes to
+        let bucket = crate::hash::hash_to_bucket("u1", "hole_salt");
+        let hole_ranges = [(0, bucket), (bucket + 1, crate::layer::BUCKET_SIZE)]
+            .into_iter()
+            .filter(|(start, end)| start < end)
+            .map(|(start, end)| json!({"start": start, "end": end, "vid": 2001}))
+            .collect::<Vec<_>>();
+        let layers = [
+            json!({"layer_id": "rule_miss", "version": "v1", "priority": 500, "hash_key": "user_id", "enabled": true,
+                   "ranges": [{"start": 0, "end": 10000, "vid": 1001}]}),
+            json!({"layer_id": "gated", "version": "v1", "priority": 400, "hash_key": "user_id", "enabled": true,
+                   "rule": {"type": "field", "field": "country", "op": "eq", "values": ["US"]},
+                   "ranges": [{"start": 0, "end": 10000, "vid": 1001}]}),
+            json!({"layer_id": "hole", "version": "v1", "priority": 300, "hash_key": "user_id", "enabled": true,
+                   "salt": "hole_salt", "ranges": hole_ranges}),
+            json!({"layer_id": "expired", "version": "v1", "priority": 200, "hash_key": "user_id", "enabled": true,
+                   "ranges": [{"start": 0, "end": 10000, "vid": 3001}]}),
+            json!({"layer_id": "capped", "version": "v1", "priority": 100, "hash_key": "user_id", "enabled": true,
+                   "ranges": [{"start": 0, "end": 10000, "vid": 4002}]}),
+        ];
+        for layer in &layers {
+            std::fs::write(layers_dir.join(format!("{}.json", layer["layer_id"].as_str().unwrap())), layer.to_string())
+                .unwrap();
+        }
+
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+        let manager = LayerManager::new(layers_dir);
+        manager.load_all_layers(&catalog).await.unwrap();
+        let field_types = HashMap::from([("country".to_string(), FieldType::String)]);
+        let request = ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([("user_id".to_string(), json!("u1")), ("country".to_string(), json!("CA"))]),
+            layers: vec![],
+            diagnostics: true,
+        };
+
+        // The cap is undecided for `u1`, so the capped layer serves its control variant
+        let tracker = crate::caps::CapTracker::new(Arc::new(crate::kv::MemoryStore::new()));
+        let gate = CapGate::new(&tracker);
+        let response =
+            merge_layers_batch_traced(&request, &Overrides::new(), &manager.snapshot(), &field_types, Some(&gate), false)
+                .unwrap();
+        let result = &response.results["svc"];
+        assert_eq!(result.vids, vec![4001]);
+        assert_eq!(result.matched_layers, vec!["capped"]);
+
+        // One count per skipped layer; the capped layer still contributed, so it isn't a skip
+        let diagnostics = result.diagnostics.as_ref().unwrap();
+        assert_eq!(diagnostics.get(&LayerOutcome::ExperimentRuleFailed), Some(&1));
+        assert_eq!(diagnostics.get(&LayerOutcome::LayerRuleFailed), Some(&1));
+        assert_eq!(diagnostics.get(&LayerOutcome::Unallocated), Some(&1));
+        assert_eq!(diagnostics.get(&LayerOutcome::Expired), Some(&1));
+        assert_eq!(diagnostics.get(&LayerOutcome::Capped), None);
+        assert_eq!(
+            serde_json::to_value(result).unwrap()["diagnostics"],
+            json!({"experiment_rule_failed": 1, "layer_rule_failed": 1, "unallocated": 1, "expired": 1})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_experiment_rule_evaluated_once_per_request() {
+        let temp_dir = TempDir::new().unwrap();
+        let layers_dir = temp_dir.path().join("layers");
+        let experiments_dir = temp_dir.path().join("experiments");
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+        std::fs::write(
+            experiments_dir.join("100.json"),
+            json!({"eid": 100, "service": "svc", "rule_expr": "country == \"US\"",
+                   "variants": [{"vid": 1001, "params": {"a": 1}}, {"vid": 1002, "params": {"b": 2}}]})
+            .to_string(),
+        )
+        .unwrap();
+        for (layer_id, vid) in [("l1", 1001), ("l2", 1002)] {
+            std::fs::write(
+                layers_dir.join(format!("{}.json", layer_id)),
+                json!({"layer_id": layer_id, "version": "v1", "priority": 1, "hash_key": "user_id",
+                       "enabled": true, "ranges": [{"start": 0, "end": 10000, "vid": vid}]})
+                .to_string(),
+            )
+            .unwrap();
+        }
+
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+        let manager = LayerManager::new(layers_dir);
+        manager.load_all_layers(&catalog).await.unwrap();
+        let snapshot = manager.snapshot();
+        let field_types = HashMap::from([("country".to_string(), FieldType::String)]);
+        let request = ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::from([("user_id".to_string(), json!("u1")), ("country".to_string(), json!("US"))]),
+            layers: vec![],
+            diagnostics: false,
+        };
+        let env = EvalEnv {
+            overrides: &Overrides::new(),
+            snapshot: &snapshot,
+            field_types: &field_types,
+            caps: None,
+        };
+        let mut timer = StageTimer::sampled();
+
+        let mut rule_memo = HashMap::new();
+        let (matched, _) = matched_variants("svc", &request, &env, &mut timer, &mut rule_memo, &mut None);
+        assert_eq!(matched.len(), 2);
+        assert_eq!(rule_memo, HashMap::from([(100, true)]));
+
+        // Both layers take the memoized outcome rather than re-evaluating
+        let mut rule_memo = HashMap::from([(100, false)]);
+        let (matched, _) = matched_variants("svc", &request, &env, &mut timer, &mut rule_memo, &mut None);
+        assert!(matched.is_empty());
+    }
+
     #[tokio::test]
     async fn test_merge_layers_batch() {
         let temp_dir = TempDir::new().unwrap();
@@ -229,15 +938,21 @@ mod tests {
         let exp1 = ExperimentDef {
             eid: 100,
             service: "test_svc".to_string(),
+            expires_at: None,
+            state: Default::default(),
+            caps: None,
             rule: None,
+            exposure_sampling: None,
             variants: vec![
                 VariantDef {
                     vid: 1001,
-                    params: json!({"feature_a": true, "timeout": 100}),
+                    params: json!({"feature_a": true, "timeout": 100, "ranker": {"model": {"dark": true, "value": "dnn"}}}),
+                    dark_params: None,
                 },
                 VariantDef {
                     vid: 1002,
                     params: json!({"feature_b": true, "timeout": 200}),
+                    dark_params: Some(json!({"ranker": {"model": "gbdt"}, "shadow": true})),
                 },
             ],
         };
@@ -247,7 +962,7 @@ mod tests {
         )
         .unwrap();
 
-        let catalog = ExperimentCatalog::load_from_dir(experiments_dir).unwrap();
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
 
         // Create test layers
         let test_user = "user_test_123";
@@ -260,30 +975,40 @@ mod tests {
             layer_id: "layer1".to_string(),
             version: "v1".to_string(),
             priority: 200,
-            hash_key: "user_id".to_string(),
+            hash_key: "user_id".into(),
             salt: Some(layer1_salt.to_string()),
+            bucket_size: None,
+            expires_at: None,
+            rule: None,
             services: vec![],
             ranges: vec![BucketRange {
                 start: bucket1,
                 end: bucket1.saturating_add(1).min(crate::layer::BUCKET_SIZE),
                 vid: 1001,
+                label: None,
             }],
             enabled: true,
+            aa_test: false,
         };
 
         let layer2 = Layer {
             layer_id: "layer2".to_string(),
             version: "v1".to_string(),
             priority: 100,
-            hash_key: "user_id".to_string(),
+            hash_key: "user_id".into(),
             salt: Some(layer2_salt.to_string()),
+            bucket_size: None,
+            expires_at: None,
+            rule: None,
             services: vec![],
             ranges: vec![BucketRange {
                 start: bucket2,
                 end: bucket2.saturating_add(1).min(crate::layer::BUCKET_SIZE),
                 vid: 1002,
+                label: None,
             }],
             enabled: true,
+            aa_test: false,
         };
 
         std::fs::write(
@@ -309,10 +1034,11 @@ mod tests {
             .into_iter()
             .collect(),
             layers: vec![],
+            diagnostics: false,
         };
 
         let field_types = HashMap::new();
-        let response = merge_layers_batch(&request, &manager, &catalog, &field_types).unwrap();
+        let response = merge_layers_batch(&request, &manager.snapshot(), &field_types).unwrap();
 
         let result = response.results.get("test_svc").unwrap();
 
@@ -322,5 +1048,36 @@ mod tests {
         assert_eq!(result.parameters["feature_b"], json!(true));
         assert_eq!(result.vids, vec![1001, 1002]);
         assert_eq!(result.matched_layers.len(), 2);
+        // Dark params merge the same way, on their own channel
+        assert!(result.parameters.get("ranker").is_none());
+        assert_eq!(result.dark_parameters, Some(json!({"ranker": {"model": "dnn"}, "shadow": true})));
+
+        // Assignments-only query agrees with the merge
+        let assignments =
+            subject_assignments(
+                "test_svc",
+                test_user,
+                HashMap::new(),
+                &Overrides::new(),
+                &manager.snapshot(),
+                &field_types,
+            );
+        assert_eq!(
+            assignments,
+            vec![
+                Assignment {
+                    layer_id: "layer1".to_string(),
+                    eid: 100,
+                    vid: 1001,
+                    label: None,
+                },
+                Assignment {
+                    layer_id: "layer2".to_string(),
+                    eid: 100,
+                    vid: 1002,
+                    label: None,
+                },
+            ]
+        );
     }
 }
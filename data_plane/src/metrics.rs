@@ -1,5 +1,10 @@
 use lazy_static::lazy_static;
-use prometheus::{Counter, Histogram, IntCounter, Registry};
+use prometheus::{Counter, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry};
+use crate::params::DedupStats;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 lazy_static! {
     pub static ref REGISTRY: Registry = Registry::new();
@@ -23,6 +28,23 @@ lazy_static! {
         .buckets(vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0])
     ).unwrap();
     
+    // Sampled per-request time spent in each evaluation stage (see [`StageTimer`])
+    pub static ref EVALUATION_STAGE_DURATION: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "experiment_evaluation_stage_duration_seconds",
+            "Time per request spent in one evaluation stage (hash / rule / catalog / merge), sampled"
+        )
+        .buckets(vec![
+            0.000_001, 0.000_005, 0.000_01, 0.000_025, 0.000_05, 0.000_1, 0.000_25, 0.000_5, 0.001, 0.005, 0.01,
+        ]),
+        &["stage"]
+    ).unwrap();
+    
+    pub static ref EVALUATION_PANICS: IntCounter = IntCounter::new(
+        "experiment_evaluation_panics_total",
+        "Requests whose evaluation panicked and was answered with an error"
+    ).unwrap();
+
     // Layer metrics
     pub static ref LAYER_RELOAD_TOTAL: IntCounter = IntCounter::new(
         "experiment_layer_reload_total",
@@ -34,7 +56,429 @@ lazy_static! {
         "Total number of layer reload errors"
     ).unwrap();
     
-    pub static ref ACTIVE_LAYERS: prometheus::IntGauge = prometheus::IntGauge::new(
+    // Hot-reloaded config: file mtime to applied, per resource kind and source
+    pub static ref CONFIG_PROPAGATION_SECONDS: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "experiment_config_propagation_seconds",
+            "Time from a config file being written to its change serving, by resource and source"
+        )
+        .buckets(vec![0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0]),
+        &["resource", "source"]
+    ).unwrap();
+
+    pub static ref CONFIG_APPLY_FAILURES: IntCounter = IntCounter::new(
+        "experiment_config_apply_failures_total",
+        "Config updates rejected while the previous snapshot kept serving"
+    ).unwrap();
+    
+    pub static ref CONFIG_MANIFEST_MISMATCHES: IntGauge = IntGauge::new(
+        "experiment_config_manifest_mismatches",
+        "Config manifest entries missing, modified or not loaded at the last check"
+    ).unwrap();
+    
+    // Config watch queue
+    pub static ref WATCH_QUEUE_DEPTH: IntGauge = IntGauge::new(
+        "experiment_watch_queue_depth",
+        "Distinct config changes waiting to be applied"
+    ).unwrap();
+    
+    pub static ref WATCH_QUEUE_HIGH_WATER: IntGauge = IntGauge::new(
+        "experiment_watch_queue_high_water",
+        "Highest config change queue depth observed"
+    ).unwrap();
+    
+    pub static ref WATCH_COALESCED_TOTAL: IntCounter = IntCounter::new(
+        "experiment_watch_coalesced_total",
+        "Config change events collapsed into an already pending change"
+    ).unwrap();
+    
+    pub static ref WATCH_OVERFLOW_TOTAL: IntCounter = IntCounter::new(
+        "experiment_watch_overflow_total",
+        "Config change queue overflows that forced a full resync"
+    ).unwrap();
+    
+    pub static ref RESYNC_TOTAL: IntCounter = IntCounter::new(
+        "experiment_resync_total",
+        "Full config resyncs (scheduled or forced by queue overflow)"
+    ).unwrap();
+    
+    pub static ref RESYNC_CHANGES_TOTAL: IntCounter = IntCounter::new(
+        "experiment_resync_changes_total",
+        "Layers added, updated or removed by full resyncs"
+    ).unwrap();
+    
+    // Config snapshot changes, recorded on every swap
+    pub static ref SNAPSHOT_CHANGES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_snapshot_changes_total",
+            "Layers/experiments added, removed or modified by applied config snapshots"
+        ),
+        &["resource", "change"]
+    ).unwrap();
+    
+    pub static ref ENABLED_LAYERS: IntGauge = IntGauge::new(
+        "experiment_enabled_layers",
+        "Enabled layers in the current snapshot"
+    ).unwrap();
+    
+    pub static ref SERVICE_ENABLED_LAYERS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "experiment_service_enabled_layers",
+            "Enabled layers per service in the current snapshot"
+        ),
+        &["service"]
+    ).unwrap();
+    
+    pub static ref SERVICE_COVERAGE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "experiment_service_coverage_ratio",
+            "Share of subjects assigned at least one variant of the service (rules ignored)"
+        ),
+        &["service"]
+    ).unwrap();
+    
+    pub static ref LAYER_UNCOVERED: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "experiment_layer_uncovered_ratio",
+            "Share of an enabled layer's buckets not covered by any range"
+        ),
+        &["layer_id"]
+    ).unwrap();
+
+    pub static ref SERVICE_EVALUATIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_service_evaluations_total",
+            "Service evaluations requested via /experiment, by service"
+        ),
+        &["service"]
+    ).unwrap();
+
+    pub static ref EVALUATION_TRUNCATED: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_evaluation_truncated_total",
+            "Service evaluations cut short by MAX_EVALUATED_LAYERS_PER_SERVICE, by service"
+        ),
+        &["service"]
+    ).unwrap();
+
+    // Exposure events
+    pub static ref EXPOSURE_SPOOLED: IntCounter = IntCounter::new(
+        "experiment_exposure_spooled_total",
+        "Exposure events written to the local spool"
+    ).unwrap();
+    
+    pub static ref EXPOSURE_DEDUPLICATED: IntCounter = IntCounter::new(
+        "experiment_exposure_deduplicated_total",
+        "Repeated exposures suppressed within the dedup window"
+    ).unwrap();
+
+    pub static ref EXPOSURE_SAMPLED_OUT: IntCounter = IntCounter::new(
+        "experiment_exposure_sampled_out_total",
+        "Exposures not logged because the experiment samples its subjects"
+    ).unwrap();
+    
+    pub static ref EXPOSURE_SHIPPED: IntCounter = IntCounter::new(
+        "experiment_exposure_shipped_total",
+        "Exposure events accepted by the sink"
+    ).unwrap();
+    
+    pub static ref EXPOSURE_SHIP_FAILURES: IntCounter = IntCounter::new(
+        "experiment_exposure_ship_failures_total",
+        "Failed attempts to ship a spool segment"
+    ).unwrap();
+    
+    pub static ref EXPOSURE_DROPPED: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_exposure_dropped_total",
+            "Exposure events lost, by reason"
+        ),
+        &["reason"]
+    ).unwrap();
+    
+    pub static ref EXPOSURE_SPOOL_SEGMENTS: IntGauge = IntGauge::new(
+        "experiment_exposure_spool_segments",
+        "Sealed spool segments waiting to be shipped"
+    ).unwrap();
+    
+    pub static ref SDK_KEY_REJECTIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_sdk_key_rejections_total",
+            "Evaluation requests rejected by SDK key checks"
+        ),
+        &["reason"]
+    ).unwrap();
+    
+    pub static ref CONTEXT_FIELDS_DROPPED: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_context_fields_dropped_total",
+            "Context fields removed (or rejected in strict mode) by the service allow-list"
+        ),
+        &["service"]
+    ).unwrap();
+    
+    // gRPC (feature `grpc`); registered unconditionally so dashboards see zeros
+    pub static ref GRPC_REQUESTS: IntCounterVec = IntCounterVec::new(
+        Opts::new("experiment_grpc_requests_total", "gRPC requests by method and status code"),
+        &["method", "code"]
+    ).unwrap();
+    
+    pub static ref GRPC_DURATION: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("experiment_grpc_request_duration_seconds", "gRPC request duration by method")
+            .buckets(vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]),
+        &["method"]
+    ).unwrap();
+    
+    pub static ref GRPC_DEADLINE_EXCEEDED: IntCounter = IntCounter::new(
+        "experiment_grpc_deadline_exceeded_total",
+        "gRPC evaluations abandoned at the client deadline"
+    ).unwrap();
+    
+    pub static ref XDS_UPDATES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_xds_updates_total",
+            "Config pushes received over xDS by resource type and outcome (ack, nack)"
+        ),
+        &["type", "outcome"]
+    ).unwrap();
+
+    pub static ref XDS_RECONNECTS: IntCounter = IntCounter::new(
+        "experiment_xds_reconnects_total",
+        "Times the xDS config stream was lost and reopened"
+    ).unwrap();
+
+    pub static ref XDS_ACTIVE_ENDPOINT: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "experiment_xds_active_endpoint",
+            "1 for the xDS control plane endpoint in use, 0 for the other listed ones"
+        ),
+        &["endpoint"]
+    ).unwrap();
+
+    pub static ref XDS_ENDPOINT_HEALTHY: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "experiment_xds_endpoint_healthy",
+            "1 if the last connection attempt or stream to the xDS control plane endpoint succeeded"
+        ),
+        &["endpoint"]
+    ).unwrap();
+
+    pub static ref XDS_ENDPOINT_SWITCHES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_xds_endpoint_switches_total",
+            "Moves between xDS control plane endpoints by direction (failover, failback)"
+        ),
+        &["direction"]
+    ).unwrap();
+
+    pub static ref FEDERATION_REQUESTS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_federation_requests_total",
+            "Evaluations delegated to federated planes by plane and outcome"
+        ),
+        &["plane", "outcome"]
+    ).unwrap();
+    
+    pub static ref FEDERATION_DURATION: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("experiment_federation_request_duration_seconds", "Delegated evaluation duration by plane")
+            .buckets(vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]),
+        &["plane"]
+    ).unwrap();
+    
+    pub static ref CONFIG_SOURCE_SWITCHES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_config_source_switches_total",
+            "Runtime config source switchovers by result"
+        ),
+        &["result"]
+    ).unwrap();
+
+    pub static ref CONFIG_SOURCE_FAILOVERS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_config_source_failovers_total",
+            "Automatic config source switches by direction (failover, failback)"
+        ),
+        &["direction"]
+    ).unwrap();
+
+    pub static ref CONFIG_SOURCE_ACTIVE: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "experiment_config_source_active",
+            "1 for the listed config source currently serving, 0 for the others"
+        ),
+        &["source"]
+    ).unwrap();
+
+    pub static ref EXCLUDED_EVALUATIONS: IntCounter = IntCounter::new(
+        "experiment_excluded_evaluations_total",
+        "Service evaluations skipped because the subject is in the exclusion population"
+    ).unwrap();
+
+    pub static ref EXCLUSION_FILE_ERRORS: IntCounter = IntCounter::new(
+        "experiment_exclusion_file_errors_total",
+        "Exclusion file reads that failed and kept the previous set"
+    ).unwrap();
+
+    pub static ref CAPPED_ASSIGNMENTS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_capped_assignments_total",
+            "Subjects sent to control because their variant's exposure cap was reached, by vid"
+        ),
+        &["vid"]
+    ).unwrap();
+
+    pub static ref EMERGENCY_DISABLED: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "experiment_emergency_disabled",
+            "Layers / experiments force-disabled by the emergency override file"
+        ),
+        &["kind"]
+    ).unwrap();
+
+    pub static ref IDENTITY_ALIASES: IntGauge = IntGauge::new(
+        "experiment_identity_aliases",
+        "Identity aliases in effect (file and API)"
+    ).unwrap();
+
+    pub static ref IDENTITY_ALIAS_RESOLUTIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_identity_alias_resolutions_total",
+            "Hash key values bucketed under an identity alias, by field"
+        ),
+        &["field"]
+    ).unwrap();
+
+    pub static ref CONTEXT_NUMBER_COERCIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_context_number_coercions_total",
+            "String-encoded context numbers parsed for Int / Float rules, by field"
+        ),
+        &["field"]
+    ).unwrap();
+
+    pub static ref CONTEXT_DATABASE_BUILD_TIMESTAMP: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "experiment_context_database_build_timestamp_seconds",
+            "Build time of the loaded context database (MMDB build_epoch, else file mtime)"
+        ),
+        &["database"]
+    ).unwrap();
+
+    pub static ref CONTEXT_DATABASE_REFRESH_ERRORS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_context_database_refresh_errors_total",
+            "Context database refreshes that failed (previous version kept)"
+        ),
+        &["database"]
+    ).unwrap();
+
+    pub static ref METRICS_PUSHES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_metrics_pushes_total",
+            "Pushes of this process's metrics to the Pushgateway by outcome (ok, error)"
+        ),
+        &["outcome"]
+    ).unwrap();
+
+    pub static ref EXPIRED_RESOURCES: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "experiment_expired_resources",
+            "Layers / experiments past their expires_at, no longer serving"
+        ),
+        &["kind"]
+    ).unwrap();
+
+    pub static ref CATALOG_PARAM_BYTES: IntGauge = IntGauge::new(
+        "experiment_catalog_param_compressed_bytes",
+        "Compressed variant params held by a lazy catalog"
+    ).unwrap();
+
+    pub static ref CATALOG_PARAM_BLOBS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "experiment_catalog_param_blobs",
+            "Variants in an eager catalog and the distinct params they share (kind = variants / unique)"
+        ),
+        &["kind"]
+    ).unwrap();
+
+    pub static ref CATALOG_PARAM_DEDUP_BYTES: IntGauge = IntGauge::new(
+        "experiment_catalog_param_dedup_saved_bytes",
+        "Serialized size of variant params not stored because an equal value is shared"
+    ).unwrap();
+
+    pub static ref CATALOG_PARAM_CACHE_ENTRIES: IntGauge = IntGauge::new(
+        "experiment_catalog_param_cache_entries",
+        "Variants with materialized params in the lazy catalog LRU"
+    ).unwrap();
+
+    pub static ref CATALOG_PARAM_CACHE_LOOKUPS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_catalog_param_cache_lookups_total",
+            "Lazy catalog param lookups by result (hit / miss)"
+        ),
+        &["result"]
+    ).unwrap();
+
+    pub static ref RESULT_CACHE_LOOKUPS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_result_cache_lookups_total",
+            "Per-subject result cache lookups by result (hit / miss)"
+        ),
+        &["result"]
+    ).unwrap();
+
+    pub static ref RESULT_CACHE_ENTRIES: IntGauge = IntGauge::new(
+        "experiment_result_cache_entries",
+        "Service results held by the per-subject result cache"
+    ).unwrap();
+
+    pub static ref EVALUATION_COALESCING: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_evaluation_coalescing_total",
+            "Per-subject in-flight dedup by outcome (led / coalesced / fallback)"
+        ),
+        &["outcome"]
+    ).unwrap();
+
+    pub static ref PROCESS_RESIDENT_BYTES: IntGauge = IntGauge::new(
+        "experiment_process_resident_memory_bytes",
+        "Resident set size of the data plane process (Linux only)"
+    ).unwrap();
+
+    pub static ref FREEZE_REFUSED: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_freeze_refused_changes_total",
+            "Config changes refused during a freeze window, by mode (reject / queue)"
+        ),
+        &["mode"]
+    ).unwrap();
+
+    pub static ref EVENTS_DROPPED: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_engine_events_dropped_total",
+            "Engine events skipped by a subscriber that fell behind, by subscriber"
+        ),
+        &["subscriber"]
+    ).unwrap();
+
+    pub static ref WATCHDOG_RESTARTS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "experiment_watchdog_restarts_total",
+            "Config tasks restarted by the watchdog after missing heartbeats, by task"
+        ),
+        &["task"]
+    ).unwrap();
+
+    pub static ref WATCHDOG_STALLED_TASKS: IntGauge = IntGauge::new(
+        "experiment_watchdog_stalled_tasks",
+        "Config tasks currently stalled (non-zero fails readiness)"
+    ).unwrap();
+
+    pub static ref EMERGENCY_OVERRIDE_ERRORS: IntCounter = IntCounter::new(
+        "experiment_emergency_override_errors_total",
+        "Emergency override file reads that failed (previous state kept)"
+    ).unwrap();
+    
+    pub static ref ACTIVE_LAYERS: IntGauge = IntGauge::new(
         "experiment_active_layers",
         "Number of active layers"
     ).unwrap();
@@ -44,7 +488,266 @@ pub fn init() {
     REGISTRY.register(Box::new(REQUEST_TOTAL.clone())).unwrap();
     REGISTRY.register(Box::new(REQUEST_ERRORS.clone())).unwrap();
     REGISTRY.register(Box::new(REQUEST_DURATION.clone())).unwrap();
+    REGISTRY.register(Box::new(EVALUATION_STAGE_DURATION.clone())).unwrap();
+    REGISTRY.register(Box::new(EVALUATION_PANICS.clone())).unwrap();
     REGISTRY.register(Box::new(LAYER_RELOAD_TOTAL.clone())).unwrap();
     REGISTRY.register(Box::new(LAYER_RELOAD_ERRORS.clone())).unwrap();
+    REGISTRY.register(Box::new(CONFIG_PROPAGATION_SECONDS.clone())).unwrap();
+    REGISTRY.register(Box::new(CONFIG_APPLY_FAILURES.clone())).unwrap();
+    REGISTRY.register(Box::new(CONFIG_MANIFEST_MISMATCHES.clone())).unwrap();
+    REGISTRY.register(Box::new(WATCH_QUEUE_DEPTH.clone())).unwrap();
+    REGISTRY.register(Box::new(WATCH_QUEUE_HIGH_WATER.clone())).unwrap();
+    REGISTRY.register(Box::new(WATCH_COALESCED_TOTAL.clone())).unwrap();
+    REGISTRY.register(Box::new(WATCH_OVERFLOW_TOTAL.clone())).unwrap();
+    REGISTRY.register(Box::new(RESYNC_TOTAL.clone())).unwrap();
+    REGISTRY.register(Box::new(RESYNC_CHANGES_TOTAL.clone())).unwrap();
+    REGISTRY.register(Box::new(SNAPSHOT_CHANGES.clone())).unwrap();
+    REGISTRY.register(Box::new(ENABLED_LAYERS.clone())).unwrap();
+    REGISTRY.register(Box::new(SERVICE_ENABLED_LAYERS.clone())).unwrap();
+    REGISTRY.register(Box::new(SERVICE_COVERAGE.clone())).unwrap();
+    REGISTRY.register(Box::new(LAYER_UNCOVERED.clone())).unwrap();
+    REGISTRY.register(Box::new(SERVICE_EVALUATIONS.clone())).unwrap();
+    REGISTRY.register(Box::new(EVALUATION_TRUNCATED.clone())).unwrap();
+    REGISTRY.register(Box::new(EXPOSURE_SPOOLED.clone())).unwrap();
+    REGISTRY.register(Box::new(EXPOSURE_DEDUPLICATED.clone())).unwrap();
+    REGISTRY.register(Box::new(EXPOSURE_SAMPLED_OUT.clone())).unwrap();
+    REGISTRY.register(Box::new(EXPOSURE_SHIPPED.clone())).unwrap();
+    REGISTRY.register(Box::new(EXPOSURE_SHIP_FAILURES.clone())).unwrap();
+    REGISTRY.register(Box::new(EXPOSURE_DROPPED.clone())).unwrap();
+    REGISTRY.register(Box::new(EXPOSURE_SPOOL_SEGMENTS.clone())).unwrap();
+    REGISTRY.register(Box::new(SDK_KEY_REJECTIONS.clone())).unwrap();
+    REGISTRY.register(Box::new(CONTEXT_FIELDS_DROPPED.clone())).unwrap();
+    REGISTRY.register(Box::new(GRPC_REQUESTS.clone())).unwrap();
+    REGISTRY.register(Box::new(GRPC_DURATION.clone())).unwrap();
+    REGISTRY.register(Box::new(GRPC_DEADLINE_EXCEEDED.clone())).unwrap();
+    REGISTRY.register(Box::new(XDS_UPDATES.clone())).unwrap();
+    REGISTRY.register(Box::new(XDS_RECONNECTS.clone())).unwrap();
+    REGISTRY.register(Box::new(XDS_ACTIVE_ENDPOINT.clone())).unwrap();
+    REGISTRY.register(Box::new(XDS_ENDPOINT_HEALTHY.clone())).unwrap();
+    REGISTRY.register(Box::new(XDS_ENDPOINT_SWITCHES.clone())).unwrap();
+    REGISTRY.register(Box::new(FEDERATION_REQUESTS.clone())).unwrap();
+    REGISTRY.register(Box::new(FEDERATION_DURATION.clone())).unwrap();
+    REGISTRY.register(Box::new(CONFIG_SOURCE_SWITCHES.clone())).unwrap();
+    REGISTRY.register(Box::new(CONFIG_SOURCE_FAILOVERS.clone())).unwrap();
+    REGISTRY.register(Box::new(CONFIG_SOURCE_ACTIVE.clone())).unwrap();
+    REGISTRY.register(Box::new(EXCLUDED_EVALUATIONS.clone())).unwrap();
+    REGISTRY.register(Box::new(EXCLUSION_FILE_ERRORS.clone())).unwrap();
+    REGISTRY.register(Box::new(CAPPED_ASSIGNMENTS.clone())).unwrap();
+    REGISTRY.register(Box::new(EMERGENCY_DISABLED.clone())).unwrap();
+    REGISTRY.register(Box::new(EMERGENCY_OVERRIDE_ERRORS.clone())).unwrap();
+    REGISTRY.register(Box::new(FREEZE_REFUSED.clone())).unwrap();
+    REGISTRY.register(Box::new(WATCHDOG_RESTARTS.clone())).unwrap();
+    REGISTRY.register(Box::new(EVENTS_DROPPED.clone())).unwrap();
+    REGISTRY.register(Box::new(WATCHDOG_STALLED_TASKS.clone())).unwrap();
+    REGISTRY.register(Box::new(IDENTITY_ALIASES.clone())).unwrap();
+    REGISTRY.register(Box::new(IDENTITY_ALIAS_RESOLUTIONS.clone())).unwrap();
+    REGISTRY.register(Box::new(CONTEXT_NUMBER_COERCIONS.clone())).unwrap();
+    REGISTRY.register(Box::new(CONTEXT_DATABASE_BUILD_TIMESTAMP.clone())).unwrap();
+    REGISTRY.register(Box::new(CONTEXT_DATABASE_REFRESH_ERRORS.clone())).unwrap();
+    REGISTRY.register(Box::new(METRICS_PUSHES.clone())).unwrap();
+    REGISTRY.register(Box::new(EXPIRED_RESOURCES.clone())).unwrap();
+    REGISTRY.register(Box::new(CATALOG_PARAM_BYTES.clone())).unwrap();
+    REGISTRY.register(Box::new(CATALOG_PARAM_BLOBS.clone())).unwrap();
+    REGISTRY.register(Box::new(CATALOG_PARAM_DEDUP_BYTES.clone())).unwrap();
+    REGISTRY.register(Box::new(CATALOG_PARAM_CACHE_ENTRIES.clone())).unwrap();
+    REGISTRY.register(Box::new(CATALOG_PARAM_CACHE_LOOKUPS.clone())).unwrap();
+    REGISTRY.register(Box::new(RESULT_CACHE_LOOKUPS.clone())).unwrap();
+    REGISTRY.register(Box::new(RESULT_CACHE_ENTRIES.clone())).unwrap();
+    REGISTRY.register(Box::new(EVALUATION_COALESCING.clone())).unwrap();
+    REGISTRY.register(Box::new(PROCESS_RESIDENT_BYTES.clone())).unwrap();
     REGISTRY.register(Box::new(ACTIVE_LAYERS.clone())).unwrap();
 }
+
+/// What a snapshot swap changed for one kind of resource
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct ChangeCounts {
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+}
+
+impl ChangeCounts {
+    /// Compare two keyed snapshots
+    pub fn between<K: Eq + Hash, V: PartialEq>(old: &HashMap<K, V>, new: &HashMap<K, V>) -> Self {
+        let mut counts = Self::default();
+        for (key, value) in new {
+            match old.get(key) {
+                None => counts.added += 1,
+                Some(previous) if previous != value => counts.modified += 1,
+                Some(_) => {}
+            }
+        }
+        counts.removed = old.keys().filter(|k| !new.contains_key(*k)).count();
+        counts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added == 0 && self.removed == 0 && self.modified == 0
+    }
+
+    /// Add to `experiment_snapshot_changes_total` under the given resource label
+    pub fn record(&self, resource: &str) {
+        for (change, n) in [("added", self.added), ("removed", self.removed), ("modified", self.modified)] {
+            if n > 0 {
+                SNAPSHOT_CHANGES.with_label_values(&[resource, change]).inc_by(n as u64);
+            }
+        }
+    }
+}
+
+/// Fraction of requests timed per stage, as f64 bits (0 = disabled)
+static STAGE_SAMPLE_RATE: AtomicU64 = AtomicU64::new(0);
+
+/// Set the fraction (0.0-1.0) of evaluations whose stages are timed
+pub fn set_stage_sample_rate(rate: f64) {
+    STAGE_SAMPLE_RATE.store(rate.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+}
+
+/// Evaluation stage reported by [`StageTimer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Bucket hashing and range lookup
+    Hash,
+    /// Layer and experiment targeting rules
+    Rule,
+    /// Variant lookup, including lazy params materialization
+    Catalog,
+    /// Param merging across layers
+    Merge,
+}
+
+impl Stage {
+    const ALL: [Stage; 4] = [Stage::Hash, Stage::Rule, Stage::Catalog, Stage::Merge];
+
+    fn label(self) -> &'static str {
+        match self {
+            Stage::Hash => "hash",
+            Stage::Rule => "rule",
+            Stage::Catalog => "catalog",
+            Stage::Merge => "merge",
+        }
+    }
+}
+
+/// Accumulates stage time over one request; a no-op unless the request is sampled
+#[derive(Debug)]
+pub struct StageTimer {
+    totals: Option<[Duration; 4]>,
+}
+
+impl StageTimer {
+    /// Timer for one request, sampled at the configured rate
+    pub fn sampled() -> Self {
+        let rate = f64::from_bits(STAGE_SAMPLE_RATE.load(Ordering::Relaxed));
+        let sampled = rate >= 1.0 || (rate > 0.0 && rand::random::<f64>() < rate);
+        Self {
+            totals: sampled.then_some([Duration::ZERO; 4]),
+        }
+    }
+
+    /// Run `f`, adding its wall time to `stage` when sampled
+    #[inline]
+    pub fn time<T>(&mut self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let Some(totals) = &mut self.totals else {
+            return f();
+        };
+        let start = Instant::now();
+        let out = f();
+        totals[stage as usize] += start.elapsed();
+        out
+    }
+
+    /// Observe one sample per stage (also for stages the request never reached)
+    pub fn finish(self) {
+        if let Some(totals) = self.totals {
+            for stage in Stage::ALL {
+                EVALUATION_STAGE_DURATION
+                    .with_label_values(&[stage.label()])
+                    .observe(totals[stage as usize].as_secs_f64());
+            }
+        }
+    }
+}
+
+/// Refresh memory gauges before a scrape
+pub fn set_memory_gauges(compressed_param_bytes: usize, cached_params: usize, dedup: DedupStats) {
+    CATALOG_PARAM_BYTES.set(compressed_param_bytes as i64);
+    CATALOG_PARAM_CACHE_ENTRIES.set(cached_params as i64);
+    CATALOG_PARAM_BLOBS.with_label_values(&["variants"]).set(dedup.variants as i64);
+    CATALOG_PARAM_BLOBS.with_label_values(&["unique"]).set(dedup.unique as i64);
+    CATALOG_PARAM_DEDUP_BYTES.set(dedup.saved_bytes as i64);
+    if let Some(rss) = resident_bytes() {
+        PROCESS_RESIDENT_BYTES.set(rss as i64);
+    }
+}
+
+/// VmRSS from /proc/self/status
+fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// Replace the per-service gauges; services no longer present are dropped
+pub fn set_service_gauges(enabled_layers: usize, services: &[(String, usize, f64)]) {
+    ENABLED_LAYERS.set(enabled_layers as i64);
+    SERVICE_ENABLED_LAYERS.reset();
+    SERVICE_COVERAGE.reset();
+    for (service, layers, coverage) in services {
+        SERVICE_ENABLED_LAYERS.with_label_values(&[service]).set(*layers as i64);
+        SERVICE_COVERAGE.with_label_values(&[service]).set(*coverage);
+    }
+}
+
+/// Export the uncovered bucket share of every enabled layer
+pub fn set_layer_uncovered(layers: &[(&str, f64)]) {
+    LAYER_UNCOVERED.reset();
+    for (layer_id, ratio) in layers {
+        LAYER_UNCOVERED.with_label_values(&[layer_id]).set(*ratio);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_change_counts_between() {
+        let old: HashMap<&str, i32> = [("a", 1), ("b", 2), ("c", 3)].into_iter().collect();
+        let new: HashMap<&str, i32> = [("a", 1), ("b", 20), ("d", 4)].into_iter().collect();
+
+        let counts = ChangeCounts::between(&old, &new);
+        assert_eq!(
+            counts,
+            ChangeCounts {
+                added: 1,
+                removed: 1,
+                modified: 1
+            }
+        );
+        assert!(ChangeCounts::between(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_stage_timer_observes_sampled_requests() {
+        let count = |stage: Stage| {
+            EVALUATION_STAGE_DURATION
+                .with_label_values(&[stage.label()])
+                .get_sample_count()
+        };
+
+        set_stage_sample_rate(1.0);
+        let before = count(Stage::Merge);
+        let mut timer = StageTimer::sampled();
+        assert_eq!(timer.time(Stage::Hash, || 42), 42);
+        timer.finish();
+        // Every stage gets one observation per sampled request
+        assert!(count(Stage::Merge) > before);
+
+        set_stage_sample_rate(0.0);
+        let mut timer = StageTimer::sampled();
+        assert_eq!(timer.time(Stage::Hash, || 7), 7);
+        assert!(timer.totals.is_none());
+    }
+}
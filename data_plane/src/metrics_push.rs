@@ -0,0 +1,122 @@
+//! Pushing metrics to a Prometheus Pushgateway.
+//!
+//! Edge builds (without the `metrics-endpoint` feature) have no `/metrics` to
+//! scrape. With `METRICS_PUSH_URL` set, any build PUTs its metrics in the text
+//! format every `METRICS_PUSH_INTERVAL_SECS`. The URL carries the grouping
+//! key, e.g. `http://pushgateway:9091/metrics/job/experiment_data_plane/instance/edge-1`;
+//! PUT replaces the whole group, so series gone here are gone there too. A
+//! failed push is logged and counted, and the next one sends current values.
+
+use crate::error::{ExperimentError, Result};
+use crate::metrics;
+use axum::body::Body;
+use axum::http::{header, Request, Uri};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use std::io;
+use std::time::Duration;
+
+/// Content type of the Prometheus text exposition format
+const TEXT_FORMAT: &str = "text/plain; version=0.0.4";
+
+/// Client for one Pushgateway group
+pub struct MetricsPusher {
+    url: Uri,
+    client: Client<HttpConnector, Body>,
+    timeout: Duration,
+}
+
+impl MetricsPusher {
+    pub fn new(url: &str, timeout: Duration) -> Result<Self> {
+        let url = url
+            .parse::<Uri>()
+            .ok()
+            .filter(|uri| uri.scheme_str() == Some("http") && uri.host().is_some())
+            .ok_or_else(|| ExperimentError::InvalidParameter(format!("Metrics push URL '{}' must be an http:// URL", url)))?;
+        Ok(Self {
+            url,
+            client: Client::builder(TokioExecutor::new()).build_http(),
+            timeout,
+        })
+    }
+
+    /// Replace the group's metrics with `exposition` (Prometheus text format)
+    pub async fn push(&self, exposition: Vec<u8>) -> Result<()> {
+        let request = Request::put(self.url.clone())
+            .header(header::CONTENT_TYPE, TEXT_FORMAT)
+            .body(Body::from(exposition))
+            .map_err(|e| ExperimentError::InvalidParameter(format!("Invalid metrics push to {}: {}", self.url, e)))?;
+
+        let response = tokio::time::timeout(self.timeout, self.client.request(request))
+            .await
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("{}: no answer within {:?}", self.url, self.timeout),
+                )
+            })?
+            .map_err(|e| io::Error::other(format!("{}: {}", self.url, e)))?;
+        if !response.status().is_success() {
+            return Err(io::Error::other(format!("{} answered {}", self.url, response.status())).into());
+        }
+        Ok(())
+    }
+}
+
+/// Push what `gather` exposes every `interval`, starting now
+pub async fn push_periodically(pusher: MetricsPusher, interval: Duration, gather: impl Fn() -> Vec<u8>) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        match pusher.push(gather()).await {
+            Ok(()) => metrics::METRICS_PUSHES.with_label_values(&["ok"]).inc(),
+            Err(e) => {
+                tracing::warn!("Failed to push metrics: {}", e);
+                metrics::METRICS_PUSHES.with_label_values(&["error"]).inc();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+    use axum::routing::put;
+    use axum::Router;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_push_puts_exposition_to_group() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let recorder = received.clone();
+        let app = Router::new().route(
+            "/metrics/job/dp/instance/edge-1",
+            put(move |headers: HeaderMap, body: String| async move {
+                let content_type = headers[header::CONTENT_TYPE].to_str().unwrap().to_string();
+                recorder.lock().push((content_type, body));
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let url = format!("http://{}/metrics/job/dp/instance/edge-1", addr);
+        let pusher = MetricsPusher::new(&url, Duration::from_secs(5)).unwrap();
+        pusher.push(b"experiment_requests_total 3\n".to_vec()).await.unwrap();
+        assert_eq!(
+            *received.lock(),
+            vec![(TEXT_FORMAT.to_string(), "experiment_requests_total 3\n".to_string())]
+        );
+
+        // A group the gateway rejects is an error, as is a non-http URL
+        let missing = MetricsPusher::new(&format!("http://{}/metrics/job/other", addr), Duration::from_secs(5)).unwrap();
+        let err = missing.push(Vec::new()).await.unwrap_err();
+        assert!(err.to_string().contains("404"), "{}", err);
+        assert!(MetricsPusher::new("https://pushgateway:9091/metrics/job/dp", Duration::from_secs(5)).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Lenient number parsing for fields clients send as strings.
+//!
+//! JavaScript clients often send int64 ids as strings (`"9007199254740993"`)
+//! to avoid precision loss, and rules typed `int` then fail to compare them.
+//! Fields listed here are coerced before rules see the context: a numeric
+//! string becomes an integer for an `int` field and a number for a `float`
+//! field. Values that don't parse, and fields of any other type, are left
+//! alone, so rules on them fail exactly as before.
+//!
+//! Only top-level context keys are coerced, after field aliases resolve.
+
+use crate::metrics;
+use crate::rule::FieldType;
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+
+/// Context fields whose numeric strings are parsed for Int / Float rules
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LenientNumbers {
+    fields: BTreeSet<String>,
+}
+
+impl LenientNumbers {
+    pub fn new(fields: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            fields: fields.into_iter().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// `context` with lenient fields' numeric strings parsed as their field
+    /// type; borrowed when nothing is coerced
+    pub fn apply<'a>(
+        &self,
+        context: Cow<'a, HashMap<String, Value>>,
+        field_types: &HashMap<String, FieldType>,
+    ) -> Cow<'a, HashMap<String, Value>> {
+        let mut resolved = context;
+        for field in &self.fields {
+            let Some(Value::String(s)) = resolved.get(field) else {
+                continue;
+            };
+            let Some(number) = field_types.get(field).and_then(|field_type| parse_number(s, field_type)) else {
+                continue;
+            };
+            metrics::CONTEXT_NUMBER_COERCIONS.with_label_values(&[field]).inc();
+            resolved.to_mut().insert(field.clone(), Value::Number(number));
+        }
+        resolved
+    }
+}
+
+fn parse_number(s: &str, field_type: &FieldType) -> Option<Number> {
+    let s = s.trim();
+    match field_type {
+        FieldType::Int => s.parse::<i64>().ok().map(Number::from),
+        FieldType::Float => s
+            .parse::<i64>()
+            .ok()
+            .map(Number::from)
+            .or_else(|| s.parse::<f64>().ok().and_then(Number::from_f64)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::{Node, Op};
+    use serde_json::json;
+
+    #[test]
+    fn test_numeric_strings_coerced_for_listed_fields() {
+        let lenient = LenientNumbers::new(["account_id".to_string(), "score".to_string(), "country".to_string()]);
+        let field_types = HashMap::from([
+            ("account_id".to_string(), FieldType::Int),
+            ("score".to_string(), FieldType::Float),
+            ("country".to_string(), FieldType::String),
+            ("age".to_string(), FieldType::Int),
+        ]);
+        let context = HashMap::from([
+            ("account_id".to_string(), json!("9007199254740993")),
+            ("score".to_string(), json!(" 0.75 ")),
+            ("country".to_string(), json!("42")),
+            ("age".to_string(), json!("30")),
+        ]);
+
+        let coerced = lenient.apply(Cow::Borrowed(&context), &field_types);
+        assert_eq!(coerced["account_id"], json!(9007199254740993i64));
+        assert_eq!(coerced["score"], json!(0.75));
+        // Other types and unlisted fields keep their strings
+        assert_eq!(coerced["country"], json!("42"));
+        assert_eq!(coerced["age"], json!("30"));
+
+        let rule = Node::Field {
+            field: "account_id".to_string(),
+            op: Op::Gt,
+            values: vec![json!(9007199254740992i64)],
+            collation: None,
+        };
+        assert!(rule.evaluate(&coerced, &field_types).unwrap());
+        assert!(rule.evaluate(&context, &field_types).is_err());
+
+        // Nothing to parse: the context is not copied
+        let invalid = HashMap::from([("account_id".to_string(), json!("abc"))]);
+        assert!(matches!(lenient.apply(Cow::Borrowed(&invalid), &field_types), Cow::Borrowed(_)));
+    }
+}
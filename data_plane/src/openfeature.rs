@@ -0,0 +1,431 @@
+//! OpenFeature provider adapter.
+//!
+//! Exposes an [`Evaluator`] through the shape of the OpenFeature provider
+//! spec, so applications standardized on OpenFeature resolve flags against
+//! this engine without bespoke glue:
+//!
+//! - a flag key is `<service>/<param.path>`: the service is evaluated and the
+//!   flag value is the merged param at the dot-separated path
+//! - the evaluation context's `targeting_key` fills every hash key field of
+//!   the service's layers not set as an attribute; attributes become the
+//!   request context
+//! - [`Hook`]s run after each evaluation; [`ExposureHook`] records exposure
+//!   events for the layers that produced the value
+//!
+//! Resolution never fails: errors return the caller's default with an
+//! [`ErrorCode`], as the spec requires.
+
+use crate::engine::Evaluator;
+use crate::exposure::{self, ExposureLog};
+use crate::layer::Snapshot;
+use crate::merge::{ExperimentRequest, ExperimentResponse, ServiceResult};
+use crate::overrides::Overrides;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Provider name reported in [`EngineProvider::metadata`]
+pub const PROVIDER_NAME: &str = "experiment-data-plane";
+
+/// OpenFeature evaluation context
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EvaluationContext {
+    /// Subject identifier, used for every hash key field not given as an attribute
+    pub targeting_key: Option<String>,
+    pub attributes: HashMap<String, Value>,
+}
+
+impl EvaluationContext {
+    pub fn new(targeting_key: impl Into<String>) -> Self {
+        Self {
+            targeting_key: Some(targeting_key.into()),
+            attributes: HashMap::new(),
+        }
+    }
+
+    pub fn with_attribute(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.attributes.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// Why a value was returned (OpenFeature resolution reasons)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Reason {
+    /// The subject was bucketed into at least one variant setting the value
+    Split,
+    /// No matched variant sets the value; the caller's default was returned
+    Default,
+    Error,
+}
+
+/// OpenFeature error codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// Key isn't `<service>/<param.path>` or the service has no layers
+    FlagNotFound,
+    TypeMismatch,
+    /// A layer's hash key can't be filled from the context
+    TargetingKeyMissing,
+    General,
+}
+
+/// Resolved value with OpenFeature resolution details
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResolutionDetails<T> {
+    pub value: T,
+    /// vids of the matched variants, comma-separated in layer order
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    pub reason: Reason,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ErrorCode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    /// `service`, plus `layers` and `vids` when any variant matched
+    pub flag_metadata: HashMap<String, Value>,
+}
+
+impl<T> ResolutionDetails<T> {
+    fn error(default: T, code: ErrorCode, message: String) -> Self {
+        Self {
+            value: default,
+            variant: None,
+            reason: Reason::Error,
+            error_code: Some(code),
+            error_message: Some(message),
+            flag_metadata: HashMap::new(),
+        }
+    }
+}
+
+/// Provider identity
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProviderMetadata {
+    pub name: &'static str,
+}
+
+/// One completed flag evaluation, passed to hooks
+#[derive(Debug)]
+pub struct FlagEvaluation<'a> {
+    pub flag_key: &'a str,
+    pub service: &'a str,
+    pub request: &'a ExperimentRequest,
+    pub result: &'a ServiceResult,
+    /// Snapshot the result was evaluated against
+    pub snapshot: &'a Snapshot,
+}
+
+/// Called after every successful evaluation (OpenFeature `after` stage).
+/// Hooks must not block: they run on the evaluation path.
+pub trait Hook: Send + Sync {
+    fn after(&self, evaluation: &FlagEvaluation<'_>);
+}
+
+/// Records exposure events for the matched layers of each evaluation
+pub struct ExposureHook {
+    exposures: ExposureLog,
+    /// Client name attached to the events
+    client: Option<String>,
+}
+
+impl ExposureHook {
+    pub fn new(exposures: ExposureLog) -> Self {
+        Self {
+            exposures,
+            client: None,
+        }
+    }
+
+    pub fn with_client(mut self, client: impl Into<String>) -> Self {
+        self.client = Some(client.into());
+        self
+    }
+}
+
+impl Hook for ExposureHook {
+    fn after(&self, evaluation: &FlagEvaluation<'_>) {
+        let response = ExperimentResponse {
+            results: HashMap::from([(evaluation.service.to_string(), evaluation.result.clone())]),
+        };
+        self.exposures.record(exposure::events_for(
+            evaluation.request,
+            &response,
+            self.client.as_deref(),
+            evaluation.snapshot,
+        ));
+    }
+}
+
+/// OpenFeature provider backed by the evaluation engine
+pub struct EngineProvider {
+    evaluator: Arc<Evaluator>,
+    hooks: Vec<Arc<dyn Hook>>,
+}
+
+impl EngineProvider {
+    pub fn new(evaluator: Arc<Evaluator>) -> Self {
+        Self {
+            evaluator,
+            hooks: Vec::new(),
+        }
+    }
+
+    pub fn with_hook(mut self, hook: Arc<dyn Hook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    pub fn metadata(&self) -> ProviderMetadata {
+        ProviderMetadata { name: PROVIDER_NAME }
+    }
+
+    pub fn resolve_bool_value(&self, flag_key: &str, default: bool, ctx: &EvaluationContext) -> ResolutionDetails<bool> {
+        self.resolve(flag_key, default, ctx, Value::as_bool)
+    }
+
+    pub fn resolve_string_value(
+        &self,
+        flag_key: &str,
+        default: String,
+        ctx: &EvaluationContext,
+    ) -> ResolutionDetails<String> {
+        self.resolve(flag_key, default, ctx, |v| v.as_str().map(str::to_string))
+    }
+
+    pub fn resolve_int_value(&self, flag_key: &str, default: i64, ctx: &EvaluationContext) -> ResolutionDetails<i64> {
+        self.resolve(flag_key, default, ctx, Value::as_i64)
+    }
+
+    pub fn resolve_float_value(&self, flag_key: &str, default: f64, ctx: &EvaluationContext) -> ResolutionDetails<f64> {
+        self.resolve(flag_key, default, ctx, Value::as_f64)
+    }
+
+    /// Objects (and any other JSON value) at the param path
+    pub fn resolve_struct_value(
+        &self,
+        flag_key: &str,
+        default: Value,
+        ctx: &EvaluationContext,
+    ) -> ResolutionDetails<Value> {
+        self.resolve(flag_key, default, ctx, |v| Some(v.clone()))
+    }
+
+    fn resolve<T>(
+        &self,
+        flag_key: &str,
+        default: T,
+        ctx: &EvaluationContext,
+        convert: impl FnOnce(&Value) -> Option<T>,
+    ) -> ResolutionDetails<T> {
+        let Some((service, path)) = flag_key.split_once('/').filter(|(s, p)| !s.is_empty() && !p.is_empty()) else {
+            return ResolutionDetails::error(
+                default,
+                ErrorCode::FlagNotFound,
+                format!("Flag key '{}' is not `<service>/<param.path>`", flag_key),
+            );
+        };
+
+        // Layers, evaluation and hooks all see one snapshot
+        let snapshot = self.evaluator.layer_manager().snapshot();
+        let layers = snapshot.layers_for_service(service);
+        if layers.is_empty() {
+            return ResolutionDetails::error(
+                default,
+                ErrorCode::FlagNotFound,
+                format!("Service '{}' has no enabled layers", service),
+            );
+        }
+
+        let mut context = ctx.attributes.clone();
+        for layer in layers.iter() {
+            for field in layer.hash_key.fields() {
+                if context.contains_key(field) {
+                    continue;
+                }
+                let Some(targeting_key) = &ctx.targeting_key else {
+                    return ResolutionDetails::error(
+                        default,
+                        ErrorCode::TargetingKeyMissing,
+                        format!("Layer '{}' hashes on '{}', not in context", layer.layer_id, field),
+                    );
+                };
+                context.insert(field.clone(), Value::String(targeting_key.clone()));
+            }
+        }
+
+        let request = ExperimentRequest {
+            services: vec![service.to_string()],
+            context,
+            layers: vec![],
+            diagnostics: false,
+        };
+        let mut response = match self.evaluator.evaluate_traced(&request, &Overrides::new(), false, &snapshot) {
+            Ok(response) => response,
+            Err(e) => return ResolutionDetails::error(default, ErrorCode::General, e.to_string()),
+        };
+        let Some(result) = response.results.remove(service) else {
+            return ResolutionDetails::error(default, ErrorCode::General, format!("No result for '{}'", service));
+        };
+
+        let evaluation = FlagEvaluation {
+            flag_key,
+            service,
+            request: &request,
+            result: &result,
+            snapshot: &snapshot,
+        };
+        for hook in &self.hooks {
+            hook.after(&evaluation);
+        }
+
+        let mut flag_metadata = HashMap::from([("service".to_string(), Value::from(service))]);
+        if !result.vids.is_empty() {
+            flag_metadata.insert("layers".to_string(), Value::from(result.matched_layers.clone()));
+            flag_metadata.insert("vids".to_string(), Value::from(result.vids.clone()));
+        }
+
+        let Some(raw) = path.split('.').try_fold(&result.parameters, |value, key| value.get(key)) else {
+            return ResolutionDetails {
+                value: default,
+                variant: None,
+                reason: Reason::Default,
+                error_code: None,
+                error_message: None,
+                flag_metadata,
+            };
+        };
+        let Some(value) = convert(raw) else {
+            let mut details = ResolutionDetails::error(
+                default,
+                ErrorCode::TypeMismatch,
+                format!("Param '{}' of '{}' has unexpected type: {}", path, service, raw),
+            );
+            details.flag_metadata = flag_metadata;
+            return details;
+        };
+
+        let variant = result.vids.iter().map(i64::to_string).collect::<Vec<_>>().join(",");
+        ResolutionDetails {
+            value,
+            variant: Some(variant),
+            reason: Reason::Split,
+            error_code: None,
+            error_message: None,
+            flag_metadata,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
+    use crate::layer::{BucketRange, Layer};
+    use serde_json::json;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct CountingHook(AtomicUsize);
+
+    impl Hook for CountingHook {
+        fn after(&self, _evaluation: &FlagEvaluation<'_>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn provider(hook: Arc<CountingHook>) -> EngineProvider {
+        let catalog = ExperimentCatalog::from_experiments(
+            vec![ExperimentDef {
+                eid: 100,
+                service: "ranker".to_string(),
+                expires_at: None,
+                state: Default::default(),
+                caps: None,
+                rule: None,
+                exposure_sampling: None,
+                variants: vec![VariantDef {
+                    vid: 101,
+                    params: json!({"ranker": {"model": "gbdt", "rerank": true, "timeout_ms": 150}}),
+                    dark_params: None,
+                }],
+            }],
+            PathBuf::new(),
+        )
+        .unwrap();
+        let layer = Layer {
+            layer_id: "ranker_layer".to_string(),
+            version: "v1".to_string(),
+            priority: 1,
+            hash_key: "user_id".into(),
+            salt: None,
+            bucket_size: None,
+            expires_at: None,
+            rule: None,
+            services: vec![],
+            ranges: vec![BucketRange {
+                start: 0,
+                end: 10000,
+                vid: 101,
+                label: None,
+            }],
+            enabled: true,
+            aa_test: false,
+        };
+        let evaluator = Evaluator::builder()
+            .with_catalog(catalog)
+            .with_layers([layer])
+            .build()
+            .unwrap();
+        EngineProvider::new(Arc::new(evaluator)).with_hook(hook)
+    }
+
+    #[test]
+    fn test_resolves_params_as_flags() {
+        let hook = Arc::new(CountingHook::default());
+        let provider = provider(hook.clone());
+        let ctx = EvaluationContext::new("u1");
+
+        let model = provider.resolve_string_value("ranker/ranker.model", "lr".to_string(), &ctx);
+        assert_eq!(model.value, "gbdt");
+        assert_eq!(model.reason, Reason::Split);
+        assert_eq!(model.variant.as_deref(), Some("101"));
+        assert_eq!(model.flag_metadata["layers"], json!(["ranker_layer"]));
+        assert!(provider.resolve_bool_value("ranker/ranker.rerank", false, &ctx).value);
+        assert_eq!(provider.resolve_int_value("ranker/ranker.timeout_ms", 0, &ctx).value, 150);
+
+        // Unset params fall back to the caller's default without an error
+        let missing = provider.resolve_float_value("ranker/ranker.temperature", 0.5, &ctx);
+        assert_eq!((missing.value, missing.reason, missing.error_code), (0.5, Reason::Default, None));
+        assert_eq!(hook.0.load(Ordering::SeqCst), 4);
+
+        let mismatch = provider.resolve_int_value("ranker/ranker.model", 7, &ctx);
+        assert_eq!((mismatch.value, mismatch.error_code), (7, Some(ErrorCode::TypeMismatch)));
+    }
+
+    #[test]
+    fn test_resolution_errors_return_default() {
+        let hook = Arc::new(CountingHook::default());
+        let provider = provider(hook.clone());
+
+        let no_subject = provider.resolve_bool_value("ranker/ranker.rerank", false, &EvaluationContext::default());
+        assert_eq!(no_subject.error_code, Some(ErrorCode::TargetingKeyMissing));
+        assert!(!no_subject.value);
+
+        // An explicit attribute stands in for the targeting key
+        let by_attribute = EvaluationContext::default().with_attribute("user_id", "u1");
+        assert!(provider.resolve_bool_value("ranker/ranker.rerank", false, &by_attribute).value);
+
+        let ctx = EvaluationContext::new("u1");
+        for key in ["ranker.model", "/ranker.model", "unknown/ranker.model"] {
+            let details = provider.resolve_string_value(key, "lr".to_string(), &ctx);
+            assert_eq!(details.error_code, Some(ErrorCode::FlagNotFound), "{}", key);
+            assert_eq!(details.reason, Reason::Error);
+        }
+        assert_eq!(hook.0.load(Ordering::SeqCst), 1);
+    }
+}
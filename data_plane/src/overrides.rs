@@ -0,0 +1,460 @@
+//! Support overrides: pin a subject to a specific vid for a limited time.
+//!
+//! Overrides live in the shared [`KvStore`] (one key per subject, expiring with
+//! the longest-lived override) so every instance using the same backend sees
+//! them. Every create/delete is appended to an audit log kept in the same
+//! store, so it is shared too and survives restarts with a persistent backend.
+//! Both are updated with compare-and-set: concurrent writers retry instead of
+//! overwriting each other.
+
+use crate::catalog::ExperimentCatalog;
+use crate::error::{ExperimentError, Result};
+use crate::kv::KvStore;
+use crate::layer::{HashKey, LayerManager};
+use crate::merge::ExperimentRequest;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Audit entries kept (oldest dropped first)
+const AUDIT_CAPACITY: usize = 1000;
+
+const KEY_PREFIX: &str = "support_override:";
+
+const AUDIT_KEY: &str = "support_override_audit";
+
+/// Compare-and-set attempts before an update gives up on a contended key
+const MAX_UPDATE_ATTEMPTS: usize = 16;
+
+/// Active overrides by subject key (hash key value)
+pub type Overrides = HashMap<String, Vec<SupportOverride>>;
+
+/// A subject pinned to `vid` in `layer_id` until `expires_at`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SupportOverride {
+    pub layer_id: String,
+    pub eid: i64,
+    pub vid: i64,
+    pub operator: String,
+    pub reason: String,
+    /// Unix timestamps (seconds)
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+/// Request body for creating an override
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateOverride {
+    pub subject: String,
+    pub vid: i64,
+    pub ttl_secs: u64,
+    pub operator: String,
+    pub reason: String,
+    /// Required only when the vid appears in more than one layer
+    #[serde(default)]
+    pub layer_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Create,
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: u64,
+    pub action: AuditAction,
+    pub subject: String,
+    pub operator: String,
+    /// SDK key client that made the change
+    pub client: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vid: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub reason: String,
+}
+
+pub struct OverrideStore {
+    kv: Arc<dyn KvStore>,
+    max_ttl: Duration,
+}
+
+impl OverrideStore {
+    pub fn new(kv: Arc<dyn KvStore>, max_ttl: Duration) -> Self {
+        Self { kv, max_ttl }
+    }
+
+    /// Pin a subject to a vid on behalf of SDK key `client`. Replaces any
+    /// existing override for the same layer.
+    pub async fn create(
+        &self,
+        request: CreateOverride,
+        client: &str,
+        layer_manager: &LayerManager,
+        catalog: &ExperimentCatalog,
+    ) -> Result<SupportOverride> {
+        if request.ttl_secs == 0 || request.ttl_secs > self.max_ttl.as_secs() {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "Override ttl_secs must be between 1 and {}",
+                self.max_ttl.as_secs()
+            )));
+        }
+        if request.operator.trim().is_empty() || request.reason.trim().is_empty() {
+            return Err(ExperimentError::InvalidParameter(
+                "Override requires `operator` and `reason`".to_string(),
+            ));
+        }
+
+        let eid = catalog.get_eid_by_vid(request.vid).ok_or_else(|| {
+            ExperimentError::InvalidParameter(format!("Unknown vid {}", request.vid))
+        })?;
+        let layer_id = resolve_layer(request.vid, request.layer_id.as_deref(), layer_manager)?;
+
+        let now = unix_now();
+        let created = SupportOverride {
+            layer_id,
+            eid,
+            vid: request.vid,
+            operator: request.operator,
+            reason: request.reason,
+            created_at: now,
+            expires_at: now + request.ttl_secs,
+        };
+
+        self.update(&key(&request.subject), |mut active: Vec<SupportOverride>| {
+            let now = unix_now();
+            active.retain(|o| o.expires_at > now && o.layer_id != created.layer_id);
+            active.push(created.clone());
+            // The key lives as long as its longest-lived override
+            let ttl = active.iter().map(|o| o.expires_at.saturating_sub(now)).max().unwrap_or(0);
+            (active, Some(Duration::from_secs(ttl)))
+        })
+        .await?;
+
+        self.record(AuditEntry {
+            at: now,
+            action: AuditAction::Create,
+            subject: request.subject,
+            operator: created.operator.clone(),
+            client: client.to_string(),
+            layer_id: Some(created.layer_id.clone()),
+            vid: Some(created.vid),
+            expires_at: Some(created.expires_at),
+            reason: created.reason.clone(),
+        })
+        .await?;
+
+        Ok(created)
+    }
+
+    /// Active (unexpired) overrides for a subject
+    pub async fn list(&self, subject: &str) -> Result<Vec<SupportOverride>> {
+        let Some(raw) = self.kv.get(&key(subject)).await? else {
+            return Ok(Vec::new());
+        };
+        let mut overrides: Vec<SupportOverride> = serde_json::from_slice(&raw)?;
+        let now = unix_now();
+        overrides.retain(|o| o.expires_at > now);
+        Ok(overrides)
+    }
+
+    /// Remove all overrides for a subject, returning how many were active
+    pub async fn clear(&self, subject: &str, operator: &str, client: &str) -> Result<usize> {
+        let active = self.list(subject).await?;
+        self.kv.delete(&key(subject)).await?;
+
+        self.record(AuditEntry {
+            at: unix_now(),
+            action: AuditAction::Delete,
+            subject: subject.to_string(),
+            operator: operator.to_string(),
+            client: client.to_string(),
+            layer_id: None,
+            vid: None,
+            expires_at: None,
+            reason: String::new(),
+        })
+        .await?;
+
+        Ok(active.len())
+    }
+
+    /// Active overrides for the given subject keys (for request evaluation)
+    pub async fn lookup<'a>(&self, subjects: impl IntoIterator<Item = &'a str>) -> Result<Overrides> {
+        let mut found = Overrides::new();
+        for subject in subjects {
+            if found.contains_key(subject) {
+                continue;
+            }
+            let active = self.list(subject).await?;
+            if !active.is_empty() {
+                found.insert(subject.to_string(), active);
+            }
+        }
+        Ok(found)
+    }
+
+    /// Recent audit entries, newest first
+    pub async fn audit_log(&self) -> Result<Vec<AuditEntry>> {
+        let mut audit: Vec<AuditEntry> = match self.kv.get(AUDIT_KEY).await? {
+            Some(raw) => serde_json::from_slice(&raw)?,
+            None => Vec::new(),
+        };
+        audit.reverse();
+        Ok(audit)
+    }
+
+    /// Read-modify-write the JSON list under `key`, retrying when another
+    /// writer changed it in between. `apply` returns the new list and its TTL.
+    async fn update<T, F>(&self, key: &str, mut apply: F) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnMut(Vec<T>) -> (Vec<T>, Option<Duration>),
+    {
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let raw = self.kv.get(key).await?;
+            let current = match &raw {
+                Some(raw) => serde_json::from_slice(raw)?,
+                None => Vec::new(),
+            };
+            let (next, ttl) = apply(current);
+            if self.kv.compare_and_set(key, raw.as_deref(), serde_json::to_vec(&next)?, ttl).await? {
+                return Ok(());
+            }
+        }
+        Err(ExperimentError::Store(format!(
+            "Gave up updating '{}' after {} conflicting writes",
+            key, MAX_UPDATE_ATTEMPTS
+        )))
+    }
+
+    async fn record(&self, entry: AuditEntry) -> Result<()> {
+        tracing::info!(
+            action = ?entry.action,
+            subject = %entry.subject,
+            operator = %entry.operator,
+            client = %entry.client,
+            vid = ?entry.vid,
+            "Support override audit"
+        );
+
+        self.update(AUDIT_KEY, |mut audit: Vec<AuditEntry>| {
+            let excess = (audit.len() + 1).saturating_sub(AUDIT_CAPACITY);
+            audit.drain(..excess);
+            audit.push(entry.clone());
+            (audit, None)
+        })
+        .await
+    }
+}
+
+/// Hash key values a request would be bucketed by (the keys overrides are stored under)
+pub fn subject_keys(request: &ExperimentRequest, layer_manager: &LayerManager) -> Vec<String> {
+    let mut hash_keys: Vec<HashKey> = if request.layers.is_empty() {
+        request
+            .services
+            .iter()
+            .flat_map(|s| {
+                layer_manager
+                    .get_layers_for_service(s)
+                    .iter()
+                    .map(|l| l.hash_key.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    } else {
+        request
+            .layers
+            .iter()
+            .filter_map(|id| layer_manager.get_layer(id))
+            .map(|l| l.hash_key.clone())
+            .collect()
+    };
+    hash_keys.sort();
+    hash_keys.dedup();
+
+    hash_keys
+        .iter()
+        .filter_map(|k| k.value(&request.context).ok().map(|v| v.into_owned()))
+        .collect()
+}
+
+/// Find the layer whose ranges contain `vid`
+fn resolve_layer(vid: i64, requested: Option<&str>, layer_manager: &LayerManager) -> Result<String> {
+    let candidates: Vec<String> = layer_manager
+        .get_layer_ids()
+        .into_iter()
+        .filter(|id| {
+            layer_manager
+                .get_layer(id)
+                .is_some_and(|l| l.ranges.iter().any(|r| r.vid == vid))
+        })
+        .collect();
+
+    match requested {
+        Some(id) if candidates.iter().any(|c| c == id) => Ok(id.to_string()),
+        Some(id) => Err(ExperimentError::InvalidParameter(format!(
+            "Layer {} has no range for vid {}",
+            id, vid
+        ))),
+        None => match candidates.as_slice() {
+            [only] => Ok(only.clone()),
+            [] => Err(ExperimentError::InvalidParameter(format!(
+                "vid {} is not assigned in any layer",
+                vid
+            ))),
+            _ => Err(ExperimentError::InvalidParameter(format!(
+                "vid {} appears in layers {}; specify `layer_id`",
+                vid,
+                candidates.join(", ")
+            ))),
+        },
+    }
+}
+
+fn key(subject: &str) -> String {
+    format!("{}{}", KEY_PREFIX, subject)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{ExperimentDef, VariantDef};
+    use crate::kv::MemoryStore;
+    use crate::merge::merge_layers_batch_with_overrides;
+    use crate::rule::{Node, Op};
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_override_pins_vid_and_is_audited() {
+        let temp_dir = TempDir::new().unwrap();
+        let layers_dir = temp_dir.path().join("layers");
+        let experiments_dir = temp_dir.path().join("experiments");
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+
+        // Rule excludes everyone; the override must bypass it
+        let exp = ExperimentDef {
+            eid: 100,
+            service: "svc".to_string(),
+            expires_at: None,
+            state: Default::default(),
+            caps: None,
+            rule: Some(Node::Field {
+                field: "country".to_string(),
+                op: Op::Eq,
+                values: vec![json!("nowhere")],
+                collation: None,
+            }),
+            exposure_sampling: None,
+            variants: vec![
+                VariantDef {
+                    vid: 1001,
+                    params: json!({"arm": "a"}),
+                    dark_params: None,
+                },
+                VariantDef {
+                    vid: 1002,
+                    params: json!({"arm": "b"}),
+                    dark_params: None,
+                },
+            ],
+        };
+        std::fs::write(experiments_dir.join("100.json"), serde_json::to_string(&exp).unwrap()).unwrap();
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+
+        let layer = json!({
+            "layer_id": "l1",
+            "version": "v1",
+            "priority": 1,
+            "hash_key": "user_id",
+            "enabled": true,
+            "ranges": [{"start": 0, "end": 9999, "vid": 1001}, {"start": 9999, "end": 10000, "vid": 1002}]
+        });
+        std::fs::write(layers_dir.join("l1.json"), layer.to_string()).unwrap();
+        let manager = LayerManager::new(layers_dir);
+        manager.load_all_layers(&catalog).await.unwrap();
+
+        let kv: Arc<dyn KvStore> = Arc::new(MemoryStore::new());
+        let store = OverrideStore::new(kv.clone(), Duration::from_secs(3600));
+
+        let too_long = CreateOverride {
+            subject: "u1".to_string(),
+            vid: 1002,
+            ttl_secs: 7200,
+            operator: "alice".to_string(),
+            reason: "ticket-1".to_string(),
+            layer_id: None,
+        };
+        assert!(store.create(too_long, "support-tool", &manager, &catalog).await.is_err());
+
+        let created = store
+            .create(
+                CreateOverride {
+                    subject: "u1".to_string(),
+                    vid: 1002,
+                    ttl_secs: 600,
+                    operator: "alice".to_string(),
+                    reason: "ticket-1".to_string(),
+                    layer_id: None,
+                },
+                "support-tool",
+                &manager,
+                &catalog,
+            )
+            .await
+            .unwrap();
+        assert_eq!(created.layer_id, "l1");
+        assert_eq!(created.eid, 100);
+
+        let request = ExperimentRequest {
+            services: vec!["svc".to_string()],
+            context: [("user_id".to_string(), json!("u1"))].into_iter().collect(),
+            layers: vec![],
+            diagnostics: false,
+        };
+        let subjects = subject_keys(&request, &manager);
+        assert_eq!(subjects, vec!["u1".to_string()]);
+
+        let overrides = store.lookup(subjects.iter().map(String::as_str)).await.unwrap();
+        let response =
+            merge_layers_batch_with_overrides(&request, &overrides, &manager.snapshot(), &HashMap::new()).unwrap();
+        assert_eq!(response.results["svc"].vids, vec![1002]);
+        assert_eq!(response.results["svc"].parameters, json!({"arm": "b"}));
+
+        // Without the override the rule excludes the subject
+        let response =
+            merge_layers_batch_with_overrides(&request, &Overrides::new(), &manager.snapshot(), &HashMap::new())
+                .unwrap();
+        assert!(response.results["svc"].vids.is_empty());
+
+        assert_eq!(store.clear("u1", "bob", "support-tool").await.unwrap(), 1);
+        assert!(store.list("u1").await.unwrap().is_empty());
+
+        // The audit log lives in the KV store, not in this instance
+        let other_instance = OverrideStore::new(kv, Duration::from_secs(3600));
+        let audit = other_instance.audit_log().await.unwrap();
+        assert_eq!(audit.len(), 2);
+        assert_eq!(audit[0].action, AuditAction::Delete);
+        assert_eq!(audit[0].operator, "bob");
+        assert_eq!(audit[0].client, "support-tool");
+        assert_eq!(audit[1].action, AuditAction::Create);
+        assert_eq!(audit[1].vid, Some(1002));
+    }
+}
@@ -0,0 +1,292 @@
+//! Variant params storage for the catalog.
+//!
+//! By default params stay parsed in memory, deduplicated by content: variants
+//! with identical params (a control copied across hundreds of experiments, say)
+//! share one allocation, which also lets merging skip a blob it has already
+//! applied by pointer. With lazy params enabled
+//! (`CATALOG_LAZY_PARAMS`) each variant's params are kept as a deflate-compressed
+//! JSON blob and materialized on first access into a bounded LRU, trading a
+//! little CPU on cache misses for a much smaller resident catalog.
+//!
+//! Blobs hold JSON by default; the `msgpack-params` feature encodes them as
+//! MessagePack, which is more compact for numeric and deeply nested params.
+
+use crate::error::{ExperimentError, Result};
+use crate::metrics;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use lru::LruCache;
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
+use std::ops::Deref;
+use std::sync::Arc;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Params of one variant: borrowed from an eager catalog or shared from the LRU
+#[derive(Debug, Clone)]
+pub enum ParamsRef<'a> {
+    Borrowed(&'a Value),
+    Shared(Arc<Value>),
+}
+
+impl Deref for ParamsRef<'_> {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        match self {
+            ParamsRef::Borrowed(value) => value,
+            ParamsRef::Shared(value) => value,
+        }
+    }
+}
+
+/// Parsed params by vid, one shared `Arc` per distinct value
+#[derive(Debug, Clone, Default)]
+pub struct ParamPool {
+    /// vid -> (content hash, shared value)
+    by_vid: HashMap<i64, (u64, Arc<Value>)>,
+    /// content hash -> distinct values with that hash
+    blobs: HashMap<u64, Vec<PooledParams>>,
+}
+
+#[derive(Debug, Clone)]
+struct PooledParams {
+    value: Arc<Value>,
+    /// Variants sharing this value
+    refs: usize,
+    /// Serialized size, for the dedup stats
+    bytes: usize,
+}
+
+/// How much a [`ParamPool`] saves by sharing equal params
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    pub variants: usize,
+    pub unique: usize,
+    /// Serialized size of the params not stored thanks to sharing
+    pub saved_bytes: usize,
+}
+
+impl ParamPool {
+    /// Store `params` for `vid`, sharing the value of any variant with equal params
+    pub fn insert(&mut self, vid: i64, params: Value) -> Result<()> {
+        self.remove(vid);
+
+        let encoded = serde_json::to_vec(&params)?;
+        let hash = xxh3_64(&encoded);
+        let candidates = self.blobs.entry(hash).or_default();
+        let value = match candidates.iter_mut().find(|pooled| *pooled.value == params) {
+            Some(pooled) => {
+                pooled.refs += 1;
+                pooled.value.clone()
+            }
+            None => {
+                let value = Arc::new(params);
+                candidates.push(PooledParams {
+                    value: value.clone(),
+                    refs: 1,
+                    bytes: encoded.len(),
+                });
+                value
+            }
+        };
+        self.by_vid.insert(vid, (hash, value));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, vid: i64) {
+        let Some((hash, value)) = self.by_vid.remove(&vid) else {
+            return;
+        };
+        let Some(candidates) = self.blobs.get_mut(&hash) else {
+            return;
+        };
+        if let Some(i) = candidates.iter().position(|pooled| Arc::ptr_eq(&pooled.value, &value)) {
+            candidates[i].refs -= 1;
+            if candidates[i].refs == 0 {
+                candidates.swap_remove(i);
+            }
+        }
+        if candidates.is_empty() {
+            self.blobs.remove(&hash);
+        }
+    }
+
+    pub fn get(&self, vid: i64) -> Option<&Arc<Value>> {
+        self.by_vid.get(&vid).map(|(_, value)| value)
+    }
+
+    /// Take every value out, leaving the pool empty
+    pub fn drain(&mut self) -> impl Iterator<Item = (i64, Arc<Value>)> + '_ {
+        self.blobs.clear();
+        self.by_vid.drain().map(|(vid, (_, value))| (vid, value))
+    }
+
+    pub fn stats(&self) -> DedupStats {
+        let pooled = || self.blobs.values().flatten();
+        DedupStats {
+            variants: self.by_vid.len(),
+            unique: pooled().count(),
+            saved_bytes: pooled().map(|p| p.bytes * (p.refs - 1)).sum(),
+        }
+    }
+}
+
+/// Compressed params by vid with an LRU of materialized values
+#[derive(Debug)]
+pub struct LazyParams {
+    blobs: HashMap<i64, Box<[u8]>>,
+    cache: Mutex<LruCache<i64, Arc<Value>>>,
+    capacity: NonZeroUsize,
+}
+
+impl LazyParams {
+    /// `cache_entries` materialized variants are kept (at least one)
+    pub fn new(cache_entries: usize) -> Self {
+        let capacity = NonZeroUsize::new(cache_entries).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            blobs: HashMap::new(),
+            cache: Mutex::new(LruCache::new(capacity)),
+            capacity,
+        }
+    }
+
+    pub fn insert(&mut self, vid: i64, params: &Value) -> Result<()> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encode(&mut encoder, params)?;
+        encoder.flush()?;
+        self.blobs.insert(vid, encoder.finish()?.into_boxed_slice());
+        self.cache.lock().pop(&vid);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, vid: i64) {
+        self.blobs.remove(&vid);
+        self.cache.lock().pop(&vid);
+    }
+
+    /// Materialize params, from the cache when possible
+    pub fn get(&self, vid: i64) -> Option<Arc<Value>> {
+        if let Some(value) = self.cache.lock().get(&vid) {
+            metrics::CATALOG_PARAM_CACHE_LOOKUPS.with_label_values(&["hit"]).inc();
+            return Some(value.clone());
+        }
+        metrics::CATALOG_PARAM_CACHE_LOOKUPS.with_label_values(&["miss"]).inc();
+
+        let value = match decode(self.blobs.get(&vid)?) {
+            Ok(value) => Arc::new(value),
+            Err(e) => {
+                tracing::error!("Failed to materialize params of vid {}: {}", vid, e);
+                return None;
+            }
+        };
+        self.cache.lock().put(vid, value.clone());
+        Some(value)
+    }
+
+    /// Compressed blob (equal for equal params)
+    pub fn blob(&self, vid: i64) -> Option<&[u8]> {
+        self.blobs.get(&vid).map(|b| &b[..])
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity.get()
+    }
+
+    pub fn compressed_bytes(&self) -> usize {
+        self.blobs.values().map(|b| b.len()).sum()
+    }
+
+    pub fn cached_entries(&self) -> usize {
+        self.cache.lock().len()
+    }
+
+    /// Same blobs with an empty cache, for building a modified copy
+    pub fn fork(&self) -> Self {
+        Self {
+            blobs: self.blobs.clone(),
+            cache: Mutex::new(LruCache::new(self.capacity)),
+            capacity: self.capacity,
+        }
+    }
+}
+
+#[cfg(not(feature = "msgpack-params"))]
+fn encode(writer: &mut impl Write, params: &Value) -> Result<()> {
+    Ok(serde_json::to_writer(writer, params)?)
+}
+
+#[cfg(feature = "msgpack-params")]
+fn encode(writer: &mut impl Write, params: &Value) -> Result<()> {
+    rmp_serde::encode::write(writer, params)
+        .map_err(|e| ExperimentError::InvalidParameter(format!("Failed to encode params: {}", e)))
+}
+
+fn decode(blob: &[u8]) -> Result<Value> {
+    let mut raw = Vec::new();
+    DeflateDecoder::new(blob)
+        .read_to_end(&mut raw)
+        .map_err(|e| ExperimentError::InvalidParameter(format!("Corrupt params blob: {}", e)))?;
+
+    #[cfg(not(feature = "msgpack-params"))]
+    let value = serde_json::from_slice(&raw)?;
+    #[cfg(feature = "msgpack-params")]
+    let value = rmp_serde::from_slice(&raw)
+        .map_err(|e| ExperimentError::InvalidParameter(format!("Corrupt params blob: {}", e)))?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_pool_shares_equal_params() {
+        let mut pool = ParamPool::default();
+        pool.insert(1, json!({"model": "a", "timeout": 100})).unwrap();
+        pool.insert(2, json!({"timeout": 100, "model": "a"})).unwrap();
+        pool.insert(3, json!({"model": "b"})).unwrap();
+
+        assert!(Arc::ptr_eq(pool.get(1).unwrap(), pool.get(2).unwrap()));
+        assert!(!Arc::ptr_eq(pool.get(1).unwrap(), pool.get(3).unwrap()));
+        let stats = pool.stats();
+        assert_eq!((stats.variants, stats.unique), (3, 2));
+        assert_eq!(stats.saved_bytes, r#"{"model":"a","timeout":100}"#.len());
+
+        // The shared value lives until its last variant goes
+        pool.remove(1);
+        assert_eq!(*pool.get(2).unwrap().as_ref(), json!({"model": "a", "timeout": 100}));
+        pool.insert(2, json!({"model": "b"})).unwrap();
+        assert_eq!(pool.stats(), DedupStats { variants: 2, unique: 1, saved_bytes: r#"{"model":"b"}"#.len() });
+    }
+
+    #[test]
+    fn test_lazy_params_roundtrip_and_eviction() {
+        let mut params = LazyParams::new(1);
+        params.insert(1, &json!({"model": "a"})).unwrap();
+        params.insert(2, &json!({"model": "b"})).unwrap();
+
+        assert_eq!(*params.get(1).unwrap(), json!({"model": "a"}));
+        assert_eq!(*params.get(2).unwrap(), json!({"model": "b"}));
+        assert_eq!(params.cached_entries(), 1);
+        // Evicted entry is materialized again from its blob
+        assert_eq!(*params.get(1).unwrap(), json!({"model": "a"}));
+
+        params.insert(1, &json!({"model": "c"})).unwrap();
+        assert_eq!(*params.get(1).unwrap(), json!({"model": "c"}));
+
+        // Every JSON shape survives the blob encoding
+        let nested = json!({"n": [1, -2, 3.5, null, true], "s": {"k": "v", "big": u64::MAX}});
+        params.insert(3, &nested).unwrap();
+        assert_eq!(*params.get(3).unwrap(), nested);
+
+        params.remove(2);
+        assert!(params.get(2).is_none());
+        assert!(params.compressed_bytes() > 0);
+    }
+}
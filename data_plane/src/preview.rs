@@ -0,0 +1,350 @@
+use crate::catalog::{ExperimentCatalog, ExperimentDef};
+use crate::error::{ExperimentError, Result};
+use crate::layer::{Layer, LayerManager};
+use crate::merge::{flatten_params, merge_layers_batch, ExperimentRequest};
+use crate::rule::FieldType;
+use crate::sim::{random_subject_key, seeded_rng, DEFAULT_SEED, MAX_POPULATION};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+/// Dry-run request: a candidate layer and/or experiment to compare against the live config
+#[derive(Debug, Clone, Deserialize)]
+pub struct PreviewRequest {
+    /// Candidate layer document (same format as a layer file)
+    #[serde(default)]
+    pub layer: Option<Value>,
+
+    /// Candidate experiment document (same format as an experiment file)
+    #[serde(default)]
+    pub experiment: Option<Value>,
+
+    /// Context shared by every sampled subject
+    #[serde(default)]
+    pub context: HashMap<String, Value>,
+
+    /// Context field that receives the sampled subject key
+    #[serde(default = "default_hash_key")]
+    pub hash_key: String,
+
+    #[serde(default = "default_sample_size")]
+    pub sample_size: usize,
+
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+fn default_hash_key() -> String {
+    "user_id".to_string()
+}
+
+fn default_sample_size() -> usize {
+    1000
+}
+
+/// Static traffic share of a vid (percent of buckets), before and after the change
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CoverageChange {
+    pub before: f64,
+    pub after: f64,
+}
+
+/// A parameter whose winning value changed for at least one sampled subject
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParameterChange {
+    /// Sampled subjects whose value changed
+    pub changed: u64,
+    /// First observed change
+    pub example_before: Option<Value>,
+    pub example_after: Option<Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ServicePreview {
+    /// vid -> coverage change (only vids whose share changed)
+    pub coverage: BTreeMap<i64, CoverageChange>,
+    /// Dot-separated parameter path -> change summary
+    pub parameters: BTreeMap<String, ParameterChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PreviewResponse {
+    pub affected_services: Vec<String>,
+    pub seed: u64,
+    pub sample_size: usize,
+    pub services: BTreeMap<String, ServicePreview>,
+}
+
+/// Apply the candidate to a copy of the current config and report what would change.
+/// The live layer manager and catalog are not modified.
+pub fn preview(
+    request: &PreviewRequest,
+    layer_manager: &LayerManager,
+    catalog: &ExperimentCatalog,
+    field_types: &HashMap<String, FieldType>,
+) -> Result<PreviewResponse> {
+    if request.layer.is_none() && request.experiment.is_none() {
+        return Err(ExperimentError::InvalidParameter(
+            "Preview requires a candidate `layer` or `experiment`".to_string(),
+        ));
+    }
+    if request.sample_size > MAX_POPULATION {
+        return Err(ExperimentError::InvalidParameter(format!(
+            "Preview sample_size {} exceeds limit {}",
+            request.sample_size, MAX_POPULATION
+        )));
+    }
+
+    let mut candidate_services = BTreeSet::new();
+
+    let candidate_catalog = match &request.experiment {
+        Some(doc) => {
+            let exp = ExperimentDef::from_value(doc.clone())?;
+            if let Some(previous) = catalog.get_experiment(exp.eid) {
+                candidate_services.insert(previous.service.clone());
+            }
+            candidate_services.insert(exp.service.clone());
+            let candidate = catalog.with_experiment(exp)?;
+            catalog.check_transitions(&candidate)?;
+            Arc::new(candidate)
+        }
+        None => Arc::new(catalog.clone()),
+    };
+
+    let candidate_manager = layer_manager.fork();
+    candidate_manager.reindex(&candidate_catalog)?;
+
+    if let Some(doc) = &request.layer {
+        let layer = Layer::from_value_against(doc.clone(), false, &candidate_catalog)?;
+        if let Some(previous) = layer_manager.get_layer(&layer.layer_id) {
+            candidate_services.extend(layer_services(&previous, catalog));
+        }
+        candidate_services.extend(layer_services(&layer, &candidate_catalog));
+
+        let path = layer_manager.layer_path(&layer.layer_id);
+        candidate_manager.upsert_layer(layer, &path, &candidate_catalog)?;
+    }
+
+    let mut services: BTreeMap<String, ServicePreview> = BTreeMap::new();
+
+    for service in &candidate_services {
+        let before = coverage(service, layer_manager, catalog);
+        let after = coverage(service, &candidate_manager, &candidate_catalog);
+
+        let vids: BTreeSet<i64> = before.keys().chain(after.keys()).copied().collect();
+        let changed: BTreeMap<i64, CoverageChange> = vids
+            .into_iter()
+            .filter_map(|vid| {
+                let change = CoverageChange {
+                    before: before.get(&vid).copied().unwrap_or(0.0),
+                    after: after.get(&vid).copied().unwrap_or(0.0),
+                };
+                (change.before != change.after).then_some((vid, change))
+            })
+            .collect();
+
+        services.entry(service.clone()).or_default().coverage = changed;
+    }
+
+    // Parameter diff over a deterministic sample of subjects
+    let live = layer_manager.snapshot();
+    let candidate = candidate_manager.snapshot();
+    let seed = request.seed.unwrap_or(DEFAULT_SEED);
+    let mut rng = seeded_rng(seed);
+    let mut eval_request = ExperimentRequest {
+        services: candidate_services.iter().cloned().collect(),
+        context: request.context.clone(),
+        layers: vec![],
+        diagnostics: false,
+    };
+
+    for _ in 0..request.sample_size {
+        let key = random_subject_key(&mut rng, "subject");
+        eval_request
+            .context
+            .insert(request.hash_key.clone(), Value::String(key));
+
+        let before = merge_layers_batch(&eval_request, &live, field_types)?;
+        let mut after = merge_layers_batch(&eval_request, &candidate, field_types)?;
+
+        for (service, result) in before.results {
+            let Some(candidate) = after.results.remove(&service) else {
+                continue;
+            };
+
+            let mut old_params = BTreeMap::new();
+            let mut new_params = BTreeMap::new();
+            flatten_params("", &result.parameters, &mut old_params);
+            flatten_params("", &candidate.parameters, &mut new_params);
+
+            let keys: BTreeSet<&String> = old_params.keys().chain(new_params.keys()).collect();
+            for key in keys {
+                let old_value = old_params.get(key);
+                let new_value = new_params.get(key);
+                if old_value == new_value {
+                    continue;
+                }
+
+                let change = services
+                    .entry(service.clone())
+                    .or_default()
+                    .parameters
+                    .entry(key.clone())
+                    .or_insert_with(|| ParameterChange {
+                        changed: 0,
+                        example_before: old_value.cloned(),
+                        example_after: new_value.cloned(),
+                    });
+                change.changed += 1;
+            }
+        }
+    }
+
+    let affected_services = services
+        .iter()
+        .filter(|(_, p)| !p.coverage.is_empty() || !p.parameters.is_empty())
+        .map(|(s, _)| s.clone())
+        .collect();
+
+    Ok(PreviewResponse {
+        affected_services,
+        seed,
+        sample_size: request.sample_size,
+        services,
+    })
+}
+
+/// Services a layer's vids belong to
+fn layer_services(layer: &Layer, catalog: &ExperimentCatalog) -> BTreeSet<String> {
+    layer
+        .ranges
+        .iter()
+        .filter_map(|r| catalog.variant_owner(r.vid))
+        .map(|(_, service)| service.to_string())
+        .collect()
+}
+
+/// vid -> percent of buckets assigned to it for a service (rules not applied)
+fn coverage(service: &str, layer_manager: &LayerManager, catalog: &ExperimentCatalog) -> BTreeMap<i64, f64> {
+    let mut percents: BTreeMap<i64, f64> = BTreeMap::new();
+
+    for layer in layer_manager.get_layers_for_service(service).iter() {
+        for range in &layer.ranges {
+            match catalog.variant_owner(range.vid) {
+                Some((_, s)) if s == service => {
+                    *percents.entry(range.vid).or_default() +=
+                        (range.end - range.start) as f64 * 100.0 / layer.bucket_size() as f64;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    percents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::VariantDef;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_preview_layer_change() {
+        let temp_dir = TempDir::new().unwrap();
+        let layers_dir = temp_dir.path().join("layers");
+        let experiments_dir = temp_dir.path().join("experiments");
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+
+        let exp = ExperimentDef {
+            eid: 100,
+            service: "svc".to_string(),
+            expires_at: None,
+            state: Default::default(),
+            caps: None,
+            rule: None,
+            exposure_sampling: None,
+            variants: vec![
+                VariantDef {
+                    vid: 1001,
+                    params: json!({"ranker": {"model": "a"}}),
+                    dark_params: None,
+                },
+                VariantDef {
+                    vid: 1002,
+                    params: json!({"ranker": {"model": "b"}}),
+                    dark_params: None,
+                },
+            ],
+        };
+        std::fs::write(
+            experiments_dir.join("100.json"),
+            serde_json::to_string_pretty(&exp).unwrap(),
+        )
+        .unwrap();
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+
+        let live = json!({
+            "layer_id": "preview_layer",
+            "version": "v1",
+            "priority": 100,
+            "hash_key": "user_id",
+            "enabled": true,
+            "ranges": [
+                {"start": 0, "end": 5000, "vid": 1001},
+                {"start": 5000, "end": 10000, "vid": 1002}
+            ]
+        });
+        std::fs::write(layers_dir.join("preview_layer.json"), live.to_string()).unwrap();
+        let manager = LayerManager::new(layers_dir);
+        manager.load_all_layers(&catalog).await.unwrap();
+
+        let request = PreviewRequest {
+            layer: Some(json!({
+                "layer_id": "preview_layer",
+                "version": "v1",
+                "priority": 100,
+                "hash_key": "user_id",
+                "enabled": true,
+                "ranges": [
+                    {"start": 0, "end": 8000, "vid": 1001},
+                    {"start": 8000, "end": 10000, "vid": 1002}
+                ]
+            })),
+            experiment: None,
+            context: HashMap::new(),
+            hash_key: "user_id".to_string(),
+            sample_size: 1000,
+            seed: Some(7),
+        };
+
+        let response = preview(&request, &manager, &catalog, &HashMap::new()).unwrap();
+        assert_eq!(response.affected_services, vec!["svc".to_string()]);
+
+        let svc = &response.services["svc"];
+        assert_eq!(svc.coverage[&1001], CoverageChange { before: 50.0, after: 80.0 });
+        assert_eq!(svc.coverage[&1002], CoverageChange { before: 50.0, after: 20.0 });
+
+        // Roughly 30% of subjects move from b to a
+        let change = &svc.parameters["ranker.model"];
+        assert!(change.changed > 200 && change.changed < 400, "{}", change.changed);
+        assert_eq!(change.example_before, Some(json!("b")));
+        assert_eq!(change.example_after, Some(json!("a")));
+
+        // Live config untouched
+        assert_eq!(manager.get_layer("preview_layer").unwrap().ranges[0].end, 5000);
+    }
+
+    #[test]
+    fn test_preview_requires_candidate() {
+        let temp_dir = TempDir::new().unwrap();
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(temp_dir.path().join("none")).unwrap());
+        let manager = LayerManager::new(temp_dir.path().to_path_buf());
+
+        let request: PreviewRequest = serde_json::from_value(json!({})).unwrap();
+        assert!(preview(&request, &manager, &catalog, &HashMap::new()).is_err());
+    }
+}
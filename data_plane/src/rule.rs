@@ -1,6 +1,18 @@
+use crate::clock::Clock;
+use crate::collation::Collation;
 use crate::error::{ExperimentError, Result};
+use lazy_static::lazy_static;
+use lru::LruCache;
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, OnceLock};
+
+pub mod compiled;
+pub mod dsl;
+pub mod semver;
 
 /// Field type information from control plane
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -11,6 +23,26 @@ pub enum FieldType {
     Float,
     Bool,
     SemVer,
+    /// IPv4 or IPv6 address string; IPv4-mapped IPv6 compares as IPv4
+    IpAddr,
+    /// RFC 3339 string or unix seconds; rule values may also be relative to
+    /// the evaluation clock (`"now"`, `"now-7d"`, `"now+12h"`)
+    Timestamp,
+    /// JSON array of strings (`["pro", "beta"]`), matched with the list operators
+    StringList,
+    /// JSON array of integers, matched with the list operators
+    IntList,
+}
+
+impl FieldType {
+    /// Type of the elements of a list type
+    fn element_type(&self) -> Option<FieldType> {
+        match self {
+            FieldType::StringList => Some(FieldType::String),
+            FieldType::IntList => Some(FieldType::Int),
+            _ => None,
+        }
+    }
 }
 
 /// Operator for rule evaluation
@@ -24,10 +56,30 @@ pub enum Op {
     Gte,
     Lt,
     Lte,
+
+    // Range operators: `[low, high]`, bounds inclusive; `not_between` is the
+    // exact complement (outside the range, bounds excluded)
+    Between,
+    NotBetween,
+
+    // Network operators: address within any of the CIDR blocks (`"10.0.0.0/8"`;
+    // a bare address is a single-host block)
+    InCidr,
+    NotInCidr,
+
+    // Presence operators: whether the context carries a non-null value; no values
+    Exists,
+    NotExists,
     
     // Set operators
     In,
     NotIn,
+
+    // List operators, for list fields: any element of the field is in the
+    // values / the field contains every value / no element is in the values
+    AnyIn,
+    AllIn,
+    NoneIn,
     
     // String operators
     Like,
@@ -40,7 +92,7 @@ pub enum Op {
 }
 
 /// Rule node for building expression trees
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Node {
     /// Boolean combination node
@@ -59,9 +111,46 @@ pub enum Node {
         field: String,
         op: Op,
         values: Vec<serde_json::Value>,
+        /// How string values compare; byte-wise when absent (see [`crate::collation`])
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        collation: Option<Collation>,
+    },
+
+    /// CEL expression over the context; needs the `cel` feature, without it
+    /// the rule fails to load
+    Cel {
+        expr: String,
+        /// Set by [`Node::compile_expressions`] at load and kept by clones
+        #[serde(skip)]
+        program: CelProgram,
     },
 }
 
+#[cfg(feature = "cel")]
+type Program = Arc<crate::cel::Program>;
+#[cfg(not(feature = "cel"))]
+type Program = std::convert::Infallible;
+
+/// Compiled program of a CEL node, so evaluation doesn't go through the
+/// process-wide program cache and its lock
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(not(feature = "cel"), allow(dead_code))]
+pub struct CelProgram(OnceLock<Program>);
+
+/// The program is derived from the expression, so nodes compare by source
+impl PartialEq for CelProgram {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Node {
+    /// CEL node for `expr`, compiled on first use
+    pub fn cel(expr: impl Into<String>) -> Self {
+        Node::Cel { expr: expr.into(), program: CelProgram::default() }
+    }
+}
+
 impl Node {
     /// Validate node structure against field type map
     #[allow(dead_code)]
@@ -90,7 +179,16 @@ impl Node {
             Node::Not { child } => {
                 child.validate(field_types)?;
             }
-            Node::Field { field, op, values } => {
+            Node::Cel { expr, program } => {
+                compile_cel(expr, program)?;
+            }
+            Node::Field { field, op, values, collation } => {
+                if field.split('.').any(str::is_empty) {
+                    return Err(ExperimentError::InvalidRule(
+                        format!("Field '{}' has an empty path segment", field)
+                    ));
+                }
+
                 // Check field exists
                 let field_type = field_types
                     .get(field)
@@ -98,6 +196,15 @@ impl Node {
                         format!("Field '{}' not found in field type map", field)
                     ))?;
                 
+                if matches!(op, Op::Exists | Op::NotExists) {
+                    if !values.is_empty() {
+                        return Err(ExperimentError::InvalidRule(
+                            format!("Field '{}' operator {:?} takes no values", field, op)
+                        ));
+                    }
+                    return Ok(());
+                }
+
                 // Check values not empty
                 if values.is_empty() {
                     return Err(ExperimentError::InvalidRule(
@@ -116,24 +223,122 @@ impl Node {
                 }
                 
                 // Validate value types match field type
-                for value in values {
-                    validate_value_type(value, field_type, field)?;
+                if matches!(op, Op::AnyIn | Op::AllIn | Op::NoneIn) {
+                    let element_type = field_type.element_type().ok_or_else(|| ExperimentError::InvalidRule(
+                        format!("Field '{}' operator {:?} requires a string_list or int_list field", field, op)
+                    ))?;
+                    for value in values {
+                        validate_value_type(value, &element_type, field)?;
+                    }
+                } else if field_type.element_type().is_some() {
+                    return Err(ExperimentError::InvalidRule(
+                        format!("Field '{}' of type {:?} only supports any_in, all_in, none_in, exists and not_exists", field, field_type)
+                    ));
+                } else if matches!(op, Op::InCidr | Op::NotInCidr) {
+                    if *field_type != FieldType::IpAddr {
+                        return Err(ExperimentError::InvalidRule(
+                            format!("Field '{}' operator {:?} requires an ip_addr field", field, op)
+                        ));
+                    }
+                    for value in values {
+                        Cidr::from_value(value).map_err(|e| ExperimentError::InvalidRule(
+                            format!("Field '{}' {}", field, e)
+                        ))?;
+                    }
+                } else {
+                    for value in values {
+                        validate_value_type(value, field_type, field)?;
+                    }
+                }
+
+                if collation.is_some() && *field_type != FieldType::String {
+                    return Err(ExperimentError::InvalidRule(
+                        format!("Field '{}' collation requires a string field", field)
+                    ));
+                }
+
+                if matches!(op, Op::Between | Op::NotBetween) {
+                    if !matches!(field_type, FieldType::Int | FieldType::Float | FieldType::SemVer | FieldType::Timestamp) {
+                        return Err(ExperimentError::InvalidRule(
+                            format!("Field '{}' operator {:?} requires an int, float, semver or timestamp field", field, op)
+                        ));
+                    }
+                    if values.len() != 2 {
+                        return Err(ExperimentError::InvalidRule(
+                            format!("Field '{}' operator {:?} requires exactly two values", field, op)
+                        ));
+                    }
+                    if compare_values(&values[0], &values[1], field_type, Clock::System.now_millis())? == std::cmp::Ordering::Greater {
+                        return Err(ExperimentError::InvalidRule(
+                            format!("Field '{}' range lower bound {} exceeds upper bound {}", field, values[0], values[1])
+                        ));
+                    }
                 }
             }
         }
         Ok(())
     }
     
-    /// Evaluate node against context
+    /// Compile every CEL expression in the tree, so syntax errors (or CEL
+    /// rules in a build without the `cel` feature) reject the config at load
+    pub fn compile_expressions(&self) -> Result<()> {
+        match self {
+            Node::And { children } | Node::Or { children } => children.iter().try_for_each(Node::compile_expressions),
+            Node::Not { child } => child.compile_expressions(),
+            Node::Cel { expr, program } => compile_cel(expr, program).map(|_| ()),
+            Node::Field { .. } => Ok(()),
+        }
+    }
+
+    /// Check that values compared with fields `policy` declares semver-typed
+    /// are versions within its segment limit, so a bad value rejects the
+    /// config when applied
+    pub fn check_versions(&self, policy: &semver::SemverPolicy) -> Result<()> {
+        match self {
+            Node::And { children } | Node::Or { children } => {
+                children.iter().try_for_each(|child| child.check_versions(policy))
+            }
+            Node::Not { child } => child.check_versions(policy),
+            Node::Cel { .. } => Ok(()),
+            Node::Field { field, op, values, .. } => {
+                let compared = matches!(
+                    op,
+                    Op::Eq | Op::Neq | Op::Gt | Op::Gte | Op::Lt | Op::Lte | Op::Between | Op::NotBetween | Op::In | Op::NotIn
+                );
+                if !compared || !policy.is_declared(field) {
+                    return Ok(());
+                }
+                values.iter().try_for_each(|value| match value {
+                    serde_json::Value::String(version) => policy.check(version).map_err(|e| {
+                        ExperimentError::InvalidRule(format!("Field '{}' value {}", field, e))
+                    }),
+                    _ => validate_value_type(value, &FieldType::SemVer, field),
+                })
+            }
+        }
+    }
+
+    /// Evaluate node against context, at the current wall-clock time
     pub fn evaluate(
         &self,
         ctx: &HashMap<String, serde_json::Value>,
         field_types: &HashMap<String, FieldType>,
+    ) -> Result<bool> {
+        self.evaluate_at(ctx, field_types, Clock::System.now_millis())
+    }
+
+    /// Evaluate node against context, with relative timestamps resolved
+    /// against `now` (unix milliseconds)
+    pub fn evaluate_at(
+        &self,
+        ctx: &HashMap<String, serde_json::Value>,
+        field_types: &HashMap<String, FieldType>,
+        now: i64,
     ) -> Result<bool> {
         match self {
             Node::And { children } => {
                 for child in children {
-                    if !child.evaluate(ctx, field_types)? {
+                    if !child.evaluate_at(ctx, field_types, now)? {
                         return Ok(false);
                     }
                 }
@@ -141,23 +346,30 @@ impl Node {
             }
             Node::Or { children } => {
                 for child in children {
-                    if child.evaluate(ctx, field_types)? {
+                    if child.evaluate_at(ctx, field_types, now)? {
                         return Ok(true);
                     }
                 }
                 Ok(false)
             }
             Node::Not { child } => {
-                let result = child.evaluate(ctx, field_types)?;
+                let result = child.evaluate_at(ctx, field_types, now)?;
                 Ok(!result)
             }
-            Node::Field { field, op, values } => {
-                // Get field value from context
-                let field_value = ctx
-                    .get(field)
-                    .ok_or_else(|| ExperimentError::InvalidRule(
-                        format!("Field '{}' not found in context", field)
-                    ))?;
+            Node::Cel { expr, program } => evaluate_cel(expr, program, ctx),
+            Node::Field { field, op, values, collation } => {
+                // Get field value from context; null counts as absent
+                let field_value = resolve_field(ctx, field).filter(|v| !v.is_null());
+                match op {
+                    Op::Exists => return Ok(field_value.is_some()),
+                    Op::NotExists => return Ok(field_value.is_none()),
+                    _ => {}
+                }
+                // Any comparison on an absent field is false, negated operators
+                // included; `not` / `not_exists` select subjects without it
+                let Some(field_value) = field_value else {
+                    return Ok(false);
+                };
                 
                 // Get field type
                 let field_type = field_types
@@ -166,13 +378,65 @@ impl Node {
                         format!("Field '{}' not found in field type map", field)
                     ))?;
                 
-                // Evaluate based on operator
-                evaluate_field_op(field_value, op, values, field_type)
+                // Evaluate based on operator, on collation keys when collated
+                match collation {
+                    Some(collation) if *field_type == FieldType::String => {
+                        let values: Vec<serde_json::Value> =
+                            values.iter().map(|v| collation.apply(v).into_owned()).collect();
+                        evaluate_field_op(&collation.apply(field_value), op, &values, field_type, now)
+                    }
+                    _ => evaluate_field_op(field_value, op, values, field_type, now),
+                }
             }
         }
     }
 }
 
+#[cfg(feature = "cel")]
+fn compile_cel<'a>(expr: &str, program: &'a CelProgram) -> Result<&'a crate::cel::Program> {
+    if let Some(compiled) = program.0.get() {
+        return Ok(compiled);
+    }
+    // Rules sharing an expression share the program
+    let compiled = crate::cel::compile(expr)?;
+    Ok(program.0.get_or_init(|| compiled))
+}
+
+#[cfg(not(feature = "cel"))]
+fn compile_cel<'a>(expr: &str, _program: &'a CelProgram) -> Result<&'a Program> {
+    Err(ExperimentError::InvalidRule(format!(
+        "CEL rule '{}' requires a data plane built with the `cel` feature",
+        expr
+    )))
+}
+
+/// Nodes loaded from config were compiled at load; only nodes built in code
+/// compile here, once
+#[cfg(feature = "cel")]
+fn evaluate_cel(expr: &str, program: &CelProgram, ctx: &HashMap<String, serde_json::Value>) -> Result<bool> {
+    compile_cel(expr, program)?.evaluate(ctx)
+}
+
+#[cfg(not(feature = "cel"))]
+fn evaluate_cel(expr: &str, program: &CelProgram, _ctx: &HashMap<String, serde_json::Value>) -> Result<bool> {
+    compile_cel(expr, program).map(|_| false)
+}
+
+/// Value of `field` in the context. A dotted name (`device.os.version`) that
+/// isn't itself a context key is a path: its first segment names the context
+/// key, the rest walk object members and array indices.
+fn resolve_field<'a>(ctx: &'a HashMap<String, serde_json::Value>, field: &str) -> Option<&'a serde_json::Value> {
+    if let Some(value) = ctx.get(field) {
+        return Some(value);
+    }
+    let (root, path) = field.split_once('.')?;
+    path.split('.').try_fold(ctx.get(root)?, |value, segment| match value {
+        serde_json::Value::Object(members) => members.get(segment),
+        serde_json::Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
 /// Validate that a value matches the expected field type
 #[allow(dead_code)]
 fn validate_value_type(value: &serde_json::Value, field_type: &FieldType, field_name: &str) -> Result<()> {
@@ -183,16 +447,23 @@ fn validate_value_type(value: &serde_json::Value, field_type: &FieldType, field_
         (FieldType::Int, Value::Number(n)) if n.is_i64() => Ok(()),
         (FieldType::Float, Value::Number(_)) => Ok(()),
         (FieldType::Bool, Value::Bool(_)) => Ok(()),
-        (FieldType::SemVer, Value::String(s)) => {
-            // Basic semver validation
-            if s.split('.').count() >= 2 {
-                Ok(())
-            } else {
-                Err(ExperimentError::InvalidRule(
-                    format!("Field '{}' value '{}' is not a valid semver", field_name, s)
-                ))
+        (FieldType::Timestamp, Value::Number(_) | Value::String(_)) => {
+            match parse_timestamp(value, Clock::System.now_millis()) {
+                Some(_) => Ok(()),
+                None => Err(ExperimentError::InvalidRule(
+                    format!("Field '{}' value {} is not an RFC 3339 timestamp, unix seconds or now offset", field_name, value)
+                )),
             }
         }
+        (FieldType::IpAddr, Value::String(s)) => match parse_ip(s) {
+            Some(_) => Ok(()),
+            None => Err(ExperimentError::InvalidRule(
+                format!("Field '{}' value '{}' is not a valid IP address", field_name, s)
+            )),
+        },
+        (FieldType::SemVer, Value::String(s)) => semver::Version::parse(s).map(|_| ()).map_err(|e| {
+            ExperimentError::InvalidRule(format!("Field '{}' value {}", field_name, e))
+        }),
         _ => Err(ExperimentError::InvalidRule(
             format!("Field '{}' value {:?} does not match type {:?}", field_name, value, field_type)
         )),
@@ -205,6 +476,7 @@ fn evaluate_field_op(
     op: &Op,
     values: &[serde_json::Value],
     field_type: &FieldType,
+    now: i64,
 ) -> Result<bool> {
     use serde_json::Value;
     
@@ -215,7 +487,7 @@ fn evaluate_field_op(
                     "Eq operator requires exactly one value".to_string()
                 ));
             }
-            Ok(compare_values(field_value, &values[0], field_type)? == std::cmp::Ordering::Equal)
+            Ok(compare_values(field_value, &values[0], field_type, now)? == std::cmp::Ordering::Equal)
         }
         Op::Neq => {
             if values.len() != 1 {
@@ -223,7 +495,7 @@ fn evaluate_field_op(
                     "Neq operator requires exactly one value".to_string()
                 ));
             }
-            Ok(compare_values(field_value, &values[0], field_type)? != std::cmp::Ordering::Equal)
+            Ok(compare_values(field_value, &values[0], field_type, now)? != std::cmp::Ordering::Equal)
         }
         Op::Gt => {
             if values.len() != 1 {
@@ -231,7 +503,7 @@ fn evaluate_field_op(
                     "Gt operator requires exactly one value".to_string()
                 ));
             }
-            Ok(compare_values(field_value, &values[0], field_type)? == std::cmp::Ordering::Greater)
+            Ok(compare_values(field_value, &values[0], field_type, now)? == std::cmp::Ordering::Greater)
         }
         Op::Gte => {
             if values.len() != 1 {
@@ -239,7 +511,7 @@ fn evaluate_field_op(
                     "Gte operator requires exactly one value".to_string()
                 ));
             }
-            let cmp = compare_values(field_value, &values[0], field_type)?;
+            let cmp = compare_values(field_value, &values[0], field_type, now)?;
             Ok(cmp == std::cmp::Ordering::Greater || cmp == std::cmp::Ordering::Equal)
         }
         Op::Lt => {
@@ -248,7 +520,7 @@ fn evaluate_field_op(
                     "Lt operator requires exactly one value".to_string()
                 ));
             }
-            Ok(compare_values(field_value, &values[0], field_type)? == std::cmp::Ordering::Less)
+            Ok(compare_values(field_value, &values[0], field_type, now)? == std::cmp::Ordering::Less)
         }
         Op::Lte => {
             if values.len() != 1 {
@@ -256,12 +528,27 @@ fn evaluate_field_op(
                     "Lte operator requires exactly one value".to_string()
                 ));
             }
-            let cmp = compare_values(field_value, &values[0], field_type)?;
+            let cmp = compare_values(field_value, &values[0], field_type, now)?;
             Ok(cmp == std::cmp::Ordering::Less || cmp == std::cmp::Ordering::Equal)
         }
+        Op::Between | Op::NotBetween => {
+            if values.len() != 2 {
+                return Err(ExperimentError::InvalidRule(
+                    format!("{:?} operator requires exactly two values", op)
+                ));
+            }
+            if !matches!(field_type, FieldType::Int | FieldType::Float | FieldType::SemVer | FieldType::Timestamp) {
+                return Err(ExperimentError::InvalidRule(
+                    format!("{:?} operator requires an int, float, semver or timestamp field", op)
+                ));
+            }
+            let within = compare_values(field_value, &values[0], field_type, now)? != std::cmp::Ordering::Less
+                && compare_values(field_value, &values[1], field_type, now)? != std::cmp::Ordering::Greater;
+            Ok(within == (*op == Op::Between))
+        }
         Op::In => {
             for value in values {
-                if compare_values(field_value, value, field_type)? == std::cmp::Ordering::Equal {
+                if compare_values(field_value, value, field_type, now)? == std::cmp::Ordering::Equal {
                     return Ok(true);
                 }
             }
@@ -269,7 +556,7 @@ fn evaluate_field_op(
         }
         Op::NotIn => {
             for value in values {
-                if compare_values(field_value, value, field_type)? == std::cmp::Ordering::Equal {
+                if compare_values(field_value, value, field_type, now)? == std::cmp::Ordering::Equal {
                     return Ok(false);
                 }
             }
@@ -306,6 +593,55 @@ fn evaluate_field_op(
                 )),
             }
         }
+        Op::InCidr | Op::NotInCidr => {
+            let addr = field_value.as_str().and_then(parse_ip).ok_or_else(|| ExperimentError::InvalidRule(
+                format!("{:?} operator requires an IP address, got {}", op, field_value)
+            ))?;
+            let mut within = false;
+            for value in values {
+                if Cidr::from_value(value).map_err(ExperimentError::InvalidRule)?.contains(addr) {
+                    within = true;
+                    break;
+                }
+            }
+            Ok(within == (*op == Op::InCidr))
+        }
+        Op::AnyIn | Op::AllIn | Op::NoneIn => {
+            let element_type = field_type.element_type().ok_or_else(|| ExperimentError::InvalidRule(
+                format!("{:?} operator requires a list field", op)
+            ))?;
+            let items = field_value.as_array().ok_or_else(|| ExperimentError::InvalidRule(
+                format!("{:?} operator requires a list value, got {}", op, field_value)
+            ))?;
+            let contains = |set: &[Value], wanted: &Value| -> Result<bool> {
+                for member in set {
+                    if compare_values(member, wanted, &element_type, now)? == std::cmp::Ordering::Equal {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            };
+            if *op == Op::AllIn {
+                for value in values {
+                    if !contains(items, value)? {
+                        return Ok(false);
+                    }
+                }
+                return Ok(true);
+            }
+            let mut any = false;
+            for item in items {
+                if contains(values, item)? {
+                    any = true;
+                    break;
+                }
+            }
+            Ok(any == (*op == Op::AnyIn))
+        }
+        Op::Exists | Op::NotExists => {
+            // Presence is decided before a value is looked at
+            Ok(*op == Op::Exists)
+        }
         Op::And | Op::Or | Op::Not => {
             Err(ExperimentError::InvalidRule(
                 format!("Boolean operator {:?} cannot be used in field comparison", op)
@@ -319,6 +655,7 @@ fn compare_values(
     left: &serde_json::Value,
     right: &serde_json::Value,
     field_type: &FieldType,
+    now: i64,
 ) -> Result<std::cmp::Ordering> {
     use serde_json::Value;
     
@@ -371,58 +708,212 @@ fn compare_values(
                 )),
             }
         }
+        FieldType::Timestamp => {
+            match (parse_timestamp(left, now), parse_timestamp(right, now)) {
+                (Some(l), Some(r)) => Ok(l.cmp(&r)),
+                _ => Err(ExperimentError::InvalidRule(
+                    format!("Timestamp comparison requires RFC 3339 strings or unix seconds: {} or {}", left, right)
+                )),
+            }
+        }
+        FieldType::IpAddr => {
+            match (left.as_str().and_then(parse_ip), right.as_str().and_then(parse_ip)) {
+                (Some(l), Some(r)) => Ok(l.cmp(&r)),
+                _ => Err(ExperimentError::InvalidRule(
+                    format!("IP address comparison requires address strings: {} or {}", left, right)
+                )),
+            }
+        }
+        FieldType::StringList | FieldType::IntList => Err(ExperimentError::InvalidRule(
+            "List fields are compared with any_in, all_in or none_in".to_string()
+        )),
     }
 }
 
-/// Compare semantic versions
-fn compare_semver(left: &str, right: &str) -> Result<std::cmp::Ordering> {
-    let left_parts: Vec<u32> = left
-        .split('.')
-        .filter_map(|s| s.parse().ok())
-        .collect();
-    let right_parts: Vec<u32> = right
-        .split('.')
-        .filter_map(|s| s.parse().ok())
-        .collect();
-    
-    if left_parts.is_empty() || right_parts.is_empty() {
-        return Err(ExperimentError::InvalidRule(
-            format!("Invalid semver format: {} or {}", left, right)
-        ));
+/// Unix milliseconds of an RFC 3339 string, unix seconds or a `now[+-]<n><unit>`
+/// offset from `now` (units `s`, `m`, `h`, `d`, `w`)
+fn parse_timestamp(value: &serde_json::Value, now: i64) -> Option<i64> {
+    match value {
+        serde_json::Value::Number(n) => n
+            .as_i64()
+            .and_then(|secs| secs.checked_mul(1000))
+            .or_else(|| n.as_f64().filter(|secs| secs.is_finite()).map(|secs| (secs * 1000.0) as i64)),
+        serde_json::Value::String(s) => {
+            let s = s.trim();
+            let Some(offset) = s.strip_prefix("now") else {
+                return chrono::DateTime::parse_from_rfc3339(s).ok().map(|t| t.timestamp_millis());
+            };
+            if offset.is_empty() {
+                return Some(now);
+            }
+            let (sign, amount) = match offset.split_at(1) {
+                ("+", amount) => (1, amount),
+                ("-", amount) => (-1, amount),
+                _ => return None,
+            };
+            let unit_millis = match amount.chars().last()? {
+                's' => 1_000,
+                'm' => 60_000,
+                'h' => 3_600_000,
+                'd' => 86_400_000,
+                'w' => 604_800_000,
+                _ => return None,
+            };
+            let count: i64 = amount[..amount.len() - 1].parse().ok()?;
+            now.checked_add(sign * count.checked_mul(unit_millis)?)
+        }
+        _ => None,
     }
-    
-    Ok(left_parts.cmp(&right_parts))
 }
 
-/// Simple pattern matching with * wildcard
-fn simple_pattern_match(text: &str, pattern: &str) -> bool {
-    if pattern == "*" {
-        return true;
+/// Address in canonical form, so `::ffff:10.0.0.1` and `10.0.0.1` are the same
+fn parse_ip(text: &str) -> Option<IpAddr> {
+    text.trim().parse::<IpAddr>().ok().map(|addr| addr.to_canonical())
+}
+
+/// CIDR block of `InCidr` / `NotInCidr`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cidr {
+    network: IpAddr,
+    prefix_len: u32,
+}
+
+impl Cidr {
+    fn from_value(value: &serde_json::Value) -> std::result::Result<Self, String> {
+        let text = value
+            .as_str()
+            .ok_or_else(|| format!("CIDR block {} must be a string", value))?;
+        let (addr, prefix_len) = match text.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (text, None),
+        };
+        let network = parse_ip(addr).ok_or_else(|| format!("CIDR block '{}' has an invalid address", text))?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            // An IPv4-mapped block keeps its IPv4 part of the prefix
+            Some(len) => match (len.parse::<u32>(), addr.trim().parse::<IpAddr>()) {
+                (Ok(len), Ok(IpAddr::V6(_))) if network.is_ipv4() && (96..=128).contains(&len) => len - 96,
+                (Ok(len), _) if len <= max_len => len,
+                _ => return Err(format!("CIDR block '{}' has an invalid prefix length", text)),
+            },
+            None => max_len,
+        };
+        let cidr = Self { network, prefix_len };
+        if cidr.masked(network) != Some(network) {
+            return Err(format!("CIDR block '{}' has host bits set", text));
+        }
+        Ok(cidr)
     }
-    
-    if !pattern.contains('*') {
-        return text == pattern;
+
+    /// `addr` with the bits after the prefix cleared, if it is of the block's family
+    fn masked(&self, addr: IpAddr) -> Option<IpAddr> {
+        match addr {
+            IpAddr::V4(v4) if self.network.is_ipv4() => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len).unwrap_or(0);
+                Some(IpAddr::V4((u32::from(v4) & mask).into()))
+            }
+            IpAddr::V6(v6) if self.network.is_ipv6() => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len).unwrap_or(0);
+                Some(IpAddr::V6((u128::from(v6) & mask).into()))
+            }
+            _ => None,
+        }
     }
-    
-    let parts: Vec<&str> = pattern.split('*').collect();
-    
-    // Pattern like "*suffix"
-    if pattern.starts_with('*') && parts.len() == 2 {
-        return text.ends_with(parts[1]);
+
+    fn contains(&self, addr: IpAddr) -> bool {
+        let network = self.masked(self.network);
+        network.is_some() && self.masked(addr.to_canonical()) == network
     }
-    
-    // Pattern like "prefix*"
-    if pattern.ends_with('*') && parts.len() == 2 {
-        return text.starts_with(parts[0]);
+}
+
+/// Compare semantic versions
+fn compare_semver(left: &str, right: &str) -> Result<std::cmp::Ordering> {
+    let left = semver::Version::parse(left).map_err(ExperimentError::InvalidRule)?;
+    let right = semver::Version::parse(right).map_err(ExperimentError::InvalidRule)?;
+    Ok(left.cmp(&right))
+}
+
+/// Compiled `Like` patterns kept across requests (and across rules sharing a pattern)
+const PATTERN_CACHE_CAPACITY: usize = 1024;
+
+lazy_static! {
+    static ref PATTERN_CACHE: Mutex<LruCache<String, Arc<LikePattern>>> = Mutex::new(LruCache::new(
+        NonZeroUsize::new(PATTERN_CACHE_CAPACITY).unwrap_or(NonZeroUsize::MIN)
+    ));
+}
+
+/// `Like` pattern split once into the literal pieces around its `*` wildcards
+#[derive(Debug, PartialEq)]
+struct LikePattern {
+    /// Literal pieces between wildcards, in order (empty strings dropped)
+    pieces: Vec<String>,
+    anchored_start: bool,
+    anchored_end: bool,
+}
+
+impl LikePattern {
+    fn compile(pattern: &str) -> Self {
+        Self {
+            pieces: pattern.split('*').filter(|p| !p.is_empty()).map(str::to_string).collect(),
+            anchored_start: !pattern.starts_with('*'),
+            anchored_end: !pattern.ends_with('*'),
+        }
     }
-    
-    // Pattern like "prefix*suffix"
-    if parts.len() == 2 {
-        return text.starts_with(parts[0]) && text.ends_with(parts[1]);
+
+    fn matches(&self, text: &str) -> bool {
+        let mut rest = text;
+        let mut pieces = self.pieces.as_slice();
+
+        if self.anchored_start && self.anchored_end && pieces.len() <= 1 {
+            // No wildcard at all
+            return rest == pieces.first().map_or("", String::as_str);
+        }
+        if self.anchored_start {
+            let Some((first, tail)) = pieces.split_first() else {
+                return true;
+            };
+            let Some(stripped) = rest.strip_prefix(first.as_str()) else {
+                return false;
+            };
+            rest = stripped;
+            pieces = tail;
+        }
+        if self.anchored_end {
+            let Some((last, init)) = pieces.split_last() else {
+                return true;
+            };
+            let Some(stripped) = rest.strip_suffix(last.as_str()) else {
+                return false;
+            };
+            rest = stripped;
+            pieces = init;
+        }
+
+        // Middle pieces: leftmost match of each, in order
+        for piece in pieces {
+            match rest.find(piece.as_str()) {
+                Some(at) => rest = &rest[at + piece.len()..],
+                None => return false,
+            }
+        }
+        true
     }
-    
-    // More complex patterns - fall back to simple check
-    text.contains(parts[0])
+}
+
+/// Compiled form of `pattern`, shared through [`PATTERN_CACHE`]
+fn compiled_pattern(pattern: &str) -> Arc<LikePattern> {
+    if let Some(compiled) = PATTERN_CACHE.lock().get(pattern) {
+        return compiled.clone();
+    }
+    // Compile outside the lock; a concurrent miss on the same pattern just compiles twice
+    let compiled = Arc::new(LikePattern::compile(pattern));
+    PATTERN_CACHE.lock().put(pattern.to_string(), compiled.clone());
+    compiled
+}
+
+/// Pattern matching with `*` wildcards (any number, anywhere in the pattern)
+fn simple_pattern_match(text: &str, pattern: &str) -> bool {
+    compiled_pattern(pattern).matches(text)
 }
 
 #[cfg(test)]
@@ -438,6 +929,7 @@ mod tests {
             ("balance".to_string(), FieldType::Float),
             ("premium".to_string(), FieldType::Bool),
             ("app_version".to_string(), FieldType::SemVer),
+            ("client_ip".to_string(), FieldType::IpAddr),
         ]
         .into_iter()
         .collect()
@@ -453,11 +945,13 @@ mod tests {
                     field: "country".to_string(),
                     op: Op::Eq,
                     values: vec![json!("US")],
+                    collation: None,
                 },
                 Node::Field {
                     field: "age".to_string(),
                     op: Op::Gte,
                     values: vec![json!(18)],
+                    collation: None,
                 },
             ],
         };
@@ -473,6 +967,7 @@ mod tests {
             field: "unknown_field".to_string(),
             op: Op::Eq,
             values: vec![json!("value")],
+            collation: None,
         };
         
         assert!(node.validate(&field_types).is_err());
@@ -486,6 +981,7 @@ mod tests {
             field: "country".to_string(),
             op: Op::Eq,
             values: vec![],
+            collation: None,
         };
         
         assert!(node.validate(&field_types).is_err());
@@ -499,6 +995,7 @@ mod tests {
             field: "age".to_string(),
             op: Op::Eq,
             values: vec![json!("not_a_number")],
+            collation: None,
         };
         
         assert!(node.validate(&field_types).is_err());
@@ -517,9 +1014,10 @@ mod tests {
             field: "country".to_string(),
             op: Op::Eq,
             values: vec![json!("US")],
+            collation: None,
         };
         
-        assert_eq!(node.evaluate(&ctx, &field_types).unwrap(), true);
+        assert!(node.evaluate(&ctx, &field_types).unwrap());
     }
     
     #[test]
@@ -535,9 +1033,10 @@ mod tests {
             field: "country".to_string(),
             op: Op::Neq,
             values: vec![json!("US")],
+            collation: None,
         };
         
-        assert_eq!(node.evaluate(&ctx, &field_types).unwrap(), true);
+        assert!(node.evaluate(&ctx, &field_types).unwrap());
     }
     
     #[test]
@@ -553,11 +1052,245 @@ mod tests {
             field: "age".to_string(),
             op: Op::Gte,
             values: vec![json!(18)],
+            collation: None,
         };
         
-        assert_eq!(node.evaluate(&ctx, &field_types).unwrap(), true);
+        assert!(node.evaluate(&ctx, &field_types).unwrap());
     }
     
+    #[test]
+    fn test_evaluate_between() {
+        let field_types = setup_field_types();
+        let between = |field: &str, op: Op, low: serde_json::Value, high: serde_json::Value| Node::Field {
+            field: field.to_string(),
+            op,
+            values: vec![low, high],
+            collation: None,
+        };
+        let age = between("age", Op::Between, json!(18), json!(25));
+        let outside = between("age", Op::NotBetween, json!(18), json!(25));
+
+        // Bounds are inclusive for between, excluded by not_between
+        for (value, within) in [(17, false), (18, true), (21, true), (25, true), (26, false)] {
+            let ctx = [("age".to_string(), json!(value))].into_iter().collect();
+            assert_eq!(age.evaluate(&ctx, &field_types).unwrap(), within, "{}", value);
+            assert_eq!(outside.evaluate(&ctx, &field_types).unwrap(), !within, "{}", value);
+        }
+
+        let version = between("app_version", Op::Between, json!("2.9"), json!("3.1.0"));
+        let ctx = [("app_version".to_string(), json!("3.0.5"))].into_iter().collect();
+        assert!(version.evaluate(&ctx, &field_types).unwrap());
+        assert!(version.validate(&field_types).is_ok());
+
+        assert!(between("age", Op::Between, json!(25), json!(18)).validate(&field_types).is_err());
+        assert!(between("country", Op::Between, json!("A"), json!("M")).validate(&field_types).is_err());
+        let one_bound = Node::Field { field: "age".to_string(), op: Op::Between, values: vec![json!(18)], collation: None };
+        assert!(one_bound.validate(&field_types).is_err());
+    }
+
+    #[test]
+    fn test_exists_and_missing_fields() {
+        let field_types = setup_field_types();
+        let presence = |op: Op| Node::Field { field: "country".to_string(), op, values: vec![], collation: None };
+        let not_us = Node::Field { field: "country".to_string(), op: Op::Neq, values: vec![json!("US")], collation: None };
+
+        let sent: HashMap<String, serde_json::Value> = [("country".to_string(), json!("CN"))].into_iter().collect();
+        let null: HashMap<String, serde_json::Value> = [("country".to_string(), json!(null))].into_iter().collect();
+        let missing = HashMap::new();
+
+        assert!(presence(Op::Exists).evaluate(&sent, &field_types).unwrap());
+        assert!(not_us.evaluate(&sent, &field_types).unwrap());
+        for ctx in [&null, &missing] {
+            assert!(!presence(Op::Exists).evaluate(ctx, &field_types).unwrap());
+            assert!(presence(Op::NotExists).evaluate(ctx, &field_types).unwrap());
+            // Comparisons on an absent field are false, negated ones too
+            assert!(!not_us.evaluate(ctx, &field_types).unwrap());
+            let negated = Node::Not { child: Box::new(not_us.clone()) };
+            assert!(negated.evaluate(ctx, &field_types).unwrap());
+        }
+
+        assert!(presence(Op::Exists).validate(&field_types).is_ok());
+        let with_value = Node::Field { field: "country".to_string(), op: Op::Exists, values: vec![json!("US")], collation: None };
+        assert!(with_value.validate(&field_types).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_cidr() {
+        let field_types = setup_field_types();
+        let cidr = |op: Op, blocks: &[&str]| Node::Field {
+            field: "client_ip".to_string(),
+            op,
+            values: blocks.iter().map(|b| json!(b)).collect(),
+            collation: None,
+        };
+        let blocks = ["10.20.0.0/16", "2001:db8::/32", "192.168.1.7"];
+        let (office, elsewhere) = (cidr(Op::InCidr, &blocks), cidr(Op::NotInCidr, &blocks));
+        assert!(office.validate(&field_types).is_ok());
+
+        for (ip, inside) in [
+            ("10.20.3.4", true),
+            ("10.21.0.1", false),
+            ("::ffff:10.20.0.9", true),
+            ("2001:db8:1::1", true),
+            ("2001:db9::1", false),
+            ("192.168.1.7", true),
+            ("192.168.1.8", false),
+        ] {
+            let ctx = [("client_ip".to_string(), json!(ip))].into_iter().collect();
+            assert_eq!(office.evaluate(&ctx, &field_types).unwrap(), inside, "{}", ip);
+            assert_eq!(elsewhere.evaluate(&ctx, &field_types).unwrap(), !inside, "{}", ip);
+        }
+
+        let bad_ctx = [("client_ip".to_string(), json!("not-an-ip"))].into_iter().collect();
+        assert!(office.evaluate(&bad_ctx, &field_types).is_err());
+        for block in ["10.20.0.1/16", "10.0.0.0/33", "10.0.0.0/x", "example.com/8"] {
+            assert!(cidr(Op::InCidr, &[block]).validate(&field_types).is_err(), "{}", block);
+        }
+        let on_string = Node::Field { field: "country".to_string(), op: Op::InCidr, values: vec![json!("10.0.0.0/8")], collation: None };
+        assert!(on_string.validate(&field_types).is_err());
+    }
+
+    #[test]
+    fn test_cel_node() {
+        let field_types = setup_field_types();
+        let node: Node = serde_json::from_value(json!({
+            "type": "and",
+            "children": [{"type": "cel", "expr": "country == 'US' && (age >= 18 || premium)"}]
+        }))
+        .unwrap();
+        let ctx: HashMap<_, _> = [("country".to_string(), json!("US")), ("age".to_string(), json!(21))]
+            .into_iter()
+            .collect();
+
+        if cfg!(feature = "cel") {
+            assert!(node.compile_expressions().is_ok());
+            // Compiled once at load; optimized and compiled rules clone it
+            let Node::And { children } = node.clone() else { unreachable!() };
+            assert!(matches!(&children[0], Node::Cel { program, .. } if program.0.get().is_some()));
+            assert!(node.evaluate(&ctx, &field_types).unwrap());
+            let broken = Node::cel("age >=");
+            assert!(broken.compile_expressions().is_err());
+        } else {
+            // Rejected at load, and never matches if it gets past it
+            let err = node.compile_expressions().unwrap_err().to_string();
+            assert!(err.contains("`cel` feature"), "{}", err);
+            assert!(node.validate(&field_types).is_err());
+            assert!(node.evaluate(&ctx, &field_types).is_err());
+        }
+    }
+
+    #[test]
+    fn test_evaluate_list_fields() {
+        let mut field_types = setup_field_types();
+        field_types.insert("entitlements".to_string(), FieldType::StringList);
+        field_types.insert("segments".to_string(), FieldType::IntList);
+        let list = |field: &str, op: Op, values: Vec<serde_json::Value>| Node::Field {
+            field: field.to_string(),
+            op,
+            values,
+            collation: None,
+        };
+        let ctx: HashMap<_, _> = [
+            ("entitlements".to_string(), json!(["pro", "beta"])),
+            ("segments".to_string(), json!([]))
+        ].into_iter().collect();
+
+        for (op, values, expected) in [
+            (Op::AnyIn, vec![json!("beta"), json!("enterprise")], true),
+            (Op::AnyIn, vec![json!("enterprise")], false),
+            (Op::AllIn, vec![json!("pro"), json!("beta")], true),
+            (Op::AllIn, vec![json!("pro"), json!("enterprise")], false),
+            (Op::NoneIn, vec![json!("enterprise")], true),
+            (Op::NoneIn, vec![json!("pro")], false),
+        ] {
+            let node = list("entitlements", op.clone(), values);
+            assert!(node.validate(&field_types).is_ok());
+            assert_eq!(node.evaluate(&ctx, &field_types).unwrap(), expected, "{:?}", node);
+        }
+        // An empty list has no element in any set and contains none of the values
+        assert!(!list("segments", Op::AnyIn, vec![json!(7)]).evaluate(&ctx, &field_types).unwrap());
+        assert!(list("segments", Op::NoneIn, vec![json!(7)]).evaluate(&ctx, &field_types).unwrap());
+        assert!(!list("segments", Op::AllIn, vec![json!(7)]).evaluate(&ctx, &field_types).unwrap());
+
+        // Element types are checked, and list fields take only list operators
+        assert!(list("segments", Op::AnyIn, vec![json!("7")]).validate(&field_types).is_err());
+        assert!(list("entitlements", Op::Eq, vec![json!("pro")]).validate(&field_types).is_err());
+        assert!(list("country", Op::AnyIn, vec![json!("US")]).validate(&field_types).is_err());
+        let scalar: HashMap<_, _> = [("entitlements".to_string(), json!("pro"))].into_iter().collect();
+        assert!(list("entitlements", Op::AnyIn, vec![json!("pro")]).evaluate(&scalar, &field_types).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_timestamps() {
+        let mut field_types = setup_field_types();
+        field_types.insert("signup_date".to_string(), FieldType::Timestamp);
+        let since = |value: serde_json::Value| Node::Field {
+            field: "signup_date".to_string(),
+            op: Op::Gte,
+            values: vec![value],
+            collation: None,
+        };
+        // 2024-01-01T00:00:00Z
+        let new_year = 1_704_067_200i64;
+        let ctx = |value: serde_json::Value| -> HashMap<String, serde_json::Value> {
+            [("signup_date".to_string(), value)].into_iter().collect()
+        };
+
+        // RFC 3339 with any offset and unix seconds compare as instants
+        let rule = since(json!("2024-01-01T00:00:00Z"));
+        assert!(rule.validate(&field_types).is_ok());
+        assert!(rule.evaluate(&ctx(json!("2024-01-01T08:00:00+08:00")), &field_types).unwrap());
+        assert!(!rule.evaluate(&ctx(json!("2023-12-31T23:59:59Z")), &field_types).unwrap());
+        assert!(rule.evaluate(&ctx(json!(new_year)), &field_types).unwrap());
+        assert!(!rule.evaluate(&ctx(json!(new_year - 1)), &field_types).unwrap());
+
+        // Relative values follow the evaluation clock
+        let recent = since(json!("now-7d"));
+        let now = (new_year + 10 * 86_400) * 1000;
+        assert!(recent.evaluate_at(&ctx(json!(new_year + 3 * 86_400)), &field_types, now).unwrap());
+        assert!(!recent.evaluate_at(&ctx(json!(new_year + 86_400)), &field_types, now).unwrap());
+
+        assert!(since(json!("2024-13-01")).validate(&field_types).is_err());
+        assert!(since(json!("now-7x")).validate(&field_types).is_err());
+        assert!(rule.evaluate(&ctx(json!("yesterday")), &field_types).is_err());
+    }
+
+    #[test]
+    fn test_dot_path_fields() {
+        let mut field_types = setup_field_types();
+        field_types.insert("device.os.version".to_string(), FieldType::SemVer);
+        field_types.insert("experiments.1".to_string(), FieldType::String);
+        field_types.insert("app.channel".to_string(), FieldType::String);
+        let field = |name: &str, op: Op, value: serde_json::Value| Node::Field {
+            field: name.to_string(),
+            op,
+            values: vec![value],
+            collation: None,
+        };
+        let ctx: HashMap<String, serde_json::Value> = [
+            ("device".to_string(), json!({"os": {"name": "ios", "version": "17.2.1"}})),
+            ("experiments".to_string(), json!(["a", "b"])),
+            // A flat key with dots wins over the path
+            ("app.channel".to_string(), json!("beta")),
+            ("app".to_string(), json!({"channel": "stable"})),
+        ]
+        .into_iter()
+        .collect();
+
+        let modern_ios = field("device.os.version", Op::Gte, json!("17.0"));
+        assert!(modern_ios.validate(&field_types).is_ok());
+        assert!(modern_ios.evaluate(&ctx, &field_types).unwrap());
+        assert!(field("experiments.1", Op::Eq, json!("b")).evaluate(&ctx, &field_types).unwrap());
+        assert!(field("app.channel", Op::Eq, json!("beta")).evaluate(&ctx, &field_types).unwrap());
+
+        // Paths that lead nowhere are missing fields
+        let flat_ctx = [("device".to_string(), json!("pixel"))].into_iter().collect();
+        assert!(!modern_ios.evaluate(&flat_ctx, &field_types).unwrap());
+
+        field_types.insert("device..os".to_string(), FieldType::String);
+        assert!(field("device..os", Op::Eq, json!("ios")).validate(&field_types).is_err());
+    }
+
     #[test]
     fn test_evaluate_in() {
         let field_types = setup_field_types();
@@ -571,9 +1304,10 @@ mod tests {
             field: "country".to_string(),
             op: Op::In,
             values: vec![json!("US"), json!("CA"), json!("UK")],
+            collation: None,
         };
         
-        assert_eq!(node.evaluate(&ctx, &field_types).unwrap(), true);
+        assert!(node.evaluate(&ctx, &field_types).unwrap());
     }
     
     #[test]
@@ -589,9 +1323,10 @@ mod tests {
             field: "country".to_string(),
             op: Op::NotIn,
             values: vec![json!("US"), json!("CA"), json!("UK")],
+            collation: None,
         };
         
-        assert_eq!(node.evaluate(&ctx, &field_types).unwrap(), true);
+        assert!(node.evaluate(&ctx, &field_types).unwrap());
     }
     
     #[test]
@@ -607,11 +1342,34 @@ mod tests {
             field: "user_id".to_string(),
             op: Op::Like,
             values: vec![json!("user_*")],
+            collation: None,
         };
         
-        assert_eq!(node.evaluate(&ctx, &field_types).unwrap(), true);
+        assert!(node.evaluate(&ctx, &field_types).unwrap());
     }
     
+    #[test]
+    fn test_evaluate_collated_strings() {
+        let field_types = setup_field_types();
+        let ctx = [("country".to_string(), json!("Österreich"))].into_iter().collect();
+        let node = |op: Op, values: Vec<serde_json::Value>, collation: Option<Collation>| Node::Field {
+            field: "country".to_string(),
+            op,
+            values,
+            collation,
+        };
+
+        assert!(!node(Op::Eq, vec![json!("osterreich")], None).evaluate(&ctx, &field_types).unwrap());
+        let accents = Some(Collation::AccentInsensitive);
+        assert!(node(Op::Eq, vec![json!("osterreich")], accents).evaluate(&ctx, &field_types).unwrap());
+        assert!(node(Op::In, vec![json!("DE"), json!("OSTERREICH")], accents).evaluate(&ctx, &field_types).unwrap());
+        assert!(node(Op::Like, vec![json!("öst*")], Some(Collation::CaseInsensitive)).evaluate(&ctx, &field_types).unwrap());
+        assert!(!node(Op::Like, vec![json!("ost*")], Some(Collation::CaseInsensitive)).evaluate(&ctx, &field_types).unwrap());
+
+        let on_int = Node::Field { field: "age".to_string(), op: Op::Eq, values: vec![json!(1)], collation: accents };
+        assert!(on_int.validate(&field_types).is_err());
+    }
+
     #[test]
     fn test_evaluate_and() {
         let field_types = setup_field_types();
@@ -628,16 +1386,18 @@ mod tests {
                     field: "country".to_string(),
                     op: Op::Eq,
                     values: vec![json!("US")],
+                    collation: None,
                 },
                 Node::Field {
                     field: "age".to_string(),
                     op: Op::Gte,
                     values: vec![json!(18)],
+                    collation: None,
                 },
             ],
         };
         
-        assert_eq!(node.evaluate(&ctx, &field_types).unwrap(), true);
+        assert!(node.evaluate(&ctx, &field_types).unwrap());
     }
     
     #[test]
@@ -656,16 +1416,18 @@ mod tests {
                     field: "country".to_string(),
                     op: Op::Eq,
                     values: vec![json!("US")],
+                    collation: None,
                 },
                 Node::Field {
                     field: "age".to_string(),
                     op: Op::Gte,
                     values: vec![json!(18)],
+                    collation: None,
                 },
             ],
         };
         
-        assert_eq!(node.evaluate(&ctx, &field_types).unwrap(), true);
+        assert!(node.evaluate(&ctx, &field_types).unwrap());
     }
     
     #[test]
@@ -682,10 +1444,11 @@ mod tests {
                 field: "country".to_string(),
                 op: Op::Eq,
                 values: vec![json!("US")],
+                collation: None,
             }),
         };
         
-        assert_eq!(node.evaluate(&ctx, &field_types).unwrap(), true);
+        assert!(node.evaluate(&ctx, &field_types).unwrap());
     }
     
     #[test]
@@ -708,11 +1471,13 @@ mod tests {
                             field: "country".to_string(),
                             op: Op::Eq,
                             values: vec![json!("US")],
+                            collation: None,
                         },
                         Node::Field {
                             field: "age".to_string(),
                             op: Op::Gte,
                             values: vec![json!(18)],
+                            collation: None,
                         },
                     ],
                 },
@@ -720,11 +1485,12 @@ mod tests {
                     field: "premium".to_string(),
                     op: Op::Eq,
                     values: vec![json!(true)],
+                    collation: None,
                 },
             ],
         };
         
-        assert_eq!(node.evaluate(&ctx, &field_types).unwrap(), true);
+        assert!(node.evaluate(&ctx, &field_types).unwrap());
     }
     
     #[test]
@@ -733,16 +1499,61 @@ mod tests {
         assert_eq!(compare_semver("1.2.4", "1.2.3").unwrap(), std::cmp::Ordering::Greater);
         assert_eq!(compare_semver("1.2.2", "1.2.3").unwrap(), std::cmp::Ordering::Less);
         assert_eq!(compare_semver("2.0.0", "1.9.9").unwrap(), std::cmp::Ordering::Greater);
+        // Prereleases rank below their release; build metadata is ignored
+        assert_eq!(compare_semver("1.2.3-beta", "1.2.3").unwrap(), std::cmp::Ordering::Less);
+        assert_eq!(compare_semver("1.2.3+456", "1.2.3+123").unwrap(), std::cmp::Ordering::Equal);
+        assert!(compare_semver("1.2.beta", "1.2.3").is_err());
+    }
+
+    #[test]
+    fn test_semver_values_checked_for_declared_fields() {
+        let rule = |value: &str| Node::And {
+            children: vec![Node::Field {
+                field: "app_version".to_string(),
+                op: Op::Gte,
+                values: vec![json!(value)],
+                collation: None,
+            }],
+        };
+        let policy = semver::SemverPolicy::default();
+        // Other field types keep any value
+        assert!(rule("2.1.x").check_versions(&policy).is_ok());
+        policy.declare_fields(["app_version".to_string()].into());
+        assert!(rule("2.1.0-rc.1").check_versions(&policy).is_ok());
+        let err = rule("2.1.x").check_versions(&policy).unwrap_err();
+        assert!(err.to_string().contains("'2.1.x' is not a valid semver"), "{}", err);
+        let err = rule("2.1.0.4").check_versions(&policy).unwrap_err();
+        assert!(err.to_string().contains("more than 3 version segments"), "{}", err);
     }
     
     #[test]
     fn test_simple_pattern_match() {
-        assert_eq!(simple_pattern_match("hello", "*"), true);
-        assert_eq!(simple_pattern_match("hello", "hello"), true);
-        assert_eq!(simple_pattern_match("hello", "world"), false);
-        assert_eq!(simple_pattern_match("hello_world", "hello*"), true);
-        assert_eq!(simple_pattern_match("hello_world", "*world"), true);
-        assert_eq!(simple_pattern_match("hello_world", "hello*world"), true);
-        assert_eq!(simple_pattern_match("hello_world", "hi*"), false);
+        assert!(simple_pattern_match("hello", "*"));
+        assert!(simple_pattern_match("hello", "hello"));
+        assert!(!simple_pattern_match("hello", "world"));
+        assert!(simple_pattern_match("hello_world", "hello*"));
+        assert!(simple_pattern_match("hello_world", "*world"));
+        assert!(simple_pattern_match("hello_world", "hello*world"));
+        assert!(!simple_pattern_match("hello_world", "hi*"));
+    }
+
+    #[test]
+    fn test_pattern_match_multiple_wildcards() {
+        assert!(simple_pattern_match("ios_17_pro_max", "ios*pro*"));
+        assert!(simple_pattern_match("ios_17_pro_max", "*17*max"));
+        assert!(!simple_pattern_match("ios_17_pro_max", "*pro*17*"));
+        assert!(simple_pattern_match("abc", "a**c"));
+        assert!(simple_pattern_match("", "**"));
+        // Prefix and suffix may not overlap
+        assert!(!simple_pattern_match("aba", "ab*ba"));
+        assert!(simple_pattern_match("abba", "ab*ba"));
+    }
+
+    #[test]
+    fn test_compiled_patterns_are_shared() {
+        let first = compiled_pattern("cache_test_*_shared");
+        let second = compiled_pattern("cache_test_*_shared");
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(second.matches("cache_test_x_shared"));
     }
 }
@@ -0,0 +1,223 @@
+//! Rules compiled to a flat program.
+//!
+//! Evaluating a [`Node`] tree recurses once per node and walks boxed children
+//! scattered over the heap; for deep rules (depth 10 and up in the
+//! `rule_depth` benchmark) that walk dominates evaluation. A [`CompiledRule`]
+//! is the same rule flattened into a postfix instruction list that a loop runs
+//! over a single boolean register:
+//!
+//! - a leaf condition (field or CEL) sets the register
+//! - the children of an `and` / `or` follow each other, each but the last
+//!   followed by a jump to the end of the group taken when the register
+//!   already decides it (false for `and`, true for `or`)
+//! - `not` flips the register
+//!
+//! A jump landing on another jump is threaded past it, so short-circuiting
+//! out of nested groups takes one jump. An error aborts the program just as it
+//! aborts the tree walk: a compiled rule gives the same result as its tree for
+//! every context. Experiment rules are compiled (after
+//! [`crate::rule_optimizer`]) when the catalog loads, layer rules when a
+//! snapshot is built.
+
+use crate::clock::Clock;
+use crate::error::Result;
+use crate::rule::{FieldType, Node};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Instr {
+    /// Register = outcome of leaf condition `n`
+    Test(usize),
+    /// Register = constant (an empty `and` / `or`)
+    Const(bool),
+    Not,
+    /// Continue at `target` when the register equals `when`
+    JumpIf { when: bool, target: usize },
+}
+
+/// A rule flattened for evaluation
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledRule {
+    code: Vec<Instr>,
+    leaves: Vec<Node>,
+}
+
+impl CompiledRule {
+    pub fn compile(rule: &Node) -> Self {
+        let mut compiled = Self {
+            code: Vec::new(),
+            leaves: Vec::new(),
+        };
+        compiled.emit(rule);
+        compiled.thread_jumps();
+        compiled
+    }
+
+    fn emit(&mut self, node: &Node) {
+        match node {
+            Node::And { children } | Node::Or { children } => {
+                let is_and = matches!(node, Node::And { .. });
+                if children.is_empty() {
+                    self.code.push(Instr::Const(is_and));
+                    return;
+                }
+                let mut jumps = Vec::with_capacity(children.len() - 1);
+                for (i, child) in children.iter().enumerate() {
+                    self.emit(child);
+                    if i + 1 < children.len() {
+                        jumps.push(self.code.len());
+                        self.code.push(Instr::JumpIf { when: !is_and, target: 0 });
+                    }
+                }
+                let end = self.code.len();
+                for jump in jumps {
+                    self.code[jump] = Instr::JumpIf { when: !is_and, target: end };
+                }
+            }
+            Node::Not { child } => {
+                self.emit(child);
+                self.code.push(Instr::Not);
+            }
+            leaf => {
+                self.code.push(Instr::Test(self.leaves.len()));
+                self.leaves.push(leaf.clone());
+            }
+        }
+    }
+
+    /// Retarget jumps landing on jumps: one testing the same value is taken
+    /// too, one testing the other value never is
+    fn thread_jumps(&mut self) {
+        for i in 0..self.code.len() {
+            let Instr::JumpIf { when, mut target } = self.code[i] else {
+                continue;
+            };
+            // Targets only move forward, so this ends
+            while let Some(Instr::JumpIf { when: next_when, target: next_target }) = self.code.get(target) {
+                target = if *next_when == when { *next_target } else { target + 1 };
+            }
+            self.code[i] = Instr::JumpIf { when, target };
+        }
+    }
+
+    /// Evaluate against context, at the current wall-clock time
+    pub fn evaluate(
+        &self,
+        ctx: &HashMap<String, serde_json::Value>,
+        field_types: &HashMap<String, FieldType>,
+    ) -> Result<bool> {
+        self.evaluate_at(ctx, field_types, Clock::System.now_millis())
+    }
+
+    /// Same as [`Node::evaluate_at`] on the rule this was compiled from
+    pub fn evaluate_at(
+        &self,
+        ctx: &HashMap<String, serde_json::Value>,
+        field_types: &HashMap<String, FieldType>,
+        now: i64,
+    ) -> Result<bool> {
+        let mut register = false;
+        let mut pc = 0;
+        while let Some(instr) = self.code.get(pc) {
+            pc += 1;
+            match instr {
+                Instr::Test(leaf) => register = self.leaves[*leaf].evaluate_at(ctx, field_types, now)?,
+                Instr::Const(value) => register = *value,
+                Instr::Not => register = !register,
+                Instr::JumpIf { when, target } => {
+                    if register == *when {
+                        pc = *target;
+                    }
+                }
+            }
+        }
+        Ok(register)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::Op;
+    use serde_json::{json, Value};
+
+    fn eq(field: &str, value: i64) -> Node {
+        Node::Field {
+            field: field.to_string(),
+            op: Op::Eq,
+            values: vec![json!(value)],
+            collation: None,
+        }
+    }
+
+    #[test]
+    fn test_jumps_threaded_past_group_ends() {
+        // (a && b) || ((c || d) && e)
+        let rule = Node::Or {
+            children: vec![
+                Node::And { children: vec![eq("a", 1), eq("b", 1)] },
+                Node::And {
+                    children: vec![Node::Or { children: vec![eq("c", 1), eq("d", 1)] }, eq("e", 1)],
+                },
+            ],
+        };
+        assert_eq!(
+            CompiledRule::compile(&rule).code,
+            vec![
+                Instr::Test(0),
+                // A false `a` can't end the `or`: straight to its next child
+                Instr::JumpIf { when: false, target: 4 },
+                Instr::Test(1),
+                Instr::JumpIf { when: true, target: 9 },
+                Instr::Test(2),
+                // A true `c` can't end the `and`: straight to `e`
+                Instr::JumpIf { when: true, target: 8 },
+                Instr::Test(3),
+                Instr::JumpIf { when: false, target: 9 },
+                Instr::Test(4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compiled_rules_match_tree_evaluation() {
+        // `d` has no type: comparing it errors
+        let field_types = HashMap::from([
+            ("a".to_string(), FieldType::Int),
+            ("b".to_string(), FieldType::Int),
+            ("c".to_string(), FieldType::Int),
+        ]);
+        let not = |node: Node| Node::Not { child: Box::new(node) };
+        let rules = vec![
+            Node::And { children: vec![] },
+            not(Node::Or { children: vec![] }),
+            Node::And { children: vec![eq("a", 1), eq("d", 1), eq("c", 1)] },
+            Node::Or { children: vec![Node::And { children: vec![eq("a", 1), not(eq("b", 1))] }, eq("c", 1)] },
+            not(Node::Or { children: vec![eq("a", 1), not(Node::And { children: vec![eq("b", 1), eq("d", 0)] })] }),
+            Node::And {
+                children: vec![
+                    Node::Or { children: vec![not(eq("a", 0)), Node::And { children: vec![eq("b", 1), eq("c", 0)] }] },
+                    not(not(eq("c", 1))),
+                ],
+            },
+        ];
+
+        // Every combination of 0, 1 and missing for each field
+        for combo in 0..81 {
+            let mut context = HashMap::new();
+            for (i, field) in ["a", "b", "c", "d"].iter().enumerate() {
+                match combo / 3usize.pow(i as u32) % 3 {
+                    2 => {}
+                    value => {
+                        context.insert(field.to_string(), Value::from(value));
+                    }
+                }
+            }
+            for rule in &rules {
+                let tree = rule.evaluate_at(&context, &field_types, 0);
+                let compiled = CompiledRule::compile(rule).evaluate_at(&context, &field_types, 0);
+                assert_eq!(tree.ok(), compiled.ok(), "{:?} with {:?}", rule, context);
+            }
+        }
+    }
+}
@@ -0,0 +1,554 @@
+//! Text syntax for rules.
+//!
+//! Nested [`Node`] JSON is tedious and easy to get wrong by hand. Experiment
+//! files may give `rule_expr` instead of `rule`, and `POST /rules/parse`
+//! shows the tree an expression stands for:
+//!
+//! ```text
+//! country == "US" && (age >= 18 || premium)
+//! ```
+//!
+//! - `&&` / `and`, `||` / `or`, `!` / `not` and parentheses; `&&` binds
+//!   tighter than `||`
+//! - `field == value`, `!=`, `>`, `>=`, `<`, `<=`
+//! - `field in [..]`, `like "pat*"`, `between [low, high]`, `in_cidr [..]`,
+//!   each also as `not in` / `not like` / ...; `any_in [..]`, `all_in [..]`
+//!   and `none_in [..]` for list fields
+//! - `exists(field)`; `!exists(field)` is `not_exists`
+//! - a bare field means `field == true`; `true` / `false` are constants
+//! - comparisons may end with `collate case_insensitive` (see [`Collation`])
+//!
+//! Fields are dot paths (`device.os`); values are double-quoted strings,
+//! numbers and `true` / `false`. Keywords can't be field names; such rules
+//! need the JSON form.
+
+use crate::collation::Collation;
+use crate::error::ExperimentError;
+use crate::rule::{Node, Op};
+use serde_json::{Number, Value};
+use std::fmt;
+
+/// Where and why an expression failed to parse
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in characters
+    pub column: usize,
+    /// The offending source line, for pointing at the column
+    source_line: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}\n  {}\n  {}^",
+            self.line,
+            self.column,
+            self.message,
+            self.source_line,
+            " ".repeat(self.column - 1)
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for ExperimentError {
+    fn from(e: ParseError) -> Self {
+        ExperimentError::InvalidRule(format!("rule_expr at {}", e))
+    }
+}
+
+const KEYWORDS: &[&str] = &[
+    "and", "or", "not", "in", "like", "between", "in_cidr", "any_in", "all_in", "none_in", "exists", "collate",
+    "true", "false",
+];
+
+/// Parse a rule expression into the rule tree it stands for
+pub fn parse(expr: &str) -> std::result::Result<Node, ParseError> {
+    let tokens = tokenize(expr).map_err(|(at, message)| error_at(expr, at, message))?;
+    let mut parser = Parser { src: expr, tokens, pos: 0 };
+    if parser.peek() == &Token::End {
+        return Err(parser.error("empty rule expression".to_string()));
+    }
+    let node = parser.parse_or()?;
+    match parser.peek() {
+        Token::End => Ok(node),
+        Token::Punct(")") => Err(parser.error("')' without a matching '('".to_string())),
+        other => Err(parser.error(format!("expected '&&', '||' or end of expression, found {}", other))),
+    }
+}
+
+fn bool_field(field: String) -> Node {
+    Node::Field {
+        field,
+        op: Op::Eq,
+        values: vec![Value::Bool(true)],
+        collation: None,
+    }
+}
+
+fn error_at(src: &str, at: usize, message: String) -> ParseError {
+    let line_start = src[..at].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = src[at..].find('\n').map_or(src.len(), |i| at + i);
+    ParseError {
+        message,
+        line: src[..at].matches('\n').count() + 1,
+        column: src[line_start..at].chars().count() + 1,
+        source_line: src[line_start..line_end].to_string(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(Number),
+    Punct(&'static str),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Num(n) => write!(f, "'{}'", n),
+            Token::Punct(p) => write!(f, "'{}'", p),
+            Token::End => write!(f, "end of expression"),
+        }
+    }
+}
+
+/// Longest first, so `<=` is not read as `<` `=`
+const PUNCTUATION: &[&str] = &["&&", "||", "==", "!=", "<=", ">=", "(", ")", "[", "]", ",", "!", "<", ">"];
+
+fn tokenize(src: &str) -> std::result::Result<Vec<(usize, Token)>, (usize, String)> {
+    let mut tokens = Vec::new();
+    let mut chars = src.char_indices().peekable();
+    while let Some(&(at, c)) = chars.peek() {
+        let starts_number = c.is_ascii_digit()
+            || (c == '-' && src[at + 1..].starts_with(|c: char| c.is_ascii_digit()));
+        if c.is_whitespace() {
+            chars.next();
+        } else if starts_number {
+            chars.next();
+            let mut end = at + 1;
+            let mut is_float = false;
+            while let Some(&(i, c)) = chars.peek() {
+                let fraction = c == '.' && !is_float && src[i + 1..].starts_with(|c: char| c.is_ascii_digit());
+                if !c.is_ascii_digit() && !fraction {
+                    break;
+                }
+                is_float |= fraction;
+                end = i + 1;
+                chars.next();
+            }
+            let text = &src[at..end];
+            let number = if is_float {
+                text.parse::<f64>().ok().and_then(Number::from_f64)
+            } else {
+                text.parse::<i64>().ok().map(Number::from)
+            };
+            let number = number.ok_or_else(|| (at, format!("number '{}' out of range", text)))?;
+            tokens.push((at, Token::Num(number)));
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((i, '\\')) => match chars.next() {
+                        Some((_, '"')) => text.push('"'),
+                        Some((_, '\\')) => text.push('\\'),
+                        Some((_, 'n')) => text.push('\n'),
+                        Some((_, 't')) => text.push('\t'),
+                        Some((_, other)) => return Err((i, format!("unknown escape '\\{}'", other))),
+                        None => return Err((at, "unterminated string".to_string())),
+                    },
+                    Some((_, c)) => text.push(c),
+                    None => return Err((at, "unterminated string".to_string())),
+                }
+            }
+            tokens.push((at, Token::Str(text)));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = at;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_' || c == '.') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push((at, Token::Ident(src[at..end].to_string())));
+        } else if let Some(p) = PUNCTUATION.iter().find(|p| src[at..].starts_with(**p)) {
+            for _ in 0..p.len() {
+                chars.next();
+            }
+            tokens.push((at, Token::Punct(p)));
+        } else {
+            let hint = match c {
+                '=' => " (did you mean '=='?)",
+                '&' => " (did you mean '&&'?)",
+                '|' => " (did you mean '||'?)",
+                '\'' => " (strings are double-quoted)",
+                _ => "",
+            };
+            return Err((at, format!("unexpected character '{}'{}", c, hint)));
+        }
+    }
+    tokens.push((src.len(), Token::End));
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+type Parsed<T> = std::result::Result<T, ParseError>;
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].1
+    }
+
+    fn peek_keyword(&self, offset: usize) -> Option<&str> {
+        match self.tokens.get(self.pos + offset) {
+            Some((_, Token::Ident(name))) if KEYWORDS.contains(&name.as_str()) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn next(&mut self) -> (usize, Token) {
+        let token = self.tokens[self.pos].clone();
+        if token.1 != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    /// Consume the next token if it is `punct` or the keyword `keyword`
+    fn eat(&mut self, punct: &str, keyword: &str) -> bool {
+        let matches = match self.peek() {
+            Token::Punct(p) => *p == punct,
+            Token::Ident(name) => name == keyword,
+            _ => false,
+        };
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        error_at(self.src, self.tokens[self.pos].0, message)
+    }
+
+    fn expect(&mut self, punct: &'static str, context: &str) -> Parsed<()> {
+        if self.eat(punct, "") {
+            return Ok(());
+        }
+        Err(self.error(format!("expected '{}' {}, found {}", punct, context, self.peek())))
+    }
+
+    fn parse_or(&mut self) -> Parsed<Node> {
+        let mut children = vec![self.parse_and()?];
+        while self.eat("||", "or") {
+            children.push(self.parse_and()?);
+        }
+        Ok(if children.len() == 1 { children.remove(0) } else { Node::Or { children } })
+    }
+
+    fn parse_and(&mut self) -> Parsed<Node> {
+        let mut children = vec![self.parse_unary()?];
+        while self.eat("&&", "and") {
+            children.push(self.parse_unary()?);
+        }
+        Ok(if children.len() == 1 { children.remove(0) } else { Node::And { children } })
+    }
+
+    fn parse_unary(&mut self) -> Parsed<Node> {
+        if self.eat("!", "not") {
+            return Ok(match self.parse_unary()? {
+                Node::Field { field, op: Op::Exists, values, collation } => Node::Field {
+                    field,
+                    op: Op::NotExists,
+                    values,
+                    collation,
+                },
+                child => Node::Not { child: Box::new(child) },
+            });
+        }
+        let open = self.tokens[self.pos].0;
+        if self.eat("(", "") {
+            let node = self.parse_or()?;
+            let (line, column) = {
+                let at = error_at(self.src, open, String::new());
+                (at.line, at.column)
+            };
+            self.expect(")", &format!("to close the '(' at line {}, column {}", line, column))?;
+            return Ok(node);
+        }
+        self.parse_predicate()
+    }
+
+    fn parse_predicate(&mut self) -> Parsed<Node> {
+        let field = match self.peek().clone() {
+            Token::Ident(name) if name == "true" || name == "false" => {
+                self.next();
+                return Ok(if name == "true" {
+                    Node::And { children: Vec::new() }
+                } else {
+                    Node::Or { children: Vec::new() }
+                });
+            }
+            Token::Ident(name) if name == "exists" && self.tokens[self.pos + 1].1 == Token::Punct("(") => {
+                self.pos += 2;
+                let field = self.parse_field("inside exists(...)")?;
+                self.expect(")", "after the field of exists(...)")?;
+                return Ok(Node::Field {
+                    field,
+                    op: Op::Exists,
+                    values: Vec::new(),
+                    collation: None,
+                });
+            }
+            _ => self.parse_field("")?,
+        };
+
+        let negated = self.peek_keyword(0) == Some("not")
+            && matches!(self.peek_keyword(1), Some("in" | "like" | "between" | "in_cidr"));
+        if negated {
+            self.next();
+        }
+        let op = match self.peek() {
+            Token::Punct("==") => Op::Eq,
+            Token::Punct("!=") => Op::Neq,
+            Token::Punct(">") => Op::Gt,
+            Token::Punct(">=") => Op::Gte,
+            Token::Punct("<") => Op::Lt,
+            Token::Punct("<=") => Op::Lte,
+            Token::Ident(name) => match (name.as_str(), negated) {
+                ("in", false) => Op::In,
+                ("in", true) => Op::NotIn,
+                ("like", false) => Op::Like,
+                ("like", true) => Op::NotLike,
+                ("between", false) => Op::Between,
+                ("between", true) => Op::NotBetween,
+                ("in_cidr", false) => Op::InCidr,
+                ("in_cidr", true) => Op::NotInCidr,
+                ("any_in", _) => Op::AnyIn,
+                ("all_in", _) => Op::AllIn,
+                ("none_in", _) => Op::NoneIn,
+                _ if self.peek_keyword(0).is_none() => {
+                    return Err(self.error(format!("expected an operator between '{}' and '{}'", field, name)));
+                }
+                // A bare field tests a boolean
+                _ => return Ok(bool_field(field)),
+            },
+            token @ (Token::Str(_) | Token::Num(_)) => {
+                return Err(self.error(format!("expected an operator between '{}' and {}", field, token)));
+            }
+            _ => return Ok(bool_field(field)),
+        };
+        self.next();
+
+        let values = match op {
+            Op::In | Op::NotIn | Op::InCidr | Op::NotInCidr | Op::AnyIn | Op::AllIn | Op::NoneIn => self.parse_list()?,
+            Op::Between | Op::NotBetween => {
+                let list_at = self.tokens[self.pos].0;
+                let bounds = self.parse_list()?;
+                if bounds.len() != 2 {
+                    return Err(error_at(
+                        self.src,
+                        list_at,
+                        format!("between takes [low, high], found {} values", bounds.len()),
+                    ));
+                }
+                bounds
+            }
+            _ => vec![self.parse_value()?],
+        };
+
+        let mut collation = None;
+        if self.eat("", "collate") {
+            let (at, token) = self.next();
+            let parsed = match &token {
+                Token::Ident(name) => serde_json::from_value::<Collation>(Value::String(name.clone())).ok(),
+                _ => None,
+            };
+            collation = Some(parsed.ok_or_else(|| {
+                error_at(
+                    self.src,
+                    at,
+                    format!("expected binary, case_insensitive or accent_insensitive after 'collate', found {}", token),
+                )
+            })?);
+        }
+
+        Ok(Node::Field {
+            field,
+            op,
+            values,
+            collation,
+        })
+    }
+
+    fn parse_field(&mut self, context: &str) -> Parsed<String> {
+        let suffix = if context.is_empty() { String::new() } else { format!(" {}", context) };
+        match self.peek().clone() {
+            Token::Ident(name) if self.peek_keyword(0).is_none() => {
+                if name.ends_with('.') || name.contains("..") {
+                    return Err(self.error(format!("field '{}' has an empty path segment", name)));
+                }
+                self.next();
+                Ok(name)
+            }
+            Token::Ident(name) => Err(self.error(format!(
+                "expected a field name{}, found keyword '{}'",
+                suffix, name
+            ))),
+            token if context.is_empty() => Err(self.error(format!(
+                "expected a field name, '(' or '!', found {}",
+                token
+            ))),
+            token => Err(self.error(format!("expected a field name{}, found {}", suffix, token))),
+        }
+    }
+
+    fn parse_value(&mut self) -> Parsed<Value> {
+        match self.peek().clone() {
+            Token::Str(s) => {
+                self.next();
+                Ok(Value::String(s))
+            }
+            Token::Num(n) => {
+                self.next();
+                Ok(Value::Number(n))
+            }
+            Token::Ident(name) if name == "true" || name == "false" => {
+                self.next();
+                Ok(Value::Bool(name == "true"))
+            }
+            token => Err(self.error(format!(
+                "expected a value (string, number, true or false), found {}",
+                token
+            ))),
+        }
+    }
+
+    fn parse_list(&mut self) -> Parsed<Vec<Value>> {
+        self.expect("[", "to start the list of values")?;
+        let mut values = Vec::new();
+        if self.eat("]", "") {
+            return Ok(values);
+        }
+        loop {
+            values.push(self.parse_value()?);
+            if self.eat("]", "") {
+                return Ok(values);
+            }
+            self.expect(",", "or ']' in the list of values")?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn field(field: &str, op: Op, values: Vec<Value>) -> Node {
+        Node::Field {
+            field: field.to_string(),
+            op,
+            values,
+            collation: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_precedence_and_operators() {
+        assert_eq!(
+            parse(r#"country == "US" && (age >= 18 || premium)"#).unwrap(),
+            Node::And {
+                children: vec![
+                    field("country", Op::Eq, vec![json!("US")]),
+                    Node::Or {
+                        children: vec![
+                            field("age", Op::Gte, vec![json!(18)]),
+                            field("premium", Op::Eq, vec![json!(true)]),
+                        ],
+                    },
+                ],
+            }
+        );
+        // `&&` binds tighter than `||`
+        assert_eq!(
+            parse("a or b and not c").unwrap(),
+            Node::Or {
+                children: vec![
+                    field("a", Op::Eq, vec![json!(true)]),
+                    Node::And {
+                        children: vec![
+                            field("b", Op::Eq, vec![json!(true)]),
+                            Node::Not { child: Box::new(field("c", Op::Eq, vec![json!(true)])) },
+                        ],
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            parse(r#"device.os not in ["ios", "android"]"#).unwrap(),
+            field("device.os", Op::NotIn, vec![json!("ios"), json!("android")])
+        );
+        assert_eq!(
+            parse("score between [-1.5, 2]").unwrap(),
+            field("score", Op::Between, vec![json!(-1.5), json!(2)])
+        );
+        assert_eq!(parse("!exists(email)").unwrap(), field("email", Op::NotExists, vec![]));
+        assert_eq!(
+            parse(r#"city == "münchen" collate accent_insensitive"#).unwrap(),
+            Node::Field {
+                field: "city".to_string(),
+                op: Op::Eq,
+                values: vec![json!("münchen")],
+                collation: Some(Collation::AccentInsensitive),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_errors_point_at_the_problem() {
+        let error = |expr: &str| {
+            let e = parse(expr).unwrap_err();
+            (e.line, e.column, e.message)
+        };
+        assert_eq!(
+            error("age = 18"),
+            (1, 5, "unexpected character '=' (did you mean '=='?)".to_string())
+        );
+        assert_eq!(
+            error("age 18"),
+            (1, 5, "expected an operator between 'age' and '18'".to_string())
+        );
+        assert_eq!(
+            error("(a && b"),
+            (1, 8, "expected ')' to close the '(' at line 1, column 1, found end of expression".to_string())
+        );
+        assert_eq!(
+            error("a &&\n  in [1]"),
+            (2, 3, "expected a field name, found keyword 'in'".to_string())
+        );
+        assert_eq!(error(r#"tier in ["gold""#).1, 16);
+        assert_eq!(error("x between [1]").2, "between takes [low, high], found 1 values");
+
+        let message = ExperimentError::from(parse("a )").unwrap_err()).to_string();
+        assert!(message.contains("line 1, column 3: ')' without a matching '('\n  a )\n    ^"), "{}", message);
+    }
+}
@@ -0,0 +1,249 @@
+//! Semantic versions for `semver` fields.
+//!
+//! Precedence follows semver 2.0: numeric core segments compare numerically,
+//! a prerelease (`1.2.3-beta.2`) ranks below its release, prerelease
+//! identifiers compare numerically when both are numeric and in ASCII order
+//! otherwise (numeric below alphanumeric, a shorter list below a longer one
+//! it prefixes), and build metadata (`+build.5`) is ignored. Two departures
+//! suit app versions: the core may have any number of segments, missing ones
+//! counting as 0 (`1.2` == `1.2.0`), and core segments may have leading zeros.
+//!
+//! Field types come from the control plane after rules may already be loaded,
+//! so each [`crate::layer::LayerManager`] keeps a [`SemverPolicy`]: the fields
+//! its evaluator was told are semver-typed, and how many core segments a rule
+//! value may have (3 unless set with `SEMVER_MAX_SEGMENTS`). Experiment and
+//! layer rules are checked against it whenever they are applied, so a value
+//! that isn't a version rejects the config instead of failing every request
+//! that reaches it; declaring new field types re-checks the live config.
+
+use arc_swap::ArcSwap;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Semver settings of one layer manager
+#[derive(Debug)]
+pub struct SemverPolicy {
+    max_segments: usize,
+    fields: ArcSwap<HashSet<String>>,
+}
+
+impl Default for SemverPolicy {
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
+
+impl Clone for SemverPolicy {
+    fn clone(&self) -> Self {
+        Self {
+            max_segments: self.max_segments,
+            fields: ArcSwap::new(self.fields.load_full()),
+        }
+    }
+}
+
+impl SemverPolicy {
+    /// Rule values may have at most `max_segments` core segments (at least 1)
+    pub fn new(max_segments: usize) -> Self {
+        Self {
+            max_segments: max_segments.max(1),
+            fields: ArcSwap::from_pointee(HashSet::new()),
+        }
+    }
+
+    pub fn max_segments(&self) -> usize {
+        self.max_segments
+    }
+
+    /// Fields currently typed `semver`, whose rule values are checked
+    pub fn declare_fields(&self, fields: HashSet<String>) {
+        self.fields.store(Arc::new(fields));
+    }
+
+    pub fn is_declared(&self, field: &str) -> bool {
+        self.fields.load().contains(field)
+    }
+
+    /// Check a rule value: a version within the segment limit
+    pub fn check(&self, value: &str) -> Result<(), String> {
+        let version = Version::parse(value)?;
+        if version.core.len() > self.max_segments {
+            return Err(format!(
+                "'{}' is not a valid semver: more than {} version segments",
+                value, self.max_segments
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Identifier {
+    Numeric(u64),
+    Alphanumeric(String),
+}
+
+impl Ord for Identifier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Identifier::Numeric(l), Identifier::Numeric(r)) => l.cmp(r),
+            (Identifier::Numeric(_), Identifier::Alphanumeric(_)) => Ordering::Less,
+            (Identifier::Alphanumeric(_), Identifier::Numeric(_)) => Ordering::Greater,
+            (Identifier::Alphanumeric(l), Identifier::Alphanumeric(r)) => l.cmp(r),
+        }
+    }
+}
+
+impl PartialOrd for Identifier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A parsed version; equality is precedence, so build metadata and trailing
+/// zero segments don't count
+#[derive(Debug, Clone)]
+pub struct Version {
+    core: Vec<u64>,
+    prerelease: Vec<Identifier>,
+}
+
+impl Version {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let invalid = |reason: &str| format!("'{}' is not a valid semver: {}", s, reason);
+
+        let (version, build) = match s.split_once('+') {
+            Some((version, build)) => (version, Some(build)),
+            None => (s, None),
+        };
+        if let Some(build) = build {
+            if !build.split('.').all(is_identifier) {
+                return Err(invalid("build metadata must be dot-separated [0-9A-Za-z-] identifiers"));
+            }
+        }
+        let (core, prerelease) = match version.split_once('-') {
+            Some((core, prerelease)) => (core, Some(prerelease)),
+            None => (version, None),
+        };
+
+        let core = core
+            .split('.')
+            .map(|segment| match segment {
+                "" => Err(invalid("empty version segment")),
+                digits if digits.bytes().all(|b| b.is_ascii_digit()) => {
+                    digits.parse().map_err(|_| invalid("version segment out of range"))
+                }
+                _ => Err(invalid("version segments must be numeric")),
+            })
+            .collect::<Result<Vec<u64>, String>>()?;
+
+        let prerelease = match prerelease {
+            None => Vec::new(),
+            Some(prerelease) => prerelease
+                .split('.')
+                .map(|identifier| {
+                    if !is_identifier(identifier) {
+                        return Err(invalid("prerelease must be dot-separated [0-9A-Za-z-] identifiers"));
+                    }
+                    if !identifier.bytes().all(|b| b.is_ascii_digit()) {
+                        return Ok(Identifier::Alphanumeric(identifier.to_string()));
+                    }
+                    if identifier.len() > 1 && identifier.starts_with('0') {
+                        return Err(invalid("numeric prerelease identifiers can't have leading zeros"));
+                    }
+                    identifier
+                        .parse()
+                        .map(Identifier::Numeric)
+                        .map_err(|_| invalid("prerelease identifier out of range"))
+                })
+                .collect::<Result<Vec<_>, String>>()?,
+        };
+
+        Ok(Self { core, prerelease })
+    }
+}
+
+fn is_identifier(identifier: &str) -> bool {
+    !identifier.is_empty() && identifier.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let segments = self.core.len().max(other.core.len());
+        let segment = |core: &[u64], i: usize| core.get(i).copied().unwrap_or(0);
+        (0..segments)
+            .map(|i| segment(&self.core, i).cmp(&segment(&other.core, i)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| match (self.prerelease.is_empty(), other.prerelease.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => self.prerelease.cmp(&other.prerelease),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Version {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_semver_precedence() {
+        // Ascending, per the semver 2.0 precedence example plus app-style versions
+        let ordered = [
+            "0.9",
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.2.3-beta",
+            "1.2.3",
+            "1.10",
+        ];
+        for pair in ordered.windows(2) {
+            let (lower, higher) = (Version::parse(pair[0]).unwrap(), Version::parse(pair[1]).unwrap());
+            assert!(lower < higher, "{} < {}", pair[0], pair[1]);
+        }
+
+        let parse = |s| Version::parse(s).unwrap();
+        assert_eq!(parse("1.2.3+build.5"), parse("1.2.3+build.6"));
+        assert_eq!(parse("1.2"), parse("1.2.0"));
+        assert_eq!(parse("1.02.3"), parse("1.2.3"));
+
+        for invalid in ["", "1..2", "1.2.x", "v1.2.3", "1.2.3-", "1.2.3-beta..1", "1.2.3-01", "1.2.3+"] {
+            assert!(Version::parse(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_policies_are_independent() {
+        let strict = SemverPolicy::default();
+        let four = SemverPolicy::new(4);
+        strict.declare_fields(HashSet::from(["app_version".to_string()]));
+        assert!(strict.is_declared("app_version"));
+        assert!(!four.is_declared("app_version"));
+
+        assert!(strict.check("1.2.3.4").unwrap_err().contains("more than 3 version segments"));
+        assert!(four.check("1.2.3.4").is_ok());
+        assert!(four.check("1.2.x").is_err());
+    }
+}
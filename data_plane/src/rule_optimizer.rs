@@ -0,0 +1,300 @@
+//! Rule optimizer applied before rules are compiled for evaluation.
+//!
+//! Layer and experiment rules are evaluated on every request; wide rules
+//! (dozens of `and`ed conditions) dominate rule cost. Each snapshot (for
+//! layers) and catalog (for experiments) carries an optimized, compiled copy
+//! of every rule (see [`crate::rule::compiled`]), while the authored rule stays
+//! what the config APIs return:
+//!
+//! - constant subtrees are folded (`and: []` is true, `or: []` is false,
+//!   `not` of a constant, a constant that decides its parent)
+//! - nested `and`s / `or`s are flattened, single-child ones unwrapped and
+//!   double negations removed
+//! - repeated identical conditions within one `and` / `or` are dropped
+//! - children of an `and` whose outcome is only ever tested for "passed" are
+//!   reordered so cheap, likely-false conditions run first
+//!
+//! A rule passes only when it evaluates to true; false and evaluation errors
+//! (e.g. a field missing from the type map) both fail it. Rewrites keep the
+//! pass/fail outcome of every rule for every context: reordering, which can
+//! turn a false into an error, only happens where the two are
+//! interchangeable. Costs and selectivities are fixed per-operator estimates.
+
+use crate::rule::compiled::CompiledRule;
+use crate::rule::{Node, Op};
+use crate::shard::ShardedMap;
+use std::sync::Arc;
+
+/// Layer rules of one snapshot as evaluated; experiment rules are compiled
+/// with their catalog
+#[derive(Debug, Default)]
+pub struct OptimizedRules {
+    /// layer_id -> optimized, compiled layer rule
+    pub(crate) layers: ShardedMap<Arc<CompiledRule>>,
+}
+
+/// Where a node's result ends up, deciding which outcomes are interchangeable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Polarity {
+    /// Only true passes: false and error are interchangeable
+    Positive,
+    /// Under a `not`: only false passes, true and error are interchangeable
+    Negative,
+    /// The exact outcome matters
+    Exact,
+}
+
+impl Polarity {
+    fn negated(self) -> Self {
+        match self {
+            Polarity::Positive => Polarity::Negative,
+            Polarity::Negative => Polarity::Positive,
+            Polarity::Exact => Polarity::Exact,
+        }
+    }
+}
+
+fn always_true() -> Node {
+    Node::And { children: Vec::new() }
+}
+
+fn always_false() -> Node {
+    Node::Or { children: Vec::new() }
+}
+
+fn is_true(node: &Node) -> bool {
+    matches!(node, Node::And { children } if children.is_empty())
+}
+
+fn is_false(node: &Node) -> bool {
+    matches!(node, Node::Or { children } if children.is_empty())
+}
+
+/// Optimized equivalent of a top-level rule
+pub fn optimize(rule: &Node) -> Node {
+    optimize_in(rule.clone(), Polarity::Positive)
+}
+
+fn optimize_in(node: Node, polarity: Polarity) -> Node {
+    match node {
+        Node::Not { child } => match optimize_in(*child, polarity.negated()) {
+            Node::Not { child } => *child,
+            child if is_true(&child) => always_false(),
+            child if is_false(&child) => always_true(),
+            child => Node::Not { child: Box::new(child) },
+        },
+        Node::And { children } => combine(children, true, polarity == Polarity::Positive),
+        Node::Or { children } => combine(children, false, polarity == Polarity::Negative),
+        // Empty set: false whenever it doesn't error (an empty `not_in` is
+        // true only when the field is present, so it stays)
+        Node::Field { op: Op::In, values, .. } if values.is_empty() && polarity == Polarity::Positive => {
+            always_false()
+        }
+        field => field,
+    }
+}
+
+/// Optimize an `and` (`is_and`) or `or`. With `reorder`, its outcome only
+/// matters as pass/fail, so children may run in any order.
+fn combine(children: Vec<Node>, is_and: bool, reorder: bool) -> Node {
+    let child_polarity = match (reorder, is_and) {
+        (false, _) => Polarity::Exact,
+        (true, true) => Polarity::Positive,
+        (true, false) => Polarity::Negative,
+    };
+    // A false child decides an `and`, a true child an `or`
+    let decides = |node: &Node| if is_and { is_false(node) } else { is_true(node) };
+
+    let mut out: Vec<Node> = Vec::with_capacity(children.len());
+    'children: for child in children {
+        let parts = match optimize_in(child, child_polarity) {
+            Node::And { children } if is_and => children,
+            Node::Or { children } if !is_and => children,
+            other => vec![other],
+        };
+        for part in parts {
+            if decides(&part) {
+                // Later children never run; earlier ones still decide errors
+                if reorder {
+                    return part;
+                }
+                out.push(part);
+                break 'children;
+            }
+            // A repeat only runs when its first occurrence didn't decide
+            if !out.contains(&part) {
+                out.push(part);
+            }
+        }
+    }
+
+    if reorder {
+        let rank = |node: &Node| {
+            let (cost, p_true) = estimate(node);
+            let p_decides = if is_and { 1.0 - p_true } else { p_true };
+            cost / p_decides.max(1e-3)
+        };
+        out.sort_by(|a, b| rank(a).total_cmp(&rank(b)));
+    }
+
+    if out.len() == 1 {
+        return out.remove(0);
+    }
+    if is_and {
+        Node::And { children: out }
+    } else {
+        Node::Or { children: out }
+    }
+}
+
+/// Estimated `(cost, probability of true)` of evaluating `node`
+fn estimate(node: &Node) -> (f64, f64) {
+    match node {
+        Node::Field { op, values, .. } => {
+            let set = (0.1 * values.len() as f64).min(0.9);
+            match op {
+                Op::Eq => (1.0, 0.1),
+                Op::Neq => (1.0, 0.9),
+                Op::Gt | Op::Gte | Op::Lt | Op::Lte => (1.0, 0.5),
+                Op::Between => (1.5, 0.3),
+                Op::NotBetween => (1.5, 0.7),
+                Op::In => (1.0 + 0.25 * values.len() as f64, set),
+                Op::NotIn => (1.0 + 0.25 * values.len() as f64, 1.0 - set),
+                Op::Like => (2.0, 0.2),
+                Op::NotLike => (2.0, 0.8),
+                Op::InCidr => (1.5 + 0.25 * values.len() as f64, set),
+                Op::NotInCidr => (1.5 + 0.25 * values.len() as f64, 1.0 - set),
+                Op::AnyIn => (1.0 + 0.5 * values.len() as f64, set),
+                Op::AllIn => (1.0 + 0.5 * values.len() as f64, 0.1),
+                Op::NoneIn => (1.0 + 0.5 * values.len() as f64, 1.0 - set),
+                Op::Exists => (0.5, 0.8),
+                Op::NotExists => (0.5, 0.2),
+                Op::And | Op::Or | Op::Not => (1.0, 0.5),
+            }
+        }
+        Node::Cel { .. } => (3.0, 0.5),
+        Node::Not { child } => {
+            let (cost, p_true) = estimate(child);
+            (cost, 1.0 - p_true)
+        }
+        Node::And { children } => {
+            // Each child runs only if every earlier one was true
+            let (mut cost, mut reach) = (0.0, 1.0);
+            for child in children {
+                let (c, p) = estimate(child);
+                cost += reach * c;
+                reach *= p;
+            }
+            (cost, reach)
+        }
+        Node::Or { children } => {
+            let (mut cost, mut reach) = (0.0, 1.0);
+            for child in children {
+                let (c, p) = estimate(child);
+                cost += reach * c;
+                reach *= 1.0 - p;
+            }
+            (cost, 1.0 - reach)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::FieldType;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    fn eq(field: &str, value: i64) -> Node {
+        Node::Field {
+            field: field.to_string(),
+            op: Op::Eq,
+            values: vec![json!(value)],
+            collation: None,
+        }
+    }
+
+    #[test]
+    fn test_optimize_folds_flattens_and_reorders() {
+        let like = Node::Field {
+            field: "name".to_string(),
+            op: Op::Like,
+            values: vec![json!("a*")],
+            collation: None,
+        };
+        let rule = Node::And {
+            children: vec![
+                like.clone(),
+                Node::And {
+                    children: vec![eq("a", 1), always_true(), eq("a", 1)],
+                },
+                Node::Not {
+                    child: Box::new(Node::Not { child: Box::new(eq("b", 2)) }),
+                },
+            ],
+        };
+        // Cheap, selective equality checks move ahead of the pattern match
+        assert_eq!(
+            optimize(&rule),
+            Node::And {
+                children: vec![eq("a", 1), eq("b", 2), like]
+            }
+        );
+
+        // Under `not`, an `or` with a true child never passes whatever runs first
+        let rule = Node::Or {
+            children: vec![eq("a", 1), Node::Not { child: Box::new(always_false()) }, eq("b", 2)],
+        };
+        assert_eq!(optimize(&Node::Not { child: Box::new(rule) }), always_false());
+        // A top-level `or` keeps the children before the constant, whose errors still fail it
+        assert_eq!(
+            optimize(&Node::Or {
+                children: vec![eq("a", 1), always_true()]
+            }),
+            Node::Or {
+                children: vec![eq("a", 1), always_true()]
+            }
+        );
+    }
+
+    #[test]
+    fn test_optimized_rules_pass_the_same_contexts() {
+        let field_types = HashMap::from([
+            ("a".to_string(), FieldType::Int),
+            ("b".to_string(), FieldType::Int),
+            ("c".to_string(), FieldType::Int),
+        ]);
+        let not = |node: Node| Node::Not { child: Box::new(node) };
+        let rules = vec![
+            Node::And { children: vec![eq("a", 1), eq("b", 1), eq("c", 1)] },
+            not(Node::And { children: vec![eq("a", 1), eq("b", 1)] }),
+            Node::Or { children: vec![Node::And { children: vec![eq("a", 1), eq("b", 1)] }, eq("c", 1)] },
+            not(Node::Or { children: vec![eq("a", 1), not(eq("b", 1)), eq("a", 1)] }),
+            Node::And { children: vec![not(Node::Or { children: vec![eq("a", 0), eq("c", 0)] }), eq("b", 1)] },
+            not(Node::And {
+                children: vec![
+                    Node::Field { field: "c".to_string(), op: Op::In, values: vec![], collation: None },
+                    Node::Or { children: vec![eq("b", 0), always_true()] },
+                ],
+            }),
+        ];
+
+        // Every combination of 0, 1 and missing for each field
+        for combo in 0..27 {
+            let mut context = HashMap::new();
+            for (i, field) in ["a", "b", "c"].iter().enumerate() {
+                match combo / 3usize.pow(i as u32) % 3 {
+                    2 => {}
+                    value => {
+                        context.insert(field.to_string(), Value::from(value));
+                    }
+                }
+            }
+            for rule in &rules {
+                let passes = |rule: &Node| matches!(rule.evaluate(&context, &field_types), Ok(true));
+                assert_eq!(passes(rule), passes(&optimize(rule)), "{:?} with {:?}", rule, context);
+            }
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Dedicated runtime for config processing.
+//!
+//! Reloads, index rebuilds and validation run synchronously inside their
+//! tasks, so a 50k-layer snapshot rebuild on the shared tokio workers would
+//! hold one of the threads serving evaluation requests for its whole
+//! duration. Config tasks (watchers, resyncs, source switches and file
+//! reloaders) are spawned on this runtime's own worker threads instead, which
+//! on Linux also run at a lower scheduling priority (higher nice value).
+
+use std::io;
+use tokio::runtime::{Builder, Handle, Runtime};
+
+pub struct ConfigRuntime {
+    /// `None` when config tasks share the serving runtime
+    runtime: Option<Runtime>,
+    handle: Handle,
+}
+
+impl ConfigRuntime {
+    /// Start `worker_threads` config workers niced by `nice`; with 0 workers,
+    /// config tasks run on the current runtime
+    pub fn start(worker_threads: usize, nice: i32) -> io::Result<Self> {
+        if worker_threads == 0 {
+            return Ok(Self {
+                runtime: None,
+                handle: Handle::current(),
+            });
+        }
+
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(worker_threads)
+            .thread_name("config-worker")
+            .on_thread_start(move || lower_priority(nice))
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            handle: runtime.handle().clone(),
+            runtime: Some(runtime),
+        })
+    }
+
+    /// Where config tasks are spawned
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+}
+
+impl Drop for ConfigRuntime {
+    fn drop(&mut self) {
+        // Dropped from async code at shutdown, where blocking is not allowed
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// Raise the calling thread's nice value by `nice` (Linux keeps it per thread)
+#[cfg(target_os = "linux")]
+fn lower_priority(nice: i32) {
+    if nice <= 0 {
+        return;
+    }
+    // SAFETY: plain syscalls on the calling thread's own id
+    let result = unsafe {
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        let current = libc::getpriority(libc::PRIO_PROCESS as _, tid);
+        libc::setpriority(libc::PRIO_PROCESS as _, tid, current.saturating_add(nice))
+    };
+    if result != 0 {
+        tracing::warn!("Failed to lower config worker priority: {}", io::Error::last_os_error());
+    }
+}
+
+/// Elsewhere the nice value is per process: config workers keep the default
+#[cfg(not(target_os = "linux"))]
+fn lower_priority(_nice: i32) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_config_tasks_run_on_their_own_threads() {
+        let runtime = ConfigRuntime::start(1, 5).unwrap();
+        let name = runtime
+            .handle()
+            .spawn(async { std::thread::current().name().map(String::from) })
+            .await
+            .unwrap();
+        assert_eq!(name.as_deref(), Some("config-worker"));
+
+        // Without workers, config tasks stay on the serving runtime
+        let shared = ConfigRuntime::start(0, 5).unwrap();
+        let name = shared
+            .handle()
+            .spawn(async { std::thread::current().name().map(String::from) })
+            .await
+            .unwrap();
+        assert_ne!(name.as_deref(), Some("config-worker"));
+    }
+}
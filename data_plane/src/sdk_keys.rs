@@ -0,0 +1,217 @@
+//! Per-caller SDK keys for the evaluation API. Each key names a client and the
+//! services it may evaluate; the client name is attached to exposure events.
+//! Keys marked `support` may also manage support overrides.
+
+use crate::config::migrate;
+use crate::error::{ExperimentError, Result};
+use crate::metrics;
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Request header carrying the SDK key
+pub const SDK_KEY_HEADER: &str = "x-sdk-key";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SdkKey {
+    /// Caller identity used for attribution
+    pub client: String,
+    pub key: String,
+    /// Services this key may evaluate; empty or `"*"` allows all
+    #[serde(default)]
+    pub services: Vec<String>,
+    /// Revoked keys are rejected but kept in the file for the audit trail
+    #[serde(default)]
+    pub revoked: bool,
+    /// May create and clear support overrides (`/support/overrides`)
+    #[serde(default)]
+    pub support: bool,
+}
+
+impl SdkKey {
+    fn allows(&self, service: &str) -> bool {
+        self.services.is_empty() || self.services.iter().any(|s| s == "*" || s == service)
+    }
+
+    /// Fail with `Forbidden` on the first service outside this key's scope
+    pub fn check_services(&self, services: &[String]) -> Result<()> {
+        match services.iter().find(|s| !self.allows(s)) {
+            Some(service) => Err(ExperimentError::Forbidden(format!(
+                "Client '{}' may not evaluate service '{}'",
+                self.client, service
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SdkKeysFile {
+    keys: Vec<SdkKey>,
+}
+
+/// Keys loaded from `SDK_KEYS_FILE`, swapped atomically on reload
+#[derive(Debug)]
+pub struct SdkKeyRegistry {
+    path: PathBuf,
+    keys: ArcSwap<HashMap<String, Arc<SdkKey>>>,
+}
+
+impl SdkKeyRegistry {
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let keys = read_keys(&path)?;
+        tracing::info!("Loaded {} SDK keys from {:?}", keys.len(), path);
+        Ok(Self {
+            path,
+            keys: ArcSwap::from_pointee(keys),
+        })
+    }
+
+    /// Re-read the keys file; on failure the current keys stay in effect
+    pub fn reload(&self) -> Result<()> {
+        let keys = read_keys(&self.path)?;
+        self.keys.store(Arc::new(keys));
+        Ok(())
+    }
+
+    /// Validate a caller's key for every requested service
+    pub fn authorize(&self, key: Option<&str>, services: &[String]) -> Result<Arc<SdkKey>> {
+        count_rejection(self.check(key, services))
+    }
+
+    /// Identify the caller without checking service scopes
+    pub fn authenticate(&self, key: Option<&str>) -> Result<Arc<SdkKey>> {
+        let result = self.lookup(key);
+        if result.is_err() {
+            metrics::SDK_KEY_REJECTIONS.with_label_values(&["unauthorized"]).inc();
+        }
+        result
+    }
+
+    /// Identify a caller allowed to manage support overrides
+    pub fn authorize_support(&self, key: Option<&str>) -> Result<Arc<SdkKey>> {
+        count_rejection(self.lookup(key).and_then(|sdk_key| {
+            if sdk_key.support {
+                Ok(sdk_key)
+            } else {
+                Err(ExperimentError::Forbidden(format!(
+                    "Client '{}' may not manage support overrides",
+                    sdk_key.client
+                )))
+            }
+        }))
+    }
+
+    fn check(&self, key: Option<&str>, services: &[String]) -> Result<Arc<SdkKey>> {
+        let sdk_key = self.lookup(key)?;
+        sdk_key.check_services(services)?;
+        Ok(sdk_key)
+    }
+
+    fn lookup(&self, key: Option<&str>) -> Result<Arc<SdkKey>> {
+        let key = key.ok_or_else(|| {
+            ExperimentError::Unauthorized(format!("Missing {} header", SDK_KEY_HEADER))
+        })?;
+
+        self.keys
+            .load()
+            .get(key)
+            .cloned()
+            .filter(|k| !k.revoked)
+            .ok_or_else(|| ExperimentError::Unauthorized("Unknown or revoked SDK key".to_string()))
+    }
+}
+
+fn count_rejection(result: Result<Arc<SdkKey>>) -> Result<Arc<SdkKey>> {
+    if let Err(e) = &result {
+        let reason = match e {
+            ExperimentError::Forbidden(_) => "forbidden",
+            _ => "unauthorized",
+        };
+        metrics::SDK_KEY_REJECTIONS.with_label_values(&[reason]).inc();
+    }
+    result
+}
+
+fn read_keys(path: &PathBuf) -> Result<HashMap<String, Arc<SdkKey>>> {
+    let content = std::fs::read_to_string(path)?;
+    let file: SdkKeysFile = serde_json::from_value(migrate::parse_document(&content)?)?;
+
+    let mut keys = HashMap::new();
+    for sdk_key in file.keys {
+        if let Some(previous) = keys.insert(sdk_key.key.clone(), Arc::new(sdk_key)) {
+            return Err(ExperimentError::InvalidParameter(format!(
+                "Duplicate SDK key (client '{}')",
+                previous.client
+            )));
+        }
+    }
+    Ok(keys)
+}
+
+/// Pick up key additions and revocations every `interval`
+pub async fn reload_periodically(registry: Arc<SdkKeyRegistry>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        if let Err(e) = registry.reload() {
+            tracing::error!("Failed to reload SDK keys, keeping previous set: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_authorize_scopes_and_revocation() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("sdk_keys.yaml");
+        std::fs::write(
+            &path,
+            "keys:\n  - client: web\n    key: k-web\n    services: [recommendation]\n  - client: batch\n    key: k-batch\n    support: true\n",
+        )
+        .unwrap();
+
+        let registry = SdkKeyRegistry::load(path.clone()).unwrap();
+        let rec = vec!["recommendation".to_string()];
+        let ranker = vec!["ranker".to_string()];
+
+        assert_eq!(registry.authorize(Some("k-web"), &rec).unwrap().client, "web");
+        assert!(matches!(
+            registry.authorize(Some("k-web"), &ranker),
+            Err(ExperimentError::Forbidden(_))
+        ));
+        assert!(registry.authorize(Some("k-batch"), &ranker).is_ok());
+        assert!(matches!(
+            registry.authorize(None, &rec),
+            Err(ExperimentError::Unauthorized(_))
+        ));
+
+        // Only keys marked `support` manage support overrides
+        assert_eq!(registry.authorize_support(Some("k-batch")).unwrap().client, "batch");
+        assert!(matches!(
+            registry.authorize_support(Some("k-web")),
+            Err(ExperimentError::Forbidden(_))
+        ));
+
+        // Revocation takes effect on reload
+        std::fs::write(
+            &path,
+            "keys:\n  - client: web\n    key: k-web\n    revoked: true\n",
+        )
+        .unwrap();
+        registry.reload().unwrap();
+        assert!(matches!(
+            registry.authorize(Some("k-web"), &rec),
+            Err(ExperimentError::Unauthorized(_))
+        ));
+    }
+}
@@ -1,62 +1,282 @@
-use crate::catalog::ExperimentCatalog;
+use crate::aa_test::AaTests;
+use crate::aliases::{self, AliasRegistry};
+use crate::applied::AppliedLog;
 use crate::config::Config;
-use crate::layer::LayerManager;
-use crate::merge::{merge_layers_batch, ExperimentRequest, ExperimentResponse};
+use crate::context_policy::{self, ContextPolicy};
+use crate::emergency;
+use crate::exclusion;
+use crate::field_alias::FieldAliases;
+use crate::number_coercion::LenientNumbers;
+use crate::engine::Evaluator;
+use crate::error::ExperimentError;
+use crate::failover::SourceFailover;
+use crate::context_db::{self, ContextDatabases};
+use crate::federation::{self, Federation};
+use crate::feed::{ConfigFeed, FeedDiff};
+use crate::expiry;
+use crate::exposure::{self, ExposureLog};
+use crate::health::ConfigHealth;
+use crate::kv::KvStore;
+use crate::merge::{ExperimentRequest, ExperimentResponse};
 use crate::metrics;
+use crate::metrics_push::{self, MetricsPusher};
+use crate::net;
+use crate::overrides::{self, OverrideStore, Overrides};
 use crate::rule::FieldType;
+use crate::sdk_keys::{self, SdkKeyRegistry, SDK_KEY_HEADER};
+use crate::signing::{ResponseSigner, SIGNATURE_HEADER};
+use crate::source::SourceSwitcher;
+use crate::stats::{ServiceLoad, TrafficStats};
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderName, StatusCode},
     response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
-use parking_lot::RwLock;
 use prometheus::{Encoder, TextEncoder};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
+use tower_http::compression::predicate::{DefaultPredicate, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
 use tower_http::trace::TraceLayer;
 
+#[cfg(feature = "admin")]
+mod admin;
+
 #[derive(Clone)]
+// Sources, aliases, databases and the applied log are only read by admin routes
+#[cfg_attr(not(feature = "admin"), allow(dead_code))]
 struct AppState {
-    layer_manager: Arc<LayerManager>,
-    catalog: Arc<ExperimentCatalog>,
-    field_types: Arc<RwLock<HashMap<String, FieldType>>>,
+    evaluator: Arc<Evaluator>,
+    health: Arc<ConfigHealth>,
+    /// Present when `SUPPORT_OVERRIDES_ENABLED`
+    overrides: Option<Arc<OverrideStore>>,
+    /// Present when `EXPOSURE_ENABLED`
+    exposures: Option<ExposureLog>,
+    /// Present when `SDK_KEYS_FILE` is set
+    sdk_keys: Option<Arc<SdkKeyRegistry>>,
+    /// Present when `CONTEXT_ALLOWLIST_FILE` is set
+    context_policy: Option<Arc<ContextPolicy>>,
+    /// Present when `FEDERATION_ROUTES_FILE` is set
+    federation: Option<Arc<Federation>>,
+    /// Present when `CONTEXT_DATABASES` is set; read by context providers
+    context_databases: Option<Arc<ContextDatabases>>,
+    /// Present when `RESPONSE_SIGNING_SECRET_FILE` is set
+    signer: Option<Arc<ResponseSigner>>,
+    source_switcher: Arc<SourceSwitcher>,
+    identity_aliases: Arc<AliasRegistry>,
+    /// Assignments served by this instance, for the dashboard
+    traffic: Arc<TrafficStats>,
+    /// Evaluations per service, for `/stats/services`
+    load: Arc<ServiceLoad>,
+    /// A/A test layer tallies, for `/layers/:layer_id/aa_test`
+    aa_tests: Arc<AaTests>,
+    /// Present when `APPLIED_LOG_DIR` is set
+    applied_log: Option<Arc<AppliedLog>>,
+    /// Present when `CONFIG_SOURCES_FILE` is set
+    failover: Option<Arc<SourceFailover>>,
+    /// Recent layer / experiment changes for `/config/subscribe`
+    feed: Arc<ConfigFeed>,
+    /// Longest a `/config/subscribe` call is held open
+    subscribe_max_wait: Duration,
 }
 
+/// `kv` is the store `evaluator` keeps exposure caps in; support overrides share it
 pub async fn run_server(
     config: Config,
-    layer_manager: Arc<LayerManager>,
-    catalog: Arc<ExperimentCatalog>,
+    evaluator: Arc<Evaluator>,
+    kv: Arc<dyn KvStore>,
+    health: Arc<ConfigHealth>,
+    source_switcher: Arc<SourceSwitcher>,
+    applied_log: Option<Arc<AppliedLog>>,
+    failover: Option<Arc<SourceFailover>>,
 ) -> anyhow::Result<()> {
     // Initialize metrics
     metrics::init();
 
+    // Reloaders rebuild snapshots; keep them off the serving workers
+    let config_tasks = source_switcher.runtime().clone();
+    metrics::set_stage_sample_rate(config.stage_timing_sample_rate);
+
+    let overrides = if config.support_overrides_enabled {
+        Some(Arc::new(OverrideStore::new(
+            kv,
+            Duration::from_secs(config.support_override_max_ttl_secs),
+        )))
+    } else {
+        None
+    };
+
+    let exposures = if config.exposure_enabled {
+        Some(ExposureLog::start(&config)?)
+    } else {
+        None
+    };
+
+    let sdk_keys = match &config.sdk_keys_file {
+        Some(path) => {
+            let registry = Arc::new(SdkKeyRegistry::load(path.clone())?);
+            config_tasks.spawn(sdk_keys::reload_periodically(
+                registry.clone(),
+                Duration::from_secs(config.sdk_keys_reload_secs.max(1)),
+            ));
+            Some(registry)
+        }
+        None => None,
+    };
+
+    let context_policy = match &config.context_allowlist_file {
+        Some(path) => {
+            let policy = Arc::new(ContextPolicy::load(path.clone(), config.context_allowlist_strict)?);
+            config_tasks.spawn(context_policy::reload_periodically(
+                policy.clone(),
+                Duration::from_secs(config.context_allowlist_reload_secs.max(1)),
+            ));
+            Some(policy)
+        }
+        None => None,
+    };
+
+    let federation = match &config.federation_routes_file {
+        Some(path) => {
+            let federation = Arc::new(Federation::load(
+                path.clone(),
+                Duration::from_millis(config.federation_timeout_ms.max(1)),
+            )?);
+            config_tasks.spawn(federation::reload_periodically(
+                federation.clone(),
+                Duration::from_secs(config.federation_reload_secs.max(1)),
+            ));
+            Some(federation)
+        }
+        None => None,
+    };
+
+    let context_databases = if config.context_databases.is_empty() {
+        None
+    } else {
+        let databases = Arc::new(ContextDatabases::load(config.context_databases.clone())?);
+        config_tasks.spawn(context_db::refresh_periodically(
+            databases.clone(),
+            Duration::from_secs(config.context_database_reload_secs.max(1)),
+        ));
+        Some(databases)
+    };
+
+    let signer = match &config.response_signing_secret_file {
+        Some(path) => Some(Arc::new(ResponseSigner::from_file(path)?)),
+        None => None,
+    };
+
+    let identity_aliases = Arc::new(AliasRegistry::load(
+        config.identity_aliases_file.clone(),
+        evaluator.layer_manager().clone(),
+    )?);
+    if config.identity_aliases_file.is_some() {
+        config_tasks.spawn(aliases::reload_periodically(
+            identity_aliases.clone(),
+            Duration::from_secs(config.identity_aliases_reload_secs.max(1)),
+        ));
+    }
+
+    // Applied before serving so a pending break-glass is never bypassed at startup
+    emergency::apply_file(&config.emergency_overrides_file, evaluator.layer_manager());
+    config_tasks.spawn(emergency::watch_file(
+        config.emergency_overrides_file.clone(),
+        evaluator.layer_manager().clone(),
+        Duration::from_secs(config.emergency_overrides_check_secs.max(1)),
+    ));
+    // Contractual exclusions likewise hold from the first request
+    exclusion::apply_file(&config.exclusions_file, evaluator.layer_manager());
+    config_tasks.spawn(exclusion::watch_file(
+        config.exclusions_file.clone(),
+        evaluator.layer_manager().clone(),
+        Duration::from_secs(config.exclusions_check_secs.max(1)),
+    ));
+    config_tasks.spawn(expiry::sweep_periodically(
+        evaluator.layer_manager().clone(),
+        Duration::from_secs(config.expiry_check_secs.max(1)),
+    ));
+
     let state = AppState {
-        layer_manager,
-        catalog,
-        field_types: Arc::new(RwLock::new(HashMap::new())),
+        feed: ConfigFeed::start(evaluator.layer_manager().clone()),
+        evaluator,
+        health,
+        overrides,
+        exposures,
+        sdk_keys,
+        context_policy,
+        federation,
+        context_databases,
+        signer,
+        source_switcher,
+        identity_aliases,
+        traffic: Arc::new(TrafficStats::new()),
+        load: Arc::new(ServiceLoad::new()),
+        aa_tests: Arc::new(AaTests::new()),
+        applied_log,
+        failover,
+        subscribe_max_wait: Duration::from_secs(config.config_subscribe_max_wait_secs.max(1)),
     };
 
-    // Build application router
+    if let Some(url) = &config.metrics_push_url {
+        let interval = Duration::from_secs(config.metrics_push_interval_secs.max(1));
+        let pusher = MetricsPusher::new(url, interval)?;
+        let evaluator = state.evaluator.clone();
+        config_tasks.spawn(metrics_push::push_periodically(pusher, interval, move || {
+            local_metrics(&evaluator)
+        }));
+        tracing::info!("Pushing metrics to {} every {:?}", url, interval);
+    }
+
+    // Build application router: evaluation, health and the field metadata the
+    // control plane pushes are always served; the rest depends on features
     let app = Router::new()
         .route("/health", get(health_check))
+        .route("/ready", get(ready_check))
         .route("/experiment", post(experiment_handler))
-        .route("/layers", get(list_layers))
-        .route("/layers/:layer_id", get(get_layer))
-        .route("/layers/:layer_id/rollback", post(rollback_layer))
+        .route("/config/subscribe", get(config_subscribe))
         .route("/field_types", get(get_field_types))
         .route("/field_types", post(update_field_types))
+        .route("/field_types/lenient", get(get_lenient_numbers).post(update_lenient_numbers))
+        .route("/field_aliases", get(get_field_aliases).post(update_field_aliases));
+    #[cfg(feature = "admin")]
+    let app = app.merge(admin::routes());
+    #[cfg(feature = "metrics-endpoint")]
+    let app = app
         .route("/metrics", get(metrics_handler))
+        .route("/metrics/federated", get(federated_metrics));
+    let app = app
         .layer(TraceLayer::new_for_http())
         .with_state(state);
 
-    let addr = format!("{}:{}", config.server_host, config.server_port);
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    // Batch responses with deep params can be hundreds of KB; compress above threshold
+    let app = if config.compression_enabled {
+        app.layer(
+            CompressionLayer::new()
+                .gzip(true)
+                .br(true)
+                .compress_when(
+                    DefaultPredicate::new().and(SizeAbove::new(config.compression_min_size)),
+                ),
+        )
+    } else {
+        app
+    };
+
+    // Hostnames such as `localhost` resolve like `TcpListener::bind` would
+    let addr = tokio::net::lookup_host((config.server_host.as_str(), config.server_port))
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("SERVER_HOST '{}' resolved to no address", config.server_host))?;
+    let listener = net::listener(addr, &config)?;
 
-    tracing::info!("Server listening on {}", addr);
+    tracing::info!("Server listening on {}", listener.local_addr()?);
 
-    axum::serve(listener, app).await?;
+    // Serves HTTP/1.1 and HTTP/2 on the same port: over TLS via ALPN, else h2c prior knowledge
+    net::serve(listener, app, &config, net::shutdown_signal()).await?;
 
     Ok(())
 }
@@ -68,24 +288,122 @@ async fn health_check() -> impl IntoResponse {
     }))
 }
 
+/// Readiness with config apply status. A degraded instance keeps serving its
+/// last good snapshot, so it stays 200 and reports why it is stale. A config
+/// volume that doesn't match its manifest is incomplete and fails with 503, as
+/// does a config pipeline the watchdog found stalled.
+async fn ready_check(State(state): State<AppState>) -> Response {
+    let stalled = state.health.stalled();
+    if !stalled.is_empty() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "status": "stalled",
+                "stalled_tasks": stalled
+            })),
+        )
+            .into_response();
+    }
+
+    let mismatches = state.health.manifest_mismatches();
+    if !mismatches.is_empty() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "status": "incomplete",
+                "manifest_mismatches": mismatches
+            })),
+        )
+            .into_response();
+    }
+
+    match state.health.degradation() {
+        None => Json(serde_json::json!({
+            "status": "ready"
+        })),
+        Some(degraded) => Json(serde_json::json!({
+            "status": "degraded",
+            "degraded": degraded
+        })),
+    }
+    .into_response()
+}
+
+/// Load label for requested services that have no enabled layers
+const OTHER_SERVICES: &str = "_other";
+
 async fn experiment_handler(
     State(state): State<AppState>,
-    Json(request): Json<ExperimentRequest>,
-) -> Result<Json<ExperimentResponse>, AppError> {
+    headers: HeaderMap,
+    Json(mut request): Json<ExperimentRequest>,
+) -> Result<Response, AppError> {
     let _timer = metrics::REQUEST_DURATION.start_timer();
     metrics::REQUEST_TOTAL.inc();
 
-    // Get field types
-    let field_types = state.field_types.read().clone();
+    let client = match &state.sdk_keys {
+        Some(registry) => {
+            let key = headers.get(SDK_KEY_HEADER).and_then(|v| v.to_str().ok());
+            Some(registry.authorize(key, &request.services)?.client.clone())
+        }
+        None => None,
+    };
 
-    // Merge layers with rule evaluation using batch API
-    let response =
-        merge_layers_batch(&request, &state.layer_manager, &state.catalog, &field_types).map_err(
-            |e| {
-                metrics::REQUEST_ERRORS.inc();
-                e
-            },
-        )?;
+    // Services owned by remote planes are evaluated there while the rest run here
+    let remote = match &state.federation {
+        Some(federation) if !headers.contains_key(federation::FEDERATED_HEADER) => {
+            federation.delegate(&mut request, headers.get(SDK_KEY_HEADER).cloned())
+        }
+        _ => None,
+    };
+
+    let overrides = lookup_overrides(&state, &request).await;
+    let trace = state.exposures.as_ref().is_some_and(ExposureLog::sample_trace);
+
+    // Merge layers with rule evaluation; exposures and traffic are recorded
+    // against the same snapshot
+    let snapshot = state.evaluator.layer_manager().snapshot();
+    let mut response = match &state.context_policy {
+        None => state.evaluator.evaluate_traced_async(&request, &overrides, trace, &snapshot).await,
+        // Each service sees only its allowed fields, so evaluate them separately
+        Some(policy) => match policy.scope(&request, state.evaluator.layer_manager()) {
+            Ok(scoped) => {
+                let mut results = HashMap::new();
+                let mut outcome = Ok(());
+                for service_request in &scoped {
+                    match state
+                        .evaluator
+                        .evaluate_traced_async(service_request, &overrides, trace, &snapshot)
+                        .await
+                    {
+                        Ok(response) => results.extend(response.results),
+                        Err(e) => {
+                            outcome = Err(e);
+                            break;
+                        }
+                    }
+                }
+                outcome.map(|_| ExperimentResponse { results })
+            }
+            Err(e) => Err(e),
+        },
+    }
+    .inspect_err(|_| metrics::REQUEST_ERRORS.inc())?;
+
+    // Remote planes record their own exposures and traffic
+    if let Some(exposures) = &state.exposures {
+        exposures.record(exposure::events_for(&request, &response, client.as_deref(), &snapshot));
+    }
+
+    for (service, result) in &response.results {
+        state.traffic.record(&result.vids);
+        state.aa_tests.record(&snapshot, &request.context, result);
+        // Services without layers are lumped together to bound label cardinality
+        if snapshot.layers_for_service(service).is_empty() {
+            state.load.record(OTHER_SERVICES);
+        } else {
+            state.load.record(service);
+        }
+    }
 
     // Update active layers metric
     let total_layers: usize = response
@@ -95,42 +413,70 @@ async fn experiment_handler(
         .sum();
     metrics::ACTIVE_LAYERS.set(total_layers as i64);
 
-    Ok(Json(response))
-}
+    if let Some(remote) = remote {
+        response.results.extend(remote.await.map_err(anyhow::Error::from)?);
+    }
 
-async fn list_layers(State(state): State<AppState>) -> impl IntoResponse {
-    let layer_ids = state.layer_manager.get_layer_ids();
-    Json(serde_json::json!({
-        "layers": layer_ids
-    }))
+    let Some(signer) = &state.signer else {
+        return Ok(Json(response).into_response());
+    };
+    // Signed over the exact bytes sent
+    let body = serde_json::to_vec(&response)?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (HeaderName::from_static(SIGNATURE_HEADER), signer.sign(&body)),
+        ],
+        body,
+    )
+        .into_response())
 }
 
-async fn get_layer(
-    State(state): State<AppState>,
-    Path(layer_id): Path<String>,
-) -> Result<Json<serde_json::Value>, AppError> {
-    let layer = state
-        .layer_manager
-        .get_layer(&layer_id)
-        .ok_or_else(|| crate::error::ExperimentError::LayerNotFound(layer_id.clone()))?;
+/// Support overrides for the request's subject. Store failures are logged and
+/// ignored so an unavailable KV backend never fails evaluation.
+async fn lookup_overrides(state: &AppState, request: &ExperimentRequest) -> Overrides {
+    let Some(store) = &state.overrides else {
+        return Overrides::new();
+    };
 
-    Ok(Json(serde_json::to_value(&*layer)?))
+    let subjects = overrides::subject_keys(request, state.evaluator.layer_manager());
+    store
+        .lookup(subjects.iter().map(String::as_str))
+        .await
+        .unwrap_or_else(|e| {
+            tracing::warn!("Support override lookup failed: {}", e);
+            Overrides::new()
+        })
 }
 
-async fn rollback_layer(
+#[derive(serde::Deserialize)]
+struct SubscribeQuery {
+    #[serde(default)]
+    since_epoch: u64,
+    /// Capped at `CONFIG_SUBSCRIBE_MAX_WAIT_SECS`
+    timeout_secs: Option<u64>,
+}
+
+/// Long-poll: answers once layers or experiments changed after `since_epoch`,
+/// or `304 Not Modified` when nothing did within the timeout
+async fn config_subscribe(
     State(state): State<AppState>,
-    Path(layer_id): Path<String>,
-) -> Result<impl IntoResponse, AppError> {
-    state.layer_manager.rollback_layer(&layer_id).await?;
+    Query(query): Query<SubscribeQuery>,
+) -> Result<Json<FeedDiff>, StatusCode> {
+    let timeout = query
+        .timeout_secs
+        .map_or(state.subscribe_max_wait, |secs| Duration::from_secs(secs).min(state.subscribe_max_wait));
 
-    Ok(Json(serde_json::json!({
-        "status": "success",
-        "message": format!("Layer {} rolled back", layer_id)
-    })))
+    state
+        .feed
+        .wait(query.since_epoch, timeout)
+        .await
+        .map(Json)
+        .ok_or(StatusCode::NOT_MODIFIED)
 }
 
 async fn get_field_types(State(state): State<AppState>) -> impl IntoResponse {
-    let field_types = state.field_types.read().clone();
+    let field_types = state.evaluator.field_types();
     Json(field_types)
 }
 
@@ -138,40 +484,127 @@ async fn update_field_types(
     State(state): State<AppState>,
     Json(new_field_types): Json<HashMap<String, FieldType>>,
 ) -> impl IntoResponse {
-    let mut field_types = state.field_types.write();
-    *field_types = new_field_types;
+    let count = new_field_types.len();
+    let semver_violations = state.evaluator.set_field_types(new_field_types);
 
-    tracing::info!("Updated field types: {} fields", field_types.len());
+    tracing::info!("Updated field types: {} fields", count);
 
+    // Live rules whose values aren't versions for the new semver fields keep
+    // serving (and fail at evaluation) until their config is fixed
     Json(serde_json::json!({
         "status": "success",
-        "message": format!("Updated {} field types", field_types.len())
+        "message": format!("Updated {} field types", count),
+        "semver_violations": semver_violations,
     }))
 }
 
-async fn metrics_handler() -> impl IntoResponse {
-    let encoder = TextEncoder::new();
-    let metric_families = metrics::REGISTRY.gather();
-    let mut buffer = vec![];
-    encoder.encode(&metric_families, &mut buffer).unwrap();
+async fn get_lenient_numbers(State(state): State<AppState>) -> impl IntoResponse {
+    Json((**state.evaluator.layer_manager().snapshot().lenient_numbers()).clone())
+}
+
+async fn update_lenient_numbers(
+    State(state): State<AppState>,
+    Json(fields): Json<Vec<String>>,
+) -> impl IntoResponse {
+    let lenient = LenientNumbers::new(fields);
+    let count = lenient.len();
+    state.evaluator.layer_manager().set_lenient_numbers(lenient);
+
+    tracing::info!("Updated lenient number fields: {} fields", count);
+
+    Json(serde_json::json!({
+        "status": "success",
+        "message": format!("Updated {} lenient number fields", count)
+    }))
+}
+
+async fn get_field_aliases(State(state): State<AppState>) -> impl IntoResponse {
+    Json((**state.evaluator.layer_manager().snapshot().field_aliases()).clone())
+}
 
+async fn update_field_aliases(
+    State(state): State<AppState>,
+    Json(renames): Json<HashMap<String, String>>,
+) -> Result<impl IntoResponse, AppError> {
+    let aliases = FieldAliases::new(renames)?;
+    let count = aliases.len();
+    state.evaluator.layer_manager().set_field_aliases(aliases);
+
+    tracing::info!("Updated field aliases: {} fields", count);
+
+    Ok(Json(serde_json::json!({
+        "status": "success",
+        "message": format!("Updated {} field aliases", count)
+    })))
+}
+
+#[cfg(feature = "metrics-endpoint")]
+async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
     (
         StatusCode::OK,
         [("content-type", "text/plain; version=0.0.4")],
-        buffer,
+        local_metrics(&state.evaluator),
     )
 }
 
+/// This plane's metrics and every federated plane's, labelled by `plane`
+#[cfg(feature = "metrics-endpoint")]
+async fn federated_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let local = String::from_utf8(local_metrics(&state.evaluator)).unwrap_or_default();
+    let remote = match &state.federation {
+        Some(federation) => federation.remote_metrics().await,
+        None => Vec::new(),
+    };
+    let mut sources = vec![(federation::LOCAL_PLANE, local.as_str())];
+    sources.extend(remote.iter().map(|(plane, text)| (plane.as_str(), text.as_str())));
+
+    (
+        StatusCode::OK,
+        [("content-type", "text/plain; version=0.0.4")],
+        federation::merge_expositions(&sources),
+    )
+}
+
+/// This plane's metrics in the Prometheus text format
+fn local_metrics(evaluator: &Evaluator) -> Vec<u8> {
+    let catalog = evaluator.catalog();
+    let lazy = catalog.lazy_params();
+    metrics::set_memory_gauges(
+        lazy.map_or(0, |l| l.compressed_bytes()),
+        lazy.map_or(0, |l| l.cached_entries()),
+        catalog.param_dedup(),
+    );
+
+    let encoder = TextEncoder::new();
+    let metric_families = metrics::REGISTRY.gather();
+    let mut buffer = vec![];
+    encoder.encode(&metric_families, &mut buffer).unwrap();
+    buffer
+}
+
 // Error handling
 struct AppError(anyhow::Error);
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let message = self.0.to_string();
-        tracing::error!("Request error: {}", message);
+
+        let status = match self.0.downcast_ref::<ExperimentError>() {
+            Some(ExperimentError::Unauthorized(_)) => StatusCode::UNAUTHORIZED,
+            Some(ExperimentError::Forbidden(_)) => StatusCode::FORBIDDEN,
+            Some(ExperimentError::ContextNotAllowed(_)) => StatusCode::BAD_REQUEST,
+            Some(ExperimentError::ExperimentNotFound(_)) => StatusCode::NOT_FOUND,
+            Some(ExperimentError::ConfigFrozen(_)) => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        if status.is_server_error() {
+            tracing::error!("Request error: {}", message);
+        } else {
+            tracing::warn!("Request rejected: {}", message);
+        }
 
         (
-            StatusCode::INTERNAL_SERVER_ERROR,
+            status,
             Json(serde_json::json!({
                 "error": message
             })),
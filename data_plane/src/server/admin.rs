@@ -0,0 +1,615 @@
+//! Management and diagnostics endpoints (feature `admin`, on by default):
+//! what-if / simulation / preview tooling, support overrides, layer and
+//! experiment inspection, the dashboard and `/admin/*` operations. Edge
+//! builds leave them out and serve only evaluation.
+
+use super::{AppError, AppState};
+use crate::aa_test::AaSummary;
+use crate::aliases::IdentityAlias;
+use crate::applied::AppliedQuery;
+use crate::caps::{self, CapUsage};
+use crate::catalog::VariantsDiff;
+use crate::churn::{self, ChurnReport, ChurnRequest};
+use crate::clone;
+use crate::coverage::{self, CoverageGaps};
+use crate::dashboard::{self, Overview};
+use crate::effective_params::{effective_params, EffectiveParams};
+use crate::error::ExperimentError;
+use crate::expiry::Expired;
+use crate::export::{self, ExportRequest};
+use crate::freeze::FreezeSchedule;
+use crate::graph;
+use crate::merge::subject_assignments;
+use crate::overrides::{CreateOverride, OverrideStore, Overrides};
+use crate::preview::{preview, PreviewRequest, PreviewResponse};
+use crate::rule::dsl;
+use crate::sdk_keys::SDK_KEY_HEADER;
+use crate::sim::{SimulateRequest, SimulateResponse};
+use crate::source::ConfigSource;
+use crate::whatif::{WhatIfRequest, WhatIfResponse};
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+
+pub(super) fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/experiment/whatif", post(whatif_handler))
+        .route("/simulate", post(simulate_handler))
+        .route("/export/assignments", post(export_assignments))
+        .route("/preview", post(preview_handler))
+        .route("/preview/churn", post(churn_handler))
+        .route("/subjects/:key/assignments", get(subject_assignments_handler))
+        .route("/support/overrides", post(create_override))
+        .route("/support/overrides/audit", get(override_audit))
+        .route(
+            "/support/overrides/:subject",
+            get(list_overrides).delete(clear_overrides),
+        )
+        .route("/identity/aliases", get(list_identity_aliases).post(link_identity_alias))
+        .route("/identity/aliases/:field/:value", delete(unlink_identity_alias))
+        .route("/services/:service/resolution_order", get(resolution_order))
+        .route("/services/:service/effective_params", get(effective_params_handler))
+        .route("/experiments/:eid/variants/diff", get(variants_diff))
+        .route("/experiments/:eid/clone", post(clone_experiment_handler))
+        .route("/layers", get(list_layers))
+        .route("/layers/:layer_id", get(get_layer))
+        .route("/layers/:layer_id/rollback", post(rollback_layer))
+        .route("/layers/:layer_id/aa_test", get(aa_test_summary))
+        .route("/diagnostics/load_errors", get(load_errors))
+        .route("/diagnostics/source_conflicts", get(source_conflicts))
+        .route("/diagnostics/emergency_overrides", get(emergency_overrides))
+        .route("/diagnostics/exclusions", get(exclusions))
+        .route("/diagnostics/variant_caps", get(variant_caps))
+        .route("/diagnostics/expired", get(expired_resources))
+        .route("/diagnostics/coverage_gaps", get(coverage_gaps))
+        .route("/diagnostics/overview", get(overview))
+        .route("/graph", get(experiment_graph))
+        .route("/stats/services", get(service_stats))
+        .route("/ui", get(ui_index))
+        .route("/ui/", get(ui_index))
+        .route("/ui/*path", get(ui_asset))
+        .route(
+            "/admin/config_source",
+            get(get_config_source).post(switch_config_source),
+        )
+        .route("/admin/config_sources", get(get_config_sources))
+        .route("/admin/freeze", get(get_freeze).post(set_freeze_override))
+        .route("/admin/context_databases", get(get_context_databases))
+        .route("/audit/applied", get(applied_changes))
+        .route("/rules/parse", post(parse_rule))
+}
+
+async fn whatif_handler(
+    State(state): State<AppState>,
+    Json(request): Json<WhatIfRequest>,
+) -> Result<Json<WhatIfResponse>, AppError> {
+    Ok(Json(state.evaluator.what_if(&request)?))
+}
+
+async fn simulate_handler(
+    State(state): State<AppState>,
+    Json(request): Json<SimulateRequest>,
+) -> Result<Json<SimulateResponse>, AppError> {
+    // Large populations are CPU-bound; keep them off the async workers
+    let response = tokio::task::spawn_blocking(move || state.evaluator.simulate(&request)).await??;
+
+    Ok(Json(response))
+}
+
+async fn export_assignments(
+    State(state): State<AppState>,
+    Json(request): Json<ExportRequest>,
+) -> Result<Response, AppError> {
+    let format = request.format;
+    let rows = export::spawn(
+        request,
+        state.evaluator.layer_manager().snapshot(),
+        state.evaluator.field_types(),
+    )?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"assignments.{}\"", format.extension()),
+            ),
+        ],
+        Body::from_stream(ReceiverStream::new(rows).map(Ok::<_, Infallible>)),
+    )
+        .into_response())
+}
+
+async fn subject_assignments_handler(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Query(mut params): Query<HashMap<String, String>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let service = params
+        .remove("service")
+        .ok_or_else(|| anyhow::anyhow!("Missing `service` query parameter"))?;
+
+    // Remaining query params are passed to rule evaluation as string context
+    let mut context = params
+        .into_iter()
+        .map(|(k, v)| (k, serde_json::Value::String(v)))
+        .collect();
+    if let Some(policy) = &state.context_policy {
+        context = policy.apply(&service, &context, state.evaluator.layer_manager())?;
+    }
+
+    let overrides = match &state.overrides {
+        Some(store) => store.lookup([key.as_str()]).await?,
+        None => Overrides::new(),
+    };
+
+    let field_types = state.evaluator.field_types();
+    let assignments = subject_assignments(
+        &service,
+        &key,
+        context,
+        &overrides,
+        &state.evaluator.layer_manager().snapshot(),
+        &field_types,
+    );
+
+    Ok(Json(serde_json::json!({
+        "subject": key,
+        "service": service,
+        "assignments": assignments
+    })))
+}
+
+fn override_store(state: &AppState) -> Result<&Arc<OverrideStore>, AppError> {
+    state
+        .overrides
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("Support overrides are disabled (SUPPORT_OVERRIDES_ENABLED)").into())
+}
+
+/// SDK key client allowed to manage support overrides
+fn support_client(state: &AppState, headers: &HeaderMap) -> Result<String, AppError> {
+    let registry = state.sdk_keys.as_ref().ok_or_else(|| {
+        ExperimentError::Forbidden("Support overrides require SDK_KEYS_FILE with a `support` key".to_string())
+    })?;
+    let key = headers.get(SDK_KEY_HEADER).and_then(|v| v.to_str().ok());
+
+    Ok(registry.authorize_support(key)?.client.clone())
+}
+
+async fn create_override(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<CreateOverride>,
+) -> Result<impl IntoResponse, AppError> {
+    let client = support_client(&state, &headers)?;
+    let created = override_store(&state)?
+        .create(
+            request,
+            &client,
+            state.evaluator.layer_manager(),
+            &state.evaluator.catalog(),
+        )
+        .await?;
+
+    Ok(Json(created))
+}
+
+async fn list_overrides(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(subject): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    support_client(&state, &headers)?;
+    let active = override_store(&state)?.list(&subject).await?;
+
+    Ok(Json(serde_json::json!({
+        "subject": subject,
+        "overrides": active
+    })))
+}
+
+async fn clear_overrides(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(subject): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<impl IntoResponse, AppError> {
+    let client = support_client(&state, &headers)?;
+    let operator = params
+        .get("operator")
+        .ok_or_else(|| anyhow::anyhow!("Missing `operator` query parameter"))?;
+    let removed = override_store(&state)?.clear(&subject, operator, &client).await?;
+
+    Ok(Json(serde_json::json!({
+        "subject": subject,
+        "removed": removed
+    })))
+}
+
+async fn override_audit(State(state): State<AppState>, headers: HeaderMap) -> Result<impl IntoResponse, AppError> {
+    support_client(&state, &headers)?;
+    let entries = override_store(&state)?.audit_log().await?;
+
+    Ok(Json(serde_json::json!({
+        "entries": entries
+    })))
+}
+
+async fn list_identity_aliases(State(state): State<AppState>) -> impl IntoResponse {
+    Json(serde_json::json!({
+        "aliases": state.identity_aliases.list()
+    }))
+}
+
+async fn link_identity_alias(
+    State(state): State<AppState>,
+    Json(alias): Json<IdentityAlias>,
+) -> Result<impl IntoResponse, AppError> {
+    state.identity_aliases.link(alias.clone())?;
+
+    Ok(Json(alias))
+}
+
+async fn unlink_identity_alias(
+    State(state): State<AppState>,
+    Path((field, value)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let removed = state.identity_aliases.unlink(&field, &value);
+
+    Json(serde_json::json!({
+        "field": field,
+        "value": value,
+        "removed": removed
+    }))
+}
+
+async fn preview_handler(
+    State(state): State<AppState>,
+    Json(request): Json<PreviewRequest>,
+) -> Result<Json<PreviewResponse>, AppError> {
+    let field_types = state.evaluator.field_types();
+
+    // Evaluates the sample twice (live + candidate); keep it off the async workers
+    let catalog = state.evaluator.catalog();
+    let response = tokio::task::spawn_blocking(move || {
+        preview(&request, state.evaluator.layer_manager(), &catalog, &field_types)
+    })
+    .await??;
+
+    Ok(Json(response))
+}
+
+async fn churn_handler(
+    State(state): State<AppState>,
+    Json(request): Json<ChurnRequest>,
+) -> Result<Json<ChurnReport>, AppError> {
+    // Replays every subject through both layers; keep it off the async workers
+    let snapshot = state.evaluator.layer_manager().snapshot();
+    let report = tokio::task::spawn_blocking(move || churn::churn(&request, &snapshot)).await??;
+
+    Ok(Json(report))
+}
+
+async fn resolution_order(
+    State(state): State<AppState>,
+    Path(service): Path<String>,
+) -> impl IntoResponse {
+    Json(
+        state
+            .evaluator
+            .layer_manager()
+            .resolution_order(&service, &state.evaluator.catalog()),
+    )
+}
+
+async fn effective_params_handler(
+    State(state): State<AppState>,
+    Path(service): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<EffectiveParams>, AppError> {
+    let vids = params
+        .get("vid")
+        .ok_or_else(|| anyhow::anyhow!("Missing `vid` query parameter"))?
+        .split(',')
+        .map(|vid| vid.trim().parse::<i64>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| anyhow::anyhow!("Invalid `vid` query parameter: {}", e))?;
+
+    Ok(Json(effective_params(&state.evaluator.layer_manager().snapshot(), &service, &vids)?))
+}
+
+async fn list_layers(State(state): State<AppState>) -> impl IntoResponse {
+    let layer_ids = state.evaluator.layer_manager().get_layer_ids();
+    Json(serde_json::json!({
+        "layers": layer_ids
+    }))
+}
+
+async fn load_errors(State(state): State<AppState>) -> impl IntoResponse {
+    let errors: Vec<_> = state
+        .evaluator
+        .layer_manager()
+        .load_errors()
+        .into_iter()
+        .map(|(path, error)| {
+            serde_json::json!({
+                "path": path,
+                "context": error.context,
+                "message": error.message,
+                "migration": error.migration,
+                "at": error.at,
+            })
+        })
+        .collect();
+    Json(serde_json::json!({
+        "errors": errors
+    }))
+}
+
+async fn source_conflicts(State(state): State<AppState>) -> impl IntoResponse {
+    Json(serde_json::json!({
+        "layers": state.evaluator.layer_manager().source_conflicts(),
+        "experiments": state.evaluator.catalog().conflicts(),
+    }))
+}
+
+async fn emergency_overrides(State(state): State<AppState>) -> impl IntoResponse {
+    Json((*state.evaluator.layer_manager().emergency_overrides()).clone())
+}
+
+async fn exclusions(State(state): State<AppState>) -> impl IntoResponse {
+    Json((**state.evaluator.layer_manager().snapshot().exclusions()).clone())
+}
+
+async fn variant_caps(State(state): State<AppState>) -> Result<Json<Vec<CapUsage>>, AppError> {
+    let snapshot = state.evaluator.layer_manager().snapshot();
+    Ok(Json(caps::usage(snapshot.catalog(), state.evaluator.cap_tracker()).await?))
+}
+
+async fn expired_resources(State(state): State<AppState>) -> Json<Expired> {
+    Json((**state.evaluator.layer_manager().snapshot().expired()).clone())
+}
+
+async fn coverage_gaps(State(state): State<AppState>) -> Json<CoverageGaps> {
+    Json(coverage::gaps(&state.evaluator.layer_manager().snapshot()))
+}
+
+/// `?service=` limits the graph to one service; `?format=dot` returns Graphviz DOT
+async fn experiment_graph(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    let snapshot = state.evaluator.layer_manager().snapshot();
+    let graph = graph::build(&snapshot, params.get("service").map(String::as_str));
+
+    match params.get("format").map(String::as_str) {
+        None | Some("json") => Ok(Json(graph).into_response()),
+        Some("dot") => Ok(([("content-type", "text/vnd.graphviz")], graph.to_dot()).into_response()),
+        Some(other) => Err(anyhow::anyhow!("Unknown graph format '{}' (expected json or dot)", other).into()),
+    }
+}
+
+async fn overview(State(state): State<AppState>) -> Json<Overview> {
+    Json(dashboard::overview(state.evaluator.layer_manager(), &state.traffic))
+}
+
+async fn service_stats(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<impl IntoResponse, AppError> {
+    let top = params
+        .get("top")
+        .map(|top| top.parse::<usize>())
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("Invalid `top` query parameter: {}", e))?;
+
+    Ok(Json(serde_json::json!({
+        "services": state.load.rates(top)
+    })))
+}
+
+#[derive(serde::Deserialize)]
+struct FreezeOverride {
+    #[serde(rename = "override")]
+    enabled: bool,
+}
+
+async fn get_context_databases(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.context_databases.as_ref().map(|databases| databases.status()).unwrap_or_default())
+}
+
+async fn get_freeze(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let freeze = freeze_schedule(&state)?;
+
+    Ok(Json(freeze.status()))
+}
+
+async fn set_freeze_override(
+    State(state): State<AppState>,
+    Json(request): Json<FreezeOverride>,
+) -> Result<impl IntoResponse, AppError> {
+    let freeze = freeze_schedule(&state)?;
+    freeze.set_override(request.enabled);
+
+    Ok(Json(freeze.status()))
+}
+
+fn freeze_schedule(state: &AppState) -> Result<&Arc<FreezeSchedule>, AppError> {
+    state
+        .evaluator
+        .layer_manager()
+        .freeze()
+        .ok_or_else(|| anyhow::anyhow!("Freeze windows are not configured (set FREEZE_WINDOWS_FILE)").into())
+}
+
+async fn applied_changes(
+    State(state): State<AppState>,
+    Query(query): Query<AppliedQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let log = state
+        .applied_log
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("Applied change log is not configured (set APPLIED_LOG_DIR)"))?;
+
+    let body = match query.at {
+        Some(at) => serde_json::json!({
+            "at": at,
+            "live": log.live_at(at)?
+        }),
+        None => serde_json::json!({
+            "entries": log.query(&query)?
+        }),
+    };
+    Ok(Json(body))
+}
+
+async fn ui_index() -> Response {
+    dashboard::asset("")
+}
+
+async fn ui_asset(Path(path): Path<String>) -> Response {
+    dashboard::asset(&path)
+}
+
+async fn variants_diff(
+    State(state): State<AppState>,
+    Path(eid): Path<i64>,
+) -> Result<Json<VariantsDiff>, AppError> {
+    let catalog = state.evaluator.catalog();
+    let experiment = catalog
+        .get_experiment(eid)
+        .ok_or(ExperimentError::ExperimentNotFound(eid))?;
+
+    Ok(Json(experiment.variants_diff()))
+}
+
+async fn clone_experiment_handler(
+    State(state): State<AppState>,
+    Path(eid): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let clone = clone::clone_experiment(&state.evaluator.layer_manager().snapshot(), eid)?;
+    let report = clone.write(&state.source_switcher.current())?;
+
+    tracing::info!(
+        "Cloned experiment {} into draft {} ({} layers)",
+        eid,
+        report.eid,
+        report.layers.len()
+    );
+
+    Ok((StatusCode::CREATED, Json(report)))
+}
+
+async fn get_layer(
+    State(state): State<AppState>,
+    Path(layer_id): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let layer = state
+        .evaluator
+        .layer_manager()
+        .get_layer(&layer_id)
+        .ok_or_else(|| ExperimentError::LayerNotFound(layer_id.clone()))?;
+
+    Ok(Json(serde_json::to_value(&*layer)?))
+}
+
+async fn aa_test_summary(
+    State(state): State<AppState>,
+    Path(layer_id): Path<String>,
+) -> Result<Json<AaSummary>, AppError> {
+    let snapshot = state.evaluator.layer_manager().snapshot();
+    Ok(Json(state.aa_tests.summary(&snapshot, &layer_id)?))
+}
+
+async fn rollback_layer(
+    State(state): State<AppState>,
+    Path(layer_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let manager = state.evaluator.layer_manager().clone();
+    let catalog = state.evaluator.catalog();
+    let rollback_id = layer_id.clone();
+    state
+        .source_switcher
+        .runtime()
+        .spawn(async move { manager.rollback_layer(&rollback_id, &catalog).await })
+        .await??;
+
+    Ok(Json(serde_json::json!({
+        "status": "success",
+        "message": format!("Layer {} rolled back", layer_id)
+    })))
+}
+
+async fn get_config_source(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.source_switcher.current())
+}
+
+async fn switch_config_source(
+    State(state): State<AppState>,
+    Json(source): Json<ConfigSource>,
+) -> Result<impl IntoResponse, AppError> {
+    let switcher = state.source_switcher.clone();
+    switcher
+        .runtime()
+        .clone()
+        .spawn(async move { switcher.switch(source).await })
+        .await??;
+
+    Ok(Json(serde_json::json!({
+        "status": "success",
+        "source": state.source_switcher.current()
+    })))
+}
+
+async fn get_config_sources(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let failover = state
+        .failover
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("Config source failover is not configured (set CONFIG_SOURCES_FILE)"))?;
+
+    Ok(Json(serde_json::json!({
+        "current": state.source_switcher.current(),
+        "sources": failover.status()
+    })))
+}
+
+#[derive(serde::Deserialize)]
+struct ParseRuleRequest {
+    expr: String,
+}
+
+/// Rule tree of a `rule_expr` expression, checked against the current field
+/// types; syntax errors are 400 with their position
+async fn parse_rule(State(state): State<AppState>, Json(request): Json<ParseRuleRequest>) -> Response {
+    let rule = match dsl::parse(&request.expr) {
+        Ok(rule) => rule,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": e.to_string(),
+                    "line": e.line,
+                    "column": e.column
+                })),
+            )
+                .into_response()
+        }
+    };
+    // Field types may not be pushed to this instance yet, so this only informs
+    let validation_error = rule.validate(&state.evaluator.field_types()).err().map(|e| e.to_string());
+    Json(serde_json::json!({
+        "rule": rule,
+        "validation_error": validation_error
+    }))
+    .into_response()
+}
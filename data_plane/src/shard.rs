@@ -0,0 +1,200 @@
+//! Copy-on-write map split into shards by key hash.
+//!
+//! Every config change publishes a new snapshot. With hundreds of thousands of
+//! layers, copying the whole layer map and service index for each update
+//! dominates publish cost and allocator churn. A [`ShardedMap`] clones in
+//! O([`SHARDS`]): changing an entry copies only the shard holding it, and
+//! untouched shards stay shared with the previous snapshot, which also lets
+//! change detection skip them.
+//!
+//! Shards are published together as part of one snapshot, so a request still
+//! sees a single consistent config.
+//!
+//! This differs from independent per-service `ArcSwap`s in two ways. The layer
+//! map is keyed by `layer_id` because a layer can serve several services; only
+//! the service index is keyed by service. And there is still one `ArcSwap` (the
+//! snapshot), since catalog, layers and index must switch together. Writers are
+//! serialized by the layer manager anyway, so extra swaps would not reduce
+//! contention.
+//!
+//! Expiry (`Expired::collect`) and the experiment budget check still walk every
+//! layer on each update, so publishing one layer stays O(layers), just with a
+//! much smaller constant than a full clone and re-index.
+//! `benches/layer_management_bench.rs` (`single_layer_update`) tracks this.
+
+use std::collections::HashMap;
+use std::ops::Index;
+use std::sync::Arc;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Shards per map
+pub const SHARDS: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct ShardedMap<V> {
+    shards: Vec<Arc<HashMap<String, V>>>,
+}
+
+impl<V> Default for ShardedMap<V> {
+    fn default() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Arc::new(HashMap::new())).collect(),
+        }
+    }
+}
+
+fn shard_of(key: &str) -> usize {
+    (xxh3_64(key.as_bytes()) % SHARDS as u64) as usize
+}
+
+impl<V> ShardedMap<V> {
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.shards[shard_of(key)].get(key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.shards[shard_of(key)].contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.is_empty())
+    }
+
+    /// Entries in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        self.shards.iter().flat_map(|shard| shard.iter())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    /// `(previous, current)` pairs of the shards not shared with `previous`;
+    /// entries outside them are identical in both maps
+    pub fn changed_shards<'a>(
+        &'a self,
+        previous: &'a Self,
+    ) -> impl Iterator<Item = (&'a HashMap<String, V>, &'a HashMap<String, V>)> {
+        previous
+            .shards
+            .iter()
+            .zip(&self.shards)
+            .filter(|(old, new)| !Arc::ptr_eq(old, new))
+            .map(|(old, new)| (old.as_ref(), new.as_ref()))
+    }
+
+    /// Map every entry with `f`, reusing the shards of `previous` (a mapping
+    /// of `previous_source`) where `self` shares the shard with it
+    pub fn map_shards<W>(
+        &self,
+        previous_source: &Self,
+        previous: &ShardedMap<W>,
+        mut f: impl FnMut(&String, &V) -> Option<W>,
+    ) -> ShardedMap<W> {
+        let shards = self
+            .shards
+            .iter()
+            .enumerate()
+            .map(|(i, shard)| {
+                if Arc::ptr_eq(shard, &previous_source.shards[i]) {
+                    previous.shards[i].clone()
+                } else {
+                    Arc::new(shard.iter().filter_map(|(key, value)| Some((key.clone(), f(key, value)?))).collect())
+                }
+            })
+            .collect();
+        ShardedMap { shards }
+    }
+}
+
+impl<V: Clone> ShardedMap<V> {
+    /// Copies the shard holding `key` if it is shared
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        let shard = &mut self.shards[shard_of(key)];
+        if !shard.contains_key(key) {
+            return None;
+        }
+        Arc::make_mut(shard).get_mut(key)
+    }
+
+    /// Copies the shard holding `key` if it is shared
+    pub fn insert(&mut self, key: String, value: V) -> Option<V> {
+        Arc::make_mut(&mut self.shards[shard_of(&key)]).insert(key, value)
+    }
+
+    /// Copies the shard holding `key` if it is shared and holds `key`
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let shard = &mut self.shards[shard_of(key)];
+        if !shard.contains_key(key) {
+            return None;
+        }
+        Arc::make_mut(shard).remove(key)
+    }
+}
+
+impl<V: PartialEq> PartialEq for ShardedMap<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.shards
+            .iter()
+            .zip(&other.shards)
+            .all(|(a, b)| Arc::ptr_eq(a, b) || a == b)
+    }
+}
+
+impl<V> Index<&str> for ShardedMap<V> {
+    type Output = V;
+
+    fn index(&self, key: &str) -> &V {
+        self.get(key).expect("key not in ShardedMap")
+    }
+}
+
+impl<V: Clone> FromIterator<(String, V)> for ShardedMap<V> {
+    fn from_iter<I: IntoIterator<Item = (String, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+impl<V: Clone> From<HashMap<String, V>> for ShardedMap<V> {
+    fn from(map: HashMap<String, V>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_updates_copy_only_the_touched_shard() {
+        let before: ShardedMap<u32> = (0..1000).map(|i| (format!("layer_{}", i), i)).collect();
+        let mut after = before.clone();
+        after.insert("layer_7".to_string(), 70);
+        after.remove("missing");
+
+        let changed: Vec<_> = after.changed_shards(&before).collect();
+        assert_eq!(changed.len(), 1);
+        let (old, new) = changed[0];
+        assert_eq!((old["layer_7"], new["layer_7"]), (7, 70));
+        assert_eq!(after.len(), 1000);
+        assert_ne!(after, before);
+
+        // Mapping reuses every shard the source shares
+        let doubled = before.map_shards(&ShardedMap::default(), &ShardedMap::default(), |_, v| Some(v * 2));
+        let remapped = after.map_shards(&before, &doubled, |_, v| Some(v * 2));
+        assert_eq!(remapped.changed_shards(&doubled).count(), 1);
+        assert_eq!((remapped["layer_7"], remapped["layer_8"]), (140, 16));
+    }
+}
@@ -0,0 +1,245 @@
+use crate::error::{ExperimentError, Result};
+use crate::layer::Snapshot;
+use crate::merge::{merge_layers_batch, ExperimentRequest};
+use crate::rule::FieldType;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Seed used when the caller does not provide one
+pub const DEFAULT_SEED: u64 = 42;
+
+/// Upper bound on synthetic population size per simulation request
+pub const MAX_POPULATION: usize = 1_000_000;
+
+/// Deterministic RNG for simulation and tests.
+///
+/// ChaCha8 is portable across platforms and `rand` versions, so the same seed
+/// always produces the same sequence (unlike `thread_rng` or `StdRng`).
+pub type SimRng = ChaCha8Rng;
+
+/// Create a seeded RNG
+pub fn seeded_rng(seed: u64) -> SimRng {
+    ChaCha8Rng::seed_from_u64(seed)
+}
+
+/// Generate a synthetic subject key (e.g. "user_1f3a...")
+pub fn random_subject_key(rng: &mut SimRng, prefix: &str) -> String {
+    format!("{}_{:016x}", prefix, rng.gen::<u64>())
+}
+
+/// Simulation request: evaluate a synthetic population against the current layers
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimulateRequest {
+    pub services: Vec<String>,
+
+    /// Context shared by every synthetic subject (hash key is filled in per subject)
+    #[serde(default)]
+    pub context: HashMap<String, serde_json::Value>,
+
+    /// Context field that receives the generated subject key
+    #[serde(default = "default_hash_key")]
+    pub hash_key: String,
+
+    pub population: usize,
+
+    #[serde(default)]
+    pub seed: Option<u64>,
+
+    #[serde(default)]
+    pub layers: Vec<String>,
+}
+
+fn default_hash_key() -> String {
+    "user_id".to_string()
+}
+
+/// Per-service assignment counts
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ServiceSimulation {
+    pub vid_counts: BTreeMap<i64, u64>,
+    pub layer_counts: BTreeMap<String, u64>,
+    /// Subjects that matched no layer for this service
+    pub unassigned: u64,
+}
+
+/// Simulation response (ordered maps so output is byte-for-byte reproducible)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SimulateResponse {
+    pub seed: u64,
+    pub population: usize,
+    pub results: BTreeMap<String, ServiceSimulation>,
+}
+
+/// Run a simulation against one config snapshot. Same seed + same config = same output.
+pub fn simulate(
+    request: &SimulateRequest,
+    snapshot: &Snapshot,
+    field_types: &HashMap<String, FieldType>,
+) -> Result<SimulateResponse> {
+    if request.population > MAX_POPULATION {
+        return Err(ExperimentError::InvalidParameter(format!(
+            "Simulation population {} exceeds limit {}",
+            request.population, MAX_POPULATION
+        )));
+    }
+
+    let seed = request.seed.unwrap_or(DEFAULT_SEED);
+    let mut rng = seeded_rng(seed);
+
+    let mut results: BTreeMap<String, ServiceSimulation> = request
+        .services
+        .iter()
+        .map(|s| (s.clone(), ServiceSimulation::default()))
+        .collect();
+
+    let mut eval_request = ExperimentRequest {
+        services: request.services.clone(),
+        context: request.context.clone(),
+        layers: request.layers.clone(),
+        diagnostics: false,
+    };
+
+    for _ in 0..request.population {
+        let key = random_subject_key(&mut rng, "subject");
+        eval_request
+            .context
+            .insert(request.hash_key.clone(), serde_json::Value::String(key));
+
+        let response = merge_layers_batch(&eval_request, snapshot, field_types)?;
+
+        for (service, result) in response.results {
+            let sim = results.entry(service).or_default();
+            if result.vids.is_empty() {
+                sim.unassigned += 1;
+            }
+            for vid in result.vids {
+                *sim.vid_counts.entry(vid).or_default() += 1;
+            }
+            for layer_id in result.matched_layers {
+                *sim.layer_counts.entry(layer_id).or_default() += 1;
+            }
+        }
+    }
+
+    Ok(SimulateResponse {
+        seed,
+        population: request.population,
+        results,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
+    use crate::layer::{BucketRange, Layer, LayerManager};
+    use serde_json::json;
+    use tempfile::TempDir;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_seeded_rng_is_reproducible() {
+        let mut a = seeded_rng(7);
+        let mut b = seeded_rng(7);
+        let mut c = seeded_rng(8);
+
+        let keys_a: Vec<String> = (0..10).map(|_| random_subject_key(&mut a, "u")).collect();
+        let keys_b: Vec<String> = (0..10).map(|_| random_subject_key(&mut b, "u")).collect();
+        let keys_c: Vec<String> = (0..10).map(|_| random_subject_key(&mut c, "u")).collect();
+
+        assert_eq!(keys_a, keys_b);
+        assert_ne!(keys_a, keys_c);
+    }
+
+    #[tokio::test]
+    async fn test_simulate_reproducible() {
+        let temp_dir = TempDir::new().unwrap();
+        let layers_dir = temp_dir.path().join("layers");
+        let experiments_dir = temp_dir.path().join("experiments");
+        std::fs::create_dir_all(&layers_dir).unwrap();
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+
+        let exp = ExperimentDef {
+            eid: 100,
+            service: "svc".to_string(),
+            expires_at: None,
+            state: Default::default(),
+            caps: None,
+            rule: None,
+            exposure_sampling: None,
+            variants: vec![
+                VariantDef {
+                    vid: 1001,
+                    params: json!({"arm": "a"}),
+                    dark_params: None,
+                },
+                VariantDef {
+                    vid: 1002,
+                    params: json!({"arm": "b"}),
+                    dark_params: None,
+                },
+            ],
+        };
+        std::fs::write(
+            experiments_dir.join("100.json"),
+            serde_json::to_string_pretty(&exp).unwrap(),
+        )
+        .unwrap();
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+
+        let layer = Layer {
+            layer_id: "sim_layer".to_string(),
+            version: "v1".to_string(),
+            priority: 100,
+            hash_key: "user_id".into(),
+            salt: None,
+            bucket_size: None,
+            expires_at: None,
+            rule: None,
+            services: vec![],
+            ranges: vec![
+                BucketRange {
+                    start: 0,
+                    end: 5000,
+                    vid: 1001,
+                    label: None,
+                },
+                BucketRange {
+                    start: 5000,
+                    end: 8000,
+                    vid: 1002,
+                    label: None,
+                },
+            ],
+            enabled: true,
+            aa_test: false,
+        };
+        std::fs::write(
+            layers_dir.join("sim_layer.json"),
+            serde_json::to_string_pretty(&layer).unwrap(),
+        )
+        .unwrap();
+        let manager = LayerManager::new(layers_dir);
+        manager.load_all_layers(&catalog).await.unwrap();
+
+        let request = SimulateRequest {
+            services: vec!["svc".to_string()],
+            context: HashMap::new(),
+            hash_key: "user_id".to_string(),
+            population: 1000,
+            seed: Some(1234),
+            layers: vec![],
+        };
+        let field_types = HashMap::new();
+
+        let first = simulate(&request, &manager.snapshot(), &field_types).unwrap();
+        let second = simulate(&request, &manager.snapshot(), &field_types).unwrap();
+        assert_eq!(first, second);
+
+        let svc = &first.results["svc"];
+        let assigned: u64 = svc.vid_counts.values().sum();
+        assert_eq!(assigned + svc.unassigned, 1000);
+    }
+}
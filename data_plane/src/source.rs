@@ -0,0 +1,373 @@
+//! Config sources: the primary layers/experiments directories, optional
+//! overlay directories that take precedence over it, and runtime switchover of
+//! the primary source (`POST /admin/config_source` or SIGUSR2).
+//!
+//! Precedence, highest first: overlays in the order configured
+//! (`CONFIG_OVERLAY_DIRS`), then the primary source. A layer or experiment
+//! defined by several sources is taken from the highest one; the shadowed
+//! definitions are reported as [`SourceConflict`]s.
+
+use crate::config::migrate;
+use crate::error::{ExperimentError, ResourceKind, Result};
+use crate::health::ConfigHealth;
+use crate::layer::LayerManager;
+use crate::manifest;
+use crate::metrics;
+use crate::watcher::{self, WatchOptions};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// Where layers and experiments are loaded from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigSource {
+    pub layers_dir: PathBuf,
+    pub experiments_dir: PathBuf,
+}
+
+impl ConfigSource {
+    /// Read a source description (JSON or YAML) from a file
+    pub fn from_file(path: &std::path::Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_value(migrate::parse_document(&content)?)?)
+    }
+
+    pub(crate) fn check_dirs(&self) -> Result<()> {
+        for dir in [&self.layers_dir, &self.experiments_dir] {
+            if !dir.is_dir() {
+                return Err(ExperimentError::InvalidParameter(format!(
+                    "Config source directory does not exist: {}",
+                    dir.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A resource defined by more than one source
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceConflict {
+    pub kind: ResourceKind,
+    /// layer_id or eid of the shadowed definition
+    pub id: String,
+    /// File that is in effect
+    pub winner: PathBuf,
+    /// Lower-precedence file that was ignored
+    pub shadowed: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// `layers/` of each overlay root, highest precedence first
+pub fn overlay_layer_dirs(roots: &[PathBuf]) -> Vec<PathBuf> {
+    roots.iter().map(|root| root.join("layers")).collect()
+}
+
+/// `experiments/` of each overlay root, highest precedence first
+pub fn overlay_experiment_dirs(roots: &[PathBuf]) -> Vec<PathBuf> {
+    roots.iter().map(|root| root.join("experiments")).collect()
+}
+
+/// Precedence rank of a file: index of the overlay directory containing it
+/// (0 = highest), or `overlay_dirs.len()` for the primary source
+pub fn precedence(path: &Path, overlay_dirs: &[PathBuf]) -> usize {
+    overlay_dirs
+        .iter()
+        .position(|dir| path.parent() == Some(dir.as_path()))
+        .unwrap_or(overlay_dirs.len())
+}
+
+/// `primary`, or `overlay_<n>` for the overlay directory of precedence rank n
+pub fn source_label(path: &Path, overlay_dirs: &[PathBuf]) -> String {
+    match precedence(path, overlay_dirs) {
+        rank if rank == overlay_dirs.len() => "primary".to_string(),
+        rank => format!("overlay_{}", rank),
+    }
+}
+
+/// Record how long the change in `path` took from being written (its mtime)
+/// to serving. Files with an mtime in the future (clock skew) are skipped.
+pub fn record_propagation(resource: &str, path: &Path, overlay_dirs: &[PathBuf]) {
+    let Ok(written) = std::fs::metadata(path).and_then(|meta| meta.modified()) else {
+        return;
+    };
+    if let Ok(latency) = std::time::SystemTime::now().duration_since(written) {
+        metrics::CONFIG_PROPAGATION_SECONDS
+            .with_label_values(&[resource, &source_label(path, overlay_dirs)])
+            .observe(latency.as_secs_f64());
+    }
+}
+
+/// Owns the config watcher so it can be restarted against a new source
+pub struct SourceSwitcher {
+    manager: Arc<LayerManager>,
+    health: Arc<ConfigHealth>,
+    options: WatchOptions,
+    /// Held for the whole switch so concurrent switches serialize
+    watcher: Mutex<JoinHandle<()>>,
+    /// Runtime the watcher runs on (see [`crate::runtime`])
+    runtime: Handle,
+}
+
+impl SourceSwitcher {
+    /// Start watching the current source; watchers run on the runtime this is called from
+    pub fn start(manager: Arc<LayerManager>, health: Arc<ConfigHealth>, options: WatchOptions) -> Arc<Self> {
+        let runtime = Handle::current();
+        let watcher = spawn_watcher(&runtime, &manager, &health, options);
+        Arc::new(Self {
+            manager,
+            health,
+            options,
+            watcher: Mutex::new(watcher),
+            runtime,
+        })
+    }
+
+    /// Runtime the watcher and other config processing run on
+    pub fn runtime(&self) -> &Handle {
+        &self.runtime
+    }
+
+    pub fn current(&self) -> ConfigSource {
+        ConfigSource {
+            layers_dir: self.manager.layers_dir(),
+            experiments_dir: self.manager.snapshot().catalog().source_dir().to_path_buf(),
+        }
+    }
+
+    /// Switch to `source`: stop the old watcher, load and validate the new
+    /// source, swap it in, then watch it. On failure the previous source keeps
+    /// serving and is watched again.
+    pub async fn switch(&self, source: ConfigSource) -> Result<()> {
+        let mut watcher = self.watcher.lock().await;
+
+        // Drain: no more events from the old source get applied
+        watcher.abort();
+        let _ = (&mut *watcher).await;
+
+        let result = self.bootstrap(&source).await;
+        match &result {
+            Ok(()) => {
+                tracing::info!("Switched config source to {:?}", source);
+                metrics::CONFIG_SOURCE_SWITCHES.with_label_values(&["success"]).inc();
+            }
+            Err(e) => {
+                tracing::error!("Config source switch to {:?} failed, keeping current source: {}", source, e);
+                metrics::CONFIG_SOURCE_SWITCHES.with_label_values(&["failure"]).inc();
+            }
+        }
+
+        *watcher = spawn_watcher(&self.runtime, &self.manager, &self.health, self.options);
+        result
+    }
+
+    /// Restart the watcher against the current source (used by [`crate::watchdog`])
+    pub async fn restart_watcher(&self) {
+        let mut watcher = self.watcher.lock().await;
+        watcher.abort();
+        let _ = (&mut *watcher).await;
+        *watcher = spawn_watcher(&self.runtime, &self.manager, &self.health, self.options);
+    }
+
+    /// Whether the watcher task ended (error or panic); false while a switch is running
+    pub fn watcher_exited(&self) -> bool {
+        self.watcher.try_lock().is_ok_and(|watcher| watcher.is_finished())
+    }
+
+    /// Load and validate `source` against a detached copy of the config,
+    /// without touching what is served (used by [`crate::failover`])
+    pub async fn probe(&self, source: &ConfigSource) -> Result<()> {
+        source.check_dirs()?;
+        let new_catalog = Arc::new(self.manager.snapshot().catalog().load_source(source.experiments_dir.clone())?);
+        self.manager
+            .fork()
+            .switch_source(source.layers_dir.clone(), &new_catalog)
+            .await
+    }
+
+    async fn bootstrap(&self, source: &ConfigSource) -> Result<()> {
+        source.check_dirs()?;
+
+        // Overlays and params mode stay in place across switches
+        let new_catalog = Arc::new(self.manager.snapshot().catalog().load_source(source.experiments_dir.clone())?);
+        // Layers are validated against the new catalog and published in one snapshot with it
+        self.manager
+            .switch_source(source.layers_dir.clone(), &new_catalog)
+            .await?;
+        self.health.mark_healthy();
+        manifest::check(&self.manager, &self.health);
+
+        Ok(())
+    }
+}
+
+fn spawn_watcher(
+    runtime: &Handle,
+    manager: &Arc<LayerManager>,
+    health: &Arc<ConfigHealth>,
+    options: WatchOptions,
+) -> JoinHandle<()> {
+    let (manager, health) = (manager.clone(), health.clone());
+    runtime.spawn(async move {
+        if let Err(e) = watcher::watch_config(manager, health, options).await {
+            tracing::error!("Watcher error: {}", e);
+        }
+    })
+}
+
+/// On SIGUSR2, switch to the source described in `source_file`
+#[cfg(all(unix, not(feature = "edge")))]
+pub async fn switch_on_signal(switcher: Arc<SourceSwitcher>, source_file: PathBuf) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined2()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            tracing::error!("Failed to install SIGUSR2 handler: {}", e);
+            return;
+        }
+    };
+
+    while signals.recv().await.is_some() {
+        tracing::info!("SIGUSR2: switching config source from {:?}", source_file);
+        match ConfigSource::from_file(&source_file) {
+            Ok(source) => {
+                let _ = switcher.switch(source).await;
+            }
+            Err(e) => tracing::error!("Failed to read config source file {:?}: {}", source_file, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::ExperimentCatalog;
+    use tempfile::TempDir;
+
+    fn write_source(root: &std::path::Path, layer_id: &str, vid: i64) -> ConfigSource {
+        let source = ConfigSource {
+            layers_dir: root.join("layers"),
+            experiments_dir: root.join("experiments"),
+        };
+        std::fs::create_dir_all(&source.layers_dir).unwrap();
+        std::fs::create_dir_all(&source.experiments_dir).unwrap();
+
+        let exp = serde_json::json!({
+            "eid": vid - 1,
+            "service": "svc",
+            "variants": [{"vid": vid, "params": {}}]
+        });
+        std::fs::write(source.experiments_dir.join("exp.json"), exp.to_string()).unwrap();
+        let layer = serde_json::json!({
+            "layer_id": layer_id,
+            "version": "v1",
+            "priority": 1,
+            "hash_key": "user_id",
+            "enabled": true,
+            "ranges": [{"start": 0, "end": 10000, "vid": vid}]
+        });
+        std::fs::write(source.layers_dir.join(format!("{}.json", layer_id)), layer.to_string()).unwrap();
+        source
+    }
+
+    #[tokio::test]
+    async fn test_switch_source() {
+        let old_root = TempDir::new().unwrap();
+        let new_root = TempDir::new().unwrap();
+        let old = write_source(old_root.path(), "old_layer", 101);
+        let new = write_source(new_root.path(), "new_layer", 201);
+
+        let catalog = Arc::new(ExperimentCatalog::load_from_dir(old.experiments_dir.clone()).unwrap());
+        let manager = Arc::new(LayerManager::new(old.layers_dir.clone()));
+        manager.load_all_layers(&catalog).await.unwrap();
+
+        let switcher = SourceSwitcher::start(manager.clone(), Arc::new(ConfigHealth::new()), WatchOptions::default());
+
+        // Missing directory: rejected, old source keeps serving
+        let missing = ConfigSource {
+            layers_dir: new_root.path().join("nope"),
+            experiments_dir: new.experiments_dir.clone(),
+        };
+        assert!(switcher.switch(missing).await.is_err());
+        assert_eq!(switcher.current(), old);
+        assert!(manager.get_layer("old_layer").is_some());
+
+        switcher.switch(new.clone()).await.unwrap();
+        assert_eq!(switcher.current(), new);
+        assert!(manager.get_layer("old_layer").is_none());
+        assert_eq!(manager.get_layers_for_service("svc")[0].layer_id, "new_layer");
+        assert!(manager.snapshot().catalog().get_variant(201).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_overlay_precedence() {
+        let root = TempDir::new().unwrap();
+        let primary = write_source(&root.path().join("primary"), "shared", 101);
+        write_source(&root.path().join("overlay"), "shared", 201);
+        let overlays = vec![root.path().join("overlay")];
+
+        // Overlay experiment reusing a primary vid shadows the whole primary experiment
+        let clash = serde_json::json!({
+            "eid": 300,
+            "service": "svc",
+            "variants": [{"vid": 101, "params": {}}]
+        });
+        std::fs::write(root.path().join("overlay/experiments/clash.json"), clash.to_string()).unwrap();
+
+        let catalog = Arc::new(
+            ExperimentCatalog::load_from_dirs(primary.experiments_dir.clone(), overlay_experiment_dirs(&overlays))
+                .unwrap(),
+        );
+        assert!(catalog.get_experiment(100).is_none());
+        assert_eq!(catalog.get_variant(101).unwrap().0, 300);
+        assert_eq!(catalog.conflicts().len(), 1);
+        assert_eq!(catalog.conflicts()[0].id, "100");
+        assert!(catalog.conflicts()[0].detail.as_deref().unwrap().contains("vid 101"));
+
+        let manager = LayerManager::new(primary.layers_dir.clone()).with_overlays(overlay_layer_dirs(&overlays));
+        manager.load_all_layers(&catalog).await.unwrap();
+        assert_eq!(manager.get_layer("shared").unwrap().ranges[0].vid, 201);
+        assert!(manager.is_overridden("shared"));
+
+        let conflicts = manager.source_conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].shadowed, primary.layers_dir.join("shared.json"));
+
+        // A primary file event can't replace the overlay definition
+        manager
+            .load_layer("shared", &primary.layers_dir.join("shared.json"), &catalog)
+            .await
+            .unwrap();
+        assert_eq!(manager.get_layer("shared").unwrap().ranges[0].vid, 201);
+    }
+
+    #[test]
+    fn test_propagation_recorded_per_source() {
+        let root = TempDir::new().unwrap();
+        let primary = write_source(&root.path().join("primary"), "shared", 101);
+        let overlay = write_source(&root.path().join("overlay"), "shared", 201);
+        let overlay_dirs = vec![overlay.layers_dir.clone()];
+
+        let primary_file = primary.layers_dir.join("shared.json");
+        let overlay_file = overlay.layers_dir.join("shared.json");
+        assert_eq!(source_label(&primary_file, &overlay_dirs), "primary");
+        assert_eq!(source_label(&overlay_file, &overlay_dirs), "overlay_0");
+
+        let observed = || {
+            metrics::CONFIG_PROPAGATION_SECONDS
+                .with_label_values(&["layer", "overlay_0"])
+                .get_sample_count()
+        };
+        let before = observed();
+        record_propagation("layer", &overlay_file, &overlay_dirs);
+        assert_eq!(observed(), before + 1);
+        // Nothing to measure without the file
+        record_propagation("layer", &overlay.layers_dir.join("gone.json"), &overlay_dirs);
+        assert_eq!(observed(), before + 1);
+    }
+}
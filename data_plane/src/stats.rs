@@ -0,0 +1,226 @@
+//! In-memory traffic counters.
+//!
+//! [`TrafficStats`] counts assignments served by this instance since it
+//! started, for the dashboard of deployments without external observability.
+//! Keyed by vid so counting stays cheap; experiments are resolved when the
+//! counters are read.
+//!
+//! [`ServiceLoad`] counts evaluations per service with rolling rates, for
+//! capacity planning (`GET /stats/services`).
+
+use crate::metrics;
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Default)]
+pub struct TrafficStats {
+    hits: RwLock<HashMap<i64, AtomicU64>>,
+}
+
+impl TrafficStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count one assignment to each vid
+    pub fn record(&self, vids: &[i64]) {
+        let mut missing = Vec::new();
+        {
+            let hits = self.hits.read();
+            for vid in vids {
+                match hits.get(vid) {
+                    Some(count) => {
+                        count.fetch_add(1, Ordering::Relaxed);
+                    }
+                    None => missing.push(*vid),
+                }
+            }
+        }
+
+        // First hit of a vid takes the write lock once
+        if !missing.is_empty() {
+            let mut hits = self.hits.write();
+            for vid in missing {
+                hits.entry(vid).or_default().fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Assignments per vid so far
+    pub fn counts(&self) -> HashMap<i64, u64> {
+        self.hits
+            .read()
+            .iter()
+            .map(|(vid, count)| (*vid, count.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+/// Seconds of history kept for rolling rates
+const WINDOW_SECS: u64 = 60;
+
+/// Evaluations per second, one slot per second of the last [`WINDOW_SECS`]
+#[derive(Debug)]
+struct RateWindow {
+    /// (unix second, count)
+    slots: [(u64, u64); WINDOW_SECS as usize],
+}
+
+impl RateWindow {
+    fn record(&mut self, now: u64) {
+        let slot = &mut self.slots[(now % WINDOW_SECS) as usize];
+        if slot.0 != now {
+            *slot = (now, 0);
+        }
+        slot.1 += 1;
+    }
+
+    /// Average per second over the `secs` seconds before `now` (the current
+    /// partial second excluded)
+    fn rate(&self, now: u64, secs: u64) -> f64 {
+        let count: u64 = self
+            .slots
+            .iter()
+            .filter(|(at, _)| *at < now && now - at <= secs)
+            .map(|(_, count)| count)
+            .sum();
+        count as f64 / secs as f64
+    }
+}
+
+#[derive(Debug)]
+struct ServiceCounter {
+    total: AtomicU64,
+    window: Mutex<RateWindow>,
+}
+
+/// One service's evaluation load
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServiceRate {
+    pub service: String,
+    /// Evaluations since this instance started
+    pub total: u64,
+    pub qps_10s: f64,
+    pub qps_1m: f64,
+}
+
+/// Per-service evaluation counters with rolling rates
+#[derive(Debug, Default)]
+pub struct ServiceLoad {
+    services: RwLock<HashMap<String, ServiceCounter>>,
+}
+
+impl ServiceLoad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count one evaluation of `service`
+    pub fn record(&self, service: &str) {
+        self.record_at(service, unix_now());
+    }
+
+    fn record_at(&self, service: &str, now: u64) {
+        metrics::SERVICE_EVALUATIONS.with_label_values(&[service]).inc();
+        {
+            let services = self.services.read();
+            if let Some(counter) = services.get(service) {
+                counter.total.fetch_add(1, Ordering::Relaxed);
+                counter.window.lock().record(now);
+                return;
+            }
+        }
+
+        let mut services = self.services.write();
+        let counter = services.entry(service.to_string()).or_insert_with(|| ServiceCounter {
+            total: AtomicU64::new(0),
+            window: Mutex::new(RateWindow {
+                slots: [(0, 0); WINDOW_SECS as usize],
+            }),
+        });
+        counter.total.fetch_add(1, Ordering::Relaxed);
+        counter.window.lock().record(now);
+    }
+
+    /// Services by 1-minute rate, heaviest first; `top` limits the list
+    pub fn rates(&self, top: Option<usize>) -> Vec<ServiceRate> {
+        self.rates_at(unix_now(), top)
+    }
+
+    fn rates_at(&self, now: u64, top: Option<usize>) -> Vec<ServiceRate> {
+        let mut rates: Vec<ServiceRate> = self
+            .services
+            .read()
+            .iter()
+            .map(|(service, counter)| {
+                let window = counter.window.lock();
+                ServiceRate {
+                    service: service.clone(),
+                    total: counter.total.load(Ordering::Relaxed),
+                    qps_10s: window.rate(now, 10),
+                    qps_1m: window.rate(now, WINDOW_SECS),
+                }
+            })
+            .collect();
+        rates.sort_by(|a, b| {
+            b.qps_1m
+                .total_cmp(&a.qps_1m)
+                .then_with(|| b.total.cmp(&a.total))
+                .then_with(|| a.service.cmp(&b.service))
+        });
+        if let Some(top) = top {
+            rates.truncate(top);
+        }
+        rates
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_counts_per_vid() {
+        let stats = TrafficStats::new();
+        stats.record(&[1001, 2001]);
+        stats.record(&[1001]);
+        stats.record(&[]);
+
+        let counts = stats.counts();
+        assert_eq!(counts[&1001], 2);
+        assert_eq!(counts[&2001], 1);
+        assert_eq!(counts.len(), 2);
+    }
+
+    #[test]
+    fn test_service_rates_heaviest_first() {
+        let load = ServiceLoad::new();
+        let now = 1_000_000;
+        for _ in 0..120 {
+            load.record_at("ranker", now - 30);
+        }
+        for _ in 0..20 {
+            load.record_at("search", now - 5);
+        }
+        load.record_at("search", now);
+
+        let rates = load.rates_at(now, None);
+        assert_eq!(rates[0].service, "ranker");
+        assert_eq!(rates[0].qps_1m, 2.0);
+        assert_eq!(rates[0].qps_10s, 0.0);
+        // The current partial second isn't counted in rates
+        assert_eq!(rates[1].total, 21);
+        assert_eq!(rates[1].qps_10s, 2.0);
+        assert_eq!(load.rates_at(now, Some(1)).len(), 1);
+    }
+}
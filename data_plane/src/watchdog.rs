@@ -0,0 +1,136 @@
+//! Config pipeline watchdog.
+//!
+//! Long-running config tasks (the file watcher, periodic resync) beat a
+//! [`Heartbeat`] registered in [`ConfigHealth`]. A task that panicked, exited
+//! or got stuck in an apply stops beating; without a watchdog the process
+//! would keep serving its last snapshot with nobody noticing.
+//!
+//! [`supervise`] checks heartbeats periodically. A stalled watcher is
+//! restarted (up to `max_restarts` times in a row); any task still stalled
+//! after that fails `/ready` until it beats again.
+
+use crate::health::ConfigHealth;
+use crate::metrics;
+use crate::source::SourceSwitcher;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Name of the file watcher's heartbeat
+pub const WATCHER_TASK: &str = "watcher";
+
+/// Liveness signal of one task
+#[derive(Debug)]
+pub struct Heartbeat {
+    /// Expected time between beats
+    interval: Duration,
+    /// Unix millis of the last beat
+    last: AtomicU64,
+}
+
+impl Heartbeat {
+    pub(crate) fn new(interval: Duration) -> Self {
+        let heartbeat = Self {
+            interval,
+            last: AtomicU64::new(0),
+        };
+        heartbeat.beat();
+        heartbeat
+    }
+
+    pub fn beat(&self) {
+        self.last.store(unix_millis(), Ordering::Relaxed);
+    }
+
+    /// Time since the last beat beyond the expected interval
+    pub(crate) fn overdue(&self, now_millis: u64) -> Duration {
+        let age = Duration::from_millis(now_millis.saturating_sub(self.last.load(Ordering::Relaxed)));
+        age.saturating_sub(self.interval)
+    }
+}
+
+/// A task that missed its heartbeat, as reported by `/ready`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StalledTask {
+    pub task: String,
+    /// Seconds since the task was expected to beat
+    pub overdue_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WatchdogOptions {
+    /// How often heartbeats are checked
+    pub interval: Duration,
+    /// Grace period past a task's beat interval before it counts as stalled
+    pub stall_after: Duration,
+    /// Consecutive watcher restarts before giving up and failing readiness
+    pub max_restarts: u32,
+}
+
+/// Check heartbeats every `options.interval`, restarting a stalled or exited watcher
+pub async fn supervise(health: Arc<ConfigHealth>, switcher: Arc<SourceSwitcher>, options: WatchdogOptions) {
+    let mut ticker = tokio::time::interval(options.interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut restarts = 0;
+
+    loop {
+        ticker.tick().await;
+
+        let mut stalled = health.stalled_tasks(options.stall_after);
+        let watcher_stalled = switcher.watcher_exited() || stalled.iter().any(|t| t.task == WATCHER_TASK);
+        if !watcher_stalled {
+            restarts = 0;
+        } else if restarts < options.max_restarts {
+            restarts += 1;
+            tracing::error!(
+                "Config watcher stalled or exited, restarting (attempt {}/{})",
+                restarts,
+                options.max_restarts
+            );
+            metrics::WATCHDOG_RESTARTS.with_label_values(&[WATCHER_TASK]).inc();
+            switcher.restart_watcher().await;
+            // The restarted watcher beats afresh; judge it on the next check
+            stalled.retain(|t| t.task != WATCHER_TASK);
+        } else if !stalled.iter().any(|t| t.task == WATCHER_TASK) {
+            stalled.push(StalledTask {
+                task: WATCHER_TASK.to_string(),
+                overdue_secs: 0,
+            });
+        }
+
+        for task in &stalled {
+            tracing::error!("Config task '{}' stalled ({}s overdue), failing readiness", task.task, task.overdue_secs);
+        }
+        metrics::WATCHDOG_STALLED_TASKS.set(stalled.len() as i64);
+        health.set_stalled(stalled);
+    }
+}
+
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stalled_after_missing_beats() {
+        let health = ConfigHealth::new();
+        let heartbeat = health.heartbeat("resync", Duration::from_secs(30));
+        assert!(health.stalled_tasks(Duration::from_secs(10)).is_empty());
+
+        // 50s without a beat: 20s past the interval, beyond the 10s grace
+        heartbeat.last.fetch_sub(50_000, Ordering::Relaxed);
+        let stalled = health.stalled_tasks(Duration::from_secs(10));
+        assert_eq!(stalled[0].task, "resync");
+        assert_eq!(stalled[0].overdue_secs, 20);
+
+        heartbeat.beat();
+        assert!(health.stalled_tasks(Duration::from_secs(10)).is_empty());
+    }
+}
@@ -1,102 +1,257 @@
-use crate::catalog::ExperimentCatalog;
+use crate::applied::AppliedChange;
+use crate::freeze;
+use crate::health::ConfigHealth;
 use crate::layer::LayerManager;
-use anyhow::Result;
-use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
-use std::path::Path;
+use crate::manifest;
+use crate::metrics;
+use crate::source;
 use std::sync::Arc;
-use tokio::sync::mpsc;
-
-/// Watch layers directory for changes and hot reload
-pub async fn watch_layers(manager: Arc<LayerManager>, catalog: Arc<ExperimentCatalog>) -> Result<()> {
-    let (tx, mut rx) = mpsc::channel(100);
-    
-    let layers_dir = manager.layers_dir.clone();
-    
-    // Create watcher
-    let mut watcher = RecommendedWatcher::new(
-        move |res: notify::Result<Event>| {
-            if let Ok(event) = res {
-                let _ = tx.blocking_send(event);
-            }
-        },
-        Config::default(),
-    )?;
-    
-    // Watch the layers directory
-    watcher.watch(&layers_dir, RecursiveMode::NonRecursive)?;
-    
-    tracing::info!("Watching layers directory: {:?}", layers_dir);
-    
-    // Process events
-    while let Some(event) = rx.recv().await {
-        match event.kind {
-            EventKind::Create(_) | EventKind::Modify(_) => {
-                for path in event.paths {
-                    if let Err(e) = handle_file_change(&manager, &catalog, &path).await {
-                        tracing::error!("Failed to handle file change {:?}: {}", path, e);
-                    }
-                }
-            }
-            EventKind::Remove(_) => {
-                for path in event.paths {
-                    if let Err(e) = handle_file_remove(&manager, &catalog, &path).await {
-                        tracing::error!("Failed to handle file remove {:?}: {}", path, e);
-                    }
+use std::time::Duration;
+
+#[cfg(feature = "watcher")]
+mod events;
+
+#[cfg(feature = "watcher")]
+pub use events::watch_config;
+
+/// Watcher tuning
+#[derive(Debug, Clone, Copy)]
+pub struct WatchOptions {
+    /// Pending distinct changes before falling back to a full resync
+    pub queue_capacity: usize,
+    /// Quiet period a burst must go without a new event before it is applied
+    /// as one batch (each event restarts it)
+    pub debounce: Duration,
+    /// How often the directories are re-read in builds without the `watcher`
+    /// feature, which get no filesystem notifications (edge builds don't poll)
+    pub poll_interval: Duration,
+}
+
+/// How often an idle watcher beats its heartbeat (see [`crate::watchdog`])
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 100,
+            debounce: Duration::from_millis(100),
+            poll_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Without filesystem notifications, pick up what the control plane wrote to
+/// the config directories with a [`full_resync`] every `poll_interval`
+#[cfg(not(any(feature = "watcher", feature = "edge")))]
+pub async fn watch_config(
+    manager: Arc<LayerManager>,
+    health: Arc<ConfigHealth>,
+    options: WatchOptions,
+) -> anyhow::Result<()> {
+    tracing::info!(
+        "Polling config directories every {:?}: {:?}, {:?}",
+        options.poll_interval,
+        manager.layers_dir(),
+        manager.snapshot().catalog().source_dir()
+    );
+
+    // Beat between polls; a resync stuck applying stops the heartbeat
+    let heartbeat = health.heartbeat(crate::watchdog::WATCHER_TASK, HEARTBEAT_INTERVAL);
+    let mut beat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    beat.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut poll = tokio::time::interval(options.poll_interval);
+    poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // First tick fires immediately; startup already loaded everything
+    poll.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = beat.tick() => heartbeat.beat(),
+            _ = poll.tick() => full_resync(&manager, &health).await,
+        }
+    }
+}
+
+/// Edge builds receive config over xDS, which applies it directly; the config
+/// directories are only read at startup, so the watcher just beats its heartbeat
+#[cfg(all(feature = "edge", not(feature = "watcher")))]
+pub async fn watch_config(
+    _manager: Arc<LayerManager>,
+    health: Arc<ConfigHealth>,
+    _options: WatchOptions,
+) -> anyhow::Result<()> {
+    tracing::info!("Config directories are not watched; changes arrive over xDS");
+    let heartbeat = health.heartbeat(crate::watchdog::WATCHER_TASK, HEARTBEAT_INTERVAL);
+    let mut beat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    beat.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        beat.tick().await;
+        heartbeat.beat();
+    }
+}
+
+/// Re-read the catalog and every layer from disk, applying only what changed
+pub async fn full_resync(manager: &LayerManager, health: &ConfigHealth) {
+    tracing::debug!("Running full config resync");
+    metrics::RESYNC_TOTAL.inc();
+
+    // A failed catalog apply keeps the previous catalog; layers still resync against it
+    let _ = reload_catalog(manager, health).await;
+
+    match manager.resync(manager.snapshot().catalog()).await {
+        Ok(summary) if summary.is_empty() => {}
+        Ok(summary) => {
+            tracing::info!(
+                "Resync applied layer changes: added {:?}, updated {:?}, removed {:?}",
+                summary.added,
+                summary.updated,
+                summary.removed
+            );
+            metrics::RESYNC_CHANGES_TOTAL
+                .inc_by((summary.added.len() + summary.updated.len() + summary.removed.len()) as u64);
+            let snapshot = manager.snapshot();
+            for layer_id in summary.added.iter().chain(&summary.updated) {
+                if let Some(path) = snapshot.layer_file(layer_id) {
+                    source::record_propagation("layer", path, manager.overlay_dirs());
                 }
             }
-            _ => {}
+        }
+        Err(e) if freeze::is_queued(&e, manager) => {
+            tracing::info!("Deferred layer resync until the freeze window closes: {}", e);
+        }
+        Err(e) => {
+            tracing::error!("Full resync of layers failed: {}", e);
+            metrics::LAYER_RELOAD_ERRORS.inc();
         }
     }
-    
-    Ok(())
+
+    manifest::check(manager, health);
 }
 
-async fn handle_file_change(manager: &LayerManager, catalog: &ExperimentCatalog, path: &Path) -> Result<()> {
-    if !path.is_file() {
-        return Ok(());
+/// Safety net against missed watch events: run [`full_resync`] every `interval`
+pub async fn resync_periodically(
+    manager: Arc<LayerManager>,
+    health: Arc<ConfigHealth>,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // First tick fires immediately; startup already loaded everything
+    ticker.tick().await;
+    let heartbeat = health.heartbeat("resync", interval);
+
+    loop {
+        ticker.tick().await;
+        full_resync(&manager, &health).await;
+        heartbeat.beat();
     }
-    
-    // Check file extension
-    if let Some(ext) = path.extension() {
-        if ext == "json" || ext == "yaml" || ext == "yml" {
-            // Extract layer_id from filename (without extension)
-            if let Some(file_stem) = path.file_stem() {
-                let layer_id = file_stem.to_string_lossy();
-                
-                tracing::info!("Detected change in layer file: {:?}", path);
-                
-                // Add small delay to ensure file write is complete
-                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                
-                match manager.load_layer(&layer_id, path, catalog).await {
-                    Ok(_) => {
-                        tracing::info!("Hot reloaded layer: {}", layer_id);
-                        crate::metrics::LAYER_RELOAD_TOTAL.inc();
-                    }
-                    Err(e) => {
-                        tracing::error!("Failed to reload layer {}: {}", layer_id, e);
-                        crate::metrics::LAYER_RELOAD_ERRORS.inc();
-                    }
+}
+
+/// Rebuild the catalog from its source directory and publish it with the
+/// layers reindexed against it.
+///
+/// On failure (e.g. a duplicate vid) the previous catalog and layer index keep
+/// serving, the instance is marked degraded and `config_apply_failures` is bumped.
+pub async fn reload_catalog(manager: &LayerManager, health: &ConfigHealth) -> crate::error::Result<()> {
+    let previous = manager.snapshot().catalog().clone();
+    let result = previous.reload().map(Arc::new).and_then(|new_catalog| {
+        if new_catalog.same_experiments(&previous) && new_catalog.conflicts() == previous.conflicts() {
+            return Ok(None);
+        }
+        previous.check_transitions(&new_catalog)?;
+        // Published in the same snapshot as the reindexed layers, so layers never
+        // point at a catalog that failed to apply
+        manager.reindex(&new_catalog)?;
+        Ok(Some(new_catalog))
+    });
+
+    match result {
+        Ok(None) => {
+            tracing::debug!("Experiment catalog unchanged");
+            health.mark_healthy();
+            Ok(())
+        }
+        Ok(Some(new_catalog)) => {
+            let changes = previous.changes_to(&new_catalog);
+            tracing::info!(
+                "Reloaded experiment catalog: {} experiments (added {}, removed {}, modified {})",
+                new_catalog.len(),
+                changes.added,
+                changes.removed,
+                changes.modified
+            );
+            changes.record("experiment");
+            for (eid, change) in previous.experiment_changes(&new_catalog) {
+                if let Some(path) = new_catalog.file_of(eid).filter(|_| change != AppliedChange::Removed) {
+                    source::record_propagation("experiment", path, new_catalog.overlay_dirs());
                 }
             }
+            health.mark_healthy();
+            Ok(())
+        }
+        Err(e) if freeze::is_queued(&e, manager) => {
+            tracing::info!("Deferred experiment catalog until the freeze window closes: {}", e);
+            Ok(())
+        }
+        Err(e) => {
+            tracing::error!("Failed to apply experiment catalog, keeping previous snapshot: {}", e);
+            metrics::CONFIG_APPLY_FAILURES.inc();
+            health.mark_degraded(e.to_string());
+            Err(e)
         }
     }
-    
-    Ok(())
 }
 
-async fn handle_file_remove(manager: &LayerManager, catalog: &ExperimentCatalog, path: &Path) -> Result<()> {
-    if let Some(file_stem) = path.file_stem() {
-        let layer_id = file_stem.to_string_lossy();
-        
-        tracing::info!("Detected removal of layer file: {:?}", path);
-        
-        if let Err(e) = manager.remove_layer(&layer_id, catalog).await {
-            tracing::error!("Failed to remove layer {}: {}", layer_id, e);
-        } else {
-            tracing::info!("Removed layer: {}", layer_id);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn write_experiment(dir: &Path, eid: i64, vid: i64) {
+        let exp = ExperimentDef {
+            eid,
+            service: "svc".to_string(),
+            expires_at: None,
+            state: Default::default(),
+            caps: None,
+            rule: None,
+            exposure_sampling: None,
+            variants: vec![VariantDef {
+                vid,
+                params: serde_json::json!({}),
+                dark_params: None,
+            }],
+        };
+        std::fs::write(dir.join(format!("{}.json", eid)), serde_json::to_string(&exp).unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_catalog_reload_keeps_stale_snapshot_on_failure() {
+        let temp_dir = TempDir::new().unwrap();
+        let experiments_dir = temp_dir.path().join("experiments");
+        std::fs::create_dir_all(&experiments_dir).unwrap();
+        write_experiment(&experiments_dir, 100, 1001);
+
+        let manager = LayerManager::new(temp_dir.path().join("layers"));
+        manager
+            .reindex(&Arc::new(ExperimentCatalog::load_from_dir(experiments_dir.clone()).unwrap()))
+            .unwrap();
+        let health = ConfigHealth::new();
+
+        // Duplicate vid pushed by mistake: reload fails, old catalog keeps serving
+        write_experiment(&experiments_dir, 200, 1001);
+        assert!(reload_catalog(&manager, &health).await.is_err());
+        assert_eq!(manager.snapshot().catalog().len(), 1);
+        let degraded = health.degradation().unwrap();
+        assert!(degraded.reason.contains("Duplicate vid 1001"), "{}", degraded.reason);
+        assert_eq!(degraded.failures, 1);
+
+        // Fixing the push recovers
+        write_experiment(&experiments_dir, 200, 2001);
+        reload_catalog(&manager, &health).await.unwrap();
+        assert_eq!(manager.snapshot().catalog().len(), 2);
+        assert!(health.degradation().is_none());
     }
-    
-    Ok(())
 }
@@ -0,0 +1,364 @@
+//! Filesystem notifications for hot reload (feature `watcher`, on by default)
+
+use super::{full_resync, reload_catalog, WatchOptions, HEARTBEAT_INTERVAL};
+use crate::catalog::ExperimentCatalog;
+use crate::freeze;
+use crate::health::ConfigHealth;
+use crate::layer::LayerManager;
+use crate::manifest;
+use crate::metrics;
+use crate::source;
+use crate::watchdog;
+use anyhow::Result;
+use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Longest a continuous burst delays applying, in quiet periods
+const MAX_SETTLE_QUIET_PERIODS: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LayerChange {
+    Upsert,
+    Remove,
+}
+
+/// Changes accumulated since the last drain
+#[derive(Debug, Default, PartialEq)]
+struct PendingChanges {
+    /// Last change per layer file (repeated updates collapse into one)
+    layers: HashMap<PathBuf, LayerChange>,
+    catalog: bool,
+    /// Queue overflowed: individual changes were dropped, reload everything
+    full_resync: bool,
+}
+
+impl PendingChanges {
+    fn depth(&self) -> usize {
+        self.layers.len() + usize::from(self.catalog)
+    }
+}
+
+/// Coalescing change queue between the notify thread and the reload task.
+///
+/// Never blocks the producer: repeated updates to the same file collapse, and
+/// past `capacity` distinct changes the queue degrades to a single full resync.
+struct ChangeQueue {
+    pending: Mutex<PendingChanges>,
+    notify: Notify,
+    capacity: usize,
+}
+
+impl ChangeQueue {
+    fn new(capacity: usize) -> Self {
+        Self {
+            pending: Mutex::new(PendingChanges::default()),
+            notify: Notify::new(),
+            capacity,
+        }
+    }
+
+    fn push_layer(&self, path: PathBuf, change: LayerChange) {
+        let mut pending = self.pending.lock();
+        if !pending.full_resync && pending.layers.insert(path, change).is_some() {
+            metrics::WATCH_COALESCED_TOTAL.inc();
+        }
+        self.after_push(&mut pending);
+    }
+
+    fn push_catalog(&self) {
+        let mut pending = self.pending.lock();
+        if pending.catalog {
+            metrics::WATCH_COALESCED_TOTAL.inc();
+        }
+        pending.catalog = true;
+        self.after_push(&mut pending);
+    }
+
+    /// Overlay changes can shadow or unshadow anything: reload everything
+    fn push_full_resync(&self) {
+        let mut pending = self.pending.lock();
+        pending.full_resync = true;
+        pending.layers.clear();
+        self.after_push(&mut pending);
+    }
+
+    fn after_push(&self, pending: &mut PendingChanges) {
+        if !pending.full_resync && pending.depth() > self.capacity {
+            tracing::warn!(
+                "Config change queue overflowed ({} pending), forcing full resync",
+                pending.depth()
+            );
+            metrics::WATCH_OVERFLOW_TOTAL.inc();
+            pending.full_resync = true;
+            pending.layers.clear();
+        }
+
+        let depth = pending.depth() as i64;
+        metrics::WATCH_QUEUE_DEPTH.set(depth);
+        if depth > metrics::WATCH_QUEUE_HIGH_WATER.get() {
+            metrics::WATCH_QUEUE_HIGH_WATER.set(depth);
+        }
+        self.notify.notify_one();
+    }
+
+    /// Wait until no change arrived for `quiet`, restarting the wait on each
+    /// one, but no longer than [`MAX_SETTLE_QUIET_PERIODS`] quiet periods so a
+    /// writer that never stops still gets applied
+    async fn settle(&self, quiet: Duration) {
+        let deadline = tokio::time::Instant::now() + quiet * MAX_SETTLE_QUIET_PERIODS;
+        loop {
+            tokio::select! {
+                _ = self.notify.notified() => {}
+                _ = tokio::time::sleep(quiet) => return,
+                _ = tokio::time::sleep_until(deadline) => return,
+            }
+        }
+    }
+
+    fn drain(&self) -> PendingChanges {
+        let drained = std::mem::take(&mut *self.pending.lock());
+        metrics::WATCH_QUEUE_DEPTH.set(0);
+        drained
+    }
+}
+
+/// Watch the layers and experiments directories for changes and hot reload.
+///
+/// Layer files reload individually; any experiment file change rebuilds the
+/// whole catalog (see [`super::reload_catalog`]). Changes under an overlay
+/// directory trigger a [`full_resync`].
+pub async fn watch_config(
+    manager: Arc<LayerManager>,
+    health: Arc<ConfigHealth>,
+    options: WatchOptions,
+) -> Result<()> {
+    let queue = Arc::new(ChangeQueue::new(options.queue_capacity));
+
+    let layers_dir = manager.layers_dir();
+    let catalog = manager.snapshot().catalog().clone();
+    let experiments_dir = catalog.source_dir().to_path_buf();
+    // Event paths are absolute; compare against the canonical directory
+    let experiments_dir = std::fs::canonicalize(&experiments_dir).unwrap_or(experiments_dir);
+    let overlay_dirs: Vec<PathBuf> = manager
+        .overlay_dirs()
+        .iter()
+        .chain(catalog.overlay_dirs())
+        .filter_map(|dir| std::fs::canonicalize(dir).ok())
+        .collect();
+
+    // Create watcher
+    let producer = queue.clone();
+    let watched_experiments_dir = experiments_dir.clone();
+    let watched_overlay_dirs = overlay_dirs.clone();
+    let mut watcher = RecommendedWatcher::new(
+        move |res: notify::Result<Event>| {
+            let Ok(event) = res else {
+                return;
+            };
+            let change = match event.kind {
+                EventKind::Create(_) | EventKind::Modify(_) => LayerChange::Upsert,
+                EventKind::Remove(_) => LayerChange::Remove,
+                _ => return,
+            };
+            for path in event.paths {
+                if !is_config_file(&path) {
+                    continue;
+                }
+                if watched_overlay_dirs.iter().any(|d| path.starts_with(d)) {
+                    producer.push_full_resync();
+                } else if path.starts_with(&watched_experiments_dir) {
+                    // Includes `extends` bases in subdirectories
+                    producer.push_catalog();
+                } else {
+                    producer.push_layer(path, change);
+                }
+            }
+        },
+        Config::default(),
+    )?;
+
+    // Watch the layers directory
+    watcher.watch(&layers_dir, RecursiveMode::NonRecursive)?;
+    tracing::info!("Watching layers directory: {:?}", layers_dir);
+
+    if experiments_dir.exists() {
+        watcher.watch(&experiments_dir, RecursiveMode::Recursive)?;
+        tracing::info!("Watching experiments directory: {:?}", experiments_dir);
+    }
+
+    for dir in &overlay_dirs {
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+        tracing::info!("Watching overlay directory: {:?}", dir);
+    }
+
+    // Beat between batches; a batch stuck applying stops the heartbeat
+    let heartbeat = health.heartbeat(watchdog::WATCHER_TASK, HEARTBEAT_INTERVAL);
+    let mut beat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    beat.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    // Process changes
+    loop {
+        tokio::select! {
+            _ = queue.notify.notified() => {}
+            _ = beat.tick() => {
+                heartbeat.beat();
+                continue;
+            }
+        }
+
+        // Let the burst settle (and file writes complete) before applying
+        queue.settle(options.debounce).await;
+        let changes = queue.drain();
+
+        if changes.full_resync {
+            full_resync(&manager, &health).await;
+            continue;
+        }
+
+        if changes.catalog {
+            tracing::info!("Detected change in experiment files");
+            let _ = reload_catalog(&manager, &health).await;
+        }
+
+        let current = manager.snapshot().catalog().clone();
+        for (path, change) in changes.layers {
+            let result = match change {
+                LayerChange::Remove => handle_file_remove(&manager, &current, &path).await,
+                LayerChange::Upsert => handle_file_change(&manager, &current, &path).await,
+            };
+            if let Err(e) = result {
+                tracing::error!("Failed to handle file event {:?}: {}", path, e);
+            }
+        }
+
+        // Files arriving late complete a partial sync without waiting for the next resync
+        if !health.manifest_mismatches().is_empty() {
+            manifest::check(&manager, &health);
+        }
+    }
+}
+
+fn is_config_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "json" || ext == "yaml" || ext == "yml")
+}
+
+async fn handle_file_change(manager: &LayerManager, catalog: &Arc<ExperimentCatalog>, path: &Path) -> Result<()> {
+    if !path.is_file() {
+        return Ok(());
+    }
+
+    // Check file extension
+    if let Some(ext) = path.extension() {
+        if ext == "json" || ext == "yaml" || ext == "yml" {
+            // Extract layer_id from filename (without extension)
+            if let Some(file_stem) = path.file_stem() {
+                let layer_id = file_stem.to_string_lossy();
+
+                tracing::info!("Detected change in layer file: {:?}", path);
+
+                match manager.load_layer(&layer_id, path, catalog).await {
+                    Ok(_) => {
+                        tracing::info!("Hot reloaded layer: {}", layer_id);
+                        metrics::LAYER_RELOAD_TOTAL.inc();
+                        source::record_propagation("layer", path, manager.overlay_dirs());
+                    }
+                    Err(e) if freeze::is_queued(&e, manager) => {
+                        tracing::info!("Deferred layer {} until the freeze window closes: {}", layer_id, e);
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to reload layer {}: {}", layer_id, e);
+                        metrics::LAYER_RELOAD_ERRORS.inc();
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_file_remove(manager: &LayerManager, catalog: &Arc<ExperimentCatalog>, path: &Path) -> Result<()> {
+    if let Some(file_stem) = path.file_stem() {
+        let layer_id = file_stem.to_string_lossy();
+
+        tracing::info!("Detected removal of layer file: {:?}", path);
+        manager.forget_load_error(path);
+
+        if manager.is_overridden(&layer_id) {
+            tracing::info!("Layer {} is served from an overlay, keeping it", layer_id);
+            return Ok(());
+        }
+
+        if let Err(e) = manager.remove_layer(&layer_id, catalog).await {
+            tracing::error!("Failed to remove layer {}: {}", layer_id, e);
+        } else {
+            tracing::info!("Removed layer: {}", layer_id);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_change_queue_coalesces_per_file() {
+        let queue = ChangeQueue::new(10);
+        let a = PathBuf::from("/layers/a.json");
+
+        queue.push_layer(a.clone(), LayerChange::Upsert);
+        queue.push_layer(a.clone(), LayerChange::Upsert);
+        queue.push_layer(a.clone(), LayerChange::Remove);
+        queue.push_catalog();
+        queue.push_catalog();
+
+        let changes = queue.drain();
+        assert_eq!(changes.layers.len(), 1);
+        assert_eq!(changes.layers[&a], LayerChange::Remove);
+        assert!(changes.catalog);
+        assert!(!changes.full_resync);
+
+        assert_eq!(queue.drain(), PendingChanges::default());
+    }
+
+    #[tokio::test]
+    async fn test_settle_restarts_quiet_period_on_each_change() {
+        let queue = Arc::new(ChangeQueue::new(10));
+        let quiet = Duration::from_millis(100);
+        let producer = queue.clone();
+        let writes = tokio::spawn(async move {
+            for i in 0..5 {
+                producer.push_layer(PathBuf::from(format!("/layers/{}.json", i)), LayerChange::Upsert);
+                tokio::time::sleep(Duration::from_millis(60)).await;
+            }
+        });
+
+        // The burst spans ~300ms of 60ms gaps, longer than one quiet period
+        queue.notify.notified().await;
+        let started = std::time::Instant::now();
+        queue.settle(quiet).await;
+        assert!(started.elapsed() >= Duration::from_millis(300), "{:?}", started.elapsed());
+        assert_eq!(queue.drain().layers.len(), 5);
+        writes.await.unwrap();
+    }
+
+    #[test]
+    fn test_change_queue_overflow_forces_full_resync() {
+        let queue = ChangeQueue::new(3);
+        for i in 0..10 {
+            queue.push_layer(PathBuf::from(format!("/layers/{}.json", i)), LayerChange::Upsert);
+        }
+
+        let changes = queue.drain();
+        assert!(changes.full_resync);
+        assert!(changes.layers.is_empty());
+    }
+}
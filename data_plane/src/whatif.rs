@@ -0,0 +1,186 @@
+use crate::error::{ExperimentError, Result};
+use crate::layer::Snapshot;
+use crate::merge::{merge_layers_batch, ExperimentRequest, ServiceResult};
+use crate::rule::FieldType;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Upper bound on variations per what-if request
+pub const MAX_VARIATIONS: usize = 100;
+
+/// What-if request: one profile evaluated as-is and under each variation
+#[derive(Debug, Clone, Deserialize)]
+pub struct WhatIfRequest {
+    pub services: Vec<String>,
+
+    /// Base context
+    pub context: HashMap<String, serde_json::Value>,
+
+    #[serde(default)]
+    pub layers: Vec<String>,
+
+    pub variations: Vec<Variation>,
+}
+
+/// Fields to change in the base context; `null` removes the field
+#[derive(Debug, Clone, Deserialize)]
+pub struct Variation {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub set: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VariationResult {
+    /// Given name, or the variation's position
+    pub name: String,
+    pub results: BTreeMap<String, ServiceResult>,
+    /// Services whose vids differ from the base
+    pub changed: Vec<String>,
+}
+
+/// All variations are evaluated against the same config snapshot as the base
+#[derive(Debug, Clone, Serialize)]
+pub struct WhatIfResponse {
+    pub epoch: u64,
+    pub base: BTreeMap<String, ServiceResult>,
+    pub variations: Vec<VariationResult>,
+}
+
+pub fn what_if(
+    request: &WhatIfRequest,
+    snapshot: &Snapshot,
+    field_types: &HashMap<String, FieldType>,
+) -> Result<WhatIfResponse> {
+    if request.variations.len() > MAX_VARIATIONS {
+        return Err(ExperimentError::InvalidParameter(format!(
+            "What-if has {} variations (limit {})",
+            request.variations.len(),
+            MAX_VARIATIONS
+        )));
+    }
+
+    let evaluate = |context: HashMap<String, serde_json::Value>| -> Result<BTreeMap<String, ServiceResult>> {
+        let request = ExperimentRequest {
+            services: request.services.clone(),
+            context,
+            layers: request.layers.clone(),
+            diagnostics: false,
+        };
+        Ok(merge_layers_batch(&request, snapshot, field_types)?.results.into_iter().collect())
+    };
+
+    let base = evaluate(request.context.clone())?;
+
+    let mut variations = Vec::with_capacity(request.variations.len());
+    for (i, variation) in request.variations.iter().enumerate() {
+        let mut context = request.context.clone();
+        for (field, value) in &variation.set {
+            if value.is_null() {
+                context.remove(field);
+            } else {
+                context.insert(field.clone(), value.clone());
+            }
+        }
+
+        let results = evaluate(context)?;
+        let changed = results
+            .iter()
+            .filter(|(service, result)| base.get(*service).map(|b| &b.vids) != Some(&result.vids))
+            .map(|(service, _)| service.clone())
+            .collect();
+        variations.push(VariationResult {
+            name: variation.name.clone().unwrap_or_else(|| i.to_string()),
+            results,
+            changed,
+        });
+    }
+
+    Ok(WhatIfResponse {
+        epoch: snapshot.epoch(),
+        base,
+        variations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
+    use crate::engine::Evaluator;
+    use crate::layer::{BucketRange, Layer};
+    use crate::rule::{Node, Op};
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_variations_report_changed_services() {
+        let catalog = ExperimentCatalog::from_experiments(
+            vec![ExperimentDef {
+                eid: 100,
+                service: "svc".to_string(),
+                expires_at: None,
+                state: Default::default(),
+                caps: None,
+                rule: Some(Node::Field {
+                    field: "country".to_string(),
+                    op: Op::Eq,
+                    values: vec![json!("US")],
+                    collation: None,
+                }),
+                exposure_sampling: None,
+                variants: vec![VariantDef {
+                    vid: 101,
+                    params: json!({"banner": true}),
+                    dark_params: None,
+                }],
+            }],
+            PathBuf::new(),
+        )
+        .unwrap();
+        let evaluator = Evaluator::builder()
+            .with_catalog(catalog)
+            .with_layers([Layer {
+                layer_id: "l1".to_string(),
+                version: "v1".to_string(),
+                priority: 1,
+                hash_key: "user_id".into(),
+                salt: None,
+                bucket_size: None,
+                expires_at: None,
+                rule: None,
+                services: vec![],
+                ranges: vec![BucketRange {
+                    start: 0,
+                    end: 10000,
+                    vid: 101,
+                    label: None,
+                }],
+                enabled: true,
+                aa_test: false,
+            }])
+            .with_field_types(HashMap::from([("country".to_string(), FieldType::String)]))
+            .build()
+            .unwrap();
+
+        let request: WhatIfRequest = serde_json::from_value(json!({
+            "services": ["svc"],
+            "context": {"user_id": "u1", "country": "US"},
+            "variations": [
+                {"name": "canada", "set": {"country": "CA"}},
+                {"set": {"country": null}},
+                {"set": {"age": 30}}
+            ]
+        }))
+        .unwrap();
+        let response = what_if(&request, &evaluator.layer_manager().snapshot(), &evaluator.field_types()).unwrap();
+
+        assert_eq!(response.base["svc"].vids, vec![101]);
+        assert_eq!(response.variations[0].name, "canada");
+        assert!(response.variations[0].results["svc"].vids.is_empty());
+        assert_eq!(response.variations[0].changed, vec!["svc"]);
+        assert_eq!(response.variations[1].name, "1");
+        assert_eq!(response.variations[1].changed, vec!["svc"]);
+        assert!(response.variations[2].changed.is_empty());
+    }
+}
@@ -0,0 +1,577 @@
+//! xDS config delivery (feature `grpc`).
+//!
+//! With `XDS_SERVER` set, the data plane subscribes to layers and experiments
+//! over the control plane's `ConfigDiscoveryService`
+//! (`proto/config_discovery.proto`). Each push carries the complete set of one
+//! resource type and is validated first: every document must parse and be
+//! named after its layer id / eid, and the experiments must form a valid
+//! catalog. A valid push is written to the layers or experiments directory
+//! (files missing from the set are removed), applied with a full resync and
+//! ACKed; an invalid one is NACKed with the reason and nothing is written, so
+//! the previous version keeps serving. When the stream breaks the subscriber
+//! reconnects with exponential backoff and resubscribes with the versions it
+//! last accepted.
+//!
+//! `XDS_SERVER` may list several control plane endpoints in priority order,
+//! failed over between with the policy of [`crate::failover`]. Every
+//! connection attempt and probe records the endpoint's health
+//! ([`XdsSubscriber::endpoints`]). Once the active endpoint has kept failing
+//! for `failover_after` the subscriber moves to the healthiest other one:
+//! last seen healthy, then never tried, then failing the fewest times in a
+//! row, ties going to the higher priority. While it isn't on the first,
+//! higher-priority endpoints are probed every `check_interval` and switched
+//! back to once they have accepted connections for `failback_after`.
+//!
+//! Writing through the directories keeps a single apply path: freeze windows,
+//! overlays, the applied log and the watchdog behave as with file delivery,
+//! and a restart while the control plane is down serves the last accepted
+//! config. Cross-resource checks (a layer naming an unknown vid) happen at
+//! apply, like for files: the push is ACKed and the instance reports degraded.
+//!
+//! [`testing`] (feature `test-support`) runs a control plane in-process for
+//! integration tests.
+
+pub mod proto;
+#[cfg(feature = "test-support")]
+pub mod testing;
+
+use crate::catalog::{ExperimentCatalog, ExperimentDef};
+use crate::config::migrate;
+use crate::error::{ExperimentError, Result};
+use crate::failover::FailoverOptions;
+use crate::health::ConfigHealth;
+use crate::layer::{Layer, LayerManager};
+use crate::metrics;
+use crate::watcher;
+use parking_lot::Mutex;
+use proto::{ConfigDiscoveryClient, DiscoveryRequest, DiscoveryResponse, Resource};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+pub const EXPERIMENT_TYPE: &str = "type.experiment.config.v1/Experiment";
+pub const LAYER_TYPE: &str = "type.experiment.config.v1/Layer";
+
+/// Subscribed in this order so pushed layers find the experiments they reference
+const SUBSCRIBED_TYPES: [&str; 2] = [EXPERIMENT_TYPE, LAYER_TYPE];
+
+#[derive(Debug, Clone)]
+pub struct XdsOptions {
+    /// Control plane URLs in priority order, e.g. `http://control-plane:18000`
+    pub endpoints: Vec<String>,
+    /// Identifies this data plane to the control plane
+    pub node_id: String,
+    /// First reconnect delay, doubled per failed attempt up to `max_backoff`
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// When to move between endpoints
+    pub failover: FailoverOptions,
+}
+
+/// Last observed health of one control plane endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EndpointStatus {
+    pub endpoint: String,
+    pub active: bool,
+    /// `None` until a connection to it was first attempted
+    pub healthy: Option<bool>,
+    /// Connection attempts and streams that failed in a row
+    pub consecutive_failures: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Why a config stream ended without an error
+enum StreamEnd {
+    /// The control plane closed it
+    Closed,
+    /// A higher-priority endpoint has been reachable for `failback_after`
+    FailBack(usize),
+}
+
+/// Subscribes to config from the control plane and applies what it pushes
+pub struct XdsSubscriber {
+    options: XdsOptions,
+    manager: Arc<LayerManager>,
+    health: Arc<ConfigHealth>,
+    /// Last accepted version per type url, sent again when resubscribing
+    accepted: Mutex<HashMap<String, String>>,
+    /// Index of the endpoint in use
+    active: AtomicUsize,
+    /// Parallel to `options.endpoints`
+    endpoint_health: Mutex<Vec<EndpointStatus>>,
+}
+
+impl XdsSubscriber {
+    pub fn new(options: XdsOptions, manager: Arc<LayerManager>, health: Arc<ConfigHealth>) -> Self {
+        let endpoint_health = options
+            .endpoints
+            .iter()
+            .map(|endpoint| EndpointStatus {
+                endpoint: endpoint.clone(),
+                active: false,
+                healthy: None,
+                consecutive_failures: 0,
+                error: None,
+            })
+            .collect();
+        Self {
+            options,
+            manager,
+            health,
+            accepted: Mutex::new(HashMap::new()),
+            active: AtomicUsize::new(0),
+            endpoint_health: Mutex::new(endpoint_health),
+        }
+    }
+
+    /// Every listed endpoint with its last observed health, in priority order
+    pub fn endpoints(&self) -> Vec<EndpointStatus> {
+        let active = self.active.load(Ordering::Relaxed);
+        let mut endpoints = self.endpoint_health.lock().clone();
+        for (i, status) in endpoints.iter_mut().enumerate() {
+            status.active = i == active;
+        }
+        endpoints
+    }
+
+    /// Control plane endpoint currently subscribed to (or being reconnected to)
+    pub fn active_endpoint(&self) -> &str {
+        &self.options.endpoints[self.active.load(Ordering::Relaxed)]
+    }
+
+    /// Last version of `type_url` accepted from the control plane
+    pub fn accepted_version(&self, type_url: &str) -> Option<String> {
+        self.accepted.lock().get(type_url).cloned()
+    }
+
+    /// Keep a config stream open, reconnecting with backoff whenever it breaks
+    /// and moving between endpoints as [`XdsOptions::failover`] says
+    pub async fn run(self: Arc<Self>) {
+        assert!(!self.options.endpoints.is_empty(), "xDS needs at least one endpoint");
+        self.activate(0);
+        let mut backoff = self.options.initial_backoff;
+        // Since when the active endpoint has failed without a stream opening
+        let mut failing_since: Option<Instant> = None;
+        loop {
+            let endpoint = self.active_endpoint().to_string();
+            match self.stream(&mut backoff, &mut failing_since).await {
+                Ok(StreamEnd::FailBack(target)) => {
+                    tracing::warn!("Failing back from {} to {}", endpoint, self.options.endpoints[target]);
+                    metrics::XDS_ENDPOINT_SWITCHES.with_label_values(&["failback"]).inc();
+                    self.activate(target);
+                    backoff = self.options.initial_backoff;
+                    failing_since = None;
+                    continue;
+                }
+                Ok(StreamEnd::Closed) => {
+                    tracing::warn!("Control plane {} closed the config stream", endpoint);
+                    self.record(self.active.load(Ordering::Relaxed), Err("stream closed".to_string()));
+                }
+                Err(e) => {
+                    tracing::warn!("Config stream to {} failed: {}", endpoint, e);
+                    self.record(self.active.load(Ordering::Relaxed), Err(e.to_string()));
+                }
+            }
+            metrics::XDS_RECONNECTS.inc();
+
+            let since = *failing_since.get_or_insert_with(Instant::now);
+            if self.options.endpoints.len() > 1 && since.elapsed() >= self.options.failover.failover_after {
+                let next = failover_target(&self.endpoint_health.lock(), self.active.load(Ordering::Relaxed));
+                tracing::warn!("Failing over from {} to {}", endpoint, self.options.endpoints[next]);
+                metrics::XDS_ENDPOINT_SWITCHES.with_label_values(&["failover"]).inc();
+                self.activate(next);
+                backoff = self.options.initial_backoff;
+                failing_since = None;
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(self.options.max_backoff);
+        }
+    }
+
+    fn activate(&self, index: usize) {
+        self.active.store(index, Ordering::Relaxed);
+        for (i, endpoint) in self.options.endpoints.iter().enumerate() {
+            metrics::XDS_ACTIVE_ENDPOINT
+                .with_label_values(&[endpoint])
+                .set((i == index) as i64);
+        }
+    }
+
+    /// Note the outcome of a connection attempt or stream to endpoint `index`
+    fn record(&self, index: usize, outcome: std::result::Result<(), String>) {
+        let mut endpoint_health = self.endpoint_health.lock();
+        let status = &mut endpoint_health[index];
+        match outcome {
+            Ok(()) => {
+                status.healthy = Some(true);
+                status.consecutive_failures = 0;
+                status.error = None;
+            }
+            Err(e) => {
+                status.healthy = Some(false);
+                status.consecutive_failures = status.consecutive_failures.saturating_add(1);
+                status.error = Some(e);
+            }
+        }
+        metrics::XDS_ENDPOINT_HEALTHY
+            .with_label_values(&[&status.endpoint])
+            .set((status.healthy == Some(true)) as i64);
+    }
+
+    /// Subscribe, then apply and answer pushes until the stream ends
+    async fn stream(&self, backoff: &mut Duration, failing_since: &mut Option<Instant>) -> anyhow::Result<StreamEnd> {
+        let active = self.active.load(Ordering::Relaxed);
+        let endpoint = self.options.endpoints[active].clone();
+        let mut client = ConfigDiscoveryClient::connect(endpoint.clone()).await?;
+        let (requests, outbound) = mpsc::channel(SUBSCRIBED_TYPES.len() + 1);
+        for type_url in SUBSCRIBED_TYPES {
+            requests.send(self.request(type_url, String::new(), String::new())).await?;
+        }
+        let mut responses = client.stream_config(ReceiverStream::new(outbound)).await?.into_inner();
+        tracing::info!("Subscribed to config from {} as '{}'", endpoint, self.options.node_id);
+        self.record(active, Ok(()));
+        *backoff = self.options.initial_backoff;
+        *failing_since = None;
+
+        let mut probe = tokio::time::interval(self.options.failover.check_interval);
+        probe.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // Higher-priority endpoint accepting connections, and since when
+        let mut reachable: Option<(usize, Instant)> = None;
+        loop {
+            tokio::select! {
+                response = responses.message() => {
+                    let Some(response) = response? else {
+                        return Ok(StreamEnd::Closed);
+                    };
+                    let reply = self.apply(response).await;
+                    requests.send(reply).await?;
+                }
+                _ = probe.tick(), if active > 0 => {
+                    let Some(target) = self.first_reachable(active).await else {
+                        reachable = None;
+                        continue;
+                    };
+                    let since = match reachable {
+                        Some((previous, since)) if previous == target => since,
+                        _ => Instant::now(),
+                    };
+                    if since.elapsed() >= self.options.failover.failback_after {
+                        return Ok(StreamEnd::FailBack(target));
+                    }
+                    reachable = Some((target, since));
+                }
+            }
+        }
+    }
+
+    /// Highest-priority endpoint before `active` that accepts a connection
+    async fn first_reachable(&self, active: usize) -> Option<usize> {
+        for (i, endpoint) in self.options.endpoints[..active].iter().enumerate() {
+            let connect = ConfigDiscoveryClient::connect(endpoint.clone());
+            match tokio::time::timeout(self.options.failover.check_interval, connect).await {
+                Ok(Ok(_)) => {
+                    self.record(i, Ok(()));
+                    return Some(i);
+                }
+                Ok(Err(e)) => self.record(i, Err(e.to_string())),
+                Err(_) => self.record(i, Err("connect timed out".to_string())),
+            }
+        }
+        None
+    }
+
+    fn request(&self, type_url: &str, response_nonce: String, error_detail: String) -> DiscoveryRequest {
+        DiscoveryRequest {
+            version_info: self.accepted_version(type_url).unwrap_or_default(),
+            node_id: self.options.node_id.clone(),
+            type_url: type_url.to_string(),
+            response_nonce,
+            error_detail,
+        }
+    }
+
+    /// Validate and write a push, returning the ACK or NACK answering it
+    async fn apply(&self, response: DiscoveryResponse) -> DiscoveryRequest {
+        let (label, written) = match response.type_url.as_str() {
+            EXPERIMENT_TYPE => ("experiment", self.write_experiments(&response.resources)),
+            LAYER_TYPE => ("layer", self.write_layers(&response.resources)),
+            other => (
+                "unknown",
+                Err(ExperimentError::InvalidParameter(format!("Unsupported resource type '{}'", other))),
+            ),
+        };
+
+        match written {
+            Ok(()) => {
+                tracing::info!(
+                    "Accepted {} version {} ({} resources)",
+                    label,
+                    response.version_info,
+                    response.resources.len()
+                );
+                self.accepted
+                    .lock()
+                    .insert(response.type_url.clone(), response.version_info);
+                watcher::full_resync(&self.manager, &self.health).await;
+                metrics::XDS_UPDATES.with_label_values(&[label, "ack"]).inc();
+                self.request(&response.type_url, response.nonce, String::new())
+            }
+            Err(e) => {
+                tracing::error!("Rejected {} version {}: {}", label, response.version_info, e);
+                metrics::XDS_UPDATES.with_label_values(&[label, "nack"]).inc();
+                self.request(&response.type_url, response.nonce, e.to_string())
+            }
+        }
+    }
+
+    fn write_experiments(&self, resources: &[Resource]) -> Result<()> {
+        let dir = self.manager.snapshot().catalog().source_dir().to_path_buf();
+        let mut documents = BTreeMap::new();
+        let mut experiments = Vec::new();
+        for resource in resources {
+            let doc = parse_resource(resource)?;
+            let experiment = ExperimentDef::from_value(doc.clone()).map_err(|e| resource_error(resource, e))?;
+            check_name(resource, &experiment.eid.to_string())?;
+            experiments.push(experiment);
+            insert_document(&mut documents, resource, doc)?;
+        }
+        // Duplicate vids and the like reject the whole set
+        let catalog = ExperimentCatalog::from_experiments(experiments, dir.clone())?;
+        self.manager.check_catalog_versions(&catalog)?;
+        write_documents(&dir, &documents)
+    }
+
+    fn write_layers(&self, resources: &[Resource]) -> Result<()> {
+        let mut documents = BTreeMap::new();
+        for resource in resources {
+            let doc = parse_resource(resource)?;
+            let layer = Layer::from_value_against(doc.clone(), self.manager.strict_config(), self.manager.snapshot().catalog())
+                .map_err(|e| resource_error(resource, e))?;
+            check_name(resource, &layer.layer_id)?;
+            self.manager.check_layer_versions(&layer).map_err(|e| resource_error(resource, e))?;
+            insert_document(&mut documents, resource, doc)?;
+        }
+        write_documents(&self.manager.layers_dir(), &documents)
+    }
+}
+
+/// Endpoint to fail over to from `active`: the healthiest other one (last
+/// seen healthy, then never tried, then fewest failures in a row), ties
+/// going to the higher priority
+fn failover_target(endpoints: &[EndpointStatus], active: usize) -> usize {
+    endpoints
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != active)
+        .min_by_key(|(i, status)| {
+            let rank = match status.healthy {
+                Some(true) => 0,
+                None => 1,
+                Some(false) => 2,
+            };
+            (rank, status.consecutive_failures, *i)
+        })
+        .map_or(active, |(i, _)| i)
+}
+
+fn resource_error(resource: &Resource, err: impl std::fmt::Display) -> ExperimentError {
+    ExperimentError::InvalidParameter(format!("Resource '{}': {}", resource.name, err))
+}
+
+fn parse_resource(resource: &Resource) -> Result<serde_json::Value> {
+    let content = std::str::from_utf8(&resource.body).map_err(|e| resource_error(resource, e))?;
+    migrate::parse_document(content).map_err(|e| resource_error(resource, e))
+}
+
+/// Resources become `<name>.json`, so the name must be the id the file is loaded by
+fn check_name(resource: &Resource, id: &str) -> Result<()> {
+    if resource.name != id {
+        return Err(resource_error(resource, format!("name must match its id '{}'", id)));
+    }
+    let valid = !resource.name.starts_with('.')
+        && resource
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if !valid {
+        return Err(resource_error(resource, "name must be ASCII letters, digits, '_', '-' or '.'"));
+    }
+    Ok(())
+}
+
+fn insert_document(
+    documents: &mut BTreeMap<String, serde_json::Value>,
+    resource: &Resource,
+    doc: serde_json::Value,
+) -> Result<()> {
+    if documents.insert(resource.name.clone(), doc).is_some() {
+        return Err(resource_error(resource, "pushed more than once"));
+    }
+    Ok(())
+}
+
+/// Make `dir`'s top-level config files exactly `documents`, one `<name>.json`
+/// each. All files are staged first; if renaming or removing fails halfway,
+/// the files already changed are restored so the directory keeps the previous
+/// version as a whole.
+fn write_documents(dir: &Path, documents: &BTreeMap<String, serde_json::Value>) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut staged = Vec::new();
+    let result = stage_documents(dir, documents, &mut staged).and_then(|()| {
+        let removed = unpushed_files(dir, documents)?;
+        let mut originals = Vec::new();
+        replace_files(&staged, &removed, &mut originals).inspect_err(|_| restore_files(dir, originals))
+    });
+    for (staging, _) in &staged {
+        let _ = std::fs::remove_file(staging);
+    }
+    result
+}
+
+/// Write changed documents aside (`.<name>.json.tmp`), so the watcher never
+/// reads a partial file; returns (staging, target) pairs
+fn stage_documents(
+    dir: &Path,
+    documents: &BTreeMap<String, serde_json::Value>,
+    staged: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<()> {
+    for (name, doc) in documents {
+        let path = dir.join(format!("{}.json", name));
+        let content = serde_json::to_vec_pretty(doc)?;
+        if std::fs::read(&path).is_ok_and(|existing| existing == content) {
+            continue;
+        }
+        let staging = dir.join(format!(".{}.json.tmp", name));
+        std::fs::write(&staging, &content)?;
+        staged.push((staging, path));
+    }
+    Ok(())
+}
+
+/// Config files in `dir` that aren't part of the push
+fn unpushed_files(dir: &Path, documents: &BTreeMap<String, serde_json::Value>) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_config = path.is_file()
+            && path
+                .extension()
+                .is_some_and(|ext| ext == "json" || ext == "yaml" || ext == "yml");
+        let pushed = path.extension().is_some_and(|ext| ext == "json")
+            && path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(|stem| documents.contains_key(stem));
+        if is_config && !pushed {
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+/// Move staged files into place and remove `removed`, recording each changed
+/// file's previous content (`None` when it didn't exist) in `originals`
+fn replace_files(
+    staged: &[(PathBuf, PathBuf)],
+    removed: &[PathBuf],
+    originals: &mut Vec<(PathBuf, Option<Vec<u8>>)>,
+) -> Result<()> {
+    let original = |path: &Path| match std::fs::read(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    };
+    for (staging, path) in staged {
+        originals.push((path.clone(), original(path)?));
+        std::fs::rename(staging, path)?;
+    }
+    for path in removed {
+        originals.push((path.clone(), original(path)?));
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Undo [`replace_files`], newest change first
+fn restore_files(dir: &Path, originals: Vec<(PathBuf, Option<Vec<u8>>)>) {
+    for (path, content) in originals.into_iter().rev() {
+        let restored = match content {
+            Some(content) => {
+                let staging = dir.join(".restore.tmp");
+                std::fs::write(&staging, content).and_then(|()| std::fs::rename(&staging, &path))
+            }
+            None => std::fs::remove_file(&path).or_else(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            }),
+        };
+        if let Err(e) = restored {
+            tracing::error!("Failed to restore {} after a failed xDS write: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_write_documents_rolls_back_on_error() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        std::fs::write(dir.join("a.json"), "{\"version\": 1}").unwrap();
+        std::fs::write(dir.join("old.yaml"), "layer_id: old").unwrap();
+        // A directory where `b.json` must go makes the second rename fail
+        std::fs::create_dir_all(dir.join("b.json").join("blocker")).unwrap();
+
+        let documents = BTreeMap::from([
+            ("a".to_string(), json!({"version": 2})),
+            ("b".to_string(), json!({"version": 2})),
+        ]);
+        assert!(write_documents(dir, &documents).is_err());
+        assert_eq!(std::fs::read_to_string(dir.join("a.json")).unwrap(), "{\"version\": 1}");
+        assert!(dir.join("old.yaml").exists());
+        let leftovers: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|name| name.ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty(), "{:?}", leftovers);
+
+        std::fs::remove_dir_all(dir.join("b.json")).unwrap();
+        write_documents(dir, &documents).unwrap();
+        assert_eq!(std::fs::read(dir.join("b.json")).unwrap(), serde_json::to_vec_pretty(&documents["b"]).unwrap());
+        assert!(!dir.join("old.yaml").exists());
+    }
+
+    #[test]
+    fn test_failover_prefers_healthy_endpoints() {
+        let status = |healthy: Option<bool>, consecutive_failures: u32| EndpointStatus {
+            endpoint: String::new(),
+            active: false,
+            healthy,
+            consecutive_failures,
+            error: None,
+        };
+        // Active primary failing: the healthy third beats the failing second
+        let endpoints = vec![status(Some(false), 5), status(Some(false), 1), status(Some(true), 0)];
+        assert_eq!(failover_target(&endpoints, 0), 2);
+        // Never tried beats failing; fewer failures in a row beats more
+        let endpoints = vec![status(Some(false), 5), status(Some(false), 1), status(None, 0)];
+        assert_eq!(failover_target(&endpoints, 0), 2);
+        let endpoints = vec![status(Some(false), 5), status(Some(false), 1), status(Some(false), 3)];
+        assert_eq!(failover_target(&endpoints, 0), 1);
+        // Equal health goes to the higher priority, wrapping past the active one
+        let endpoints = vec![status(None, 0), status(None, 0), status(Some(false), 4)];
+        assert_eq!(failover_target(&endpoints, 2), 0);
+        assert_eq!(failover_target(&endpoints[..1], 0), 0);
+    }
+}
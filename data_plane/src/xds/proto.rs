@@ -0,0 +1,156 @@
+//! Bindings for `proto/config_discovery.proto`, written in the shape
+//! `tonic-build` generates so no protoc is needed at build time.
+
+use std::sync::Arc;
+use tonic::codec::{ProstCodec, Streaming};
+use tonic::codegen::{empty_body, http, Body, BoxFuture, Context, Poll, Service, StdError};
+use tonic::transport::Channel;
+use tonic::{Request, Response, Status};
+use tokio_stream::Stream;
+
+const STREAM_CONFIG_PATH: &str = "/experiment.config.v1.ConfigDiscoveryService/StreamConfig";
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DiscoveryRequest {
+    #[prost(string, tag = "1")]
+    pub version_info: String,
+    #[prost(string, tag = "2")]
+    pub node_id: String,
+    #[prost(string, tag = "3")]
+    pub type_url: String,
+    #[prost(string, tag = "4")]
+    pub response_nonce: String,
+    #[prost(string, tag = "5")]
+    pub error_detail: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Resource {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DiscoveryResponse {
+    #[prost(string, tag = "1")]
+    pub version_info: String,
+    #[prost(message, repeated, tag = "2")]
+    pub resources: Vec<Resource>,
+    #[prost(string, tag = "3")]
+    pub type_url: String,
+    #[prost(string, tag = "4")]
+    pub nonce: String,
+}
+
+/// Data plane side of `ConfigDiscoveryService`
+#[derive(Debug, Clone)]
+pub struct ConfigDiscoveryClient {
+    inner: tonic::client::Grpc<Channel>,
+}
+
+impl ConfigDiscoveryClient {
+    pub async fn connect(endpoint: String) -> Result<Self, tonic::transport::Error> {
+        let channel = tonic::transport::Endpoint::new(endpoint)?.connect().await?;
+        Ok(Self {
+            inner: tonic::client::Grpc::new(channel),
+        })
+    }
+
+    pub async fn stream_config(
+        &mut self,
+        requests: impl Stream<Item = DiscoveryRequest> + Send + 'static,
+    ) -> Result<Response<Streaming<DiscoveryResponse>>, Status> {
+        self.inner
+            .ready()
+            .await
+            .map_err(|e| Status::unknown(format!("Service was not ready: {}", e)))?;
+        let path = http::uri::PathAndQuery::from_static(STREAM_CONFIG_PATH);
+        self.inner
+            .streaming(Request::new(requests), path, ProstCodec::default())
+            .await
+    }
+}
+
+/// Control plane side of `ConfigDiscoveryService`
+#[tonic::async_trait]
+pub trait ConfigDiscovery: Send + Sync + 'static {
+    type StreamConfigStream: Stream<Item = Result<DiscoveryResponse, Status>> + Send + 'static;
+
+    async fn stream_config(
+        &self,
+        request: Request<Streaming<DiscoveryRequest>>,
+    ) -> Result<Response<Self::StreamConfigStream>, Status>;
+}
+
+/// Serves a [`ConfigDiscovery`] implementation with `tonic::transport::Server`
+#[derive(Debug)]
+pub struct ConfigDiscoveryServer<T> {
+    inner: Arc<T>,
+}
+
+impl<T> ConfigDiscoveryServer<T> {
+    pub fn new(inner: T) -> Self {
+        Self { inner: Arc::new(inner) }
+    }
+}
+
+impl<T> Clone for ConfigDiscoveryServer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T, B> Service<http::Request<B>> for ConfigDiscoveryServer<T>
+where
+    T: ConfigDiscovery,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = std::convert::Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        if req.uri().path() != STREAM_CONFIG_PATH {
+            return Box::pin(async move {
+                Ok(http::Response::builder()
+                    .status(200)
+                    .header("grpc-status", (tonic::Code::Unimplemented as i32).to_string())
+                    .header("content-type", "application/grpc")
+                    .body(empty_body())
+                    .unwrap())
+            });
+        }
+
+        struct StreamConfigSvc<T>(Arc<T>);
+
+        impl<T: ConfigDiscovery> tonic::server::StreamingService<DiscoveryRequest> for StreamConfigSvc<T> {
+            type Response = DiscoveryResponse;
+            type ResponseStream = T::StreamConfigStream;
+            type Future = BoxFuture<Response<Self::ResponseStream>, Status>;
+
+            fn call(&mut self, request: Request<Streaming<DiscoveryRequest>>) -> Self::Future {
+                let inner = self.0.clone();
+                Box::pin(async move { inner.stream_config(request).await })
+            }
+        }
+
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            let mut grpc = tonic::server::Grpc::new(ProstCodec::default());
+            Ok(grpc.streaming(StreamConfigSvc(inner), req).await)
+        })
+    }
+}
+
+impl<T: ConfigDiscovery> tonic::server::NamedService for ConfigDiscoveryServer<T> {
+    const NAME: &'static str = "experiment.config.v1.ConfigDiscoveryService";
+}
@@ -0,0 +1,219 @@
+//! In-process control plane for tests (feature `test-support`).
+//!
+//! [`MockControlPlane`] serves `ConfigDiscoveryService` on a local port so a
+//! [`super::XdsSubscriber`] can be driven end to end: the test pushes
+//! versions, reads back the subscriptions, ACKs and NACKs the data plane
+//! sends, and breaks streams to exercise reconnects.
+//!
+//! ```ignore
+//! let control_plane = MockControlPlane::start().await;
+//! control_plane.push(EXPERIMENT_TYPE, "v1", vec![resource("100", &experiment)]);
+//! let ack = control_plane.next_reply(EXPERIMENT_TYPE).await;
+//! assert!(ack.error_detail.is_empty());
+//! ```
+
+use super::proto::{ConfigDiscovery, ConfigDiscoveryServer, DiscoveryRequest, DiscoveryResponse, Resource};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::codec::Streaming;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+
+/// Longest [`MockControlPlane::next_request`] waits before failing the test
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A resource whose body is `document` as JSON
+pub fn resource(name: &str, document: &serde_json::Value) -> Resource {
+    Resource {
+        name: name.to_string(),
+        body: serde_json::to_vec(document).unwrap(),
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    /// Latest push per type url, sent to whoever subscribes later
+    current: Mutex<HashMap<String, DiscoveryResponse>>,
+    nonce: AtomicU64,
+    streams_opened: AtomicUsize,
+}
+
+struct MockService {
+    state: Arc<MockState>,
+    pushes: broadcast::Sender<DiscoveryResponse>,
+    disconnects: broadcast::Sender<()>,
+    requests: mpsc::UnboundedSender<DiscoveryRequest>,
+}
+
+/// A control plane serving on `127.0.0.1`, stopped when dropped
+pub struct MockControlPlane {
+    addr: SocketAddr,
+    state: Arc<MockState>,
+    pushes: broadcast::Sender<DiscoveryResponse>,
+    disconnects: broadcast::Sender<()>,
+    requests: tokio::sync::Mutex<mpsc::UnboundedReceiver<DiscoveryRequest>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockControlPlane {
+    pub async fn start() -> Self {
+        Self::start_at("127.0.0.1:0".parse().unwrap()).await
+    }
+
+    /// Serve on `addr`, e.g. to bring back an endpoint a data plane lists
+    pub async fn start_at(addr: SocketAddr) -> Self {
+        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+
+        let state = Arc::new(MockState::default());
+        let (pushes, _) = broadcast::channel(64);
+        let (disconnects, _) = broadcast::channel(4);
+        let (requests_tx, requests_rx) = mpsc::unbounded_channel();
+        let service = MockService {
+            state: state.clone(),
+            pushes: pushes.clone(),
+            disconnects: disconnects.clone(),
+            requests: requests_tx,
+        };
+
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(ConfigDiscoveryServer::new(service))
+                .serve_with_incoming_shutdown(incoming, async {
+                    let _ = stopped.await;
+                }),
+        );
+
+        Self {
+            addr,
+            state,
+            pushes,
+            disconnects,
+            requests: tokio::sync::Mutex::new(requests_rx),
+            shutdown: Some(shutdown),
+        }
+    }
+
+    /// URL for [`super::XdsOptions::endpoints`]
+    pub fn endpoint(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Publish `resources` as the complete `version` of `type_url` to current
+    /// and future subscribers; returns the response nonce
+    pub fn push(&self, type_url: &str, version: &str, resources: Vec<Resource>) -> String {
+        let nonce = self.state.nonce.fetch_add(1, Ordering::Relaxed).to_string();
+        let response = DiscoveryResponse {
+            version_info: version.to_string(),
+            resources,
+            type_url: type_url.to_string(),
+            nonce: nonce.clone(),
+        };
+        self.state.current.lock().insert(type_url.to_string(), response.clone());
+        let _ = self.pushes.send(response);
+        nonce
+    }
+
+    /// Next request any data plane sent: a subscription, ACK or NACK
+    pub async fn next_request(&self) -> DiscoveryRequest {
+        let mut requests = self.requests.lock().await;
+        tokio::time::timeout(REQUEST_TIMEOUT, requests.recv())
+            .await
+            .expect("no request from the data plane in time")
+            .expect("control plane stopped")
+    }
+
+    /// Next ACK or NACK of `type_url`, skipping subscriptions and other types
+    pub async fn next_reply(&self, type_url: &str) -> DiscoveryRequest {
+        loop {
+            let request = self.next_request().await;
+            if request.type_url == type_url && !request.response_nonce.is_empty() {
+                return request;
+            }
+        }
+    }
+
+    /// End every open stream with `UNAVAILABLE`, as a control plane restart would
+    pub fn disconnect_all(&self) {
+        let _ = self.disconnects.send(());
+    }
+
+    /// Streams opened since start, counting reconnects
+    pub fn streams_opened(&self) -> usize {
+        self.state.streams_opened.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for MockControlPlane {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ConfigDiscovery for MockService {
+    type StreamConfigStream = ReceiverStream<Result<DiscoveryResponse, Status>>;
+
+    async fn stream_config(
+        &self,
+        request: Request<Streaming<DiscoveryRequest>>,
+    ) -> Result<Response<Self::StreamConfigStream>, Status> {
+        self.state.streams_opened.fetch_add(1, Ordering::Relaxed);
+        let mut inbound = request.into_inner();
+        let (outbound, responses) = mpsc::channel(16);
+        let state = self.state.clone();
+        let mut pushes = self.pushes.subscribe();
+        let mut disconnects = self.disconnects.subscribe();
+        let requests = self.requests.clone();
+
+        tokio::spawn(async move {
+            let mut subscribed = HashSet::new();
+            loop {
+                let response = tokio::select! {
+                    request = inbound.message() => {
+                        let Ok(Some(request)) = request else {
+                            break;
+                        };
+                        // The first request of a type subscribes to it; send
+                        // the current version unless the data plane has it
+                        let current = if subscribed.insert(request.type_url.clone()) {
+                            let current = state.current.lock().get(&request.type_url).cloned();
+                            current.filter(|response| response.version_info != request.version_info)
+                        } else {
+                            None
+                        };
+                        let _ = requests.send(request);
+                        match current {
+                            Some(response) => response,
+                            None => continue,
+                        }
+                    }
+                    push = pushes.recv() => match push {
+                        Ok(response) if subscribed.contains(&response.type_url) => response,
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    _ = disconnects.recv() => {
+                        let _ = outbound.send(Err(Status::unavailable("control plane restarting"))).await;
+                        break;
+                    }
+                };
+                if outbound.send(Ok(response)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(responses)))
+    }
+}
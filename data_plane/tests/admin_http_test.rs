@@ -0,0 +1,174 @@
+use experiment_data_plane::catalog::{ExperimentCatalog, ExperimentDef, VariantDef};
+use experiment_data_plane::config::Config;
+use experiment_data_plane::engine::Evaluator;
+use experiment_data_plane::hash::hash_to_bucket;
+use experiment_data_plane::health::ConfigHealth;
+use experiment_data_plane::kv::MemoryStore;
+use experiment_data_plane::layer::{BucketRange, Layer, LayerManager, BUCKET_SIZE};
+use experiment_data_plane::server::run_server;
+use experiment_data_plane::source::SourceSwitcher;
+use experiment_data_plane::watcher::WatchOptions;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn write_experiment(dir: &Path, eid: i64, service: &str) {
+    let exp = ExperimentDef {
+        eid,
+        service: service.to_string(),
+        expires_at: None,
+        state: Default::default(),
+        caps: None,
+        rule: None,
+        exposure_sampling: None,
+        variants: vec![VariantDef {
+            vid: eid * 10 + 1,
+            params: json!({"eid": eid}),
+            dark_params: None,
+        }],
+    };
+    std::fs::write(
+        dir.join(format!("{}.json", eid)),
+        serde_json::to_string_pretty(&exp).unwrap(),
+    )
+    .unwrap();
+}
+
+/// Layer holding a single bucket: the one `subject` hashes to under `salt`
+fn write_layer(dir: &Path, layer_id: &str, priority: i32, salt: &str, subject: &str, vid: i64) {
+    let bucket = hash_to_bucket(subject, salt);
+    let layer = Layer {
+        layer_id: layer_id.to_string(),
+        version: "v1".to_string(),
+        priority,
+        hash_key: "user_id".into(),
+        salt: Some(salt.to_string()),
+        bucket_size: None,
+        expires_at: None,
+        rule: None,
+        services: vec![],
+        ranges: vec![BucketRange {
+            start: bucket,
+            end: bucket.saturating_add(1).min(BUCKET_SIZE),
+            vid,
+            label: Some(format!("{}_treatment", layer_id)),
+        }],
+        enabled: true,
+        aa_test: false,
+    };
+    std::fs::write(
+        dir.join(format!("{}.json", layer_id)),
+        serde_json::to_string_pretty(&layer).unwrap(),
+    )
+    .unwrap();
+}
+
+async fn get_json(port: u16, path: &str) -> (u16, Value) {
+    let client = Client::builder(TokioExecutor::new()).build_http::<axum::body::Body>();
+    let uri = format!("http://127.0.0.1:{}{}", port, path);
+    let response = client.get(uri.parse().unwrap()).await.unwrap();
+    let status = response.status().as_u16();
+    let body = axum::body::to_bytes(axum::body::Body::new(response.into_body()), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn test_subject_assignments_endpoint() {
+    let temp_dir = TempDir::new().unwrap();
+    let layers_dir = temp_dir.path().join("layers");
+    let experiments_dir = temp_dir.path().join("experiments");
+    std::fs::create_dir_all(&layers_dir).unwrap();
+    std::fs::create_dir_all(&experiments_dir).unwrap();
+
+    let in_both = "user_in_both";
+    let unassigned = "user_unassigned";
+    write_experiment(&experiments_dir, 100, "checkout");
+    write_experiment(&experiments_dir, 200, "checkout");
+    write_layer(&layers_dir, "ui_layer", 200, "ui_salt", in_both, 1001);
+    write_layer(&layers_dir, "pricing_layer", 100, "pricing_salt", in_both, 2001);
+    // Each layer holds only `in_both`'s bucket, so `unassigned` must hash elsewhere
+    assert_ne!(hash_to_bucket(unassigned, "ui_salt"), hash_to_bucket(in_both, "ui_salt"));
+    assert_ne!(
+        hash_to_bucket(unassigned, "pricing_salt"),
+        hash_to_bucket(in_both, "pricing_salt")
+    );
+
+    let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir.clone()).unwrap());
+    let manager = Arc::new(LayerManager::new(layers_dir.clone()));
+    manager.load_all_layers(&catalog).await.unwrap();
+
+    let kv = Arc::new(MemoryStore::new());
+    let evaluator = Arc::new(
+        Evaluator::builder()
+            .with_layer_manager(manager.clone())
+            .with_kv_store(kv.clone())
+            .build()
+            .unwrap(),
+    );
+    let health = Arc::new(ConfigHealth::new());
+    let switcher = SourceSwitcher::start(
+        manager,
+        health.clone(),
+        WatchOptions {
+            queue_capacity: 64,
+            debounce: Duration::from_millis(50),
+            poll_interval: Duration::from_secs(1),
+        },
+    );
+
+    // Claim a free port, then hand it to the server
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut config = Config::from_env().unwrap();
+    config.server_host = "127.0.0.1".to_string();
+    config.server_port = port;
+    config.layers_dir = layers_dir;
+    config.experiments_dir = experiments_dir;
+    config.emergency_overrides_file = temp_dir.path().join("emergency_overrides.json");
+    config.exclusions_file = temp_dir.path().join("exclusions.json");
+    let server = tokio::spawn(run_server(config, evaluator, kv, health, switcher, None, None));
+
+    let mut ready = false;
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            ready = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(ready, "server did not start listening");
+
+    // Subject in both layers: one assignment per layer, in resolution order
+    let (status, body) = get_json(port, &format!("/subjects/{}/assignments?service=checkout", in_both)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["subject"], json!(in_both));
+    assert_eq!(body["service"], json!("checkout"));
+    assert_eq!(
+        body["assignments"],
+        json!([
+            {"layer_id": "ui_layer", "eid": 100, "vid": 1001, "label": "ui_layer_treatment"},
+            {"layer_id": "pricing_layer", "eid": 200, "vid": 2001, "label": "pricing_layer_treatment"},
+        ])
+    );
+
+    // Subject outside every range: an empty list, not an error
+    let (status, body) = get_json(port, &format!("/subjects/{}/assignments?service=checkout", unassigned)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["subject"], json!(unassigned));
+    assert_eq!(body["assignments"], json!([]));
+
+    // The service is required
+    let (status, _) = get_json(port, &format!("/subjects/{}/assignments", in_both)).await;
+    assert!(status >= 400);
+
+    server.abort();
+}
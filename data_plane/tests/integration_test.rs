@@ -19,15 +19,21 @@ async fn test_layer_loading_and_service_index() {
     let exp = ExperimentDef {
         eid: 100,
         service: "test_service".to_string(),
+        expires_at: None,
+        state: Default::default(),
+        caps: None,
         rule: None,
+        exposure_sampling: None,
         variants: vec![
             VariantDef {
                 vid: 1001,
                 params: json!({"feature": "a"}),
+                dark_params: None,
             },
             VariantDef {
                 vid: 1002,
                 params: json!({"feature": "b"}),
+                dark_params: None,
             },
         ],
     };
@@ -37,7 +43,7 @@ async fn test_layer_loading_and_service_index() {
     )
     .unwrap();
 
-    let catalog = ExperimentCatalog::load_from_dir(experiments_dir).unwrap();
+    let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
     assert_eq!(catalog.len(), 1);
 
     // Create layers
@@ -45,22 +51,28 @@ async fn test_layer_loading_and_service_index() {
         layer_id: "test_layer".to_string(),
         version: "v1".to_string(),
         priority: 200,
-        hash_key: "user_id".to_string(),
+        hash_key: "user_id".into(),
         salt: None,
+        bucket_size: None,
+        expires_at: None,
+        rule: None,
         services: vec![],
         ranges: vec![
             BucketRange {
                 start: 0,
                 end: 5000,
                 vid: 1001,
+                label: None,
             },
             BucketRange {
                 start: 5000,
                 end: 10000,
                 vid: 1002,
+                label: None,
             },
         ],
         enabled: true,
+        aa_test: false,
     };
 
     std::fs::write(
@@ -90,15 +102,21 @@ async fn test_merge_with_ranges() {
     let exp = ExperimentDef {
         eid: 200,
         service: "api".to_string(),
+        expires_at: None,
+        state: Default::default(),
+        caps: None,
         rule: None,
+        exposure_sampling: None,
         variants: vec![
             VariantDef {
                 vid: 2001,
                 params: json!({"timeout": 100, "retries": 3}),
+                dark_params: None,
             },
             VariantDef {
                 vid: 2002,
                 params: json!({"timeout": 200, "cache": true}),
+                dark_params: None,
             },
         ],
     };
@@ -119,15 +137,20 @@ async fn test_merge_with_ranges() {
         layer_id: "api_layer".to_string(),
         version: "v1".to_string(),
         priority: 100,
-        hash_key: "user_id".to_string(),
+        hash_key: "user_id".into(),
         salt: Some(salt.to_string()),
+        bucket_size: None,
+        expires_at: None,
+        rule: None,
         services: vec![],
         ranges: vec![BucketRange {
             start: bucket,
             end: bucket.saturating_add(1).min(BUCKET_SIZE),
             vid: 2001,
+            label: None,
         }],
         enabled: true,
+        aa_test: false,
     };
 
     std::fs::write(
@@ -145,10 +168,11 @@ async fn test_merge_with_ranges() {
             .into_iter()
             .collect(),
         layers: vec![],
+        diagnostics: false,
     };
 
     let field_types = HashMap::new();
-    let response = merge_layers_batch(&request, &manager, &catalog, &field_types).unwrap();
+    let response = merge_layers_batch(&request, &manager.snapshot(), &field_types).unwrap();
 
     let result = response.results.get("api").unwrap();
     assert_eq!(result.vids, vec![2001]);
@@ -168,19 +192,26 @@ async fn test_eid_rule_evaluation_memo() {
     let exp = ExperimentDef {
         eid: 300,
         service: "api".to_string(),
+        expires_at: None,
+        state: Default::default(),
+        caps: None,
         rule: Some(experiment_data_plane::rule::Node::Field {
             field: "region".to_string(),
             op: experiment_data_plane::rule::Op::Eq,
             values: vec![json!("US")],
+            collation: None,
         }),
+        exposure_sampling: None,
         variants: vec![
             VariantDef {
                 vid: 3001,
                 params: json!({"feature": "a"}),
+                dark_params: None,
             },
             VariantDef {
                 vid: 3002,
                 params: json!({"feature": "b"}),
+                dark_params: None,
             },
         ],
     };
@@ -203,30 +234,40 @@ async fn test_eid_rule_evaluation_memo() {
         layer_id: "layer1".to_string(),
         version: "v1".to_string(),
         priority: 200,
-        hash_key: "user_id".to_string(),
+        hash_key: "user_id".into(),
         salt: Some(salt1.to_string()),
+        bucket_size: None,
+        expires_at: None,
+        rule: None,
         services: vec![],
         ranges: vec![BucketRange {
             start: bucket1,
             end: bucket1.saturating_add(1).min(BUCKET_SIZE),
             vid: 3001,
+            label: None,
         }],
         enabled: true,
+        aa_test: false,
     };
 
     let layer2 = Layer {
         layer_id: "layer2".to_string(),
         version: "v1".to_string(),
         priority: 100,
-        hash_key: "user_id".to_string(),
+        hash_key: "user_id".into(),
         salt: Some(salt2.to_string()),
+        bucket_size: None,
+        expires_at: None,
+        rule: None,
         services: vec![],
         ranges: vec![BucketRange {
             start: bucket2,
             end: bucket2.saturating_add(1).min(BUCKET_SIZE),
             vid: 3002,
+            label: None,
         }],
         enabled: true,
+        aa_test: false,
     };
 
     std::fs::write(
@@ -252,12 +293,13 @@ async fn test_eid_rule_evaluation_memo() {
         services: vec!["api".to_string()],
         context,
         layers: vec![],
+        diagnostics: false,
     };
 
     let mut field_types = HashMap::new();
     field_types.insert("region".to_string(), experiment_data_plane::rule::FieldType::String);
 
-    let response = merge_layers_batch(&request, &manager, &catalog, &field_types).unwrap();
+    let response = merge_layers_batch(&request, &manager.snapshot(), &field_types).unwrap();
 
     let result = response.results.get("api").unwrap();
     // Both variants should be matched (rule evaluated once and cached for eid 300)
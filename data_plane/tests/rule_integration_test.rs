@@ -19,14 +19,20 @@ async fn test_rule_based_experiment() {
     let exp = ExperimentDef {
         eid: 400,
         service: "api".to_string(),
+        expires_at: None,
+        state: Default::default(),
+        caps: None,
         rule: Some(Node::Field {
             field: "country".to_string(),
             op: Op::Eq,
             values: vec![json!("CN")],
+            collation: None,
         }),
+        exposure_sampling: None,
         variants: vec![VariantDef {
             vid: 4001,
             params: json!({"feature": "china_special"}),
+            dark_params: None,
         }],
     };
 
@@ -46,15 +52,20 @@ async fn test_rule_based_experiment() {
         layer_id: "geo_layer".to_string(),
         version: "v1".to_string(),
         priority: 100,
-        hash_key: "user_id".to_string(),
+        hash_key: "user_id".into(),
         salt: Some(salt.to_string()),
+        bucket_size: None,
+        expires_at: None,
+        rule: None,
         services: vec![],
         ranges: vec![BucketRange {
             start: bucket,
             end: bucket.saturating_add(1).min(BUCKET_SIZE),
             vid: 4001,
+            label: None,
         }],
         enabled: true,
+        aa_test: false,
     };
 
     std::fs::write(
@@ -76,12 +87,13 @@ async fn test_rule_based_experiment() {
             services: vec!["api".to_string()],
             context,
             layers: vec![],
+            diagnostics: false,
         };
 
         let mut field_types = HashMap::new();
         field_types.insert("country".to_string(), FieldType::String);
 
-        let response = merge_layers_batch(&request, &manager, &catalog, &field_types).unwrap();
+        let response = merge_layers_batch(&request, &manager.snapshot(), &field_types).unwrap();
         let result = response.results.get("api").unwrap();
 
         assert_eq!(result.vids, vec![4001]);
@@ -98,15 +110,92 @@ async fn test_rule_based_experiment() {
             services: vec!["api".to_string()],
             context,
             layers: vec![],
+            diagnostics: false,
         };
 
         let mut field_types = HashMap::new();
         field_types.insert("country".to_string(), FieldType::String);
 
-        let response = merge_layers_batch(&request, &manager, &catalog, &field_types).unwrap();
+        let response = merge_layers_batch(&request, &manager.snapshot(), &field_types).unwrap();
         let result = response.results.get("api").unwrap();
 
         // Rule failed, no vids should be matched
         assert_eq!(result.vids.len(), 0);
     }
 }
+
+#[tokio::test]
+async fn test_layer_rule_scopes_whole_layer() {
+    let temp_dir = TempDir::new().unwrap();
+    let layers_dir = temp_dir.path().join("layers");
+    let experiments_dir = temp_dir.path().join("experiments");
+    std::fs::create_dir_all(&layers_dir).unwrap();
+    std::fs::create_dir_all(&experiments_dir).unwrap();
+
+    let exp = ExperimentDef {
+        eid: 500,
+        service: "api".to_string(),
+        expires_at: None,
+        state: Default::default(),
+        caps: None,
+        rule: None,
+        exposure_sampling: None,
+        variants: vec![VariantDef {
+            vid: 5001,
+            params: json!({"feature": "ios_only"}),
+            dark_params: None,
+        }],
+    };
+    std::fs::write(
+        experiments_dir.join("500.json"),
+        serde_json::to_string_pretty(&exp).unwrap(),
+    )
+    .unwrap();
+
+    let catalog = Arc::new(ExperimentCatalog::load_from_dir(experiments_dir).unwrap());
+
+    std::fs::write(
+        layers_dir.join("ios_layer.json"),
+        json!({
+            "layer_id": "ios_layer",
+            "version": "v1",
+            "priority": 100,
+            "hash_key": "user_id",
+            "enabled": true,
+            "rule": {"type": "field", "field": "platform", "op": "eq", "values": ["ios"]},
+            "ranges": [{"start": 0, "end": 10000, "vid": 5001}]
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let manager = LayerManager::new(layers_dir);
+    manager.load_all_layers(&catalog).await.unwrap();
+
+    let mut field_types = HashMap::new();
+    field_types.insert("platform".to_string(), FieldType::String);
+
+    let vids_for = |platform: Option<&str>| {
+        let mut context = HashMap::new();
+        context.insert("user_id".to_string(), json!("user_1"));
+        if let Some(platform) = platform {
+            context.insert("platform".to_string(), json!(platform));
+        }
+        let request = ExperimentRequest {
+            services: vec!["api".to_string()],
+            context,
+            layers: vec![],
+            diagnostics: false,
+        };
+        merge_layers_batch(&request, &manager.snapshot(), &field_types)
+            .unwrap()
+            .results["api"]
+            .vids
+            .clone()
+    };
+
+    assert_eq!(vids_for(Some("ios")), vec![5001]);
+    assert!(vids_for(Some("android")).is_empty());
+    // Missing field fails the rule: the layer is skipped
+    assert!(vids_for(None).is_empty());
+}
@@ -24,11 +24,15 @@ fn test_layer_get_salt() {
         layer_id: "test".to_string(),
         version: "v1".to_string(),
         priority: 100,
-        hash_key: "user_id".to_string(),
+        hash_key: "user_id".into(),
         salt: Some("custom_salt".to_string()),
+        bucket_size: None,
+        expires_at: None,
+        rule: None,
         services: vec![],
         ranges: vec![],
         enabled: true,
+        aa_test: false,
     };
     assert_eq!(layer1.get_salt(), "custom_salt");
 
@@ -37,11 +41,15 @@ fn test_layer_get_salt() {
         layer_id: "test2".to_string(),
         version: "v2".to_string(),
         priority: 100,
-        hash_key: "user_id".to_string(),
+        hash_key: "user_id".into(),
         salt: None,
+        bucket_size: None,
+        expires_at: None,
+        rule: None,
         services: vec![],
         ranges: vec![],
         enabled: true,
+        aa_test: false,
     };
     assert_eq!(layer2.get_salt(), "test2_v2");
 }
@@ -52,22 +60,28 @@ fn test_ranges_deterministic_hit() {
         layer_id: "deterministic".to_string(),
         version: "v1".to_string(),
         priority: 100,
-        hash_key: "user_id".to_string(),
+        hash_key: "user_id".into(),
         salt: Some("fixed_salt".to_string()),
+        bucket_size: None,
+        expires_at: None,
+        rule: None,
         services: vec![],
         ranges: vec![
             BucketRange {
                 start: 0,
                 end: 5000,
                 vid: 1,
+                label: None,
             },
             BucketRange {
                 start: 5000,
                 end: 10000,
                 vid: 2,
+                label: None,
             },
         ],
         enabled: true,
+        aa_test: false,
     };
 
     let key = "consistent_user";
@@ -0,0 +1,169 @@
+//! End-to-end config delivery over xDS against the in-process control plane.
+//! Run with `cargo test --features test-support --test xds_integration_test`.
+
+use experiment_data_plane::catalog::ExperimentCatalog;
+use experiment_data_plane::failover::FailoverOptions;
+use experiment_data_plane::health::ConfigHealth;
+use experiment_data_plane::layer::LayerManager;
+use experiment_data_plane::xds::testing::{resource, MockControlPlane};
+use experiment_data_plane::xds::{XdsOptions, XdsSubscriber, EXPERIMENT_TYPE, LAYER_TYPE};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn experiment(eid: i64, vids: [i64; 2]) -> Value {
+    json!({
+        "eid": eid,
+        "service": "checkout",
+        "variants": [
+            {"vid": vids[0], "params": {"button": "blue"}},
+            {"vid": vids[1], "params": {"button": "green"}}
+        ]
+    })
+}
+
+fn layer(layer_id: &str, version: &str, vids: [i64; 2]) -> Value {
+    json!({
+        "layer_id": layer_id,
+        "version": version,
+        "priority": 100,
+        "hash_key": "user_id",
+        "ranges": [
+            {"start": 0, "end": 5000, "vid": vids[0]},
+            {"start": 5000, "end": 10000, "vid": vids[1]}
+        ],
+        "enabled": true
+    })
+}
+
+fn options(endpoints: Vec<String>) -> XdsOptions {
+    XdsOptions {
+        endpoints,
+        node_id: "dp-test".to_string(),
+        initial_backoff: Duration::from_millis(50),
+        max_backoff: Duration::from_millis(200),
+        failover: FailoverOptions {
+            check_interval: Duration::from_millis(50),
+            failover_after: Duration::from_millis(200),
+            failback_after: Duration::from_millis(200),
+        },
+    }
+}
+
+#[tokio::test]
+async fn test_subscribe_ack_nack_and_reconnect() {
+    let temp_dir = TempDir::new().unwrap();
+    let layers_dir = temp_dir.path().join("layers");
+    let experiments_dir = temp_dir.path().join("experiments");
+    std::fs::create_dir_all(&layers_dir).unwrap();
+    std::fs::create_dir_all(&experiments_dir).unwrap();
+
+    let manager = Arc::new(LayerManager::new(layers_dir.clone()));
+    manager
+        .reindex(&Arc::new(ExperimentCatalog::load_from_dir(experiments_dir.clone()).unwrap()))
+        .unwrap();
+    let health = Arc::new(ConfigHealth::new());
+
+    let control_plane = MockControlPlane::start().await;
+    let subscriber = Arc::new(XdsSubscriber::new(
+        options(vec![control_plane.endpoint()]),
+        manager.clone(),
+        health,
+    ));
+    let task = tokio::spawn(subscriber.clone().run());
+
+    // Experiments are subscribed before the layers referencing them
+    let first = control_plane.next_request().await;
+    let second = control_plane.next_request().await;
+    assert_eq!((first.type_url.as_str(), second.type_url.as_str()), (EXPERIMENT_TYPE, LAYER_TYPE));
+    assert_eq!(first.node_id, "dp-test");
+    assert!(first.version_info.is_empty() && first.response_nonce.is_empty());
+
+    let nonce = control_plane.push(EXPERIMENT_TYPE, "v1", vec![resource("100", &experiment(100, [1001, 1002]))]);
+    let ack = control_plane.next_reply(EXPERIMENT_TYPE).await;
+    assert_eq!((ack.version_info.as_str(), ack.response_nonce.as_str()), ("v1", nonce.as_str()));
+    assert!(ack.error_detail.is_empty(), "{}", ack.error_detail);
+    assert_eq!(manager.snapshot().catalog().len(), 1);
+    assert!(experiments_dir.join("100.json").exists());
+
+    control_plane.push(LAYER_TYPE, "l1", vec![resource("checkout", &layer("checkout", "1", [1001, 1002]))]);
+    let ack = control_plane.next_reply(LAYER_TYPE).await;
+    assert!(ack.error_detail.is_empty(), "{}", ack.error_detail);
+    assert_eq!(manager.get_layer("checkout").unwrap().version, "1");
+
+    // An invalid set is NACKed with the previous version and nothing changes
+    control_plane.push(
+        EXPERIMENT_TYPE,
+        "v2",
+        vec![
+            resource("100", &experiment(100, [1001, 1002])),
+            resource("200", &experiment(200, [1002, 2002])),
+        ],
+    );
+    let nack = control_plane.next_reply(EXPERIMENT_TYPE).await;
+    assert_eq!(nack.version_info, "v1");
+    assert!(nack.error_detail.contains("Duplicate vid"), "{}", nack.error_detail);
+    assert_eq!(manager.snapshot().catalog().len(), 1);
+    assert!(!experiments_dir.join("200.json").exists());
+    assert_eq!(subscriber.accepted_version(EXPERIMENT_TYPE).as_deref(), Some("v1"));
+
+    // After a control plane restart the data plane resubscribes with what it accepted
+    control_plane.disconnect_all();
+    let resubscribe = control_plane.next_request().await;
+    assert_eq!(resubscribe.type_url, EXPERIMENT_TYPE);
+    assert_eq!(resubscribe.version_info, "v1");
+    let resubscribe = control_plane.next_request().await;
+    assert_eq!((resubscribe.type_url.as_str(), resubscribe.version_info.as_str()), (LAYER_TYPE, "l1"));
+    assert_eq!(control_plane.streams_opened(), 2);
+
+    // The rejected v2 is still current, so it's resent on resubscription and NACKed again
+    let nack = control_plane.next_reply(EXPERIMENT_TYPE).await;
+    assert_eq!(nack.version_info, "v1");
+
+    control_plane.push(LAYER_TYPE, "l2", vec![resource("checkout", &layer("checkout", "2", [1002, 1001]))]);
+    let ack = control_plane.next_reply(LAYER_TYPE).await;
+    assert_eq!(ack.version_info, "l2");
+    assert!(ack.error_detail.is_empty(), "{}", ack.error_detail);
+    assert_eq!(manager.get_layer("checkout").unwrap().version, "2");
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_fails_over_between_endpoints_and_back() {
+    let temp_dir = TempDir::new().unwrap();
+    let layers_dir = temp_dir.path().join("layers");
+    let experiments_dir = temp_dir.path().join("experiments");
+    std::fs::create_dir_all(&layers_dir).unwrap();
+    std::fs::create_dir_all(&experiments_dir).unwrap();
+    let manager = Arc::new(LayerManager::new(layers_dir));
+    manager
+        .reindex(&Arc::new(ExperimentCatalog::load_from_dir(experiments_dir.clone()).unwrap()))
+        .unwrap();
+
+    // The primary is down at first: reserve its address without serving on it
+    let primary_addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let standby = MockControlPlane::start().await;
+    let subscriber = Arc::new(XdsSubscriber::new(
+        options(vec![format!("http://{}", primary_addr), standby.endpoint()]),
+        manager.clone(),
+        Arc::new(ConfigHealth::new()),
+    ));
+    let task = tokio::spawn(subscriber.clone().run());
+
+    let subscription = standby.next_request().await;
+    assert_eq!(subscription.type_url, EXPERIMENT_TYPE);
+    assert_eq!(subscriber.active_endpoint(), standby.endpoint());
+    standby.push(EXPERIMENT_TYPE, "v1", vec![resource("100", &experiment(100, [1001, 1002]))]);
+    standby.next_reply(EXPERIMENT_TYPE).await;
+    assert_eq!(manager.snapshot().catalog().len(), 1);
+
+    // The primary comes back and is switched to once it stayed up long enough
+    let primary = MockControlPlane::start_at(primary_addr).await;
+    let resubscribe = primary.next_request().await;
+    assert_eq!((resubscribe.type_url.as_str(), resubscribe.version_info.as_str()), (EXPERIMENT_TYPE, "v1"));
+    assert_eq!(subscriber.active_endpoint(), primary.endpoint());
+
+    task.abort();
+}